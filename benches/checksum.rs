@@ -0,0 +1,27 @@
+//! Buffered against memory-mapped SHA-256 of a download-sized file:
+//! `cargo bench --bench checksum`.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use nitro::core::checksum::{sha256_buffered, sha256_mapped};
+use std::io::Write;
+
+const SIZE: usize = 256 * 1024 * 1024;
+
+fn checksum(c: &mut Criterion) {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    let chunk: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+    for _ in 0..SIZE / chunk.len() {
+        file.write_all(&chunk).unwrap();
+    }
+    file.flush().unwrap();
+
+    let mut group = c.benchmark_group("sha256");
+    group.throughput(Throughput::Bytes(SIZE as u64));
+    group.sample_size(10);
+    group.bench_function("buffered", |b| b.iter(|| sha256_buffered(file.reopen().unwrap()).unwrap()));
+    group.bench_function("mapped", |b| b.iter(|| sha256_mapped(file.as_file()).unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, checksum);
+criterion_main!(benches);
@@ -0,0 +1,87 @@
+//! Kegs built from source, packed like bottles so a reinstall (or another machine with
+//! the same platform and prefix sharing the directory) unpacks the build instead of
+//! compiling it again.
+
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+use crate::core::formula::Formula;
+
+/// A directory of packed kegs named by their cache key.
+#[derive(Debug, Clone)]
+pub struct KegCache {
+    dir: PathBuf,
+}
+
+impl KegCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// `kegs` under nitro's cache directory.
+    pub fn default_dir() -> Option<PathBuf> {
+        directories::ProjectDirs::from("com", "nitro", "nitro").map(|dirs| dirs.cache_dir().join("kegs"))
+    }
+
+    /// The key a build of `formula` is cached under. Anything that changes what the
+    /// build produces is part of it: the formula's definition, the platform (including
+    /// OS release) and prefix it was built for, and `options` such as the versions of
+    /// the dependencies it linked against.
+    pub fn key(formula: &Formula, platform: &str, prefix: &Path, options: &[String]) -> String {
+        let definition = formula.source_hash.clone().unwrap_or_else(|| {
+            let spec = serde_json::json!([formula.sources, formula.install_script, formula.resources, formula.dependencies]);
+            hex::encode(Sha256::digest(spec.to_string()))
+        });
+        let mut options = options.to_vec();
+        options.sort();
+
+        let mut hasher = Sha256::new();
+        hasher.update(format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            formula.name, formula.pkg_version(), definition, platform, prefix.display(), options.join("\n")
+        ));
+        format!("{}--{}--{}", formula.name, formula.pkg_version(), &hex::encode(hasher.finalize())[..32])
+    }
+
+    pub fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.tar.gz", key))
+    }
+
+    /// The packed keg for `key`, if one has been stored.
+    pub fn get(&self, key: &str) -> Option<PathBuf> {
+        Some(self.path(key)).filter(|path| path.is_file())
+    }
+
+    /// Pack the keg at `keg` under `key`. The archive is laid out like a bottle
+    /// (`<name>/<version>/...`) and written under a temporary name first, so readers
+    /// never see half an archive.
+    pub fn store(&self, key: &str, formula: &Formula, keg: &Path) -> Result<PathBuf> {
+        use flate2::{write::GzEncoder, Compression};
+
+        std::fs::create_dir_all(&self.dir)?;
+        let dest = self.path(key);
+        let partial = dest.with_extension(format!("partial-{}", std::process::id()));
+
+        let result = (|| -> Result<()> {
+            let file = std::fs::File::create(&partial)?;
+            let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+            builder.follow_symlinks(false);
+            builder.append_dir_all(Path::new(&formula.name).join(formula.pkg_version()), keg)?;
+            builder.into_inner()?.finish()?;
+            std::fs::rename(&partial, &dest)?;
+            Ok(())
+        })();
+        if result.is_err() {
+            let _ = std::fs::remove_file(&partial);
+        }
+        result.map(|_| dest)
+    }
+
+    /// Unpack the keg stored under `key` into `workspace`, returning the unpacked keg.
+    pub fn unpack(&self, key: &str, formula: &Formula, workspace: &Path) -> Result<PathBuf> {
+        let file = std::fs::File::open(self.path(key))?;
+        tar::Archive::new(flate2::read::GzDecoder::new(file)).unpack(workspace)?;
+        Ok(workspace.join(&formula.name).join(formula.pkg_version()))
+    }
+}
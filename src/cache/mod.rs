@@ -1,9 +1,16 @@
+pub mod kegs;
+pub mod shared;
+
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use std::time::{Duration, SystemTime};
 
 use crate::core::NitroError;
+use crate::download::Downloader;
+use shared::SharedCache;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CacheEntry {
@@ -22,11 +29,20 @@ pub struct CacheManager {
 }
 
 impl CacheManager {
+    /// The directory the cache keeps its entries in.
+    pub fn dir(&self) -> &Path {
+        &self.cache_dir
+    }
+
     pub fn new() -> Result<Self> {
         let config_dir = directories::ProjectDirs::from("com", "nitro", "nitro")
             .ok_or_else(|| NitroError::Other("Could not determine config directory".into()))?;
-        
-        let cache_dir = config_dir.cache_dir().to_path_buf();
+        Self::open(config_dir.cache_dir())
+    }
+
+    /// A cache kept in `cache_dir`.
+    pub fn open(cache_dir: &Path) -> Result<Self> {
+        let cache_dir = cache_dir.to_path_buf();
         std::fs::create_dir_all(&cache_dir)?;
         
         let db_path = cache_dir.join("cache.db");
@@ -132,17 +148,47 @@ impl CacheManager {
     pub async fn size(&self) -> Result<u64> {
         let mut total_size = 0u64;
         
-        for entry in self.db.iter() {
-            if let Ok((_, value)) = entry {
-                if let Ok(cache_entry) = serde_json::from_slice::<CacheEntry>(&value) {
-                    total_size += cache_entry.size;
-                }
+        for (_, value) in self.db.iter().flatten() {
+            if let Ok(cache_entry) = serde_json::from_slice::<CacheEntry>(&value) {
+                total_size += cache_entry.size;
             }
         }
         
         Ok(total_size)
     }
 
+    /// Drop expired entries, entries whose file is gone, and the least recently used
+    /// entries until the cache is back under 90% of its size limit. Returns what was
+    /// dropped; with `dry_run`, what would be.
+    pub async fn prune(&self, dry_run: bool) -> Result<Vec<CacheEntry>> {
+        let mut entries: Vec<CacheEntry> = self.db.iter().flatten()
+            .filter_map(|(_, value)| serde_json::from_slice(&value).ok())
+            .collect();
+        entries.sort_by_key(|entry| entry.accessed_at);
+
+        let (mut pruned, kept): (Vec<CacheEntry>, Vec<CacheEntry>) = entries.into_iter().partition(|entry| {
+            let expired = entry.ttl.is_some_and(|ttl| entry.created_at.elapsed().unwrap_or_default() > ttl);
+            expired || !entry.path.exists()
+        });
+        let mut size: u64 = kept.iter().map(|entry| entry.size).sum();
+        if size > self.max_size {
+            for entry in kept {
+                if size <= self.max_size * 9 / 10 {
+                    break;
+                }
+                size -= entry.size;
+                pruned.push(entry);
+            }
+        }
+
+        if !dry_run {
+            for entry in &pruned {
+                self.remove(&entry.key).await?;
+            }
+        }
+        Ok(pruned)
+    }
+
     async fn evict_if_needed(&self) -> Result<()> {
         let current_size = self.size().await?;
         
@@ -150,15 +196,13 @@ impl CacheManager {
             // Collect all entries with access times
             let mut entries: Vec<(String, SystemTime, u64)> = Vec::new();
             
-            for entry in self.db.iter() {
-                if let Ok((key, value)) = entry {
-                    if let Ok(cache_entry) = serde_json::from_slice::<CacheEntry>(&value) {
-                        entries.push((
-                            String::from_utf8_lossy(&key).to_string(),
-                            cache_entry.accessed_at,
-                            cache_entry.size,
-                        ));
-                    }
+            for (key, value) in self.db.iter().flatten() {
+                if let Ok(cache_entry) = serde_json::from_slice::<CacheEntry>(&value) {
+                    entries.push((
+                        String::from_utf8_lossy(&key).to_string(),
+                        cache_entry.accessed_at,
+                        cache_entry.size,
+                    ));
                 }
             }
             
@@ -183,13 +227,80 @@ impl CacheManager {
 
 pub struct DownloadCache {
     cache_manager: CacheManager,
+    downloader: Downloader,
+    /// Consulted on a local miss, and given what had to be downloaded
+    shared: Option<SharedCache>,
 }
 
 impl DownloadCache {
-    pub fn new() -> Result<Self> {
-        Ok(Self {
-            cache_manager: CacheManager::new()?,
-        })
+    pub fn new(downloader: Downloader) -> Result<Self> {
+        Ok(Self::with_manager(CacheManager::new()?, downloader))
+    }
+
+    pub fn with_manager(cache_manager: CacheManager, downloader: Downloader) -> Self {
+        Self { cache_manager, downloader, shared: None }
+    }
+
+    /// The directory downloads are cached in.
+    pub fn dir(&self) -> &Path {
+        self.cache_manager.dir()
+    }
+
+    /// Share downloads with other machines through `shared`.
+    pub fn with_shared(mut self, shared: SharedCache) -> Self {
+        self.shared = Some(shared);
+        self
+    }
+
+    /// Fetch the artifact with SHA-256 `digest` from `url` into `dest`, trying the
+    /// local cache, then the shared cache, then `url` itself. A download from `url` is
+    /// cached locally and uploaded to the shared cache, but only if it matches `digest`;
+    /// a mismatch is left in `dest` for the caller's checksum verification to report.
+    /// An unreachable shared cache only costs a warning.
+    pub async fn fetch_digest(&self, url: &str, digest: &str, dest: &Path) -> Result<()> {
+        let key = format!("sha256-{}", digest.to_lowercase());
+        if let Some(path) = self.cache_manager.get(&key).await {
+            crate::metrics::global().record_cache_lookup(true);
+            tokio::fs::copy(&path, dest).await?;
+            return Ok(());
+        }
+
+        if let Some(shared) = &self.shared {
+            match shared.get(digest, dest).await {
+                Ok(true) => {
+                    crate::metrics::global().record_cache_lookup(true);
+                    self.cache_manager.put(&key, dest, None).await?;
+                    return Ok(());
+                }
+                Ok(false) => {}
+                Err(e) => eprintln!("Warning: shared cache unavailable ({}); downloading directly", e),
+            }
+        }
+        crate::metrics::global().record_cache_lookup(false);
+
+        // Registry blob URLs (ghcr.io bottles) get their pull token in `download_file`
+        self.downloader.download_file(url, dest).await?;
+        if !shared::file_digest(dest)?.eq_ignore_ascii_case(digest) {
+            return Ok(());
+        }
+        self.cache_manager.put(&key, dest, None).await?;
+        if let Some(shared) = &self.shared {
+            if let Err(e) = shared.put(digest, dest).await {
+                eprintln!("Warning: could not upload {} to the shared cache: {}", url, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the cached copy of `url`, downloading it with the shared client on a miss.
+    pub async fn fetch(&self, url: &str) -> Result<PathBuf> {
+        let temp_dir = tempfile::tempdir()?;
+        let temp_path = temp_dir.path().join("download");
+
+        self.get_or_download(url, async {
+            self.downloader.download_file(url, &temp_path).await?;
+            Ok(temp_path.clone())
+        }).await
     }
 
     pub async fn get_or_download<F>(
@@ -204,8 +315,10 @@ impl DownloadCache {
         
         // Check cache first
         if let Some(path) = self.cache_manager.get(&key).await {
+            crate::metrics::global().record_cache_lookup(true);
             return Ok(path);
         }
+        crate::metrics::global().record_cache_lookup(false);
         
         // Download to temporary location
         let temp_path = downloader.await?;
@@ -231,6 +344,70 @@ impl DownloadCache {
     }
 }
 
+/// Bounded in-process LRU cache, the memory level in front of the on-disk caches.
+pub struct MemoryCache<V> {
+    capacity: usize,
+    inner: Mutex<MemoryCacheInner<V>>,
+}
+
+struct MemoryCacheInner<V> {
+    tick: u64,
+    entries: HashMap<String, (u64, V)>,
+}
+
+impl<V: Clone> MemoryCache<V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            inner: Mutex::new(MemoryCacheInner {
+                tick: 0,
+                entries: HashMap::new(),
+            }),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<V> {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.tick += 1;
+        let tick = inner.tick;
+        inner.entries.get_mut(key).map(|(last_used, value)| {
+            *last_used = tick;
+            value.clone()
+        })
+    }
+
+    pub fn insert(&self, key: &str, value: V) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.tick += 1;
+        let tick = inner.tick;
+
+        if !inner.entries.contains_key(key) && inner.entries.len() >= self.capacity {
+            // Evict the least recently used entry
+            let oldest = inner.entries.iter()
+                .min_by_key(|(_, (last_used, _))| *last_used)
+                .map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                inner.entries.remove(&oldest);
+            }
+        }
+
+        inner.entries.insert(key.to_string(), (tick, value));
+    }
+
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.entries.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner()).entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
 impl Drop for CacheManager {
     fn drop(&mut self) {
         // Ensure the database is properly flushed before dropping
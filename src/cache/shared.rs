@@ -0,0 +1,114 @@
+//! A cache shared by a team's machines or CI runners over plain HTTP. Artifacts are
+//! addressed by their SHA-256 (`GET`/`PUT <base>/sha256/<digest>`), so any server that
+//! can store and return files works, and nothing fetched from it is trusted until its
+//! digest checks out.
+
+use anyhow::Result;
+use futures::StreamExt;
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use tokio::io::AsyncWriteExt;
+
+/// The `[cache]` section of the config file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// Base URL of a shared cache server, e.g. `http://cache.internal:8080/nitro`
+    pub shared_url: Option<String>,
+    /// Only download from the shared cache, never upload to it
+    pub shared_read_only: bool,
+    /// Bearer token sent to the shared cache
+    pub shared_token: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SharedCache {
+    base_url: String,
+    client: Client,
+    read_only: bool,
+    token: Option<String>,
+}
+
+impl SharedCache {
+    pub fn new(base_url: &str, client: Client, read_only: bool) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            client,
+            read_only,
+            token: None,
+        }
+    }
+
+    /// The shared cache `config` points at, if any.
+    pub fn from_config(config: &CacheConfig, client: Client) -> Option<Self> {
+        let base_url = config.shared_url.as_deref().filter(|url| !url.is_empty())?;
+        Some(Self {
+            token: config.shared_token.clone(),
+            ..Self::new(base_url, client, config.shared_read_only)
+        })
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn url(&self, digest: &str) -> String {
+        format!("{}/sha256/{}", self.base_url, digest.to_lowercase())
+    }
+
+    fn request(&self, method: reqwest::Method, digest: &str) -> reqwest::RequestBuilder {
+        let request = self.client.request(method, self.url(digest));
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    /// Download the artifact with SHA-256 `digest` to `dest`. `Ok(false)` when the
+    /// cache doesn't have it, or has a copy that doesn't match the digest; errors mean
+    /// the cache couldn't be reached.
+    pub async fn get(&self, digest: &str, dest: &Path) -> Result<bool> {
+        let response = self.request(reqwest::Method::GET, digest).send().await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        let response = response.error_for_status()?;
+
+        let mut hasher = Sha256::new();
+        let mut file = tokio::fs::File::create(dest).await?;
+        let mut stream = response.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            hasher.update(&chunk);
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+
+        if !hex::encode(hasher.finalize()).eq_ignore_ascii_case(digest) {
+            tracing::warn!("Shared cache returned a corrupt copy of {}", digest);
+            let _ = tokio::fs::remove_file(dest).await;
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// Upload the file at `path` as the artifact with SHA-256 `digest`. Does nothing
+    /// in read-only mode.
+    pub async fn put(&self, digest: &str, path: &Path) -> Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+        let body = tokio::fs::read(path).await?;
+        self.request(reqwest::Method::PUT, digest).body(body).send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+/// The SHA-256 of the file at `path`, hex encoded. Read a buffer at a time rather than
+/// mapped: shared-cache files can be replaced or truncated by other machines' writers
+/// while they're hashed.
+pub fn file_digest(path: &Path) -> std::io::Result<String> {
+    crate::core::checksum::sha256_buffered(std::fs::File::open(path)?)
+}
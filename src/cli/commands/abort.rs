@@ -0,0 +1,31 @@
+use anyhow::Result;
+use clap::Args;
+
+#[derive(Args)]
+pub struct AbortArgs {
+    /// Package name(s) to abort (aborts all interrupted installs if not specified)
+    pub packages: Vec<String>,
+}
+
+pub async fn execute(args: AbortArgs) -> Result<()> {
+    use crate::core::package::PackageManager;
+
+    let package_manager = PackageManager::new().await?;
+    let pending: Vec<_> = package_manager.pending_installs()?
+        .into_iter()
+        .filter(|r| args.packages.is_empty() || args.packages.contains(&r.formula.name))
+        .collect();
+
+    if pending.is_empty() {
+        println!("No interrupted installs to abort");
+        return Ok(());
+    }
+
+    for record in &pending {
+        println!("Aborting {} {} (last phase: {})", record.formula.name, record.formula.version, record.phase);
+        package_manager.abort(record).await?;
+    }
+
+    println!("Rolled back {} interrupted install(s)", pending.len());
+    Ok(())
+}
@@ -0,0 +1,64 @@
+use anyhow::Result;
+use clap::{Args, Subcommand};
+
+#[derive(Args)]
+pub struct AnalyticsArgs {
+    #[command(subcommand)]
+    pub command: AnalyticsCommands,
+}
+
+#[derive(Subcommand)]
+pub enum AnalyticsCommands {
+    /// Record usage locally and send anonymized events to the configured endpoint
+    On,
+    /// Disable all usage recording
+    Off,
+    /// Record usage locally only (viewable with `nitro stats`)
+    Local,
+    /// Show the current analytics setting
+    State,
+}
+
+pub async fn execute(args: AnalyticsArgs) -> Result<()> {
+    use crate::core::analytics::{Analytics, AnalyticsMode};
+
+    match args.command {
+        AnalyticsCommands::On => {
+            Analytics::set_mode(AnalyticsMode::On)?;
+            println!("Analytics are enabled.");
+        }
+        AnalyticsCommands::Off => {
+            Analytics::set_mode(AnalyticsMode::Off)?;
+            println!("Analytics are disabled.");
+        }
+        AnalyticsCommands::Local => {
+            Analytics::set_mode(AnalyticsMode::Local)?;
+            println!("Analytics are recorded locally only.");
+        }
+        AnalyticsCommands::State => {
+            let analytics = Analytics::new()?;
+            let config = analytics.config();
+            let mode = Analytics::effective_mode(config);
+
+            match mode {
+                AnalyticsMode::Off => println!("Analytics are disabled."),
+                AnalyticsMode::Local => println!("Analytics are recorded locally only."),
+                AnalyticsMode::On => println!("Analytics are enabled."),
+            }
+            if mode != config.mode {
+                println!("(overridden by NITRO_NO_ANALYTICS; configured mode is {})", config.mode);
+            }
+            if mode == AnalyticsMode::On {
+                match &config.endpoint {
+                    Some(endpoint) => println!("Reporting endpoint: {}", endpoint),
+                    None => println!("No reporting endpoint configured; events are kept locally."),
+                }
+                if let Some(client_id) = &config.client_id {
+                    println!("Anonymous client ID: {}", client_id);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use anyhow::Result;
+use clap::{Args, Subcommand};
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub struct AuditLogArgs {
+    #[command(subcommand)]
+    pub command: AuditLogCommands,
+
+    /// Audit log to read (defaults to var/log/nitro/audit.jsonl under the prefix)
+    #[arg(long, global = true, value_name = "PATH")]
+    pub log: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+pub enum AuditLogCommands {
+    /// Write the log's entries out, one JSON object per line, after verifying the chain
+    Export {
+        /// File to write to instead of standard output
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+
+        /// Write a single JSON document with the verification result and an array of entries
+        #[arg(long)]
+        json: bool,
+    },
+    /// Check that no entry has been changed, removed or reordered, and the log wasn't cut short
+    Verify,
+}
+
+/// Export or verify the install audit log (see `core::audit`). Both fail when the hash
+/// chain is broken; an export still writes everything first, for investigating.
+pub async fn execute(args: AuditLogArgs) -> Result<()> {
+    use crate::core::audit::{self, AuditLog};
+    use crate::core::installer::Installer;
+    use crate::core::package::PackageManager;
+    use crate::core::NitroError;
+
+    let path = match args.log {
+        Some(path) => path,
+        None => AuditLog::default_path(&Installer::prefix()?),
+    };
+    let log = PackageManager::new().await?.audit_log(path)?;
+    let entries = log.entries()?;
+    let head = log.head()?;
+    let verified = audit::verify(&entries).and_then(|()| audit::verify_head(&entries, head.as_ref()));
+
+    match args.command {
+        AuditLogCommands::Export { output, json } => {
+            let mut text = if json {
+                serde_json::to_string_pretty(&serde_json::json!({
+                    "log": log.path(),
+                    "verified": verified.is_ok(),
+                    "entries": entries,
+                }))?
+            } else {
+                entries.iter().map(serde_json::to_string).collect::<Result<Vec<_>, _>>()?.join("\n")
+            };
+            if !text.is_empty() {
+                text.push('\n');
+            }
+            match &output {
+                Some(output) => std::fs::write(output, text)?,
+                None => print!("{}", text),
+            }
+        }
+        AuditLogCommands::Verify => {
+            if verified.is_ok() && !crate::ui::is_quiet() {
+                println!("{}: {} entries, chain intact", log.path().display(), entries.len());
+            }
+        }
+    }
+
+    verified.map_err(|broken| NitroError::Other(format!(
+        "{} fails verification at line {}: {}", log.path().display(), broken.line, broken.reason
+    )).into())
+}
@@ -0,0 +1,33 @@
+use anyhow::Result;
+use clap::Args;
+
+#[derive(Args)]
+pub struct AutoremoveArgs {
+    /// Show what would be uninstalled without uninstalling it
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+}
+
+/// Uninstall packages that were only installed as dependencies and that nothing
+/// installed depends on any more.
+pub async fn execute(args: AutoremoveArgs) -> Result<()> {
+    use crate::core::package::PackageManager;
+
+    let package_manager = PackageManager::new().await?;
+    let removed = package_manager.autoremove(args.dry_run).await?;
+    if crate::ui::is_quiet() {
+        return Ok(());
+    }
+
+    if removed.is_empty() {
+        println!("No unneeded dependencies to remove");
+    } else if args.dry_run {
+        println!("Would uninstall {} unneeded package(s):", removed.len());
+        for name in &removed {
+            println!("  {}", name);
+        }
+    } else {
+        println!("Uninstalled {} unneeded package(s)", removed.len());
+    }
+    Ok(())
+}
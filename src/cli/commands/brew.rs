@@ -0,0 +1,111 @@
+use anyhow::Result;
+use clap::{Args, Parser};
+
+use crate::cli::{Cli, Commands};
+use crate::core::NitroError;
+
+#[derive(Args)]
+pub struct BrewArgs {
+    /// brew command line, e.g. `install wget` or `list --versions`
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+    pub args: Vec<String>,
+}
+
+pub async fn execute(args: BrewArgs) -> Result<()> {
+    let translated = translate(&args.args)?;
+    let cli = Cli::try_parse_from(std::iter::once("nitro".to_string()).chain(translated))?;
+
+    if matches!(cli.command, Commands::Brew(_)) {
+        return Err(NitroError::Other("Nested brew invocations are not supported".into()).into());
+    }
+
+    // Boxed because `run` is what dispatched us here
+    Box::pin(crate::cli::run(cli.command)).await
+}
+
+/// Map a brew argument list onto the equivalent nitro arguments. Flags nitro has no
+/// equivalent for are dropped with a warning rather than failing the whole command.
+pub fn translate(args: &[String]) -> Result<Vec<String>> {
+    let Some((command, rest)) = args.split_first() else {
+        return Err(NitroError::Other("No brew command given".into()).into());
+    };
+
+    let (flags, operands): (Vec<&String>, Vec<&String>) = rest.iter().partition(|a| a.starts_with('-'));
+    let operands: Vec<String> = operands.into_iter().cloned().collect();
+
+    let (mut translated, flag_map): (Vec<String>, &[(&str, &str)]) = match command.as_str() {
+        "install" if flags.iter().any(|f| *f == "--interactive" || *f == "-i") => {
+            let mut translated = vec!["debug-build".to_string(), "--interactive".to_string()];
+            translated.extend(operands);
+            return Ok(translated);
+        }
+        "install" | "reinstall" => (
+            vec!["install".into()],
+            &[("--force", "--force"), ("-f", "--force"), ("--build-from-source", "--build-from-source"),
+              ("-s", "--build-from-source"), ("--only-dependencies", "--only-deps"),
+              ("--ignore-dependencies", "--skip-deps"), ("--debug", "--debug"), ("-d", "--debug"),
+              ("--keep-tmp", "--keep-tmp"), ("--overwrite", "--overwrite")],
+        ),
+        "uninstall" | "remove" | "rm" => (
+            vec!["uninstall".into()],
+            &[("--force", "--force"), ("-f", "--force")],
+        ),
+        "list" | "ls" => (
+            vec!["list".into()],
+            &[("--versions", "--versions")],
+        ),
+        "info" | "abv" => (
+            vec!["info".into()],
+            &[("--json", "--json"), ("--json=v1", "--json"), ("--json=v2", "--json")],
+        ),
+        "search" => {
+            // brew accepts several words; nitro searches for a single query string
+            let mut translated = vec!["search".to_string()];
+            if !operands.is_empty() {
+                translated.push(operands.join(" "));
+            }
+            let flag_map: &[(&str, &str)] = &[("--desc", "--description")];
+            return Ok(with_flags(translated, &flags, flag_map, command));
+        }
+        "tap" if operands.is_empty() => (vec!["tap".into(), "list".into()], &[]),
+        "tap" => {
+            // `brew tap user/repo [URL]`
+            let mut translated = vec!["tap".to_string(), "add".to_string(), operands[0].clone()];
+            if let Some(url) = operands.get(1) {
+                translated.extend(["--url".to_string(), url.clone()]);
+            }
+            return Ok(with_flags(translated, &flags, &[], command));
+        }
+        "untap" => (vec!["tap".into(), "remove".into()], &[]),
+        "update" => (vec!["update".into()], &[("--quiet", "--quiet"), ("-q", "--quiet")]),
+        "outdated" => (
+            vec!["outdated".into()],
+            &[("--json", "--json"), ("--json=v2", "--json"), ("--cask", "--cask"), ("--greedy", "--greedy"),
+              ("--quiet", "--quiet"), ("-q", "--quiet")],
+        ),
+        "upgrade" => (
+            vec!["upgrade".into()],
+            &[("--dry-run", "--dry-run"), ("-n", "--dry-run"), ("--cask", "--cask"), ("--greedy", "--greedy"),
+              ("--quiet", "--quiet"), ("-q", "--quiet")],
+        ),
+        other => {
+            return Err(NitroError::Other(format!(
+                "brew command '{}' has no nitro equivalent (supported: install, uninstall, list, info, search, tap, untap, update, outdated, upgrade)",
+                other
+            )).into());
+        }
+    };
+
+    translated.extend(operands);
+    Ok(with_flags(translated, &flags, flag_map, command))
+}
+
+fn with_flags(mut translated: Vec<String>, flags: &[&String], flag_map: &[(&str, &str)], command: &str) -> Vec<String> {
+    for flag in flags {
+        match flag_map.iter().find(|(brew, _)| brew == flag) {
+            Some((_, nitro)) => translated.push(nitro.to_string()),
+            None => eprintln!("Warning: ignoring unsupported flag '{}' for brew {}", flag, command),
+        }
+    }
+    translated
+}
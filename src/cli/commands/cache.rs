@@ -0,0 +1,135 @@
+use anyhow::Result;
+use clap::{Args, Subcommand};
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub struct CacheArgs {
+    #[command(subcommand)]
+    pub command: CacheCommands,
+}
+
+#[derive(Subcommand)]
+pub enum CacheCommands {
+    /// Drop cached formulae whose formula files have changed
+    InvalidateFormulae {
+        /// Drop every cached formula, not just stale ones
+        #[arg(long)]
+        all: bool,
+    },
+    /// Download everything installing a Brewfile's formulae on this platform needs:
+    /// bottles, or sources and resources for formulae without one. Meant for a CI
+    /// step that restores the cache before installing
+    Warm {
+        /// Brewfile, or a manifest listing one formula per line
+        #[arg(long, value_name = "FILE")]
+        from: PathBuf,
+
+        /// How many downloads to run at once
+        #[arg(short, long, default_value_t = 4)]
+        jobs: usize,
+    },
+}
+
+/// The formulae a Brewfile's `brew` lines name or, for a file without any, the
+/// formulae a plain manifest lists one per line. Blank lines, `#` comments and other
+/// Brewfile entries (`cask "firefox"`) are skipped.
+pub fn manifest_formulae(content: &str) -> Vec<String> {
+    let brewfile = super::deps::brewfile_formulae(content);
+    if !brewfile.is_empty() {
+        return brewfile;
+    }
+    content
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty() && !line.contains(char::is_whitespace))
+        .map(str::to_string)
+        .collect()
+}
+
+pub async fn execute(args: CacheArgs) -> Result<()> {
+    use crate::core::formula::FormulaManager;
+
+    match args.command {
+        CacheCommands::InvalidateFormulae { all } => {
+            let formula_manager = FormulaManager::new().await?;
+            let removed = if all {
+                formula_manager.clear_cache()?
+            } else {
+                formula_manager.invalidate_stale_cache()?
+            };
+            println!("Invalidated {} cached formula(e)", removed);
+        }
+        CacheCommands::Warm { from, jobs } => warm(&from, jobs).await?,
+    }
+
+    Ok(())
+}
+
+/// Resolve the closure of the manifest at `from` and fetch each formula's artifacts for
+/// this platform into the caches installs read, `jobs` at a time.
+async fn warm(from: &std::path::Path, jobs: usize) -> Result<()> {
+    use crate::core::formula::FormulaManager;
+    use crate::core::installer::{bottle_cache_dir, source_cache_dir, Installer};
+    use crate::core::resolver::DependencyResolver;
+    use crate::core::NitroError;
+    use crate::download::Downloader;
+    use futures::StreamExt;
+
+    let roots = manifest_formulae(&std::fs::read_to_string(from)?);
+    if roots.is_empty() {
+        return Err(NitroError::Other(format!("{} lists no formulae", from.display())).into());
+    }
+
+    let formula_manager = FormulaManager::new().await?;
+    let resolver = DependencyResolver::new();
+    let mut closure = Vec::new();
+    for name in &roots {
+        let formula = formula_manager.get_formula(name).await?;
+        for formula in resolver.resolve(&formula, &formula_manager, &Default::default()).await?.into_iter().chain([formula]) {
+            if !closure.iter().any(|f: &crate::core::formula::Formula| f.name == formula.name) {
+                closure.push(formula);
+            }
+        }
+    }
+
+    crate::core::deterministic::sort_by_key(&mut closure, |f| f.name.clone());
+
+    let installer = Installer::new(Downloader::shared()?)?;
+    let platform = Installer::platform_tag();
+    let (bottle_dir, source_dir) = (bottle_cache_dir()?, source_cache_dir()?);
+    println!("Warming the cache for {} formula(e) on {}", closure.len(), platform);
+
+    let (installer, platform, bottle_dir, source_dir) = (&installer, &platform, &bottle_dir, &source_dir);
+    let results: Vec<_> = futures::stream::iter(&closure)
+        .map(|formula| async move {
+            let result = if installer.has_bottle(formula) {
+                installer.fetch_bottle(formula, platform, bottle_dir).await.map(|path| vec![path])
+            } else {
+                installer.fetch_sources(formula, source_dir).await
+            };
+            (formula, result)
+        })
+        .buffered(jobs.max(1))
+        .collect()
+        .await;
+
+    let mut failed = 0;
+    for (formula, result) in results {
+        match result {
+            Ok(paths) => {
+                for path in paths {
+                    println!("{}: {}", formula.name, path.display());
+                }
+            }
+            Err(e) => {
+                eprintln!("Error: {}: {}", formula.name, e);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(NitroError::Other(format!("{} formula(e) could not be cached", failed)).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,121 @@
+use anyhow::Result;
+use clap::Args;
+
+#[derive(Args)]
+pub struct CleanupArgs {
+    /// Show what would be removed without removing anything
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+
+    /// Empty the download cache entirely, fetched bottles and sources included,
+    /// instead of only dropping expired entries
+    #[arg(short, long)]
+    pub scrub: bool,
+}
+
+/// Free disk space: old kegs in the Cellar, expired or oversized download cache entries
+/// (or the whole cache with `--scrub`), unused unpacked bottles, week-old workspaces kept
+/// from failed installs, stale formula caches and broken symlinks.
+pub async fn execute(args: CleanupArgs) -> Result<()> {
+    use crate::cache::CacheManager;
+    use crate::core::formula::FormulaManager;
+    use crate::core::installer::{bottle_cache_dir, source_cache_dir, unpacked_bottle_dir, Installer};
+    use crate::core::package::PackageManager;
+    use crate::core::{cleanup, disk, prune, workspace};
+    use indicatif::HumanBytes;
+
+    let verb = if args.dry_run { "Would remove" } else { "Removing" };
+    let quiet = crate::ui::is_quiet();
+    let mut freed = 0;
+
+    // Done before anything else opens the cache's database
+    {
+        let cache = CacheManager::new()?;
+        if args.scrub {
+            let fetched: Vec<_> = [bottle_cache_dir()?, source_cache_dir()?, unpacked_bottle_dir()?].into_iter().filter(|dir| dir.exists()).collect();
+            let size = cache.size().await? + fetched.iter().map(|dir| disk::size_of(dir)).sum::<u64>();
+            if !quiet {
+                println!("{} everything in the download cache ({})", verb, HumanBytes(size));
+            }
+            if !args.dry_run {
+                cache.clear().await?;
+                for dir in &fetched {
+                    std::fs::remove_dir_all(dir)?;
+                }
+            }
+            freed += size;
+        } else {
+            for entry in cache.prune(args.dry_run).await? {
+                if !quiet {
+                    println!("{} {} ({})", verb, entry.path.display(), HumanBytes(entry.size));
+                }
+                freed += entry.size;
+            }
+        }
+    }
+
+    let package_manager = PackageManager::new().await?;
+    let kegs = package_manager.old_kegs()?;
+    for keg in &kegs {
+        if !quiet {
+            println!("{} {} ({})", verb, keg.path.display(), HumanBytes(keg.size));
+        }
+        freed += keg.size;
+    }
+    if !args.dry_run {
+        package_manager.remove_kegs(&kegs)?;
+    }
+
+    // After the old kegs, which may have been the last clones of some of them
+    if !args.scrub {
+        for path in cleanup::unused_unpacked_bottles(&unpacked_bottle_dir()?) {
+            let size = disk::size_of(&path);
+            if !quiet {
+                println!("{} {} ({})", verb, path.display(), HumanBytes(size));
+            }
+            if !args.dry_run {
+                std::fs::remove_dir_all(&path)?;
+            }
+            freed += size;
+        }
+    }
+
+    for path in workspace::stale(&workspace::root(&Installer::prefix()?), workspace::KEPT_WORKSPACE_AGE) {
+        let size = disk::size_of(&path);
+        if !quiet {
+            println!("{} {} ({})", verb, path.display(), HumanBytes(size));
+        }
+        if !args.dry_run {
+            std::fs::remove_dir_all(&path)?;
+        }
+        freed += size;
+    }
+
+    let formula_manager = FormulaManager::new().await?;
+    let stale = formula_manager.stale_cache_entries()?;
+    for path in &stale {
+        let size = std::fs::metadata(path).map_or(0, |m| m.len());
+        if !quiet {
+            println!("{} {} ({})", verb, path.display(), HumanBytes(size));
+        }
+        freed += size;
+    }
+    if !args.dry_run {
+        formula_manager.invalidate_stale_cache()?;
+    }
+
+    let links = prune::broken_links(&prune::link_dirs(&Installer::prefix()?, &Installer::link_dir()?));
+    let links: Vec<&prune::BrokenLink> = if args.dry_run { links.iter().collect() } else { prune::prune(&links)? };
+    if !quiet && !links.is_empty() {
+        println!("{} {} broken symlink(s)", verb, links.len());
+    }
+
+    if !quiet {
+        if args.dry_run {
+            println!("This would free approximately {}", HumanBytes(freed));
+        } else {
+            println!("Freed approximately {}", HumanBytes(freed));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,18 @@
+use anyhow::Result;
+use clap::Args;
+
+#[derive(Args)]
+pub struct DaemonArgs {
+    /// Address to listen on (overrides `daemon.listen` in the config)
+    #[arg(long, value_name = "ADDR")]
+    pub listen: Option<String>,
+}
+
+/// Run in the foreground, serving the job API until interrupted.
+pub async fn execute(args: DaemonArgs) -> Result<()> {
+    let mut config = crate::core::config::Config::load()?.daemon;
+    if let Some(listen) = args.listen {
+        config.listen = listen;
+    }
+    crate::daemon::serve(config).await
+}
@@ -0,0 +1,47 @@
+use anyhow::Result;
+use clap::Args;
+
+#[derive(Args)]
+pub struct DebugBuildArgs {
+    /// Formula to build
+    pub formula: String,
+
+    /// Open the shell before running the install steps, like `brew install --interactive`
+    #[arg(short, long)]
+    pub interactive: bool,
+}
+
+/// Build a formula from source with a shell to fall back on: dependencies install as
+/// usual, then the formula's build directory is handed to the user when its build
+/// fails (or, with `--interactive`, before it starts). The build directory is kept.
+pub async fn execute(args: DebugBuildArgs) -> Result<()> {
+    use crate::cli::commands::install::InstallArgs;
+    use crate::core::debug_build::{self, ShellAt};
+    use crate::core::package::PackageManager;
+    use crate::core::NitroError;
+    use std::io::IsTerminal;
+
+    if !std::io::stdin().is_terminal() {
+        return Err(NitroError::Other("debug-build needs an interactive terminal".into()).into());
+    }
+
+    let package_manager = PackageManager::new().await?;
+    let name = package_manager.resolve_package_formula(&args.formula).await?.name;
+    let packages = vec![name.clone()];
+
+    // Dependencies from bottles where possible; only the formula itself is debugged
+    package_manager.install(&name, &InstallArgs {
+        packages: packages.clone(),
+        only_deps: true,
+        ..Default::default()
+    }).await?;
+
+    debug_build::enable(&name, if args.interactive { ShellAt::Start } else { ShellAt::Failure });
+    crate::core::workspace::keep_all();
+    package_manager.install(&name, &InstallArgs {
+        packages,
+        skip_deps: true,
+        build_from_source: true,
+        ..Default::default()
+    }).await
+}
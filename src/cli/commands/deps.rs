@@ -0,0 +1,229 @@
+use anyhow::Result;
+use clap::Args;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::PathBuf;
+
+use crate::core::formula::{DependencyKind, Formula, FormulaManager};
+use crate::core::resolver::DependencyResolver;
+
+#[derive(Args)]
+pub struct DepsArgs {
+    /// Formulae whose dependencies to show
+    pub formulae: Vec<String>,
+
+    /// Include every installed package
+    #[arg(long)]
+    pub installed: bool,
+
+    /// Include the formulae listed in a Brewfile (`brew "name"` lines)
+    #[arg(long, value_name = "BREWFILE")]
+    pub file: Option<PathBuf>,
+
+    /// Only show formulae without a bottle for the platform, i.e. source builds
+    #[arg(long)]
+    pub missing_bottles: bool,
+
+    /// Platform to check bottles for, as a bottle tag (arm64_sonoma) or platform/arch (linux/x86_64)
+    #[arg(long, requires = "missing_bottles")]
+    pub platform: Option<String>,
+
+    /// Show each formula's dependencies as a tree
+    #[arg(long, conflicts_with = "missing_bottles")]
+    pub tree: bool,
+
+    /// Only show dependencies that are installed
+    #[arg(long)]
+    pub installed_only: bool,
+
+    /// Include build dependencies as well as runtime ones
+    #[arg(long, conflicts_with_all = ["missing_bottles", "inverse"])]
+    pub include_build: bool,
+
+    /// Show what depends on the formulae instead (everything in the index, or with
+    /// --installed-only, installed packages)
+    #[arg(long, conflicts_with = "missing_bottles")]
+    pub inverse: bool,
+}
+
+/// Print the combined runtime dependency closure of the given formulae, one per line so
+/// CI can consume it. With `--missing-bottles`, only the ones that would build from source;
+/// with `--tree`, each formula's dependencies as a tree; with `--inverse`, their dependents.
+pub async fn execute(args: DepsArgs) -> Result<()> {
+    use crate::core::installer::Installer;
+    use crate::core::NitroError;
+    use crate::search::SearchEngine;
+
+    let mut roots = args.formulae.clone();
+    if let Some(path) = &args.file {
+        roots.extend(brewfile_formulae(&std::fs::read_to_string(path)?));
+    }
+    let installed = if args.installed || args.installed_only || args.inverse || args.tree || args.include_build {
+        use crate::core::package::PackageManager;
+
+        PackageManager::new().await?.list_installed(&Default::default()).await?
+    } else {
+        Vec::new()
+    };
+    if args.installed {
+        roots.extend(installed.iter().map(|p| p.name.clone()));
+    }
+    if roots.is_empty() {
+        return Err(NitroError::Other("No formulae given; pass names, --file or --installed".into()).into());
+    }
+
+    let platform = match &args.platform {
+        Some(spec) => crate::core::formula::platform_tag(spec)
+            .ok_or_else(|| NitroError::Other(format!("Unknown platform '{}'", spec)))?,
+        None => Installer::platform_tag(),
+    };
+
+    let search_engine = SearchEngine::new().await?;
+    for root in &roots {
+        if search_engine.lookup(root)?.is_none() {
+            eprintln!("Warning: {} is not in the search index; run `nitro update` if it should be", root);
+        }
+    }
+
+    let installed_names: HashSet<String> = installed.iter().map(|p| p.name.clone()).collect();
+    let shown = |name: &str| !args.installed_only || installed_names.contains(name);
+
+    if args.missing_bottles {
+        for formula in search_engine.missing_bottles(&roots, &platform)? {
+            println!("{}", formula.name);
+        }
+    } else if args.inverse {
+        // Installed packages record their dependencies; anything else comes from the index
+        let dependents = |name: &str| -> Vec<String> {
+            if args.installed_only {
+                installed.iter().filter(|p| p.dependencies.iter().any(|d| d == name)).map(|p| p.name.clone()).collect()
+            } else {
+                search_engine.dependents(name).map(|found| found.into_iter().map(|f| f.name).collect()).unwrap_or_default()
+            }
+        };
+        print_graph(&roots, args.tree, &dependents);
+    } else if args.tree || args.include_build {
+        let graph = dependency_graph(&roots, args.include_build, &installed_names).await?;
+        let children = |name: &str| -> Vec<String> {
+            graph.get(name)
+                .map(|formula| direct_dependencies(formula, args.include_build))
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|dep| shown(dep))
+                .collect()
+        };
+        print_graph(&roots, args.tree, &children);
+    } else {
+        let mut closure = std::collections::BTreeSet::new();
+        for root in &roots {
+            closure.extend(search_engine.dependency_closure(root)?.into_iter().map(|f| f.name));
+        }
+        for name in closure.into_iter().filter(|name| shown(name)) {
+            println!("{}", name);
+        }
+    }
+    Ok(())
+}
+
+/// The formulae `formula` depends on directly: runtime dependencies, and build ones
+/// with `include_build`.
+fn direct_dependencies(formula: &Formula, include_build: bool) -> Vec<String> {
+    let build = formula.build_dependencies.iter().filter(|_| include_build);
+    let mut names: Vec<String> = Vec::new();
+    for dep in formula.dependencies.iter().chain(build) {
+        let wanted = dep.is_runtime() || (include_build && dep.kind() == DependencyKind::Build);
+        if wanted && !names.contains(&dep.name) {
+            names.push(dep.name.clone());
+        }
+    }
+    names
+}
+
+/// Every formula reachable from `roots`, by name, as `DependencyResolver` resolves them
+/// (so virtual dependencies name their provider). The resolver only follows runtime
+/// dependencies below the root; with `include_build`, each formula's build dependencies
+/// are resolved in turn.
+async fn dependency_graph(roots: &[String], include_build: bool, installed: &HashSet<String>) -> Result<BTreeMap<String, Formula>> {
+    let formula_manager = FormulaManager::new().await?;
+    let resolver = DependencyResolver::new();
+
+    let mut graph = BTreeMap::new();
+    let mut pending: Vec<String> = roots.to_vec();
+    while let Some(name) = pending.pop() {
+        if graph.contains_key(&name) {
+            continue;
+        }
+        let formula = match formula_manager.get_formula(&name).await {
+            Ok(formula) => formula,
+            Err(e) => {
+                eprintln!("Warning: could not load {}: {}", name, e);
+                continue;
+            }
+        };
+        for dep in resolver.resolve(&formula, &formula_manager, installed).await? {
+            if include_build {
+                pending.extend(direct_dependencies(&dep, true));
+            }
+            graph.entry(dep.name.clone()).or_insert(dep);
+        }
+        graph.insert(name, formula);
+    }
+    Ok(graph)
+}
+
+/// Print what `next` gives for each root: the whole closure, one name per line, or with
+/// `tree`, a tree under each root.
+fn print_graph(roots: &[String], tree: bool, next: &dyn Fn(&str) -> Vec<String>) {
+    if tree {
+        for root in roots {
+            for line in render_tree(root, next) {
+                println!("{}", line);
+            }
+        }
+        return;
+    }
+    let mut closure = BTreeSet::new();
+    let mut queue: Vec<String> = roots.iter().flat_map(|root| next(root)).collect();
+    while let Some(name) = queue.pop() {
+        if closure.insert(name.clone()) {
+            queue.extend(next(&name));
+        }
+    }
+    for name in closure {
+        println!("{}", name);
+    }
+}
+
+/// `root` and everything below it by `children`, drawn as a tree. A formula already on
+/// the path to it is marked as a cycle rather than followed.
+pub fn render_tree(root: &str, children: &dyn Fn(&str) -> Vec<String>) -> Vec<String> {
+    fn walk(name: &str, prefix: &str, path: &mut Vec<String>, children: &dyn Fn(&str) -> Vec<String>, lines: &mut Vec<String>) {
+        let below = children(name);
+        for (i, child) in below.iter().enumerate() {
+            let last = i + 1 == below.len();
+            let branch = if last { "└── " } else { "├── " };
+            if path.contains(child) {
+                lines.push(format!("{}{}{} (cycle)", prefix, branch, child));
+                continue;
+            }
+            lines.push(format!("{}{}{}", prefix, branch, child));
+            path.push(child.clone());
+            walk(child, &format!("{}{}", prefix, if last { "    " } else { "│   " }), path, children, lines);
+            path.pop();
+        }
+    }
+
+    let mut lines = vec![root.to_string()];
+    walk(root, "", &mut vec![root.to_string()], children, &mut lines);
+    lines
+}
+
+/// Formula names from a Brewfile's `brew "name"` entries. Tap-qualified names
+/// (`user/tap/name`) are reduced to the formula name; other entries are ignored.
+pub fn brewfile_formulae(content: &str) -> Vec<String> {
+    let entry = regex::Regex::new(r#"^\s*brew\s+["']([^"']+)["']"#).unwrap();
+    content
+        .lines()
+        .filter_map(|line| entry.captures(line))
+        .map(|cap| cap[1].rsplit('/').next().unwrap_or(&cap[1]).to_string())
+        .collect()
+}
@@ -0,0 +1,147 @@
+use anyhow::Result;
+use clap::{Args, Subcommand};
+use std::path::Path;
+use std::time::Duration;
+
+#[derive(Args)]
+pub struct DevArgs {
+    #[command(subcommand)]
+    pub command: DevCommands,
+}
+
+#[derive(Subcommand)]
+pub enum DevCommands {
+    /// Watch a tap's formulae, re-parsing and re-indexing them as they change
+    Watch {
+        /// Tap name (e.g. user/repo) or path to a local tap checkout
+        tap: String,
+    },
+}
+
+/// Editors write files in bursts (temp file, rename, chmod); wait this long for things to settle
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+pub async fn execute(args: DevArgs) -> Result<()> {
+    match args.command {
+        DevCommands::Watch { tap } => watch(&tap).await,
+    }
+}
+
+async fn watch(tap: &str) -> Result<()> {
+    use crate::core::formula::FormulaParser;
+    use crate::core::interrupt;
+    use crate::core::tap::TapManager;
+    use crate::core::NitroError;
+    use crate::search::SearchEngine;
+    use notify::{RecursiveMode, Watcher};
+
+    let (tap_name, tap_path) = if Path::new(tap).is_dir() {
+        let path = std::fs::canonicalize(tap)?;
+        let tap_manager = TapManager::new().await?;
+        let name = tap_manager.tap_for_path(&path).await.unwrap_or_else(|| tap.to_string());
+        (name, path)
+    } else {
+        let tap_manager = TapManager::new().await?;
+        let found = tap_manager.list_taps().await?
+            .into_iter()
+            .find(|t| t.name == tap)
+            .ok_or_else(|| NitroError::TapError(format!("Tap {} not found", tap)))?;
+        (found.name, found.path)
+    };
+
+    let formula_dir = tap_path.join("Formula");
+    let watch_dir = if formula_dir.is_dir() { formula_dir } else { tap_path.clone() };
+
+    let parser = FormulaParser::new();
+    let search_engine = SearchEngine::new().await?;
+    let mut writer = search_engine.writer()?;
+
+    // Report the current state once so problems that predate the session are visible too
+    let mut errors = 0;
+    let mut total = 0;
+    for entry in walkdir::WalkDir::new(&watch_dir).into_iter().flatten() {
+        if is_formula_file(entry.path()) {
+            total += 1;
+            if let Err(e) = parser.parse_file(entry.path()).await {
+                errors += 1;
+                println!("✗ {}: {}", display_path(entry.path(), &tap_path), e);
+            }
+        }
+    }
+    println!("👀 Watching {} ({} formulae, {} with errors). Press Ctrl-C to stop.", watch_dir.display(), total, errors);
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if let Ok(event) = event {
+            for path in event.paths {
+                let _ = tx.send(path);
+            }
+        }
+    })?;
+    watcher.watch(&watch_dir, RecursiveMode::Recursive)?;
+
+    loop {
+        let first = tokio::select! {
+            path = rx.recv() => path,
+            _ = interrupt::wait() => break,
+        };
+        let Some(first) = first else {
+            break;
+        };
+
+        // Collect the rest of the burst
+        let mut changed = vec![first];
+        while let Ok(Some(path)) = tokio::time::timeout(DEBOUNCE, rx.recv()).await {
+            changed.push(path);
+        }
+        changed.sort();
+        changed.dedup();
+
+        let mut touched_index = false;
+        for path in changed.iter().filter(|p| is_formula_file(p)) {
+            let label = display_path(path, &tap_path);
+            if !path.exists() {
+                search_engine.remove_path(&mut writer, path);
+                touched_index = true;
+                println!("− {} removed", label);
+                continue;
+            }
+
+            match parser.parse_file(path).await {
+                Ok(formula) => {
+                    search_engine.update_formula(&mut writer, &formula, &tap_name, path)?;
+                    touched_index = true;
+
+                    let mut notes = Vec::new();
+                    if formula.sources.is_empty() {
+                        notes.push("no url/sha256".to_string());
+                    }
+                    if formula.version == "unknown" {
+                        notes.push("version not detected".to_string());
+                    }
+                    if notes.is_empty() {
+                        println!("✓ {} {} {}", label, formula.name, formula.version);
+                    } else {
+                        println!("⚠ {} {} {} ({})", label, formula.name, formula.version, notes.join(", "));
+                    }
+                }
+                Err(e) => println!("✗ {}: {}", label, e),
+            }
+        }
+
+        if touched_index {
+            writer.commit()?;
+        }
+    }
+
+    println!("Stopped watching {}", tap_name);
+    Ok(())
+}
+
+fn is_formula_file(path: &Path) -> bool {
+    path.extension().and_then(|s| s.to_str()) == Some("rb")
+}
+
+fn display_path(path: &Path, root: &Path) -> String {
+    path.strip_prefix(root).unwrap_or(path).display().to_string()
+}
@@ -0,0 +1,36 @@
+use anyhow::Result;
+use clap::Args;
+
+#[derive(Args)]
+pub struct DoctorArgs {
+    /// Print the checks as JSON, with their IDs, severities and remediations
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// Check the environment for problems that break installs, starting with the network,
+/// then the links in the prefix.
+/// Fails when a check finds an error; warnings alone don't.
+pub async fn execute(args: DoctorArgs) -> Result<()> {
+    use crate::core::doctor::{self, CheckStatus};
+    use crate::core::installer::Installer;
+    use crate::core::NitroError;
+    use crate::download::Downloader;
+
+    if !args.json {
+        println!("Checking network...");
+    }
+    let mut checks = doctor::network_checks(&Downloader::shared()?).await;
+    checks.extend(doctor::link_checks(&Installer::prefix()?, &Installer::link_dir()?));
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&doctor::report_json(&checks))?);
+    } else {
+        crate::ui::display::show_checks(&checks);
+    }
+
+    let errors = checks.iter().filter(|c| c.status == CheckStatus::Error).count();
+    if errors > 0 {
+        return Err(NitroError::Other(format!("{} problem(s) found", errors)).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,45 @@
+use anyhow::Result;
+use clap::{Args, Subcommand};
+
+#[derive(Args)]
+pub struct EnvArgs {
+    #[command(subcommand)]
+    pub command: EnvCommands,
+}
+
+#[derive(Subcommand)]
+pub enum EnvCommands {
+    /// Check that the commands nitro links are the ones PATH finds, not a system or
+    /// Homebrew binary of the same name earlier on PATH
+    Doctor {
+        /// Print the checks as JSON, with their IDs, severities and remediations
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+pub async fn execute(args: EnvArgs) -> Result<()> {
+    use crate::core::doctor::{self, CheckStatus};
+    use crate::core::installer::Installer;
+    use crate::core::NitroError;
+
+    match args.command {
+        EnvCommands::Doctor { json } => {
+            let link_dir = Installer::link_dir()?;
+            let commands = doctor::linked_commands(&link_dir, &Installer::prefix()?.join("Cellar"));
+            let path = std::env::var_os("PATH").unwrap_or_default();
+            let checks = doctor::path_checks(&link_dir, &commands, &path);
+            if json {
+                println!("{}", serde_json::to_string_pretty(&doctor::report_json(&checks))?);
+            } else {
+                crate::ui::display::show_checks(&checks);
+            }
+
+            let errors = checks.iter().filter(|c| c.status == CheckStatus::Error).count();
+            if errors > 0 {
+                return Err(NitroError::Other(format!("{} problem(s) found", errors)).into());
+            }
+        }
+    }
+    Ok(())
+}
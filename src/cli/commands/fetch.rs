@@ -0,0 +1,125 @@
+use anyhow::Result;
+use clap::Args;
+use std::path::PathBuf;
+
+use crate::core::formula::FormulaManager;
+use crate::core::installer::Installer;
+
+#[derive(Args)]
+pub struct FetchArgs {
+    /// Formulae whose bottles to download
+    #[arg(required = true)]
+    pub formulae: Vec<String>,
+
+    /// Fetch every dependency too
+    #[arg(long)]
+    pub deps: bool,
+
+    /// Fetch source archives and resources rather than bottles. Formulae without a
+    /// bottle for a platform have their sources fetched either way
+    #[arg(short = 's', long)]
+    pub build_from_source: bool,
+
+    /// Platform to fetch for, as platform/arch (darwin/aarch64) or a bottle tag
+    /// (arm64_sonoma); repeat for several. Defaults to this machine's platform
+    #[arg(long = "platform", value_name = "PLATFORM")]
+    pub platforms: Vec<String>,
+
+    /// Directory to store downloads in (defaults to nitro's bottle and source caches,
+    /// where installs look for them)
+    #[arg(short, long)]
+    pub output_dir: Option<PathBuf>,
+}
+
+/// Download and verify bottles (or sources) without installing them, printing where
+/// each went, so one machine can seed the caches or mirror of a fleet running other
+/// platforms, a CI runner or an air-gapped machine.
+pub async fn execute(args: FetchArgs) -> Result<()> {
+    use crate::download::Downloader;
+
+    let formula_manager = FormulaManager::new().await?;
+    let installer = Installer::new(Downloader::shared()?)?;
+    fetch(&args, &formula_manager, &installer).await
+}
+
+/// `nitro fetch` with the formulae looked up in `formula_manager` and downloaded by
+/// `installer`.
+pub async fn fetch(args: &FetchArgs, formula_manager: &FormulaManager, installer: &Installer) -> Result<()> {
+    use crate::core::formula::{platform_tag, Formula};
+    use crate::core::installer::{bottle_cache_dir, bottle_for, source_cache_dir};
+    use crate::core::resolver::DependencyResolver;
+    use crate::core::NitroError;
+
+    let mut platforms = Vec::new();
+    for spec in &args.platforms {
+        let tag = platform_tag(spec)
+            .ok_or_else(|| NitroError::Other(format!("Unknown platform '{}'", spec)))?;
+        if !platforms.contains(&tag) {
+            platforms.push(tag);
+        }
+    }
+    if platforms.is_empty() {
+        platforms.push(Installer::platform_tag());
+    }
+
+    let (bottle_dir, source_dir) = match &args.output_dir {
+        Some(dir) => (dir.clone(), dir.clone()),
+        None => (bottle_cache_dir()?, source_cache_dir()?),
+    };
+
+    let mut formulae: Vec<Formula> = Vec::new();
+    for name in &args.formulae {
+        let formula = formula_manager.get_formula(name).await?;
+        let deps = if args.deps {
+            DependencyResolver::new().resolve(&formula, formula_manager, &Default::default()).await?
+        } else {
+            vec![]
+        };
+        for formula in deps.into_iter().chain([formula]) {
+            if !formulae.iter().any(|f| f.name == formula.name) {
+                formulae.push(formula);
+            }
+        }
+    }
+
+    let transfers = crate::ui::progress::TransferSummary::start();
+    let mut failed = 0;
+    for formula in &formulae {
+        // Sources are the same on every platform, so they're fetched at most once
+        let mut wants_sources = args.build_from_source;
+        if !args.build_from_source {
+            for platform in &platforms {
+                if bottle_for(formula, platform).is_none() {
+                    wants_sources = true;
+                    continue;
+                }
+                match installer.fetch_bottle(formula, platform, &bottle_dir).await {
+                    Ok(path) => println!("Fetched {} for {}: {}", formula.name, platform, path.display()),
+                    Err(e) => {
+                        eprintln!("Error: {}", e);
+                        failed += 1;
+                    }
+                }
+            }
+        }
+        if wants_sources {
+            match installer.fetch_sources(formula, &source_dir).await {
+                Ok(paths) => {
+                    for path in paths {
+                        println!("Fetched {} source: {}", formula.name, path.display());
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    transfers.finish();
+    if failed > 0 {
+        return Err(NitroError::Other(format!("{} download(s) could not be fetched", failed)).into());
+    }
+    Ok(())
+}
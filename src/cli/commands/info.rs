@@ -14,14 +14,67 @@ pub struct InfoArgs {
     /// Show all versions
     #[arg(long)]
     pub all_versions: bool,
+
+    /// Also summarize the full dependency closure (a read-only install plan)
+    #[arg(long)]
+    pub with_deps: bool,
+
+    /// Describe a cask rather than a formula
+    #[arg(long)]
+    pub cask: bool,
+
+    /// Print where the formula's .rb file can be read on its tap's GitHub, at the
+    /// commit nitro uses
+    #[arg(long, conflicts_with = "cask")]
+    pub github: bool,
+
+    /// Print the project's upstream repository
+    #[arg(long, conflicts_with = "cask")]
+    pub upstream: bool,
+
+    /// Open the --github and --upstream pages in the browser instead of printing them
+    #[arg(long)]
+    pub open: bool,
 }
 
 pub async fn execute(args: InfoArgs) -> Result<()> {
     use crate::core::formula::FormulaManager;
+    use crate::core::package::PackageManager;
     use crate::ui::display;
 
-    let formula_manager = FormulaManager::new().await?;
+    // The package database is only needed for dependency annotations, so info still
+    // works (unannotated) when it can't be opened
+    let package_manager = PackageManager::new().await.ok();
+    let standalone;
+    let formula_manager = match &package_manager {
+        Some(pm) => pm.formula_manager(),
+        None => {
+            standalone = FormulaManager::new().await?;
+            &standalone
+        }
+    };
     
+    if args.cask {
+        let installed = match &package_manager {
+            Some(pm) => pm.installed_cask(&args.package)?,
+            None => None,
+        };
+        let cask = match formula_manager.tap_manager().find_cask(&args.package).await {
+            Ok(path) => Some(crate::core::cask::CaskParser::new().parse_file(&path)?),
+            Err(e) if installed.is_none() => return Err(e.into()),
+            Err(_) => None,
+        };
+        if args.json {
+            println!("{}", serde_json::to_string_pretty(&serde_json::json!({
+                "cask": cask,
+                "installed": installed,
+            }))?);
+        } else {
+            display::show_cask_info(cask.as_ref(), installed.as_ref());
+        }
+        return Ok(());
+    }
+
     // Try common aliases first
     let package_name = match args.package.as_str() {
         "python" => "python@3.12",
@@ -35,20 +88,82 @@ pub async fn execute(args: InfoArgs) -> Result<()> {
         _ => &args.package,
     };
     
-    let formula = match formula_manager.get_formula(package_name).await {
-        Ok(f) => f,
-        Err(e) if package_name != args.package => {
-            // If alias failed, try original name
-            formula_manager.get_formula(&args.package).await?
-        }
-        Err(e) => return Err(e.into()),
+    // An installed package is described from the tap it was installed from
+    let installed = match &package_manager {
+        Some(pm) => pm.installed_package(&args.package)?.or(pm.installed_package(package_name)?),
+        None => None,
     };
+    let formula = match (&package_manager, &installed) {
+        (Some(pm), Some(package)) => pm.installed_formula(package).await?,
+        _ => match formula_manager.get_formula(package_name).await {
+            Ok(f) => f,
+            Err(_) if package_name != args.package => {
+                // If alias failed, try original name
+                formula_manager.get_formula(&args.package).await?
+            }
+            Err(e) => return Err(e.into()),
+        },
+    };
+
+    if args.github || args.upstream {
+        return show_sources(&formula, formula_manager, &args).await;
+    }
 
-    if args.json {
+    if args.with_deps {
+        let Some(pm) = &package_manager else {
+            return Err(crate::core::NitroError::Other("Could not open the package database".into()).into());
+        };
+        let plan = pm.install_plan(&formula).await?;
+        if args.json {
+            println!("{}", serde_json::to_string_pretty(&serde_json::json!({
+                "formula": formula,
+                "plan": plan,
+            }))?);
+        } else {
+            display::show_formula_info(&formula, &pm.dependency_status(&formula).await?, &args);
+            display::show_install_plan(&plan);
+        }
+    } else if args.json {
         println!("{}", serde_json::to_string_pretty(&formula)?);
     } else {
-        display::show_formula_info(&formula, &args);
+        let dependencies = match &package_manager {
+            Some(pm) => pm.dependency_status(&formula).await?,
+            None => vec![],
+        };
+        display::show_formula_info(&formula, &dependencies, &args);
+        if let Some(package) = &installed {
+            display::show_package_info(package);
+        }
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Print (or with `--open`, open) the pages `--github` and `--upstream` ask for.
+async fn show_sources(formula: &crate::core::formula::Formula, formula_manager: &crate::core::formula::FormulaManager, args: &InfoArgs) -> Result<()> {
+    use crate::core::NitroError;
+
+    let mut urls = Vec::new();
+    if args.github {
+        // Links still work out without the API (no config, offline), just less exactly
+        let github = crate::download::github::GitHubClient::new().ok();
+        let url = formula_manager.tap_manager().formula_github_url(formula, github.as_ref()).await?;
+        urls.push(url.ok_or_else(|| NitroError::Other(match &formula.tap {
+            Some(tap) => format!("{} comes from {}, which isn't hosted on GitHub", formula.name, tap),
+            None => format!("{} wasn't read from a tap's formula file, so there's none to link to", formula.name),
+        }))?);
+    }
+    if args.upstream {
+        urls.push(formula.upstream_repository().ok_or_else(|| NitroError::Other(format!(
+            "Could not tell where {} is developed from its URLs", formula.name
+        )))?);
+    }
+    for url in urls {
+        if args.open {
+            crate::ui::open_url(&url)?;
+        } else {
+            println!("{}", url);
+        }
+    }
+    Ok(())
+}
@@ -1,7 +1,7 @@
 use anyhow::Result;
 use clap::Args;
 
-#[derive(Args)]
+#[derive(Args, Default)]
 pub struct InstallArgs {
     /// Package name(s) to install
     #[arg(required = true)]
@@ -15,18 +15,68 @@ pub struct InstallArgs {
     #[arg(long)]
     pub build_from_source: bool,
 
-    /// Don't install dependencies
-    #[arg(long)]
+    /// Install only the dependencies, not the packages themselves
+    #[arg(long, visible_alias = "only-dependencies", conflicts_with = "skip_deps")]
     pub only_deps: bool,
 
-    /// Install only dependencies
-    #[arg(long)]
+    /// Install the packages without any of their dependencies
+    #[arg(long, visible_alias = "ignore-dependencies")]
     pub skip_deps: bool,
 
+    /// Also install the packages' optional dependencies
+    #[arg(long, conflicts_with = "skip_deps")]
+    pub include_optional: bool,
+
+    /// Also install the dependencies the packages' tests need
+    #[arg(long, conflicts_with = "skip_deps")]
+    pub include_test: bool,
+
     /// Use specific version
     #[arg(short, long)]
     pub version: Option<String>,
 
+    /// Install even if the disk space check says there isn't enough room
+    #[arg(long)]
+    pub skip_space_check: bool,
+
+    /// Install casks rather than formulae
+    #[arg(long)]
+    pub cask: bool,
+
+    /// Directory to install cask fonts into (defaults to the user's font directory;
+    /// /Library/Fonts or /usr/local/share/fonts installs them for all users)
+    #[arg(long, value_name = "DIR")]
+    pub fontdir: Option<std::path::PathBuf>,
+
+    /// Directory to install cask applications into (defaults to /Applications)
+    #[arg(long, value_name = "DIR")]
+    pub appdir: Option<std::path::PathBuf>,
+
+    /// Accept a source download with this SHA-256 when it doesn't match the formula's
+    /// (for tarballs re-rolled upstream). Recorded in the package's receipt.
+    #[arg(long, value_name = "HASH")]
+    pub sha256_override: Option<String>,
+
+    /// Replace files and links in the link directory that aren't nitro's (say, a
+    /// manually installed tool) instead of refusing to link over them
+    #[arg(long)]
+    pub overwrite: bool,
+
+    /// Keep the download and build directories of each install instead of removing
+    /// them (they're always kept when an install fails)
+    #[arg(long)]
+    pub keep_tmp: bool,
+
+    /// Give up on each package's install after this long (e.g. 90s, 30m, 2h), rolling
+    /// it back; covers downloading, unpacking and building
+    #[arg(long, value_name = "DURATION", value_parser = crate::core::cancel::parse_duration)]
+    pub timeout: Option<std::time::Duration>,
+
+    /// Carry on with the remaining packages when one fails, then list what was and
+    /// wasn't installed. Exits with status 2 if only some failed
+    #[arg(long)]
+    pub keep_going: bool,
+
     /// Run installation in verbose mode
     #[arg(long)]
     pub debug: bool,
@@ -34,25 +84,62 @@ pub struct InstallArgs {
 
 pub async fn execute(args: InstallArgs) -> Result<()> {
     use crate::core::package::PackageManager;
-    use crate::ui::progress::ProgressReporter;
+    use crate::core::NitroError;
+    use crate::ui::progress::{ProgressReporter, TransferSummary};
+
+    if args.keep_tmp {
+        crate::core::workspace::keep_all();
+    }
+    if args.overwrite {
+        crate::core::installer::allow_overwrite();
+    }
 
     let progress = ProgressReporter::new();
-    let package_manager = PackageManager::new().await?;
+    let transfers = TransferSummary::start();
+    let mut package_manager = PackageManager::new().await?;
+    package_manager.set_progress(progress.clone());
+
+    let pending = package_manager.pending_installs()?;
+    if !pending.is_empty() {
+        let names: Vec<_> = pending.iter().map(|r| r.formula.name.as_str()).collect();
+        eprintln!("Warning: interrupted install(s) found: {}", names.join(", "));
+        eprintln!("Run 'nitro resume' to finish them or 'nitro abort' to roll them back.");
+    }
 
-    for package_name in &args.packages {
+    let mut packages = args.packages.clone();
+    crate::core::deterministic::sort_by_key(&mut packages, |name| name.clone());
+    let (mut installed, mut failed) = (Vec::new(), Vec::new());
+    for package_name in &packages {
         progress.start_package(package_name);
         
-        match package_manager.install(package_name, &args).await {
-            Ok(_) => progress.complete_package(package_name),
+        let result = if args.cask {
+            package_manager.install_cask(package_name, &args).await
+        } else {
+            package_manager.install(package_name, &args).await
+        };
+        match result {
+            Ok(_) => {
+                progress.complete_package(package_name);
+                installed.push(package_name.clone());
+            }
             Err(e) => {
                 progress.fail_package(package_name, &crate::core::NitroError::Other(e.to_string()));
-                if !args.force {
+                if !args.keep_going || crate::core::interrupt::is_interrupted() {
+                    progress.finish();
                     return Err(e);
                 }
+                failed.push(package_name.clone());
             }
         }
     }
 
     progress.finish();
+    transfers.finish();
+    if args.keep_going {
+        crate::ui::display::show_installation_summary(&installed, &failed);
+    }
+    if !failed.is_empty() {
+        return Err(NitroError::PartialFailure { failed: failed.len(), total: packages.len() }.into());
+    }
     Ok(())
 }
\ No newline at end of file
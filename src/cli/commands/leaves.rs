@@ -0,0 +1,29 @@
+use anyhow::Result;
+use clap::Args;
+
+#[derive(Args)]
+pub struct LeavesArgs {
+    /// Only list packages that were asked for by name
+    #[arg(short = 'r', long, conflicts_with = "installed_as_dependency")]
+    pub installed_on_request: bool,
+
+    /// Only list packages that were installed as dependencies
+    #[arg(short = 'p', long)]
+    pub installed_as_dependency: bool,
+}
+
+/// List installed packages that no other installed package depends on.
+pub async fn execute(args: LeavesArgs) -> Result<()> {
+    use crate::core::package::PackageManager;
+
+    let package_manager = PackageManager::new().await?;
+    for package in package_manager.leaves()? {
+        if (args.installed_on_request && !package.installed_on_request)
+            || (args.installed_as_dependency && package.installed_on_request)
+        {
+            continue;
+        }
+        println!("{}", package.name);
+    }
+    Ok(())
+}
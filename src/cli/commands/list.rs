@@ -1,7 +1,7 @@
 use anyhow::Result;
-use clap::Args;
+use clap::{Args, ValueEnum};
 
-#[derive(Args)]
+#[derive(Args, Default)]
 pub struct ListArgs {
     /// Show all versions
     #[arg(long)]
@@ -18,6 +18,44 @@ pub struct ListArgs {
     /// Filter by prefix
     #[arg(short, long)]
     pub prefix: Option<String>,
+
+    /// Only packages installed from this tap (e.g. user/repo)
+    #[arg(long)]
+    pub tap: Option<String>,
+
+    /// Only packages whose name or description contains all of these words
+    #[arg(short, long)]
+    pub search: Option<String>,
+
+    /// Only list casks
+    #[arg(long, conflicts_with = "formula")]
+    pub cask: bool,
+
+    /// Only list formulae
+    #[arg(long)]
+    pub formula: bool,
+
+    /// How to show the formulae: a few lines each, or one row each in aligned columns
+    #[arg(long, value_enum, default_value = "lines")]
+    pub format: ListFormat,
+
+    /// Order formulae by name, by size (largest first) or by install date (newest first)
+    #[arg(long, value_enum)]
+    pub sort: Option<ListSort>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ListFormat {
+    #[default]
+    Lines,
+    Columns,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ListSort {
+    Name,
+    Size,
+    Date,
 }
 
 pub async fn execute(args: ListArgs) -> Result<()> {
@@ -25,9 +63,81 @@ pub async fn execute(args: ListArgs) -> Result<()> {
     use crate::ui::display;
 
     let package_manager = PackageManager::new().await?;
-    let packages = package_manager.list_installed(&args).await?;
 
-    display::show_package_list(&packages);
+    // Casks are a separate namespace, listed after the formulae
+    let mut casks = if args.formula { vec![] } else { package_manager.installed_casks()? };
+    casks.retain(|cask| {
+        args.prefix.as_ref().is_none_or(|prefix| cask.token.starts_with(prefix))
+            && args.tap.as_ref().is_none_or(|tap| cask.tap.as_ref() == Some(tap))
+            && args.search.as_ref().is_none_or(|query| {
+                query.split_whitespace().all(|word| cask.token.contains(&word.to_lowercase()))
+            })
+    });
+    if args.cask {
+        display::show_cask_list(&casks);
+        return Ok(());
+    }
+
+    let mut packages = package_manager.list_installed(&args).await?;
+    match args.sort {
+        Some(ListSort::Name) => packages.sort_by(|a, b| a.name.cmp(&b.name)),
+        Some(ListSort::Size) => packages.sort_by_cached_key(|p| std::cmp::Reverse(installed_size(p))),
+        Some(ListSort::Date) => packages.sort_by_cached_key(|p| std::cmp::Reverse(installed_at(p))),
+        None => {}
+    }
+
+    if packages.is_empty() && casks.is_empty() {
+        if let Some(query) = &args.search {
+            println!("No installed packages match '{}'", query);
+            return Ok(());
+        }
+    }
+    // With only casks installed there's no empty formula list to report
+    let show_formulae = !packages.is_empty() || casks.is_empty();
+    if show_formulae {
+        match args.format {
+            ListFormat::Lines => display::show_package_list(&packages),
+            ListFormat::Columns => {
+                // Without the formulae to compare against (offline, say) nothing is marked outdated
+                let updates = package_manager.check_updates(&[], args.tap.as_deref()).await.unwrap_or_default();
+                let rows: Vec<_> = packages.iter()
+                    .map(|package| display::ListRow {
+                        name: package.name.clone(),
+                        version: package.installed_version.clone().unwrap_or_else(|| package.version.clone()),
+                        size: installed_size(package),
+                        tap: package.tap.clone(),
+                        pinned: package.pinned,
+                        latest: updates.iter().find(|(name, ..)| *name == package.name).map(|(.., latest)| latest.clone()),
+                    })
+                    .collect();
+                display::show_package_table(&rows);
+            }
+        }
+    }
+    if !casks.is_empty() {
+        if show_formulae {
+            println!();
+        }
+        display::show_cask_list(&casks);
+    }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Bytes the package takes up in the cellar, as recorded or else as measured now.
+fn installed_size(package: &crate::core::package::Package) -> Option<u64> {
+    package.size.or_else(|| {
+        let path = package.install_path.as_deref().filter(|p| p.exists())?;
+        Some(crate::core::disk::size_of(path))
+    })
+}
+
+/// When the package's current version went in: as recorded, or else when its formula
+/// directory in the cellar last changed.
+fn installed_at(package: &crate::core::package::Package) -> Option<std::time::SystemTime> {
+    package.installed_at.map(std::time::SystemTime::from).or_else(|| {
+        package.install_path.as_deref()
+            .and_then(|path| std::fs::metadata(path).ok())
+            .and_then(|metadata| metadata.modified().ok())
+    })
+}
@@ -5,4 +5,28 @@ pub mod list;
 pub mod update;
 pub mod info;
 pub mod tap;
-pub mod homebrew;
\ No newline at end of file
+pub mod homebrew;
+pub mod analytics;
+pub mod stats;
+pub mod cache;
+pub mod resume;
+pub mod abort;
+pub mod brew;
+pub mod dev;
+pub mod shellenv;
+pub mod deps;
+pub mod fetch;
+pub mod doctor;
+pub mod upgrade;
+pub mod daemon;
+pub mod debug_build;
+pub mod shellrc;
+pub mod outdated;
+pub mod prune;
+pub mod audit_log;
+pub mod leaves;
+pub mod autoremove;
+pub mod cleanup;
+pub mod pin;
+pub mod env;
+pub mod services;
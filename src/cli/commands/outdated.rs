@@ -0,0 +1,168 @@
+use anyhow::Result;
+use clap::Args;
+use serde::Serialize;
+
+use crate::core::package::PackageManager;
+
+#[derive(Args, Default)]
+pub struct OutdatedArgs {
+    /// Formulae or casks to check (checks everything installed if not specified)
+    pub packages: Vec<String>,
+
+    /// Only check casks
+    #[arg(long)]
+    pub cask: bool,
+
+    /// Only check formulae installed from this tap (e.g. user/repo)
+    #[arg(long)]
+    pub tap: Option<String>,
+
+    #[command(flatten)]
+    pub greedy: GreedyArgs,
+
+    /// Print the outdated formulae and casks as JSON
+    #[arg(long)]
+    pub json: bool,
+
+    /// Refresh tap metadata and the index first, as `nitro update` does. Nothing is
+    /// upgraded either way
+    #[arg(long)]
+    pub fetch_only: bool,
+}
+
+/// Flags choosing which casks count as outdated though their version can't say so.
+#[derive(Args, Default, Clone, Copy)]
+pub struct GreedyArgs {
+    /// Also include casks that update themselves or whose version is `latest`
+    #[arg(long)]
+    pub greedy: bool,
+
+    /// Also include casks whose version is `latest`
+    #[arg(long)]
+    pub greedy_latest: bool,
+
+    /// Also include casks that update themselves
+    #[arg(long)]
+    pub greedy_auto_updates: bool,
+}
+
+impl GreedyArgs {
+    pub fn greedy(&self) -> crate::core::cask::Greedy {
+        crate::core::cask::Greedy {
+            latest: self.greedy || self.greedy_latest,
+            auto_updates: self.greedy || self.greedy_auto_updates,
+        }
+    }
+}
+
+/// An installed formula or cask with a newer version available.
+#[derive(Debug, Clone, Serialize)]
+pub struct OutdatedPackage {
+    pub name: String,
+    pub installed_version: String,
+    pub current_version: String,
+}
+
+/// What `outdated` reports and `upgrade` acts on.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Outdated {
+    pub formulae: Vec<OutdatedPackage>,
+    pub casks: Vec<OutdatedPackage>,
+}
+
+impl Outdated {
+    /// The outdated packages among `packages` (everything installed when empty). Named
+    /// packages that aren't installed formulae are looked for among the casks.
+    pub async fn find(
+        package_manager: &PackageManager,
+        packages: &[String],
+        cask_only: bool,
+        tap: Option<&str>,
+        greedy: crate::core::cask::Greedy,
+    ) -> Result<Self> {
+        let mut formula_names = Vec::new();
+        let mut cask_names = Vec::new();
+        for name in packages {
+            if !cask_only && package_manager.installed_package(name)?.is_some() {
+                formula_names.push(name.clone());
+            } else {
+                cask_names.push(name.clone());
+            }
+        }
+        let everything = packages.is_empty();
+        let entries = |updates: Vec<(String, String, String)>| -> Vec<OutdatedPackage> {
+            updates.into_iter()
+                .map(|(name, installed_version, current_version)| OutdatedPackage { name, installed_version, current_version })
+                .collect()
+        };
+
+        let formulae = if !cask_only && (everything || !formula_names.is_empty()) {
+            entries(package_manager.check_updates(&formula_names, tap).await?)
+        } else {
+            vec![]
+        };
+        // Casks don't come from taps nitro tracks, so scoping to a tap leaves them out
+        let casks = if tap.is_none() && (everything || !cask_names.is_empty()) {
+            entries(package_manager.outdated_casks(&cask_names, greedy).await?)
+        } else {
+            vec![]
+        };
+        Ok(Self { formulae, casks })
+    }
+
+    /// Take out the formulae held with `nitro pin`, which `upgrade` leaves alone unless
+    /// forced.
+    pub fn take_pinned(&mut self, package_manager: &PackageManager) -> Result<Vec<OutdatedPackage>> {
+        let mut pinned = Vec::new();
+        let mut formulae = Vec::new();
+        for package in std::mem::take(&mut self.formulae) {
+            if package_manager.installed_package(&package.name)?.is_some_and(|p| p.pinned) {
+                pinned.push(package);
+            } else {
+                formulae.push(package);
+            }
+        }
+        self.formulae = formulae;
+        Ok(pinned)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.formulae.is_empty() && self.casks.is_empty()
+    }
+
+    /// One line per package, `name installed -> current`, or just the names when quiet.
+    pub fn print(&self) {
+        for package in self.formulae.iter().chain(&self.casks) {
+            if crate::ui::is_quiet() {
+                println!("{}", package.name);
+            } else {
+                println!("  {} {} -> {}", package.name, package.installed_version, package.current_version);
+            }
+        }
+    }
+}
+
+/// Report installed formulae and casks with newer versions. Changes nothing unless
+/// `--fetch-only` asks for the metadata to be refreshed first.
+pub async fn execute(args: OutdatedArgs) -> Result<()> {
+    if args.fetch_only {
+        super::update::refresh(args.tap.as_deref(), false).await?;
+    }
+
+    let package_manager = PackageManager::new().await?;
+    let outdated = Outdated::find(&package_manager, &args.packages, args.cask, args.tap.as_deref(), args.greedy.greedy()).await?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&outdated)?);
+    } else if outdated.is_empty() {
+        if !crate::ui::is_quiet() {
+            println!("Everything is up to date");
+        }
+    } else {
+        if !crate::ui::is_quiet() {
+            println!("Outdated:");
+        }
+        outdated.print();
+    }
+    Ok(())
+}
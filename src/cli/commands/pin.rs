@@ -0,0 +1,46 @@
+use anyhow::Result;
+use clap::Args;
+
+#[derive(Args)]
+pub struct PinArgs {
+    /// Installed packages to pin
+    #[arg(required = true)]
+    pub packages: Vec<String>,
+}
+
+#[derive(Args)]
+pub struct UnpinArgs {
+    /// Pinned packages to unpin
+    #[arg(required = true)]
+    pub packages: Vec<String>,
+}
+
+/// Hold installed packages at their current version; `upgrade` skips them unless
+/// given `--force`.
+pub async fn execute(args: PinArgs) -> Result<()> {
+    set_pinned(&args.packages, true).await
+}
+
+/// Let pinned packages be upgraded again.
+pub async fn execute_unpin(args: UnpinArgs) -> Result<()> {
+    set_pinned(&args.packages, false).await
+}
+
+async fn set_pinned(packages: &[String], pinned: bool) -> Result<()> {
+    use crate::core::package::PackageManager;
+
+    let package_manager = PackageManager::new().await?;
+    for name in packages {
+        let package = package_manager.set_pinned(name, pinned)?;
+        if crate::ui::is_quiet() {
+            continue;
+        }
+        let version = package.installed_version.unwrap_or(package.version);
+        if pinned {
+            println!("Pinned {} at {}", name, version);
+        } else {
+            println!("Unpinned {} ({})", name, version);
+        }
+    }
+    Ok(())
+}
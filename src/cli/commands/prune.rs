@@ -0,0 +1,47 @@
+use anyhow::Result;
+use clap::Args;
+
+#[derive(Args)]
+pub struct PruneArgs {
+    /// Show the broken links without removing them
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Print the broken links as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// Remove dangling symlinks from the link directories, naming the formula each one
+/// belonged to where its target says.
+pub async fn execute(args: PruneArgs) -> Result<()> {
+    use crate::core::installer::Installer;
+    use crate::core::prune;
+
+    let links = prune::broken_links(&prune::link_dirs(&Installer::prefix()?, &Installer::link_dir()?));
+    let removed: Vec<&prune::BrokenLink> = if args.dry_run { links.iter().collect() } else { prune::prune(&links)? };
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&serde_json::json!({
+            "dry_run": args.dry_run,
+            "links": removed,
+        }))?);
+        return Ok(());
+    }
+    if crate::ui::is_quiet() {
+        return Ok(());
+    }
+
+    if removed.is_empty() {
+        println!("No broken symlinks found");
+        return Ok(());
+    }
+    println!("{} {} broken symlink(s):", if args.dry_run { "Would remove" } else { "Removed" }, removed.len());
+    for link in removed {
+        match &link.owner {
+            Some(owner) => println!("  {} -> {} ({})", link.link.display(), link.target.display(), owner),
+            None => println!("  {} -> {}", link.link.display(), link.target.display()),
+        }
+    }
+    Ok(())
+}
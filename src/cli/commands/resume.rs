@@ -0,0 +1,45 @@
+use anyhow::Result;
+use clap::Args;
+
+#[derive(Args)]
+pub struct ResumeArgs {
+    /// Package name(s) to resume (resumes all interrupted installs if not specified)
+    pub packages: Vec<String>,
+}
+
+pub async fn execute(args: ResumeArgs) -> Result<()> {
+    use crate::core::package::PackageManager;
+    use crate::ui::progress::ProgressReporter;
+
+    let mut package_manager = PackageManager::new().await?;
+    let pending: Vec<_> = package_manager.pending_installs()?
+        .into_iter()
+        .filter(|r| args.packages.is_empty() || args.packages.contains(&r.formula.name))
+        .collect();
+
+    if pending.is_empty() {
+        println!("No interrupted installs to resume");
+        return Ok(());
+    }
+
+    let progress = ProgressReporter::new();
+    package_manager.set_progress(progress.clone());
+
+    for record in &pending {
+        let name = &record.formula.name;
+        println!("Resuming {} {} (last phase: {})", name, record.formula.version, record.phase);
+        progress.start_package(name);
+
+        match package_manager.resume(record).await {
+            Ok(_) => progress.complete_package(name),
+            Err(e) => {
+                progress.fail_package(name, &crate::core::NitroError::Other(e.to_string()));
+                progress.finish();
+                return Err(e);
+            }
+        }
+    }
+
+    progress.finish();
+    Ok(())
+}
@@ -1,6 +1,9 @@
 use anyhow::Result;
 use clap::Args;
 
+use crate::core::NitroError;
+use crate::search::SearchResult;
+
 #[derive(Args)]
 pub struct SearchArgs {
     /// Search query
@@ -18,6 +21,99 @@ pub struct SearchArgs {
     /// Maximum number of results
     #[arg(short, long, default_value = "20")]
     pub limit: usize,
+
+    /// Only show formulae from this tap (e.g. user/repo)
+    #[arg(long)]
+    pub tap: Option<String>,
+
+    /// Only show casks
+    #[arg(long, conflicts_with = "formula")]
+    pub cask: bool,
+
+    /// Only show formulae
+    #[arg(long)]
+    pub formula: bool,
+
+    /// Match any of the words instead of all of them
+    #[arg(long)]
+    pub any: bool,
+
+    /// Open the homepage of result N (as numbered in the results) in a browser
+    #[arg(long, value_name = "N", conflicts_with_all = ["info", "interactive"])]
+    pub open: Option<usize>,
+
+    /// Show details of result N (as numbered in the results)
+    #[arg(long, value_name = "N", conflicts_with = "interactive")]
+    pub info: Option<usize>,
+
+    /// Pick a result with the arrow keys, then install it, show it or open its homepage
+    #[arg(short, long)]
+    pub interactive: bool,
+}
+
+/// What can be done with a picked result.
+const ACTIONS: &[&str] = &["Install", "Show info", "Open homepage", "Cancel"];
+
+/// Result `number` (1-based, in display order).
+fn nth_result(results: &[SearchResult], number: usize) -> Result<&SearchResult> {
+    crate::ui::display::search_display_order(results).get(number.wrapping_sub(1)).copied()
+        .ok_or_else(|| NitroError::Other(format!("No result {}; the search found {}", number, results.len())).into())
+}
+
+/// The homepage a result's formula or cask declares.
+async fn homepage(result: &SearchResult) -> Result<String> {
+    let homepage = if result.cask {
+        crate::core::cask::CaskParser::new().parse_file(&result.formula_path)?.homepage
+    } else {
+        crate::core::formula::FormulaManager::new().await?.get_formula_at(&result.formula_path).await?.homepage
+    };
+    homepage.ok_or_else(|| NitroError::Other(format!("{} has no homepage", result.name)).into())
+}
+
+async fn show_info(result: &SearchResult) -> Result<()> {
+    super::info::execute(super::info::InfoArgs {
+        package: result.name.clone(),
+        json: false,
+        all_versions: false,
+        with_deps: false,
+        cask: result.cask,
+        github: false,
+        upstream: false,
+        open: false,
+    }).await
+}
+
+/// Let the user pick a result and what to do with it. Needs a terminal.
+async fn pick(results: &[SearchResult]) -> Result<()> {
+    use dialoguer::Select;
+    use std::io::IsTerminal;
+
+    if !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+        return Err(NitroError::Other("--interactive needs a terminal; use --open N or --info N instead".into()).into());
+    }
+    let ordered = crate::ui::display::search_display_order(results);
+    let items: Vec<String> = ordered.iter()
+        .map(|r| match &r.description {
+            Some(description) => format!("{} ({}) - {}", r.name, r.version, description),
+            None => format!("{} ({})", r.name, r.version),
+        })
+        .collect();
+    let Some(choice) = Select::new().with_prompt("Result").items(&items).default(0).interact_opt()? else {
+        return Ok(());
+    };
+    let result = ordered[choice];
+    let action = Select::new().with_prompt(result.name.as_str()).items(ACTIONS).default(0).interact_opt()?;
+
+    match action {
+        Some(0) => super::install::execute(super::install::InstallArgs {
+            packages: vec![result.name.clone()],
+            cask: result.cask,
+            ..Default::default()
+        }).await,
+        Some(1) => show_info(result).await,
+        Some(2) => crate::ui::open_url(&homepage(result).await?),
+        _ => Ok(()),
+    }
 }
 
 fn find_matching_formulae(dir: &std::path::Path, query: &str) -> Result<Vec<(String, std::path::PathBuf)>> {
@@ -53,7 +149,10 @@ pub async fn execute(args: SearchArgs) -> Result<()> {
     let search_engine = SearchEngine::new().await?;
     let results = search_engine.search(&args.query, &args).await?;
 
-    if results.is_empty() {
+    if results.is_empty() && args.cask {
+        println!("No casks found matching '{}'", args.query);
+        println!("\nTip: casks are indexed from taps with a Casks/ directory; set `taps.cask = true` in the config to add homebrew/cask");
+    } else if results.is_empty() {
         // Try partial matching as fallback
         use crate::core::tap::TapManager;
         let tap_manager = TapManager::new().await?;
@@ -61,6 +160,9 @@ pub async fn execute(args: SearchArgs) -> Result<()> {
         
         // Search for formulae containing the query string
         for tap in tap_manager.list_taps().await? {
+            if args.tap.as_ref().is_some_and(|t| t != &tap.name) {
+                continue;
+            }
             let formula_dir = tap.path.join("Formula");
             if formula_dir.exists() {
                 if let Ok(entries) = find_matching_formulae(&formula_dir, &args.query) {
@@ -88,19 +190,16 @@ pub async fn execute(args: SearchArgs) -> Result<()> {
             if aliased_query != args.query {
                 use crate::core::formula::FormulaManager;
                 let formula_manager = FormulaManager::new().await?;
-                match formula_manager.get_formula(aliased_query).await {
-                    Ok(formula) => {
-                        println!("Found package: {} (using common alias)", formula.name);
-                        if let Some(desc) = &formula.description {
-                            println!("  {}", desc);
-                        }
-                        println!("  Version: {}", formula.version);
-                        if let Some(homepage) = &formula.homepage {
-                            println!("  Homepage: {}", homepage);
-                        }
-                        return Ok(());
+                if let Ok(formula) = formula_manager.get_formula(aliased_query).await {
+                    println!("Found package: {} (using common alias)", formula.name);
+                    if let Some(desc) = &formula.description {
+                        println!("  {}", desc);
+                    }
+                    println!("  Version: {}", formula.version);
+                    if let Some(homepage) = &formula.homepage {
+                        println!("  Homepage: {}", homepage);
                     }
-                    Err(_) => {}
+                    return Ok(());
                 }
             }
             
@@ -120,6 +219,15 @@ pub async fn execute(args: SearchArgs) -> Result<()> {
             
             println!("\nUse 'nitro info <package>' to see details");
         }
+    } else if args.interactive {
+        pick(&results).await?;
+    } else if let Some(number) = args.open {
+        let result = nth_result(&results, number)?;
+        let url = homepage(result).await?;
+        println!("Opening {}", url);
+        crate::ui::open_url(&url)?;
+    } else if let Some(number) = args.info {
+        show_info(nth_result(&results, number)?).await?;
     } else {
         display::show_search_results(&results);
     }
@@ -0,0 +1,117 @@
+use anyhow::Result;
+use clap::{Args, Subcommand};
+
+use crate::core::NitroError;
+
+#[derive(Args)]
+pub struct ServicesArgs {
+    #[command(subcommand)]
+    pub command: ServicesCommands,
+}
+
+#[derive(Subcommand)]
+pub enum ServicesCommands {
+    /// List installed formulae that have a service, and whether it's running
+    List,
+    /// Start a formula's service now and at every login
+    Start {
+        /// Installed formula whose service to start
+        formula: String,
+    },
+    /// Stop a formula's service and stop it starting at login
+    Stop {
+        /// Formula whose service to stop
+        formula: String,
+    },
+    /// Stop a formula's service, then start it again with a fresh service file
+    Restart {
+        /// Installed formula whose service to restart
+        formula: String,
+    },
+}
+
+impl ServicesCommands {
+    /// The formula the subcommand acts on, if any
+    pub fn formula(&self) -> Option<&str> {
+        match self {
+            ServicesCommands::List => None,
+            ServicesCommands::Start { formula } | ServicesCommands::Stop { formula } | ServicesCommands::Restart { formula } => Some(formula),
+        }
+    }
+}
+
+/// Manage the background services of installed formulae with launchd (macOS) or
+/// systemd user units (Linux).
+pub async fn execute(args: ServicesArgs) -> Result<()> {
+    use crate::core::formula::FormulaManager;
+    use crate::core::package::PackageManager;
+    use crate::core::services::{self, Manager, Status};
+
+    let manager = Manager::detect();
+    let dir = manager.service_dir()?;
+    let formula_manager = FormulaManager::new().await?;
+
+    match &args.command {
+        ServicesCommands::List => {
+            let package_manager = PackageManager::new().await?;
+            let mut rows = Vec::new();
+            for package in package_manager.installed_packages()? {
+                let Ok(formula) = formula_manager.get_formula(&package.name).await else {
+                    continue;
+                };
+                if let Some(service) = service_for(&formula)? {
+                    let file = service.files(manager).into_iter().next().map(|(name, _)| dir.join(name));
+                    let status = services::status(manager, &service, &dir);
+                    rows.push((package.name, status, file.filter(|_| status != Status::None)));
+                }
+            }
+            if rows.is_empty() {
+                println!("No installed formulae have services");
+                return Ok(());
+            }
+            println!("{:<24} {:<8} File", "Name", "Status");
+            for (name, status, file) in rows {
+                println!("{:<24} {:<8} {}", name, status, file.map(|f| f.display().to_string()).unwrap_or_default());
+            }
+        }
+        ServicesCommands::Start { formula } => {
+            let service = installed_service(&formula_manager, formula).await?;
+            services::start(manager, &service, &dir)?;
+            println!("Started {} ({})", formula, service.label);
+        }
+        // A service can outlive its formula's uninstall, so stopping doesn't need it installed
+        ServicesCommands::Stop { formula } => {
+            let service = service_for(&formula_manager.get_formula(formula).await?)?
+                .ok_or_else(|| NitroError::Other(format!("{} has no service", formula)))?;
+            services::stop(manager, &service, &dir)?;
+            println!("Stopped {}", formula);
+        }
+        ServicesCommands::Restart { formula } => {
+            let service = installed_service(&formula_manager, formula).await?;
+            services::stop(manager, &service, &dir)?;
+            services::start(manager, &service, &dir)?;
+            println!("Restarted {} ({})", formula, service.label);
+        }
+    }
+    Ok(())
+}
+
+/// The service of the installed formula `name`.
+async fn installed_service(formula_manager: &crate::core::formula::FormulaManager, name: &str) -> Result<crate::core::services::Service> {
+    use crate::core::package::PackageManager;
+
+    if !PackageManager::new().await?.installed_packages()?.iter().any(|p| p.name == name) {
+        return Err(NitroError::PackageNotFound(name.to_string()).into());
+    }
+    let formula = formula_manager.get_formula(name).await?;
+    Ok(service_for(&formula)?.ok_or_else(|| NitroError::Other(format!("{} has no service", name)))?)
+}
+
+/// `formula`'s service with paths for this machine's prefix.
+fn service_for(formula: &crate::core::formula::Formula) -> Result<Option<crate::core::services::Service>> {
+    use crate::core::installer::Installer;
+    use crate::core::interpolate::PathContext;
+
+    let prefix = Installer::prefix()?;
+    Ok(crate::core::services::Service::for_formula(formula, &PathContext::new(&prefix, formula), &prefix))
+}
@@ -0,0 +1,34 @@
+use anyhow::Result;
+use clap::Args;
+
+#[derive(Args)]
+pub struct ShellenvArgs {
+    /// Shell to print for (sh, bash, zsh, fish); detected from $SHELL by default
+    #[arg(long)]
+    pub shell: Option<String>,
+}
+
+/// Print the commands that put nitro's link directory on PATH, ahead of Homebrew's.
+/// Meant for shell startup files: `eval "$(nitro shellenv)"`.
+pub async fn execute(args: ShellenvArgs) -> Result<()> {
+    use crate::core::installer::Installer;
+
+    let link_dir = Installer::link_dir()?;
+    let shell = args.shell
+        .or_else(|| std::env::var("SHELL").ok())
+        .unwrap_or_default();
+
+    println!("{}", path_command(&shell, &link_dir.display().to_string()));
+    Ok(())
+}
+
+/// The shell command prepending `dir` to PATH.
+pub fn path_command(shell: &str, dir: &str) -> String {
+    if shell.ends_with("fish") {
+        format!("fish_add_path --global --move --path \"{}\";", dir)
+    } else if shell.ends_with("csh") {
+        format!("setenv PATH \"{}:$PATH\";", dir)
+    } else {
+        format!("export PATH=\"{}:$PATH\";", dir)
+    }
+}
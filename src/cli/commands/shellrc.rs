@@ -0,0 +1,88 @@
+use anyhow::Result;
+use clap::{Args, Subcommand};
+
+#[derive(Args)]
+pub struct ShellrcArgs {
+    #[command(subcommand)]
+    pub command: ShellrcCommands,
+
+    /// Shell whose startup file to use (bash, zsh, fish); detected from $SHELL by default
+    #[arg(long, global = true)]
+    pub shell: Option<String>,
+}
+
+#[derive(Subcommand)]
+pub enum ShellrcCommands {
+    /// Show installed formulae with shell startup lines, and whether they're applied
+    List,
+    /// Add formulae's lines to the shell startup file
+    Apply {
+        #[arg(required = true)]
+        formulae: Vec<String>,
+    },
+    /// Take formulae's lines out of the shell startup file
+    Remove {
+        #[arg(required = true)]
+        formulae: Vec<String>,
+    },
+}
+
+/// Manage the lines formulae declare for shell startup files. Nothing is added unless
+/// asked for here (or with `install.apply_shell_rc`).
+pub async fn execute(args: ShellrcArgs) -> Result<()> {
+    use crate::cli::commands::list::ListArgs;
+    use crate::core::package::PackageManager;
+    use crate::core::shellrc::RcFile;
+    use crate::core::NitroError;
+
+    let rc = RcFile::detect(args.shell.as_deref())?;
+
+    match args.command {
+        ShellrcCommands::List => {
+            let package_manager = PackageManager::new().await?;
+            let applied = rc.applied()?;
+            println!("{} ({})", rc.path.display(), rc.shell.name());
+            let mut listed = Vec::new();
+            for package in package_manager.list_installed(&ListArgs::default()).await? {
+                let formula = package_manager.installed_formula(&package).await?;
+                let lines = rc.shell.lines(&formula);
+                if lines.is_empty() {
+                    continue;
+                }
+                let status = if applied.contains(&formula.name) { "applied" } else { "not applied" };
+                println!("  {} ({})", formula.name, status);
+                for line in lines {
+                    println!("    {}", line);
+                }
+                listed.push(formula.name);
+            }
+            for name in applied.iter().filter(|name| !listed.contains(name)) {
+                println!("  {} (applied, no longer installed; remove with: nitro shellrc remove {})", name, name);
+            }
+        }
+        ShellrcCommands::Apply { formulae } => {
+            let package_manager = PackageManager::new().await?;
+            for name in &formulae {
+                let package = package_manager.installed_package(name)?
+                    .ok_or_else(|| NitroError::PackageNotFound(name.clone()))?;
+                let formula = package_manager.installed_formula(&package).await?;
+                if rc.apply(&formula)? {
+                    println!("Added {}'s lines to {}", formula.name, rc.path.display());
+                } else {
+                    println!("{}'s lines are already in {}", formula.name, rc.path.display());
+                }
+            }
+        }
+        ShellrcCommands::Remove { formulae } => {
+            for name in &formulae {
+                if rc.remove(name)? {
+                    println!("Removed {}'s lines from {}", name, rc.path.display());
+                } else {
+                    println!("{} has no lines in {}", name, rc.path.display());
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,40 @@
+use anyhow::Result;
+use clap::Args;
+
+#[derive(Args)]
+pub struct StatsArgs {
+    /// Maximum number of formulae to show
+    #[arg(short, long, default_value = "10")]
+    pub limit: usize,
+
+    /// Show JSON output
+    #[arg(long)]
+    pub json: bool,
+
+    /// Delete all locally recorded usage data
+    #[arg(long)]
+    pub clear: bool,
+}
+
+pub async fn execute(args: StatsArgs) -> Result<()> {
+    use crate::core::analytics::Analytics;
+    use crate::ui::display;
+
+    let analytics = Analytics::new()?;
+
+    if args.clear {
+        analytics.clear()?;
+        println!("Local usage statistics cleared");
+        return Ok(());
+    }
+
+    let stats = analytics.stats()?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+    } else {
+        display::show_usage_stats(&stats, args.limit);
+    }
+
+    Ok(())
+}
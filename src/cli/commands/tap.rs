@@ -21,20 +21,55 @@ pub enum TapCommands {
     Remove {
         /// Tap name to remove
         name: String,
+        /// Remove the tap even if installed packages came from it
+        #[arg(long)]
+        force: bool,
     },
     /// List all taps
     List,
+    /// Pin a formula to its current file (for installed packages, the file they were
+    /// installed from) so tap updates can't change it. `nitro pin` instead holds an
+    /// installed package at its version without freezing the file
+    Pin {
+        /// Formula to pin; lists pins when omitted
+        name: Option<String>,
+        /// Re-pin to the tap's current version of the formula
+        #[arg(long, requires = "name")]
+        bump: bool,
+    },
+    /// Remove a formula pin
+    Unpin {
+        /// Formula to unpin
+        name: String,
+    },
     /// Update taps
     Update {
         /// Specific tap to update (updates all if not specified)
         name: Option<String>,
+        /// Reset taps with local edits or commits to upstream, saving the changes first
+        #[arg(long)]
+        force: bool,
     },
 }
 
 pub async fn execute(args: TapArgs) -> Result<()> {
-    use crate::core::tap::TapManager;
+    use crate::core::tap::{TapManager, TapUpdate};
     use crate::ui::display;
 
+    // Checked before opening the tap database, which the package manager opens too
+    if let TapCommands::Remove { name, force: false } = &args.command {
+        check_no_installed_packages(name).await?;
+    }
+    // Pinning needs the package database as well, so it goes through the package manager
+    if let TapCommands::Pin { name: Some(name), bump } = &args.command {
+        use crate::core::package::PackageManager;
+
+        let pin = PackageManager::new().await?.pin_formula(name, *bump).await?;
+        println!("Pinned {} at {}{}", pin.name, &pin.hash[..12],
+            pin.tap_commit.map(|c| format!(" (tap commit {})", &c[..c.len().min(12)])).unwrap_or_default());
+        return Ok(());
+    }
+
     let tap_manager = TapManager::new().await?;
 
     match args.command {
@@ -43,31 +78,71 @@ pub async fn execute(args: TapArgs) -> Result<()> {
             tap_manager.add_tap(&name, url.as_deref()).await?;
             println!("Successfully added tap {}", name);
         }
-        TapCommands::Remove { name } => {
+        TapCommands::Remove { name, .. } => {
             println!("Removing tap {}...", name);
             tap_manager.remove_tap(&name).await?;
             println!("Successfully removed tap {}", name);
         }
+        TapCommands::Pin { .. } => {
+            let pins = tap_manager.list_formula_pins()?;
+            if pins.is_empty() {
+                println!("No pinned formulae");
+            }
+            for pin in pins {
+                println!("{:<24} {}  {}  pinned {}", pin.name, &pin.hash[..12],
+                    pin.tap.as_deref().unwrap_or("-"), pin.pinned_at.format("%Y-%m-%d"));
+            }
+        }
+        TapCommands::Unpin { name } => {
+            if tap_manager.unpin_formula(&name)? {
+                println!("Unpinned {}", name);
+            } else {
+                println!("{} is not pinned", name);
+            }
+        }
         TapCommands::List => {
             let taps = tap_manager.list_taps().await?;
             if taps.is_empty() {
                 println!("No taps configured");
             } else {
-                display::show_tap_list(&taps);
+                display::show_tap_list(&taps, &crate::core::config::Config::load()?.taps);
             }
         }
-        TapCommands::Update { name } => {
+        TapCommands::Update { name, force } => {
             if let Some(tap_name) = name {
                 println!("Updating tap {}...", tap_name);
-                tap_manager.update_tap(&tap_name).await?;
-                println!("Successfully updated tap {}", tap_name);
+                let status = tap_manager.update_tap(&tap_name, force).await?;
+                display::show_tap_update_summary(&[TapUpdate { name: tap_name, status }]);
             } else {
                 println!("Updating all taps...");
-                tap_manager.update_all_taps().await?;
-                println!("Successfully updated all taps");
+                let updates = tap_manager.update_all_taps(force).await?;
+                display::show_tap_update_summary(&updates);
             }
         }
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Refuse to remove a tap that installed packages came from: upgrading or reinstalling
+/// them needs its formulae.
+async fn check_no_installed_packages(tap: &str) -> Result<()> {
+    use crate::cli::commands::list::ListArgs;
+    use crate::core::package::PackageManager;
+    use crate::core::NitroError;
+
+    let package_manager = PackageManager::new().await?;
+    let installed = package_manager.list_installed(&ListArgs {
+        tap: Some(tap.to_string()),
+        ..Default::default()
+    }).await?;
+
+    if !installed.is_empty() {
+        let names: Vec<&str> = installed.iter().map(|p| p.name.as_str()).collect();
+        return Err(NitroError::TapError(format!(
+            "{} installed package(s) came from {}: {}\nUninstall them first, or pass --force to remove the tap anyway",
+            names.len(), tap, names.join(", ")
+        )).into());
+    }
+    Ok(())
+}
@@ -14,6 +14,10 @@ pub struct UninstallArgs {
     /// Remove all versions
     #[arg(long)]
     pub all_versions: bool,
+
+    /// Uninstall casks rather than formulae
+    #[arg(long)]
+    pub cask: bool,
 }
 
 pub async fn execute(args: UninstallArgs) -> Result<()> {
@@ -30,7 +34,8 @@ pub async fn execute(args: UninstallArgs) -> Result<()> {
             Ok(_) => progress.complete_package(package_name),
             Err(e) => {
                 progress.fail_package(package_name, &crate::core::NitroError::Other(e.to_string()));
-                if !args.force {
+                if !args.force || crate::core::interrupt::is_interrupted() {
+                    progress.finish();
                     return Err(e);
                 }
             }
@@ -3,57 +3,126 @@ use clap::Args;
 
 #[derive(Args)]
 pub struct UpdateArgs {
-    /// Package name(s) to update (updates all if not specified)
-    pub packages: Vec<String>,
+    /// Only refresh this tap (e.g. user/repo)
+    #[arg(long)]
+    pub tap: Option<String>,
 
-    /// Update formulae database
+    /// Print what was refreshed as JSON
     #[arg(long)]
+    pub json: bool,
+
+    /// Accepted for compatibility; refreshing metadata is all `update` does
+    #[arg(long, hide = true)]
     pub formulae: bool,
 
-    /// Upgrade all packages
-    #[arg(long)]
+    /// Deprecated: use `nitro upgrade`
+    #[arg(hide = true)]
+    pub packages: Vec<String>,
+
+    /// Deprecated: use `nitro upgrade`
+    #[arg(long, hide = true)]
     pub upgrade: bool,
 
-    /// Dry run - show what would be updated
-    #[arg(long)]
+    /// Deprecated: use `nitro upgrade --dry-run` or `nitro outdated`
+    #[arg(long, hide = true)]
     pub dry_run: bool,
 }
 
+/// Refresh tap metadata and the formula index. Upgrading is `nitro upgrade`'s job;
+/// the old package arguments are still forwarded there so scripts keep working.
 pub async fn execute(args: UpdateArgs) -> Result<()> {
-    use crate::core::package::PackageManager;
-    use crate::core::formula::FormulaManager;
-    use crate::ui::progress::ProgressReporter;
+    use super::upgrade::UpgradeArgs;
+
+    if args.upgrade || !args.packages.is_empty() {
+        eprintln!("Warning: `nitro update` no longer upgrades packages; use `nitro upgrade`");
+        return super::upgrade::execute(UpgradeArgs {
+            packages: args.packages,
+            tap: args.tap,
+            dry_run: args.dry_run,
+            json: args.json,
+            ..Default::default()
+        }).await;
+    }
 
-    let progress = ProgressReporter::new();
+    refresh(args.tap.as_deref(), args.json).await
+}
 
-    if args.formulae {
+/// Pull `tap` (every tap when `None`), drop the cached formulae that changed, refresh
+/// the JSON API index when homebrew/core is in scope and rebuild the search index.
+/// Taps or an index that couldn't be refreshed are reported, then fail the command.
+pub async fn refresh(tap: Option<&str>, json: bool) -> Result<()> {
+    use crate::core::formula::FormulaManager;
+    use crate::core::tap::{TapUpdate, TapUpdateStatus};
+    use crate::core::NitroError;
+
+    let quiet = json || crate::ui::is_quiet();
+    if !quiet {
         println!("Updating formulae database...");
-        let formula_manager = FormulaManager::new().await?;
-        formula_manager.update_formulae().await?;
-        
-        println!("Rebuilding search index...");
-        formula_manager.rebuild_search_index().await?;
-        println!("Formulae database updated");
     }
+    let formula_manager = FormulaManager::new().await?;
+    let updates = match tap {
+        Some(name) => vec![TapUpdate {
+            name: name.to_string(),
+            status: formula_manager.tap_manager().update_tap(name, false).await?,
+        }],
+        None => formula_manager.tap_manager().update_all_taps(false).await?,
+    };
+    let invalidated = formula_manager.invalidate_stale_cache()?;
 
-    if args.upgrade || !args.packages.is_empty() {
-        let package_manager = PackageManager::new().await?;
-        
-        if args.dry_run {
-            let updates = package_manager.check_updates(&args.packages).await?;
-            if updates.is_empty() {
-                println!("All packages are up to date");
-            } else {
-                println!("Available updates:");
-                for (pkg, from_ver, to_ver) in updates {
-                    println!("  {} {} -> {}", pkg, from_ver, to_ver);
-                }
+    let mut api_error = None;
+    let api_index = if tap.is_none_or(|name| name == "homebrew/core") {
+        match formula_manager.refresh_api_index().await {
+            Ok(refreshed) => Some(refreshed),
+            Err(e) => {
+                api_error = Some(e.context("Could not refresh the formula API index"));
+                None
             }
-        } else {
-            package_manager.update_packages(&args).await?;
+        }
+    } else {
+        None
+    };
+
+    formula_manager.rebuild_search_index().await?;
+
+    if json {
+        let taps: Vec<serde_json::Value> = updates.iter()
+            .map(|update| match &update.status {
+                TapUpdateStatus::Updated { new_formulae } => serde_json::json!({
+                    "name": update.name, "status": "updated", "new_formulae": new_formulae,
+                }),
+                TapUpdateStatus::Unchanged => serde_json::json!({ "name": update.name, "status": "unchanged" }),
+                TapUpdateStatus::Failed(e) => serde_json::json!({ "name": update.name, "status": "failed", "error": e }),
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&serde_json::json!({
+            "taps": taps,
+            "invalidated_formulae": invalidated,
+            "api_index_updated": api_index,
+        }))?);
+    } else if !quiet {
+        crate::ui::display::show_tap_update_summary(&updates);
+        if invalidated > 0 {
+            println!("Invalidated {} cached formula(e)", invalidated);
+        }
+        match api_index {
+            Some(true) => println!("Formula API index updated"),
+            Some(false) => println!("Formula API index already up to date"),
+            None => {}
         }
     }
 
-    progress.finish();
+    let failed: Vec<&str> = updates.iter()
+        .filter(|update| matches!(update.status, TapUpdateStatus::Failed(_)))
+        .map(|update| update.name.as_str())
+        .collect();
+    if !failed.is_empty() {
+        return Err(NitroError::TapError(format!("Could not update {}", failed.join(", "))).into());
+    }
+    if let Some(e) = api_error {
+        return Err(e);
+    }
+    if !quiet {
+        println!("Formulae database updated");
+    }
     Ok(())
-}
\ No newline at end of file
+}
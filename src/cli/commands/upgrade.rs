@@ -0,0 +1,137 @@
+use anyhow::Result;
+use clap::Args;
+
+use super::outdated::{GreedyArgs, Outdated};
+use crate::core::changes::FormulaChanges;
+
+#[derive(Args, Default)]
+pub struct UpgradeArgs {
+    /// Formulae or casks to upgrade (upgrades everything outdated if not specified)
+    pub packages: Vec<String>,
+
+    /// Upgrade everything outdated, as when no packages are given
+    #[arg(long, conflicts_with = "packages")]
+    pub all: bool,
+
+    /// Only upgrade casks
+    #[arg(long)]
+    pub cask: bool,
+
+    /// Only upgrade formulae installed from this tap (e.g. user/repo)
+    #[arg(long)]
+    pub tap: Option<String>,
+
+    #[command(flatten)]
+    pub greedy: GreedyArgs,
+
+    /// Show what would be upgraded without changing anything
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Remove each formula's old keg once the new version is installed
+    #[arg(long)]
+    pub cleanup: bool,
+
+    /// Upgrade pinned formulae too
+    #[arg(short, long)]
+    pub force: bool,
+
+    /// Print what was (or, with --dry-run, would be) upgraded as JSON
+    #[arg(long)]
+    pub json: bool,
+
+    /// Before upgrading, show what changes in each formula: version, dependencies,
+    /// caveats and license
+    #[arg(long)]
+    pub show_changes: bool,
+}
+
+/// The changes upgrading each of the outdated formulae brings.
+async fn formula_changes(package_manager: &crate::core::package::PackageManager, outdated: &Outdated) -> Result<Vec<FormulaChanges>> {
+    let mut changes = Vec::new();
+    for package in &outdated.formulae {
+        let Some(installed) = package_manager.installed_package(&package.name)? else {
+            continue;
+        };
+        let new = package_manager.installed_formula(&installed).await?;
+        let old = package_manager.formula_as_installed(&installed).await;
+        changes.push(FormulaChanges::between(&installed, old.as_ref(), &new));
+    }
+    Ok(changes)
+}
+
+/// Upgrade outdated formulae and casks. Casks that keep themselves up to date are
+/// skipped unless asked for with the `--greedy` flags. Metadata isn't refreshed first;
+/// that's `nitro update`. Formulae held with `nitro pin` are left out, from `--dry-run`
+/// and `--json` too, unless `--force`.
+pub async fn execute(args: UpgradeArgs) -> Result<()> {
+    use crate::cli::commands::install::InstallArgs;
+    use crate::core::package::PackageManager;
+
+    if args.json {
+        crate::ui::set_quiet();
+    }
+    let quiet = crate::ui::is_quiet();
+
+    let package_manager = PackageManager::new().await?;
+    let mut outdated = Outdated::find(&package_manager, &args.packages, args.cask, args.tap.as_deref(), args.greedy.greedy()).await?;
+    let pinned = if args.force { vec![] } else { outdated.take_pinned(&package_manager)? };
+
+    let changes = if args.show_changes { formula_changes(&package_manager, &outdated).await? } else { vec![] };
+
+    if args.json {
+        let mut report = serde_json::to_value(&outdated)?;
+        if args.show_changes {
+            report["changes"] = serde_json::to_value(&changes)?;
+        }
+        if !pinned.is_empty() {
+            report["pinned"] = serde_json::to_value(&pinned)?;
+        }
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else if outdated.is_empty() {
+        if !quiet {
+            println!("Everything is up to date");
+        }
+    } else if args.dry_run {
+        if !quiet {
+            println!("Would upgrade:");
+        }
+        outdated.print();
+    }
+    if !quiet {
+        for package in &pinned {
+            println!("Not upgrading {}: pinned at {} ({} is available; unpin it or use --force)",
+                package.name, package.installed_version, package.current_version);
+        }
+    }
+    if !args.json {
+        for change in &changes {
+            println!("{}:", change.name);
+            for line in change.lines() {
+                println!("  {}", line);
+            }
+        }
+    }
+    if args.dry_run || outdated.is_empty() {
+        return Ok(());
+    }
+
+    let transfers = crate::ui::progress::TransferSummary::start();
+    if !outdated.formulae.is_empty() {
+        let names: Vec<String> = outdated.formulae.iter().map(|package| package.name.clone()).collect();
+        package_manager.update_packages(&names, args.tap.as_deref(), args.cleanup, args.force).await?;
+    }
+    for cask in &outdated.casks {
+        if !quiet {
+            println!("Upgrading {} {} -> {}...", cask.name, cask.installed_version, cask.current_version);
+        }
+        package_manager.install_cask(&cask.name, &InstallArgs {
+            packages: vec![cask.name.clone()],
+            force: true,
+            cask: true,
+            ..Default::default()
+        }).await?;
+    }
+    transfers.finish();
+    Ok(())
+}
@@ -1,5 +1,6 @@
 pub mod commands;
 
+use anyhow::Result;
 use clap::{Parser, Subcommand};
 
 #[derive(Parser)]
@@ -14,9 +15,27 @@ pub struct Cli {
     #[arg(short, long, action = clap::ArgAction::Count)]
     pub verbose: u8,
 
-    /// Suppress all output except errors
-    #[arg(short, long)]
+    /// Suppress all output except errors and the data asked for
+    #[arg(short, long, global = true)]
     pub quiet: bool,
+
+    /// Don't clone missing default taps such as homebrew/core
+    #[arg(long, global = true)]
+    pub no_auto_tap: bool,
+
+    /// Refuse to install anything from a tap that isn't trusted, rather than asking
+    #[arg(long, global = true, env = "NITRO_REQUIRE_TRUSTED")]
+    pub require_trusted: bool,
+
+    /// Install from untrusted taps without asking, e.g. in scripts with no terminal to
+    /// ask on
+    #[arg(long, global = true, env = "NITRO_ALLOW_UNTRUSTED", conflicts_with = "require_trusted")]
+    pub allow_untrusted: bool,
+
+    /// Resolve, fetch and install in a stable order and stamp receipts with
+    /// SOURCE_DATE_EPOCH, so identical inputs give identical results and output
+    #[arg(long, global = true, env = "NITRO_DETERMINISTIC")]
+    pub deterministic: bool,
 }
 
 #[derive(Subcommand)]
@@ -33,9 +52,15 @@ pub enum Commands {
     /// List installed packages
     List(commands::list::ListArgs),
 
-    /// Update packages or formulae
+    /// Refresh tap metadata and the formula index
     Update(commands::update::UpdateArgs),
 
+    /// Show installed formulae and casks with newer versions
+    Outdated(commands::outdated::OutdatedArgs),
+
+    /// Upgrade outdated formulae and casks
+    Upgrade(commands::upgrade::UpgradeArgs),
+
     /// Show information about a package
     Info(commands::info::InfoArgs),
 
@@ -44,4 +69,264 @@ pub enum Commands {
 
     /// Homebrew compatibility commands
     Homebrew(commands::homebrew::HomebrewArgs),
-}
\ No newline at end of file
+
+    /// Control anonymous usage analytics
+    Analytics(commands::analytics::AnalyticsArgs),
+
+    /// Show locally recorded usage statistics
+    Stats(commands::stats::StatsArgs),
+
+    /// Manage Nitro's caches
+    Cache(commands::cache::CacheArgs),
+
+    /// Resume interrupted installs
+    Resume(commands::resume::ResumeArgs),
+
+    /// Roll back interrupted installs
+    Abort(commands::abort::AbortArgs),
+
+    /// Run a brew-style command line (e.g. `nitro brew install wget`)
+    Brew(commands::brew::BrewArgs),
+
+    /// Tools for formula and tap authors
+    Dev(commands::dev::DevArgs),
+
+    /// Print shell commands that put nitro's link directory on PATH
+    Shellenv(commands::shellenv::ShellenvArgs),
+
+    /// Show dependency closures, or which of them lack bottles
+    Deps(commands::deps::DepsArgs),
+
+    /// Download bottles or sources without installing them, for any platform
+    Fetch(commands::fetch::FetchArgs),
+
+    /// Diagnose problems with the network and environment
+    Doctor(commands::doctor::DoctorArgs),
+
+    /// Build a formula from source, opening a shell in its build directory when the
+    /// build fails
+    DebugBuild(commands::debug_build::DebugBuildArgs),
+
+    /// Serve an HTTP API that queues install jobs from multiple clients
+    Daemon(commands::daemon::DaemonArgs),
+
+    /// Add or remove the lines formulae declare for shell startup files
+    Shellrc(commands::shellrc::ShellrcArgs),
+
+    /// Remove broken symlinks from the link directories
+    Prune(commands::prune::PruneArgs),
+
+    /// Export or verify the log of installs kept for compliance
+    AuditLog(commands::audit_log::AuditLogArgs),
+
+    /// List installed packages that no other installed package depends on
+    Leaves(commands::leaves::LeavesArgs),
+
+    /// Uninstall dependencies that nothing installed needs any more
+    Autoremove(commands::autoremove::AutoremoveArgs),
+
+    /// Remove old kegs, expired downloads and stale caches
+    Cleanup(commands::cleanup::CleanupArgs),
+
+    /// Hold installed packages at their current version so `upgrade` skips them (`tap
+    /// pin` instead freezes the formula file a package installs from)
+    Pin(commands::pin::PinArgs),
+
+    /// Let pinned packages be upgraded again
+    Unpin(commands::pin::UnpinArgs),
+
+    /// Inspect the shell environment nitro's commands run in
+    Env(commands::env::EnvArgs),
+
+    /// Start, stop and list formulae's background services
+    Services(commands::services::ServicesArgs),
+}
+
+impl Commands {
+    /// Name used when recording usage analytics
+    pub fn name(&self) -> &'static str {
+        match self {
+            Commands::Install(_) => "install",
+            Commands::Uninstall(_) => "uninstall",
+            Commands::Search(_) => "search",
+            Commands::List(_) => "list",
+            Commands::Update(_) => "update",
+            Commands::Outdated(_) => "outdated",
+            Commands::Upgrade(_) => "upgrade",
+            Commands::Info(_) => "info",
+            Commands::Tap(_) => "tap",
+            Commands::Homebrew(_) => "homebrew",
+            Commands::Analytics(_) => "analytics",
+            Commands::Stats(_) => "stats",
+            Commands::Cache(_) => "cache",
+            Commands::Resume(_) => "resume",
+            Commands::Abort(_) => "abort",
+            Commands::Brew(_) => "brew",
+            Commands::Dev(_) => "dev",
+            Commands::Shellenv(_) => "shellenv",
+            Commands::Deps(_) => "deps",
+            Commands::Fetch(_) => "fetch",
+            Commands::Doctor(_) => "doctor",
+            Commands::DebugBuild(_) => "debug-build",
+            Commands::Daemon(_) => "daemon",
+            Commands::Shellrc(_) => "shellrc",
+            Commands::Prune(_) => "prune",
+            Commands::AuditLog(_) => "audit-log",
+            Commands::Leaves(_) => "leaves",
+            Commands::Autoremove(_) => "autoremove",
+            Commands::Cleanup(_) => "cleanup",
+            Commands::Pin(_) => "pin",
+            Commands::Unpin(_) => "unpin",
+            Commands::Env(_) => "env",
+            Commands::Services(_) => "services",
+        }
+    }
+
+    /// Formula names the command operates on, for usage analytics
+    pub fn formulae(&self) -> Vec<String> {
+        match self {
+            Commands::Install(args) => args.packages.clone(),
+            Commands::Uninstall(args) => args.packages.clone(),
+            Commands::Info(args) => vec![args.package.clone()],
+            Commands::Resume(args) => args.packages.clone(),
+            Commands::Deps(args) => args.formulae.clone(),
+            Commands::Fetch(args) => args.formulae.clone(),
+            Commands::Outdated(args) => args.packages.clone(),
+            Commands::Upgrade(args) => args.packages.clone(),
+            Commands::DebugBuild(args) => vec![args.formula.clone()],
+            Commands::Pin(args) => args.packages.clone(),
+            Commands::Unpin(args) => args.packages.clone(),
+            Commands::Services(args) => args.command.formula().map(str::to_string).into_iter().collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Whether invocations of this command should be recorded at all
+    pub fn is_tracked(&self) -> bool {
+        // shellenv runs on every shell startup
+        !matches!(self, Commands::Analytics(_) | Commands::Stats(_) | Commands::Shellenv(_))
+    }
+
+    /// Informational commands, which run in read-only mode (see `core::readonly`)
+    pub fn is_read_only(&self) -> bool {
+        // Picking a search result can install it
+        if let Commands::Search(args) = self {
+            return !args.interactive;
+        }
+        if let Commands::Services(args) = self {
+            return matches!(args.command, commands::services::ServicesCommands::List);
+        }
+        matches!(self, Commands::Info(_) | Commands::List(_) | Commands::Shellenv(_) | Commands::Deps(_) | Commands::Doctor(_) | Commands::Leaves(_) | Commands::Env(_))
+    }
+}
+
+/// Execute a parsed command.
+pub async fn run(command: Commands) -> Result<()> {
+    if command.is_read_only() {
+        crate::core::readonly::enable();
+    }
+
+    match command {
+        Commands::Install(args) => {
+            commands::install::execute(args).await?;
+        }
+        Commands::Uninstall(args) => {
+            commands::uninstall::execute(args).await?;
+        }
+        Commands::Search(args) => {
+            commands::search::execute(args).await?;
+        }
+        Commands::List(args) => {
+            commands::list::execute(args).await?;
+        }
+        Commands::Update(args) => {
+            commands::update::execute(args).await?;
+        }
+        Commands::Outdated(args) => {
+            commands::outdated::execute(args).await?;
+        }
+        Commands::Upgrade(args) => {
+            commands::upgrade::execute(args).await?;
+        }
+        Commands::Info(args) => {
+            commands::info::execute(args).await?;
+        }
+        Commands::Tap(args) => {
+            commands::tap::execute(args).await?;
+        }
+        Commands::Homebrew(args) => {
+            commands::homebrew::execute(args).await?;
+        }
+        Commands::Analytics(args) => {
+            commands::analytics::execute(args).await?;
+        }
+        Commands::Stats(args) => {
+            commands::stats::execute(args).await?;
+        }
+        Commands::Cache(args) => {
+            commands::cache::execute(args).await?;
+        }
+        Commands::Resume(args) => {
+            commands::resume::execute(args).await?;
+        }
+        Commands::Abort(args) => {
+            commands::abort::execute(args).await?;
+        }
+        Commands::Brew(args) => {
+            commands::brew::execute(args).await?;
+        }
+        Commands::Dev(args) => {
+            commands::dev::execute(args).await?;
+        }
+        Commands::Shellenv(args) => {
+            commands::shellenv::execute(args).await?;
+        }
+        Commands::Deps(args) => {
+            commands::deps::execute(args).await?;
+        }
+        Commands::Fetch(args) => {
+            commands::fetch::execute(args).await?;
+        }
+        Commands::Doctor(args) => {
+            commands::doctor::execute(args).await?;
+        }
+        Commands::DebugBuild(args) => {
+            commands::debug_build::execute(args).await?;
+        }
+        Commands::Daemon(args) => {
+            commands::daemon::execute(args).await?;
+        }
+        Commands::Shellrc(args) => {
+            commands::shellrc::execute(args).await?;
+        }
+        Commands::Prune(args) => {
+            commands::prune::execute(args).await?;
+        }
+        Commands::AuditLog(args) => {
+            commands::audit_log::execute(args).await?;
+        }
+        Commands::Leaves(args) => {
+            commands::leaves::execute(args).await?;
+        }
+        Commands::Autoremove(args) => {
+            commands::autoremove::execute(args).await?;
+        }
+        Commands::Cleanup(args) => {
+            commands::cleanup::execute(args).await?;
+        }
+        Commands::Pin(args) => {
+            commands::pin::execute(args).await?;
+        }
+        Commands::Unpin(args) => {
+            commands::pin::execute_unpin(args).await?;
+        }
+        Commands::Env(args) => {
+            commands::env::execute(args).await?;
+        }
+        Commands::Services(args) => {
+            commands::services::execute(args).await?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,251 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::core::config::Config;
+use crate::core::NitroError;
+
+/// How much usage data Nitro is allowed to collect.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnalyticsMode {
+    /// Nothing is recorded (the default)
+    #[default]
+    Off,
+    /// Events are only recorded locally for `nitro stats`
+    Local,
+    /// Events are recorded locally and sent, anonymized, to the configured endpoint
+    On,
+}
+
+impl std::fmt::Display for AnalyticsMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AnalyticsMode::Off => write!(f, "off"),
+            AnalyticsMode::Local => write!(f, "local"),
+            AnalyticsMode::On => write!(f, "on"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AnalyticsConfig {
+    pub mode: AnalyticsMode,
+    /// Where anonymized events are POSTed when the mode is `on`
+    pub endpoint: Option<String>,
+    /// Random identifier generated when analytics are enabled; not derived from the user or machine
+    pub client_id: Option<String>,
+}
+
+/// A single recorded command invocation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnalyticsEvent {
+    pub command: String,
+    pub formula: Option<String>,
+    pub success: bool,
+    pub duration_ms: u64,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+/// Aggregated local statistics for a single command.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CommandStats {
+    pub command: String,
+    pub runs: u64,
+    pub failures: u64,
+    pub total_duration_ms: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UsageStats {
+    pub commands: Vec<CommandStats>,
+    pub formulae: Vec<(String, u64)>,
+}
+
+/// The longest a command's exit waits on sending its events
+const SEND_TIMEOUT: Duration = Duration::from_millis(500);
+
+pub struct Analytics {
+    config: AnalyticsConfig,
+    db: Option<sled::Db>,
+}
+
+impl Analytics {
+    pub fn new() -> Result<Self> {
+        let config = Config::load()?.analytics;
+
+        let db = if Self::effective_mode(&config) == AnalyticsMode::Off {
+            None
+        } else {
+            Some(Self::open_db()?)
+        };
+
+        Ok(Self { config, db })
+    }
+
+    /// The mode actually in effect, honoring the `NITRO_NO_ANALYTICS` override.
+    pub fn effective_mode(config: &AnalyticsConfig) -> AnalyticsMode {
+        if std::env::var_os("NITRO_NO_ANALYTICS").is_some() {
+            AnalyticsMode::Off
+        } else {
+            config.mode
+        }
+    }
+
+    pub fn set_mode(mode: AnalyticsMode) -> Result<()> {
+        let mut config = Config::load()?;
+        config.analytics.mode = mode;
+
+        match mode {
+            AnalyticsMode::On if config.analytics.client_id.is_none() => {
+                config.analytics.client_id = Some(Self::generate_client_id()?);
+            }
+            AnalyticsMode::Off => {
+                // Forget the identifier so re-enabling starts a fresh, unlinkable id
+                config.analytics.client_id = None;
+            }
+            _ => {}
+        }
+
+        config.save()
+    }
+
+    pub fn config(&self) -> &AnalyticsConfig {
+        &self.config
+    }
+
+    /// Record one command invocation. Failures are swallowed so analytics can never break a command.
+    pub async fn record(&self, command: &str, formulae: &[String], success: bool, duration: Duration) {
+        let Some(db) = &self.db else {
+            return;
+        };
+
+        let timestamp = chrono::Utc::now();
+        let events: Vec<AnalyticsEvent> = if formulae.is_empty() {
+            vec![AnalyticsEvent {
+                command: command.to_string(),
+                formula: None,
+                success,
+                duration_ms: duration.as_millis() as u64,
+                timestamp,
+            }]
+        } else {
+            formulae.iter().map(|formula| AnalyticsEvent {
+                command: command.to_string(),
+                formula: Some(formula.clone()),
+                success,
+                duration_ms: duration.as_millis() as u64,
+                timestamp,
+            }).collect()
+        };
+
+        for event in &events {
+            if let (Ok(id), Ok(data)) = (db.generate_id(), serde_json::to_vec(event)) {
+                let _ = db.insert(id.to_be_bytes(), data);
+            }
+        }
+        let _ = db.flush_async().await;
+
+        if Self::effective_mode(&self.config) == AnalyticsMode::On {
+            self.send(&events).await;
+        }
+    }
+
+    pub fn stats(&self) -> Result<UsageStats> {
+        let db = match &self.db {
+            Some(db) => db.clone(),
+            None => Self::open_db()?,
+        };
+
+        let mut commands: HashMap<String, CommandStats> = HashMap::new();
+        let mut formulae: HashMap<String, u64> = HashMap::new();
+
+        for entry in db.iter() {
+            let (_, value) = entry?;
+            let Ok(event) = serde_json::from_slice::<AnalyticsEvent>(&value) else {
+                continue;
+            };
+
+            let stats = commands.entry(event.command.clone()).or_insert_with(|| CommandStats {
+                command: event.command.clone(),
+                ..Default::default()
+            });
+            stats.runs += 1;
+            stats.total_duration_ms += event.duration_ms;
+            if !event.success {
+                stats.failures += 1;
+            }
+
+            if let Some(formula) = event.formula {
+                if event.success {
+                    *formulae.entry(formula).or_default() += 1;
+                }
+            }
+        }
+
+        let mut commands: Vec<_> = commands.into_values().collect();
+        commands.sort_by(|a, b| b.runs.cmp(&a.runs).then_with(|| a.command.cmp(&b.command)));
+
+        let mut formulae: Vec<_> = formulae.into_iter().collect();
+        formulae.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        Ok(UsageStats { commands, formulae })
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        let db = match &self.db {
+            Some(db) => db.clone(),
+            None => Self::open_db()?,
+        };
+        db.clear()?;
+        db.flush()?;
+        Ok(())
+    }
+
+    /// POST `events` to the endpoint all at once in the background, waiting at most
+    /// `SEND_TIMEOUT` for them. Whatever hasn't been sent by then is dropped with the
+    /// process rather than holding up the command's exit.
+    async fn send(&self, events: &[AnalyticsEvent]) {
+        let (Some(endpoint), Some(client_id)) = (&self.config.endpoint, &self.config.client_id) else {
+            return;
+        };
+        let Ok(downloader) = crate::download::Downloader::shared() else {
+            return;
+        };
+
+        let requests: Vec<_> = events.iter().map(|event| {
+            // Only coarse, non-identifying fields leave the machine
+            let payload = serde_json::json!({
+                "client_id": client_id,
+                "command": event.command,
+                "formula": event.formula,
+                "success": event.success,
+                "duration_ms": event.duration_ms,
+                "nitro_version": env!("CARGO_PKG_VERSION"),
+                "os": std::env::consts::OS,
+                "arch": std::env::consts::ARCH,
+            });
+            let request = downloader.client().post(endpoint).timeout(SEND_TIMEOUT).json(&payload);
+            tokio::spawn(request.send())
+        }).collect();
+
+        let _ = tokio::time::timeout(SEND_TIMEOUT, futures::future::join_all(requests)).await;
+    }
+
+    fn open_db() -> Result<sled::Db> {
+        let config_dir = directories::ProjectDirs::from("com", "nitro", "nitro")
+            .ok_or_else(|| NitroError::Other("Could not determine config directory".into()))?;
+
+        let db_path = config_dir.data_dir().join("analytics.db");
+        std::fs::create_dir_all(config_dir.data_dir())?;
+        Ok(sled::open(db_path)?)
+    }
+
+    fn generate_client_id() -> Result<String> {
+        let mut bytes = [0u8; 16];
+        getrandom::getrandom(&mut bytes).map_err(|e| NitroError::Other(format!("Could not generate an analytics client id: {}", e)))?;
+        Ok(hex::encode(bytes))
+    }
+}
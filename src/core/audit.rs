@@ -0,0 +1,212 @@
+//! Append-only log of what was installed, from where and by whom, for compliance
+//! reviews. Entries are JSON lines, each carrying the SHA-256 of the one before it
+//! and its own, so an edited, dropped or reordered entry breaks the chain from that
+//! point on and `verify` reports where. The last entry's seq and hash are also kept in
+//! the package database, so a log whose tail was cut off fails `verify_head`.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use crate::core::formula::Formula;
+use crate::core::install_state::InstallSource;
+use crate::core::NitroError;
+
+/// `prev_hash` of the first entry
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// One install, as recorded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Position in the log, from 1
+    pub seq: u64,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub action: String,
+    pub formula: String,
+    pub version: String,
+    pub tap: Option<String>,
+    pub tap_commit: Option<String>,
+    /// Bottle, source archive or cached build, with its URL and digest
+    pub source: Option<InstallSource>,
+    /// The checksum accepted in place of the formula's, if one was
+    pub checksum_override: Option<String>,
+    pub user: String,
+    pub hostname: String,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl AuditEntry {
+    /// The hash of this entry: SHA-256 over the previous hash and the entry's JSON
+    /// with `hash` left empty.
+    pub fn compute_hash(&self) -> String {
+        let unhashed = Self { hash: String::new(), ..self.clone() };
+        let json = serde_json::to_string(&unhashed).expect("audit entries always serialize");
+        hex::encode(Sha256::digest(format!("{}\n{}", self.prev_hash, json)))
+    }
+}
+
+/// Where the chain breaks: the first entry that doesn't follow from the ones before.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainBreak {
+    /// Line number in the log, from 1
+    pub line: usize,
+    pub reason: String,
+}
+
+/// Check that `entries` form an unbroken chain from the start of the log.
+pub fn verify(entries: &[AuditEntry]) -> std::result::Result<(), ChainBreak> {
+    let mut prev_hash = GENESIS_HASH.to_string();
+    for (i, entry) in entries.iter().enumerate() {
+        let line = i + 1;
+        if entry.seq != line as u64 {
+            return Err(ChainBreak { line, reason: format!("entry {} where {} was expected", entry.seq, line) });
+        }
+        if entry.prev_hash != prev_hash {
+            return Err(ChainBreak { line, reason: "doesn't follow from the entry before it".to_string() });
+        }
+        if entry.compute_hash() != entry.hash {
+            return Err(ChainBreak { line, reason: "contents don't match its hash".to_string() });
+        }
+        prev_hash = entry.hash.clone();
+    }
+    Ok(())
+}
+
+/// The newest entry as of the last append, kept outside the log itself.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditHead {
+    pub seq: u64,
+    pub hash: String,
+}
+
+/// Check that the entry `head` records is still in `entries`, unchanged. Entries after
+/// it are fine (an append that couldn't update the head); a missing one means the log
+/// was truncated.
+pub fn verify_head(entries: &[AuditEntry], head: Option<&AuditHead>) -> std::result::Result<(), ChainBreak> {
+    let Some(head) = head else { return Ok(()) };
+    match entries.get((head.seq as usize).saturating_sub(1)) {
+        Some(entry) if entry.seq == head.seq && entry.hash == head.hash => Ok(()),
+        Some(_) => Err(ChainBreak { line: head.seq as usize, reason: "doesn't match the recorded head of the log".to_string() }),
+        None => Err(ChainBreak {
+            line: entries.len() + 1,
+            reason: format!("log ends at entry {} but entry {} was recorded", entries.len(), head.seq),
+        }),
+    }
+}
+
+/// Who is installing: the login name and this machine's hostname.
+pub fn identity() -> (String, String) {
+    let user = ["USER", "LOGNAME", "USERNAME"].iter()
+        .find_map(|var| std::env::var(var).ok().filter(|v| !v.is_empty()))
+        .unwrap_or_else(|| "unknown".to_string());
+    let hostname = sysinfo::System::host_name().unwrap_or_else(|| "unknown".to_string());
+    (user, hostname)
+}
+
+pub struct AuditLog {
+    path: PathBuf,
+    heads: Option<sled::Tree>,
+}
+
+impl AuditLog {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into(), heads: None }
+    }
+
+    /// Keep the log's head in `db`'s `audit_head` tree, keyed by the log's path.
+    pub fn with_heads(mut self, db: &sled::Db) -> Result<Self> {
+        self.heads = Some(db.open_tree("audit_head")?);
+        Ok(self)
+    }
+
+    /// `var/log/nitro/audit.jsonl` under the prefix.
+    pub fn default_path(prefix: &Path) -> PathBuf {
+        prefix.join("var/log/nitro/audit.jsonl")
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Every entry, in order. A missing log has none; a line that isn't an entry is an
+    /// error, since it's either corruption or tampering.
+    pub fn entries(&self) -> Result<Vec<AuditEntry>> {
+        let file = match std::fs::File::open(&self.path) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            result => result?,
+        };
+        let mut entries = Vec::new();
+        for (i, line) in std::io::BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            let entry = serde_json::from_str(&line).map_err(|e| NitroError::Other(format!(
+                "{} line {} is not an audit entry: {}", self.path.display(), i + 1, e
+            )))?;
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    /// The head recorded by the last append, if there is a database to keep it in and
+    /// anything has been appended.
+    pub fn head(&self) -> Result<Option<AuditHead>> {
+        let Some(heads) = &self.heads else { return Ok(None) };
+        match heads.get(self.head_key())? {
+            Some(data) => Ok(Some(serde_json::from_slice(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn head_key(&self) -> Vec<u8> {
+        self.path.to_string_lossy().into_owned().into_bytes()
+    }
+
+    /// Append an entry for installing `formula`, chained onto the last one. The log is
+    /// locked from reading the last entry until the new one is written, so installs
+    /// finishing together, in this process or another, can't chain onto the same entry.
+    pub fn record_install(
+        &self,
+        formula: &Formula,
+        tap_commit: Option<String>,
+        source: Option<InstallSource>,
+        checksum_override: Option<String>,
+    ) -> Result<AuditEntry> {
+        use fs2::FileExt;
+
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut file = std::fs::OpenOptions::new().create(true).append(true).open(&self.path)?;
+        file.lock_exclusive()?;
+        let last = self.entries()?.pop();
+        let (user, hostname) = identity();
+        let mut entry = AuditEntry {
+            seq: last.as_ref().map_or(1, |e| e.seq + 1),
+            timestamp: crate::core::deterministic::now(),
+            action: "install".to_string(),
+            formula: formula.name.clone(),
+            version: formula.pkg_version(),
+            tap: formula.tap.clone(),
+            tap_commit,
+            source,
+            checksum_override,
+            user,
+            hostname,
+            prev_hash: last.map_or_else(|| GENESIS_HASH.to_string(), |e| e.hash),
+            hash: String::new(),
+        };
+        entry.hash = entry.compute_hash();
+
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        file.sync_data()?;
+        if let Some(heads) = &self.heads {
+            let head = AuditHead { seq: entry.seq, hash: entry.hash.clone() };
+            heads.insert(self.head_key(), serde_json::to_vec(&head)?)?;
+            heads.flush()?;
+        }
+        // Unlocks when `file` closes
+        Ok(entry)
+    }
+}
@@ -0,0 +1,157 @@
+//! The environment a source build's commands run in, and the `ENV` statements of
+//! install scripts (`ENV["FOO"] = ...`, `ENV.append`, `ENV.prepend_path`) that shape
+//! it. Changes stay in the build; nitro's own environment is never touched.
+
+use std::collections::BTreeMap;
+use std::process::Command;
+
+use crate::core::interpolate::PathContext;
+
+/// Flags variables `ENV.append_to_cflags` adds to.
+const CFLAGS_VARIABLES: &[&str] = &["CFLAGS", "CXXFLAGS", "OBJCFLAGS", "OBJCXXFLAGS"];
+
+/// Variables set or removed on top of the inherited environment.
+#[derive(Debug, Clone, Default)]
+pub struct BuildEnv {
+    /// `None` removes the variable
+    vars: BTreeMap<String, Option<String>>,
+}
+
+impl BuildEnv {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The value a build command would see.
+    pub fn get(&self, key: &str) -> Option<String> {
+        match self.vars.get(key) {
+            Some(value) => value.clone(),
+            None => std::env::var(key).ok(),
+        }
+    }
+
+    pub fn set(&mut self, key: &str, value: impl Into<String>) {
+        self.vars.insert(key.to_string(), Some(value.into()));
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.vars.insert(key.to_string(), None);
+    }
+
+    /// Add `value` to the end of `key`, after `separator`.
+    pub fn append(&mut self, key: &str, value: &str, separator: &str) {
+        let joined = match self.get(key).filter(|current| !current.is_empty()) {
+            Some(current) => format!("{}{}{}", current, separator, value),
+            None => value.to_string(),
+        };
+        self.set(key, joined);
+    }
+
+    /// Add `value` to the start of `key`, before `separator`.
+    pub fn prepend(&mut self, key: &str, value: &str, separator: &str) {
+        let joined = match self.get(key).filter(|current| !current.is_empty()) {
+            Some(current) => format!("{}{}{}", value, separator, current),
+            None => value.to_string(),
+        };
+        self.set(key, joined);
+    }
+
+    /// Add a directory to the end of a `:`-separated search path, unless it's already there.
+    pub fn append_path(&mut self, key: &str, dir: &str) {
+        let mut dirs = self.path_entries(key, dir);
+        dirs.push(dir.to_string());
+        self.set(key, dirs.join(":"));
+    }
+
+    /// Add a directory to the start of a `:`-separated search path, moving it there
+    /// if it's already present.
+    pub fn prepend_path(&mut self, key: &str, dir: &str) {
+        let mut dirs = self.path_entries(key, dir);
+        dirs.insert(0, dir.to_string());
+        self.set(key, dirs.join(":"));
+    }
+
+    fn path_entries(&self, key: &str, except: &str) -> Vec<String> {
+        self.get(key).unwrap_or_default()
+            .split(':')
+            .filter(|d| !d.is_empty() && *d != except)
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Apply these variables to a build command.
+    pub fn apply_to(&self, command: &mut Command) {
+        for (key, value) in &self.vars {
+            match value {
+                Some(value) => command.env(key, value),
+                None => command.env_remove(key),
+            };
+        }
+    }
+
+    /// Carry out an install script line if it's an `ENV` statement, resolving paths in
+    /// its arguments with `paths`. Returns whether it was one; statements for compiler
+    /// tuning nitro doesn't do (`ENV.O0`, `ENV.permit_arch_flags`) are accepted and ignored.
+    pub fn apply_statement(&mut self, line: &str, paths: &PathContext) -> bool {
+        let line = line.trim();
+        if let Some(cap) = regex::Regex::new(r#"^ENV\[\s*["']([^"']+)["']\s*\]\s*=\s*(.+)$"#).unwrap().captures(line) {
+            match cap[2].trim() {
+                "nil" => self.remove(&cap[1]),
+                value => self.set(&cap[1], paths.evaluate(value).unwrap_or_else(|| value.to_string())),
+            }
+            return true;
+        }
+
+        let Some(cap) = regex::Regex::new(r"^ENV\.(\w+[!?]?)\s*\(?(.*?)\)?$").unwrap().captures(line) else {
+            return false;
+        };
+        let args: Vec<String> = split_args(&cap[2]).iter()
+            .map(|arg| paths.evaluate(arg).unwrap_or_else(|| arg.to_string()))
+            .collect();
+        let arg = |i: usize| args.get(i).map(String::as_str).unwrap_or_default();
+
+        match &cap[1] {
+            "append" => self.append(arg(0), arg(1), args.get(2).map(String::as_str).unwrap_or(" ")),
+            "prepend" => self.prepend(arg(0), arg(1), args.get(2).map(String::as_str).unwrap_or(" ")),
+            "append_path" => self.append_path(arg(0), arg(1)),
+            "prepend_path" => self.prepend_path(arg(0), arg(1)),
+            "prepend_create_path" => {
+                let _ = std::fs::create_dir_all(arg(1));
+                self.prepend_path(arg(0), arg(1));
+            }
+            "append_to_cflags" => {
+                for key in CFLAGS_VARIABLES {
+                    self.append(key, arg(0), " ");
+                }
+            }
+            "delete" => self.remove(arg(0)),
+            "deparallelize" => self.set("MAKEFLAGS", "-j1"),
+            "cxx11" => self.append("CXXFLAGS", "-std=c++11", " "),
+            other => tracing::debug!("Ignoring ENV.{} in install script", other),
+        }
+        true
+    }
+}
+
+/// Split a Ruby argument list on the commas outside quotes.
+fn split_args(args: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut quote = None;
+    let mut start = 0;
+    for (i, c) in args.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, ',') => {
+                parts.push(args[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    let last = args[start..].trim();
+    if !last.is_empty() {
+        parts.push(last);
+    }
+    parts
+}
@@ -0,0 +1,175 @@
+//! Cancellation for long-running steps: downloads, extraction and build commands check
+//! a token between chunks, entries and polls, and stop with an error when it fires.
+//! A token fires when cancelled, when its deadline passes (`install --timeout`), or on
+//! Ctrl-C, so code that takes a token needs no separate interrupt handling.
+
+use std::future::Future;
+use std::io::Read;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+use crate::core::{interrupt, NitroError, NitroResult};
+
+/// How often a running command is checked on
+const COMMAND_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    notify: Notify,
+    /// When the token fires by itself, and the limit it was given, for the error
+    deadline: Option<(Instant, Duration)>,
+    /// What the token was created for, e.g. "installing wget"
+    label: String,
+}
+
+/// Cheap to clone; clones share their state.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+impl CancellationToken {
+    /// A token that fires only on Ctrl-C or when cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A token that also fires `timeout` from now, for the step described by `label`.
+    pub fn with_timeout(label: impl Into<String>, timeout: Duration) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                deadline: Some((Instant::now() + timeout, timeout)),
+                label: label.into(),
+                ..Default::default()
+            }),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        self.inner.notify.notify_waiters();
+    }
+
+    fn timed_out(&self) -> bool {
+        self.inner.deadline.is_some_and(|(deadline, _)| Instant::now() >= deadline)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        interrupt::is_interrupted() || self.inner.cancelled.load(Ordering::SeqCst) || self.timed_out()
+    }
+
+    /// The error a step stopped by this token fails with: `Interrupted` for Ctrl-C or
+    /// an explicit cancel, `TimedOut` past the deadline.
+    pub fn error(&self) -> NitroError {
+        match self.inner.deadline {
+            Some((_, timeout)) if self.timed_out() && !interrupt::is_interrupted() => {
+                let label = if self.inner.label.is_empty() { "the install" } else { &self.inner.label };
+                NitroError::TimedOut(format!("{} took longer than {}s", label, timeout.as_secs()))
+            }
+            _ => NitroError::Interrupted,
+        }
+    }
+
+    /// Fail with `error()` if the token has fired. Call between steps that are safe to
+    /// stop at.
+    pub fn check(&self) -> NitroResult<()> {
+        if self.is_cancelled() {
+            Err(self.error())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Resolve once the token fires.
+    pub async fn cancelled(&self) {
+        let deadline = async {
+            match self.inner.deadline {
+                Some((deadline, _)) => tokio::time::sleep_until(deadline.into()).await,
+                None => std::future::pending().await,
+            }
+        };
+        let cancelled = async {
+            loop {
+                let notified = self.inner.notify.notified();
+                if self.inner.cancelled.load(Ordering::SeqCst) {
+                    return;
+                }
+                notified.await;
+            }
+        };
+        tokio::select! {
+            _ = interrupt::wait() => {}
+            _ = deadline => {}
+            _ = cancelled => {}
+        }
+    }
+
+    /// Run `future`, or drop it and fail with `error()` if the token fires first.
+    pub async fn run<T, E>(&self, future: impl Future<Output = Result<T, E>>) -> Result<T, E>
+    where
+        E: From<NitroError>,
+    {
+        tokio::select! {
+            result = future => result,
+            _ = self.cancelled() => Err(self.error().into()),
+        }
+    }
+}
+
+impl CancellationToken {
+    /// Run `command` with its output discarded and its stderr collected, killing it if
+    /// the token fires. It runs in a process group of its own and the whole group is
+    /// killed, so a `make -j` or `pip` doesn't leave its children running.
+    pub fn run_command(&self, command: &mut Command) -> NitroResult<(ExitStatus, Vec<u8>)> {
+        use std::os::unix::process::CommandExt;
+
+        self.check()?;
+        let mut child = command.stdout(Stdio::null()).stderr(Stdio::piped()).process_group(0).spawn()
+            .map_err(|e| NitroError::Other(format!("Failed to run {:?}: {}", command.get_program(), e)))?;
+
+        // Drained on its own thread so a chatty build can't fill the pipe and stall
+        let mut stderr_pipe = child.stderr.take();
+        let stderr = std::thread::spawn(move || {
+            let mut output = Vec::new();
+            if let Some(pipe) = stderr_pipe.as_mut() {
+                let _ = pipe.read_to_end(&mut output);
+            }
+            output
+        });
+
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if self.is_cancelled() {
+                // The group's id is the child's pid
+                unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+                let _ = child.wait();
+                return Err(self.error());
+            }
+            std::thread::sleep(COMMAND_POLL_INTERVAL);
+        };
+        Ok((status, stderr.join().unwrap_or_default()))
+    }
+}
+
+/// Parse a duration such as `90s`, `30m` or `2h`; a bare number is seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let (number, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let n: u64 = number.parse().map_err(|_| format!("'{}' is not a duration like 90s, 30m or 2h", s))?;
+    let seconds = match unit {
+        "" | "s" => n,
+        "m" => n * 60,
+        "h" => n * 60 * 60,
+        _ => return Err(format!("unknown unit '{}' in '{}'; use s, m or h", unit, s)),
+    };
+    if seconds == 0 {
+        return Err("the duration must be more than zero".to_string());
+    }
+    Ok(Duration::from_secs(seconds))
+}
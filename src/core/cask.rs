@@ -0,0 +1,188 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::core::{NitroError, NitroResult};
+
+/// A Homebrew cask (`cask "token" do ... end`): its searchable metadata, its download
+/// and the artifacts installed from it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Cask {
+    pub token: String,
+    /// Human-readable application names, e.g. "Visual Studio Code"
+    pub names: Vec<String>,
+    pub description: Option<String>,
+    pub version: String,
+    pub homepage: Option<String>,
+    /// Download URL, with `#{version}` filled in
+    #[serde(default)]
+    pub url: Option<String>,
+    /// `None` for `sha256 :no_check`
+    #[serde(default)]
+    pub sha256: Option<String>,
+    #[serde(default)]
+    pub artifacts: Vec<CaskArtifact>,
+    /// Receipt IDs from `uninstall pkgutil: ...`, possibly with `*` wildcards
+    #[serde(default)]
+    pub uninstall_pkgutil: Vec<String>,
+    /// `auto_updates true`: the app updates itself once installed
+    #[serde(default)]
+    pub auto_updates: bool,
+}
+
+/// Which casks `upgrade` reinstalls even though their version can't say they're outdated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Greedy {
+    /// Casks with `version :latest`, which can't be compared
+    pub latest: bool,
+    /// Casks with `auto_updates true`, which keep themselves current
+    pub auto_updates: bool,
+}
+
+impl Cask {
+    /// Whether the cask, installed at `installed_version`, should be upgraded. Apps that
+    /// update themselves are left alone unless `greedy` says otherwise, as are `latest`
+    /// casks, which would otherwise be reinstalled on every upgrade. Versions compare
+    /// as formula versions do, so a tap that moved back to an older one isn't an update.
+    pub fn is_outdated(&self, installed_version: &str, greedy: Greedy) -> bool {
+        use super::version::Version;
+
+        if self.version == "latest" {
+            return greedy.latest;
+        }
+        if Version::new(self.version.as_str()) <= Version::new(installed_version) {
+            return false;
+        }
+        !self.auto_updates || greedy.auto_updates
+    }
+}
+
+/// Something a cask installs, with its path inside the staged download.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CaskArtifact {
+    /// `font "FiraCode-Regular.ttf"`: copied into the font directory
+    Font(String),
+    /// `pkg "Installer.pkg"`: run through macOS's `installer`
+    Pkg(String),
+    /// `app "Firefox.app"`: copied into the applications directory
+    App(String),
+}
+
+impl CaskArtifact {
+    /// The artifact's path inside the download.
+    pub fn path(&self) -> &str {
+        match self {
+            CaskArtifact::Font(path) | CaskArtifact::Pkg(path) | CaskArtifact::App(path) => path,
+        }
+    }
+
+    /// The `font "..."` style stanza the artifact was declared with.
+    pub fn stanza(&self) -> &'static str {
+        match self {
+            CaskArtifact::Font(_) => "font",
+            CaskArtifact::Pkg(_) => "pkg",
+            CaskArtifact::App(_) => "app",
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct CaskParser;
+
+impl CaskParser {
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub fn parse_file(&self, path: &Path) -> NitroResult<Cask> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| NitroError::FormulaParse(format!("Failed to read cask file: {}", e)))?;
+        self.parse_content(&content)
+    }
+
+    pub fn parse_content(&self, content: &str) -> NitroResult<Cask> {
+        let token = regex::Regex::new(r#"cask\s+"([^"]+)"\s+do"#).unwrap()
+            .captures(content)
+            .map(|cap| cap[1].to_string())
+            .ok_or_else(|| NitroError::FormulaParse("No cask declaration found".into()))?;
+
+        let string_stanza = |stanza: &str| {
+            regex::Regex::new(&format!(r#"(?m)^\s*{}\s+"([^"]*)""#, stanza)).unwrap()
+                .captures(content)
+                .map(|cap| cap[1].to_string())
+        };
+
+        let names = regex::Regex::new(r#"(?m)^\s*name\s+"([^"]*)""#).unwrap()
+            .captures_iter(content)
+            .map(|cap| cap[1].to_string())
+            .collect();
+
+        // `version :latest` is common for casks without versioned downloads
+        let version = string_stanza("version")
+            .or_else(|| regex::Regex::new(r"(?m)^\s*version\s+:(\w+)").unwrap()
+                .captures(content)
+                .map(|cap| cap[1].to_string()))
+            .unwrap_or_else(|| "unknown".to_string());
+
+        let sha256 = regex::Regex::new(r#"(?m)^\s*sha256\s+"([a-fA-F0-9]{64})""#).unwrap()
+            .captures(content)
+            .map(|cap| cap[1].to_string());
+
+        let artifacts = regex::Regex::new(r#"(?m)^\s*(font|pkg|app)\s+"([^"]+)""#).unwrap()
+            .captures_iter(content)
+            .map(|cap| {
+                let path = interpolate_version(&cap[2], &version);
+                match &cap[1] {
+                    "font" => CaskArtifact::Font(path),
+                    "pkg" => CaskArtifact::Pkg(path),
+                    _ => CaskArtifact::App(path),
+                }
+            })
+            .collect();
+
+        // `pkgutil: "id"` or `pkgutil: ["id", ...]` inside the `uninstall` stanza
+        let uninstall_pkgutil = regex::Regex::new(r#"pkgutil:\s*(\[[^\]]*\]|"[^"]*")"#).unwrap()
+            .captures(content)
+            .map(|cap| regex::Regex::new(r#""([^"]+)""#).unwrap()
+                .captures_iter(&cap[1])
+                .map(|id| id[1].to_string())
+                .collect())
+            .unwrap_or_default();
+
+        Ok(Cask {
+            token,
+            names,
+            description: string_stanza("desc"),
+            url: string_stanza("url").map(|url| interpolate_version(&url, &version)),
+            sha256,
+            artifacts,
+            uninstall_pkgutil,
+            auto_updates: regex::Regex::new(r"(?m)^\s*auto_updates\s+true\b").unwrap().is_match(content),
+            version,
+            homepage: string_stanza("homepage"),
+        })
+    }
+}
+
+/// Fill in the `#{version}` interpolations casks use in urls and artifact paths,
+/// including the common `#{version.major}`-style helpers. Others are left as they are.
+pub fn interpolate_version(text: &str, version: &str) -> String {
+    let re = regex::Regex::new(r"#\{version(?:\.(\w+(?:\.\w+)?))?\}").unwrap();
+    re.replace_all(text, |cap: &regex::Captures| {
+        let parts: Vec<&str> = version.split('.').collect();
+        let csv: Vec<&str> = version.split(',').collect();
+        let value = match cap.get(1).map(|m| m.as_str()) {
+            None => Some(version.to_string()),
+            Some("major") => parts.first().map(|p| p.to_string()),
+            Some("minor") => parts.get(1).map(|p| p.to_string()),
+            Some("patch") => parts.get(2).map(|p| p.to_string()),
+            Some("major_minor") => Some(parts.iter().take(2).copied().collect::<Vec<_>>().join(".")),
+            Some("no_dots") => Some(version.replace('.', "")),
+            Some("dots_to_underscores") => Some(version.replace('.', "_")),
+            Some("csv.first") => csv.first().map(|p| p.to_string()),
+            Some("csv.second") => csv.get(1).map(|p| p.to_string()),
+            Some(_) => None,
+        };
+        value.unwrap_or_else(|| cap[0].to_string())
+    }).into_owned()
+}
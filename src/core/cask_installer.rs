@@ -0,0 +1,299 @@
+//! Installing casks: fetching the download, staging it, and putting each artifact
+//! where it belongs, with a record of every file placed so uninstall can undo it.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::core::cancel::CancellationToken;
+use crate::core::cask::{Cask, CaskArtifact};
+use crate::core::installer::Installer;
+use crate::core::dmg::{self, MountedImage};
+use crate::core::{fonts, pkg, NitroError};
+use crate::download::Downloader;
+
+/// An installed cask, kept in the `casks` tree of the package database so cask
+/// tokens never collide with formula names.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstalledCask {
+    pub token: String,
+    pub version: String,
+    /// Tap the cask was installed from
+    pub tap: Option<String>,
+    pub artifacts: Vec<CaskArtifact>,
+    /// Every file installed, removed again on uninstall
+    pub files: Vec<PathBuf>,
+    /// Receipts of `.pkg` installers run, whose files uninstall removes
+    #[serde(default)]
+    pub pkg_ids: Vec<String>,
+    pub installed_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Clone)]
+pub struct CaskStore {
+    tree: sled::Tree,
+}
+
+impl CaskStore {
+    pub fn open(db: &sled::Db) -> Result<Self> {
+        Ok(Self {
+            tree: db.open_tree("casks")?,
+        })
+    }
+
+    pub fn get(&self, token: &str) -> Result<Option<InstalledCask>> {
+        match self.tree.get(token)? {
+            Some(data) => Ok(Some(serde_json::from_slice(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn insert(&self, cask: &InstalledCask) -> Result<()> {
+        self.tree.insert(&cask.token, serde_json::to_vec(cask)?)?;
+        Ok(())
+    }
+
+    pub fn remove(&self, token: &str) -> Result<()> {
+        self.tree.remove(token)?;
+        Ok(())
+    }
+
+    pub fn list(&self) -> Result<Vec<InstalledCask>> {
+        let mut casks = Vec::new();
+        for entry in self.tree.iter() {
+            let (_key, value) = entry?;
+            casks.push(serde_json::from_slice(&value)?);
+        }
+        Ok(casks)
+    }
+}
+
+/// Where a cask's artifacts go.
+#[derive(Debug, Clone)]
+pub struct CaskTargets {
+    pub font_dir: PathBuf,
+    pub app_dir: PathBuf,
+    /// Replace existing files that weren't installed by this cask
+    pub force: bool,
+}
+
+/// Download, verify and stage `cask`, then install its artifacts. On failure,
+/// whatever was installed so far is removed again. A `previous` install of the cask
+/// is replaced: its files are only moved aside while the artifacts install, and put
+/// back if that fails.
+pub async fn install(cask: &Cask, downloader: &Downloader, targets: &CaskTargets, previous: Option<&InstalledCask>) -> Result<InstalledCask> {
+    let url = cask.url.as_deref()
+        .ok_or_else(|| NitroError::Other(format!("Cask {} has no url", cask.token)))?;
+    if cask.artifacts.is_empty() {
+        return Err(NitroError::Other(format!("Cask {} has no supported artifacts", cask.token)).into());
+    }
+
+    let temp_dir = tempfile::tempdir()?;
+    let file_name = url.split(['?', '#']).next().unwrap_or(url).rsplit('/').next().unwrap_or("download");
+    let download = temp_dir.path().join(file_name);
+    downloader.download_file(url, &download).await?;
+    if let Some(sha256) = &cask.sha256 {
+        Installer::verify_checksum(&download, sha256)?;
+    }
+
+    // Keeps a disk image attached until the artifacts are installed
+    let staged = stage(&download, &temp_dir.path().join("staged"))?;
+
+    let mut installed = InstalledCask {
+        token: cask.token.clone(),
+        version: cask.version.clone(),
+        tap: None,
+        artifacts: cask.artifacts.clone(),
+        files: Vec::new(),
+        pkg_ids: Vec::new(),
+        installed_at: super::deterministic::now(),
+    };
+    let mut set_aside = SetAside::new(previous.map_or(&[][..], |previous| &previous.files))?;
+    for artifact in &cask.artifacts {
+        if let Err(e) = install_artifact(artifact, &staged, targets, &mut installed, &mut set_aside) {
+            if let Err(cleanup) = uninstall(&installed) {
+                eprintln!("Warning: could not undo a partial install of {}: {}", cask.token, cleanup);
+            }
+            set_aside.restore();
+            return Err(e);
+        }
+    }
+    set_aside.discard();
+    // Receipts the new version's installers didn't take over are the old version's alone
+    for id in previous.map_or(&[][..], |previous| &previous.pkg_ids) {
+        if !installed.pkg_ids.contains(id) {
+            pkg::uninstall(id)?;
+        }
+    }
+
+    // A pkg that upgrades itself adds no new receipt, but the cask names its receipts
+    for pattern in &cask.uninstall_pkgutil {
+        if cask.artifacts.iter().any(|a| matches!(a, CaskArtifact::Pkg(_))) {
+            for id in pkg::matching_ids(pattern)? {
+                if !installed.pkg_ids.contains(&id) {
+                    installed.pkg_ids.push(id);
+                }
+            }
+        }
+    }
+
+    if cask.artifacts.iter().any(|a| matches!(a, CaskArtifact::Font(_))) {
+        fonts::refresh_font_cache(&targets.font_dir);
+    }
+    Ok(installed)
+}
+
+fn install_artifact(artifact: &CaskArtifact, staged: &Staged, targets: &CaskTargets, installed: &mut InstalledCask, set_aside: &mut SetAside) -> Result<()> {
+    match artifact {
+        CaskArtifact::Font(path) => {
+            let source = staged.locate(path)?;
+            installed.files.push(fonts::install_font(&source, &targets.font_dir, targets.force)?);
+        }
+        CaskArtifact::Pkg(path) => {
+            installed.pkg_ids.extend(pkg::install(&staged.locate(path)?)?);
+        }
+        CaskArtifact::App(path) => {
+            let source = staged.locate(path)?;
+            let dest = targets.app_dir.join(source.file_name().unwrap_or_default());
+            if dest.exists() && !targets.force {
+                return Err(NitroError::Other(format!(
+                    "{} already exists; use --force to replace it", dest.display()
+                )).into());
+            }
+            // An app being replaced is only removed once the whole cask is installed
+            set_aside.add(&dest)?;
+            std::fs::create_dir_all(&targets.app_dir)?;
+            if let Err(e) = dmg::copy_app(&source, &dest) {
+                let _ = std::fs::remove_dir_all(&dest);
+                return Err(e);
+            }
+            installed.files.push(dest);
+        }
+    }
+    Ok(())
+}
+
+/// Remove an installed cask's files and the contents of its pkg receipts. Files
+/// already gone are skipped.
+pub fn uninstall(cask: &InstalledCask) -> Result<()> {
+    remove_files(&cask.files);
+    if cask.artifacts.iter().any(|a| matches!(a, CaskArtifact::Font(_))) {
+        if let Some(dir) = cask.files.first().and_then(|f| f.parent()) {
+            fonts::refresh_font_cache(dir);
+        }
+    }
+    for id in &cask.pkg_ids {
+        pkg::uninstall(id)?;
+    }
+    Ok(())
+}
+
+/// Installed files moved out of the way, next to where they were, while what
+/// replaces them is installed.
+struct SetAside {
+    /// (original path, where it was moved)
+    moved: Vec<(PathBuf, PathBuf)>,
+}
+
+impl SetAside {
+    /// Move each of `files` aside. Files already gone are skipped; if one can't be
+    /// moved, those moved so far are put back.
+    fn new(files: &[PathBuf]) -> Result<Self> {
+        let mut set_aside = Self { moved: Vec::new() };
+        for file in files {
+            if let Err(e) = set_aside.add(file) {
+                set_aside.restore();
+                return Err(e);
+            }
+        }
+        Ok(set_aside)
+    }
+
+    /// Move `file` aside too, if it exists.
+    fn add(&mut self, file: &Path) -> Result<()> {
+        if file.symlink_metadata().is_err() {
+            return Ok(());
+        }
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        let aside = file.with_file_name(format!(".{}.nitro-previous", name));
+        std::fs::rename(file, &aside)?;
+        self.moved.push((file.to_path_buf(), aside));
+        Ok(())
+    }
+
+    /// Put the files back where they were.
+    fn restore(self) {
+        for (file, aside) in self.moved.iter().rev() {
+            if let Err(e) = std::fs::rename(aside, file) {
+                eprintln!("Warning: could not restore {} from {}: {}", file.display(), aside.display(), e);
+            }
+        }
+    }
+
+    /// Remove the files for good.
+    fn discard(self) {
+        let asides: Vec<PathBuf> = self.moved.into_iter().map(|(_, aside)| aside).collect();
+        remove_files(&asides);
+    }
+}
+
+fn remove_files(files: &[PathBuf]) {
+    for file in files {
+        // App bundles are directories
+        let removed = if file.is_dir() { std::fs::remove_dir_all(file) } else { std::fs::remove_file(file) };
+        if let Err(e) = removed {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                eprintln!("Warning: could not remove {}: {}", file.display(), e);
+            }
+        }
+    }
+}
+
+/// A download ready to install from: the directories its artifacts are found in.
+pub struct Staged {
+    pub roots: Vec<PathBuf>,
+    _image: Option<MountedImage>,
+}
+
+impl Staged {
+    /// An artifact's file in the download: at the path the cask gives, or failing that
+    /// anywhere in the download with the same file name.
+    pub fn locate(&self, path: &str) -> Result<PathBuf> {
+        if let Some(direct) = self.roots.iter().map(|root| root.join(path)).find(|p| p.exists()) {
+            return Ok(direct);
+        }
+        let file_name = Path::new(path).file_name();
+        self.roots.iter()
+            // Don't descend into bundles looking for another bundle
+            .flat_map(|root| walkdir::WalkDir::new(root).max_depth(3).into_iter().flatten())
+            .find(|entry| Some(entry.file_name()) == file_name)
+            .map(|entry| entry.into_path())
+            .ok_or_else(|| NitroError::Other(format!("{} not found in the download", path)).into())
+    }
+}
+
+/// Unpack `download` into `dir`: disk images are attached, zip and tar archives are
+/// extracted, anything else (a bare `.ttf`, say) is staged as it is.
+pub fn stage(download: &Path, dir: &Path) -> Result<Staged> {
+    std::fs::create_dir_all(dir)?;
+    let file_name = download.file_name()
+        .ok_or_else(|| NitroError::Other(format!("{} is not a file", download.display())))?;
+    let name = file_name.to_string_lossy().to_lowercase();
+
+    if name.ends_with(".dmg") {
+        let image = MountedImage::attach(download, dir)?;
+        return Ok(Staged { roots: image.mount_points.clone(), _image: Some(image) });
+    }
+
+    if name.ends_with(".zip") {
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(download)?)
+            .map_err(|e| NitroError::Other(format!("Failed to open zip archive: {}", e)))?;
+        archive.extract(dir)
+            .map_err(|e| NitroError::Other(format!("Failed to extract zip archive: {}", e)))?;
+    } else if [".tar.gz", ".tgz", ".tar.xz", ".tar.bz2"].iter().any(|ext| name.ends_with(ext)) {
+        Installer::extract_tarball(download, dir, &CancellationToken::new(), &|_| {})?;
+    } else {
+        std::fs::copy(download, dir.join(file_name))?;
+    }
+    Ok(Staged { roots: vec![dir.to_path_buf()], _image: None })
+}
@@ -0,0 +1,73 @@
+//! What an upgrade changes about a formula, beyond its version: dependencies added or
+//! dropped, new caveats and a different license. The installed side comes from the
+//! receipt, and from the formula file it records when that can still be found (in the
+//! tap, or in the tap's history at the recorded commit).
+
+use serde::Serialize;
+
+use crate::core::formula::Formula;
+use crate::core::package::Package;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct FormulaChanges {
+    pub name: String,
+    pub from_version: String,
+    pub to_version: String,
+    pub dependencies_added: Vec<String>,
+    pub dependencies_removed: Vec<String>,
+    /// The new formula's caveats, when they differ from the installed one's
+    pub caveats: Option<String>,
+    /// `(installed, new)` licenses, when they differ
+    pub license: Option<(Option<String>, Option<String>)>,
+}
+
+impl FormulaChanges {
+    /// The changes from `installed` (with `installed_formula`, the formula file it was
+    /// installed from, if known) to `new`. Caveats and licenses aren't in the receipt,
+    /// so they're only compared when the installed formula is known.
+    pub fn between(installed: &Package, installed_formula: Option<&Formula>, new: &Formula) -> Self {
+        let runtime = |formula: &Formula| -> Vec<String> {
+            formula.dependencies.iter().filter(|d| d.is_runtime()).map(|d| d.name.clone()).collect()
+        };
+        let old_dependencies = installed_formula.map(runtime).unwrap_or_else(|| installed.dependencies.clone());
+        let new_dependencies = runtime(new);
+
+        let (caveats, license) = match installed_formula {
+            Some(old) => (
+                new.caveats.clone().filter(|caveats| old.caveats.as_ref() != Some(caveats)),
+                (old.license != new.license).then(|| (old.license.clone(), new.license.clone())),
+            ),
+            None => (None, None),
+        };
+
+        Self {
+            name: new.name.clone(),
+            from_version: installed.installed_version.clone().unwrap_or_else(|| installed.version.clone()),
+            to_version: new.pkg_version(),
+            dependencies_added: new_dependencies.iter().filter(|d| !old_dependencies.contains(d)).cloned().collect(),
+            dependencies_removed: old_dependencies.iter().filter(|d| !new_dependencies.contains(d)).cloned().collect(),
+            caveats,
+            license,
+        }
+    }
+
+    /// The changes as indented lines, for printing under the formula's name.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![format!("version: {} -> {}", self.from_version, self.to_version)];
+        if !self.dependencies_added.is_empty() {
+            lines.push(format!("new dependencies: {}", self.dependencies_added.join(", ")));
+        }
+        if !self.dependencies_removed.is_empty() {
+            lines.push(format!("dropped dependencies: {}", self.dependencies_removed.join(", ")));
+        }
+        if let Some((from, to)) = &self.license {
+            let name = |license: &Option<String>| license.clone().unwrap_or_else(|| "none".to_string());
+            lines.push(format!("license: {} -> {}", name(from), name(to)));
+        }
+        if let Some(caveats) = &self.caveats {
+            lines.push("new caveats:".to_string());
+            lines.extend(caveats.lines().map(|line| format!("  {}", line)));
+        }
+        lines
+    }
+}
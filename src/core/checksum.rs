@@ -0,0 +1,61 @@
+//! SHA-256 of downloaded artifacts. `sha2` picks the CPU's SHA extensions (SHA-NI on
+//! x86-64, the ARMv8 crypto extensions) at runtime, so the cost left is getting the bytes
+//! to it: large files are memory-mapped and read sequentially rather than copied
+//! through a buffer. A single digest can't be split across threads, since each block
+//! depends on the one before, so parallelism is across files (`sha256_files`).
+
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Files at least this big are memory-mapped; below it, mapping costs more than it saves
+pub const MMAP_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+/// Read size for files hashed through a buffer
+const BUFFER_SIZE: usize = 256 * 1024;
+
+/// The SHA-256 of the file at `path`, hex encoded.
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let file = File::open(path)?;
+    if file.metadata()?.len() >= MMAP_THRESHOLD {
+        if let Some(digest) = sha256_mapped(&file) {
+            return Ok(digest);
+        }
+    }
+    sha256_buffered(file)
+}
+
+/// The SHA-256 of `file` read into memory a buffer at a time.
+pub fn sha256_buffered(mut file: File) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; BUFFER_SIZE];
+    loop {
+        let n = match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        hasher.update(&buffer[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// The SHA-256 of `file` hashed straight from a memory map, or `None` if it can't be
+/// mapped (some filesystems don't support it) so the caller can read it instead.
+pub fn sha256_mapped(file: &File) -> Option<String> {
+    // Safety: the map is only read, and only while this function runs. Downloads are
+    // written to completion before they're verified; a file truncated underneath us
+    // anyway would fault rather than hash, which nothing here does to its own files.
+    let map = unsafe { memmap2::Mmap::map(file) }.ok()?;
+    #[cfg(unix)]
+    let _ = map.advise(memmap2::Advice::Sequential);
+    Some(hex::encode(Sha256::digest(&map[..])))
+}
+
+/// The SHA-256 of each of `paths`, hashed in parallel.
+pub fn sha256_files(paths: &[PathBuf]) -> Vec<io::Result<String>> {
+    paths.par_iter().map(|path| sha256_file(path)).collect()
+}
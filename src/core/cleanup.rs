@@ -0,0 +1,65 @@
+//! Old kegs: versions of a formula left in the Cellar beside the one in use, by
+//! upgrades run without `--cleanup` or installs of a specific version. Also unpacked
+//! bottles no installed keg was cloned from any more.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use super::installer::{keg_owner, KegOwner};
+use super::version::PkgVersion;
+
+/// A keg `cleanup` removes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OldKeg {
+    pub name: String,
+    pub version: String,
+    pub path: PathBuf,
+    /// Bytes taken up by the keg's files
+    pub size: u64,
+}
+
+/// The version of `name` in use: the version registered as installed, or failing that
+/// the keg `opt/<name>` under `prefix` points at. The database comes first because in a
+/// prefix shared with Homebrew the `opt/` links are Homebrew's.
+pub fn current_version(prefix: &Path, name: &str, installed: &HashMap<String, String>) -> Option<String> {
+    installed.get(name).cloned().or_else(|| {
+        let target = std::fs::read_link(prefix.join("opt").join(name)).ok()?;
+        Some(target.file_name()?.to_string_lossy().into_owned())
+    })
+}
+
+/// Every keg in `cellar` other than the version of its formula in use, sorted by name
+/// and version. Formulae whose version in use can't be told are skipped, as are
+/// Homebrew's kegs in a Cellar shared with it.
+pub fn old_kegs(cellar: &Path, prefix: &Path, installed: &HashMap<String, String>) -> Vec<OldKeg> {
+    let mut kegs = Vec::new();
+    for formula_dir in std::fs::read_dir(cellar).into_iter().flatten().flatten() {
+        let name = formula_dir.file_name().to_string_lossy().into_owned();
+        let Some(current) = current_version(prefix, &name, installed) else {
+            continue;
+        };
+        for keg in std::fs::read_dir(formula_dir.path()).into_iter().flatten().flatten() {
+            let version = keg.file_name().to_string_lossy().into_owned();
+            let path = keg.path();
+            if version == current || !path.is_dir() || keg_owner(&path) == Some(KegOwner::Homebrew) {
+                continue;
+            }
+            kegs.push(OldKeg { name: name.clone(), version, size: super::disk::size_of(&path), path });
+        }
+    }
+    kegs.sort_by_cached_key(|keg| (keg.name.clone(), PkgVersion::parse(&keg.version)));
+    kegs
+}
+
+/// The unpacked bottles in `dir` (see `installer::unpacked_bottle_dir`) that no keg
+/// shares files with any more, sorted. Bottles cloned with APFS never share files, so
+/// on macOS this is all of them.
+pub fn unused_unpacked_bottles(dir: &Path) -> Vec<PathBuf> {
+    let mut unused: Vec<PathBuf> = std::fs::read_dir(dir).into_iter().flatten().flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir() && !super::clone::is_shared(path))
+        .collect();
+    unused.sort();
+    unused
+}
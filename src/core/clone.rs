@@ -0,0 +1,71 @@
+//! Copying a directory tree without copying its data: an APFS clone on macOS, hard
+//! links elsewhere. Both need the copy on the same filesystem as the original; callers
+//! fall back to copying properly when `clone_tree` fails. Hard-linked files share one
+//! inode, so whatever changes a cloned file must replace it (write a new file and
+//! rename it over) rather than write into it.
+
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+/// Clone the directory `src` to `dst`, which must not exist. On failure nothing is
+/// left at `dst`.
+pub fn clone_tree(src: &Path, dst: &Path) -> io::Result<()> {
+    if std::fs::symlink_metadata(dst).is_ok() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", dst.display())));
+    }
+    #[cfg(target_os = "macos")]
+    if clonefile(src, dst).is_ok() {
+        return Ok(());
+    }
+    link_tree(src, dst).inspect_err(|_| {
+        let _ = std::fs::remove_dir_all(dst);
+    })
+}
+
+#[cfg(target_os = "macos")]
+fn clonefile(src: &Path, dst: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let src = CString::new(src.as_os_str().as_bytes())?;
+    let dst = CString::new(dst.as_os_str().as_bytes())?;
+    // SAFETY: both are valid NUL-terminated paths for the duration of the call
+    if unsafe { libc::clonefile(src.as_ptr(), dst.as_ptr(), 0) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Recreate `src`'s directories and symlinks at `dst` and hard-link its files.
+/// Directories get their permissions once everything is in them, so a read-only one
+/// can still be filled.
+fn link_tree(src: &Path, dst: &Path) -> io::Result<()> {
+    let mut dirs = Vec::new();
+    for entry in walkdir::WalkDir::new(src) {
+        let entry = entry.map_err(io::Error::other)?;
+        let target = dst.join(entry.path().strip_prefix(src).map_err(io::Error::other)?);
+        let file_type = entry.file_type();
+        if file_type.is_dir() {
+            std::fs::create_dir(&target)?;
+            dirs.push((target, entry.metadata().map_err(io::Error::other)?.permissions()));
+        } else if file_type.is_symlink() {
+            std::os::unix::fs::symlink(std::fs::read_link(entry.path())?, &target)?;
+        } else {
+            std::fs::hard_link(entry.path(), &target)?;
+        }
+    }
+    for (dir, permissions) in dirs.into_iter().rev() {
+        std::fs::set_permissions(dir, permissions)?;
+    }
+    Ok(())
+}
+
+/// Whether any file under `dir` has another hard link, i.e. a clone of it still uses
+/// it. APFS clones don't count; they share blocks, not inodes.
+pub fn is_shared(dir: &Path) -> bool {
+    walkdir::WalkDir::new(dir).into_iter().flatten()
+        .filter(|entry| entry.file_type().is_file())
+        .any(|entry| entry.metadata().is_ok_and(|m| m.nlink() > 1))
+}
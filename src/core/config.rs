@@ -0,0 +1,69 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::cache::shared::CacheConfig;
+use crate::core::analytics::AnalyticsConfig;
+use crate::core::installer::InstallConfig;
+use crate::core::tap::TapsConfig;
+use crate::core::NitroError;
+use crate::daemon::DaemonConfig;
+use crate::download::github::GitHubConfig;
+use crate::download::DownloadConfig;
+use crate::ui::i18n::UiConfig;
+
+/// User configuration stored in `config.toml` under the Nitro config directory.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub analytics: AnalyticsConfig,
+    pub download: DownloadConfig,
+    pub github: GitHubConfig,
+    pub install: InstallConfig,
+    pub taps: TapsConfig,
+    pub daemon: DaemonConfig,
+    pub ui: UiConfig,
+    pub cache: CacheConfig,
+    /// Providers of virtual dependencies by capability, most preferred first (see
+    /// `core::providers`), replacing the built-in list for that capability
+    pub providers: BTreeMap<String, Vec<String>>,
+}
+
+impl Config {
+    pub fn path() -> Result<PathBuf> {
+        if let Ok(path) = std::env::var("NITRO_CONFIG") {
+            return Ok(PathBuf::from(path));
+        }
+
+        let config_dir = directories::ProjectDirs::from("com", "nitro", "nitro")
+            .ok_or_else(|| NitroError::Other("Could not determine config directory".into()))?;
+
+        Ok(config_dir.config_dir().join("config.toml"))
+    }
+
+    /// Load the configuration, falling back to defaults when no file exists yet.
+    pub fn load() -> Result<Self> {
+        let path = Self::path()?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let data = std::fs::read_to_string(&path)?;
+        toml::from_str(&data).map_err(|e| {
+            NitroError::Other(format!("Invalid config file {}: {}", path.display(), e)).into()
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path()?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let data = toml::to_string_pretty(self)
+            .map_err(|e| NitroError::Other(format!("Could not serialize config: {}", e)))?;
+        std::fs::write(path, data)?;
+        Ok(())
+    }
+}
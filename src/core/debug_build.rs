@@ -0,0 +1,54 @@
+//! `nitro debug-build`: a source build that hands the prepared build directory to the
+//! user in a shell, with the build environment loaded, either when the build fails or
+//! before it starts. Whatever the shell leaves in the keg is installed.
+
+use std::path::Path;
+use std::process::Command;
+use std::sync::OnceLock;
+
+use crate::core::build_env::BuildEnv;
+
+/// When the shell is opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellAt {
+    /// After the install steps fail
+    Failure,
+    /// Instead of running the install steps
+    Start,
+}
+
+static DEBUG_BUILD: OnceLock<(String, ShellAt)> = OnceLock::new();
+
+/// Debug the source build of `formula` in this process. Other formulae (its
+/// dependencies) build as usual.
+pub fn enable(formula: &str, at: ShellAt) {
+    let _ = DEBUG_BUILD.set((formula.to_string(), at));
+}
+
+/// When to open a shell while building `formula`, if it's being debugged.
+pub fn shell_at(formula: &str) -> Option<ShellAt> {
+    DEBUG_BUILD.get().filter(|(name, _)| name == formula).map(|(_, at)| *at)
+}
+
+/// Run the user's shell in `dir` with `env` applied, and wait for it to exit. A
+/// non-zero exit status is the user giving up, and is an error.
+pub fn open_shell(dir: &Path, env: &BuildEnv, keg: &Path) -> std::io::Result<()> {
+    let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string());
+    eprintln!("Opening {} in {}", shell, dir.display());
+    eprintln!("The build environment is loaded. Install into {} (it's $PREFIX), then exit;", keg.display());
+    eprintln!("whatever is there is installed. Exit non-zero, or without installing anything, to give up.");
+
+    let mut command = Command::new(&shell);
+    command.current_dir(dir).env("NITRO_DEBUG_BUILD", "1");
+    env.apply_to(&mut command);
+    let status = command.status()?;
+    if !status.success() {
+        return Err(std::io::Error::other(format!("{} exited with {}", shell, status)));
+    }
+    Ok(())
+}
+
+/// Whether the shell left anything in `keg`.
+pub fn keg_populated(keg: &Path) -> bool {
+    std::fs::read_dir(keg).is_ok_and(|mut entries| entries.next().is_some())
+}
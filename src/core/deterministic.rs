@@ -0,0 +1,41 @@
+//! `--deterministic`: the same inputs give the same result, byte for byte, so CI can
+//! diff plans and installs between runs. Formulae are resolved, fetched and installed
+//! in name order rather than discovery order, and receipts are stamped with
+//! `SOURCE_DATE_EPOCH` instead of the current time.
+
+use chrono::{DateTime, Utc};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static DETERMINISTIC: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+    DETERMINISTIC.store(true, Ordering::SeqCst);
+}
+
+pub fn is_enabled() -> bool {
+    DETERMINISTIC.load(Ordering::SeqCst)
+}
+
+/// The time to record in receipts: now, or in deterministic mode the time
+/// `SOURCE_DATE_EPOCH` gives (the Unix epoch when it's unset or invalid).
+pub fn now() -> DateTime<Utc> {
+    if !is_enabled() {
+        return Utc::now();
+    }
+    source_date_epoch(std::env::var("SOURCE_DATE_EPOCH").ok().as_deref())
+}
+
+/// The time a `SOURCE_DATE_EPOCH` value stands for: seconds since the Unix epoch.
+pub fn source_date_epoch(value: Option<&str>) -> DateTime<Utc> {
+    value
+        .and_then(|value| value.trim().parse::<i64>().ok())
+        .and_then(|seconds| DateTime::from_timestamp(seconds, 0))
+        .unwrap_or(DateTime::UNIX_EPOCH)
+}
+
+/// Sort `items` by `key` in deterministic mode; otherwise leave their order alone.
+pub fn sort_by_key<T, K: Ord>(items: &mut [T], key: impl FnMut(&T) -> K) {
+    if is_enabled() {
+        items.sort_by_key(key);
+    }
+}
@@ -0,0 +1,96 @@
+use indicatif::HumanBytes;
+use std::path::{Path, PathBuf};
+
+use crate::core::{NitroError, NitroResult};
+
+/// Headroom kept free on every volume on top of the computed requirement
+pub const SPACE_MARGIN: u64 = 100 * 1024 * 1024;
+
+/// Bottles typically expand to about this many times their compressed size
+pub const BOTTLE_EXPANSION_FACTOR: u64 = 3;
+
+/// Source builds need the unpacked tree plus objects, on top of the final keg
+pub const SOURCE_EXPANSION_FACTOR: u64 = 6;
+
+/// Space needed under a directory for an operation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpaceRequirement {
+    pub path: PathBuf,
+    pub bytes: u64,
+}
+
+impl SpaceRequirement {
+    pub fn new(path: impl Into<PathBuf>, bytes: u64) -> Self {
+        Self { path: path.into(), bytes }
+    }
+}
+
+/// Free space available to the current user on the volume holding `path`.
+pub fn available_space(path: &Path) -> Option<u64> {
+    let disks = sysinfo::Disks::new_with_refreshed_list();
+    let path = existing_ancestor(path)?;
+
+    // The most specific mount point containing the path is its volume
+    disks.list().iter()
+        .filter(|disk| path.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .map(|disk| disk.available_space())
+}
+
+/// Mount point of the volume holding `path`, used to add up requirements that share a disk.
+fn volume_of(path: &Path) -> Option<PathBuf> {
+    let disks = sysinfo::Disks::new_with_refreshed_list();
+    let path = existing_ancestor(path)?;
+
+    disks.list().iter()
+        .filter(|disk| path.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .map(|disk| disk.mount_point().to_path_buf())
+}
+
+fn existing_ancestor(path: &Path) -> Option<PathBuf> {
+    path.ancestors()
+        .find(|p| p.exists())
+        .and_then(|p| p.canonicalize().ok())
+}
+
+/// Refuse to proceed when any volume lacks room for its share of `requirements`.
+/// Requirements on the same volume are added together. Volumes whose free space
+/// can't be determined are not checked.
+pub fn ensure_space(requirements: &[SpaceRequirement]) -> NitroResult<()> {
+    let mut volumes: Vec<(PathBuf, PathBuf, u64)> = Vec::new();
+    for requirement in requirements {
+        let volume = volume_of(&requirement.path).unwrap_or_else(|| requirement.path.clone());
+        match volumes.iter_mut().find(|(v, _, _)| *v == volume) {
+            Some((_, _, bytes)) => *bytes += requirement.bytes,
+            None => volumes.push((volume, requirement.path.clone(), requirement.bytes)),
+        }
+    }
+
+    for (volume, path, bytes) in volumes {
+        let Some(available) = available_space(&path) else {
+            continue;
+        };
+        let needed = bytes + SPACE_MARGIN;
+        if available < needed {
+            return Err(NitroError::InsufficientSpace(format!(
+                "{} needed on {} (for {}), only {} available. Free up space or pass --skip-space-check",
+                HumanBytes(needed),
+                volume.display(),
+                path.display(),
+                HumanBytes(available),
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Bytes taken up by the files under `path`, not following symlinks.
+pub fn size_of(path: &Path) -> u64 {
+    walkdir::WalkDir::new(path).follow_links(false).into_iter()
+        .filter_map(|entry| entry.ok()?.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
@@ -0,0 +1,118 @@
+//! macOS disk images: attached with `hdiutil` for the cask installer to copy from, and
+//! always detached again, even when the install fails partway.
+
+use anyhow::Result;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::core::NitroError;
+
+/// Answers for images with a license agreement: quit the pager, then agree.
+const EULA_RESPONSE: &[u8] = b"q\nY\n";
+
+/// An attached disk image, detached when dropped.
+#[derive(Debug)]
+pub struct MountedImage {
+    /// Every volume the image mounted; APFS images and images with several
+    /// partitions can have more than one
+    pub mount_points: Vec<PathBuf>,
+}
+
+impl MountedImage {
+    /// Attach `image` read-only under `mount_root` without showing it in the Finder.
+    pub fn attach(image: &Path, mount_root: &Path) -> Result<Self> {
+        if !cfg!(target_os = "macos") {
+            return Err(NitroError::Other("Disk images are only supported on macOS".into()).into());
+        }
+
+        let mut child = Command::new("hdiutil")
+            .args(["attach", "-plist", "-nobrowse", "-readonly", "-noverify", "-noautoopen", "-mountrandom"])
+            .arg(mount_root)
+            .arg(image)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| NitroError::Other(format!("Failed to run hdiutil: {}", e)))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            // Images without a license never read this
+            let _ = stdin.write_all(EULA_RESPONSE);
+        }
+        let output = child.wait_with_output()?;
+
+        // The plist comes after the license text, if there was one
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let mounted = Self { mount_points: mount_points(&stdout) };
+        if !output.status.success() {
+            return Err(NitroError::Other(format!(
+                "hdiutil attach {} failed: {}", image.display(), String::from_utf8_lossy(&output.stderr).trim()
+            )).into());
+        }
+        if mounted.mount_points.is_empty() {
+            return Err(NitroError::Other(format!("{} has no mountable volumes", image.display())).into());
+        }
+        Ok(mounted)
+    }
+
+    /// Detach every volume, forcing it if a plain detach fails (e.g. Spotlight still
+    /// has a file open).
+    pub fn detach(&mut self) -> Result<()> {
+        let mut failed = Vec::new();
+        for mount_point in self.mount_points.drain(..) {
+            let detached = |force: bool| {
+                let mut command = Command::new("hdiutil");
+                command.arg("detach").arg(&mount_point);
+                if force {
+                    command.arg("-force");
+                }
+                command.output().map(|o| o.status.success()).unwrap_or(false)
+            };
+            if !detached(false) && !detached(true) {
+                failed.push(mount_point.display().to_string());
+            }
+        }
+        if !failed.is_empty() {
+            return Err(NitroError::Other(format!("Could not detach {}", failed.join(", "))).into());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MountedImage {
+    fn drop(&mut self) {
+        if let Err(e) = self.detach() {
+            eprintln!("Warning: {}", e);
+        }
+    }
+}
+
+/// The `mount-point` values in `hdiutil attach -plist` output.
+pub fn mount_points(plist: &str) -> Vec<PathBuf> {
+    regex::Regex::new(r"<key>mount-point</key>\s*<string>([^<]+)</string>").unwrap()
+        .captures_iter(plist)
+        .map(|cap| PathBuf::from(&cap[1]))
+        .collect()
+}
+
+/// Copy an `.app` bundle to `dest`, keeping its symlinks, permissions and (on macOS)
+/// extended attributes and code signature intact.
+pub fn copy_app(app: &Path, dest: &Path) -> Result<()> {
+    let mut command = if cfg!(target_os = "macos") {
+        let mut command = Command::new("ditto");
+        command.arg(app).arg(dest);
+        command
+    } else {
+        let mut command = Command::new("cp");
+        command.arg("-a").arg(app).arg(dest);
+        command
+    };
+    let output = command.output()
+        .map_err(|e| NitroError::Other(format!("Failed to copy {}: {}", app.display(), e)))?;
+    if !output.status.success() {
+        return Err(NitroError::Other(format!(
+            "Failed to copy {}: {}", app.display(), String::from_utf8_lossy(&output.stderr).trim()
+        )).into());
+    }
+    Ok(())
+}
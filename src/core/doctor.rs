@@ -0,0 +1,266 @@
+use serde::Serialize;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::download::Downloader;
+
+/// Requests slower than this are reported as a warning
+const SLOW_RESPONSE: Duration = Duration::from_secs(2);
+
+/// Time allowed for each endpoint check
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How serious a check's finding is. Only errors make `nitro doctor` fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Ok,
+    Warning,
+    Error,
+}
+
+/// The result of one diagnostic check.
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    /// Stable identifier for tools consuming `doctor --json`, e.g. `network.endpoint.ghcr.io`
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "severity")]
+    pub status: CheckStatus,
+    pub message: String,
+    /// What to do about a warning or error
+    pub remediation: Option<String>,
+    /// Round-trip time, for network checks that got a response
+    pub latency_ms: Option<u64>,
+}
+
+impl Check {
+    fn new(id: impl Into<String>, name: impl Into<String>, status: CheckStatus, message: impl Into<String>) -> Self {
+        Self { id: id.into(), name: name.into(), status, message: message.into(), remediation: None, latency_ms: None }
+    }
+
+    fn with_remediation(self, remediation: impl Into<String>) -> Self {
+        Self { remediation: Some(remediation.into()), ..self }
+    }
+}
+
+/// Checks as `doctor --json` prints them, with a count per severity.
+pub fn report_json(checks: &[Check]) -> serde_json::Value {
+    let count = |status| checks.iter().filter(|c| c.status == status).count();
+    serde_json::json!({
+        "checks": checks,
+        "summary": {
+            "ok": count(CheckStatus::Ok),
+            "warning": count(CheckStatus::Warning),
+            "error": count(CheckStatus::Error),
+        },
+    })
+}
+
+/// Hosts every install depends on: formula metadata, bottles and taps.
+pub const DEFAULT_ENDPOINTS: &[(&str, &str)] = &[
+    ("github.com", "https://github.com"),
+    ("ghcr.io", "https://ghcr.io/v2/"),
+    ("formulae.brew.sh", "https://formulae.brew.sh/api/formula.json"),
+];
+
+/// Proxy variables, in the order HTTP clients consult them.
+const PROXY_VARIABLES: &[&str] = &["HTTPS_PROXY", "https_proxy", "HTTP_PROXY", "http_proxy", "ALL_PROXY", "all_proxy"];
+
+/// Network diagnostics: proxy settings, then reachability and latency of the default
+/// endpoints and the shared cache, if one is configured (`cache.shared_url`).
+pub async fn network_checks(downloader: &Downloader) -> Vec<Check> {
+    let mut checks = proxy_checks(|name| std::env::var(name).ok());
+
+    let mut endpoints: Vec<(String, String, String)> = DEFAULT_ENDPOINTS.iter()
+        .map(|(name, url)| (format!("network.endpoint.{}", name), name.to_string(), url.to_string()))
+        .collect();
+    if let Some(url) = crate::core::config::Config::load().ok().and_then(|config| config.cache.shared_url) {
+        endpoints.push(("network.shared_cache".to_string(), format!("{} (cache.shared_url)", url), url));
+    }
+
+    let results = futures::future::join_all(
+        endpoints.iter().map(|(id, name, url)| async move {
+            Check { id: id.clone(), ..check_endpoint(downloader, name, url).await }
+        })
+    ).await;
+    checks.extend(results);
+    checks
+}
+
+/// Validate proxy environment variables, read through `var`.
+pub fn proxy_checks(var: impl Fn(&str) -> Option<String>) -> Vec<Check> {
+    let mut checks = Vec::new();
+    for name in PROXY_VARIABLES {
+        let Some(value) = var(name).filter(|v| !v.trim().is_empty()) else {
+            continue;
+        };
+        let id = format!("network.proxy.{}", name.to_lowercase());
+        let label = format!("proxy {}", name);
+        let check = match reqwest::Url::parse(&value) {
+            Ok(url) if matches!(url.scheme(), "http" | "https" | "socks5" | "socks5h") && url.host().is_some() => {
+                Check::new(id, label, CheckStatus::Ok, format!("using {}://{}", url.scheme(), url.host_str().unwrap_or_default()))
+            }
+            Ok(url) => Check::new(id, label, CheckStatus::Error, format!("unsupported proxy scheme '{}' in {}", url.scheme(), value))
+                .with_remediation(format!("Set {} to an http, https or socks5 URL such as http://proxy.example.com:3128", name)),
+            Err(e) => Check::new(id, label, CheckStatus::Error, format!("'{}' is not a valid proxy URL: {}", value, e))
+                .with_remediation(format!("Set {} to a full URL including the scheme, such as http://{}", name, value.trim())),
+        };
+        checks.push(check);
+    }
+    checks
+}
+
+/// Broken symlinks in the link directories under `prefix` (see `core::prune`).
+pub fn link_checks(prefix: &std::path::Path, link_dir: &std::path::Path) -> Vec<Check> {
+    let links = super::prune::broken_links(&super::prune::link_dirs(prefix, link_dir));
+    let check = if links.is_empty() {
+        Check::new("links.broken", "broken symlinks", CheckStatus::Ok, "none")
+    } else {
+        let mut owners: Vec<&str> = links.iter().filter_map(|link| link.owner.as_deref()).collect();
+        owners.sort();
+        owners.dedup();
+        let from = if owners.is_empty() { String::new() } else { format!(" (from {})", owners.join(", ")) };
+        Check::new("links.broken", "broken symlinks", CheckStatus::Warning, format!("{} dangling link(s){}", links.len(), from))
+            .with_remediation("Remove them with `nitro prune`")
+    };
+    vec![check]
+}
+
+/// The commands nitro has linked into `link_dir`: its symlinks that resolve to a file in
+/// `cellar`, sorted by name. Broken links are `link_checks`' business.
+pub fn linked_commands(link_dir: &Path, cellar: &Path) -> Vec<String> {
+    let cellar = cellar.canonicalize().unwrap_or_else(|_| cellar.to_path_buf());
+    let mut commands: Vec<String> = std::fs::read_dir(link_dir).into_iter().flatten().flatten()
+        .filter(|entry| entry.path().is_symlink() && entry.path().canonicalize().is_ok_and(|target| target.starts_with(&cellar)))
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+    commands.sort();
+    commands
+}
+
+/// Where a shell looking `command` up in `path` (a `PATH` value) finds it, like `which`.
+pub fn which(command: &str, path: &OsStr) -> Option<PathBuf> {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::env::split_paths(path)
+        .map(|dir| dir.join(command))
+        .find(|candidate| std::fs::metadata(candidate).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0))
+}
+
+/// Whose a directory on PATH is, for telling the user what shadows a command.
+fn path_owner(dir: &Path) -> String {
+    const SYSTEM_DIRS: &[&str] = &["/bin", "/sbin", "/usr/bin", "/usr/sbin"];
+    const HOMEBREW_DIRS: &[&str] = &["/opt/homebrew/bin", "/opt/homebrew/sbin", "/usr/local/bin", "/usr/local/sbin", "/home/linuxbrew/.linuxbrew/bin", "/home/linuxbrew/.linuxbrew/sbin"];
+
+    if SYSTEM_DIRS.iter().any(|d| dir == Path::new(d)) {
+        "the system".into()
+    } else if HOMEBREW_DIRS.iter().any(|d| dir == Path::new(d)) {
+        "Homebrew".into()
+    } else {
+        dir.display().to_string()
+    }
+}
+
+/// Whether each of `commands` linked into `link_dir` is what a shell with `path` as its
+/// `PATH` runs, or whether something earlier on PATH (a system binary, brew's) shadows
+/// it. The check IDs are `path.link_dir` and `path.shadowed.<command>`.
+pub fn path_checks(link_dir: &Path, commands: &[String], path: &OsStr) -> Vec<Check> {
+    let same_dir = |a: &Path, b: &Path| a == b || a.canonicalize().ok().is_some_and(|a| b.canonicalize().is_ok_and(|b| a == b));
+    let fix = format!(
+        "Put {} first on PATH: add `eval \"$(nitro shellenv)\"` to the end of your shell profile, or `export PATH=\"{}:$PATH\"`",
+        link_dir.display(), link_dir.display()
+    );
+
+    if !std::env::split_paths(path).any(|dir| same_dir(&dir, link_dir)) {
+        return vec![
+            Check::new("path.link_dir", "link directory on PATH", CheckStatus::Error, format!("{} is not on PATH", link_dir.display()))
+                .with_remediation(fix),
+        ];
+    }
+    let mut checks = vec![Check::new("path.link_dir", "link directory on PATH", CheckStatus::Ok, link_dir.display().to_string())];
+
+    let mut shadowed = 0;
+    for command in commands {
+        let Some(found) = which(command, path) else {
+            continue;
+        };
+        let dir = found.parent().unwrap_or(Path::new(""));
+        if same_dir(dir, link_dir) {
+            continue;
+        }
+        shadowed += 1;
+        checks.push(
+            Check::new(
+                format!("path.shadowed.{}", command),
+                format!("`{}` shadowed", command),
+                CheckStatus::Warning,
+                format!("{} from {} runs instead of nitro's", found.display(), path_owner(dir)),
+            )
+            .with_remediation(fix.clone()),
+        );
+    }
+    if shadowed == 0 {
+        checks.push(Check::new("path.shadowed", "shadowed commands", CheckStatus::Ok, format!("none of {} checked", commands.len())));
+    }
+    checks
+}
+
+/// Request `url` and time the response. Any HTTP status counts as reachable (ghcr.io
+/// answers 401 without a token); connection, TLS and timeout failures don't. The
+/// check's ID is `network.endpoint.<name>`.
+pub async fn check_endpoint(downloader: &Downloader, name: &str, url: &str) -> Check {
+    let id = format!("network.endpoint.{}", name);
+    let start = Instant::now();
+    let result = downloader.client().head(url).timeout(CHECK_TIMEOUT).send().await;
+    let latency = start.elapsed();
+
+    match result {
+        Ok(response) => {
+            let check = if latency > SLOW_RESPONSE {
+                Check::new(id, name, CheckStatus::Warning, format!("slow response (HTTP {})", response.status().as_u16()))
+                    .with_remediation("Downloads from this host will be slow; set cache.shared_url in the config file to a cache closer to this machine")
+            } else {
+                Check::new(id, name, CheckStatus::Ok, format!("reachable (HTTP {})", response.status().as_u16()))
+            };
+            Check { latency_ms: Some(latency.as_millis() as u64), ..check }
+        }
+        Err(e) => {
+            let (message, remediation) = describe_request_error(&e);
+            Check::new(id, name, CheckStatus::Error, message).with_remediation(remediation)
+        }
+    }
+}
+
+/// A diagnosis for a failed request and what to do about it, spotting TLS
+/// interception by proxies and firewalls.
+fn describe_request_error(error: &reqwest::Error) -> (String, &'static str) {
+    let mut chain = error.to_string();
+    let mut source = std::error::Error::source(error);
+    while let Some(e) = source {
+        chain.push_str(": ");
+        chain.push_str(&e.to_string());
+        source = e.source();
+    }
+
+    let lower = chain.to_lowercase();
+    if lower.contains("certificate") || lower.contains("unknownissuer") || lower.contains("invalid peer") {
+        (
+            format!("TLS certificate not trusted, which usually means a proxy or firewall is intercepting HTTPS ({})", chain),
+            "Add the intercepting proxy's CA certificate to the system trust store, or point SSL_CERT_FILE at a bundle that includes it",
+        )
+    } else if error.is_timeout() {
+        (
+            format!("timed out after {}s", CHECK_TIMEOUT.as_secs()),
+            "Check that this host isn't blocked by a firewall, or set HTTPS_PROXY if connections must go through a proxy",
+        )
+    } else if error.is_connect() {
+        (
+            format!("could not connect ({})", chain),
+            "Check the network connection and DNS, or set HTTPS_PROXY if connections must go through a proxy",
+        )
+    } else {
+        (chain, "Check the network connection and proxy settings")
+    }
+}
@@ -23,9 +23,39 @@ pub enum NitroError {
     #[error("Tap error: {0}")]
     TapError(String),
 
+    #[error("Git error: {0}")]
+    Git(#[from] super::git::GitError),
+
     #[error("Search error: {0}")]
     SearchError(String),
 
+    #[error("Insufficient disk space: {0}")]
+    InsufficientSpace(String),
+
+    #[error("Checksum mismatch: expected {expected}, got {actual}")]
+    ChecksumMismatch { expected: String, actual: String },
+
+    #[error("Incompatible bottle: {0}")]
+    IncompatibleBottle(String),
+
+    #[error("Blocked by policy: {0}")]
+    BlockedByPolicy(String),
+
+    #[error("Link conflict: {0}")]
+    LinkConflict(String),
+
+    #[error("Untrusted tap: {0}")]
+    UntrustedTap(String),
+
+    #[error("Interrupted")]
+    Interrupted,
+
+    #[error("Timed out: {0}")]
+    TimedOut(String),
+
+    #[error("{failed} of {total} failed")]
+    PartialFailure { failed: usize, total: usize },
+
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 
@@ -48,4 +78,53 @@ pub enum NitroError {
     Other(String),
 }
 
+impl NitroError {
+    /// The error in the user's language (see `ui::i18n`).
+    pub fn localized(&self) -> String {
+        use crate::ui::i18n::tf;
+
+        let (key, detail): (&str, String) = match self {
+            NitroError::PackageNotFound(detail) => ("error.package_not_found", detail.clone()),
+            NitroError::FormulaParse(detail) => ("error.formula_parse", detail.clone()),
+            NitroError::DependencyResolution(detail) => ("error.dependency_resolution", detail.clone()),
+            NitroError::InstallationFailed(detail) => ("error.installation_failed", detail.clone()),
+            NitroError::DownloadFailed(detail) => ("error.download_failed", detail.clone()),
+            NitroError::CacheError(detail) => ("error.cache", detail.clone()),
+            NitroError::TapError(detail) => ("error.tap", detail.clone()),
+            NitroError::Git(e) => ("error.git", e.to_string()),
+            NitroError::SearchError(detail) => ("error.search", detail.clone()),
+            NitroError::InsufficientSpace(detail) => ("error.insufficient_space", detail.clone()),
+            NitroError::ChecksumMismatch { expected, actual } => {
+                return tf("error.checksum_mismatch", &[("expected", expected), ("actual", actual)]);
+            }
+            NitroError::IncompatibleBottle(detail) => ("error.incompatible_bottle", detail.clone()),
+            NitroError::BlockedByPolicy(detail) => ("error.blocked_by_policy", detail.clone()),
+            NitroError::LinkConflict(detail) => ("error.link_conflict", detail.clone()),
+            NitroError::UntrustedTap(detail) => ("error.untrusted_tap", detail.clone()),
+            NitroError::Interrupted => ("error.interrupted", String::new()),
+            NitroError::TimedOut(detail) => ("error.timed_out", detail.clone()),
+            NitroError::PartialFailure { failed, total } => {
+                return tf("error.partial_failure", &[("failed", failed), ("total", total)]);
+            }
+            NitroError::Io(e) => ("error.io", e.to_string()),
+            NitroError::Http(e) => ("error.http", e.to_string()),
+            NitroError::Json(e) => ("error.json", e.to_string()),
+            NitroError::Database(e) => ("error.database", e.to_string()),
+            NitroError::Tantivy(e) => ("error.tantivy", e.to_string()),
+            NitroError::General(e) => ("error.general", e.to_string()),
+            NitroError::Other(detail) => ("error.other", detail.clone()),
+        };
+        tf(key, &[("detail", &detail)])
+    }
+
+    /// The process exit code for a command failing with this error: 2 when only some
+    /// of a batch failed, 1 otherwise.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            NitroError::PartialFailure { failed, total } if failed < total => 2,
+            _ => 1,
+        }
+    }
+}
+
 pub type NitroResult<T> = Result<T, NitroError>;
\ No newline at end of file
@@ -0,0 +1,53 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+use crate::core::NitroError;
+
+/// The current user's font directory: `~/Library/Fonts` on macOS,
+/// `$XDG_DATA_HOME/fonts` (usually `~/.local/share/fonts`) elsewhere.
+pub fn user_font_dir() -> Result<PathBuf> {
+    let base = directories::BaseDirs::new()
+        .ok_or_else(|| NitroError::Other("Could not determine home directory".into()))?;
+    if cfg!(target_os = "macos") {
+        Ok(base.home_dir().join("Library/Fonts"))
+    } else {
+        Ok(base.data_dir().join("fonts"))
+    }
+}
+
+/// The font directory shared by all users.
+pub fn system_font_dir() -> PathBuf {
+    if cfg!(target_os = "macos") {
+        PathBuf::from("/Library/Fonts")
+    } else {
+        PathBuf::from("/usr/local/share/fonts")
+    }
+}
+
+/// Copy the font at `source` into `dir`, keeping its file name. An existing font of
+/// the same name is only replaced with `force`, since it may belong to someone else.
+pub fn install_font(source: &Path, dir: &Path, force: bool) -> Result<PathBuf> {
+    let file_name = source.file_name()
+        .ok_or_else(|| NitroError::Other(format!("{} is not a font file", source.display())))?;
+    let dest = dir.join(file_name);
+    if dest.exists() && !force {
+        return Err(NitroError::Other(format!(
+            "{} already exists; use --force to replace it", dest.display()
+        )).into());
+    }
+
+    std::fs::create_dir_all(dir)?;
+    std::fs::copy(source, &dest)?;
+    Ok(dest)
+}
+
+/// Tell fontconfig about added or removed fonts. macOS picks changes up by itself,
+/// and a missing `fc-cache` just means nothing needs telling.
+pub fn refresh_font_cache(dir: &Path) {
+    if cfg!(target_os = "macos") {
+        return;
+    }
+    if let Err(e) = std::process::Command::new("fc-cache").arg("-f").arg(dir).output() {
+        tracing::debug!("fc-cache not run: {}", e);
+    }
+}
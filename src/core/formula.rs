@@ -2,9 +2,14 @@ use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 
+use crate::cache::MemoryCache;
+use crate::core::requirements::Requirement;
+use crate::core::shellrc::ShellRcSnippet;
+use crate::core::ruby::{self, Body, Call, Node, Program, Stmt, StrPart};
+use crate::core::version::Version;
 use crate::core::{NitroError, NitroResult};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Formula {
     pub name: String,
     pub version: String,
@@ -20,21 +25,195 @@ pub struct Formula {
     pub test_script: Option<String>,
     pub caveats: Option<String>,
     pub binary_packages: Vec<BinaryPackage>,
+    #[serde(default)]
+    pub service: Option<ServiceDefinition>,
+    /// Tap the formula was loaded from
+    #[serde(default)]
+    pub tap: Option<String>,
+    /// Bumped when a formula is rebuilt without a version change; part of the keg name
+    #[serde(default)]
+    pub revision: u32,
+    /// Bumped when upstream changes versioning, so a lower-looking version is still newer
+    #[serde(default)]
+    pub version_scheme: u32,
+    /// The `head` spec: building from the development branch. `sources` is the stable spec.
+    #[serde(default)]
+    pub head: Option<Source>,
+    /// The .rb file the formula was parsed from; `None` for API formulae
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+    /// SHA-256 of that file's contents
+    #[serde(default)]
+    pub source_hash: Option<String>,
+    /// Vendored libraries declared with `resource "name" do ... end`
+    #[serde(default)]
+    pub resources: Vec<Resource>,
+    /// `depends_on :xcode` and `depends_on macos:` requirements on the machine
+    #[serde(default)]
+    pub requirements: Vec<Requirement>,
+    /// `shell_rc` lines for the user's shell startup file
+    #[serde(default)]
+    pub shell_rc: Vec<ShellRcSnippet>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl Formula {
+    /// The version including the revision, as used for keg names: `1.2.3_1`.
+    pub fn pkg_version(&self) -> String {
+        pkg_version(&self.version, self.revision)
+    }
+
+    /// The project's repository on GitHub, GitLab, Codeberg or Bitbucket, going by its
+    /// head, source and homepage URLs in that order.
+    pub fn upstream_repository(&self) -> Option<String> {
+        const FORGES: &[&str] = &["github.com", "gitlab.com", "codeberg.org", "bitbucket.org"];
+
+        let urls = self.head.iter().chain(&self.sources).map(|s| s.url.as_str()).chain(self.homepage.as_deref());
+        urls.filter_map(|url| {
+            let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+            let rest = rest.strip_prefix("www.").unwrap_or(rest);
+            let host = FORGES.iter().find(|host| rest.starts_with(&format!("{}/", host)))?;
+            let mut parts = rest[host.len() + 1..].split('/');
+            let (owner, repo) = (parts.next()?, parts.next()?.trim_end_matches(".git"));
+            (!owner.is_empty() && !repo.is_empty()).then(|| format!("https://{}/{}/{}", host, owner, repo))
+        }).next()
+    }
+
+    /// Build a formula from one entry of the formulae.brew.sh JSON API, for API-only
+    /// mode where homebrew/core isn't cloned.
+    pub fn from_api_json(entry: &serde_json::Value) -> Option<Formula> {
+        let text = |value: &serde_json::Value| value.as_str().map(str::to_string);
+        let names = |key: &str| -> Vec<String> {
+            entry[key].as_array()
+                .map(|a| a.iter().filter_map(|v| v.as_str().map(str::to_string)).collect())
+                .unwrap_or_default()
+        };
+        let deps = |key: &str, build_only: bool, optional: bool, test_only: bool| -> Vec<Dependency> {
+            names(key).into_iter()
+                .map(|name| Dependency { name, version: None, build_only, optional, test_only })
+                .collect()
+        };
+
+        let name = text(&entry["name"])?;
+        let stable = &entry["urls"]["stable"];
+        let sources = match (text(&stable["url"]), text(&stable["checksum"])) {
+            (Some(url), _) if url.ends_with(".git") => {
+                vec![Source { url, tag: text(&stable["tag"]), revision: text(&stable["revision"]), ..Default::default() }]
+            }
+            (Some(url), Some(sha256)) => vec![Source { url, sha256, ..Default::default() }],
+            _ => Vec::new(),
+        };
+
+        let mut binary_packages = Vec::new();
+        if let Some(files) = entry["bottle"]["stable"]["files"].as_object() {
+            for (tag, file) in files {
+                let (Some((platform, arch)), Some(url), Some(sha256)) = (bottle_platform(tag), text(&file["url"]), text(&file["sha256"])) else {
+                    continue;
+                };
+                binary_packages.push(BinaryPackage { platform: platform.to_string(), arch: arch.to_string(), url, sha256 });
+            }
+        }
+
+        let mut dependencies = deps("dependencies", false, false, false);
+        dependencies.extend(deps("test_dependencies", false, false, true));
+
+        Some(Formula {
+            version: text(&entry["versions"]["stable"]).unwrap_or_else(|| "unknown".to_string()),
+            description: text(&entry["desc"]),
+            homepage: text(&entry["homepage"]),
+            license: text(&entry["license"]),
+            sources,
+            dependencies,
+            build_dependencies: deps("build_dependencies", true, false, false),
+            optional_dependencies: deps("optional_dependencies", false, true, false),
+            conflicts: names("conflicts_with"),
+            caveats: text(&entry["caveats"]),
+            binary_packages,
+            tap: text(&entry["tap"]).or_else(|| Some(super::tap::CORE_TAP.to_string())),
+            revision: entry["revision"].as_u64().unwrap_or(0) as u32,
+            head: text(&entry["urls"]["head"]["url"]).map(|url| Source { url, ..Default::default() }),
+            version_scheme: entry["version_scheme"].as_u64().unwrap_or(0) as u32,
+            requirements: super::requirements::from_api_json(&entry["requirements"]),
+            service: ServiceDefinition::from_api_json(&entry["service"]),
+            name,
+            ..Default::default()
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Source {
     pub url: String,
     pub sha256: String,
     pub mirror: Option<String>,
+    /// For a git checkout: the tag to check out
+    #[serde(default)]
+    pub tag: Option<String>,
+    /// For a git checkout: the commit the tag should point at
+    #[serde(default)]
+    pub revision: Option<String>,
+    /// Dependencies of building this spec alone, on top of the formula's: those in a
+    /// `head do` block, which only a HEAD build needs
+    #[serde(default)]
+    pub dependencies: Vec<Dependency>,
 }
 
+/// A `resource` block: an extra download, typically a library the formula vendors.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Resource {
+    pub name: String,
+    pub url: String,
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Dependency {
     pub name: String,
     pub version: Option<String>,
     pub build_only: bool,
     pub optional: bool,
+    /// Only needed to run the formula's `test do` block
+    #[serde(default)]
+    pub test_only: bool,
+}
+
+/// What a dependency is needed for, as declared by `depends_on ... => :kind`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DependencyKind {
+    Runtime,
+    Build,
+    Optional,
+    Test,
+}
+
+impl std::fmt::Display for DependencyKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DependencyKind::Runtime => write!(f, "runtime"),
+            DependencyKind::Build => write!(f, "build"),
+            DependencyKind::Optional => write!(f, "optional"),
+            DependencyKind::Test => write!(f, "test"),
+        }
+    }
+}
+
+impl Dependency {
+    pub fn kind(&self) -> DependencyKind {
+        if self.build_only {
+            DependencyKind::Build
+        } else if self.test_only {
+            DependencyKind::Test
+        } else if self.optional {
+            DependencyKind::Optional
+        } else {
+            DependencyKind::Runtime
+        }
+    }
+
+    /// Whether the dependency has to be present for the installed formula to work
+    pub fn is_runtime(&self) -> bool {
+        self.kind() == DependencyKind::Runtime
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -45,10 +224,100 @@ pub struct BinaryPackage {
     pub sha256: String,
 }
 
+/// A formula's `service do` block. Path expressions such as `opt_bin/"redis-server"`
+/// are kept as `#{opt_bin}/redis-server` placeholders for the installation to fill in.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ServiceDefinition {
+    pub command: Option<String>,
+    pub args: Vec<String>,
+    pub env: std::collections::BTreeMap<String, String>,
+    pub keep_alive: bool,
+    /// Restart only after an abnormal exit: `keep_alive crashed: true`
+    #[serde(default)]
+    pub keep_alive_crashed: bool,
+    /// `immediate` (default), `interval` or `cron`
+    pub run_type: Option<String>,
+    /// Seconds between runs, for `run_type :interval`
+    #[serde(default)]
+    pub interval: Option<u32>,
+    /// Five-field crontab schedule (`"0 3 * * *"`), for `run_type :cron`
+    #[serde(default)]
+    pub cron: Option<String>,
+    pub working_dir: Option<String>,
+    pub log_path: Option<String>,
+    pub error_log_path: Option<String>,
+}
+
+impl ServiceDefinition {
+    /// The `service` object of a formulae.brew.sh API entry, whose paths start with
+    /// `$HOMEBREW_PREFIX` where a `service do` block's would use path helpers.
+    pub fn from_api_json(value: &serde_json::Value) -> Option<Self> {
+        let object = value.as_object()?;
+        let text = |value: &serde_json::Value| value.as_str().map(|s| s.replace("$HOMEBREW_PREFIX", "#{HOMEBREW_PREFIX}"));
+
+        // `run` is a command, an argument list, or either of those per OS
+        let os = if cfg!(target_os = "macos") { "macos" } else { "linux" };
+        let run = match &object.get("run") {
+            Some(serde_json::Value::Object(per_os)) => per_os.get(os).cloned().unwrap_or_default(),
+            Some(run) => (*run).clone(),
+            None => serde_json::Value::Null,
+        };
+        let mut program: Vec<String> = match &run {
+            serde_json::Value::Array(parts) => parts.iter().filter_map(text).collect(),
+            run => text(run).into_iter().collect(),
+        };
+        let command = (!program.is_empty()).then(|| program.remove(0));
+
+        let env = object.get("environment_variables").and_then(|e| e.as_object())
+            .map(|vars| vars.iter().filter_map(|(key, value)| Some((key.clone(), text(value)?))).collect())
+            .unwrap_or_default();
+        // `{"always": true}`, or conditions such as `{"crashed": true}` that still keep it alive
+        let (keep_alive, keep_alive_crashed) = match object.get("keep_alive") {
+            Some(serde_json::Value::Object(conditions)) => {
+                let set = |key: &str| conditions.get(key).and_then(|v| v.as_bool()) == Some(true);
+                (conditions.values().any(|v| v.as_bool() != Some(false)), set("crashed") && !set("always"))
+            }
+            Some(value) => (value.as_bool().unwrap_or(false), false),
+            None => (false, false),
+        };
+
+        Some(Self {
+            command,
+            args: program,
+            env,
+            keep_alive,
+            keep_alive_crashed,
+            run_type: object.get("run_type").and_then(text),
+            interval: object.get("interval").and_then(|i| i.as_u64()).map(|i| i as u32),
+            cron: object.get("cron").and_then(text),
+            working_dir: object.get("working_dir").and_then(text),
+            log_path: object.get("log_path").and_then(text),
+            error_log_path: object.get("error_log_path").and_then(text),
+        })
+    }
+}
+
+/// On-disk cache record: the parsed formula plus the hash of the .rb it came from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedFormula {
+    source_path: PathBuf,
+    source_hash: String,
+    formula: Formula,
+}
+
+/// Number of parsed formulae kept in memory during a single invocation
+const MEMORY_CACHE_CAPACITY: usize = 1024;
+
+/// Homebrew's JSON index of every core formula
+pub const FORMULA_API_URL: &str = "https://formulae.brew.sh/api/formula.json";
+
 pub struct FormulaManager {
     cache_dir: PathBuf,
+    memory_cache: MemoryCache<Formula>,
     tap_manager: super::tap::TapManager,
     parser: FormulaParser,
+    /// Formulae missing from the taps are looked up in the JSON API
+    api_only: bool,
 }
 
 impl FormulaManager {
@@ -57,49 +326,228 @@ impl FormulaManager {
             .ok_or_else(|| NitroError::Other("Could not determine config directory".into()))?;
         
         let cache_dir = config_dir.cache_dir().join("formulae");
-        std::fs::create_dir_all(&cache_dir)?;
+        if !super::readonly::is_enabled() {
+            std::fs::create_dir_all(&cache_dir)?;
+        }
 
         let tap_manager = super::tap::TapManager::new().await?;
-        let parser = FormulaParser::new();
+        let mut manager = Self::with_tap_manager(cache_dir, tap_manager);
+        manager.api_only = super::config::Config::load().map(|c| c.taps.api_only).unwrap_or(false);
+        Ok(manager)
+    }
 
-        Ok(Self {
+    /// A formula manager looking formulae up in `tap_manager`'s taps only, caching them
+    /// under `cache_dir`.
+    pub fn with_tap_manager(cache_dir: PathBuf, tap_manager: super::tap::TapManager) -> Self {
+        Self {
             cache_dir,
+            memory_cache: MemoryCache::new(MEMORY_CACHE_CAPACITY),
             tap_manager,
-            parser,
-        })
+            parser: FormulaParser::new(),
+            api_only: false,
+        }
     }
 
     pub async fn get_formula(&self, name: &str) -> NitroResult<Formula> {
+        // Formulae already seen in this process skip disk I/O entirely
+        if let Some(formula) = self.memory_cache.get(name) {
+            return Ok(formula);
+        }
+
+        // A pinned formula always comes from its pinned file
+        if let Some(formula) = self.pinned_formula(name).await? {
+            self.memory_cache.insert(name, formula.clone());
+            return Ok(formula);
+        }
+
         // Check cache first
         if let Ok(formula) = self.load_from_cache(name) {
             eprintln!("DEBUG: Loaded formula {} from cache with {} sources", formula.name, formula.sources.len());
+            self.memory_cache.insert(name, formula.clone());
             return Ok(formula);
         }
         eprintln!("DEBUG: Formula {} not in cache, will parse", name);
 
         // Find formula in taps
-        let formula_path = self.tap_manager.find_formula(name).await?;
+        let formula_path = match self.tap_manager.find_formula(name).await {
+            Ok(path) => path,
+            Err(NitroError::PackageNotFound(_)) if self.api_only => return self.load_from_api(name).await,
+            Err(e) => return Err(e),
+        };
         eprintln!("DEBUG: Found formula at: {}", formula_path.display());
-        let formula = self.parser.parse_file(&formula_path).await?;
+        let (formula, hash) = self.parse_formula_file(&formula_path).await?;
         eprintln!("DEBUG: Parsed formula {} with {} sources", formula.name, formula.sources.len());
         
-        // Cache the parsed formula
-        self.save_to_cache(&formula)?;
+        // Cache the parsed formula, keyed by the content it was parsed from
+        self.save_to_cache(&formula, &formula_path, &hash)?;
+        self.memory_cache.insert(name, formula.clone());
         
         Ok(formula)
     }
 
-    pub async fn update_formulae(&self) -> Result<()> {
-        // Clear cache when updating formulae
-        if self.cache_dir.exists() {
-            std::fs::remove_dir_all(&self.cache_dir)?;
-            std::fs::create_dir_all(&self.cache_dir)?;
+    /// The pinned revision of `name`, if it is pinned. Fails if the pinned file no
+    /// longer matches the pinned hash.
+    pub async fn pinned_formula(&self, name: &str) -> NitroResult<Option<Formula>> {
+        let Some((pin, path)) = self.tap_manager.formula_pin(name)? else {
+            return Ok(None);
+        };
+        let (mut formula, hash) = self.parse_formula_file(&path).await?;
+        if hash != pin.hash {
+            return Err(NitroError::Other(format!(
+                "Pinned formula file {} doesn't match its pinned hash; re-pin {} with `nitro tap pin {} --bump`",
+                path.display(), name, name
+            )));
         }
-        
+        formula.tap = pin.tap;
+        Ok(Some(formula))
+    }
+
+    pub fn tap_manager(&self) -> &super::tap::TapManager {
+        &self.tap_manager
+    }
+
+    /// Load the formula from a specific .rb file, e.g. the one a package was installed
+    /// from, rather than whichever tap a name lookup would find first.
+    pub async fn get_formula_at(&self, path: &Path) -> NitroResult<Formula> {
+        Ok(self.parse_formula_file(path).await?.0)
+    }
+
+    /// Load formula `name` from `tap` only.
+    pub async fn get_formula_in_tap(&self, name: &str, tap: &str) -> NitroResult<Formula> {
+        let path = self.tap_manager.find_formula_in_tap(tap, name).await?;
+        self.get_formula_at(&path).await
+    }
+
+    /// Parse a formula file, filling in its tap and path. Also returns the content hash.
+    async fn parse_formula_file(&self, path: &Path) -> NitroResult<(Formula, String)> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| NitroError::FormulaParse(format!("Failed to read formula file: {}", e)))?;
+        let mut formula = self.parser.parse_content(&content)?;
+        formula.tap = self.tap_manager.tap_for_path(path).await;
+        formula.path = Some(path.to_path_buf());
+        let hash = content_hash(content.as_bytes());
+        formula.source_hash = Some(hash.clone());
+
+        // The API knows versions the URL heuristics can't work out
+        if Version::new(formula.version.as_str()).is_unknown() && formula.tap.as_deref() == Some("homebrew/core") {
+            if let Ok(Some(api)) = self.api_formula(&formula.name, false).await {
+                formula.version = api.version;
+            }
+        }
+        Ok((formula, hash))
+    }
+
+    pub async fn update_formulae(&self) -> Result<Vec<super::tap::TapUpdate>> {
         // Update all taps
-        self.tap_manager.update_all_taps().await?;
+        let updates = self.tap_manager.update_all_taps(false).await?;
         
-        Ok(())
+        // Drop only the cache entries whose formula files changed
+        let removed = self.invalidate_stale_cache()?;
+        if removed > 0 {
+            println!("Invalidated {} cached formula(e)", removed);
+        }
+        
+        Ok(updates)
+    }
+
+    /// Refresh the cached formula API index. Returns false when the server reported
+    /// it unchanged since the last fetch, and fails when it couldn't be reached, even
+    /// though the cached copy remains usable.
+    pub async fn refresh_api_index(&self) -> Result<bool> {
+        use crate::download::{metadata::MetadataFetcher, Downloader};
+
+        let fetcher = MetadataFetcher::new(Downloader::shared()?)?;
+        let fetched = fetcher.fetch(FORMULA_API_URL).await?;
+        if let Some(e) = fetched.refresh_error {
+            return Err(NitroError::DownloadFailed(format!("{}: {}", FORMULA_API_URL, e)).into());
+        }
+        Ok(!fetched.from_cache)
+    }
+
+    /// Look a formula up in the cached JSON API index (fetching it first if needed),
+    /// by name, old name or alias.
+    async fn load_from_api(&self, name: &str) -> NitroResult<Formula> {
+        let formula = self.api_formula(name, !super::readonly::is_enabled()).await?
+            .ok_or_else(|| NitroError::PackageNotFound(name.to_string()))?;
+        self.memory_cache.insert(name, formula.clone());
+        Ok(formula)
+    }
+
+    /// The formula's entry in the JSON API index, if the index is cached (or `fetch` is
+    /// set and it can be downloaded) and lists it.
+    async fn api_formula(&self, name: &str, fetch: bool) -> NitroResult<Option<Formula>> {
+        use crate::download::{metadata::MetadataFetcher, Downloader};
+
+        let api_error = |e: anyhow::Error| NitroError::Other(format!("Formula API unavailable: {}", e));
+        let fetcher = MetadataFetcher::new(Downloader::shared().map_err(api_error)?).map_err(api_error)?;
+        let path = match fetcher.cached(FORMULA_API_URL) {
+            Some(path) => path,
+            None if !fetch => return Ok(None),
+            None => fetcher.fetch(FORMULA_API_URL).await.map_err(api_error)?.path,
+        };
+
+        let data = std::fs::read(&path)?;
+        let entries: Vec<serde_json::Value> = serde_json::from_slice(&data)?;
+        let matches = |entry: &&serde_json::Value| {
+            entry["name"].as_str() == Some(name)
+                || ["oldnames", "aliases"].iter().any(|key| {
+                    entry[*key].as_array().is_some_and(|a| a.iter().any(|v| v.as_str() == Some(name)))
+                })
+        };
+
+        Ok(entries.iter().find(matches).and_then(Formula::from_api_json))
+    }
+
+    /// Remove cached formulae whose source file changed or disappeared.
+    /// Returns the number of entries removed.
+    pub fn invalidate_stale_cache(&self) -> Result<usize> {
+        self.memory_cache.clear();
+        let stale = self.stale_cache_entries()?;
+        for path in &stale {
+            std::fs::remove_file(path)?;
+        }
+        Ok(stale.len())
+    }
+
+    /// The cached formulae `invalidate_stale_cache` would remove: those whose formula
+    /// file has changed or that can't be read.
+    pub fn stale_cache_entries(&self) -> Result<Vec<PathBuf>> {
+        let mut stale = Vec::new();
+        
+        for entry in std::fs::read_dir(&self.cache_dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|s| s.to_str()) != Some("json") {
+                continue;
+            }
+            
+            let fresh = std::fs::read(&path).ok()
+                .and_then(|data| serde_json::from_slice::<CachedFormula>(&data).ok())
+                .map(|cached| Self::is_fresh(&cached))
+                .unwrap_or(false);
+            
+            if !fresh {
+                stale.push(path);
+            }
+        }
+        
+        stale.sort();
+        Ok(stale)
+    }
+
+    /// Remove every cached formula. Returns the number of entries removed.
+    pub fn clear_cache(&self) -> Result<usize> {
+        self.memory_cache.clear();
+        let mut removed = 0;
+        
+        for entry in std::fs::read_dir(&self.cache_dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|s| s.to_str()) == Some("json") {
+                std::fs::remove_file(&path)?;
+                removed += 1;
+            }
+        }
+        
+        Ok(removed)
     }
 
     pub async fn rebuild_search_index(&self) -> Result<()> {
@@ -115,22 +563,79 @@ impl FormulaManager {
         let cache_path = self.cache_dir.join(format!("{}.json", name));
         if cache_path.exists() {
             let data = std::fs::read_to_string(&cache_path)?;
-            let formula: Formula = serde_json::from_str(&data)?;
-            Ok(formula)
+            let cached: CachedFormula = serde_json::from_str(&data)?;
+            if Self::is_fresh(&cached) {
+                Ok(cached.formula)
+            } else {
+                tracing::debug!("Cached formula {} is stale", name);
+                Err(NitroError::CacheError(format!("Cached formula {} is stale", name)))
+            }
         } else {
             Err(NitroError::PackageNotFound(name.to_string()))
         }
     }
 
-    fn save_to_cache(&self, formula: &Formula) -> Result<()> {
+    fn save_to_cache(&self, formula: &Formula, source_path: &Path, source_hash: &str) -> Result<()> {
+        if super::readonly::is_enabled() {
+            return Ok(());
+        }
         eprintln!("DEBUG: Saving formula {} to cache with {} sources", formula.name, formula.sources.len());
         let cache_path = self.cache_dir.join(format!("{}.json", formula.name));
-        let data = serde_json::to_string_pretty(formula)?;
+        let cached = CachedFormula {
+            source_path: source_path.to_path_buf(),
+            source_hash: source_hash.to_string(),
+            formula: formula.clone(),
+        };
+        let data = serde_json::to_string_pretty(&cached)?;
         std::fs::write(cache_path, data)?;
         Ok(())
     }
+
+    fn is_fresh(cached: &CachedFormula) -> bool {
+        std::fs::read(&cached.source_path)
+            .map(|content| content_hash(&content) == cached.source_hash)
+            .unwrap_or(false)
+    }
+}
+
+/// `version` with `_revision` appended when the revision is non-zero.
+pub fn pkg_version(version: &str, revision: u32) -> String {
+    if revision == 0 {
+        version.to_string()
+    } else {
+        format!("{}_{}", version, revision)
+    }
+}
+
+/// Map a Homebrew bottle tag (`arm64_sonoma`, `x86_64_linux`) to our platform/arch.
+pub fn bottle_platform(tag: &str) -> Option<(&'static str, &'static str)> {
+    match tag {
+        "arm64_sequoia" | "arm64_sonoma" | "arm64_ventura" | "arm64_monterey" => Some(("darwin", "aarch64")),
+        "sequoia" | "sonoma" | "ventura" | "monterey" | "big_sur" => Some(("darwin", "x86_64")),
+        "x86_64_linux" => Some(("linux", "x86_64")),
+        "aarch64_linux" => Some(("linux", "aarch64")),
+        _ => None,
+    }
 }
 
+/// Normalize a platform given on the command line, either a bottle tag (`arm64_sonoma`)
+/// or `platform/arch` (`linux/x86_64`), to the `platform/arch` form used in the index.
+pub fn platform_tag(spec: &str) -> Option<String> {
+    match spec.split_once('/') {
+        Some((platform, arch)) if !platform.is_empty() && !arch.is_empty() => Some(spec.to_string()),
+        Some(_) => None,
+        None => bottle_platform(spec).map(|(platform, arch)| format!("{}/{}", platform, arch)),
+    }
+}
+
+/// SHA-256 of a formula file's contents, used to detect edited or updated formulae.
+pub fn content_hash(content: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+
+    hex::encode(Sha256::digest(content))
+}
+
+#[derive(Default)]
 pub struct FormulaParser {
     // We'll implement a basic Ruby formula parser
 }
@@ -149,321 +654,459 @@ impl FormulaParser {
         self.parse_content(&content)
     }
 
+    /// Read a formula from its Ruby source. Stanzas are taken from the class body and
+    /// from the `on_macos`/`on_linux`/`on_arm`/`on_intel` blocks and `if OS.mac?`
+    /// branches that apply to this machine; `url`, `sha256`, `mirror` and `version`
+    /// come from the `stable do` block when there is one.
     pub fn parse_content(&self, content: &str) -> NitroResult<Formula> {
-        // This is a simplified parser - in reality, we'd need a proper Ruby parser
-        // For now, we'll use regex to extract basic information
-        
-        let name = self.extract_class_name(content)?;
-        eprintln!("DEBUG: Parsing formula: {}", name);
-        let desc = self.extract_desc(content);
-        let homepage = self.extract_homepage(content);
-        let url = self.extract_url(content).ok();
-        eprintln!("DEBUG: Extracted URL: {:?}", url);
-        let sha256 = self.extract_sha256(content).ok();
-        eprintln!("DEBUG: Extracted SHA256: {:?}", sha256);
-        let version = if let Some(ref u) = url {
-            self.extract_version_from_url(u)
-        } else {
-            self.extract_version_from_content(content).unwrap_or_else(|| "unknown".to_string())
+        let program = Program::parse(content);
+        let (class_name, body) = formula_class(&program.stmts)
+            .ok_or_else(|| NitroError::FormulaParse("Could not find formula class name".into()))?;
+        let name = formula_name(class_name);
+        let stanzas = applicable(&body.stmts);
+        let stable_stanzas = match stanza(&stanzas, "stable").filter(|c| c.block.is_some()) {
+            Some(stable) => applicable(stable.block_stmts()),
+            None => stanzas.clone(),
         };
-        let (dependencies, build_dependencies) = self.extract_dependencies(content)?;
-        
-        let binary_packages = self.extract_bottles(content, &name, &version)?;
-        
+        let stable = |name: &str| stanza(&stable_stanzas, name);
+
+        let version_stanza = first_arg(stable("version")).and_then(Node::as_str);
+        let mut vars = Vars { name: &name, version: version_stanza.as_deref() };
+        let url_call = stable("url");
+        let url = first_arg(url_call).and_then(|url| vars.text(url));
+        let tag = url_call.and_then(|c| c.option("tag")).and_then(|tag| vars.text(tag));
+        let revision = url_call.and_then(|c| c.option("revision")).and_then(Node::as_str);
+        // An explicit `version` stanza beats anything guessed from the URL or git tag
+        let version = version_stanza.clone()
+            .or_else(|| url.as_deref().and_then(Version::detect_from_url).map(|v| v.to_string()))
+            .or_else(|| tag.as_deref().and_then(tag_version))
+            .or_else(|| revision.clone())
+            .unwrap_or_else(|| "unknown".to_string());
+        vars.version = Some(&version);
+        let sha256 = first_arg(stable("sha256")).and_then(Node::as_str);
+        let mirror = first_arg(stable("mirror")).and_then(|m| vars.text(m));
+
+        let sources = match (url, sha256) {
+            // Git checkouts are pinned by tag and revision, not a checksum
+            (Some(url), _) if url.ends_with(".git") => vec![Source { url, tag, revision, ..Default::default() }],
+            (Some(url), Some(sha256)) => vec![Source { url, sha256, mirror, ..Default::default() }],
+            _ => vec![],
+        };
+
+        // The head block's dependencies stay with it, out of the stable build's
+        let head = stanza(&stanzas, "head").and_then(|head| {
+            let head_stanzas = applicable(head.block_stmts());
+            let url = match &head.block {
+                Some(_) => first_arg(stanza(&head_stanzas, "url")),
+                None => head.args.first(),
+            };
+            let url = url.and_then(|u| vars.text(u))?;
+            Some(Source { url, dependencies: dependencies(head_stanzas.iter().copied()).0, ..Default::default() })
+        });
+        let (dependencies, build_dependencies) = dependencies(stanzas.iter().chain(&stable_stanzas).copied());
+        let integer = |name: &str| match first_arg(stanza(&stanzas, name)) {
+            Some(Node::Int(n)) => u32::try_from(*n).unwrap_or(0),
+            _ => 0,
+        };
+        let def = |def_name: &str| stanzas.iter().find_map(|s| match &s.node {
+            Node::Def { name, body } if name == def_name => Some(body),
+            _ => None,
+        });
+        let calls = |stanza: &'static str| stanzas.iter()
+            .filter_map(|s| s.node.call())
+            .filter(move |c| c.receiver.is_none() && c.name == stanza);
+        let conflicts = calls("conflicts_with")
+            .flat_map(|c| c.args.iter().filter_map(Node::as_str))
+            .collect();
+
         Ok(Formula {
-            name,
-            version,
-            description: desc,
-            homepage,
-            license: None, // TODO: Extract license
-            sources: if let Some(url) = url {
-                // For git URLs, we don't need SHA256
-                if url.ends_with(".git") {
-                    vec![Source {
-                        url,
-                        sha256: String::new(), // Empty SHA256 for git URLs
-                        mirror: None,
-                    }]
-                } else if let Some(sha256) = sha256 {
-                    vec![Source {
-                        url,
-                        sha256,
-                        mirror: None,
-                    }]
-                } else {
-                    vec![] // No valid source
-                }
-            } else {
-                vec![] // No sources for formulas that build from git or other methods
-            },
+            description: first_arg(stanza(&stanzas, "desc")).and_then(|d| vars.text(d)),
+            homepage: first_arg(stanza(&stanzas, "homepage")).and_then(|h| vars.text(h)),
+            license: first_arg(stanza(&stanzas, "license")).and_then(license),
+            sources,
             dependencies,
             build_dependencies,
             optional_dependencies: vec![],
-            conflicts: vec![],
-            install_script: self.extract_install_block(content),
-            test_script: self.extract_test_block(content),
-            caveats: self.extract_caveats(content),
-            binary_packages,
+            conflicts,
+            install_script: def("install").map(|body| program.script(body)),
+            test_script: stanza(&stanzas, "test").filter(|c| c.block.is_some())
+                .and_then(|c| c.block.as_ref())
+                .map(|block| program.script(&block.body)),
+            caveats: def("caveats").and_then(|body| body.stmts.last())
+                .and_then(|last| match &last.node {
+                    Node::Str(parts) => Some(ruby::parts_text(parts).trim().to_string()),
+                    _ => None,
+                }),
+            binary_packages: stanza(&stanzas, "bottle").map(|bottle| bottles(bottle.block_stmts(), &name)).unwrap_or_default(),
+            service: stanza(&stanzas, "service").filter(|c| c.block.is_some()).map(|c| service(c.block_stmts())),
+            tap: None,
+            revision: integer("revision"),
+            version_scheme: integer("version_scheme"),
+            head,
+            path: None,
+            source_hash: None,
+            resources: resources(&stanzas, &vars),
+            requirements: calls("depends_on").filter_map(super::requirements::from_depends_on).collect(),
+            shell_rc: calls("shell_rc").filter_map(super::shellrc::from_call).collect(),
+            name,
+            version,
         })
     }
+}
 
-    fn extract_class_name(&self, content: &str) -> NitroResult<String> {
-        let re = regex::Regex::new(r"class\s+(\w+)\s*<\s*Formula").unwrap();
-        if let Some(cap) = re.captures(content) {
-            if let Some(name_match) = cap.get(1) {
-                // Convert class name format to package name format
-                // e.g., PythonAT312 -> python@3.12
-                let class_name = name_match.as_str();
-                let name = if let Some(at_pos) = class_name.find("AT") {
-                    // Handle versioned formulae like PythonAT312
-                    let (base, version_part) = class_name.split_at(at_pos);
-                    let version = &version_part[2..]; // Skip "AT"
-                    
-                    // Insert dots in version number (312 -> 3.12)
-                    let formatted_version = if version.len() >= 2 {
-                        format!("{}.{}", &version[0..1], &version[1..])
-                    } else {
-                        version.to_string()
-                    };
-                    
-                    format!("{}@{}", base.to_lowercase(), formatted_version)
-                } else {
-                    class_name.to_lowercase()
-                };
-                Ok(name)
-            } else {
-                Err(NitroError::FormulaParse("Could not extract formula class name".into()))
-            }
-        } else {
-            Err(NitroError::FormulaParse("Could not find formula class name".into()))
+/// The first `name ...` stanza among `stanzas`.
+fn stanza<'a>(stanzas: &[&'a Stmt], name: &str) -> Option<&'a Call> {
+    stanzas.iter().filter_map(|s| s.node.call()).find(|c| c.receiver.is_none() && c.name == name)
+}
+
+fn first_arg(call: Option<&Call>) -> Option<&Node> {
+    call.and_then(|c| c.args.first())
+}
+
+/// The class deriving from `Formula`, at the top of the file or inside a module.
+fn formula_class(stmts: &[Stmt]) -> Option<(&str, &Body)> {
+    stmts.iter().find_map(|stmt| match &stmt.node {
+        Node::Class { name, superclass: Some(superclass), body } if **superclass == Node::Const("Formula".into()) => {
+            Some((name.rsplit("::").next().unwrap_or(name), body))
         }
+        Node::Class { body, .. } => formula_class(&body.stmts),
+        _ => None,
+    })
+}
+
+/// The formula name for a class name: `PythonAT312` is `python@3.12`.
+fn formula_name(class_name: &str) -> String {
+    if let Some(at_pos) = class_name.find("AT") {
+        let (base, version_part) = class_name.split_at(at_pos);
+        let version = &version_part[2..];
+
+        // Insert dots in version number (312 -> 3.12)
+        let formatted_version = if version.len() >= 2 {
+            format!("{}.{}", &version[0..1], &version[1..])
+        } else {
+            version.to_string()
+        };
+
+        format!("{}@{}", base.to_lowercase(), formatted_version)
+    } else {
+        class_name.to_lowercase()
     }
+}
+
+/// What `#{...}` in a formula's strings can be resolved to while parsing.
+struct Vars<'a> {
+    name: &'a str,
+    version: Option<&'a str>,
+}
 
-    fn extract_desc(&self, content: &str) -> Option<String> {
-        let re = regex::Regex::new(r#"desc\s+"([^"]+)""#).unwrap();
-        re.captures(content).and_then(|cap| cap.get(1).map(|m| m.as_str().to_string()))
+impl Vars<'_> {
+    /// A string or symbol as text, with `#{version}`, `#{version.major_minor}` and the
+    /// like filled in. Other interpolations stay as written, to be filled in at install
+    /// time (`#{prefix}`).
+    fn text(&self, node: &Node) -> Option<String> {
+        let parts = match node {
+            Node::Str(parts) => parts,
+            Node::Symbol(symbol) => return Some(symbol.clone()),
+            _ => return None,
+        };
+        let resolved = parts.iter()
+            .map(|part| match part {
+                StrPart::Text(text) => text.clone(),
+                StrPart::Code(code) => self.resolve(code).unwrap_or_else(|| format!("#{{{}}}", code)),
+            })
+            .collect();
+        Some(resolved)
     }
 
-    fn extract_homepage(&self, content: &str) -> Option<String> {
-        let re = regex::Regex::new(r#"homepage\s+"([^"]+)""#).unwrap();
-        re.captures(content).and_then(|cap| cap.get(1).map(|m| m.as_str().to_string()))
+    fn resolve(&self, code: &str) -> Option<String> {
+        if code == "name" {
+            return Some(self.name.to_string());
+        }
+        let version = self.version?;
+        let components: Vec<&str> = version.split('.').collect();
+        let first = |n: usize| components[..n.min(components.len())].join(".");
+        match code.strip_prefix("version")? {
+            "" | ".to_s" => Some(version.to_string()),
+            ".major" => Some(first(1)),
+            ".major_minor" => Some(first(2)),
+            ".major_minor_patch" => Some(first(3)),
+            ".minor" => components.get(1).map(|s| s.to_string()),
+            ".patch" => components.get(2).map(|s| s.to_string()),
+            _ => None,
+        }
     }
+}
 
-    fn extract_url(&self, content: &str) -> NitroResult<String> {
-        // Try standard URL format (with optional trailing comma for multiline entries)
-        let re = regex::Regex::new(r#"url\s+"([^"]+)",?"#).unwrap();
-        if let Some(cap) = re.captures(content) {
-            if let Some(url_match) = cap.get(1) {
-                let url = url_match.as_str();
-                eprintln!("DEBUG: Extracted URL: {}", url);
-                // Check if it's a git URL with additional parameters
-                if url.ends_with(".git") {
-                    // For git URLs, we need to extract tag/revision info
-                    if let Some(tag_match) = regex::Regex::new(r#"tag:\s*"([^"]+)""#).unwrap().captures(content) {
-                        if let Some(tag) = tag_match.get(1) {
-                            eprintln!("DEBUG: Found git URL with tag: {}", tag.as_str());
-                        }
+/// The version in a git tag: `v1.2.3` and `release-1.2.3` are `1.2.3`.
+fn tag_version(tag: &str) -> Option<String> {
+    let version = tag.trim_start_matches(|c: char| !c.is_ascii_digit());
+    (!version.is_empty()).then(|| version.to_string())
+}
+
+/// The statements among `stmts` that apply to this machine: those of `on_macos`,
+/// `on_linux`, `on_arm`, `on_intel` and `on_<macOS release>` blocks and of `if`
+/// branches on `OS.mac?` and the like are taken in, the rest of those left out.
+fn applicable(stmts: &[Stmt]) -> Vec<&Stmt> {
+    let mut kept = Vec::new();
+    for stmt in stmts {
+        match &stmt.node {
+            Node::Call(call) if call.receiver.is_none() && call.block.is_some() && call.name.starts_with("on_") => {
+                if let Some(applies) = on_block_applies(call) {
+                    if applies {
+                        kept.extend(applicable(call.block_stmts()));
                     }
+                    continue;
                 }
-                return Ok(url.to_string());
+                kept.push(stmt);
             }
+            Node::If { negated, cond, then, otherwise } => match condition(cond) {
+                Some(holds) => kept.extend(applicable(if holds != *negated { then } else { otherwise })),
+                None => kept.push(stmt),
+            },
+            _ => kept.push(stmt),
         }
-        
-        Err(NitroError::FormulaParse("Could not find download URL".into()))
     }
+    kept
+}
 
-    fn extract_sha256(&self, content: &str) -> NitroResult<String> {
-        // Try multiple SHA256 patterns
-        let patterns = [
-            r#"sha256\s+"([a-fA-F0-9]{64})""#,  // Standard format
-            r#"sha256\s+["']([a-fA-F0-9]{64})["']"#,  // With single quotes
-            r#"sha256\s+:?\s*["']([a-fA-F0-9]{64})["']"#,  // With symbol notation
-        ];
-        
-        for pattern in &patterns {
-            let re = regex::Regex::new(pattern).unwrap();
-            if let Some(cap) = re.captures(content) {
-                if let Some(sha_match) = cap.get(1) {
-                    return Ok(sha_match.as_str().to_string());
-                }
+/// Whether an `on_*` block is for this machine; `None` for blocks that aren't about
+/// the platform.
+fn on_block_applies(call: &Call) -> Option<bool> {
+    const MACOS_RELEASES: &[&str] = &["catalina", "big_sur", "monterey", "ventura", "sonoma", "sequoia", "tahoe"];
+
+    let macos = cfg!(target_os = "macos");
+    Some(match &call.name["on_".len()..] {
+        "macos" => macos,
+        "linux" => !macos,
+        "arm" => cfg!(target_arch = "aarch64"),
+        "intel" => cfg!(target_arch = "x86_64"),
+        "system" => {
+            let linux = call.args.iter().any(|a| *a == Node::Symbol("linux".into()));
+            (linux && !macos) || (macos && call.option("macos").is_some())
+        }
+        release if MACOS_RELEASES.contains(&release) => macos,
+        _ => return None,
+    })
+}
+
+/// The value of an `if` condition on the platform (`OS.mac?`, `Hardware::CPU.arm?`,
+/// combined with `&&`, `||` and `!`); `None` for anything else.
+fn condition(node: &Node) -> Option<bool> {
+    match node {
+        Node::Call(Call { receiver: Some(receiver), name, args, block: None }) if args.is_empty() => {
+            match (&**receiver, name.as_str()) {
+                (Node::Const(os), "mac?") if os == "OS" => Some(cfg!(target_os = "macos")),
+                (Node::Const(os), "linux?") if os == "OS" => Some(cfg!(target_os = "linux")),
+                (Node::Const(cpu), "arm?") if cpu == "Hardware::CPU" => Some(cfg!(target_arch = "aarch64")),
+                (Node::Const(cpu), "intel?") if cpu == "Hardware::CPU" => Some(cfg!(target_arch = "x86_64")),
+                _ => None,
             }
         }
-        
-        eprintln!("DEBUG: Could not find SHA256 in formula content");
-        Err(NitroError::FormulaParse("Could not find SHA256 checksum".into()))
+        Node::Unary("!", inner) => condition(inner).map(|holds| !holds),
+        Node::Binary("&&", a, b) => Some(condition(a)? && condition(b)?),
+        Node::Binary("||", a, b) => Some(condition(a)? || condition(b)?),
+        _ => None,
     }
+}
 
-    fn extract_version_from_url(&self, url: &str) -> String {
-        // Try multiple patterns to extract version
-        let patterns = [
-            r"/tags/v?(\d+\.\d+(?:\.\d+)*)",  // GitHub tags
-            r"download/v?(\d+\.\d+(?:\.\d+)*)", // GitHub releases
-            r"[-_/]v?(\d+\.\d+(?:\.\d+)*)",  // Common patterns like -1.2.3 or /v1.2.3
-        ];
-        
-        for pattern in &patterns {
-            let re = regex::Regex::new(pattern).unwrap();
-            if let Some(cap) = re.captures(url) {
-                if let Some(ver_match) = cap.get(1) {
-                    return ver_match.as_str().to_string();
-                }
-            }
+/// `license "MIT"`, `license :public_domain`, `license any_of: ["MIT", "Apache-2.0"]`
+/// or `all_of:` as an SPDX-style expression.
+fn license(node: &Node) -> Option<String> {
+    match node {
+        Node::Str(_) | Node::Symbol(_) => node.as_str(),
+        Node::Hash(pairs) => {
+            let (key, value) = pairs.first()?;
+            let joiner = match key.as_str()?.as_str() {
+                "any_of" => " OR ",
+                "all_of" => " AND ",
+                name => return value.call().is_none().then(|| name.to_string()),
+            };
+            let Node::Array(items) = value else {
+                return None;
+            };
+            let parts: Vec<String> = items.iter().filter_map(license).collect();
+            (!parts.is_empty()).then(|| parts.join(joiner))
         }
-        
-        "unknown".to_string()
+        _ => None,
     }
+}
 
-    fn extract_version_from_content(&self, content: &str) -> Option<String> {
-        // Try to extract version from version directive
-        let re = regex::Regex::new(r#"version\s+"([^"]+)""#).unwrap();
-        if let Some(cap) = re.captures(content) {
-            if let Some(ver_match) = cap.get(1) {
-                return Some(ver_match.as_str().to_string());
-            }
+/// The `depends_on "name"` stanzas, tagged `=> :build`, `=> [:build, :test]` and so on,
+/// and the build-only ones among them. Requirements (`depends_on :xcode`,
+/// `depends_on macos: :ventura`) aren't formulae and are left out.
+fn dependencies<'a>(stanzas: impl Iterator<Item = &'a Stmt>) -> (Vec<Dependency>, Vec<Dependency>) {
+    let mut deps = Vec::new();
+    let mut build_deps = Vec::new();
+    for call in stanzas.filter_map(|s| s.node.call()).filter(|c| c.receiver.is_none() && c.name == "depends_on") {
+        let (name, tags) = match call.args.first() {
+            Some(Node::Str(_)) => (call.args[0].as_str(), Vec::new()),
+            Some(Node::Hash(pairs)) => match pairs.first() {
+                Some((name @ Node::Str(_), tags)) => (name.as_str(), match tags {
+                    Node::Array(tags) => tags.iter().filter_map(Node::as_str).collect(),
+                    tag => tag.as_str().into_iter().collect(),
+                }),
+                _ => continue,
+            },
+            _ => continue,
+        };
+        let Some(name) = name else {
+            continue;
+        };
+        if deps.iter().any(|d: &Dependency| d.name == name) {
+            continue;
         }
-        
-        // Try to extract from revision or tag
-        let re = regex::Regex::new(r#"revision\s+"([^"]+)""#).unwrap();
-        if let Some(cap) = re.captures(content) {
-            if let Some(ver_match) = cap.get(1) {
-                return Some(ver_match.as_str().to_string());
-            }
+        let has_tag = |tag: &str| tags.iter().any(|t| t == tag);
+        let build_only = has_tag("build");
+        let dep = Dependency {
+            name,
+            version: None,
+            build_only,
+            optional: has_tag("optional"),
+            test_only: has_tag("test") && !build_only,
+        };
+
+        // Build-only deps stay in the main list (flagged) so callers see the full set
+        if build_only {
+            build_deps.push(dep.clone());
         }
-        
-        None
+        deps.push(dep);
     }
+    (deps, build_deps)
+}
 
-    fn extract_dependencies(&self, content: &str) -> NitroResult<(Vec<Dependency>, Vec<Dependency>)> {
-        let mut deps = Vec::new();
-        let mut build_deps = Vec::new();
-        let re = regex::Regex::new(r#"depends_on\s+"([^"]+)"(?:\s*=>\s*:(\w+))?"#).unwrap();
-        
-        for cap in re.captures_iter(content) {
-            if let Some(name_match) = cap.get(1) {
-                let name = name_match.as_str().to_string();
-                let build_only = cap.get(2).map(|m| m.as_str() == "build").unwrap_or(false);
-                
-                let dep = Dependency {
-                    name,
-                    version: None,
-                    build_only,
-                    optional: false,
-                };
-                
-                if build_only {
-                    build_deps.push(dep);
-                } else {
-                    deps.push(dep);
-                }
-            }
+/// The bottles of a `bottle do` block, from `sha256 cellar: :any, arm64_sonoma: "..."`
+/// lines (or the older `sha256 "..." => :big_sur`).
+fn bottles(stmts: &[Stmt], formula_name: &str) -> Vec<BinaryPackage> {
+    let mut bottles = Vec::new();
+    for call in stmts.iter().filter_map(|s| s.node.call()).filter(|c| c.name == "sha256") {
+        let Some(Node::Hash(pairs)) = call.args.last() else {
+            continue;
+        };
+        for (key, value) in pairs {
+            let (tag, sha256) = match (key, value) {
+                (Node::Symbol(tag), Node::Str(_)) if tag != "cellar" => (tag.clone(), value.as_str()),
+                (Node::Str(_), Node::Symbol(tag)) => (tag.clone(), key.as_str()),
+                _ => continue,
+            };
+            let Some(sha256) = sha256.filter(|s| s.len() == 64 && s.chars().all(|c| c.is_ascii_hexdigit())) else {
+                continue;
+            };
+            // Skip unknown platforms
+            let Some((platform, arch)) = bottle_platform(&tag) else {
+                continue;
+            };
+
+            // Downloading it takes an anonymous registry token (see download::oci)
+            let url = format!(
+                "https://ghcr.io/v2/homebrew/core/{}/blobs/sha256:{}",
+                formula_name.replace('@', "/").replace('+', "x"),
+                sha256
+            );
+            bottles.push(BinaryPackage {
+                platform: platform.to_string(),
+                arch: arch.to_string(),
+                url,
+                sha256,
+            });
         }
-        
-        Ok((deps, build_deps))
     }
+    bottles
+}
 
-    fn extract_install_block(&self, content: &str) -> Option<String> {
-        // Extract the install block (simplified - doesn't handle nested blocks properly)
-        let re = regex::Regex::new(r"def install\s*\n((?:.*\n)*?)\s*end").unwrap();
-        re.captures(content).and_then(|cap| cap.get(1).map(|m| m.as_str().trim().to_string()))
-    }
+/// The `resource "name" do ... end` blocks, in declaration order. Resources without a
+/// url or checksum for this machine are left out.
+fn resources(stanzas: &[&Stmt], vars: &Vars) -> Vec<Resource> {
+    stanzas.iter()
+        .filter_map(|s| s.node.call())
+        .filter(|c| c.receiver.is_none() && c.name == "resource" && c.block.is_some())
+        .filter_map(|call| {
+            let name = call.args.first()?.as_str()?;
+            let body = applicable(call.block_stmts());
+            let arg = |stanza: &str| body.iter()
+                .filter_map(|s| s.node.call())
+                .find(|c| c.receiver.is_none() && c.name == stanza)
+                .and_then(|c| c.args.first());
+            let url = vars.text(arg("url")?)?;
+            let sha256 = arg("sha256")?.as_str().filter(|s| s.len() == 64)?;
+            Some(Resource { name, url, sha256 })
+        })
+        .collect()
+}
 
-    fn extract_test_block(&self, content: &str) -> Option<String> {
-        let re = regex::Regex::new(r"test do\s*\n((?:.*\n)*?)\s*end").unwrap();
-        re.captures(content).and_then(|cap| cap.get(1).map(|m| m.as_str().trim().to_string()))
-    }
+/// A `service do` block.
+fn service(stmts: &[Stmt]) -> ServiceDefinition {
+    let mut service = ServiceDefinition::default();
 
-    fn extract_caveats(&self, content: &str) -> Option<String> {
-        // Handle heredoc style caveats (most common in modern formulae)
-        let heredoc_re = regex::Regex::new(r"def caveats\s*\n\s*<<[-~]EOS\s*\n((?:.*\n)*?)\s*EOS").unwrap();
-        if let Some(cap) = heredoc_re.captures(content) {
-            if let Some(caveats_match) = cap.get(1) {
-                return Some(caveats_match.as_str().trim().to_string());
+    for call in stmts.iter().filter_map(|s| s.node.call()).filter(|c| c.receiver.is_none()) {
+        let Some(value) = call.args.first() else {
+            continue;
+        };
+        match call.name.as_str() {
+            "run" => {
+                let mut parts = match select_for_os(value) {
+                    Node::Array(items) => items.iter().map(path_expr).collect(),
+                    command => vec![path_expr(command)],
+                }
+                .into_iter();
+                service.command = parts.next();
+                service.args = parts.collect();
             }
-        }
-        
-        // Handle string style caveats (less common)
-        let string_re = regex::Regex::new(r#"def caveats\s*\n?\s*"([^"]*)""#).unwrap();
-        if let Some(cap) = string_re.captures(content) {
-            if let Some(caveats_match) = cap.get(1) {
-                return Some(caveats_match.as_str().to_string());
+            "keep_alive" => {
+                service.keep_alive = *value != Node::Bool(false);
+                service.keep_alive_crashed = match value {
+                    Node::Hash(pairs) => {
+                        let set = |key: &str| pairs.iter().any(|(k, v)| k.as_str().as_deref() == Some(key) && *v == Node::Bool(true));
+                        set("crashed") && !set("always")
+                    }
+                    _ => false,
+                };
             }
-        }
-        
-        None
-    }
-
-    fn extract_bottles(&self, content: &str, formula_name: &str, _version: &str) -> NitroResult<Vec<BinaryPackage>> {
-        let mut bottles = Vec::new();
-        
-        // Find the bottle block
-        let bottle_re = regex::Regex::new(r"bottle do\s*\n((?:.*\n)*?)\s*end").unwrap();
-        if let Some(bottle_cap) = bottle_re.captures(content) {
-            if let Some(bottle_block) = bottle_cap.get(1) {
-                let bottle_content = bottle_block.as_str();
-                eprintln!("DEBUG: Found bottle block with {} chars", bottle_content.len());
-                
-                // Extract SHA256 entries
-                // Pattern: sha256 cellar: :any_skip_relocation, platform: "sha256"
-                let sha_re = regex::Regex::new(r#"sha256(?:\s+cellar:\s*:\w+,)?\s+(\w+):\s*"([a-fA-F0-9]{64})""#).unwrap();
-                
-                for cap in sha_re.captures_iter(bottle_content) {
-                    if let (Some(platform_match), Some(sha_match)) = (cap.get(1), cap.get(2)) {
-                        let platform_str = platform_match.as_str();
-                        let sha256 = sha_match.as_str().to_string();
-                        
-                        // Map Homebrew platform names to our platform/arch
-                        let (platform, arch) = match platform_str {
-                            "arm64_sequoia" | "arm64_sonoma" | "arm64_ventura" | "arm64_monterey" => ("darwin", "aarch64"),
-                            "sequoia" | "sonoma" | "ventura" | "monterey" | "big_sur" => ("darwin", "x86_64"),
-                            "x86_64_linux" => ("linux", "x86_64"),
-                            "aarch64_linux" => ("linux", "aarch64"),
-                            _ => continue, // Skip unknown platforms
-                        };
-                        
-                        // Construct bottle URL
-                        // Homebrew bottles are actually stored at a different location
-                        // The ghcr.io URLs need special handling, so we'll use the direct download URL
-                        let os_name = match platform_str {
-                            "arm64_sequoia" => "arm64_sequoia",
-                            "arm64_sonoma" => "arm64_sonoma", 
-                            "arm64_ventura" => "arm64_ventura",
-                            "arm64_monterey" => "arm64_monterey",
-                            "sequoia" => "sequoia",
-                            "sonoma" => "sonoma",
-                            "ventura" => "ventura", 
-                            "monterey" => "monterey",
-                            "big_sur" => "big_sur",
-                            "x86_64_linux" => "x86_64_linux",
-                            _ => platform_str,
-                        };
-                        
-                        // Use the direct GitHub Packages download URL format
-                        // Format: https://ghcr.io/v2/homebrew/core/FORMULA/blobs/sha256:SHA256
-                        // But we need to use the bottle filename format instead
-                        let _bottle_filename = format!("{}-{}.{}.bottle.tar.gz", 
-                            formula_name, _version, os_name);
-                        
-                        // Store the ghcr.io URL - proper authentication will be needed for download
-                        let url = format!(
-                            "https://ghcr.io/v2/homebrew/core/{}/blobs/sha256:{}",
-                            formula_name.replace("@", "/"),
-                            sha256
-                        );
-                        
-                        bottles.push(BinaryPackage {
-                            platform: platform.to_string(),
-                            arch: arch.to_string(),
-                            url,
-                            sha256,
-                        });
-                        
-                        eprintln!("DEBUG: Found bottle for {}/{}: {}", platform, arch, platform_str);
+            "run_type" => service.run_type = value.as_str(),
+            "interval" => service.interval = match value {
+                Node::Int(n) => u32::try_from(*n).ok(),
+                _ => None,
+            },
+            "cron" => service.cron = value.as_str(),
+            "working_dir" => service.working_dir = Some(path_expr(value)),
+            "log_path" => service.log_path = Some(path_expr(value)),
+            "error_log_path" => service.error_log_path = Some(path_expr(value)),
+            "environment_variables" => {
+                if let Node::Hash(pairs) = value {
+                    for (key, val) in pairs {
+                        if let Some(key) = key.as_str() {
+                            service.env.insert(key, path_expr(val));
+                        }
                     }
                 }
             }
+            _ => {}
         }
-        
-        eprintln!("DEBUG: Extracted {} bottles for {}", bottles.len(), formula_name);
-        Ok(bottles)
     }
-}
\ No newline at end of file
+
+    service
+}
+
+/// For `run macos: [...], linux: [...]`, the command for the current OS; otherwise the value itself.
+fn select_for_os(value: &Node) -> &Node {
+    let os_key = if cfg!(target_os = "macos") { "macos" } else { "linux" };
+    match value {
+        Node::Hash(pairs) => pairs.iter()
+            .find(|(key, _)| key.as_str().as_deref() == Some(os_key))
+            .map_or(value, |(_, command)| command),
+        _ => value,
+    }
+}
+
+/// A Ruby path expression like `var/"log/redis.log"` as `#{var}/log/redis.log`.
+/// Strings are unquoted; path helpers and other names become placeholders.
+fn path_expr(node: &Node) -> String {
+    match node {
+        Node::Binary("/", left, right) => format!("{}/{}", path_expr(left), path_expr(right)),
+        Node::Str(parts) => ruby::parts_text(parts),
+        Node::Symbol(symbol) => symbol.clone(),
+        Node::Ident(name) => format!("#{{{}}}", name),
+        Node::Int(n) => n.to_string(),
+        Node::Call(Call { receiver: Some(receiver), name, args, .. }) if args.is_empty() && name == "to_s" => path_expr(receiver),
+        _ => String::new(),
+    }
+}
@@ -0,0 +1,208 @@
+//! Git operations used by taps and source installs.
+//!
+//! These drive the `git` binary rather than linking git2 or gitoxide. Neither is
+//! among the crates this build can depend on, and the binary brings the user's own
+//! credential helpers, proxy and `url.*.insteadOf` settings, which private taps rely
+//! on. Failures still come back as a [`GitError`] classified from git's output, so
+//! callers can match on what went wrong instead of parsing stderr themselves.
+
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use thiserror::Error;
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum GitError {
+    #[error("git is not installed or not on PATH")]
+    NotInstalled,
+
+    #[error("{0} is not a git repository")]
+    NotARepository(PathBuf),
+
+    #[error("repository not found: {0}")]
+    RepositoryNotFound(String),
+
+    #[error("local changes would be overwritten")]
+    LocalChanges,
+
+    #[error("local history has diverged from upstream; cannot fast-forward")]
+    NotFastForward,
+
+    #[error("network error: {0}")]
+    Network(String),
+
+    #[error("git {command} failed: {message}")]
+    Failed { command: String, message: String },
+}
+
+/// Map git's stderr for a failed `command` (e.g. `pull`) to a [`GitError`].
+pub fn classify_error(command: &str, stderr: &str) -> GitError {
+    let message = stderr.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .find(|line| line.starts_with("fatal:") || line.starts_with("error:"))
+        .or_else(|| stderr.lines().map(str::trim).find(|line| !line.is_empty()))
+        .unwrap_or("unknown error")
+        .to_string();
+    let lower = stderr.to_lowercase();
+
+    if lower.contains("not a git repository") {
+        GitError::NotARepository(PathBuf::from("."))
+    } else if lower.contains("would be overwritten") || lower.contains("commit your changes or stash them") {
+        GitError::LocalChanges
+    } else if lower.contains("not possible to fast-forward") || lower.contains("diverging branches") {
+        GitError::NotFastForward
+    } else if lower.contains("repository not found") || lower.contains("does not appear to be a git repository") {
+        GitError::RepositoryNotFound(message)
+    } else if lower.contains("could not resolve host")
+        || lower.contains("unable to access")
+        || lower.contains("connection timed out")
+        || lower.contains("connection refused")
+    {
+        GitError::Network(message)
+    } else {
+        GitError::Failed { command: command.to_string(), message }
+    }
+}
+
+/// Clone `url` into `dest`. `depth` limits history (`None` clones everything), and
+/// `branch` checks out that branch or tag rather than the default branch.
+/// `on_progress` receives the percentage of objects received as git reports it.
+pub async fn clone(url: &str, dest: &Path, depth: Option<u32>, branch: Option<&str>, mut on_progress: impl FnMut(u8)) -> Result<(), GitError> {
+    let mut command = Command::new("git");
+    command.arg("clone").arg("--progress");
+    if let Some(depth) = depth {
+        command.args(["--depth", &depth.to_string()]);
+    }
+    if let Some(branch) = branch {
+        command.args(["--branch", branch]);
+    }
+    command.arg(url).arg(dest);
+
+    let mut child = command
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(spawn_error)?;
+
+    // Progress lines are separated by \r, so read raw bytes rather than lines
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    let mut output = Vec::new();
+    let mut buffer = [0; 4096];
+    loop {
+        let n = stderr.read(&mut buffer).await.map_err(io_error("clone"))?;
+        if n == 0 {
+            break;
+        }
+        output.extend_from_slice(&buffer[..n]);
+        let text = String::from_utf8_lossy(&buffer[..n]);
+        for line in text.split(['\r', '\n']) {
+            if let Some(percent) = receiving_percent(line) {
+                on_progress(percent);
+            }
+        }
+    }
+
+    let status = child.wait().await.map_err(io_error("clone"))?;
+    if !status.success() {
+        return Err(classify_error("clone", &String::from_utf8_lossy(&output)));
+    }
+    Ok(())
+}
+
+/// Percentage from a `Receiving objects:  45% (450/1000)` progress line.
+fn receiving_percent(line: &str) -> Option<u8> {
+    let rest = line.trim().strip_prefix("Receiving objects:")?;
+    rest.trim().split('%').next()?.trim().parse().ok()
+}
+
+/// `git pull --ff-only` in `repo`.
+pub async fn pull_ff_only(repo: &Path) -> Result<(), GitError> {
+    run(repo, "pull", &["pull", "--ff-only"]).await.map(|_| ())
+}
+
+/// The commit `HEAD` points at.
+pub async fn head(repo: &Path) -> Result<String, GitError> {
+    Ok(run(repo, "rev-parse", &["rev-parse", "HEAD"]).await?.trim().to_string())
+}
+
+/// Paths of files added between two commits.
+pub async fn added_paths(repo: &Path, from: &str, to: &str) -> Result<Vec<String>, GitError> {
+    let output = run(repo, "diff", &["diff", "--name-only", "--diff-filter=A", from, to]).await?;
+    Ok(output.lines().map(str::to_string).collect())
+}
+
+/// Paths with uncommitted changes, including untracked files.
+pub async fn changed_paths(repo: &Path) -> Result<Vec<String>, GitError> {
+    let output = run(repo, "status", &["status", "--porcelain", "--untracked-files=all"]).await?;
+    Ok(output.lines()
+        .filter_map(|line| line.get(3..))
+        // Renames are reported as `old -> new`
+        .map(|path| path.rsplit(" -> ").next().unwrap_or(path).to_string())
+        .collect())
+}
+
+/// Number of commits in `range`, e.g. `@{upstream}..HEAD`.
+pub async fn count_commits(repo: &Path, range: &str) -> Result<usize, GitError> {
+    let output = run(repo, "rev-list", &["rev-list", "--count", range]).await?;
+    output.trim().parse().map_err(|_| GitError::Failed {
+        command: "rev-list".to_string(),
+        message: format!("unexpected output '{}'", output.trim()),
+    })
+}
+
+/// Contents of `path` (relative to the repository root) at `commit`.
+pub async fn show_file(repo: &Path, commit: &str, path: &Path) -> Result<String, GitError> {
+    let spec = format!("{}:{}", commit, path.to_string_lossy());
+    run(repo, "show", &["show", &spec]).await
+}
+
+/// Uncommitted changes to tracked files, as a patch against `HEAD`.
+pub async fn diff_head(repo: &Path) -> Result<String, GitError> {
+    run(repo, "diff", &["diff", "HEAD"]).await
+}
+
+/// Write the commits in `range` as patch files into `dir`.
+pub async fn format_patch(repo: &Path, range: &str, dir: &Path) -> Result<(), GitError> {
+    let dir = dir.to_string_lossy();
+    run(repo, "format-patch", &["format-patch", "--quiet", "-o", &dir, range]).await.map(|_| ())
+}
+
+/// Point the current branch at `rev` and discard every local change, untracked files included.
+pub async fn reset_hard(repo: &Path, rev: &str) -> Result<(), GitError> {
+    run(repo, "reset", &["reset", "--quiet", "--hard", rev]).await?;
+    run(repo, "clean", &["clean", "--quiet", "-fd"]).await.map(|_| ())
+}
+
+async fn run(repo: &Path, command: &str, args: &[&str]) -> Result<String, GitError> {
+    if !repo.is_dir() {
+        return Err(GitError::NotARepository(repo.to_path_buf()));
+    }
+
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(repo)
+        .output()
+        .await
+        .map_err(spawn_error)?;
+
+    if !output.status.success() {
+        return Err(match classify_error(command, &String::from_utf8_lossy(&output.stderr)) {
+            GitError::NotARepository(_) => GitError::NotARepository(repo.to_path_buf()),
+            error => error,
+        });
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn spawn_error(error: std::io::Error) -> GitError {
+    match error.kind() {
+        std::io::ErrorKind::NotFound => GitError::NotInstalled,
+        _ => GitError::Failed { command: "spawn".to_string(), message: error.to_string() },
+    }
+}
+
+fn io_error(command: &'static str) -> impl Fn(std::io::Error) -> GitError {
+    move |error| GitError::Failed { command: command.to_string(), message: error.to_string() }
+}
@@ -0,0 +1,201 @@
+//! Whether Linux bottles can run here. Homebrew builds them on an old glibc and links
+//! C++ code against its own GCC's libstdc++, so a bottle only loads on a host whose
+//! glibc (and libstdc++, for C++) is at least as new as the symbols it uses.
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::core::version::Version;
+
+/// The glibc Homebrew's `x86_64_linux` and `aarch64_linux` bottles are built against
+/// (Ubuntu 22.04). Older hosts can't load them at all.
+pub const BOTTLE_GLIBC: &str = "2.35";
+
+/// The C libraries of this machine.
+#[derive(Debug, Clone, Default)]
+pub struct HostLibraries {
+    /// `None` on musl and other non-glibc systems
+    pub glibc: Option<Version>,
+    /// Newest `GLIBCXX_` symbol version the system libstdc++ provides
+    pub glibcxx: Option<Version>,
+}
+
+/// This machine's libraries, looked up once.
+pub fn host() -> &'static HostLibraries {
+    static HOST: OnceLock<HostLibraries> = OnceLock::new();
+    HOST.get_or_init(|| HostLibraries {
+        glibc: std::process::Command::new("getconf").arg("GNU_LIBC_VERSION").output().ok()
+            .filter(|o| o.status.success())
+            .and_then(|o| String::from_utf8_lossy(&o.stdout).split_whitespace().nth(1).map(Version::new)),
+        glibcxx: LIBSTDCXX_PATHS.iter()
+            .map(Path::new)
+            .find(|path| path.exists())
+            .and_then(|path| std::fs::read(path).ok())
+            .and_then(|data| newest_symbol_version(&data, "GLIBCXX_")),
+    })
+}
+
+const LIBSTDCXX_PATHS: &[&str] = &[
+    "/usr/lib/x86_64-linux-gnu/libstdc++.so.6",
+    "/usr/lib/aarch64-linux-gnu/libstdc++.so.6",
+    "/usr/lib64/libstdc++.so.6",
+    "/usr/lib/libstdc++.so.6",
+    "/lib64/libstdc++.so.6",
+];
+
+/// The symbol versions a bottle's ELF files need.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BottleNeeds {
+    pub glibc: Option<Version>,
+    pub glibcxx: Option<Version>,
+}
+
+/// Scan the ELF files under `dir` (an extracted bottle) for the newest `GLIBC_` and
+/// `GLIBCXX_` symbol versions they need. A library the bottle ships that defines those
+/// versions itself, such as gcc's libstdc++, doesn't make the bottle need them.
+pub fn bottle_needs(dir: &Path) -> BottleNeeds {
+    let mut needs = BottleNeeds::default();
+    for path in elf_files(dir) {
+        let Ok(data) = std::fs::read(&path) else {
+            continue;
+        };
+        for name in needed_versions(&data) {
+            needs.glibc = needs.glibc.max(symbol_version(&name, "GLIBC_"));
+            needs.glibcxx = needs.glibcxx.max(symbol_version(&name, "GLIBCXX_"));
+        }
+    }
+    needs
+}
+
+/// `SHT_GNU_verneed`: the symbol versions an ELF file needs from its libraries
+const SHT_GNU_VERNEED: u32 = 0x6fff_fffe;
+
+/// The names of the symbol versions the ELF file `data` needs (its `.gnu.version_r`
+/// entries, such as `GLIBC_2.34`). Not an ELF file, or a malformed one, needs none.
+pub fn needed_versions(data: &[u8]) -> Vec<String> {
+    needed_versions_checked(data).unwrap_or_default()
+}
+
+fn needed_versions_checked(data: &[u8]) -> Option<Vec<String>> {
+    if data.get(..4)? != b"\x7fELF" {
+        return None;
+    }
+    let wide = *data.get(4)? == 2;
+    let big_endian = *data.get(5)? == 2;
+    let bytes = |offset: usize, len: usize| data.get(offset..offset.checked_add(len)?);
+    let u16_at = |offset: usize| bytes(offset, 2).map(|b| {
+        let b = [b[0], b[1]];
+        if big_endian { u16::from_be_bytes(b) } else { u16::from_le_bytes(b) }
+    });
+    let u32_at = |offset: usize| bytes(offset, 4).map(|b| {
+        let b = [b[0], b[1], b[2], b[3]];
+        if big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) }
+    });
+    let u64_at = |offset: usize| bytes(offset, 8).map(|b| {
+        let b = b.try_into().unwrap_or_default();
+        if big_endian { u64::from_be_bytes(b) } else { u64::from_le_bytes(b) }
+    });
+    // An address or size field: 8 bytes in 64-bit files, 4 in 32-bit ones
+    let word_at = |offset: usize| if wide { u64_at(offset).map(|w| w as usize) } else { u32_at(offset).map(|w| w as usize) };
+
+    let (shoff, shentsize, shnum) = if wide {
+        (word_at(0x28)?, u16_at(0x3a)? as usize, u16_at(0x3c)? as usize)
+    } else {
+        (word_at(0x20)?, u16_at(0x2e)? as usize, u16_at(0x30)? as usize)
+    };
+    // (type, offset, size, link, info) of each section header
+    let section = |index: usize| {
+        let header = shoff.checked_add(index.checked_mul(shentsize)?)?;
+        if wide {
+            Some((u32_at(header + 4)?, word_at(header + 24)?, word_at(header + 32)?, u32_at(header + 40)?, u32_at(header + 44)?))
+        } else {
+            Some((u32_at(header + 4)?, word_at(header + 16)?, word_at(header + 20)?, u32_at(header + 24)?, u32_at(header + 28)?))
+        }
+    };
+
+    let mut names = Vec::new();
+    for index in 0..shnum {
+        let (kind, offset, _, link, count) = section(index)?;
+        if kind != SHT_GNU_VERNEED {
+            continue;
+        }
+        let (_, strtab, strtab_size, _, _) = section(link as usize)?;
+        let string = |name: u32| {
+            let start = strtab.checked_add(name as usize)?;
+            let table = data.get(start..strtab.checked_add(strtab_size)?)?;
+            let end = table.iter().position(|&b| b == 0)?;
+            Some(String::from_utf8_lossy(&table[..end]).into_owned())
+        };
+
+        // Each library's entry lists the versions wanted from it
+        let mut need = offset;
+        for _ in 0..count {
+            let mut aux = need.checked_add(u32_at(need + 8)? as usize)?;
+            for _ in 0..u16_at(need + 2)? {
+                names.extend(string(u32_at(aux + 8)?));
+                aux = aux.checked_add(u32_at(aux + 12)? as usize)?;
+            }
+            need = need.checked_add(u32_at(need + 12)? as usize)?;
+        }
+    }
+    Some(names)
+}
+
+/// The version in a symbol version name such as `GLIBC_2.34`, if it has `prefix`.
+fn symbol_version(name: &str, prefix: &str) -> Option<Version> {
+    let version = name.strip_prefix(prefix)?;
+    let numeric = version.contains('.') && version.chars().all(|c| c.is_ascii_digit() || c == '.');
+    numeric.then(|| Version::new(version))
+}
+
+fn elf_files(dir: &Path) -> Vec<PathBuf> {
+    use std::io::Read;
+
+    walkdir::WalkDir::new(dir).into_iter().flatten()
+        .filter(|entry| entry.file_type().is_file())
+        .filter(|entry| {
+            let mut magic = [0u8; 4];
+            std::fs::File::open(entry.path()).and_then(|mut f| f.read_exact(&mut magic)).is_ok() && magic == *b"\x7fELF"
+        })
+        .map(|entry| entry.into_path())
+        .collect()
+}
+
+/// The newest `<prefix>X.Y[.Z]` version string in `data`.
+pub fn newest_symbol_version(data: &[u8], prefix: &str) -> Option<Version> {
+    let pattern = format!(r"{}(\d+\.\d+(?:\.\d+)?)", regex::escape(prefix));
+    regex::bytes::Regex::new(&pattern).ok()?
+        .captures_iter(data)
+        .map(|cap| Version::new(String::from_utf8_lossy(&cap[1])))
+        .max()
+}
+
+/// Why no Linux bottle can run on `host`, judged before downloading one.
+pub fn host_incompatibility(host: &HostLibraries) -> Option<String> {
+    match &host.glibc {
+        None => Some("this system doesn't use glibc, which Linux bottles are built against".to_string()),
+        Some(glibc) if *glibc < Version::new(BOTTLE_GLIBC) => Some(format!(
+            "Linux bottles need glibc {} or newer and this system has {}", BOTTLE_GLIBC, glibc
+        )),
+        Some(_) => None,
+    }
+}
+
+/// Why a bottle needing `needs` can't load on `host`, if it can't.
+pub fn bottle_incompatibility(needs: &BottleNeeds, host: &HostLibraries) -> Option<String> {
+    if let Some(reason) = host_incompatibility(host) {
+        return Some(reason);
+    }
+    if let (Some(needed), Some(glibc)) = (&needs.glibc, &host.glibc) {
+        if needed > glibc {
+            return Some(format!("the bottle needs glibc {} and this system has {}", needed, glibc));
+        }
+    }
+    match (&needs.glibcxx, &host.glibcxx) {
+        (Some(needed), Some(glibcxx)) if needed > glibcxx => Some(format!(
+            "the bottle needs libstdc++ with GLIBCXX_{} and the system's only goes up to GLIBCXX_{}", needed, glibcxx
+        )),
+        (Some(_), None) => Some("the bottle needs libstdc++, which wasn't found on this system".to_string()),
+        _ => None,
+    }
+}
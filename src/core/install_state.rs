@@ -0,0 +1,188 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use super::formula::Formula;
+
+/// Phases an install moves through, in order. Each transition is persisted so an
+/// interrupted install can be resumed from the last durable phase or rolled back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InstallPhase {
+    Resolved,
+    Fetched,
+    Verified,
+    Staged,
+    Linked,
+    Registered,
+}
+
+impl std::fmt::Display for InstallPhase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            InstallPhase::Resolved => "resolved",
+            InstallPhase::Fetched => "fetched",
+            InstallPhase::Verified => "verified",
+            InstallPhase::Staged => "staged",
+            InstallPhase::Linked => "linked",
+            InstallPhase::Registered => "registered",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Persisted state of an install that has started but not yet been registered.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstallRecord {
+    pub formula: Formula,
+    pub phase: InstallPhase,
+    pub build_from_source: bool,
+    pub keg_path: PathBuf,
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+    /// A source checksum the user accepted in place of the formula's
+    #[serde(default)]
+    pub checksum_override: Option<ChecksumOverride>,
+    /// What the keg was made from, once the installer has fetched it
+    #[serde(default)]
+    pub source: Option<InstallSource>,
+    /// Whether the user asked for this formula, rather than it being a dependency
+    #[serde(default)]
+    pub on_request: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InstallMethod {
+    Bottle,
+    Source,
+    /// Unpacked from the cache of kegs built from source
+    CachedBuild,
+}
+
+/// The artifact an install was made from, as recorded in the audit log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstallSource {
+    pub method: InstallMethod,
+    /// The bottle or source archive, or the cached keg
+    pub url: String,
+    pub sha256: Option<String>,
+    /// Where the artifact was read from: `download`, `fetch cache`, `download cache`
+    /// or `keg cache`
+    pub fetched_from: String,
+}
+
+/// A source tarball installed despite not matching the formula's `sha256`, because
+/// the user accepted its actual checksum. Kept in the package's receipt.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChecksumOverride {
+    pub url: String,
+    /// What the formula says
+    pub expected: String,
+    /// What was downloaded and accepted
+    pub accepted: String,
+    pub accepted_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Install phase records, kept in their own tree of the package database.
+#[derive(Clone)]
+pub struct InstallStateStore {
+    tree: sled::Tree,
+}
+
+impl InstallStateStore {
+    pub fn open(db: &sled::Db) -> Result<Self> {
+        Ok(Self {
+            tree: db.open_tree("install_state")?,
+        })
+    }
+
+    pub fn begin(&self, formula: &Formula, keg_path: PathBuf, build_from_source: bool) -> Result<InstallRecord> {
+        let now = chrono::Utc::now();
+        let record = InstallRecord {
+            formula: formula.clone(),
+            phase: InstallPhase::Resolved,
+            build_from_source,
+            keg_path,
+            started_at: now,
+            updated_at: now,
+            checksum_override: None,
+            source: None,
+            on_request: false,
+        };
+        self.write(&record)?;
+        Ok(record)
+    }
+
+    /// Note that `name` is being installed because the user asked for it.
+    pub fn set_on_request(&self, name: &str) -> Result<()> {
+        if let Some(mut record) = self.get(name)? {
+            record.on_request = true;
+            record.updated_at = chrono::Utc::now();
+            self.write(&record)?;
+        }
+        Ok(())
+    }
+
+    /// Note that the install of `name` uses an accepted checksum override.
+    pub fn set_checksum_override(&self, name: &str, checksum_override: ChecksumOverride) -> Result<()> {
+        if let Some(mut record) = self.get(name)? {
+            record.checksum_override = Some(checksum_override);
+            record.updated_at = chrono::Utc::now();
+            self.write(&record)?;
+        }
+        Ok(())
+    }
+
+    /// Note what the install of `name` is made from.
+    pub fn set_source(&self, name: &str, source: InstallSource) -> Result<()> {
+        if let Some(mut record) = self.get(name)? {
+            record.source = Some(source);
+            record.updated_at = chrono::Utc::now();
+            self.write(&record)?;
+        }
+        Ok(())
+    }
+
+    pub fn advance(&self, name: &str, phase: InstallPhase) -> Result<()> {
+        if let Some(mut record) = self.get(name)? {
+            record.phase = phase;
+            record.updated_at = chrono::Utc::now();
+            self.write(&record)?;
+        }
+        Ok(())
+    }
+
+    /// Drop the record once the install is registered (or has been aborted).
+    pub fn complete(&self, name: &str) -> Result<()> {
+        self.tree.remove(name)?;
+        self.tree.flush()?;
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Result<Option<InstallRecord>> {
+        match self.tree.get(name)? {
+            Some(data) => Ok(Some(serde_json::from_slice(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn pending(&self) -> Result<Vec<InstallRecord>> {
+        let mut records = Vec::new();
+        for entry in self.tree.iter() {
+            let (_, value) = entry?;
+            if let Ok(record) = serde_json::from_slice::<InstallRecord>(&value) {
+                records.push(record);
+            }
+        }
+        records.sort_by_key(|r| r.started_at);
+        Ok(records)
+    }
+
+    fn write(&self, record: &InstallRecord) -> Result<()> {
+        self.tree.insert(&record.formula.name, serde_json::to_vec(record)?)?;
+        // Flush eagerly: the whole point is surviving a crash between phases
+        self.tree.flush()?;
+        Ok(())
+    }
+}
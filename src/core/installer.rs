@@ -1,133 +1,871 @@
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::LazyLock;
 use std::process::Command;
 use tokio::fs;
 
+use crate::cache::kegs::KegCache;
+use crate::cache::shared::SharedCache;
+use crate::cache::DownloadCache;
+use crate::core::build_env::BuildEnv;
+use crate::core::debug_build::{self, ShellAt};
+use crate::core::disk::{self, SpaceRequirement};
+use crate::core::interpolate::PathContext;
+use crate::core::language::LanguageInstaller;
+use crate::core::{glibc, requirements, toolchain};
+use crate::core::cancel::CancellationToken;
 use crate::core::{NitroError, NitroResult};
+use crate::core::workspace::{self, Workspace};
+use crate::core::transaction::Transaction;
 use crate::download::Downloader;
+use crate::ui::progress::ProgressReporter;
 use super::formula::Formula;
+use super::install_state::{InstallMethod, InstallPhase, InstallSource, InstallStateStore};
+use super::manifest::Manifest;
 use super::package::Package;
 
+/// Time allowed for a HEAD request when sizing up downloads
+const HEAD_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Install locations, read from the `[install]` section of the config file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InstallConfig {
+    /// Where executables are linked. Defaults to `<prefix>/bin`, or `~/.nitro/bin` when
+    /// the prefix is shared with Homebrew so brew's links are left alone. `~` is expanded.
+    pub link_dir: Option<PathBuf>,
+    /// Point installed scripts' shebangs at their dependencies' interpreters
+    pub rewrite_shebangs: bool,
+    /// Formula providing an interpreter, when it isn't found among the dependencies
+    /// (e.g. `python3 = "python@3.12"`)
+    pub interpreters: std::collections::BTreeMap<String, String>,
+    /// Keep a packed copy of every keg built from source, so reinstalling it doesn't
+    /// compile it again
+    pub cache_source_builds: bool,
+    /// Where those kegs are kept (defaults to `kegs` in nitro's cache directory). A
+    /// directory shared between machines of the same platform lets them reuse each
+    /// other's builds
+    pub keg_cache_dir: Option<PathBuf>,
+    /// Add formulae's `shell_rc` lines to the shell startup file when installing them,
+    /// and take them out again when uninstalling
+    pub apply_shell_rc: bool,
+    /// Build a formula from source when pouring its bottle fails (a download refused,
+    /// a keg that can't be relocated), rather than failing its install
+    pub retry_from_source: bool,
+    /// Keep every bottle poured unpacked, and install it again (into this prefix or
+    /// another on the same filesystem) by cloning that copy instead of downloading and
+    /// extracting it
+    pub clone_bottles: bool,
+    /// Where those bottles are kept (defaults to `unpacked` in nitro's cache directory).
+    /// Cloning only works when this is on the same filesystem as the prefix
+    pub unpacked_bottle_dir: Option<PathBuf>,
+}
+
+impl Default for InstallConfig {
+    fn default() -> Self {
+        Self {
+            link_dir: None,
+            rewrite_shebangs: true,
+            interpreters: Default::default(),
+            cache_source_builds: false,
+            keg_cache_dir: None,
+            apply_shell_rc: false,
+            retry_from_source: true,
+            clone_bottles: true,
+            unpacked_bottle_dir: None,
+        }
+    }
+}
+
+/// The directory executables are linked into for `prefix`.
+pub fn resolve_link_dir(prefix: &Path, config: &InstallConfig) -> PathBuf {
+    let home = || directories::BaseDirs::new().map(|d| d.home_dir().to_path_buf());
+
+    if let Some(dir) = &config.link_dir {
+        return match (dir.strip_prefix("~"), home()) {
+            (Ok(rest), Some(home)) => home.join(rest),
+            _ => dir.clone(),
+        };
+    }
+
+    match home() {
+        Some(home) if prefix.join("bin/brew").exists() => home.join(".nitro/bin"),
+        _ => prefix.join("bin"),
+    }
+}
+
+static OVERWRITE: AtomicBool = AtomicBool::new(false);
+
+/// Replace whatever is in the way when linking (`--overwrite`), rather than refusing
+/// to link.
+pub fn allow_overwrite() {
+    OVERWRITE.store(true, Ordering::SeqCst);
+}
+
+pub fn overwrites() -> bool {
+    OVERWRITE.load(Ordering::SeqCst)
+}
+
+/// Who owns the existing entry at `link` if it isn't ours to replace when linking
+/// formula `name`: the formula another keg's symlink points into, or a description
+/// of the file or link in the way. `None` when the path is free, already links into
+/// one of `name`'s kegs, or is a broken link outside any Cellar.
+pub fn link_conflict(link: &Path, name: &str) -> Option<String> {
+    let metadata = std::fs::symlink_metadata(link).ok()?;
+    if !metadata.file_type().is_symlink() {
+        return Some("an existing file".to_string());
+    }
+
+    let target = std::fs::read_link(link).ok()?.to_string_lossy().to_string();
+    match target.split("Cellar/").nth(1).and_then(|rest| rest.split('/').next()) {
+        Some(owner) => (owner != name).then(|| format!("{} (linked to {})", owner, target)),
+        // A manually made link to a tool installed some other way
+        None => link.exists().then(|| format!("a link to {}", target)),
+    }
+}
+
+/// A path linking a keg would replace, and what is there now.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkConflict {
+    pub link: PathBuf,
+    pub owner: String,
+}
+
+/// The links linking `keg` makes, as `(target in the keg, link)` pairs: its executables
+/// in `link_dir`, and when that's the prefix's own `bin`, the files under its `lib` and
+/// `share` at the same places under `prefix`.
+pub fn keg_links(keg: &Path, prefix: &Path, link_dir: &Path) -> std::io::Result<Vec<(PathBuf, PathBuf)>> {
+    let mut links = Vec::new();
+    let bin = keg.join("bin");
+    if bin.is_dir() {
+        for entry in std::fs::read_dir(&bin)? {
+            let entry = entry?;
+            links.push((entry.path(), link_dir.join(entry.file_name())));
+        }
+    }
+    if link_dir == prefix.join("bin") {
+        for dir in ["lib", "share"] {
+            let root = keg.join(dir);
+            if !root.is_dir() {
+                continue;
+            }
+            for entry in walkdir::WalkDir::new(&root).min_depth(1).follow_links(false) {
+                let entry = entry.map_err(std::io::Error::other)?;
+                if entry.file_type().is_dir() {
+                    continue;
+                }
+                let relative = entry.path().strip_prefix(keg).map_err(std::io::Error::other)?;
+                links.push((entry.path().to_path_buf(), prefix.join(relative)));
+            }
+        }
+    }
+    links.sort_by(|a, b| a.1.cmp(&b.1));
+    Ok(links)
+}
+
+/// The entries linking `keg` (a keg of formula `name`, see [`keg_links`]) would replace.
+pub fn link_conflicts(keg: &Path, prefix: &Path, link_dir: &Path, name: &str) -> std::io::Result<Vec<LinkConflict>> {
+    Ok(keg_links(keg, prefix, link_dir)?.into_iter()
+        .filter_map(|(_, link)| link_conflict(&link, name).map(|owner| LinkConflict { link, owner }))
+        .collect())
+}
+
+/// File nitro leaves in every keg it installs, to tell its kegs apart from brew's.
+pub const KEG_MARKER: &str = ".nitro-keg";
+
+/// The bottle of `formula` for `platform_tag` (`platform/arch`, e.g. `linux/x86_64`).
+pub fn bottle_for<'a>(formula: &'a Formula, platform_tag: &str) -> Option<&'a super::formula::BinaryPackage> {
+    let (platform, arch) = platform_tag.split_once('/')?;
+    formula.binary_packages.iter().find(|pkg| pkg.platform == platform && pkg.arch == arch)
+}
+
+/// Name a fetched bottle is stored under, e.g. `wget--1.24.5_1.linux_x86_64.bottle.tar.gz`.
+pub fn bottle_filename(formula: &Formula, platform_tag: &str) -> String {
+    format!("{}--{}.{}.bottle.tar.gz", formula.name, formula.pkg_version(), platform_tag.replace('/', "_"))
+}
+
+/// Name a fetched source archive or resource is stored under, e.g.
+/// `wget--1.24.5--wget-1.24.5.tar.gz`.
+pub fn source_filename(formula: &Formula, url: &str) -> String {
+    let file_name = url.split('/').next_back().unwrap_or("source.tar.gz");
+    format!("{}--{}--{}", formula.name, formula.pkg_version(), file_name)
+}
+
+/// An install script statement that puts one of the formula's resources in place.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResourceStage {
+    /// `resource("name").stage(target)` or `target.install resource("name")`
+    Into { resource: String, target: String },
+    /// `resource("name").stage do`, whose block runs in the staged resource: in
+    /// `target` if one is given, otherwise a directory of its own
+    Block { resource: String, target: Option<String> },
+}
+
+static STAGE: LazyLock<regex::Regex> = LazyLock::new(|| {
+    regex::Regex::new(r#"^resource\(?\s*"([^"]+)"\s*\)?\.stage(?:\s*\(\s*(.+?)\s*\)|\s+(.+?))??(\s+do(?:\s*\|[^|]*\|)?)?$"#).unwrap()
+});
+static INSTALL_RESOURCE: LazyLock<regex::Regex> = LazyLock::new(|| {
+    regex::Regex::new(r#"^(.+?)\.install\s*\(?\s*resource\(?\s*"([^"]+)"\s*\)?\s*\)?$"#).unwrap()
+});
+static OPENS_DO_BLOCK: LazyLock<regex::Regex> = LazyLock::new(|| regex::Regex::new(r"\bdo(\s*\|[^|]*\|)?$").unwrap());
+
+/// The resource staging `line` does, if it does any.
+pub fn resource_stage(line: &str) -> Option<ResourceStage> {
+    let line = line.trim();
+    if let Some(cap) = STAGE.captures(line) {
+        let resource = cap[1].to_string();
+        let target = cap.get(2).or(cap.get(3)).map(|m| m.as_str().to_string());
+        return Some(match (cap.get(4), target) {
+            (Some(_), target) => ResourceStage::Block { resource, target },
+            (None, Some(target)) => ResourceStage::Into { resource, target },
+            // Without a block or a target it's staged where the script is
+            (None, None) => ResourceStage::Into { resource, target: "Pathname.pwd".to_string() },
+        });
+    }
+    let cap = INSTALL_RESOURCE.captures(line)?;
+    Some(ResourceStage::Into { resource: cap[2].to_string(), target: cap[1].to_string() })
+}
+
+/// Whether `line` opens a block that a later `end` closes.
+fn opens_block(line: &str) -> bool {
+    let keyword = line.split_whitespace().next().unwrap_or_default();
+    matches!(keyword, "if" | "unless" | "case" | "while" | "until" | "begin" | "def" | "class" | "module")
+        || OPENS_DO_BLOCK.is_match(line)
+}
+
+/// Where the path expression `expr` of an install script points: `buildpath` is
+/// `build_dir`, `Pathname.pwd` is `cwd`, path helpers are in the keg, and other relative
+/// paths are in `cwd`. `None` for anything it can't work out.
+fn script_path(expr: &str, build_dir: &Path, cwd: &Path, paths: &PathContext) -> Option<PathBuf> {
+    let mut expr = expr.trim();
+    while let Some(inner) = expr.strip_prefix('(').and_then(|e| e.strip_suffix(')')) {
+        expr = inner.trim();
+    }
+    for (base, dir) in [("buildpath", build_dir), ("Pathname.pwd", cwd)] {
+        if expr == base {
+            return Some(dir.to_path_buf());
+        }
+        if let Some(rest) = expr.strip_prefix(base).and_then(|e| e.strip_prefix('/')) {
+            return Some(dir.join(paths.evaluate(rest)?));
+        }
+    }
+    Some(cwd.join(paths.evaluate(expr)?))
+}
+
+/// Where fetched bottles are kept. `fetch`, `cache warm` and the daemon fill it, and
+/// installs look there before downloading.
+pub fn bottle_cache_dir() -> NitroResult<PathBuf> {
+    cache_subdir("bottles")
+}
+
+/// Where fetched source archives and resources are kept, like [`bottle_cache_dir`].
+pub fn source_cache_dir() -> NitroResult<PathBuf> {
+    cache_subdir("sources")
+}
+
+/// Where poured bottles are kept unpacked for cloning, one directory per bottle
+/// checksum (see `InstallConfig::clone_bottles`).
+pub fn unpacked_bottle_dir() -> NitroResult<PathBuf> {
+    match crate::core::config::Config::load().ok().and_then(|c| c.install.unpacked_bottle_dir) {
+        Some(dir) => Ok(dir),
+        None => cache_subdir("unpacked"),
+    }
+}
+
+fn cache_subdir(name: &str) -> NitroResult<PathBuf> {
+    directories::ProjectDirs::from("com", "nitro", "nitro")
+        .map(|dirs| dirs.cache_dir().join(name))
+        .ok_or_else(|| NitroError::Other("Could not determine cache directory".into()))
+}
+
+/// Homebrew writes this into every keg it installs (and ships it inside bottles)
+const HOMEBREW_RECEIPT: &str = "INSTALL_RECEIPT.json";
+
+/// Who installed a keg, judging by the files in it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KegOwner {
+    Nitro,
+    Homebrew,
+    Unknown,
+}
+
+/// The owner of the keg at `keg`, or `None` if there is no keg there. A keg with a brew
+/// receipt may still be nitro's if it was installed before markers existed; callers
+/// check the package database for that.
+pub fn keg_owner(keg: &Path) -> Option<KegOwner> {
+    if !keg.is_dir() {
+        return None;
+    }
+    Some(if keg.join(KEG_MARKER).exists() {
+        KegOwner::Nitro
+    } else if keg.join(HOMEBREW_RECEIPT).exists() {
+        KegOwner::Homebrew
+    } else {
+        KegOwner::Unknown
+    })
+}
+
+/// How far an archive's extraction has got: entries unpacked, and bytes of the archive
+/// file read of its `total`. The decompressor reads ahead of what's unpacked, so the
+/// bytes say how far along it is without counting the entries first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Extraction {
+    pub entries: u64,
+    pub read: u64,
+    pub total: u64,
+}
+
+impl Extraction {
+    pub fn percent(&self) -> u64 {
+        (self.read * 100).checked_div(self.total).unwrap_or(100).min(100)
+    }
+}
+
+/// A reader counting the bytes read through it into `read`.
+struct CountingReader<R> {
+    inner: R,
+    read: std::rc::Rc<std::cell::Cell<u64>>,
+}
+
+impl<R: std::io::Read> std::io::Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read.set(self.read.get() + n as u64);
+        Ok(n)
+    }
+}
+
+/// Unpack the tar stream `archive` into `destination` an entry at a time, checking
+/// `cancel` before each, and call `report` with `progress` each time the share of the
+/// archive read moves on (and once at the end). Returns the entry count. As in
+/// `tar::Archive::unpack`, directories get their own entries (and modes) last, deepest
+/// first, so a read-only directory doesn't stop its contents being written.
+fn unpack_entries(archive: impl std::io::Read, destination: &Path, cancel: &CancellationToken, progress: impl Fn() -> Extraction, report: &dyn Fn(&Extraction)) -> NitroResult<u64> {
+    let mut archive = tar::Archive::new(archive);
+    std::fs::create_dir_all(destination)?;
+    let mut count = 0;
+    let mut reported = None;
+    let mut directories = Vec::new();
+    for entry in archive.entries()? {
+        cancel.check()?;
+        let mut entry = entry?;
+        if entry.header().entry_type() == tar::EntryType::Directory {
+            directories.push(entry);
+        } else {
+            entry.unpack_in(destination)?;
+        }
+        count += 1;
+        let current = Extraction { entries: count, ..progress() };
+        if reported != Some(current.percent()) {
+            reported = Some(current.percent());
+            report(&current);
+        }
+    }
+    directories.sort_by(|a, b| b.path_bytes().cmp(&a.path_bytes()));
+    for mut directory in directories {
+        directory.unpack_in(destination)?;
+    }
+    report(&Extraction { entries: count, ..progress() });
+    Ok(count)
+}
+
+/// Record that nitro owns the keg at `keg`.
+pub fn write_keg_marker(keg: &Path) -> std::io::Result<()> {
+    let marker = serde_json::json!({
+        "installed_by": "nitro",
+        "nitro_version": env!("CARGO_PKG_VERSION"),
+        "installed_at": super::deterministic::now(),
+    });
+    std::fs::create_dir_all(keg)?;
+    std::fs::write(keg.join(KEG_MARKER), marker.to_string())
+}
+
 pub struct Installer {
     prefix: PathBuf,
     cellar: PathBuf,
     bin_dir: PathBuf,
     downloader: Downloader,
+    /// Set when a shared cache is configured; downloads with a known digest go through it
+    download_cache: Option<DownloadCache>,
+    /// The package database, for the installed versions of dependencies
+    packages: Option<sled::Db>,
+    /// Where to report how installs are going, when something is showing it
+    progress: Option<ProgressReporter>,
 }
 
 impl Installer {
-    pub fn new() -> Result<Self> {
-        let prefix = Self::get_prefix()?;
+    pub fn new(downloader: Downloader) -> Result<Self> {
+        let mut installer = Self::with_prefix(downloader, &Self::get_prefix()?, &Self::link_dir()?)?;
+
+        let config = crate::core::config::Config::load()?.cache;
+        installer.download_cache = match SharedCache::from_config(&config, installer.downloader.client().clone()) {
+            Some(shared) if !super::readonly::is_enabled() => match DownloadCache::new(installer.downloader.clone()) {
+                Ok(cache) => Some(cache.with_shared(shared)),
+                Err(e) => {
+                    eprintln!("Warning: not using the shared cache, the local cache could not be opened: {}", e);
+                    None
+                }
+            },
+            _ => None,
+        };
+        Ok(installer)
+    }
+
+    /// An installer for `prefix` linking into `link_dir`, rather than wherever this
+    /// machine's config says, and without a shared cache.
+    pub fn with_prefix(downloader: Downloader, prefix: &Path, link_dir: &Path) -> Result<Self> {
         let cellar = prefix.join("Cellar");
-        let bin_dir = prefix.join("bin");
 
         // Create directories if they don't exist
-        std::fs::create_dir_all(&cellar)?;
-        std::fs::create_dir_all(&bin_dir)?;
-
-        let downloader = Downloader::new()?;
+        if !super::readonly::is_enabled() {
+            std::fs::create_dir_all(&cellar)?;
+            std::fs::create_dir_all(link_dir)?;
+        }
 
         Ok(Self {
-            prefix,
+            prefix: prefix.to_path_buf(),
             cellar,
-            bin_dir,
+            bin_dir: link_dir.to_path_buf(),
             downloader,
+            download_cache: None,
+            packages: None,
+            progress: None,
         })
     }
 
-    pub async fn install(&self, formula: &Formula, build_from_source: bool) -> NitroResult<()> {
-        // Try binary installation first unless building from source
+    /// Read installed versions from `db`, the package database.
+    pub fn with_packages(mut self, db: sled::Db) -> Self {
+        self.packages = Some(db);
+        self
+    }
+
+    /// Report extraction progress to `progress`.
+    pub fn set_progress(&mut self, progress: ProgressReporter) {
+        self.progress = Some(progress);
+    }
+
+    /// Tell the progress reporter, if there is one, how extracting `name`'s archive is going.
+    fn report_extraction<'a>(&'a self, name: &'a str) -> impl Fn(&Extraction) + 'a {
+        move |extraction| {
+            if let Some(progress) = &self.progress {
+                progress.update_package_progress(name, &format!("extracting {}% ({} files)", extraction.percent(), extraction.entries));
+            }
+        }
+    }
+
+    /// The version of `name` the package database records as installed.
+    fn installed_version(&self, name: &str) -> Option<String> {
+        let data = self.packages.as_ref()?.get(name).ok()??;
+        serde_json::from_slice::<Package>(&data).ok()?.installed_version
+    }
+
+    /// Start the transaction an install of `formula` records its changes in.
+    pub fn begin(&self, formula: &Formula) -> NitroResult<Transaction> {
+        Ok(Transaction::begin(&workspace::root(&self.prefix), &formula.name, &formula.pkg_version())?)
+    }
+
+    /// Install `formula` into the Cellar and link it, recording every change in `tx`.
+    /// Downloads, extraction and build commands stop when `cancel` fires. Callers roll
+    /// `tx` back if this fails.
+    pub async fn install(&self, formula: &Formula, build_from_source: bool, state: &InstallStateStore, tx: &Transaction, cancel: &CancellationToken) -> NitroResult<()> {
+        // A formula this machine can't run fails here, whether or not there's a bottle
+        let unmet = requirements::unmet_for_install(formula, &requirements::Host::detect());
+        if !unmet.is_empty() {
+            return Err(NitroError::Other(unmet.join("\n")));
+        }
+        self.link_dependencies_opt(formula, tx)?;
+
+        // Try binary installation first unless building from source. Only this formula
+        // falls back to a source build; its dependencies keep their bottles.
         if !build_from_source && !formula.binary_packages.is_empty() {
-            match self.install_binary(formula).await {
+            let savepoint = tx.savepoint();
+            match self.install_binary(formula, state, tx, cancel).await {
                 Ok(_) => return Ok(()),
+                Err(e @ (NitroError::Interrupted | NitroError::TimedOut(_) | NitroError::LinkConflict(_))) => return Err(e),
                 Err(e) => {
-                    eprintln!("Binary installation failed: {}. Falling back to source installation.", e);
-                    eprintln!("Note: Homebrew bottle downloads require authentication that is not yet implemented.");
+                    // Whatever the failed pour left in the prefix goes before building
+                    tx.rollback_to(savepoint)?;
+                    match e {
+                        NitroError::IncompatibleBottle(reason) => {
+                            eprintln!("Not using the bottle of {}: {}. Building from source instead.", formula.name, reason);
+                        }
+                        e if !Self::falls_back_to_source(&e) => {
+                            return Err(NitroError::InstallationFailed(format!(
+                                "Pouring the bottle of {} failed: {}\nInstall it with --build-from-source, or set install.retry_from_source to build failed pours automatically",
+                                formula.name, e
+                            )));
+                        }
+                        e => eprintln!("Pouring the bottle of {} failed: {}. Retrying it from source.", formula.name, e),
+                    }
                 }
             }
         }
 
         // Fall back to source installation
-        self.install_from_source(formula).await
+        self.install_from_source(formula, state, tx, cancel).await
+    }
+
+    /// Estimate the space installing `formulae` will take: the archives and their extracted
+    /// contents in the workspace directory, and the finished kegs in the cellar, plus
+    /// the copies kept in the download cache and, for poured bottles, the unpacked
+    /// bottle cache. Archive sizes come from HEAD requests; archives whose size the
+    /// server doesn't report count as zero.
+    pub async fn space_requirements(&self, formulae: &[&Formula], build_from_source: bool) -> Vec<SpaceRequirement> {
+        let mut download_bytes = 0;
+        let mut keg_bytes = 0;
+        let mut poured_bytes = 0;
+
+        for formula in formulae {
+            let poured = !build_from_source && self.has_bottle(formula);
+            let factor = if poured { disk::BOTTLE_EXPANSION_FACTOR } else { disk::SOURCE_EXPANSION_FACTOR };
+            let size = self.download_size(formula, build_from_source).await.unwrap_or(0);
+
+            download_bytes += size;
+            keg_bytes += size * factor;
+            if poured {
+                poured_bytes += size * factor;
+            }
+        }
+
+        let mut requirements = vec![
+            SpaceRequirement::new(workspace::root(&self.prefix), download_bytes + keg_bytes),
+            SpaceRequirement::new(&self.cellar, keg_bytes),
+        ];
+        if let Some(cache) = &self.download_cache {
+            requirements.push(SpaceRequirement::new(cache.dir(), download_bytes));
+        }
+        if let Some(unpacked) = Self::unpacked_bottles().filter(|_| poured_bytes > 0) {
+            requirements.push(SpaceRequirement::new(unpacked, poured_bytes));
+        }
+        requirements
+    }
+
+    /// Size of the archive installing `formula` would download (the bottle, or the source
+    /// tarball when building from source), if the server reports it.
+    pub async fn download_size(&self, formula: &Formula, build_from_source: bool) -> Option<u64> {
+        let url = match self.find_binary_package(formula) {
+            Some(pkg) if !build_from_source => &pkg.url,
+            _ => &formula.sources.first()?.url,
+        };
+
+        // Registry blobs answer an anonymous HEAD with a 401, like an anonymous GET
+        if let Some(blob) = crate::download::oci::BlobRef::parse(url) {
+            let oci = crate::download::oci::OciClient::new(self.downloader.clone());
+            return oci.blob_size(&blob, HEAD_TIMEOUT).await.ok().flatten();
+        }
+        match self.downloader.client().head(url).timeout(HEAD_TIMEOUT).send().await {
+            Ok(response) if response.status().is_success() => crate::download::content_length(&response),
+            _ => None,
+        }
+    }
+
+    /// This machine's bottle platform as `platform/arch`, matching the search index.
+    pub fn platform_tag() -> String {
+        format!("{}/{}", Self::get_platform(), Self::get_arch())
+    }
+
+    /// Whether a bottle is available for this platform, and this machine can run it.
+    pub fn has_bottle(&self, formula: &Formula) -> bool {
+        self.find_binary_package(formula).is_some() && Self::bottle_incompatibility().is_none()
     }
 
-    pub async fn uninstall(&self, package: &Package) -> NitroResult<()> {
+    /// Why this machine can't run any bottle, judged before downloading one. Only
+    /// Linux bottles have such limits: the glibc they were built against.
+    fn bottle_incompatibility() -> Option<String> {
+        if Self::get_platform() != "linux" {
+            return None;
+        }
+        glibc::host_incompatibility(glibc::host())
+    }
+
+    /// This machine's C libraries as a bottle sees them: a `gcc` installed in the
+    /// prefix supplies a newer libstdc++ than the system's to bottles that depend on it.
+    /// Its keg is the one the database has installed, or else its opt link's.
+    fn linux_libraries(&self) -> glibc::HostLibraries {
+        let mut host = glibc::host().clone();
+        let gcc = match self.installed_version("gcc") {
+            Some(version) => self.cellar.join("gcc").join(version),
+            None => self.prefix.join("opt/gcc"),
+        };
+        let prefix_libstdcxx = gcc.join("lib/gcc/current/libstdc++.so.6");
+        if let Ok(data) = std::fs::read(prefix_libstdcxx) {
+            host.glibcxx = host.glibcxx.max(glibc::newest_symbol_version(&data, "GLIBCXX_"));
+        }
+        host
+    }
+
+    fn find_binary_package<'a>(&self, formula: &'a Formula) -> Option<&'a super::formula::BinaryPackage> {
+        bottle_for(formula, &Self::platform_tag())
+    }
+
+    /// Download and verify the bottle of `formula` for `platform_tag` (`platform/arch`,
+    /// not necessarily this machine's) into `dir`, for seeding caches and mirrors.
+    /// A previously fetched file with the right checksum is reused.
+    pub async fn fetch_bottle(&self, formula: &Formula, platform_tag: &str, dir: &Path) -> NitroResult<PathBuf> {
+        let bottle = bottle_for(formula, platform_tag)
+            .ok_or_else(|| NitroError::Other(format!("No bottle of {} for {}", formula.name, platform_tag)))?;
+
+        self.fetch_verified(&bottle.url, &[], &bottle.sha256, &dir.join(bottle_filename(formula, platform_tag))).await
+    }
+
+    /// Download and verify the source archive and resources of `formula` into `dir`,
+    /// for warming the cache of a machine that will build it. Git sources and sources
+    /// without a checksum can't be verified, so they aren't fetched.
+    pub async fn fetch_sources(&self, formula: &Formula, dir: &Path) -> NitroResult<Vec<PathBuf>> {
+        let source = formula.sources.first()
+            .ok_or_else(|| NitroError::Other(format!("{} has no source to fetch", formula.name)))?;
+        let mirrors: Vec<&str> = source.mirror.as_deref().into_iter().collect();
+        let artifacts = std::iter::once((source.url.as_str(), mirrors.as_slice(), source.sha256.as_str()))
+            .chain(formula.resources.iter().map(|r| (r.url.as_str(), &[][..], r.sha256.as_str())));
+
+        let mut paths = Vec::new();
+        for (url, mirrors, sha256) in artifacts {
+            if url.ends_with(".git") || sha256.is_empty() {
+                return Err(NitroError::Other(format!(
+                    "{} of {} has no checksum, so it can't be cached", url, formula.name
+                )));
+            }
+            paths.push(self.fetch_verified(url, mirrors, sha256, &dir.join(source_filename(formula, url))).await?);
+        }
+        Ok(paths)
+    }
+
+    /// Download `url` to `dest` and verify it, unless `dest` already holds a copy with
+    /// the right checksum.
+    async fn fetch_verified(&self, url: &str, mirrors: &[&str], sha256: &str, dest: &Path) -> NitroResult<PathBuf> {
+        if dest.exists() && Self::verify_checksum(dest, sha256).is_ok() {
+            crate::metrics::global().record_cache_lookup(true);
+            return Ok(dest.to_path_buf());
+        }
+        crate::metrics::global().record_cache_lookup(false);
+
+        if let Some(dir) = dest.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        // Registry blob URLs (ghcr.io bottles) get their pull token in `download_file`
+        self.downloader.download_with_mirrors(url, mirrors, dest).await?;
+        if let Err(e) = Self::verify_checksum(dest, sha256) {
+            let _ = std::fs::remove_file(dest);
+            return Err(e);
+        }
+        Ok(dest.to_path_buf())
+    }
+
+    /// Copy the fetched artifact `file_name` from the cache `dir` to `dest`, if it's
+    /// there and matches `sha256`.
+    fn restore_fetched(dir: NitroResult<PathBuf>, file_name: &str, sha256: &str, dest: &Path) -> bool {
+        let Ok(dir) = dir else {
+            return false;
+        };
+        let cached = dir.join(file_name);
+        if sha256.is_empty() || !cached.is_file() || Self::verify_checksum(&cached, sha256).is_err() {
+            return false;
+        }
+        let restored = std::fs::copy(&cached, dest).is_ok();
+        if restored {
+            crate::metrics::global().record_cache_lookup(true);
+        }
+        restored
+    }
+
+    /// Link an already staged keg into the prefix.
+    pub async fn link(&self, formula: &Formula, tx: &Transaction) -> NitroResult<()> {
+        self.create_symlinks(&formula.name, &formula.pkg_version(), tx).await?;
+        Ok(())
+    }
+
+    /// Undo a partially completed install: unlink it and remove its keg.
+    pub async fn rollback(&self, formula: &Formula) -> NitroResult<()> {
+        let keg = self.get_keg_path(formula);
+        self.unlink_keg(&keg)?;
+        if keg.exists() {
+            fs::remove_dir_all(&keg).await?;
+        }
+
+        // Drop the formula directory too if that keg was the only thing in it
+        let formula_dir = self.get_install_path(&formula.name);
+        if formula_dir.exists() && std::fs::read_dir(&formula_dir)?.next().is_none() {
+            fs::remove_dir(&formula_dir).await?;
+        }
+
+        Ok(())
+    }
+
+    pub fn get_keg_path(&self, formula: &Formula) -> PathBuf {
+        self.cellar.join(&formula.name).join(formula.pkg_version())
+    }
+
+    /// Remove `package`'s kegs and the links into them. A keg with a manifest loses
+    /// exactly what it lists, `manifest` standing in for the current keg's if that's gone
+    /// missing; one installed before manifests existed is removed whole. Files nobody
+    /// recorded are left in place with a warning.
+    pub async fn uninstall(&self, package: &Package, manifest: Option<&Manifest>) -> NitroResult<()> {
         let install_path = package.install_path.as_ref()
             .ok_or_else(|| NitroError::Other("Package install path not found".into()))?;
 
-        // Remove symlinks
-        self.remove_symlinks(&package.name).await?;
-
-        // Remove installation directory
+        let mut kept = Vec::new();
+        if let Some(manifest) = manifest {
+            kept.extend(manifest.remove()?);
+        }
         if install_path.exists() {
-            fs::remove_dir_all(install_path).await?;
+            for entry in std::fs::read_dir(install_path)? {
+                let keg = entry?.path();
+                // The database's manifest has dealt with its keg, files it left included
+                if !keg.is_dir() || manifest.is_some_and(|m| m.keg == keg) {
+                    continue;
+                }
+                match Manifest::read(&keg) {
+                    Some(manifest) => kept.extend(manifest.remove()?),
+                    None => {
+                        self.unlink_keg(&keg)?;
+                        fs::remove_dir_all(&keg).await?;
+                    }
+                }
+            }
+        }
+        // An opt link given to a keg that predates them isn't in its manifest
+        let opt = self.prefix.join("opt").join(&package.name);
+        if std::fs::read_link(&opt).is_ok_and(|target| target.starts_with(install_path)) {
+            std::fs::remove_file(&opt)?;
+        }
+        for path in &kept {
+            eprintln!("Warning: leaving {}, which the install of {} didn't create", path.display(), package.name);
+        }
+
+        // The formula directory goes too once nothing is left in it
+        if install_path.exists() && std::fs::read_dir(install_path)?.next().is_none() {
+            fs::remove_dir(install_path).await?;
         }
 
         Ok(())
     }
 
+    pub fn cellar(&self) -> &Path {
+        &self.cellar
+    }
+
+    /// The prefix this installer links into, which is this machine's unless it was
+    /// made with [`Installer::with_prefix`].
+    pub fn install_prefix(&self) -> &Path {
+        &self.prefix
+    }
+
     pub fn get_install_path(&self, name: &str) -> PathBuf {
         self.cellar.join(name)
     }
 
-    async fn install_binary(&self, formula: &Formula) -> NitroResult<()> {
+    /// A fresh workspace for installing `formula`.
+    fn workspace(&self, formula: &Formula) -> NitroResult<Workspace> {
+        Ok(Workspace::create(&workspace::root(&self.prefix), &formula.name, &formula.pkg_version())?)
+    }
+
+    /// Clean up after an install step that ran in `workspace`. A failed step's workspace
+    /// is kept for debugging and the error says where; failures callers act on (an
+    /// interrupt, a checksum to override, a bottle to skip, files in the way of linking)
+    /// pass through untouched. A build that timed out keeps its workspace too.
+    fn finish_workspace(workspace: Workspace, result: NitroResult<()>) -> NitroResult<()> {
+        match result {
+            Err(e @ (NitroError::Interrupted | NitroError::ChecksumMismatch { .. } | NitroError::IncompatibleBottle(_) | NitroError::LinkConflict(_))) => Err(e),
+            Err(NitroError::TimedOut(detail)) => {
+                let path = workspace.keep();
+                Err(NitroError::TimedOut(format!("{}\nBuild files were kept in {}", detail, path.display())))
+            }
+            Err(e) => {
+                let path = workspace.keep();
+                Err(NitroError::InstallationFailed(format!("{}\nBuild files were kept in {}", e, path.display())))
+            }
+            Ok(()) => {
+                if workspace::keeps_all() {
+                    eprintln!("Build files were kept in {}", workspace.keep().display());
+                }
+                Ok(())
+            }
+        }
+    }
+
+    async fn install_binary(&self, formula: &Formula, state: &InstallStateStore, tx: &Transaction, cancel: &CancellationToken) -> NitroResult<()> {
+        let workspace = self.workspace(formula)?;
+        let result = self.install_binary_in(formula, state, workspace.path(), tx, cancel).await;
+        match result {
+            // The source build that follows is what's worth debugging, so the pour's
+            // files go unless everything is being kept
+            Err(e) if Self::falls_back_to_source(&e) => {
+                if workspace::keeps_all() {
+                    workspace.keep();
+                }
+                Err(e)
+            }
+            result => Self::finish_workspace(workspace, result),
+        }
+    }
+
+    /// Whether `install` builds from source after a pour fails with `e`.
+    fn falls_back_to_source(e: &NitroError) -> bool {
+        match e {
+            NitroError::Interrupted | NitroError::TimedOut(_) => false,
+            // A source build would run into the same files
+            NitroError::LinkConflict(_) => false,
+            NitroError::IncompatibleBottle(_) => true,
+            _ => Self::retries_from_source(),
+        }
+    }
+
+    async fn install_binary_in(&self, formula: &Formula, state: &InstallStateStore, workspace: &Path, tx: &Transaction, cancel: &CancellationToken) -> NitroResult<()> {
         eprintln!("DEBUG: Attempting binary installation for {}", formula.name);
         
         // Get platform-specific binary package
-        let platform = self.get_platform();
-        let arch = self.get_arch();
+        let platform = Self::get_platform();
+        let arch = Self::get_arch();
         eprintln!("DEBUG: Looking for bottle for {}/{}", platform, arch);
         
-        let binary_pkg = formula.binary_packages.iter()
-            .find(|pkg| pkg.platform == platform && pkg.arch == arch)
-            .ok_or_else(|| NitroError::Other(format!(
-                "No binary package available for {}/{}", platform, arch
+        let binary_pkg = self.find_binary_package(formula)
+            .ok_or_else(|| NitroError::IncompatibleBottle(format!(
+                "there is no bottle for {}/{}", platform, arch
             )))?;
+        if let Some(reason) = Self::bottle_incompatibility() {
+            return Err(NitroError::IncompatibleBottle(reason));
+        }
 
         eprintln!("DEBUG: Found bottle, downloading from: {}", binary_pkg.url);
 
-        // Download binary package (bottle)
-        let temp_dir = tempfile::tempdir()?;
-        let download_path = temp_dir.path().join("bottle.tar.gz");
-        
-        // For Homebrew bottles from ghcr.io, we need to handle the download specially
-        if binary_pkg.url.starts_with("https://ghcr.io/") {
-            // Download the bottle manifest first to get the actual download URL
-            self.download_bottle(&binary_pkg.url, &download_path).await?;
+        // A bottle poured before is cloned from its unpacked copy
+        let extract_dir = workspace.join("extract");
+        if Self::clone_unpacked(&binary_pkg.sha256, &extract_dir) {
+            tracing::debug!("Cloned the unpacked bottle of {} into {}", formula.name, extract_dir.display());
+            state.set_source(&formula.name, InstallSource {
+                method: InstallMethod::Bottle,
+                url: binary_pkg.url.clone(),
+                sha256: Some(binary_pkg.sha256.clone()),
+                fetched_from: "unpacked bottle".to_string(),
+            })?;
+            state.advance(&formula.name, InstallPhase::Fetched)?;
+            state.advance(&formula.name, InstallPhase::Verified)?;
+            cancel.check()?;
         } else {
-            self.downloader.download_file(&binary_pkg.url, &download_path).await?;
+            self.fetch_and_extract_bottle(formula, binary_pkg, state, workspace, &extract_dir, cancel).await?;
+            Self::keep_unpacked(&binary_pkg.sha256, &extract_dir);
         }
 
-        // Verify checksum
-        self.verify_checksum(&download_path, &binary_pkg.sha256)?;
-
-        // Extract bottle to temporary location first
-        let extract_dir = temp_dir.path().join("extract");
-        std::fs::create_dir_all(&extract_dir)?;
-        self.extract_tarball(&download_path, &extract_dir)?;
+        // A bottle built against a newer glibc than ours installs fine but won't load
+        if platform == "linux" {
+            let needs = glibc::bottle_needs(&extract_dir);
+            if let Some(reason) = glibc::bottle_incompatibility(&needs, &self.linux_libraries()) {
+                return Err(NitroError::IncompatibleBottle(reason));
+            }
+        }
 
         // Bottles have a specific structure - they extract to a path like:
         // micro/2.0.14/bin/micro
         // We need to move this to our cellar: /usr/local/Cellar/micro/2.0.14/
-        let install_path = self.cellar.join(&formula.name).join(&formula.version);
+        let install_path = self.cellar.join(&formula.name).join(formula.pkg_version());
         
         // Find the extracted directory (usually formula_name/version/)
-        let expected_dir = extract_dir.join(&formula.name).join(&formula.version);
+        let expected_dir = extract_dir.join(&formula.name).join(formula.pkg_version());
         if expected_dir.exists() {
             eprintln!("DEBUG: Moving bottle contents from {} to {}", expected_dir.display(), install_path.display());
-            
-            // Remove existing installation if present
-            if install_path.exists() {
-                std::fs::remove_dir_all(&install_path)?;
-            }
-            
-            // Create parent directory
-            if let Some(parent) = install_path.parent() {
-                std::fs::create_dir_all(parent)?;
-            }
-            
-            // Move the directory
-            std::fs::rename(&expected_dir, &install_path)?;
+            Self::stage_keg(&expected_dir, &install_path, tx)?;
         } else {
             // Fallback: look for any directory in extract_dir
             eprintln!("DEBUG: Expected bottle structure not found, searching for content...");
@@ -148,16 +886,7 @@ impl Installer {
                         if version_entry.file_type()?.is_dir() {
                             let source = version_entry.path();
                             eprintln!("DEBUG: Moving {} to {}", source.display(), install_path.display());
-                            
-                            if install_path.exists() {
-                                std::fs::remove_dir_all(&install_path)?;
-                            }
-                            
-                            if let Some(parent) = install_path.parent() {
-                                std::fs::create_dir_all(parent)?;
-                            }
-                            
-                            std::fs::rename(&source, &install_path)?;
+                            Self::stage_keg(&source, &install_path, tx)?;
                             found = true;
                             break;
                         }
@@ -173,103 +902,438 @@ impl Installer {
                 return Err(NitroError::Other("Could not find bottle contents after extraction".into()));
             }
         }
+        self.rewrite_shebangs(formula)?;
+        self.wrap_python_scripts(formula)?;
+        write_keg_marker(&self.get_keg_path(formula))?;
+        state.advance(&formula.name, InstallPhase::Staged)?;
+        cancel.check()?;
 
         // Create symlinks
-        self.create_symlinks(&formula.name, &formula.version).await?;
+        self.create_symlinks(&formula.name, &formula.pkg_version(), tx).await?;
+        state.advance(&formula.name, InstallPhase::Linked)?;
+
+        Ok(())
+    }
+
+    /// Download the bottle `binary_pkg` of `formula` into `workspace` (or take it from a
+    /// cache), verify it and extract it to `extract_dir`.
+    async fn fetch_and_extract_bottle(&self, formula: &Formula, binary_pkg: &super::formula::BinaryPackage, state: &InstallStateStore, workspace: &Path, extract_dir: &Path, cancel: &CancellationToken) -> NitroResult<()> {
+        let download_path = workspace.join("bottle.tar.gz");
+        
+        let fetched_from = if Self::restore_fetched(bottle_cache_dir(), &bottle_filename(formula, &Self::platform_tag()), &binary_pkg.sha256, &download_path) {
+            tracing::debug!("Using the fetched bottle of {} from the cache", formula.name);
+            "fetch cache"
+        } else if let Some(cache) = &self.download_cache {
+            cancel.run(cache.fetch_digest(&binary_pkg.url, &binary_pkg.sha256, &download_path)).await?;
+            "download cache"
+        } else if let Some(blob) = crate::download::oci::BlobRef::parse(&binary_pkg.url) {
+            self.download_bottle(&blob, &formula.pkg_version(), &download_path, cancel).await?;
+            "download"
+        } else {
+            self.downloader.with_cancellation(cancel.clone()).download_file(&binary_pkg.url, &download_path).await?;
+            "download"
+        };
+        state.set_source(&formula.name, InstallSource {
+            method: InstallMethod::Bottle,
+            url: binary_pkg.url.clone(),
+            sha256: Some(binary_pkg.sha256.clone()),
+            fetched_from: fetched_from.to_string(),
+        })?;
+        state.advance(&formula.name, InstallPhase::Fetched)?;
 
+        // Verify checksum
+        Self::verify_checksum(&download_path, &binary_pkg.sha256)?;
+        state.advance(&formula.name, InstallPhase::Verified)?;
+        cancel.check()?;
+
+        // Extract bottle to temporary location first
+        std::fs::create_dir_all(extract_dir)?;
+        Self::extract_tarball(&download_path, extract_dir, cancel, &self.report_extraction(&formula.name))?;
         Ok(())
     }
 
-    async fn install_from_source(&self, formula: &Formula) -> NitroResult<()> {
+    async fn install_from_source(&self, formula: &Formula, state: &InstallStateStore, tx: &Transaction, cancel: &CancellationToken) -> NitroResult<()> {
         eprintln!("DEBUG: Installing {} from source", formula.name);
         
         if formula.sources.is_empty() {
             return Err(NitroError::Other("No source URL found".into()));
         }
 
+        // A cached build for this machine skips the build, and everything it needs
+        if let Some(cache) = self.keg_cache() {
+            let key = self.keg_cache_key(formula);
+            if cache.get(&key).is_some() {
+                match self.restore_cached_build(formula, state, &cache, &key, tx, cancel).await {
+                    Ok(()) => return Ok(()),
+                    Err(e @ (NitroError::Interrupted | NitroError::TimedOut(_))) => return Err(e),
+                    Err(e) => eprintln!("Warning: could not use the cached build of {}: {}. Building it again.", formula.name, e),
+                }
+            }
+        }
+
+        // Fail before downloading anything rather than halfway through configure
+        let unmet = requirements::unmet_for_source_build(formula, &requirements::Host::detect());
+        if !unmet.is_empty() {
+            return Err(NitroError::Other(unmet.join("\n")));
+        }
+        let missing = self.missing_build_tools(formula);
+        if !missing.is_empty() {
+            let programs: Vec<&str> = missing.iter().map(|t| t.program).collect();
+            return Err(NitroError::Other(format!(
+                "Building {} from source needs {}, which could not be found; {}",
+                formula.name, programs.join(", "), toolchain::install_hint(&missing)
+            )));
+        }
+
+        let workspace = self.workspace(formula)?;
+        let result = self.build_from_source(formula, state, workspace.path(), tx, cancel).await;
+        Self::finish_workspace(workspace, result)
+    }
+
+    async fn build_from_source(&self, formula: &Formula, state: &InstallStateStore, workspace: &Path, tx: &Transaction, cancel: &CancellationToken) -> NitroResult<()> {
         let source = &formula.sources[0];
         eprintln!("DEBUG: Source URL: {}", source.url);
-        
-        // Download source
-        let temp_dir = tempfile::tempdir()?;
-        
+
         // Determine file extension from URL
-        let file_name = source.url.split('/').last().unwrap_or("source.tar.gz");
-        let download_path = temp_dir.path().join(file_name);
+        let file_name = source.url.split('/').next_back().unwrap_or("source.tar.gz");
+        let download_path = workspace.join(file_name);
         eprintln!("DEBUG: Download path: {}", download_path.display());
         
         // Extract source (if it's an archive)
         let extracted_dir = if source.url.ends_with(".git") {
             eprintln!("DEBUG: Cloning git repository: {}", source.url);
             // For git URLs, we need to clone the repository
-            let clone_dir = temp_dir.path().join("source");
-            let output = Command::new("git")
-                .args(&["clone", "--depth", "1", &source.url, clone_dir.to_str().unwrap()])
-                .output()?;
-            
-            if !output.status.success() {
-                return Err(NitroError::Other(format!(
-                    "Failed to clone repository: {}",
-                    String::from_utf8_lossy(&output.stderr)
-                )));
+            let clone_dir = workspace.join("source");
+            cancel.run(async { Ok::<_, NitroError>(super::git::clone(&source.url, &clone_dir, Some(1), source.tag.as_deref(), |_| {}).await?) }).await?;
+            // A moved tag is caught by the formula's revision, as a changed tarball is by its checksum
+            if let (Some(tag), Some(revision)) = (&source.tag, &source.revision) {
+                let head = super::git::head(&clone_dir).await?;
+                if head != *revision {
+                    return Err(NitroError::InstallationFailed(format!(
+                        "tag {} of {} is at {}, but the formula expects {}", tag, source.url, head, revision
+                    )));
+                }
             }
             
             // No checksum verification for git repos
+            state.set_source(&formula.name, InstallSource {
+                method: InstallMethod::Source,
+                url: source.url.clone(),
+                sha256: None,
+                fetched_from: "git clone".to_string(),
+            })?;
+            state.advance(&formula.name, InstallPhase::Fetched)?;
             clone_dir
         } else {
-            self.downloader.download_file(&source.url, &download_path).await?;
+            let mirrors: Vec<&str> = source.mirror.as_deref().into_iter().collect();
+            let fetched = Self::restore_fetched(source_cache_dir(), &source_filename(formula, &source.url), &source.sha256, &download_path);
+            let cached = !fetched && match &self.download_cache {
+                Some(cache) if !source.sha256.is_empty() => {
+                    cancel.run(cache.fetch_digest(&source.url, &source.sha256, &download_path)).await.is_ok()
+                }
+                _ => false,
+            };
+            if !fetched && !cached {
+                self.downloader.with_cancellation(cancel.clone()).download_with_mirrors(&source.url, &mirrors, &download_path).await?;
+            }
+            let fetched_from = if fetched { "fetch cache" } else if cached { "download cache" } else { "download" };
+            state.set_source(&formula.name, InstallSource {
+                method: InstallMethod::Source,
+                url: source.url.clone(),
+                sha256: Some(source.sha256.clone()).filter(|sha256| !sha256.is_empty()),
+                fetched_from: fetched_from.to_string(),
+            })?;
+            state.advance(&formula.name, InstallPhase::Fetched)?;
             
             // Verify checksum only if provided
             if !source.sha256.is_empty() {
-                self.verify_checksum(&download_path, &source.sha256)?;
+                Self::verify_checksum(&download_path, &source.sha256)?;
             }
+            state.advance(&formula.name, InstallPhase::Verified)?;
             
-            let build_dir = temp_dir.path().join("build");
+            let build_dir = workspace.join("build");
             std::fs::create_dir_all(&build_dir)?;
             
             if download_path.extension().and_then(|s| s.to_str()) == Some("pem") ||
                download_path.extension().and_then(|s| s.to_str()) == Some("txt") ||
-               download_path.extension().and_then(|s| s.to_str()) == Some("patch") {
+               download_path.extension().and_then(|s| s.to_str()) == Some("patch") ||
+               download_path.extension().and_then(|s| s.to_str()) == Some("gem") {
                 // Handle non-archive files (like ca-certificates .pem file)
                 std::fs::copy(&download_path, build_dir.join(file_name))?;
                 build_dir
             } else {
-                self.extract_tarball(&download_path, &build_dir)?;
+                Self::extract_tarball(&download_path, &build_dir, cancel, &self.report_extraction(&formula.name))?;
                 // Find extracted directory
                 self.find_extracted_dir(&build_dir)?
             }
         };
 
-        // Run install script
-        if let Some(install_script) = &formula.install_script {
-            self.run_install_script(&extracted_dir, install_script, formula).await?;
-        } else {
-            // Default configure, make, make install
-            self.run_default_install(&extracted_dir, formula).await?;
+        cancel.check()?;
+
+        // The build installs straight into the keg, so whatever was there goes aside first
+        let keg = self.get_keg_path(formula);
+        tx.move_aside(&keg)?;
+        if let Some(parent) = keg.parent() {
+            tx.create_dir_all(parent)?;
+        }
+        tx.created(&keg);
+
+        let mut env = self.source_build_env(formula);
+        match debug_build::shell_at(&formula.name) {
+            Some(ShellAt::Start) => self.debug_shell(formula, &extracted_dir, &env)?,
+            Some(ShellAt::Failure) => {
+                if let Err(e) = self.run_install_steps(formula, &extracted_dir, workspace, &mut env, cancel).await {
+                    if cancel.is_cancelled() {
+                        return Err(e);
+                    }
+                    eprintln!("Building {} failed: {}", formula.name, e);
+                    self.debug_shell(formula, &extracted_dir, &env)?;
+                }
+            }
+            None => self.run_install_steps(formula, &extracted_dir, workspace, &mut env, cancel).await?,
+        }
+        self.rewrite_shebangs(formula)?;
+        self.wrap_python_scripts(formula)?;
+        write_keg_marker(&self.get_keg_path(formula))?;
+        state.advance(&formula.name, InstallPhase::Staged)?;
+        if debug_build::shell_at(&formula.name).is_none() {
+            self.cache_build(formula);
         }
+        cancel.check()?;
 
         // Create symlinks
-        self.create_symlinks(&formula.name, &formula.version).await?;
+        self.create_symlinks(&formula.name, &formula.pkg_version(), tx).await?;
+        state.advance(&formula.name, InstallPhase::Linked)?;
 
         Ok(())
     }
 
-    async fn run_install_script(&self, build_dir: &Path, script: &str, formula: &Formula) -> Result<()> {
-        let install_path = self.cellar.join(&formula.name).join(&formula.version);
-        std::fs::create_dir_all(&install_path)?;
+    /// Whether a failed bottle pour is retried from source (`install.retry_from_source`).
+    fn retries_from_source() -> bool {
+        crate::core::config::Config::load().map_or(true, |config| config.install.retry_from_source)
+    }
+
+    /// Where poured bottles are kept unpacked, when `install.clone_bottles` is on.
+    fn unpacked_bottles() -> Option<PathBuf> {
+        if !crate::core::config::Config::load().map_or(true, |config| config.install.clone_bottles) {
+            return None;
+        }
+        unpacked_bottle_dir().ok()
+    }
 
-        // Set up environment variables
-        std::env::set_var("PREFIX", &install_path);
-        std::env::set_var("HOMEBREW_PREFIX", &self.prefix);
+    /// The unpacked copy of the bottle with checksum `sha256`, when
+    /// `install.clone_bottles` is on.
+    fn unpacked_bottle(sha256: &str) -> Option<PathBuf> {
+        Self::unpacked_bottles().map(|dir| dir.join(sha256))
+    }
+
+    /// Clone the unpacked copy of the bottle with checksum `sha256` to `extract_dir`,
+    /// if there is one on the same filesystem.
+    fn clone_unpacked(sha256: &str, extract_dir: &Path) -> bool {
+        let Some(unpacked) = Self::unpacked_bottle(sha256).filter(|dir| dir.is_dir()) else {
+            return false;
+        };
+        match super::clone::clone_tree(&unpacked, extract_dir) {
+            Ok(()) => true,
+            Err(e) => {
+                tracing::debug!("Could not clone {}: {}", unpacked.display(), e);
+                false
+            }
+        }
+    }
+
+    /// Keep a clone of the bottle with checksum `sha256` just extracted to `extract_dir`,
+    /// for `clone_unpacked`. Not managing to only costs the next install its shortcut.
+    fn keep_unpacked(sha256: &str, extract_dir: &Path) {
+        let Some(unpacked) = Self::unpacked_bottle(sha256).filter(|dir| !dir.exists()) else {
+            return;
+        };
+        // Cloned under a temporary name first, so a half-made copy is never cloned
+        let partial = unpacked.with_extension(format!("partial-{}", std::process::id()));
+        let result = unpacked.parent().map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| super::clone::clone_tree(extract_dir, &partial))
+            .and_then(|_| std::fs::rename(&partial, &unpacked));
+        if let Err(e) = result {
+            tracing::debug!("Could not keep the unpacked bottle at {}: {}", unpacked.display(), e);
+            let _ = std::fs::remove_dir_all(&partial);
+        }
+    }
+
+    /// The cache of source builds, when `install.cache_source_builds` is on.
+    fn keg_cache(&self) -> Option<KegCache> {
+        let config = crate::core::config::Config::load().ok()?.install;
+        if !config.cache_source_builds {
+            return None;
+        }
+        config.keg_cache_dir.or_else(KegCache::default_dir).map(KegCache::new)
+    }
+
+    /// The keg cache key for building `formula` here: this platform and OS release,
+    /// the prefix, and the versions of the runtime dependencies it links against.
+    fn keg_cache_key(&self, formula: &Formula) -> String {
+        let release = if Self::get_platform() == "linux" {
+            glibc::host().glibc.as_ref().map(|v| format!("glibc {}", v))
+        } else {
+            requirements::Host::detect().macos.map(|v| format!("macOS {}", v))
+        };
+        let platform = format!("{} {}", Self::platform_tag(), release.unwrap_or_default());
+
+        let dependencies: Vec<String> = formula.dependencies.iter()
+            .filter(|d| d.is_runtime())
+            .map(|d| format!("{}={}", d.name, self.installed_version(&d.name).unwrap_or_default()))
+            .collect();
+        KegCache::key(formula, &platform, &self.prefix, &dependencies)
+    }
+
+    /// Install `formula` from its cached build under `key` rather than building it.
+    async fn restore_cached_build(&self, formula: &Formula, state: &InstallStateStore, cache: &KegCache, key: &str, tx: &Transaction, cancel: &CancellationToken) -> NitroResult<()> {
+        eprintln!("Using the cached build of {} {}", formula.name, formula.pkg_version());
+        let workspace = self.workspace(formula)?;
+        let unpacked = cache.unpack(key, formula, workspace.path())?;
+        state.set_source(&formula.name, InstallSource {
+            method: InstallMethod::CachedBuild,
+            url: cache.path(key).display().to_string(),
+            sha256: None,
+            fetched_from: "keg cache".to_string(),
+        })?;
+        state.advance(&formula.name, InstallPhase::Fetched)?;
+        state.advance(&formula.name, InstallPhase::Verified)?;
+        cancel.check()?;
+
+        let keg = self.get_keg_path(formula);
+        Self::stage_keg(&unpacked, &keg, tx)?;
+        write_keg_marker(&keg)?;
+        state.advance(&formula.name, InstallPhase::Staged)?;
+        cancel.check()?;
+
+        self.create_symlinks(&formula.name, &formula.pkg_version(), tx).await?;
+        state.advance(&formula.name, InstallPhase::Linked)?;
+        Ok(())
+    }
+
+    /// Pack the freshly built keg of `formula` into the keg cache, if it's enabled.
+    /// Failing to is only worth a warning; the install itself succeeded.
+    fn cache_build(&self, formula: &Formula) {
+        let Some(cache) = self.keg_cache() else {
+            return;
+        };
+        match cache.store(&self.keg_cache_key(formula), formula, &self.get_keg_path(formula)) {
+            Ok(path) => tracing::info!("Cached the build of {} at {}", formula.name, path.display()),
+            Err(e) => eprintln!("Warning: could not cache the build of {}: {}", formula.name, e),
+        }
+    }
+
+    /// Run the formula's install steps in `build_dir`: its language's installer, its
+    /// install script, or `./configure && make install` without one.
+    async fn run_install_steps(&self, formula: &Formula, build_dir: &Path, workspace: &Path, env: &mut BuildEnv, cancel: &CancellationToken) -> NitroResult<()> {
+        let language = formula.install_script.as_deref().and_then(LanguageInstaller::detect);
+        if let Some(language) = language {
+            let resources = self.fetch_resources(formula, &workspace.join("resources"), cancel).await?;
+            cancel.check()?;
+            let interpreter = super::language::python_interpreter(&self.prefix, formula);
+            let entry_points = language.install(&self.get_keg_path(formula), build_dir, &resources, &interpreter, cancel)?;
+            tracing::info!("Installed {} with {} resource(s), exposing {}", formula.name, resources.len(), entry_points.join(", "));
+        } else if let Some(install_script) = &formula.install_script {
+            let resources = self.fetch_resources(formula, &workspace.join("resources"), cancel).await?;
+            cancel.check()?;
+            self.run_install_script(build_dir, install_script, formula, &resources, env, cancel).await?;
+        } else {
+            self.run_default_install(build_dir, formula, env, cancel).await?;
+        }
+        Ok(())
+    }
+
+    /// Hand the build directory to the user for `nitro debug-build`, failing if they
+    /// exit the shell non-zero or leave without installing anything into the keg.
+    fn debug_shell(&self, formula: &Formula, build_dir: &Path, env: &BuildEnv) -> NitroResult<()> {
+        let keg = self.get_keg_path(formula);
+        std::fs::create_dir_all(&keg)?;
+        debug_build::open_shell(build_dir, env, &keg)
+            .map_err(|e| NitroError::InstallationFailed(format!("Debugging the build of {} was given up: {}", formula.name, e)))?;
+        if !debug_build::keg_populated(&keg) {
+            return Err(NitroError::InstallationFailed(format!("Nothing was installed into {}", keg.display())));
+        }
+        Ok(())
+    }
+
+    /// Build tools that building `formula` from source needs but this machine lacks.
+    /// Tools nitro has linked count even when the link directory isn't on `PATH`.
+    pub fn missing_build_tools(&self, formula: &Formula) -> Vec<toolchain::BuildTool> {
+        toolchain::missing(&toolchain::required_tools(formula), std::slice::from_ref(&self.bin_dir))
+    }
+
+    /// Download and verify each of the formula's resources into `dir`.
+    async fn fetch_resources(&self, formula: &Formula, dir: &Path, cancel: &CancellationToken) -> Result<Vec<PathBuf>> {
+        let downloader = self.downloader.with_cancellation(cancel.clone());
+        let mut paths = Vec::new();
+        for resource in &formula.resources {
+            let file_name = resource.url.split('/').next_back().unwrap_or(&resource.name);
+            let path = dir.join(&resource.name).join(file_name);
+            std::fs::create_dir_all(dir.join(&resource.name))?;
+            if !Self::restore_fetched(source_cache_dir(), &source_filename(formula, &resource.url), &resource.sha256, &path) {
+                downloader.download_file(&resource.url, &path).await?;
+            }
+            cancel.check()?;
+            paths.push(path);
+        }
+
+        // Verified together, so large resources hash in parallel
+        let digests = super::checksum::sha256_files(&paths);
+        for (resource, digest) in formula.resources.iter().zip(digests) {
+            Self::check_digest(digest?, &resource.sha256)
+                .map_err(|e| NitroError::Other(format!("Resource {}: {}", resource.name, e)))?;
+        }
+        Ok(paths)
+    }
+
+    /// Run the `system` calls and environment changes of `script` in `build_dir`, and
+    /// stage the formula's fetched `resources` (in the formula's order) where it says to.
+    async fn run_install_script(&self, build_dir: &Path, script: &str, formula: &Formula, resources: &[PathBuf], env: &mut BuildEnv, cancel: &CancellationToken) -> Result<()> {
+        std::fs::create_dir_all(self.get_keg_path(formula))?;
 
         // Parse and execute install script commands
         // This is simplified - in reality we'd need a proper Ruby interpreter
+        let paths = PathContext::new(&self.prefix, formula);
+        // The blocks the current line is in, with the directory of those that change it
+        let mut blocks: Vec<Option<PathBuf>> = Vec::new();
         for line in script.lines() {
             let line = line.trim();
+            let cwd = blocks.iter().rev().flatten().next().cloned().unwrap_or_else(|| build_dir.to_path_buf());
+            if line == "end" || line.starts_with("end ") || line.starts_with("end.") {
+                blocks.pop();
+                continue;
+            }
+            if let Some(stage) = resource_stage(line) {
+                let (name, target, block) = match stage {
+                    ResourceStage::Into { resource, target } => (resource, Some(target), false),
+                    ResourceStage::Block { resource, target } => (resource, target, true),
+                };
+                let archive = formula.resources.iter().position(|r| r.name == name)
+                    .and_then(|i| resources.get(i))
+                    .ok_or_else(|| NitroError::Other(format!("{} has no resource named {}", formula.name, name)))?;
+                let target = match target {
+                    Some(target) => script_path(&target, build_dir, &cwd, &paths).ok_or_else(|| NitroError::Other(format!(
+                        "Can't tell where {} stages resource {}: {}", formula.name, name, target
+                    )))?,
+                    // Beside its archive, apart from every other resource's
+                    None => archive.with_file_name(format!("{}-staged", name.replace('/', "-"))),
+                };
+                Self::stage_resource(archive, &target, cancel)?;
+                if block {
+                    blocks.push(Some(target));
+                }
+                continue;
+            }
+            if opens_block(line) {
+                blocks.push(None);
+            }
+            if env.apply_statement(line, &paths) {
+                continue;
+            }
             if line.starts_with("system") {
                 // Extract command from system call
                 if let Some(cmd) = self.extract_system_command(line) {
-                    self.run_command(&cmd, build_dir)?;
+                    self.run_command(&paths.interpolate(&cmd), &cwd, env, cancel)?;
                 }
             }
         }
@@ -277,97 +1341,208 @@ impl Installer {
         Ok(())
     }
 
-    async fn run_default_install(&self, build_dir: &Path, formula: &Formula) -> Result<()> {
-        let install_path = self.cellar.join(&formula.name).join(&formula.version);
+    async fn run_default_install(&self, build_dir: &Path, formula: &Formula, env: &BuildEnv, cancel: &CancellationToken) -> Result<()> {
+        let install_path = self.cellar.join(&formula.name).join(formula.pkg_version());
         let prefix_arg = format!("--prefix={}", install_path.display());
 
         // Configure
         if build_dir.join("configure").exists() {
-            self.run_command(&format!("./configure {}", prefix_arg), build_dir)?;
+            self.run_command(&format!("./configure {}", prefix_arg), build_dir, env, cancel)?;
         }
 
         // Make
-        self.run_command("make", build_dir)?;
+        self.run_command("make", build_dir, env, cancel)?;
 
         // Make install
-        self.run_command("make install", build_dir)?;
+        self.run_command("make install", build_dir, env, cancel)?;
 
         Ok(())
     }
 
-    async fn create_symlinks(&self, name: &str, version: &str) -> Result<()> {
-        let install_path = self.cellar.join(name).join(version);
-        let bin_path = install_path.join("bin");
+    /// Point the keg's scripts at the interpreters of its runtime dependencies, as
+    /// Homebrew does, so `#!/usr/bin/env python3` doesn't pick up whatever is on PATH.
+    fn rewrite_shebangs(&self, formula: &Formula) -> Result<()> {
+        use super::shebang::{rewrite_keg_shebangs, ShebangContext};
 
-        if bin_path.exists() {
-            for entry in std::fs::read_dir(&bin_path)? {
-                let entry = entry?;
-                let file_name = entry.file_name();
-                let src = entry.path();
-                let dst = self.bin_dir.join(&file_name);
+        let config = crate::core::config::Config::load()?.install;
+        if !config.rewrite_shebangs {
+            return Ok(());
+        }
 
-                // Remove existing symlink if it exists
-                if dst.exists() {
-                    std::fs::remove_file(&dst)?;
-                }
+        let dependencies: Vec<String> = formula.dependencies.iter()
+            .filter(|d| d.is_runtime())
+            .map(|d| d.name.clone())
+            .collect();
+        let context = ShebangContext::for_dependencies(&self.prefix, &dependencies, config.interpreters);
+        let rewritten = rewrite_keg_shebangs(&self.get_keg_path(formula), &context)?;
+        if rewritten > 0 {
+            tracing::info!("Rewrote shebangs of {} script(s) in {}", rewritten, formula.name);
+        }
+        Ok(())
+    }
+
+    /// Point the keg's Python scripts at the packages it installed, when it depends on
+    /// a Python (see `language::write_python_env_scripts`).
+    fn wrap_python_scripts(&self, formula: &Formula) -> Result<()> {
+        use super::language::{write_python_env_scripts, Runtime, Toolchain};
 
-                // Create new symlink
-                std::os::unix::fs::symlink(&src, &dst)?;
+        let keg = self.get_keg_path(formula);
+        for toolchain in Toolchain::for_runtime(&self.prefix, formula).iter().filter(|t| t.runtime == Runtime::Python) {
+            let wrapped = write_python_env_scripts(&keg, toolchain)?;
+            if !wrapped.is_empty() {
+                tracing::info!("Wrapped {} with {}'s PYTHONPATH", wrapped.join(", "), toolchain.formula);
             }
         }
+        Ok(())
+    }
+
+    /// This machine's install prefix (`HOMEBREW_PREFIX`).
+    pub fn prefix() -> Result<PathBuf> {
+        Self::get_prefix()
+    }
+
+    /// Where executables get linked for this machine's prefix and config.
+    pub fn link_dir() -> Result<PathBuf> {
+        let config = crate::core::config::Config::load()?.install;
+        Ok(resolve_link_dir(&Self::get_prefix()?, &config))
+    }
 
+    /// Move the keg built or extracted at `staged` to `keg` in the Cellar, moving aside
+    /// whatever keg is there already.
+    fn stage_keg(staged: &Path, keg: &Path, tx: &Transaction) -> NitroResult<()> {
+        tx.move_aside(keg)?;
+        if let Some(parent) = keg.parent() {
+            tx.create_dir_all(parent)?;
+        }
+        tx.created(keg);
+        workspace::move_dir(staged, keg)?;
         Ok(())
     }
 
-    async fn remove_symlinks(&self, name: &str) -> Result<()> {
-        // Find and remove all symlinks pointing to this package
-        for entry in std::fs::read_dir(&self.bin_dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            
-            if path.is_symlink() {
-                if let Ok(target) = std::fs::read_link(&path) {
-                    if target.to_string_lossy().contains(&format!("Cellar/{}/", name)) {
-                        std::fs::remove_file(&path)?;
-                    }
-                }
+    /// Link the keg of `name` at `version` into the link directories, then write its
+    /// manifest: the keg's files and the links just made.
+    async fn create_symlinks(&self, name: &str, version: &str, tx: &Transaction) -> NitroResult<()> {
+        let install_path = self.cellar.join(name).join(version);
+        let planned = keg_links(&install_path, &self.prefix, &self.bin_dir)?;
+
+        // Nothing that isn't ours is replaced without --overwrite, and nothing is linked
+        // until that's settled, so a refused install leaves no partial links
+        let conflicts = link_conflicts(&install_path, &self.prefix, &self.bin_dir, name)?;
+        if !conflicts.is_empty() && !overwrites() {
+            let listed: Vec<String> = conflicts.iter()
+                .map(|c| format!("  {} ({})", c.link.display(), c.owner))
+                .collect();
+            return Err(NitroError::LinkConflict(format!(
+                "linking {} would overwrite:\n{}\nRemove them, or run again with --overwrite to replace them",
+                name, listed.join("\n")
+            )));
+        }
+
+        let mut links = Vec::new();
+        for (src, dst) in planned {
+            if let Some(conflict) = conflicts.iter().find(|c| c.link == dst) {
+                eprintln!("Overwriting {} ({})", dst.display(), conflict.owner);
+            }
+            if let Some(parent) = dst.parent() {
+                tx.create_dir_all(parent)?;
+            }
+            tx.symlink(&src, &dst)?;
+            links.push(dst);
+        }
+        links.extend(self.link_opt(name, &install_path, tx)?);
+
+        if links.iter().any(|link| link.parent() == Some(self.bin_dir.as_path())) {
+            let on_path = std::env::var_os("PATH")
+                .is_some_and(|path| std::env::split_paths(&path).any(|p| p == self.bin_dir));
+            if !on_path {
+                println!("Note: {} is not on your PATH; add `eval \"$(nitro shellenv)\"` to your shell profile", self.bin_dir.display());
             }
         }
 
+        Manifest::scan(name, version, &install_path, links)?.write()?;
         Ok(())
     }
 
-    fn verify_checksum(&self, file_path: &Path, expected_sha256: &str) -> Result<()> {
-        use sha2::{Sha256, Digest};
-        use std::io::Read;
+    /// Point `opt/<name>` at `keg`: the path that stays put across versions, which
+    /// services, rewritten shebangs and dependents refer to. A link into a Homebrew keg,
+    /// or anything that isn't a link, is left alone.
+    fn link_opt(&self, name: &str, keg: &Path, tx: &Transaction) -> NitroResult<Option<PathBuf>> {
+        let opt_dir = self.prefix.join("opt");
+        let opt = opt_dir.join(name);
+        match std::fs::read_link(&opt) {
+            // brew's links are relative: `../Cellar/<name>/<version>`
+            Ok(target) if keg_owner(&opt_dir.join(&target)) == Some(KegOwner::Homebrew) => return Ok(None),
+            Err(_) if opt.symlink_metadata().is_ok() => return Ok(None),
+            _ => {}
+        }
+        tx.create_dir_all(&opt_dir)?;
+        tx.symlink(keg, &opt)?;
+        Ok(Some(opt))
+    }
+
+    /// Give `formula`'s installed dependencies that predate opt links their `opt/<name>`,
+    /// the path its build and rewritten shebangs refer to them by.
+    fn link_dependencies_opt(&self, formula: &Formula, tx: &Transaction) -> NitroResult<()> {
+        for dep in formula.dependencies.iter().chain(&formula.build_dependencies) {
+            if self.prefix.join("opt").join(&dep.name).symlink_metadata().is_ok() {
+                continue;
+            }
+            let newest = std::fs::read_dir(self.cellar.join(&dep.name)).into_iter().flatten().flatten()
+                .map(|entry| entry.path())
+                .filter(|keg| keg.is_dir())
+                .max_by_key(|keg| super::version::PkgVersion::parse(&keg.file_name().unwrap_or_default().to_string_lossy()));
+            if let Some(keg) = newest {
+                self.link_opt(&dep.name, &keg, tx)?;
+            }
+        }
+        Ok(())
+    }
 
-        let mut file = std::fs::File::open(file_path)?;
-        let mut hasher = Sha256::new();
-        let mut buffer = [0; 8192];
+    /// Remove the links into `keg` that are still in place, e.g. the old version's
+    /// commands the new version no longer has, and return them. They're the links its
+    /// manifest recorded, or for a keg from before manifests, the ones linking it would
+    /// make; nothing else in the prefix is looked at.
+    pub fn unlink_keg(&self, keg: &Path) -> Result<Vec<PathBuf>> {
+        let mut candidates = match Manifest::read(keg) {
+            Some(manifest) => manifest.links,
+            None => keg_links(keg, &self.prefix, &self.bin_dir)?.into_iter().map(|(_, link)| link).collect(),
+        };
+        if let Some(name) = keg.parent().and_then(Path::file_name) {
+            candidates.push(self.prefix.join("opt").join(name));
+        }
 
-        loop {
-            let n = file.read(&mut buffer)?;
-            if n == 0 {
-                break;
+        let mut removed = Vec::new();
+        for link in candidates {
+            if std::fs::read_link(&link).is_ok_and(|target| target.starts_with(keg)) && !removed.contains(&link) {
+                std::fs::remove_file(&link)?;
+                removed.push(link);
             }
-            hasher.update(&buffer[..n]);
         }
+        Ok(removed)
+    }
 
-        let result = hasher.finalize();
-        let calculated = hex::encode(result);
+    /// Fail unless the SHA-256 of `file_path` is `expected_sha256`.
+    pub fn verify_checksum(file_path: &Path, expected_sha256: &str) -> NitroResult<()> {
+        Self::check_digest(super::checksum::sha256_file(file_path)?, expected_sha256)
+    }
 
+    fn check_digest(calculated: String, expected_sha256: &str) -> NitroResult<()> {
         if calculated != expected_sha256 {
-            return Err(NitroError::Other(
-                format!("Checksum mismatch: expected {}, got {}", expected_sha256, calculated)
-            ).into());
+            return Err(NitroError::ChecksumMismatch {
+                expected: expected_sha256.to_string(),
+                actual: calculated,
+            });
         }
 
         Ok(())
     }
 
-    fn extract_tarball(&self, tarball: &Path, destination: &Path) -> Result<()> {
-        use tar::Archive;
+    /// Unpack a .tar.gz, .tar.xz or .tar.bz2 archive into `destination`, stopping
+    /// between entries if `cancel` fires and telling `report` how far it's got (see
+    /// [`Extraction`]). Returns how many entries it had.
+    pub fn extract_tarball(tarball: &Path, destination: &Path, cancel: &CancellationToken, report: &dyn Fn(&Extraction)) -> Result<u64> {
         use flate2::read::GzDecoder;
+        use std::io::Read;
         use xz2::read::XzDecoder;
 
         // Check if file exists and has content
@@ -383,59 +1558,68 @@ impl Installer {
         
         eprintln!("DEBUG: Extracting {} with extension: {}", tarball.display(), extension);
 
-        let file = std::fs::File::open(tarball)?;
+        let read = std::rc::Rc::new(std::cell::Cell::new(0));
+        let file = CountingReader { inner: std::fs::File::open(tarball)?, read: read.clone() };
+        let progress = || Extraction { entries: 0, read: read.get(), total: metadata.len() };
         
-        match extension {
-            "gz" => {
-                let decoder = GzDecoder::new(file);
-                let mut archive = Archive::new(decoder);
-                archive.unpack(destination).map_err(|e| {
-                    anyhow::Error::from(NitroError::Other(format!("Failed to extract tar.gz archive: {}", e)))
-                })?;
-            }
-            "xz" => {
-                let decoder = XzDecoder::new(file);
-                let mut archive = Archive::new(decoder);
-                archive.unpack(destination).map_err(|e| {
-                    anyhow::Error::from(NitroError::Other(format!("Failed to extract tar.xz archive: {}", e)))
-                })?;
-            }
-            "bz2" => {
-                use bzip2::read::BzDecoder;
-                let decoder = BzDecoder::new(file);
-                let mut archive = Archive::new(decoder);
-                archive.unpack(destination).map_err(|e| {
-                    anyhow::Error::from(NitroError::Other(format!("Failed to extract tar.bz2 archive: {}", e)))
-                })?;
-            }
+        let (decoder, format): (Box<dyn Read>, &str) = match extension {
+            "gz" => (Box::new(GzDecoder::new(file)), "tar.gz"),
+            "xz" => (Box::new(XzDecoder::new(file)), "tar.xz"),
+            "bz2" => (Box::new(bzip2::read::BzDecoder::new(file)), "tar.bz2"),
             _ => {
                 // Try to detect by reading file header
-                let mut file = std::fs::File::open(tarball)?;
                 let mut header = [0u8; 6];
-                use std::io::Read;
-                file.read_exact(&mut header)?;
-                
-                // Reset file
-                let file = std::fs::File::open(tarball)?;
+                std::fs::File::open(tarball)?.read_exact(&mut header)?;
                 
                 if header[0..2] == [0x1f, 0x8b] {
-                    // gzip
-                    let decoder = GzDecoder::new(file);
-                    let mut archive = Archive::new(decoder);
-                    archive.unpack(destination)?;
+                    (Box::new(GzDecoder::new(file)), "gzip")
                 } else if header[0..6] == [0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00] {
-                    // xz
-                    let decoder = XzDecoder::new(file);
-                    let mut archive = Archive::new(decoder);
-                    archive.unpack(destination)?;
+                    (Box::new(XzDecoder::new(file)), "xz")
                 } else {
                     return Err(NitroError::Other(
                         "Unknown archive format. Supported formats: .tar.gz, .tar.xz, .tar.bz2".into()
                     ).into());
                 }
             }
+        };
+
+        unpack_entries(decoder, destination, cancel, progress, report).map_err(|e| match e {
+            e @ (NitroError::Interrupted | NitroError::TimedOut(_)) => e.into(),
+            e => NitroError::Other(format!("Failed to extract {} archive: {}", format, e)).into(),
+        })
+    }
+
+    /// Put the resource downloaded to `archive` in `target` the way Homebrew stages one:
+    /// an archive is unpacked, without its top-level directory if everything is in one,
+    /// and anything else is copied in as it is.
+    pub fn stage_resource(archive: &Path, target: &Path, cancel: &CancellationToken) -> Result<()> {
+        std::fs::create_dir_all(target)?;
+        let file_name = archive.file_name().unwrap_or_default().to_string_lossy();
+        let is_tarball = [".tar.gz", ".tgz", ".tar.xz", ".txz", ".tar.bz2", ".tbz", ".tar"].iter()
+            .any(|extension| file_name.ends_with(extension));
+        if !is_tarball {
+            std::fs::copy(archive, target.join(&*file_name))?;
+            return Ok(());
+        }
+
+        let unpacked = tempfile::tempdir_in(target.parent().unwrap_or(target))?;
+        Self::extract_tarball(archive, unpacked.path(), cancel, &|_| {})?;
+        let entries: Vec<PathBuf> = std::fs::read_dir(unpacked.path())?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<std::io::Result<_>>()?;
+        let root = match entries.as_slice() {
+            [only] if only.is_dir() => only.clone(),
+            _ => unpacked.path().to_path_buf(),
+        };
+        for entry in std::fs::read_dir(&root)? {
+            let entry = entry?;
+            let dest = target.join(entry.file_name());
+            if entry.file_type()?.is_dir() && dest.is_dir() {
+                workspace::copy_dir(&entry.path(), &dest)?;
+            } else {
+                std::fs::rename(entry.path(), dest)?;
+            }
         }
-        
         Ok(())
     }
 
@@ -450,20 +1634,36 @@ impl Installer {
         Err(NitroError::Other("No extracted directory found".into()).into())
     }
 
-    fn run_command(&self, command: &str, cwd: &Path) -> Result<()> {
+    /// The environment building `formula` from source starts from.
+    fn source_build_env(&self, formula: &Formula) -> BuildEnv {
+        let mut env = BuildEnv::new();
+        // Build tools installed by nitro are found even if the link directory isn't on PATH
+        env.prepend_path("PATH", &self.bin_dir.display().to_string());
+        env.set("PREFIX", self.get_keg_path(formula).display().to_string());
+        env.set("HOMEBREW_PREFIX", self.prefix.display().to_string());
+        // The Python or Node it depends on comes before any other on PATH
+        for toolchain in super::language::Toolchain::for_build(&self.prefix, formula).iter().rev() {
+            toolchain.apply_to_build(&mut env, &self.get_keg_path(formula));
+        }
+        env
+    }
+
+    /// Run `command` in `cwd`, killing it and failing with the token's error if
+    /// `cancel` fires first.
+    fn run_command(&self, command: &str, cwd: &Path, env: &BuildEnv, cancel: &CancellationToken) -> Result<()> {
         let parts: Vec<&str> = command.split_whitespace().collect();
         if parts.is_empty() {
             return Ok(());
         }
 
-        let output = Command::new(parts[0])
-            .args(&parts[1..])
-            .current_dir(cwd)
-            .output()?;
+        let mut command = Command::new(parts[0]);
+        command.args(&parts[1..]).current_dir(cwd);
+        env.apply_to(&mut command);
+        let (status, stderr) = cancel.run_command(&mut command)?;
 
-        if !output.status.success() {
+        if !status.success() {
             return Err(NitroError::Other(
-                format!("Command failed: {}", String::from_utf8_lossy(&output.stderr))
+                format!("Command failed: {}", String::from_utf8_lossy(&stderr))
             ).into());
         }
 
@@ -484,7 +1684,7 @@ impl Installer {
         None
     }
 
-    fn get_platform(&self) -> String {
+    fn get_platform() -> String {
         if cfg!(target_os = "macos") {
             "darwin".to_string()  // Homebrew uses "darwin" for macOS
         } else if cfg!(target_os = "linux") {
@@ -494,7 +1694,7 @@ impl Installer {
         }
     }
 
-    fn get_arch(&self) -> String {
+    fn get_arch() -> String {
         if cfg!(target_arch = "x86_64") {
             "x86_64".to_string()  // Match Homebrew's naming
         } else if cfg!(target_arch = "aarch64") {
@@ -504,14 +1704,12 @@ impl Installer {
         }
     }
 
-    async fn download_bottle(&self, bottle_url: &str, dest: &Path) -> Result<()> {
-        eprintln!("DEBUG: Downloading Homebrew bottle from: {}", bottle_url);
-        
-        // For ghcr.io bottles, we can download directly
-        // The URL format is already the direct download link
-        self.downloader.download_file(bottle_url, dest).await?;
-        
-        Ok(())
+    /// Download a bottle published to an OCI registry, after checking the registry's
+    /// index for `tag` still lists it.
+    async fn download_bottle(&self, blob: &crate::download::oci::BlobRef, tag: &str, dest: &Path, cancel: &CancellationToken) -> Result<()> {
+        let client = crate::download::oci::OciClient::new(self.downloader.with_cancellation(cancel.clone()));
+        client.check_published(blob, tag).await?;
+        client.download_blob(blob, dest).await
     }
 
     fn get_prefix() -> Result<PathBuf> {
@@ -527,10 +1725,8 @@ impl Installer {
         let intel_path = PathBuf::from("/usr/local");
         
         // Check if running on Apple Silicon
-        if cfg!(target_os = "macos") && cfg!(target_arch = "aarch64") {
-            if apple_silicon_path.join("bin/brew").exists() {
-                return Ok(apple_silicon_path);
-            }
+        if cfg!(target_os = "macos") && cfg!(target_arch = "aarch64") && apple_silicon_path.join("bin/brew").exists() {
+            return Ok(apple_silicon_path);
         }
         
         // Check standard Homebrew location
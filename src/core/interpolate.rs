@@ -0,0 +1,132 @@
+use anyhow::Result;
+use std::path::PathBuf;
+
+use crate::core::formula::Formula;
+
+/// Resolves the `#{...}` path placeholders found in caveats, service blocks and install
+/// scripts (`#{opt_prefix}`, `#{etc}`, `#{HOMEBREW_PREFIX}`, `#{Formula["openssl@3"].opt_lib}`)
+/// to real paths for a formula in this installation.
+#[derive(Debug, Clone)]
+pub struct PathContext {
+    homebrew_prefix: PathBuf,
+    name: String,
+    version: String,
+    revision: u32,
+}
+
+impl PathContext {
+    pub fn new(homebrew_prefix: impl Into<PathBuf>, formula: &Formula) -> Self {
+        Self {
+            homebrew_prefix: homebrew_prefix.into(),
+            name: formula.name.clone(),
+            version: formula.version.clone(),
+            revision: formula.revision,
+        }
+    }
+
+    /// A context for `formula` under this machine's install prefix.
+    pub fn detect(formula: &Formula) -> Result<Self> {
+        Ok(Self::new(crate::core::installer::Installer::prefix()?, formula))
+    }
+
+    fn cellar(&self) -> PathBuf {
+        self.homebrew_prefix.join("Cellar")
+    }
+
+    /// The versioned keg, `Cellar/<name>/<version>[_<revision>]`
+    pub fn keg(&self) -> PathBuf {
+        self.cellar().join(&self.name).join(crate::core::formula::pkg_version(&self.version, self.revision))
+    }
+
+    /// The version-independent `opt/<name>` link to the keg
+    pub fn opt_prefix(&self) -> PathBuf {
+        self.homebrew_prefix.join("opt").join(&self.name)
+    }
+
+    /// The value of a single placeholder expression, or `None` if it isn't one we know.
+    pub fn resolve(&self, expr: &str) -> Option<String> {
+        let expr = expr.trim();
+
+        // Another formula's opt paths: Formula["openssl@3"].opt_prefix
+        if let Some(cap) = regex::Regex::new(r#"^Formula\[["']([^"']+)["']\]\.(opt_\w+)$"#).unwrap().captures(expr) {
+            let other = Self {
+                homebrew_prefix: self.homebrew_prefix.clone(),
+                name: cap[1].to_string(),
+                version: String::new(),
+                revision: 0,
+            };
+            return other.resolve(&cap[2]);
+        }
+
+        let path = match expr {
+            "HOMEBREW_PREFIX" => self.homebrew_prefix.clone(),
+            "HOMEBREW_CELLAR" => self.cellar(),
+            "name" => return Some(self.name.clone()),
+            "version" => return Some(self.version.clone()),
+            "prefix" => self.keg(),
+            "opt_prefix" => self.opt_prefix(),
+            // Shared, version-independent locations under the prefix
+            "etc" => self.homebrew_prefix.join("etc"),
+            "var" => self.homebrew_prefix.join("var"),
+            "pkgetc" => self.homebrew_prefix.join("etc").join(&self.name),
+            _ => {
+                let (base, dir) = match expr.strip_prefix("opt_") {
+                    Some(dir) => (self.opt_prefix(), dir),
+                    None => (self.keg(), expr),
+                };
+                base.join(keg_subdir(dir, &self.name)?)
+            }
+        };
+        Some(path.display().to_string())
+    }
+
+    /// The value of a Ruby argument in an install script: a string literal (interpolated
+    /// when double-quoted), a path helper such as `lib`, or a helper joined with a
+    /// string (`libexec/"lib/perl5"`). `None` for anything else.
+    pub fn evaluate(&self, expr: &str) -> Option<String> {
+        let expr = expr.trim();
+        let expr = expr.strip_suffix(".to_s").unwrap_or(expr);
+        if let Some(text) = expr.strip_prefix('"').and_then(|e| e.strip_suffix('"')) {
+            return Some(self.interpolate(text));
+        }
+        if let Some(text) = expr.strip_prefix('\'').and_then(|e| e.strip_suffix('\'')) {
+            return Some(text.to_string());
+        }
+        if let Some((base, rest)) = expr.split_once('/') {
+            return Some(format!("{}/{}", self.evaluate(base)?, self.evaluate(rest)?));
+        }
+        self.resolve(expr)
+    }
+
+    /// Replace every known `#{...}` placeholder in `text`; unknown ones are left as written.
+    pub fn interpolate(&self, text: &str) -> String {
+        regex::Regex::new(r"#\{([^}]+)\}").unwrap()
+            .replace_all(text, |cap: &regex::Captures| {
+                self.resolve(&cap[1]).unwrap_or_else(|| cap[0].to_string())
+            })
+            .into_owned()
+    }
+}
+
+/// Keg-relative directory for a path helper such as `bin` or `pkgshare`.
+fn keg_subdir(helper: &str, name: &str) -> Option<String> {
+    let dir = match helper {
+        "bin" | "sbin" | "lib" | "libexec" | "include" | "share" | "frameworks" => helper.to_string(),
+        "pkgshare" => format!("share/{}", name),
+        "doc" => format!("share/doc/{}", name),
+        "info" => "share/info".to_string(),
+        "man" => "share/man".to_string(),
+        "bash_completion" => "etc/bash_completion.d".to_string(),
+        "zsh_completion" => "share/zsh/site-functions".to_string(),
+        "fish_completion" => "share/fish/vendor_completions.d".to_string(),
+        _ => {
+            // man1 .. man8
+            let section = helper.strip_prefix("man")?;
+            if section.len() != 1 || !section.chars().all(|c| c.is_ascii_digit()) {
+                return None;
+            }
+            format!("share/man/{}", helper)
+        }
+    };
+    Some(dir)
+}
@@ -0,0 +1,58 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+use crate::core::{NitroError, NitroResult};
+
+/// How long a command gets after Ctrl-C to reach a safe point and roll back.
+pub const GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+fn notify() -> &'static Notify {
+    static NOTIFY: OnceLock<Notify> = OnceLock::new();
+    NOTIFY.get_or_init(Notify::new)
+}
+
+/// Install the Ctrl-C handler. The first signal requests a graceful stop; a second one
+/// exits immediately.
+pub fn install_handler() {
+    tokio::spawn(async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        notify().notify_waiters();
+        eprintln!("\nInterrupted, cleaning up... (press Ctrl-C again to quit immediately)");
+
+        if tokio::signal::ctrl_c().await.is_ok() {
+            std::process::exit(130);
+        }
+    });
+}
+
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Fail with `NitroError::Interrupted` if Ctrl-C has been pressed. Call between steps
+/// that are safe to stop at.
+pub fn check() -> NitroResult<()> {
+    if is_interrupted() {
+        Err(NitroError::Interrupted)
+    } else {
+        Ok(())
+    }
+}
+
+/// Resolve once Ctrl-C has been pressed.
+pub async fn wait() {
+    loop {
+        let notified = notify().notified();
+        if is_interrupted() {
+            return;
+        }
+        notified.await;
+    }
+}
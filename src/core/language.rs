@@ -0,0 +1,328 @@
+//! Installers for language formulae that vendor their libraries as resources.
+//!
+//! Most Python, Node and Ruby tools in homebrew-core don't build with configure/make:
+//! they install the package and its resources into an isolated environment under
+//! `libexec` and expose only the package's own entry points in `bin`.
+
+use anyhow::Result;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::core::build_env::BuildEnv;
+use crate::core::cancel::CancellationToken;
+use crate::core::NitroError;
+use super::formula::{Dependency, Formula};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LanguageInstaller {
+    /// `virtualenv_install_with_resources`: a venv in `libexec`
+    Python,
+    /// `system "npm", "install", *std_npm_args`: a global prefix in `libexec`
+    Node,
+    /// `system "gem", "install", ...`: a `GEM_HOME` in `libexec`
+    Ruby,
+}
+
+impl LanguageInstaller {
+    /// The installer an `install do` block asks for, if it is one of the supported idioms.
+    pub fn detect(install_script: &str) -> Option<Self> {
+        if install_script.contains("virtualenv_install_with_resources") || install_script.contains("virtualenv_create") {
+            Some(Self::Python)
+        } else if install_script.contains("std_npm_args") || install_script.contains(r#""npm", "install""#) {
+            Some(Self::Node)
+        } else if install_script.contains(r#""gem", "install""#) || install_script.contains("GEM_HOME") {
+            Some(Self::Ruby)
+        } else {
+            None
+        }
+    }
+
+    /// Install the package in `source` and its downloaded `resources` into `keg`'s
+    /// `libexec`, then expose the package's entry points in `keg/bin`. Returns the
+    /// entry points. Each step is killed if `cancel` fires.
+    pub fn install(self, keg: &Path, source: &Path, resources: &[PathBuf], interpreter: &Path, cancel: &CancellationToken) -> Result<Vec<String>> {
+        let run = |command: &mut Command| run(command, cancel);
+        let libexec = keg.join("libexec");
+        std::fs::create_dir_all(&libexec)?;
+
+        match self {
+            Self::Python => {
+                run(Command::new(interpreter).args(["-m", "venv"]).arg(&libexec))?;
+                let pip = libexec.join("bin").join("pip");
+                for resource in resources {
+                    run(Command::new(&pip).args(["install", "--no-deps", "--ignore-installed"]).arg(resource))?;
+                }
+                // The venv's own scripts (pip, activate) and the resources' aren't entry points
+                let before = executables(&libexec.join("bin"));
+                run(Command::new(&pip).args(["install", "--no-deps", "--ignore-installed"]).arg(source))?;
+                let entry_points = new_entry_points(&before, &libexec.join("bin"));
+                link_entry_points(keg, &entry_points)?;
+                Ok(entry_points)
+            }
+            Self::Node => {
+                for package in resources.iter().map(PathBuf::as_path).chain(std::iter::once(source)) {
+                    run(Command::new("npm").args(["install", "--global", "--no-audit", "--no-fund", "--prefix"]).arg(&libexec).arg(package))?;
+                }
+                let entry_points = new_entry_points(&BTreeSet::new(), &libexec.join("bin"));
+                link_entry_points(keg, &entry_points)?;
+                Ok(entry_points)
+            }
+            Self::Ruby => {
+                let bin = libexec.join("bin");
+                let gem_args = |gem: &Path| {
+                    let mut command = Command::new("gem");
+                    command.args(["install", "--no-document", "--ignore-dependencies", "--install-dir"])
+                        .arg(&libexec)
+                        .arg("--bindir")
+                        .arg(&bin)
+                        .arg(gem)
+                        .env("GEM_HOME", &libexec);
+                    command
+                };
+                for resource in resources {
+                    run(&mut gem_args(resource))?;
+                }
+                let before = executables(&bin);
+                run(&mut gem_args(&package_gem(source, cancel)?))?;
+                let entry_points = new_entry_points(&before, &bin);
+                write_gem_wrappers(keg, &entry_points)?;
+                Ok(entry_points)
+            }
+        }
+    }
+}
+
+/// The interpreter a Python formula builds its venv with: `python3` from its
+/// `python@3.x` dependency's opt link when it has one, so the venv outlives upgrades of
+/// that Python, otherwise whatever is on PATH.
+pub fn python_interpreter(prefix: &Path, formula: &Formula) -> PathBuf {
+    Toolchain::resolve(prefix, &formula.dependencies).into_iter()
+        .find(|t| t.runtime == Runtime::Python)
+        .map(|t| t.interpreter())
+        .filter(|path| path.exists())
+        .unwrap_or_else(|| PathBuf::from("python3"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Runtime {
+    Python,
+    Node,
+}
+
+/// A language runtime a formula depends on (`python@3.12`, `node`), as installed in
+/// the prefix. Builds run with it first on `PATH`, and the formula's scripts are
+/// pointed at it rather than at whatever the user's `PATH` finds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Toolchain {
+    pub runtime: Runtime,
+    /// The formula providing it
+    pub formula: String,
+    /// Its `bin` under `opt`, which build environments and wrappers refer to so they
+    /// don't name one version's keg
+    pub bin: PathBuf,
+    /// The Python version its libraries are installed under (`lib/python3.12`)
+    pub version: Option<String>,
+}
+
+impl Toolchain {
+    /// The toolchains `dependencies` name, in the order they're listed.
+    pub fn resolve(prefix: &Path, dependencies: &[Dependency]) -> Vec<Self> {
+        dependencies.iter().filter_map(|dep| {
+            let (base, versioned) = dep.name.split_once('@').map_or((dep.name.as_str(), None), |(b, v)| (b, Some(v)));
+            let runtime = match base {
+                "python" => Runtime::Python,
+                "node" => Runtime::Node,
+                _ => return None,
+            };
+            let bin = super::shebang::formula_bin(prefix, &dep.name);
+            let version = match runtime {
+                Runtime::Python => versioned.map(str::to_string).or_else(|| python_lib_version(&bin)),
+                Runtime::Node => None,
+            };
+            Some(Self { runtime, formula: dep.name.clone(), bin, version })
+        }).collect()
+    }
+
+    /// The toolchains building `formula` uses: any it depends on, build-only or not.
+    pub fn for_build(prefix: &Path, formula: &Formula) -> Vec<Self> {
+        let dependencies: Vec<Dependency> = formula.dependencies.iter().chain(&formula.build_dependencies).cloned().collect();
+        Self::resolve(prefix, &dependencies)
+    }
+
+    /// The toolchains `formula`'s installed scripts run with.
+    pub fn for_runtime(prefix: &Path, formula: &Formula) -> Vec<Self> {
+        let dependencies: Vec<Dependency> = formula.dependencies.iter().filter(|d| d.is_runtime()).cloned().collect();
+        Self::resolve(prefix, &dependencies)
+    }
+
+    pub fn interpreter(&self) -> PathBuf {
+        match self.runtime {
+            Runtime::Python => self.bin.join("python3"),
+            Runtime::Node => self.bin.join("node"),
+        }
+    }
+
+    /// Where Python packages installed under `root` (a keg, or its `libexec`) go:
+    /// `lib/python3.12/site-packages`.
+    pub fn site_packages(&self, root: &Path) -> Option<PathBuf> {
+        let version = self.version.as_deref().filter(|_| self.runtime == Runtime::Python)?;
+        Some(root.join("lib").join(format!("python{}", version)).join("site-packages"))
+    }
+
+    /// Put the toolchain in a build's environment: its `bin` first on `PATH`, and for
+    /// Python the keg's site-packages on `PYTHONPATH`, so `pip install --prefix` and
+    /// `setup.py` builds use this interpreter and find what they've installed.
+    pub fn apply_to_build(&self, env: &mut BuildEnv, keg: &Path) {
+        env.prepend_path("PATH", &self.bin.display().to_string());
+        match self.runtime {
+            Runtime::Python => {
+                env.set("PYTHON", self.interpreter().display().to_string());
+                for root in [keg.join("libexec"), keg.to_path_buf()] {
+                    if let Some(site_packages) = self.site_packages(&root) {
+                        env.prepend_path("PYTHONPATH", &site_packages.display().to_string());
+                    }
+                }
+            }
+            Runtime::Node => env.prepend_path("NODE_PATH", &keg.join("libexec/lib/node_modules").display().to_string()),
+        }
+    }
+}
+
+/// The `python3.X` directory under a Python's `lib`, for a `python` formula whose name
+/// doesn't say which version it is.
+fn python_lib_version(bin: &Path) -> Option<String> {
+    use crate::core::version::Version;
+
+    std::fs::read_dir(bin.parent()?.join("lib")).ok()?.flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.strip_prefix("python").map(str::to_string))
+        .filter(|version| version.starts_with("3."))
+        .max_by_key(|version| Version::new(version.as_str()))
+}
+
+/// Make the Python scripts in `keg/bin` find the packages installed in the keg's
+/// site-packages: each script moves to `libexec/bin` and `bin` gets a wrapper that
+/// runs it with `PYTHONPATH` set, like the gem wrappers. Symlinks (a venv's entry
+/// points) are left alone, as are kegs without site-packages. Returns the wrapped
+/// scripts.
+pub fn write_python_env_scripts(keg: &Path, toolchain: &Toolchain) -> Result<Vec<String>> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let Some(site_packages) = toolchain.site_packages(keg).filter(|dir| dir.is_dir()) else {
+        return Ok(Vec::new());
+    };
+    let bin = keg.join("bin");
+    let libexec_bin = keg.join("libexec/bin");
+
+    let mut wrapped = Vec::new();
+    for name in executables(&bin) {
+        let script = bin.join(&name);
+        if script.symlink_metadata()?.file_type().is_symlink() || !is_python_script(&script) {
+            continue;
+        }
+        std::fs::create_dir_all(&libexec_bin)?;
+        let target = libexec_bin.join(&name);
+        std::fs::rename(&script, &target)?;
+        let wrapper = format!(
+            "#!/bin/bash\nPYTHONPATH=\"{}${{PYTHONPATH:+:$PYTHONPATH}}\" exec \"{}\" \"$@\"\n",
+            site_packages.display(),
+            target.display(),
+        );
+        std::fs::write(&script, wrapper)?;
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755))?;
+        wrapped.push(name);
+    }
+    Ok(wrapped)
+}
+
+fn is_python_script(path: &Path) -> bool {
+    use std::io::{BufRead, BufReader, Read};
+
+    let Ok(file) = std::fs::File::open(path) else {
+        return false;
+    };
+    let mut first = Vec::new();
+    let _ = BufReader::new(file.take(256)).read_until(b'\n', &mut first);
+    first.starts_with(b"#!") && String::from_utf8_lossy(&first).contains("python")
+}
+
+/// Executables in `bin` that aren't in `before`.
+pub fn new_entry_points(before: &BTreeSet<String>, bin: &Path) -> Vec<String> {
+    executables(bin).into_iter().filter(|name| !before.contains(name)).collect()
+}
+
+/// Symlink each of `entry_points` from `keg/bin` into `keg/libexec/bin`.
+pub fn link_entry_points(keg: &Path, entry_points: &[String]) -> Result<()> {
+    let bin = keg.join("bin");
+    std::fs::create_dir_all(&bin)?;
+    for name in entry_points {
+        let link = bin.join(name);
+        if link.symlink_metadata().is_ok() {
+            std::fs::remove_file(&link)?;
+        }
+        std::os::unix::fs::symlink(Path::new("../libexec/bin").join(name), &link)?;
+    }
+    Ok(())
+}
+
+/// Write a script in `keg/bin` for each of `entry_points` that runs the gem's executable
+/// with `GEM_HOME` pointing at `keg/libexec`, so it finds its vendored gems.
+pub fn write_gem_wrappers(keg: &Path, entry_points: &[String]) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let bin = keg.join("bin");
+    let libexec = keg.join("libexec");
+    std::fs::create_dir_all(&bin)?;
+    for name in entry_points {
+        let script = format!(
+            "#!/bin/bash\nGEM_HOME=\"{}\" exec \"{}\" \"$@\"\n",
+            libexec.display(),
+            libexec.join("bin").join(name).display(),
+        );
+        let path = bin.join(name);
+        std::fs::write(&path, script)?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+/// The `.gem` to install for a Ruby source: the download itself, or one built from the
+/// gemspec of an unpacked source tree.
+fn package_gem(source: &Path, cancel: &CancellationToken) -> Result<PathBuf> {
+    if let Some(gem) = find_with_extension(source, "gem") {
+        return Ok(gem);
+    }
+    let gemspec = find_with_extension(source, "gemspec")
+        .ok_or_else(|| NitroError::Other(format!("No .gem or .gemspec found in {}", source.display())))?;
+    run(Command::new("gem").arg("build").arg(&gemspec).current_dir(source), cancel)?;
+    find_with_extension(source, "gem")
+        .ok_or_else(|| NitroError::Other(format!("gem build {} produced no gem", gemspec.display())).into())
+}
+
+fn find_with_extension(dir: &Path, extension: &str) -> Option<PathBuf> {
+    if dir.extension().and_then(|e| e.to_str()) == Some(extension) {
+        return Some(dir.to_path_buf());
+    }
+    std::fs::read_dir(dir).ok()?.flatten()
+        .map(|entry| entry.path())
+        .find(|path| path.extension().and_then(|e| e.to_str()) == Some(extension))
+}
+
+fn executables(dir: &Path) -> BTreeSet<String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::read_dir(dir).into_iter().flatten().flatten()
+        .filter(|entry| entry.metadata().map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0).unwrap_or(false))
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect()
+}
+
+fn run(command: &mut Command, cancel: &CancellationToken) -> Result<()> {
+    let (status, stderr) = cancel.run_command(command)?;
+    if !status.success() {
+        return Err(NitroError::Other(
+            format!("Command failed: {}", String::from_utf8_lossy(&stderr))
+        ).into());
+    }
+    Ok(())
+}
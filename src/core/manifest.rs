@@ -0,0 +1,127 @@
+//! A receipt of what installing a keg put in the prefix: every file and symlink in the
+//! keg, and every link made into it from outside. It's written into the keg and kept in
+//! the package database, so uninstalling removes exactly those paths rather than
+//! guessing from link targets, and leaves alone anything added since.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The manifest's name inside the keg it describes
+pub const MANIFEST_FILE: &str = ".nitro-manifest.json";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub name: String,
+    pub version: String,
+    pub keg: PathBuf,
+    /// Files and symlinks in the keg, relative to it
+    pub files: Vec<PathBuf>,
+    /// Links outside the keg that point into it
+    pub links: Vec<PathBuf>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl Manifest {
+    /// The manifest of the keg at `keg` as it is now, with `links` made into it.
+    pub fn scan(name: &str, version: &str, keg: &Path, links: Vec<PathBuf>) -> io::Result<Self> {
+        let mut files = Vec::new();
+        for entry in walkdir::WalkDir::new(keg).min_depth(1).sort_by_file_name() {
+            let entry = entry.map_err(io::Error::other)?;
+            if entry.file_type().is_dir() {
+                continue;
+            }
+            let relative = entry.path().strip_prefix(keg).map_err(io::Error::other)?;
+            if relative != Path::new(MANIFEST_FILE) {
+                files.push(relative.to_path_buf());
+            }
+        }
+        Ok(Self {
+            name: name.to_string(),
+            version: version.to_string(),
+            keg: keg.to_path_buf(),
+            files,
+            links,
+            created_at: super::deterministic::now(),
+        })
+    }
+
+    /// The manifest written into the keg at `keg`, if there is a readable one.
+    pub fn read(keg: &Path) -> Option<Self> {
+        let data = std::fs::read(keg.join(MANIFEST_FILE)).ok()?;
+        serde_json::from_slice(&data).ok()
+    }
+
+    /// Write the manifest into its keg.
+    pub fn write(&self) -> io::Result<()> {
+        let data = serde_json::to_vec_pretty(self).map_err(io::Error::other)?;
+        std::fs::write(self.keg.join(MANIFEST_FILE), data)
+    }
+
+    /// Remove the links, then the files, then whichever of the keg's directories that
+    /// leaves empty, the keg included. A link that no longer points into the keg was
+    /// replaced by something else and stays. Returns the files in the keg the manifest
+    /// doesn't list, which are left where they are.
+    pub fn remove(&self) -> io::Result<Vec<PathBuf>> {
+        for link in &self.links {
+            if std::fs::read_link(link).is_ok_and(|target| target.starts_with(&self.keg)) {
+                std::fs::remove_file(link)?;
+            }
+        }
+        if !self.keg.exists() {
+            return Ok(Vec::new());
+        }
+
+        for file in self.files.iter().map(|f| self.keg.join(f)).chain([self.keg.join(MANIFEST_FILE)]) {
+            match std::fs::remove_file(&file) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+
+        let mut kept = Vec::new();
+        for entry in walkdir::WalkDir::new(&self.keg).contents_first(true) {
+            let entry = entry.map_err(io::Error::other)?;
+            if !entry.file_type().is_dir() {
+                kept.push(entry.into_path());
+            } else if std::fs::read_dir(entry.path())?.next().is_none() {
+                std::fs::remove_dir(entry.path())?;
+            }
+        }
+        kept.sort();
+        Ok(kept)
+    }
+}
+
+/// Manifests of installed kegs, kept in their own tree of the package database and
+/// keyed by formula name.
+#[derive(Clone)]
+pub struct ManifestStore {
+    tree: sled::Tree,
+}
+
+impl ManifestStore {
+    pub fn open(db: &sled::Db) -> Result<Self> {
+        Ok(Self {
+            tree: db.open_tree("manifests")?,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Result<Option<Manifest>> {
+        match self.tree.get(name)? {
+            Some(data) => Ok(Some(serde_json::from_slice(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn insert(&self, manifest: &Manifest) -> Result<()> {
+        self.tree.insert(&manifest.name, serde_json::to_vec(manifest)?)?;
+        Ok(())
+    }
+
+    pub fn remove(&self, name: &str) -> Result<()> {
+        self.tree.remove(name)?;
+        Ok(())
+    }
+}
@@ -4,5 +4,43 @@ pub mod resolver;
 pub mod installer;
 pub mod tap;
 pub mod errors;
+pub mod config;
+pub mod analytics;
+pub mod install_state;
+pub mod interrupt;
+pub mod disk;
+pub mod cask;
+pub mod readonly;
+pub mod version;
+pub mod interpolate;
+pub mod git;
+pub mod doctor;
+pub mod shebang;
+pub mod language;
+pub mod fonts;
+pub mod cask_installer;
+pub mod pkg;
+pub mod dmg;
+pub mod policy;
+pub mod toolchain;
+pub mod requirements;
+pub mod glibc;
+pub mod build_env;
+pub mod workspace;
+pub mod debug_build;
+pub mod deterministic;
+pub mod shellrc;
+pub mod prune;
+pub mod changes;
+pub mod audit;
+pub mod transaction;
+pub mod providers;
+pub mod cancel;
+pub mod cleanup;
+pub mod checksum;
+pub mod manifest;
+pub mod clone;
+pub mod services;
+pub mod ruby;
 
-pub use errors::{NitroError, NitroResult};
\ No newline at end of file
+pub use errors::{NitroError, NitroResult};
@@ -1,9 +1,22 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use crate::cli::commands::{install::InstallArgs, uninstall::UninstallArgs, list::ListArgs, update::UpdateArgs};
-use crate::core::{NitroError, NitroResult};
+use crate::cli::commands::{install::InstallArgs, uninstall::UninstallArgs, list::ListArgs};
+use crate::core::cancel::CancellationToken;
+use crate::core::cask_installer::CaskStore;
+use crate::core::formula::DependencyKind;
+use crate::core::install_state::{ChecksumOverride, InstallPhase, InstallRecord, InstallSource, InstallStateStore};
+use crate::core::installer::{keg_owner, Installer, KegOwner};
+use crate::core::manifest::{Manifest, ManifestStore};
+use crate::core::policy::Policy;
+use crate::core::providers::Providers;
+use crate::core::resolver::ResolveOptions;
+use crate::core::tap::FormulaPin;
+use crate::core::version::PkgVersion;
+use crate::core::transaction::Transaction;
+use crate::core::{disk, NitroError, NitroResult};
+use crate::download::Downloader;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Package {
@@ -15,7 +28,90 @@ pub struct Package {
     pub installed_version: Option<String>,
     pub dependencies: Vec<String>,
     pub install_path: Option<PathBuf>,
+    /// Bytes the keg took up in the cellar when it was installed
     pub size: Option<u64>,
+    /// Tap the package was installed from
+    #[serde(default)]
+    pub tap: Option<String>,
+    /// The formula's `version_scheme` when it was installed
+    #[serde(default)]
+    pub version_scheme: u32,
+    /// The formula file the package was installed from
+    #[serde(default)]
+    pub formula_path: Option<PathBuf>,
+    /// SHA-256 of that formula file at install time
+    #[serde(default)]
+    pub formula_hash: Option<String>,
+    /// Commit of the tap at install time
+    #[serde(default)]
+    pub tap_commit: Option<String>,
+    /// Set when the source tarball was accepted with a checksum other than the formula's
+    #[serde(default)]
+    pub checksum_override: Option<ChecksumOverride>,
+    /// Whether the user asked for the package, rather than it being installed as a
+    /// dependency. Packages registered before this was tracked count as asked for, so
+    /// `autoremove` leaves them alone.
+    #[serde(default = "installed_on_request_default")]
+    pub installed_on_request: bool,
+    /// Held at its installed version: `upgrade` leaves it alone unless forced
+    #[serde(default)]
+    pub pinned: bool,
+    /// When the installed version was registered; unknown for packages registered
+    /// before this was tracked
+    #[serde(default)]
+    pub installed_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Whether installing put links to the keg's executables in the prefix; unknown for
+    /// packages registered before this was tracked
+    #[serde(default)]
+    pub linked: Option<bool>,
+}
+
+fn installed_on_request_default() -> bool {
+    true
+}
+
+impl Package {
+    /// How well the package matches every word of `query`, case-insensitively: 2 per
+    /// word found in the name, 1 per word only in the description. `None` if any word
+    /// is missing from both.
+    pub fn match_score(&self, query: &str) -> Option<u32> {
+        let name = self.name.to_lowercase();
+        let description = self.description.as_deref().unwrap_or_default().to_lowercase();
+
+        let mut score = 0;
+        for word in query.split_whitespace().map(str::to_lowercase) {
+            if name.contains(&word) {
+                score += 2;
+            } else if description.contains(&word) {
+                score += 1;
+            } else {
+                return None;
+            }
+        }
+        Some(score)
+    }
+}
+
+/// A formula's dependency annotated with what installing the formula would do about it.
+#[derive(Debug, Clone, Serialize)]
+pub struct DependencyStatus {
+    pub name: String,
+    pub kind: DependencyKind,
+    /// Installed version, if any
+    pub installed_version: Option<String>,
+    /// Whether a bottle exists for this platform; `None` when the formula couldn't be loaded
+    pub bottle: Option<bool>,
+}
+
+/// One formula in a dry-run install plan.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlanEntry {
+    pub name: String,
+    pub version: String,
+    pub installed_version: Option<String>,
+    pub bottle: bool,
+    /// Archive size reported by the server, if any
+    pub download_size: Option<u64>,
 }
 
 pub struct PackageManager {
@@ -23,6 +119,9 @@ pub struct PackageManager {
     formula_manager: super::formula::FormulaManager,
     installer: super::installer::Installer,
     resolver: super::resolver::DependencyResolver,
+    install_state: InstallStateStore,
+    casks: CaskStore,
+    manifests: ManifestStore,
 }
 
 impl PackageManager {
@@ -31,27 +130,52 @@ impl PackageManager {
             .ok_or_else(|| NitroError::Other("Could not determine config directory".into()))?;
         
         let db_path = config_dir.data_dir().join("packages.db");
-        std::fs::create_dir_all(db_path.parent().unwrap())?;
-        
-        let db = sled::open(&db_path)?;
+        let db = if super::readonly::is_enabled() {
+            super::readonly::open_snapshot(&db_path)?
+        } else {
+            std::fs::create_dir_all(db_path.parent().unwrap())?;
+            sled::open(&db_path)?
+        };
         let formula_manager = super::formula::FormulaManager::new().await?;
-        let installer = super::installer::Installer::new()?;
+        let installer = super::installer::Installer::new(Downloader::shared()?)?;
+        Self::with_db(db, formula_manager, installer)
+    }
+
+    /// A package manager keeping its records in `db`, rather than this machine's
+    /// package database, and installing with `installer`.
+    pub fn with_db(db: sled::Db, formula_manager: super::formula::FormulaManager, installer: super::installer::Installer) -> Result<Self> {
+        let installer = installer.with_packages(db.clone());
         let resolver = super::resolver::DependencyResolver::new();
+        let install_state = InstallStateStore::open(&db)?;
+        let casks = CaskStore::open(&db)?;
+        let manifests = ManifestStore::open(&db)?;
 
         Ok(Self {
             db,
             formula_manager,
             installer,
             resolver,
+            install_state,
+            casks,
+            manifests,
         })
     }
 
     pub async fn install(&self, package_name: &str, args: &InstallArgs) -> Result<()> {
         // Try to resolve the package name intelligently
         let formula = self.resolve_package_formula(package_name).await?;
-        
-        // Check if already installed
-        if !args.force && self.is_installed(&formula.name)? {
+        self.install_resolved(formula, args, true).await
+    }
+
+    /// Install an already loaded formula and its dependencies. `on_request` marks the
+    /// formula as asked for by the user; its dependencies never are.
+    async fn install_resolved(&self, formula: super::formula::Formula, args: &InstallArgs, on_request: bool) -> Result<()> {
+        // Check if already installed; with --only-deps it isn't touched
+        if !args.force && !args.only_deps && self.is_installed(&formula.name)? {
+            // Asking for a dependency by name makes it one the user wants kept
+            if on_request {
+                self.mark_on_request(&formula.name)?;
+            }
             return Err(NitroError::Other(format!("{} is already installed", formula.name)).into());
         }
         
@@ -59,15 +183,49 @@ impl PackageManager {
         let deps = if args.skip_deps {
             vec![]
         } else {
-            self.resolver.resolve(&formula, &self.formula_manager).await?
+            let options = ResolveOptions { include_optional: args.include_optional, include_test: args.include_test, choose_providers: true };
+            self.resolver.resolve_with(&formula, &self.formula_manager, &self.installed_names()?, options).await?
         };
 
+        let mut pending: Vec<_> = Vec::new();
+        for dep_formula in &deps {
+            if !self.is_installed(&dep_formula.name)? {
+                pending.push(dep_formula);
+            }
+        }
+        if !args.only_deps {
+            pending.push(&formula);
+        }
+
+        // Refuse the whole install up front rather than stopping after some dependencies
+        let policy = Policy::load()?;
+        for formula in &pending {
+            policy.check_formula(formula)?;
+        }
+        check_tap_trust(&pending, &policy)?;
+        let host = super::requirements::Host::detect();
+        let unmet: Vec<String> = pending.iter()
+            .flat_map(|f| super::requirements::unmet_for_install(f, &host))
+            .collect();
+        if !unmet.is_empty() {
+            return Err(NitroError::Other(unmet.join("\n")).into());
+        }
+
+        self.ensure_build_tools(&pending, args.build_from_source).await?;
+
+        // Make sure everything will fit before downloading anything
+        if !args.skip_space_check {
+            let requirements = self.installer.space_requirements(&pending, args.build_from_source).await;
+            disk::ensure_space(&requirements)?;
+        }
+
         // Install dependencies first
         for dep_formula in &deps {
             if !self.is_installed(&dep_formula.name)? {
-                println!("Installing dependency: {}", dep_formula.name);
-                self.installer.install(dep_formula, args.build_from_source).await?;
-                self.mark_installed(dep_formula)?;
+                if !crate::ui::is_quiet() {
+                    println!("Installing dependency: {}", dep_formula.name);
+                }
+                self.install_formula(dep_formula, args.build_from_source, args.force, args.sha256_override.as_deref(), args.timeout, false).await?;
             }
         }
 
@@ -77,14 +235,381 @@ impl PackageManager {
             if !formula.sources.is_empty() {
                 eprintln!("DEBUG: First source URL: {}", formula.sources[0].url);
             }
-            self.installer.install(&formula, args.build_from_source).await?;
-            self.mark_installed(&formula)?;
+            self.install_formula(&formula, args.build_from_source, args.force, args.sha256_override.as_deref(), args.timeout, on_request).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Make sure any of `formulae` that will be built from source can be: their Xcode
+    /// and macOS requirements are met, and the tools they run are present, offering
+    /// to install missing tools with nitro first. Formulae with a bottle for this
+    /// machine need none of it, unless `build_from_source` is set.
+    async fn ensure_build_tools(&self, formulae: &[&super::formula::Formula], build_from_source: bool) -> Result<()> {
+        use super::requirements::{unmet_for_source_build, Host};
+        use std::io::IsTerminal;
+
+        let building: Vec<_> = formulae.iter().filter(|f| build_from_source || !self.installer.has_bottle(f)).collect();
+        if building.is_empty() {
+            return Ok(());
+        }
+
+        let host = Host::detect();
+        let mut unmet: Vec<String> = Vec::new();
+        for formula in &building {
+            for problem in unmet_for_source_build(formula, &host) {
+                if !unmet.contains(&problem) {
+                    unmet.push(problem);
+                }
+            }
+        }
+        if !unmet.is_empty() {
+            return Err(NitroError::Other(unmet.join("\n")).into());
+        }
+
+        let missing_tools = || {
+            let mut missing: Vec<super::toolchain::BuildTool> = Vec::new();
+            for formula in &building {
+                for tool in self.installer.missing_build_tools(formula) {
+                    if !missing.contains(&tool) {
+                        missing.push(tool);
+                    }
+                }
+            }
+            missing
+        };
+        let missing = missing_tools();
+        if missing.is_empty() {
+            return Ok(());
         }
 
+        let programs: Vec<&str> = missing.iter().map(|t| t.program).collect();
+        let installable: Vec<&str> = missing.iter().filter_map(|t| t.formula).collect();
+        let offered = installable.len() == missing.len()
+            && std::io::stdin().is_terminal()
+            && crate::ui::display::confirm_build_tools(&missing);
+        if !offered {
+            return Err(NitroError::Other(format!(
+                "Building from source needs {}, which could not be found; {}",
+                programs.join(", "), super::toolchain::install_hint(&missing)
+            )).into());
+        }
+
+        for name in &installable {
+            if !crate::ui::is_quiet() {
+                println!("Installing build tool: {}", name);
+            }
+            let args = InstallArgs { packages: vec![name.to_string()], ..Default::default() };
+            Box::pin(self.install(name, &args)).await?;
+        }
+
+        // A formula that installed without the command it was meant to provide
+        let still_missing: Vec<&str> = missing_tools().iter().map(|tool| tool.program).collect();
+        if !still_missing.is_empty() {
+            return Err(NitroError::Other(format!(
+                "Building from source needs {}, which still could not be found after installing {}",
+                still_missing.join(", "), installable.join(", ")
+            )).into());
+        }
+        Ok(())
+    }
+
+    /// Install and register a single formula as one transaction: a failure or Ctrl-C
+    /// anywhere rolls back the keg, its links and its database entry. Each phase is
+    /// also persisted, so an install killed outright can be picked up by `resume` or
+    /// cleaned up by `abort`. A source
+    /// tarball that fails its checksum is only accepted if it has `sha256_override` or
+    /// the user accepts it when asked. With a `timeout`, an install still running
+    /// after it is stopped and rolled back like a failed one. `on_request` is recorded
+    /// with the package (see [`Package::installed_on_request`]).
+    async fn install_formula(&self, formula: &super::formula::Formula, build_from_source: bool, force: bool, sha256_override: Option<&str>, timeout: Option<std::time::Duration>, on_request: bool) -> Result<()> {
+        // Resumed installs may predate the policy
+        Policy::load()?.check_formula(formula)?;
+
+        let keg_path = self.installer.get_keg_path(formula);
+
+        // A keg left by an interrupted install of ours is fair game
+        let resuming = self.install_state.get(&formula.name)?.is_some();
+        if !force && !resuming && self.is_foreign_keg(&formula.name, &formula.pkg_version(), &keg_path) {
+            return Err(NitroError::Other(format!(
+                "{} is a Homebrew-managed keg; use --force to overwrite it", keg_path.display()
+            )).into());
+        }
+
+        self.install_state.begin(formula, keg_path.clone(), build_from_source)?;
+        if on_request {
+            self.install_state.set_on_request(&formula.name)?;
+        }
+
+        // Everything from staging the keg to registering it is undone if any of it fails
+        let tx = self.installer.begin(formula)?;
+        let cancel = match timeout {
+            Some(timeout) => CancellationToken::with_timeout(format!("installing {}", formula.name), timeout),
+            None => CancellationToken::new(),
+        };
+        let mut result = self.installer.install(formula, build_from_source, &self.install_state, &tx, &cancel).await;
+        if let Err(NitroError::ChecksumMismatch { expected, actual }) = &result {
+            if let Some(checksum_override) = checksum_override(formula, expected, actual, sha256_override)? {
+                let mut accepted = formula.clone();
+                accepted.sources[0].sha256 = checksum_override.accepted.clone();
+                // Nothing was staged from the rejected download, so this starts over
+                self.install_state.begin(&accepted, keg_path, true)?;
+                if on_request {
+                    self.install_state.set_on_request(&formula.name)?;
+                }
+                self.install_state.set_checksum_override(&formula.name, checksum_override)?;
+                result = self.installer.install(&accepted, true, &self.install_state, &tx, &cancel).await;
+            }
+        }
+
+        let result = match result {
+            Ok(()) => self.register_in(formula, &tx).await,
+            Err(e) => Err(e.into()),
+        };
+        match result {
+            Ok(()) => {
+                tx.commit();
+                Ok(())
+            }
+            Err(e) => {
+                // Leave the prefix as it was. If that fails the install stays pending,
+                // for `nitro abort` to finish the job.
+                match tx.rollback() {
+                    Ok(()) => self.install_state.complete(&formula.name)?,
+                    Err(rollback) => eprintln!("Warning: could not roll back the install of {}: {}", formula.name, rollback),
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Register `formula`, recording its previous entry in `tx`.
+    async fn register_in(&self, formula: &super::formula::Formula, tx: &Transaction) -> Result<()> {
+        tx.record_db_entry(&self.db, &formula.name)?;
+        self.register(formula).await
+    }
+
+    /// Whether the keg at `keg` belongs to Homebrew: it has a brew receipt, no nitro
+    /// marker, and isn't the version nitro has registered for `name`.
+    fn is_foreign_keg(&self, name: &str, version: &str, keg: &Path) -> bool {
+        if keg_owner(keg) != Some(KegOwner::Homebrew) {
+            return false;
+        }
+        !self.get_package(name)
+            .map(|p| p.installed && p.installed_version.as_deref().unwrap_or(&p.version) == version)
+            .unwrap_or(false)
+    }
+
+    async fn register(&self, formula: &super::formula::Formula) -> Result<()> {
+        // A pinned formula came from the pinned commit, not wherever the tap is now
+        let tap_manager = self.formula_manager.tap_manager();
+        let tap_commit = match (tap_manager.formula_pin(&formula.name)?, &formula.tap) {
+            (Some((pin, _)), _) => pin.tap_commit,
+            (None, Some(tap)) => tap_manager.tap_commit(tap).await,
+            (None, None) => None,
+        };
+        let record = self.install_state.get(&formula.name)?;
+        let checksum_override = record.as_ref().and_then(|r| r.checksum_override.clone());
+        // Reinstalling or upgrading a package the user asked for keeps it asked for
+        let on_request = record.as_ref().is_some_and(|r| r.on_request)
+            || self.get_package(&formula.name).is_ok_and(|p| p.installed_on_request);
+        let source = record.and_then(|r| r.source);
+        let manifest = Manifest::read(&self.installer.get_keg_path(formula));
+        let linked = manifest.as_ref().map(|m| !m.links.is_empty());
+        self.mark_installed(formula, tap_commit.clone(), checksum_override.clone(), on_request, linked)?;
+        if let Some(manifest) = manifest {
+            self.manifests.insert(&manifest)?;
+        }
+        self.install_state.advance(&formula.name, InstallPhase::Registered)?;
+        self.install_state.complete(&formula.name)?;
+        self.record_audit(formula, tap_commit, source, checksum_override.map(|o| o.accepted));
+        Self::update_shell_rc(&formula.name, Some(formula));
+        Ok(())
+    }
+
+    /// Add the install of `formula` to the prefix's audit log. The install has happened
+    /// either way, so failing to is a warning.
+    fn record_audit(&self, formula: &super::formula::Formula, tap_commit: Option<String>, source: Option<InstallSource>, checksum_override: Option<String>) {
+        use super::audit::AuditLog;
+
+        let result = AuditLog::new(AuditLog::default_path(self.installer.install_prefix()))
+            .with_heads(&self.db)
+            .and_then(|log| log.record_install(formula, tap_commit, source, checksum_override));
+        if let Err(e) = result {
+            eprintln!("Warning: could not add {} to the audit log: {}", formula.name, e);
+        }
+    }
+
+    /// With `install.apply_shell_rc` set, add `formula`'s lines to the user's shell
+    /// startup file, or with `None`, remove `name`'s. Failing to is only worth a warning.
+    fn update_shell_rc(name: &str, formula: Option<&super::formula::Formula>) {
+        use super::shellrc::RcFile;
+
+        if !crate::core::config::Config::load().is_ok_and(|c| c.install.apply_shell_rc) {
+            return;
+        }
+        // Shells nitro doesn't know have no lines to add or remove
+        let Ok(rc) = RcFile::detect(None) else {
+            return;
+        };
+        let result = match formula {
+            Some(formula) if rc.shell.lines(formula).is_empty() => Ok(false),
+            Some(formula) => rc.apply(formula),
+            None => rc.remove(name),
+        };
+        if let Err(e) = result {
+            eprintln!("Warning: could not update the shell startup file for {}: {}", name, e);
+        }
+    }
+
+    /// Installs that were started but never registered.
+    pub fn pending_installs(&self) -> Result<Vec<InstallRecord>> {
+        self.install_state.pending()
+    }
+
+    /// Continue interrupted installs from their last durable phase.
+    pub async fn resume(&self, record: &InstallRecord) -> Result<()> {
+        let formula = &record.formula;
+
+        match record.phase {
+            InstallPhase::Linked | InstallPhase::Registered => {
+                self.register(formula).await?;
+            }
+            InstallPhase::Staged if record.keg_path.exists() => {
+                // The keg was already there; only linking and registering are undone
+                let tx = self.installer.begin(formula)?;
+                self.installer.link(formula, &tx).await?;
+                self.install_state.advance(&formula.name, InstallPhase::Linked)?;
+                self.register_in(formula, &tx).await?;
+                tx.commit();
+            }
+            _ => {
+                // Downloads live in temporary directories, so earlier phases start over
+                self.install_formula(formula, record.build_from_source, false, None, None, record.on_request).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Roll back an interrupted install and forget its state.
+    pub async fn abort(&self, record: &InstallRecord) -> Result<()> {
+        let formula = &record.formula;
+
+        // A keg may exist from the point the build starts writing into the Cellar
+        if record.phase >= InstallPhase::Verified {
+            let registered = self.get_package(&formula.name)
+                .map(|p| p.installed && p.installed_version.as_deref() == Some(&formula.pkg_version()))
+                .unwrap_or(false);
+
+            if registered {
+                eprintln!("Warning: {} {} is registered as installed; leaving its keg in place",
+                    formula.name, formula.version);
+            } else {
+                self.installer.rollback(formula).await?;
+            }
+        }
+
+        self.install_state.complete(&formula.name)?;
+        Ok(())
+    }
+
+    /// Install cask `token` from whichever tap has it and record the files it placed.
+    pub async fn install_cask(&self, token: &str, args: &InstallArgs) -> Result<()> {
+        use super::cask::CaskParser;
+        use super::cask_installer::{self, CaskTargets};
+
+        if !args.force && self.casks.get(token)?.is_some() {
+            return Err(NitroError::Other(format!("{} is already installed", token)).into());
+        }
+
+        let tap_manager = self.formula_manager.tap_manager();
+        let path = tap_manager.find_cask(token).await?;
+        let tap = tap_manager.tap_for_path(&path).await;
+        if let Some(tap) = &tap {
+            Policy::load()?.check_tap(tap)?;
+        }
+        let cask = CaskParser::new().parse_file(&path)?;
+        let targets = CaskTargets {
+            font_dir: match &args.fontdir {
+                Some(dir) => dir.clone(),
+                None => super::fonts::user_font_dir()?,
+            },
+            app_dir: args.appdir.clone().unwrap_or_else(|| PathBuf::from("/Applications")),
+            force: args.force,
+        };
+
+        // Reinstalling over ourselves replaces our own files, whatever --force says. The
+        // previous install stays recorded, and in place, unless the new one succeeds.
+        let previous = self.casks.get(token)?;
+        let mut installed = cask_installer::install(&cask, &Downloader::shared()?, &targets, previous.as_ref()).await?;
+        installed.tap = tap;
+        self.casks.insert(&installed)?;
+        Ok(())
+    }
+
+    /// Installed casks with a newer version in their tap, as (token, installed, latest).
+    /// With no `tokens`, every installed cask is checked.
+    pub async fn outdated_casks(&self, tokens: &[String], greedy: super::cask::Greedy) -> Result<Vec<(String, String, String)>> {
+        use super::cask::CaskParser;
+
+        let installed = if tokens.is_empty() {
+            self.casks.list()?
+        } else {
+            let mut casks = Vec::new();
+            for token in tokens {
+                casks.push(self.casks.get(token)?
+                    .ok_or_else(|| NitroError::PackageNotFound(format!("cask {}", token)))?);
+            }
+            casks
+        };
+
+        let tap_manager = self.formula_manager.tap_manager();
+        let mut outdated = Vec::new();
+        for cask in installed {
+            let path = match tap_manager.find_cask(&cask.token).await {
+                Ok(path) => path,
+                Err(e) => {
+                    eprintln!("Warning: skipping {}: {}", cask.token, e);
+                    continue;
+                }
+            };
+            let latest = CaskParser::new().parse_file(&path)?;
+            if latest.is_outdated(&cask.version, greedy) {
+                outdated.push((cask.token, cask.version, latest.version));
+            }
+        }
+        outdated.sort();
+        Ok(outdated)
+    }
+
+    /// Installed casks, sorted by token.
+    pub fn installed_casks(&self) -> Result<Vec<super::cask_installer::InstalledCask>> {
+        let mut casks = self.casks.list()?;
+        casks.sort_by(|a, b| a.token.cmp(&b.token));
+        Ok(casks)
+    }
+
+    /// The installed record for cask `token`, if it is installed.
+    pub fn installed_cask(&self, token: &str) -> Result<Option<super::cask_installer::InstalledCask>> {
+        self.casks.get(token)
+    }
+
+    /// Remove an installed cask's files and forget it.
+    pub fn uninstall_cask(&self, token: &str) -> Result<()> {
+        let cask = self.casks.get(token)?
+            .ok_or_else(|| NitroError::PackageNotFound(format!("cask {}", token)))?;
+        super::cask_installer::uninstall(&cask)?;
+        self.casks.remove(token)?;
         Ok(())
     }
 
     pub async fn uninstall(&self, package_name: &str, args: &UninstallArgs) -> Result<()> {
+        // Casks have their own namespace; fall back to it only when no formula matches
+        if args.cask || (!self.is_installed(package_name)? && self.casks.get(package_name)?.is_some()) {
+            return self.uninstall_cask(package_name);
+        }
+
         if !self.is_installed(package_name)? {
             return Err(NitroError::PackageNotFound(package_name.to_string()).into());
         }
@@ -101,13 +626,89 @@ impl PackageManager {
             }
         }
 
+        // Uninstalling removes the whole formula directory; don't take brew's kegs with it
+        if !args.force {
+            if let Some(formula_dir) = &package.install_path {
+                for entry in std::fs::read_dir(formula_dir).into_iter().flatten().flatten() {
+                    let version = entry.file_name().to_string_lossy().to_string();
+                    if self.is_foreign_keg(package_name, &version, &entry.path()) {
+                        return Err(NitroError::Other(format!(
+                            "{} also contains Homebrew-managed keg {}; use --force to remove it too",
+                            formula_dir.display(), version
+                        )).into());
+                    }
+                }
+            }
+        }
+
         // Uninstall the package
-        self.installer.uninstall(&package).await?;
+        let manifest = self.manifests.get(package_name)?;
+        self.installer.uninstall(&package, manifest.as_ref()).await?;
+        self.manifests.remove(package_name)?;
         self.mark_uninstalled(package_name)?;
+        Self::update_shell_rc(package_name, None);
+
+        Ok(())
+    }
+
+    /// Every installed package.
+    pub fn installed_packages(&self) -> Result<Vec<Package>> {
+        let mut packages = Vec::new();
+        for entry in self.db.iter() {
+            let (_key, value) = entry?;
+            let package: Package = serde_json::from_slice(&value)?;
+            if package.installed {
+                packages.push(package);
+            }
+        }
+        packages.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(packages)
+    }
+
+    /// Kegs in the Cellar beside the version of their formula in use (see
+    /// [`super::cleanup::old_kegs`]).
+    pub fn old_kegs(&self) -> Result<Vec<super::cleanup::OldKeg>> {
+        let installed = self.installed_packages()?.into_iter()
+            .filter_map(|p| Some((p.name, p.installed_version?)))
+            .collect();
+        Ok(super::cleanup::old_kegs(self.installer.cellar(), self.installer.install_prefix(), &installed))
+    }
 
+    /// Remove `kegs` and any links still pointing into them.
+    pub fn remove_kegs(&self, kegs: &[super::cleanup::OldKeg]) -> Result<()> {
+        for keg in kegs {
+            for link in self.installer.unlink_keg(&keg.path)? {
+                tracing::info!("Removed {}, which pointed into {} {}", link.display(), keg.name, keg.version);
+            }
+            std::fs::remove_dir_all(&keg.path)?;
+        }
         Ok(())
     }
 
+    /// Installed packages no other installed package depends on.
+    pub fn leaves(&self) -> Result<Vec<Package>> {
+        let packages = self.installed_packages()?;
+        Ok(leaves(&packages, &Providers::load()).into_iter().cloned().collect())
+    }
+
+    /// Uninstall the packages installed only as dependencies that nothing needs any
+    /// more (see [`orphans`]), returning their names. With `dry_run`, only name them.
+    pub async fn autoremove(&self, dry_run: bool) -> Result<Vec<String>> {
+        let packages = self.installed_packages()?;
+        let names: Vec<String> = orphans(&packages, &Providers::load()).iter().map(|p| p.name.clone()).collect();
+        if dry_run {
+            return Ok(names);
+        }
+        let args = UninstallArgs { packages: names.clone(), force: false, all_versions: false, cask: false };
+        for name in &names {
+            if !crate::ui::is_quiet() {
+                println!("Uninstalling {}", name);
+            }
+            self.uninstall(name, &args).await?;
+        }
+        Ok(names)
+    }
+
     pub async fn list_installed(&self, args: &ListArgs) -> Result<Vec<Package>> {
         let mut packages = Vec::new();
         
@@ -121,23 +722,42 @@ impl PackageManager {
                         continue;
                     }
                 }
+                if let Some(tap) = &args.tap {
+                    if package.tap.as_deref() != Some(tap.as_str()) {
+                        continue;
+                    }
+                }
+                if args.search.as_ref().is_some_and(|q| package.match_score(q).is_none()) {
+                    continue;
+                }
                 packages.push(package);
             }
         }
 
         packages.sort_by(|a, b| a.name.cmp(&b.name));
+        if let Some(query) = &args.search {
+            // Best matches first; the sort is stable so ties stay alphabetical
+            packages.sort_by_key(|p| std::cmp::Reverse(p.match_score(query)));
+        }
         Ok(packages)
     }
 
-    pub async fn check_updates(&self, packages: &[String]) -> Result<Vec<(String, String, String)>> {
+    /// Installed packages with a newer formula version, optionally only those installed from `tap`.
+    pub async fn check_updates(&self, packages: &[String], tap: Option<&str>) -> Result<Vec<(String, String, String)>> {
         let mut updates = Vec::new();
         
         let installed = if packages.is_empty() {
-            self.list_installed(&ListArgs::default()).await?
+            self.list_installed(&ListArgs {
+                tap: tap.map(str::to_string),
+                ..Default::default()
+            }).await?
         } else {
             let mut pkgs = Vec::new();
             for name in packages {
                 if let Ok(pkg) = self.get_package(name) {
+                    if tap.is_some() && pkg.tap.as_deref() != tap {
+                        continue;
+                    }
                     pkgs.push(pkg);
                 }
             }
@@ -145,30 +765,234 @@ impl PackageManager {
         };
 
         for package in installed {
-            let formula = self.formula_manager.get_formula(&package.name).await?;
-            if formula.version != package.version {
-                updates.push((package.name, package.version, formula.version));
+            let formula = self.installed_formula(&package).await?;
+            let installed = package.installed_version.clone().unwrap_or_else(|| package.version.clone());
+            let newer_scheme = formula.version_scheme > package.version_scheme;
+            let (latest, current) = (PkgVersion::new(formula.version.as_str(), formula.revision), PkgVersion::parse(&installed));
+            // An undetected version can't be compared either way
+            let newer_version = !latest.version.is_unknown() && !current.version.is_unknown() && latest > current;
+            if newer_scheme || (formula.version_scheme == package.version_scheme && newer_version) {
+                updates.push((package.name, installed, formula.pkg_version()));
             }
         }
 
         Ok(updates)
     }
 
-    pub async fn update_packages(&self, args: &UpdateArgs) -> Result<()> {
-        let updates = self.check_updates(&args.packages).await?;
+    /// Upgrade the outdated formulae among `packages` (all of them when empty), each
+    /// into a keg of its new version alongside the old one. The new keg's links replace
+    /// the old ones as it's installed; links left into the old keg are removed after,
+    /// and with `cleanup` so is the old keg. Pinned formulae are skipped unless `force`.
+    pub async fn update_packages(&self, packages: &[String], tap: Option<&str>, cleanup: bool, force: bool) -> Result<()> {
+        let updates = self.check_updates(packages, tap).await?;
         
-        for (name, _, _) in updates {
-            println!("Updating {}...", name);
-            self.install(&name, &InstallArgs {
+        for (name, installed, latest) in updates {
+            let package = self.get_package(&name)?;
+            if package.pinned && !force {
+                if !crate::ui::is_quiet() {
+                    println!("Not upgrading {}: pinned at {} ({} is available; unpin it or use --force)", name, installed, latest);
+                }
+                continue;
+            }
+            if !crate::ui::is_quiet() {
+                println!("Updating {}...", name);
+            }
+            // Upgrade from the tap it came from, not whichever tap has the name first
+            let formula = self.installed_formula(&package).await?;
+            let new_version = formula.pkg_version();
+            self.install_resolved(formula, &InstallArgs {
                 packages: vec![name.clone()],
                 force: true,
                 ..Default::default()
-            }).await?;
+            }, false).await?;
+
+            let Some(old_version) = package.installed_version.filter(|v| *v != new_version) else {
+                continue;
+            };
+            let old_keg = self.installer.get_install_path(&name).join(&old_version);
+            // They'd run the old version, or nothing once it's cleaned up
+            for link in self.installer.unlink_keg(&old_keg)? {
+                tracing::info!("Removed {}, which {} {} no longer has", link.display(), name, new_version);
+            }
+            if cleanup && old_keg.exists() {
+                if !crate::ui::is_quiet() {
+                    println!("Removing {} {}", name, old_version);
+                }
+                std::fs::remove_dir_all(&old_keg)?;
+            }
         }
 
         Ok(())
     }
 
+    /// The audit log at `path`, with its head kept in the package database.
+    pub fn audit_log(&self, path: std::path::PathBuf) -> Result<super::audit::AuditLog> {
+        super::audit::AuditLog::new(path).with_heads(&self.db)
+    }
+
+    /// Show how installs are going on `progress`.
+    pub fn set_progress(&mut self, progress: crate::ui::progress::ProgressReporter) {
+        self.installer.set_progress(progress);
+    }
+
+    pub fn formula_manager(&self) -> &super::formula::FormulaManager {
+        &self.formula_manager
+    }
+
+    /// The formula file `package` was installed from, as it was then: the recorded file
+    /// if it hasn't changed since, or that file at the recorded tap commit. `None` when
+    /// the receipt doesn't say, or the history no longer has it.
+    pub async fn formula_as_installed(&self, package: &Package) -> Option<super::formula::Formula> {
+        use super::formula::{content_hash, FormulaParser};
+
+        let (Some(path), Some(hash)) = (&package.formula_path, &package.formula_hash) else {
+            return None;
+        };
+        let current = std::fs::read_to_string(path).ok().filter(|content| content_hash(content.as_bytes()) == *hash);
+        let content = match (current, &package.tap, &package.tap_commit) {
+            (Some(content), _, _) => content,
+            (None, Some(tap), Some(commit)) => {
+                let content = self.formula_manager.tap_manager().formula_at_commit(tap, commit, path).await.ok()?;
+                (content_hash(content.as_bytes()) == *hash).then_some(content)?
+            }
+            _ => return None,
+        };
+        FormulaParser::new().parse_content(&content).ok()
+    }
+
+    /// The current formula for an installed package, looked up where it was installed
+    /// from: its pin, the recorded formula file, then the recorded tap, then by name.
+    pub async fn installed_formula(&self, package: &Package) -> NitroResult<super::formula::Formula> {
+        if let Some(formula) = self.formula_manager.pinned_formula(&package.name).await? {
+            return Ok(formula);
+        }
+        if let Some(path) = package.formula_path.as_deref().filter(|p| p.exists()) {
+            return self.formula_manager.get_formula_at(path).await;
+        }
+        if let Some(tap) = &package.tap {
+            match self.formula_manager.get_formula_in_tap(&package.name, tap).await {
+                Ok(formula) => return Ok(formula),
+                Err(e) => eprintln!("Warning: {} not found in {} ({}); looking it up by name", package.name, tap, e),
+            }
+        }
+        self.formula_manager.get_formula(&package.name).await
+    }
+
+    /// Pin formula `name` so lookups keep using its current file. For an installed
+    /// package that is the file it was installed from, fetched from the tap's history
+    /// if the tap has moved on since; `bump` pins the tap's current file instead.
+    pub async fn pin_formula(&self, name: &str, bump: bool) -> Result<FormulaPin> {
+        use super::formula::content_hash;
+
+        let tap_manager = self.formula_manager.tap_manager();
+        let path = tap_manager.find_formula(name).await?;
+        let current = std::fs::read_to_string(&path)?;
+        let tap = tap_manager.tap_for_path(&path).await;
+
+        let installed = self.installed_package(name)?.filter(|_| !bump);
+        let (content, tap_commit) = match installed {
+            Some(Package { formula_hash: Some(hash), tap_commit, formula_path: Some(installed_path), tap: Some(installed_tap), .. })
+                if content_hash(current.as_bytes()) != hash =>
+            {
+                let commit = tap_commit.ok_or_else(|| NitroError::Other(format!(
+                    "{} has changed since it was installed and the tap commit wasn't recorded; use --bump to pin the current formula", name
+                )))?;
+                let content = tap_manager.formula_at_commit(&installed_tap, &commit, &installed_path).await?;
+                if content_hash(content.as_bytes()) != hash {
+                    return Err(NitroError::Other(format!("{} at {} doesn't match the installed formula", name, commit)).into());
+                }
+                (content, Some(commit))
+            }
+            _ => {
+                let commit = match &tap {
+                    Some(tap) => tap_manager.tap_commit(tap).await,
+                    None => None,
+                };
+                (current, commit)
+            }
+        };
+
+        let pin = FormulaPin {
+            name: name.to_string(),
+            tap,
+            hash: content_hash(content.as_bytes()),
+            tap_commit,
+            pinned_at: chrono::Utc::now(),
+        };
+        tap_manager.pin_formula(&pin, &content)?;
+        Ok(pin)
+    }
+
+    /// The installed record for `package_name`, if it is installed.
+    pub fn installed_package(&self, package_name: &str) -> Result<Option<Package>> {
+        match self.get_package(package_name) {
+            Ok(package) if package.installed => Ok(Some(package)),
+            Ok(_) | Err(NitroError::PackageNotFound(_)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Annotate each direct dependency of `formula` with its kind, installed version and bottle availability.
+    pub async fn dependency_status(&self, formula: &super::formula::Formula) -> Result<Vec<DependencyStatus>> {
+        // The search index answers bottle availability without parsing each dependency
+        let index = crate::search::SearchEngine::new().await.ok();
+        let platform = Installer::platform_tag();
+
+        let mut statuses = Vec::new();
+        for dep in &formula.dependencies {
+            let indexed = index.as_ref().and_then(|i| i.lookup(&dep.name).ok().flatten());
+            let bottle = match indexed {
+                Some(indexed) => Some(indexed.has_bottle_for(&platform)),
+                None => self.formula_manager.get_formula(&dep.name).await.ok()
+                    .map(|f| self.installer.has_bottle(&f)),
+            };
+            statuses.push(DependencyStatus {
+                name: dep.name.clone(),
+                kind: dep.kind(),
+                installed_version: self.installed_package(&dep.name)?.and_then(|p| p.installed_version),
+                bottle,
+            });
+        }
+        Ok(statuses)
+    }
+
+    /// What installing `formula` would involve: its full runtime dependency closure in
+    /// install order, followed by the formula itself. Nothing is downloaded or changed.
+    pub async fn install_plan(&self, formula: &super::formula::Formula) -> Result<Vec<PlanEntry>> {
+        let mut formulae = self.resolver.resolve(formula, &self.formula_manager, &self.installed_names()?).await?;
+        formulae.retain(|f| f.name != formula.name);
+        formulae.push(formula.clone());
+
+        let sizes = futures::future::join_all(
+            formulae.iter().map(|f| self.installer.download_size(f, false))
+        ).await;
+
+        let mut plan = Vec::new();
+        for (f, download_size) in formulae.iter().zip(sizes) {
+            plan.push(PlanEntry {
+                name: f.name.clone(),
+                version: f.version.clone(),
+                installed_version: self.installed_package(&f.name)?.and_then(|p| p.installed_version),
+                bottle: self.installer.has_bottle(f),
+                download_size,
+            });
+        }
+        Ok(plan)
+    }
+
+    /// Names of every installed formula.
+    fn installed_names(&self) -> Result<std::collections::HashSet<String>> {
+        let mut names = std::collections::HashSet::new();
+        for entry in self.db.iter() {
+            let (key, data) = entry?;
+            let package: Package = serde_json::from_slice(&data)?;
+            if package.installed {
+                names.insert(String::from_utf8_lossy(&key).into_owned());
+            }
+        }
+        Ok(names)
+    }
+
     fn is_installed(&self, package_name: &str) -> Result<bool> {
         if let Some(data) = self.db.get(package_name)? {
             let package: Package = serde_json::from_slice(&data)?;
@@ -187,23 +1011,59 @@ impl PackageManager {
         }
     }
 
-    fn mark_installed(&self, formula: &super::formula::Formula) -> Result<()> {
+    fn mark_installed(&self, formula: &super::formula::Formula, tap_commit: Option<String>, checksum_override: Option<ChecksumOverride>, on_request: bool, linked: Option<bool>) -> Result<()> {
+        let keg = self.installer.get_keg_path(formula);
         let package = Package {
             name: formula.name.clone(),
             version: formula.version.clone(),
             description: formula.description.clone(),
             homepage: formula.homepage.clone(),
             installed: true,
-            installed_version: Some(formula.version.clone()),
-            dependencies: formula.dependencies.iter().map(|d| d.name.clone()).collect(),
+            installed_version: Some(formula.pkg_version()),
+            dependencies: formula.dependencies.iter()
+                .filter(|d| d.is_runtime())
+                .map(|d| d.name.clone())
+                .collect(),
             install_path: Some(self.installer.get_install_path(&formula.name)),
-            size: None, // TODO: Calculate installed size
+            size: keg.exists().then(|| super::disk::size_of(&keg)),
+            tap: formula.tap.clone(),
+            version_scheme: formula.version_scheme,
+            formula_path: formula.path.clone(),
+            formula_hash: formula.source_hash.clone(),
+            tap_commit,
+            checksum_override,
+            installed_on_request: on_request,
+            // Reinstalling a pinned package keeps it pinned
+            pinned: self.get_package(&formula.name).is_ok_and(|p| p.pinned),
+            installed_at: Some(super::deterministic::now()),
+            linked,
         };
 
         self.db.insert(&formula.name, serde_json::to_vec(&package)?)?;
         Ok(())
     }
 
+    /// Pin the installed package `name` at its version, or with `pinned` false, unpin it.
+    pub fn set_pinned(&self, name: &str, pinned: bool) -> Result<Package> {
+        let mut package = self.get_package(name)?;
+        if !package.installed {
+            return Err(NitroError::PackageNotFound(name.to_string()).into());
+        }
+        package.pinned = pinned;
+        self.db.insert(name, serde_json::to_vec(&package)?)?;
+        Ok(package)
+    }
+
+    /// Record that the user asked for the installed package `name`.
+    fn mark_on_request(&self, name: &str) -> Result<()> {
+        let mut package = self.get_package(name)?;
+        if !package.installed_on_request {
+            package.installed_on_request = true;
+            self.db.insert(name, serde_json::to_vec(&package)?)?;
+        }
+        Ok(())
+    }
+
     fn mark_uninstalled(&self, package_name: &str) -> Result<()> {
         self.db.remove(package_name)?;
         Ok(())
@@ -224,7 +1084,7 @@ impl PackageManager {
         Ok(dependents)
     }
 
-    async fn resolve_package_formula(&self, package_name: &str) -> Result<super::formula::Formula> {
+    pub async fn resolve_package_formula(&self, package_name: &str) -> Result<super::formula::Formula> {
         eprintln!("DEBUG: Resolving package formula for: {}", package_name);
         
         // Try common aliases first
@@ -313,6 +1173,13 @@ impl PackageManager {
             description: true,
             fuzzy: true,
             limit: 10,
+            tap: None,
+            cask: false,
+            formula: true,
+            any: false,
+            open: None,
+            info: None,
+            interactive: false,
         };
         let results = search_engine.search(package_name, &search_args).await?;
         
@@ -340,27 +1207,125 @@ impl PackageManager {
     }
 }
 
-impl Default for ListArgs {
-    fn default() -> Self {
-        Self {
-            versions: false,
-            installed: false,
-            size: false,
-            prefix: None,
+impl Drop for PackageManager {
+    fn drop(&mut self) {
+        // Ensure the database is properly flushed before dropping
+        let _ = self.db.flush();
+    }
+}
+
+/// Whether any package in `packages` other than `package` depends on it, directly or
+/// through a capability it provides.
+fn has_dependents(package: &Package, packages: &[&Package], providers: &Providers) -> bool {
+    packages.iter().any(|other| {
+        other.name != package.name && other.dependencies.iter().any(|d| providers.satisfies(d, &package.name))
+    })
+}
+
+/// The installed packages in `packages` that no other installed package depends on.
+pub fn leaves<'a>(packages: &'a [Package], providers: &Providers) -> Vec<&'a Package> {
+    let installed: Vec<&Package> = packages.iter().filter(|p| p.installed).collect();
+    installed.iter()
+        .filter(|package| !has_dependents(package, &installed, providers))
+        .copied()
+        .collect()
+}
+
+/// The packages `autoremove` removes: those installed only as dependencies that nothing
+/// depends on once the others are gone, in an order that uninstalls dependents first.
+pub fn orphans<'a>(packages: &'a [Package], providers: &Providers) -> Vec<&'a Package> {
+    let mut remaining: Vec<&Package> = packages.iter().filter(|p| p.installed).collect();
+    let mut orphans = Vec::new();
+    loop {
+        let (removable, kept): (Vec<&Package>, Vec<&Package>) = remaining.iter()
+            .partition(|package| !package.installed_on_request && !has_dependents(package, &remaining, providers));
+        if removable.is_empty() {
+            return orphans;
+        }
+        orphans.extend(removable);
+        remaining = kept;
+    }
+}
+
+/// Make sure every formula in `formulae` comes from a trusted tap, or that the user
+/// accepts the ones that don't after seeing what they download. When trust is required
+/// (by the config, the policy or `--require-trusted`) they're refused outright. Without
+/// a terminal to ask on they're refused too, unless `--allow-untrusted` accepts them.
+fn check_tap_trust(formulae: &[&super::formula::Formula], policy: &Policy) -> Result<()> {
+    use std::io::IsTerminal;
+
+    let config = crate::core::config::Config::load()?.taps;
+    let required = config.require_trusted || policy.require_trusted_taps || super::tap::trust_required();
+    for formula in formulae {
+        // Formulae without a tap come from the Homebrew API
+        let Some(tap) = &formula.tap else {
+            continue;
+        };
+        if config.trust(tap).is_trusted() {
+            continue;
+        }
+        if required {
+            return Err(NitroError::UntrustedTap(format!(
+                "{} comes from {}, and only trusted taps may be installed from", formula.name, tap
+            )).into());
+        }
+        if super::tap::untrusted_allowed() {
+            eprintln!("Warning: installing {} from untrusted tap {}", formula.name, tap);
+            continue;
+        }
+        if !std::io::stdin().is_terminal() {
+            return Err(NitroError::UntrustedTap(format!(
+                "{} comes from untrusted tap {}, and there's no terminal to confirm it on. Trust the tap in the config's [taps.trust], or run again with --allow-untrusted",
+                formula.name, tap
+            )).into());
+        }
+
+        let mut downloads: Vec<(String, Option<String>)> = Vec::new();
+        if let Some(bottle) = super::installer::bottle_for(formula, &Installer::platform_tag()) {
+            downloads.push((bottle.url.clone(), Some(bottle.sha256.clone())));
+        }
+        downloads.extend(formula.sources.iter().map(|source| {
+            (source.url.clone(), Some(source.sha256.clone()).filter(|sha256| !sha256.is_empty()))
+        }));
+        if !crate::ui::display::confirm_untrusted_tap(&formula.name, tap, &downloads) {
+            return Err(NitroError::UntrustedTap(format!("not installing {} from {}", formula.name, tap)).into());
         }
     }
+    Ok(())
 }
 
-impl Default for InstallArgs {
-    fn default() -> Self {
-        Self {
-            packages: vec![],
-            force: false,
-            build_from_source: false,
-            only_deps: false,
-            skip_deps: false,
-            version: None,
-            debug: false,
+/// Whether to install `formula` from a source tarball whose checksum is `actual`
+/// rather than the formula's `expected`: with `--sha256-override` it must match
+/// exactly, otherwise the user is shown both and asked. Mismatches other than the
+/// source tarball's are never overridden.
+fn checksum_override(formula: &super::formula::Formula, expected: &str, actual: &str, sha256_override: Option<&str>) -> Result<Option<ChecksumOverride>> {
+    use std::io::IsTerminal;
+
+    let Some(source) = formula.sources.first().filter(|s| s.sha256 == expected) else {
+        return Ok(None);
+    };
+    let accepted = match sha256_override {
+        Some(hash) if hash.eq_ignore_ascii_case(actual) => true,
+        Some(hash) => {
+            return Err(NitroError::Other(format!(
+                "{} downloaded with checksum {}, which matches neither the formula ({}) nor --sha256-override ({})",
+                formula.name, actual, expected, hash
+            )).into());
         }
+        None => std::io::stdin().is_terminal()
+            && crate::ui::display::confirm_checksum_override(&formula.name, &source.url, expected, actual),
+    };
+    if !accepted {
+        return Err(NitroError::Other(format!(
+            "Checksum mismatch for {}: expected {}, got {}. If upstream re-rolled the tarball, rerun with --sha256-override {}",
+            source.url, expected, actual, actual
+        )).into());
     }
-}
\ No newline at end of file
+
+    Ok(Some(ChecksumOverride {
+        url: source.url.clone(),
+        expected: expected.to_string(),
+        accepted: actual.to_string(),
+        accepted_at: super::deterministic::now(),
+    }))
+}
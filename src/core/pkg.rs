@@ -0,0 +1,141 @@
+//! macOS `.pkg` installers: run through `installer -pkg`, tracked by the package
+//! receipts (`pkgutil`) they leave behind, and removed by their receipts' file lists.
+
+use anyhow::Result;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::core::NitroError;
+
+/// Run the installer package at `pkg` into `/` and return the IDs of the receipts it
+/// added. Uses sudo unless already running as root.
+pub fn install(pkg: &Path) -> Result<Vec<String>> {
+    ensure_supported()?;
+    let before = installed_ids()?;
+
+    println!("Running installer for {} (this may ask for your password)", pkg.display());
+    let mut command = privileged("installer");
+    command.arg("-pkg").arg(pkg).args(["-target", "/"]);
+    run(&mut command)?;
+
+    Ok(new_ids(&before, &installed_ids()?))
+}
+
+/// Remove everything the receipt `id` lists, then forget the receipt. Directories are
+/// only removed once empty, so ones shared with other packages survive. A receipt
+/// that's gone, e.g. forgotten by hand or by the package's own uninstaller, counts as
+/// already removed.
+pub fn uninstall(id: &str) -> Result<()> {
+    ensure_supported()?;
+    if !installed_ids()?.contains(id) {
+        return Ok(());
+    }
+    let info = output(Command::new("pkgutil").args(["--pkg-info", id]))?;
+    let root = receipt_root(&info);
+
+    let files = output(Command::new("pkgutil").args(["--only-files", "--files", id]))?;
+    let files = receipt_paths(&root, &files);
+    for chunk in files.chunks(100) {
+        run(privileged("rm").arg("-f").args(chunk))?;
+    }
+
+    let dirs = output(Command::new("pkgutil").args(["--only-dirs", "--files", id]))?;
+    let mut dirs = receipt_paths(&root, &dirs);
+    // Deepest first, so children go before their parents
+    dirs.sort_by_key(|d| std::cmp::Reverse(d.components().count()));
+    for dir in dirs {
+        if std::fs::read_dir(&dir).map(|mut entries| entries.next().is_none()).unwrap_or(false) {
+            // A directory something else still uses isn't worth failing the uninstall over
+            let _ = run(privileged("rmdir").arg(&dir));
+        }
+    }
+
+    run(privileged("pkgutil").args(["--forget", id]))
+}
+
+/// Installed receipt IDs matching `pattern`, where `*` matches any run of characters
+/// (`uninstall pkgutil: "com.microsoft.package.*"`).
+pub fn matching_ids(pattern: &str) -> Result<Vec<String>> {
+    ensure_supported()?;
+    Ok(installed_ids()?.into_iter().filter(|id| id_matches(pattern, id)).collect())
+}
+
+/// Whether receipt `id` matches `pattern`, where `*` matches any run of characters.
+pub fn id_matches(pattern: &str, id: &str) -> bool {
+    let regex = format!("^{}$", pattern.split('*').map(regex::escape).collect::<Vec<_>>().join(".*"));
+    regex::Regex::new(&regex).map(|re| re.is_match(id)).unwrap_or(false)
+}
+
+/// Receipt IDs in `after` that weren't in `before`.
+pub fn new_ids(before: &BTreeSet<String>, after: &BTreeSet<String>) -> Vec<String> {
+    after.difference(before).cloned().collect()
+}
+
+/// Where a receipt's paths are relative to, from `pkgutil --pkg-info` output: its
+/// `volume` joined with its `location`.
+pub fn receipt_root(info: &str) -> PathBuf {
+    let field = |name: &str| info.lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+        .map(str::trim)
+        .unwrap_or_default()
+        .to_string();
+    let volume = field("volume");
+    let root = PathBuf::from(if volume.is_empty() { "/".to_string() } else { volume });
+    root.join(field("location"))
+}
+
+/// Absolute paths for the relative paths `pkgutil --files` lists, one per line.
+pub fn receipt_paths(root: &Path, listing: &str) -> Vec<PathBuf> {
+    listing.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| root.join(line))
+        .collect()
+}
+
+fn installed_ids() -> Result<BTreeSet<String>> {
+    Ok(output(Command::new("pkgutil").arg("--pkgs"))?.lines().map(str::to_string).collect())
+}
+
+fn ensure_supported() -> Result<()> {
+    if !cfg!(target_os = "macos") {
+        return Err(NitroError::Other(".pkg installers are only supported on macOS".into()).into());
+    }
+    Ok(())
+}
+
+/// `program`, through sudo unless we're root already.
+fn privileged(program: &str) -> Command {
+    let is_root = Command::new("id").arg("-u").output()
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim() == "0")
+        .unwrap_or(false);
+    if is_root {
+        Command::new(program)
+    } else {
+        let mut command = Command::new("sudo");
+        command.arg(program);
+        command
+    }
+}
+
+fn output(command: &mut Command) -> Result<String> {
+    let output = command.output()
+        .map_err(|e| NitroError::Other(format!("Failed to run {:?}: {}", command.get_program(), e)))?;
+    if !output.status.success() {
+        return Err(NitroError::Other(
+            format!("Command failed: {}", String::from_utf8_lossy(&output.stderr))
+        ).into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn run(command: &mut Command) -> Result<()> {
+    // sudo may need the terminal for a password prompt, so nothing is captured
+    let status = command.status()
+        .map_err(|e| NitroError::Other(format!("Failed to run {:?}: {}", command.get_program(), e)))?;
+    if !status.success() {
+        return Err(NitroError::Other(format!("{:?} exited with {}", command.get_program(), status)).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,139 @@
+//! Admin-managed policy for managed machines: which formulae and taps may be used,
+//! versions formulae are held at, and licenses that may not be installed. Consulted
+//! before every install and tap; a missing file means no restrictions, and while the
+//! system file exists `NITRO_POLICY` can't point elsewhere.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::core::formula::Formula;
+use crate::core::{NitroError, NitroResult};
+
+/// Where the policy file lives. Outside the user's config so only an admin can change
+/// it; `NITRO_POLICY` only names another file on machines without one here.
+pub const DEFAULT_PATH: &str = "/etc/nitro/policy.toml";
+
+/// Names a list applies to. Entries may use `*` to match any run of characters
+/// (`"mycorp/*"`).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NameRules {
+    /// When non-empty, only these may be used
+    pub allow: Vec<String>,
+    /// Never usable, even when also allowed
+    pub block: Vec<String>,
+}
+
+impl NameRules {
+    /// Why `name` is not permitted, if it isn't.
+    fn denies(&self, name: &str) -> Option<&'static str> {
+        if self.block.iter().any(|pattern| glob_matches(pattern, name)) {
+            Some("is blocked")
+        } else if !self.allow.is_empty() && !self.allow.iter().any(|pattern| glob_matches(pattern, name)) {
+            Some("is not on the allow list")
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Policy {
+    pub formulae: NameRules,
+    pub taps: NameRules,
+    /// Formula name to the only version that may be installed
+    pub required_pins: BTreeMap<String, String>,
+    /// SPDX identifiers of licenses that may not be installed, compared case-insensitively
+    pub forbidden_licenses: Vec<String>,
+    /// Refuse installs from taps the user's config doesn't trust, whatever the config's
+    /// own `require_trusted` says
+    pub require_trusted_taps: bool,
+}
+
+impl Policy {
+    pub fn path() -> PathBuf {
+        Self::path_from(Path::new(DEFAULT_PATH), std::env::var_os("NITRO_POLICY").map(PathBuf::from))
+    }
+
+    /// The policy file given the system one and the `NITRO_POLICY` override. An existing
+    /// system file always wins, so a user can't lift an admin's policy by pointing the
+    /// variable at an empty file.
+    pub fn path_from(system: &Path, env_override: Option<PathBuf>) -> PathBuf {
+        match env_override {
+            Some(path) if !system.exists() => path,
+            _ => system.to_path_buf(),
+        }
+    }
+
+    /// Load the policy. An unreadable or invalid file is an error rather than no
+    /// policy, so a typo never lifts the restrictions.
+    pub fn load() -> Result<Self> {
+        let path = Self::path();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let data = std::fs::read_to_string(&path)
+            .map_err(|e| NitroError::Other(format!("Could not read policy file {}: {}", path.display(), e)))?;
+        toml::from_str(&data).map_err(|e| {
+            NitroError::Other(format!("Invalid policy file {}: {}", path.display(), e)).into()
+        })
+    }
+
+    /// Fail unless `formula` may be installed: its name, its tap, its license and,
+    /// when the policy pins it, its version.
+    pub fn check_formula(&self, formula: &Formula) -> NitroResult<()> {
+        if let Some(reason) = self.formulae.denies(&formula.name) {
+            return Err(NitroError::BlockedByPolicy(format!("formula {} {}", formula.name, reason)));
+        }
+        // Formulae from the JSON API have no tap of their own but are homebrew/core's
+        let tap = match (&formula.tap, &formula.path) {
+            (Some(tap), _) => Some(tap.as_str()),
+            (None, None) => Some(crate::core::tap::CORE_TAP),
+            (None, Some(_)) => None,
+        };
+        if let Some(tap) = tap {
+            self.check_tap(tap)
+                .map_err(|_| NitroError::BlockedByPolicy(format!("{} comes from tap {}, which is not permitted", formula.name, tap)))?;
+        }
+        if let Some(license) = &formula.license {
+            let ids = license_ids(license);
+            if let Some(forbidden) = self.forbidden_licenses.iter().find(|f| ids.contains(&f.to_lowercase())) {
+                return Err(NitroError::BlockedByPolicy(format!("{} is licensed under {}, which is forbidden", formula.name, forbidden)));
+            }
+        }
+        if let Some(required) = self.required_pins.get(&formula.name) {
+            if *required != formula.version && *required != formula.pkg_version() {
+                return Err(NitroError::BlockedByPolicy(format!(
+                    "{} must stay at version {} (found {})", formula.name, required, formula.version
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fail unless tap `name` may be added or installed from.
+    pub fn check_tap(&self, name: &str) -> NitroResult<()> {
+        match self.taps.denies(name) {
+            Some(reason) => Err(NitroError::BlockedByPolicy(format!("tap {} {}", name, reason))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Whether `name` matches `pattern`, where `*` matches any run of characters.
+pub fn glob_matches(pattern: &str, name: &str) -> bool {
+    let regex = format!("^{}$", pattern.split('*').map(regex::escape).collect::<Vec<_>>().join(".*"));
+    regex::RegexBuilder::new(&regex).case_insensitive(true).build().map(|re| re.is_match(name)).unwrap_or(false)
+}
+
+/// The lowercased license identifiers in a license field, which may be a single
+/// SPDX ID or an expression like `any_of: ["MIT", "Apache-2.0"]`.
+fn license_ids(license: &str) -> Vec<String> {
+    license.split(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+')))
+        .filter(|id| !id.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
@@ -0,0 +1,74 @@
+//! Virtual dependencies: capabilities such as a JDK or an MPI implementation that any of
+//! several formulae provide. A dependency on the capability's name, or on its default
+//! (first) provider, is satisfied by whichever provider is installed; with none
+//! installed, the default provider is used, or for the bare capability the user picks
+//! when installing (other commands take the most preferred).
+//! Dependencies on any other provider (`openjdk@17`) mean exactly that formula.
+
+use std::collections::BTreeMap;
+
+/// Built-in capabilities and their providers, most preferred first. The `[providers]`
+/// section of the config file replaces or adds to these.
+const BUILTIN: &[(&str, &[&str])] = &[
+    ("java", &["openjdk", "openjdk@21", "openjdk@17", "openjdk@11", "openjdk@8"]),
+    ("mpi", &["open-mpi", "mpich"]),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Providers {
+    groups: BTreeMap<String, Vec<String>>,
+}
+
+impl Default for Providers {
+    fn default() -> Self {
+        Self {
+            groups: BUILTIN.iter()
+                .map(|(name, providers)| (name.to_string(), providers.iter().map(|p| p.to_string()).collect()))
+                .collect(),
+        }
+    }
+}
+
+impl Providers {
+    /// The built-in capabilities with `overrides` from the config applied. A
+    /// capability overridden with an empty list is dropped.
+    pub fn with_overrides(overrides: &BTreeMap<String, Vec<String>>) -> Self {
+        let mut providers = Self::default();
+        for (name, list) in overrides {
+            if list.is_empty() {
+                providers.groups.remove(name);
+            } else {
+                providers.groups.insert(name.clone(), list.clone());
+            }
+        }
+        providers
+    }
+
+    /// The built-in capabilities with the config's applied; just the built-in ones if
+    /// the config can't be read.
+    pub fn load() -> Self {
+        match crate::core::config::Config::load() {
+            Ok(config) => Self::with_overrides(&config.providers),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// The capability `dependency` stands for and its providers, if it stands for one:
+    /// it's a capability's name or its default provider.
+    pub fn capability(&self, dependency: &str) -> Option<(&str, &[String])> {
+        self.groups.iter()
+            .find(|(name, providers)| *name == dependency || providers.first().is_some_and(|p| p == dependency))
+            .map(|(name, providers)| (name.as_str(), providers.as_slice()))
+    }
+
+    /// Whether the formula `name` satisfies `dependency`: it's that formula, or
+    /// `dependency` stands for a capability `name` provides.
+    pub fn satisfies(&self, dependency: &str, name: &str) -> bool {
+        dependency == name || self.capability(dependency).is_some_and(|(_, providers)| providers.iter().any(|p| p == name))
+    }
+
+    /// Whether `dependency` is a capability's name rather than a formula.
+    pub fn is_virtual(&self, dependency: &str) -> bool {
+        self.groups.contains_key(dependency)
+    }
+}
@@ -0,0 +1,66 @@
+//! Dangling symlinks in the directories nitro links into, left behind when an uninstall
+//! fails halfway or a keg is deleted from the Cellar by hand.
+
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// A symlink whose target no longer exists.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BrokenLink {
+    pub link: PathBuf,
+    pub target: PathBuf,
+    /// The formula whose keg the link pointed into, when the target says
+    pub owner: Option<String>,
+}
+
+/// The directories to scan: the link directory, plus the prefix's other link
+/// directories when nitro links into the prefix itself. A prefix shared with Homebrew
+/// (nitro linking elsewhere) is brew's to tidy.
+pub fn link_dirs(prefix: &Path, link_dir: &Path) -> Vec<PathBuf> {
+    let mut dirs = vec![link_dir.to_path_buf()];
+    if link_dir == prefix.join("bin") {
+        dirs.extend(["sbin", "lib", "include", "share", "etc", "opt"].iter().map(|dir| prefix.join(dir)));
+    }
+    dirs
+}
+
+/// The formula a link target points into: `.../Cellar/<name>/...` or `.../opt/<name>`.
+/// The last `opt` counts, since the prefix itself may be `/opt/homebrew`.
+pub fn link_owner(target: &Path) -> Option<String> {
+    let components: Vec<String> = target.iter().map(|c| c.to_string_lossy().to_string()).collect();
+    let after = |marker: &str| components.iter().rposition(|c| c == marker).and_then(|i| components.get(i + 1));
+    after("Cellar").or_else(|| after("opt")).cloned()
+}
+
+/// The broken symlinks under `dirs`, sorted by path. Directories that don't exist are
+/// skipped, and links to directories are never followed.
+pub fn broken_links(dirs: &[PathBuf]) -> Vec<BrokenLink> {
+    let mut links = Vec::new();
+    for dir in dirs.iter().filter(|dir| dir.is_dir()) {
+        for entry in walkdir::WalkDir::new(dir).follow_links(false).into_iter().filter_map(|e| e.ok()) {
+            if !entry.path_is_symlink() || entry.path().exists() {
+                continue;
+            }
+            let Ok(target) = std::fs::read_link(entry.path()) else {
+                continue;
+            };
+            links.push(BrokenLink { link: entry.path().to_path_buf(), owner: link_owner(&target), target });
+        }
+    }
+    links.sort_by(|a, b| a.link.cmp(&b.link));
+    links
+}
+
+/// Remove `links`, returning the ones removed. A link that has been fixed since it was
+/// found (its target exists again) is left alone.
+pub fn prune(links: &[BrokenLink]) -> std::io::Result<Vec<&BrokenLink>> {
+    let mut removed = Vec::new();
+    for link in links {
+        if link.link.exists() || std::fs::symlink_metadata(&link.link).is_err() {
+            continue;
+        }
+        std::fs::remove_file(&link.link)?;
+        removed.push(link);
+    }
+    Ok(removed)
+}
@@ -0,0 +1,43 @@
+use anyhow::Result;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Switch the process to read-only mode. Informational commands (search, info, list)
+/// call this so they work without write access and never change any state: no
+/// directories are created, nothing is cloned or cached, and databases are read from
+/// a throwaway snapshot.
+pub fn enable() {
+    READ_ONLY.store(true, Ordering::SeqCst);
+}
+
+pub fn is_enabled() -> bool {
+    READ_ONLY.load(Ordering::SeqCst)
+}
+
+/// Open a copy of the sled database at `path`. sled has no read-only mode (opening a
+/// database takes a lock and may run recovery), so the copy is what gets opened; it
+/// lives in the temp directory and is deleted when the returned handle is dropped.
+/// A missing database opens as an empty one.
+pub fn open_snapshot(path: &Path) -> Result<sled::Db> {
+    let name = path.file_name().and_then(|s| s.to_str()).unwrap_or("db");
+    let snapshot = std::env::temp_dir().join(format!("nitro-readonly-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&snapshot);
+
+    if path.is_dir() {
+        for entry in walkdir::WalkDir::new(path).into_iter().flatten() {
+            let Ok(relative) = entry.path().strip_prefix(path) else {
+                continue;
+            };
+            let target = snapshot.join(relative);
+            if entry.file_type().is_dir() {
+                std::fs::create_dir_all(&target)?;
+            } else {
+                std::fs::copy(entry.path(), &target)?;
+            }
+        }
+    }
+
+    Ok(sled::Config::new().path(&snapshot).temporary(true).open()?)
+}
@@ -0,0 +1,280 @@
+//! Requirements a formula places on the machine rather than on other formulae:
+//! `depends_on :xcode`, `depends_on macos:` and `depends_on arch:`. Checked before
+//! anything is downloaded, so an unsupported macOS release or CPU is reported up front
+//! and a missing Xcode with instructions, instead of as a build or runtime failure.
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::process::Command;
+
+use crate::core::formula::Formula;
+use crate::core::ruby::{Call, Node};
+use crate::core::version::Version;
+
+/// macOS release names as formulae spell them, newest first.
+const MACOS_RELEASES: &[(&str, &str)] = &[
+    ("tahoe", "26"),
+    ("sequoia", "15"),
+    ("sonoma", "14"),
+    ("ventura", "13"),
+    ("monterey", "12"),
+    ("big_sur", "11"),
+    ("catalina", "10.15"),
+    ("mojave", "10.14"),
+    ("high_sierra", "10.13"),
+    ("sierra", "10.12"),
+    ("el_capitan", "10.11"),
+    ("yosemite", "10.10"),
+];
+
+const XCODE_APP_STORE: &str = "https://apps.apple.com/app/xcode/id497799835";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Comparator {
+    AtLeast,
+    AtMost,
+    Exactly,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Requirement {
+    /// The full Xcode app (not just the Command Line Tools), optionally at least `version`
+    Xcode { version: Option<String>, build_only: bool },
+    /// macOS, optionally a range of releases; `version` is numeric (`13`, `10.15`)
+    Macos { comparator: Comparator, version: Option<String>, build_only: bool },
+    /// A CPU architecture, `arm64` or `x86_64`
+    Arch { arch: String, build_only: bool },
+}
+
+/// The facts about this machine requirements are checked against.
+#[derive(Debug, Clone, Default)]
+pub struct Host {
+    pub macos: Option<Version>,
+    pub xcode: Option<Version>,
+    pub command_line_tools: bool,
+    /// `arm64` or `x86_64`; `None` when unknown
+    pub arch: Option<String>,
+}
+
+impl Host {
+    /// Look at the running system. Off macOS everything but the architecture is
+    /// `None`/`false`.
+    pub fn detect() -> Self {
+        let arch = arch_name(std::env::consts::ARCH).map(str::to_string);
+        if !cfg!(target_os = "macos") {
+            return Self { arch, ..Self::default() };
+        }
+        let stdout = |program: &str, args: &[&str]| {
+            Command::new(program).args(args).output().ok()
+                .filter(|o| o.status.success())
+                .map(|o| String::from_utf8_lossy(&o.stdout).into_owned())
+        };
+
+        // xcodebuild refuses to run when only the Command Line Tools are selected
+        let xcode = stdout("xcodebuild", &["-version"])
+            .and_then(|out| out.lines().next()?.strip_prefix("Xcode ").map(|v| Version::new(v.trim())));
+        Self {
+            macos: stdout("sw_vers", &["-productVersion"]).map(|v| Version::new(v.trim())),
+            command_line_tools: xcode.is_some() || Path::new("/Library/Developer/CommandLineTools/usr/bin/clang").exists(),
+            xcode,
+            arch,
+        }
+    }
+}
+
+/// The architecture an `arch:` requirement or CPU name means: `arm64` (`:arm`,
+/// `aarch64`) or `x86_64` (`:intel`, `amd64`).
+pub fn arch_name(name: &str) -> Option<&'static str> {
+    match name.trim().trim_start_matches(':').trim_matches('"') {
+        "arm64" | "arm" | "aarch64" => Some("arm64"),
+        "x86_64" | "intel" | "amd64" => Some("x86_64"),
+        _ => None,
+    }
+}
+
+/// The numeric version of a macOS release name (`ventura` → `13`), or a version
+/// given as a number unchanged.
+pub fn macos_release(name: &str) -> Option<String> {
+    let name = name.trim().trim_start_matches(':').trim_matches('"');
+    if name.chars().next().is_some_and(|c| c.is_ascii_digit()) {
+        return Some(name.to_string());
+    }
+    MACOS_RELEASES.iter().find(|(release, _)| *release == name).map(|(_, version)| version.to_string())
+}
+
+fn release_name(version: &str) -> Option<&'static str> {
+    MACOS_RELEASES.iter().find(|(_, v)| *v == version).map(|(name, _)| *name)
+}
+
+/// The requirement a `depends_on :xcode`, `depends_on macos:`, `maximum_macos:` or
+/// `arch:` stanza places, or `None` for a formula dependency.
+pub fn from_depends_on(call: &Call) -> Option<Requirement> {
+    let (kind, value) = match call.args.first()? {
+        Node::Symbol(kind) => (kind.as_str(), None),
+        Node::Hash(pairs) => match pairs.first()? {
+            (Node::Symbol(kind), value) => (kind.as_str(), Some(value)),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    // `:ventura`, `"12.0"`, `">= :big_sur"` or `[:sonoma, :build]`
+    let values: Vec<String> = match value {
+        Some(Node::Array(items)) => items.iter().filter_map(Node::as_str).collect(),
+        Some(value) => value.as_str().into_iter().collect(),
+        None => Vec::new(),
+    };
+    let build_only = values.iter().any(|v| v == "build");
+    let spec = values.iter().find(|v| *v != "build").map(|v| v.trim());
+
+    match kind {
+        "xcode" => Some(Requirement::Xcode { version: spec.map(str::to_string), build_only }),
+        "arch" => Some(Requirement::Arch { arch: arch_name(spec?)?.to_string(), build_only }),
+        "macos" | "maximum_macos" => {
+            let spec = spec.unwrap_or_default();
+            let (comparator, release) = match spec {
+                s if s.starts_with(">=") => (Comparator::AtLeast, &s[2..]),
+                s if s.starts_with("<=") => (Comparator::AtMost, &s[2..]),
+                s if s.starts_with("==") => (Comparator::Exactly, &s[2..]),
+                s if kind == "maximum_macos" => (Comparator::AtMost, s),
+                s => (Comparator::AtLeast, s),
+            };
+            let release = release.trim();
+            Some(Requirement::Macos {
+                comparator,
+                version: if release.is_empty() { None } else { macos_release(release) },
+                build_only,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Requirements from the `requirements` array of the formulae.brew.sh JSON API.
+pub fn from_api_json(entries: &serde_json::Value) -> Vec<Requirement> {
+    let Some(entries) = entries.as_array() else {
+        return Vec::new();
+    };
+    entries.iter().filter_map(|entry| {
+        let version = entry["version"].as_str().map(str::to_string);
+        let build_only = entry["contexts"].as_array()
+            .is_some_and(|contexts| contexts.iter().any(|c| c == "build"));
+        match entry["name"].as_str()? {
+            "xcode" => Some(Requirement::Xcode { version, build_only }),
+            "macos" => Some(Requirement::Macos { comparator: Comparator::AtLeast, version, build_only }),
+            "maximum_macos" => Some(Requirement::Macos { comparator: Comparator::AtMost, version, build_only }),
+            "arch" => Some(Requirement::Arch { arch: arch_name(version.as_deref()?)?.to_string(), build_only }),
+            _ => None,
+        }
+    }).collect()
+}
+
+impl std::fmt::Display for Requirement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Requirement::Xcode { version: Some(version), .. } => write!(f, "Xcode {} or newer", version)?,
+            Requirement::Xcode { version: None, .. } => write!(f, "Xcode")?,
+            Requirement::Macos { version: None, .. } => write!(f, "macOS")?,
+            Requirement::Arch { arch, .. } => write!(f, "{} architecture", arch)?,
+            Requirement::Macos { comparator, version: Some(version), .. } => {
+                let range = match comparator {
+                    Comparator::AtLeast => " or newer",
+                    Comparator::AtMost => " or older",
+                    Comparator::Exactly => "",
+                };
+                write!(f, "macOS {}{}", version, range)?;
+            }
+        }
+        if self.build_only() {
+            write!(f, " (build)")?;
+        }
+        Ok(())
+    }
+}
+
+impl Requirement {
+    pub fn build_only(&self) -> bool {
+        match self {
+            Requirement::Xcode { build_only, .. }
+            | Requirement::Macos { build_only, .. }
+            | Requirement::Arch { build_only, .. } => *build_only,
+        }
+    }
+
+    /// Why `host` doesn't meet the requirement and what to do about it, if it doesn't.
+    /// Xcode requirements, and macOS ones naming a version, only apply on macOS.
+    pub fn unmet(&self, formula: &str, host: &Host) -> Option<String> {
+        match self {
+            Requirement::Xcode { version, .. } => {
+                host.macos.as_ref()?;
+                let wanted = version.as_deref().map(|v| format!(" {} or newer", v)).unwrap_or_default();
+                match &host.xcode {
+                    None => Some(format!(
+                        "{} requires Xcode{}. Install it from the App Store ({}), then run `sudo xcode-select --switch /Applications/Xcode.app`",
+                        formula, wanted, XCODE_APP_STORE
+                    )),
+                    Some(installed) if version.as_ref().is_some_and(|v| installed < &Version::new(v.as_str())) => Some(format!(
+                        "{} requires Xcode{}, but Xcode {} is installed. Update it from the App Store ({})",
+                        formula, wanted, installed, XCODE_APP_STORE
+                    )),
+                    Some(_) => None,
+                }
+            }
+            Requirement::Macos { comparator, version, .. } => {
+                // A bare `depends_on :macos` rules Linux out; a versioned one only says
+                // which macOS, and Homebrew ignores it elsewhere
+                let Some(installed) = &host.macos else {
+                    return version.is_none().then(|| format!("{} requires macOS", formula));
+                };
+                let version = version.as_deref()?;
+                let release = macos_major(installed.as_str());
+                let wanted = macos_major(version);
+                let (met, range) = match comparator {
+                    Comparator::AtLeast => (release >= wanted, "or newer"),
+                    Comparator::AtMost => (release <= wanted, "or older"),
+                    Comparator::Exactly => (release == wanted, "exactly"),
+                };
+                let name = release_name(version).map(|n| format!(" ({})", n)).unwrap_or_default();
+                (!met).then(|| format!(
+                    "{} requires macOS {}{} {}; this machine runs {}", formula, version, name, range, installed
+                ))
+            }
+            Requirement::Arch { arch, .. } => {
+                let host_arch = host.arch.as_deref()?;
+                (host_arch != arch).then(|| format!(
+                    "{} requires an {} machine; this one is {}", formula, arch, host_arch
+                ))
+            }
+        }
+    }
+}
+
+/// A macOS version reduced to its release: `14.5` → (14, 0), `10.15.7` → (10, 15).
+fn macos_major(version: &str) -> (u32, u32) {
+    let mut parts = version.split('.').map(|p| p.parse::<u32>().unwrap_or(0));
+    let major = parts.next().unwrap_or(0);
+    let minor = if major == 10 { parts.next().unwrap_or(0) } else { 0 };
+    (major, minor)
+}
+
+/// Everything stopping `formula` from being installed at all on `host`: its
+/// requirements other than those only its build has.
+pub fn unmet_for_install(formula: &Formula, host: &Host) -> Vec<String> {
+    formula.requirements.iter()
+        .filter(|r| !r.build_only())
+        .filter_map(|r| r.unmet(&formula.name, host))
+        .collect()
+}
+
+/// Everything stopping `formula` from being built from source on `host`: its own
+/// requirements, and on macOS the Command Line Tools every build needs.
+pub fn unmet_for_source_build(formula: &Formula, host: &Host) -> Vec<String> {
+    let mut unmet: Vec<String> = formula.requirements.iter()
+        .filter_map(|r| r.unmet(&formula.name, host))
+        .collect();
+    if host.macos.is_some() && !host.command_line_tools {
+        unmet.push("Building from source requires the Command Line Tools. Install them with `xcode-select --install`".to_string());
+    }
+    unmet
+}
@@ -1,25 +1,68 @@
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 
-use super::formula::{Formula, FormulaManager};
+use super::formula::{Dependency, Formula, FormulaManager};
+use super::providers::Providers;
 use crate::core::{NitroError, NitroResult};
 
+/// Which of the root formula's optional and test dependencies to install too. Those of
+/// its dependencies never are, as with brew.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResolveOptions {
+    pub include_optional: bool,
+    pub include_test: bool,
+    /// Ask which provider to install for a capability with none installed, when
+    /// there's a terminal to ask on. Only installs ask; anything else takes the
+    /// most preferred one.
+    pub choose_providers: bool,
+}
+
+impl ResolveOptions {
+    /// Whether the root formula's dependency `dep` is installed with it.
+    pub fn wants(&self, dep: &Dependency) -> bool {
+        (!dep.optional || self.include_optional) && (!dep.test_only || self.include_test)
+    }
+}
+
+#[derive(Default)]
 pub struct DependencyResolver {
-    // The resolver is currently stateless. A cache could be added here later.
+    providers: Providers,
 }
 
 impl DependencyResolver {
+    /// A resolver using the configured providers for virtual dependencies.
     pub fn new() -> Self {
-        Self {}
+        Self::with_providers(Providers::load())
+    }
+
+    pub fn with_providers(providers: Providers) -> Self {
+        Self { providers }
     }
 
-    pub async fn resolve(&self, formula: &Formula, formula_manager: &FormulaManager) -> NitroResult<Vec<Formula>> {
-        let mut resolved = Vec::new();
+    /// The dependencies `formula` needs, in install order. Dependencies on a capability
+    /// (see `core::providers`) are met by whichever provider of it is in `installed`;
+    /// with none installed, the most preferred one when the dependency names the
+    /// capability itself. Resolved formulae list the providers in place of capabilities.
+    pub async fn resolve(&self, formula: &Formula, formula_manager: &FormulaManager, installed: &HashSet<String>) -> NitroResult<Vec<Formula>> {
+        self.resolve_with(formula, formula_manager, installed, ResolveOptions::default()).await
+    }
+
+    /// `resolve`, also taking in the optional and test dependencies of `formula` that
+    /// `options` asks for.
+    pub async fn resolve_with(
+        &self,
+        formula: &Formula,
+        formula_manager: &FormulaManager,
+        installed: &HashSet<String>,
+        options: ResolveOptions,
+    ) -> NitroResult<Vec<Formula>> {
+        let mut resolved: Vec<Formula> = Vec::new();
         let mut seen = HashSet::new();
         let mut queue = VecDeque::new();
+        let mut substitutions: HashMap<String, String> = HashMap::new();
 
         // Add initial dependencies to queue
         for dep in &formula.dependencies {
-            if !dep.optional {
+            if options.wants(dep) {
                 queue.push_back(dep.clone());
             }
         }
@@ -36,8 +79,23 @@ impl DependencyResolver {
             }
             seen.insert(dep.name.clone());
 
+            let name = match self.provider_for(&dep.name, installed, options.choose_providers) {
+                Provider::Installed(provider) => {
+                    substitutions.insert(dep.name.clone(), provider);
+                    continue;
+                }
+                Provider::Chosen(provider) => {
+                    substitutions.insert(dep.name.clone(), provider.clone());
+                    if !seen.insert(provider.clone()) {
+                        continue;
+                    }
+                    provider
+                }
+                Provider::Itself => dep.name.clone(),
+            };
+
             // Get formula for dependency, handling special name mappings
-            let dep_formula = match formula_manager.get_formula(&dep.name).await {
+            let dep_formula = match formula_manager.get_formula(&name).await {
                 Ok(f) => f,
                 Err(_) => {
                     // Try common dependency name variations
@@ -72,7 +130,7 @@ impl DependencyResolver {
 
             // Add sub-dependencies to queue
             for sub_dep in &dep_formula.dependencies {
-                if !sub_dep.optional && !seen.contains(&sub_dep.name) {
+                if sub_dep.is_runtime() && !seen.contains(&sub_dep.name) {
                     queue.push_back(sub_dep.clone());
                 }
             }
@@ -80,12 +138,39 @@ impl DependencyResolver {
             resolved.push(dep_formula);
         }
 
+        for formula in &mut resolved {
+            for dep in &mut formula.dependencies {
+                if let Some(provider) = substitutions.get(&dep.name) {
+                    dep.name = provider.clone();
+                }
+            }
+        }
+
         // Sort by dependency order (topological sort)
         let sorted = self.topological_sort(resolved)?;
         
         Ok(sorted)
     }
 
+    /// What meets the dependency `name`: an installed provider of the capability it
+    /// stands for, another one picked for it (by the user, with `ask`), or just the
+    /// formula by that name.
+    fn provider_for(&self, name: &str, installed: &HashSet<String>, ask: bool) -> Provider {
+        if installed.contains(name) {
+            return Provider::Itself;
+        }
+        let Some((capability, providers)) = self.providers.capability(name) else {
+            return Provider::Itself;
+        };
+        if let Some(provider) = providers.iter().find(|p| installed.contains(*p)) {
+            return Provider::Installed(provider.clone());
+        }
+        if !self.providers.is_virtual(name) {
+            return Provider::Itself;
+        }
+        Provider::Chosen(choose_provider(capability, providers, ask))
+    }
+
     fn check_conflicts(&self, formula: &Formula, resolved: &[Formula]) -> NitroResult<()> {
         // Check if this formula conflicts with any already resolved
         for resolved_formula in resolved {
@@ -103,72 +188,151 @@ impl DependencyResolver {
         Ok(())
     }
 
-    fn topological_sort(&self, formulae: Vec<Formula>) -> NitroResult<Vec<Formula>> {
-        // Build dependency graph
-        let mut graph: HashMap<String, Vec<String>> = HashMap::new();
-        let mut in_degree: HashMap<String, usize> = HashMap::new();
-        let mut formula_map: HashMap<String, Formula> = HashMap::new();
-
-        // Initialize graph
-        for formula in &formulae {
-            graph.insert(formula.name.clone(), Vec::new());
-            in_degree.insert(formula.name.clone(), 0);
-            formula_map.insert(formula.name.clone(), formula.clone());
-        }
+    /// Order `formulae` so each comes after its dependencies. Of the formulae ready at
+    /// any point, the one listed first goes first, so the result only depends on the
+    /// input order; deterministic mode sorts the input by name.
+    fn topological_sort(&self, mut formulae: Vec<Formula>) -> NitroResult<Vec<Formula>> {
+        super::deterministic::sort_by_key(&mut formulae, |f| f.name.clone());
+        let index: HashMap<&str, usize> = formulae.iter().enumerate().map(|(i, f)| (f.name.as_str(), i)).collect();
 
-        // Build edges
-        for formula in &formulae {
+        // Build dependency graph
+        let mut dependents: Vec<Vec<usize>> = vec![Vec::new(); formulae.len()];
+        let mut in_degree = vec![0usize; formulae.len()];
+        for (i, formula) in formulae.iter().enumerate() {
             for dep in &formula.dependencies {
-                if let Some(deps) = graph.get_mut(&dep.name) {
-                    deps.push(formula.name.clone());
-                    *in_degree.get_mut(&formula.name).unwrap() += 1;
+                if let Some(&d) = index.get(dep.name.as_str()) {
+                    dependents[d].push(i);
+                    in_degree[i] += 1;
                 }
             }
         }
 
-        // Kahn's algorithm for topological sort
-        let mut queue = VecDeque::new();
-        let mut sorted = Vec::new();
-
-        // Find nodes with no incoming edges
-        for (name, &degree) in &in_degree {
-            if degree == 0 {
-                queue.push_back(name.clone());
-            }
-        }
-
-        while let Some(name) = queue.pop_front() {
-            if let Some(formula) = formula_map.get(&name) {
-                sorted.push(formula.clone());
-            }
-
-            if let Some(dependents) = graph.get(&name) {
-                for dependent in dependents {
-                    let degree = in_degree.get_mut(dependent).unwrap();
-                    *degree -= 1;
-                    if *degree == 0 {
-                        queue.push_back(dependent.clone());
-                    }
+        // Kahn's algorithm, always taking the earliest ready formula
+        let mut ready: BTreeSet<usize> = (0..formulae.len()).filter(|&i| in_degree[i] == 0).collect();
+        let mut order = Vec::new();
+        while let Some(i) = ready.pop_first() {
+            order.push(i);
+            for &dependent in &dependents[i] {
+                in_degree[dependent] -= 1;
+                if in_degree[dependent] == 0 {
+                    ready.insert(dependent);
                 }
             }
         }
 
-        if sorted.len() != formulae.len() {
+        if order.len() != formulae.len() {
             return Err(NitroError::DependencyResolution(
                 "Circular dependency detected".into()
             ));
         }
 
-        Ok(sorted)
+        let mut formulae: Vec<Option<Formula>> = formulae.into_iter().map(Some).collect();
+        Ok(order.into_iter().filter_map(|i| formulae[i].take()).collect())
     }
 }
 
+enum Provider {
+    Installed(String),
+    Chosen(String),
+    Itself,
+}
+
+/// The provider to install for `capability`: the user's pick with `ask` and a terminal
+/// to ask on, otherwise the most preferred.
+fn choose_provider(capability: &str, providers: &[String], ask: bool) -> String {
+    use std::io::IsTerminal;
+
+    let interactive = ask && std::io::stdin().is_terminal() && !super::deterministic::is_enabled();
+    let choice = if interactive { crate::ui::display::choose_provider(capability, providers) } else { None };
+    let provider = providers[choice.unwrap_or(0)].clone();
+    if choice.is_none() {
+        eprintln!("Using {} for {} (one of {})", provider, capability, providers.join(", "));
+    }
+    provider
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use crate::core::formula::Dependency;
+
+    fn formula(name: &str, deps: &[&str]) -> Formula {
+        Formula {
+            name: name.to_string(),
+            version: "1.0".to_string(),
+            dependencies: deps.iter().map(|d| Dependency {
+                name: d.to_string(),
+                ..Default::default()
+            }).collect(),
+            ..Default::default()
+        }
+    }
+
     #[test]
     fn test_topological_sort() {
-        // TODO: Add tests for dependency resolution
+        let resolver = DependencyResolver::new();
+        let sorted = resolver.topological_sort(vec![
+            formula("app", &["libb", "liba"]),
+            formula("libb", &["liba"]),
+            formula("liba", &[]),
+        ]).unwrap();
+
+        let names: Vec<_> = sorted.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["liba", "libb", "app"]);
+    }
+
+    #[test]
+    fn test_topological_sort_detects_cycle() {
+        let resolver = DependencyResolver::new();
+        let result = resolver.topological_sort(vec![
+            formula("a", &["b"]),
+            formula("b", &["a"]),
+        ]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_topological_sort_is_stable() {
+        let resolver = DependencyResolver::new();
+        let input = || vec![
+            formula("zlib", &[]),
+            formula("curl", &["zlib", "openssl"]),
+            formula("openssl", &[]),
+            formula("brotli", &[]),
+        ];
+        // Independent formulae keep their input order, every time
+        for _ in 0..5 {
+            let sorted = resolver.topological_sort(input()).unwrap();
+            let names: Vec<_> = sorted.iter().map(|f| f.name.as_str()).collect();
+            assert_eq!(names, vec!["zlib", "openssl", "curl", "brotli"]);
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_provider_for() {
+        use std::collections::BTreeMap;
+
+        let overrides = BTreeMap::from([("blas".to_string(), vec!["openblas".to_string(), "blis".to_string()])]);
+        let resolver = DependencyResolver::with_providers(Providers::with_overrides(&overrides));
+        let installed = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<HashSet<_>>();
+        let name = |provider: Provider| match provider {
+            Provider::Installed(name) => format!("installed {}", name),
+            Provider::Chosen(name) => format!("chosen {}", name),
+            Provider::Itself => "itself".to_string(),
+        };
+
+        // Any installed provider meets a dependency on the default one
+        assert_eq!(name(resolver.provider_for("open-mpi", &installed(&["mpich"]), false)), "installed mpich");
+        assert_eq!(name(resolver.provider_for("openjdk", &installed(&["openjdk@17"]), false)), "installed openjdk@17");
+        assert_eq!(name(resolver.provider_for("openjdk", &installed(&["openjdk", "openjdk@17"]), false)), "itself");
+        // But not one on a specific other provider
+        assert_eq!(name(resolver.provider_for("openjdk@11", &installed(&["openjdk"]), false)), "itself");
+        // With none installed, the default provider is used as is, and a capability
+        // gets one picked (the most preferred, when not asking)
+        assert_eq!(name(resolver.provider_for("openjdk", &installed(&[]), false)), "itself");
+        assert_eq!(name(resolver.provider_for("blas", &installed(&[]), false)), "chosen openblas");
+        assert_eq!(name(resolver.provider_for("blas", &installed(&["blis"]), false)), "installed blis");
+        assert_eq!(name(resolver.provider_for("wget", &installed(&[]), false)), "itself");
+    }
+}
@@ -0,0 +1,1472 @@
+//! A parser for the Ruby that formula files are written in. It reads the syntax
+//! formulae use — strings with interpolation, heredocs, `%w[]` lists, method calls with
+//! and without parentheses, `do` and brace blocks, `if`/`unless`/`case` and `def` —
+//! into a tree of statements, and knows nothing of what any of it means. A statement
+//! it can't make sense of becomes `Node::Other`, skipped to the end of its line or
+//! its matching `end`, so one unusual construct costs that statement and not the file.
+
+use std::ops::Range;
+
+/// A piece of a string literal: literal text, or the Ruby code of a `#{...}`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StrPart {
+    Text(String),
+    Code(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(Vec<StrPart>),
+    Symbol(String),
+    Regex(String),
+    Array(Vec<Node>),
+    /// A hash literal, or the `key: value` and `key => value` arguments of a call
+    Hash(Vec<(Node, Node)>),
+    /// A bare name: a local variable, or a method called without receiver or arguments
+    Ident(String),
+    /// A constant, with its namespace: `Hardware::CPU`
+    Const(String),
+    Call(Call),
+    Index(Box<Node>, Vec<Node>),
+    Unary(&'static str, Box<Node>),
+    Binary(&'static str, Box<Node>, Box<Node>),
+    Ternary(Box<Node>, Box<Node>, Box<Node>),
+    /// `*args`, `**options` or `&block` among arguments
+    Splat(Box<Node>),
+    Assign(&'static str, Box<Node>, Box<Node>),
+    /// `if` and `unless`, as statements or modifiers; `elsif` nests in `otherwise`
+    If { negated: bool, cond: Box<Node>, then: Vec<Stmt>, otherwise: Vec<Stmt> },
+    Class { name: String, superclass: Option<Box<Node>>, body: Body },
+    Def { name: String, body: Body },
+    Lambda(Box<Block>),
+    /// `case`, `begin`, `while` and the like: their statements, in order
+    Compound(&'static str, Vec<Stmt>),
+    /// A statement that couldn't be parsed
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Call {
+    pub receiver: Option<Box<Node>>,
+    pub name: String,
+    pub args: Vec<Node>,
+    pub block: Option<Box<Block>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub params: Vec<String>,
+    pub body: Body,
+}
+
+/// The statements between a `do`, `def` or `class` line and its `end`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Body {
+    pub stmts: Vec<Stmt>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stmt {
+    pub node: Node,
+    /// Where the statement is in the source, heredoc bodies included
+    pub span: Range<usize>,
+}
+
+impl Node {
+    /// The call this node is, if it is one. A bare name counts as a call without
+    /// arguments.
+    pub fn call(&self) -> Option<&Call> {
+        match self {
+            Node::Call(call) => Some(call),
+            _ => None,
+        }
+    }
+
+    /// A string literal without interpolation, or a symbol, as text.
+    pub fn as_str(&self) -> Option<String> {
+        match self {
+            Node::Str(parts) => parts.iter()
+                .map(|part| match part {
+                    StrPart::Text(text) => Some(text.as_str()),
+                    StrPart::Code(_) => None,
+                })
+                .collect(),
+            Node::Symbol(symbol) => Some(symbol.clone()),
+            _ => None,
+        }
+    }
+
+    /// Whether anything in the node has a block or a body of statements.
+    fn is_compound(&self) -> bool {
+        match self {
+            Node::Call(call) => call.block.is_some()
+                || call.receiver.as_deref().is_some_and(Node::is_compound)
+                || call.args.iter().any(Node::is_compound),
+            Node::Array(items) => items.iter().any(Node::is_compound),
+            Node::Hash(pairs) => pairs.iter().any(|(k, v)| k.is_compound() || v.is_compound()),
+            Node::Index(node, args) => node.is_compound() || args.iter().any(Node::is_compound),
+            Node::Unary(_, node) | Node::Splat(node) => node.is_compound(),
+            Node::Binary(_, a, b) | Node::Assign(_, a, b) => a.is_compound() || b.is_compound(),
+            Node::Ternary(a, b, c) => a.is_compound() || b.is_compound() || c.is_compound(),
+            Node::If { .. } | Node::Class { .. } | Node::Def { .. } | Node::Lambda(_) | Node::Compound(..) | Node::Other => true,
+            _ => false,
+        }
+    }
+}
+
+impl Call {
+    /// The `key:` (or `:key =>`) option among the call's arguments.
+    pub fn option(&self, key: &str) -> Option<&Node> {
+        self.args.iter()
+            .filter_map(|arg| match arg {
+                Node::Hash(pairs) => Some(pairs),
+                _ => None,
+            })
+            .flatten()
+            .find(|(k, _)| matches!(k, Node::Symbol(s) if s == key))
+            .map(|(_, v)| v)
+    }
+
+    /// The statements of the call's block; none without one.
+    pub fn block_stmts(&self) -> &[Stmt] {
+        self.block.as_ref().map_or(&[], |block| &block.body.stmts)
+    }
+}
+
+/// A parsed file: its statements, and the source and tokens they were read from.
+pub struct Program<'a> {
+    source: &'a str,
+    tokens: Vec<Token>,
+    pub stmts: Vec<Stmt>,
+}
+
+impl<'a> Program<'a> {
+    pub fn parse(source: &'a str) -> Self {
+        let tokens = Lexer::new(source).run();
+        let mut parser = Parser { tokens: &tokens, pos: 0, no_do: false };
+        let stmts = parser.stmts(&[]).stmts;
+        Self { source, tokens, stmts }
+    }
+
+    /// The source text of `span`.
+    pub fn text(&self, span: &Range<usize>) -> &'a str {
+        &self.source[span.clone()]
+    }
+
+    /// The statements of `body` as source text, one per line: a call continued over
+    /// several lines is put back on one, and comments are left out. Statements with a
+    /// block, a heredoc or a body of their own are kept as written, less the
+    /// indentation of their first line.
+    pub fn script(&self, body: &Body) -> String {
+        let mut lines = Vec::new();
+        for stmt in &body.stmts {
+            let tokens: Vec<&Token> = self.tokens.iter()
+                .filter(|t| t.span.start >= stmt.span.start && t.span.end <= stmt.span.end && t.kind != Kind::Newline)
+                .collect();
+            if stmt.node.is_compound() || tokens.iter().any(|t| t.end > t.span.end) {
+                lines.push(self.dedented(&stmt.span));
+                continue;
+            }
+            let mut line = String::new();
+            for token in tokens {
+                if token.spaced && !line.is_empty() {
+                    line.push(' ');
+                }
+                line.push_str(&self.source[token.span.clone()]);
+            }
+            lines.push(line);
+        }
+        lines.join("\n")
+    }
+
+    fn dedented(&self, span: &Range<usize>) -> String {
+        let line_start = self.source[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        let indent = span.start - line_start;
+        let text = self.text(span);
+        let mut lines = text.lines();
+        let mut out = vec![lines.next().unwrap_or_default().to_string()];
+        for line in lines {
+            let strip = line.len() - line.trim_start_matches([' ', '\t']).len();
+            out.push(line[strip.min(indent)..].to_string());
+        }
+        out.join("\n")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Kind {
+    Ident(String),
+    Const(String),
+    Label(String),
+    Symbol(String),
+    Str(Vec<StrPart>),
+    Words(Vec<String>),
+    Regex(String),
+    Int(i64),
+    Float(f64),
+    Punct(&'static str),
+    Newline,
+    Eof,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: Kind,
+    span: Range<usize>,
+    /// Whitespace or a line break comes before it
+    spaced: bool,
+    /// Where its text ends: past the body, for a heredoc
+    end: usize,
+}
+
+/// Keywords after which an operand comes, so `/` starts a regex and `<<` a heredoc.
+const OPERAND_KEYWORDS: &[&str] = &[
+    "if", "unless", "while", "until", "and", "or", "not", "return", "when", "then", "else",
+    "elsif", "do", "case", "in", "begin", "rescue", "ensure", "yield", "puts",
+];
+
+/// Words that can't start a method's first argument when it's called without parentheses.
+const NOT_ARGUMENTS: &[&str] = &[
+    "do", "end", "if", "unless", "while", "until", "and", "or", "then", "rescue", "ensure",
+    "else", "elsif", "when", "in",
+];
+
+/// Longest first, so `**=` isn't read as `**` and `=`.
+const PUNCTUATION: &[&str] = &[
+    "**=", "<=>", "===", "...", "<<=", ">>=", "&&=", "||=",
+    "**", "==", "!=", ">=", "<=", "&&", "||", "<<", ">>", "+=", "-=", "*=", "/=", "%=", "|=", "&=",
+    "=~", "!~", "..", "::", "=>", "->", "&.",
+    "+", "-", "*", "/", "%", "=", "<", ">", "!", "&", "|", "^", "~", "?", ":", ",", ".",
+    "(", ")", "[", "]", "{", "}",
+];
+
+struct Heredoc {
+    token: usize,
+    id: String,
+    squiggly: bool,
+    indented: bool,
+    interpolating: bool,
+}
+
+struct Lexer<'a> {
+    src: &'a str,
+    pos: usize,
+    tokens: Vec<Token>,
+    heredocs: Vec<Heredoc>,
+    spaced: bool,
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || !c.is_ascii()
+}
+
+impl<'a> Lexer<'a> {
+    fn new(src: &'a str) -> Self {
+        Self { src, pos: 0, tokens: Vec::new(), heredocs: Vec::new(), spaced: true }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    fn peek_at(&self, n: usize) -> Option<char> {
+        self.src[self.pos..].chars().nth(n)
+    }
+
+    fn push(&mut self, kind: Kind, start: usize) {
+        self.tokens.push(Token { kind, span: start..self.pos, spaced: self.spaced, end: self.pos });
+        self.spaced = false;
+    }
+
+    /// Whether an operand is expected here rather than an operator: at the start of
+    /// an expression, or after a method name that's followed by a space but not by
+    /// one after the operator (`regex /x/` but not `a / b`).
+    fn expects_operand(&self, followed_by_space: bool) -> bool {
+        match self.tokens.last().map(|t| &t.kind) {
+            None | Some(Kind::Newline) | Some(Kind::Label(_)) => true,
+            Some(Kind::Punct(p)) => !matches!(*p, ")" | "]" | "}"),
+            Some(Kind::Ident(word)) if OPERAND_KEYWORDS.contains(&word.as_str()) => true,
+            Some(Kind::Ident(_)) => self.spaced && !followed_by_space,
+            _ => false,
+        }
+    }
+
+    fn run(mut self) -> Vec<Token> {
+        while let Some(c) = self.peek() {
+            let start = self.pos;
+            match c {
+                ' ' | '\t' | '\r' => {
+                    self.pos += 1;
+                    self.spaced = true;
+                }
+                '\\' if self.peek_at(1) == Some('\n') => {
+                    self.pos += 2;
+                    self.spaced = true;
+                }
+                '\n' => {
+                    self.pos += 1;
+                    self.push(Kind::Newline, start);
+                    self.spaced = true;
+                    self.read_heredocs();
+                    if self.src[self.pos..].starts_with("=begin") {
+                        let end = self.src[self.pos..].find("\n=end").map_or(self.src.len(), |i| self.pos + i + 5);
+                        self.pos = self.src[end..].find('\n').map_or(self.src.len(), |i| end + i);
+                    }
+                    if self.src[self.pos..].starts_with("__END__") {
+                        break;
+                    }
+                }
+                '#' => self.pos = self.src[self.pos..].find('\n').map_or(self.src.len(), |i| self.pos + i),
+                ';' => {
+                    self.pos += 1;
+                    self.push(Kind::Newline, start);
+                    self.spaced = true;
+                }
+                '0'..='9' => self.number(),
+                '"' | '`' => {
+                    self.pos += 1;
+                    let raw = self.delimited(c, c, true);
+                    self.push(Kind::Str(string_parts(raw, true, c)), start);
+                }
+                '\'' => {
+                    self.pos += 1;
+                    let raw = self.delimited('\'', '\'', false);
+                    self.push(Kind::Str(string_parts(raw, false, '\'')), start);
+                }
+                ':' => self.colon(),
+                '@' | '$' => {
+                    self.pos += 1;
+                    while self.peek().is_some_and(|c| is_ident_char(c) || c == '@') {
+                        self.pos += self.peek().map_or(1, char::len_utf8);
+                    }
+                    let name = self.src[start..self.pos].to_string();
+                    self.push(Kind::Ident(name), start);
+                }
+                '%' if self.percent_literal() => {}
+                '/' if self.expects_operand(self.peek_at(1).is_some_and(char::is_whitespace)) => {
+                    self.pos += 1;
+                    let raw = self.delimited('/', '/', true).to_string();
+                    while self.peek().is_some_and(|c| c.is_ascii_lowercase()) {
+                        self.pos += 1;
+                    }
+                    self.push(Kind::Regex(raw), start);
+                }
+                '<' if self.heredoc() => {}
+                '?' if self.expects_operand(false)
+                    && self.peek_at(1).is_some_and(|c| !c.is_whitespace())
+                    && !self.peek_at(2).is_some_and(is_ident_char) =>
+                {
+                    let c = self.peek_at(1).unwrap_or_default();
+                    self.pos += 1 + c.len_utf8();
+                    self.push(Kind::Str(vec![StrPart::Text(c.to_string())]), start);
+                }
+                c if is_ident_char(c) => self.word(),
+                _ => {
+                    let punct = PUNCTUATION.iter().find(|p| self.src[self.pos..].starts_with(**p)).copied();
+                    self.pos += punct.map_or(c.len_utf8(), str::len);
+                    self.push(Kind::Punct(punct.unwrap_or("?")), start);
+                }
+            }
+        }
+        let end = self.src.len();
+        self.tokens.push(Token { kind: Kind::Newline, span: end..end, spaced: true, end });
+        self.tokens.push(Token { kind: Kind::Eof, span: end..end, spaced: true, end });
+        self.tokens
+    }
+
+    fn word(&mut self) {
+        let start = self.pos;
+        while self.peek().is_some_and(is_ident_char) {
+            self.pos += self.peek().map_or(1, char::len_utf8);
+        }
+        // `arm?` and `save!`, but not `a ?b : c` or `x!=y`
+        if matches!(self.peek(), Some('?' | '!')) && self.peek_at(1) != Some('=') && self.peek_at(1) != Some(':') {
+            self.pos += 1;
+        }
+        let word = self.src[start..self.pos].to_string();
+        let after_dot = matches!(self.tokens.last().map(|t| &t.kind), Some(Kind::Punct("." | "&.")));
+        if self.peek() == Some(':') && self.peek_at(1) != Some(':') && !after_dot {
+            self.pos += 1;
+            self.push(Kind::Label(word), start);
+        } else if word.starts_with(|c: char| c.is_ascii_uppercase()) {
+            self.push(Kind::Const(word), start);
+        } else {
+            self.push(Kind::Ident(word), start);
+        }
+    }
+
+    fn number(&mut self) {
+        let start = self.pos;
+        let radix = match self.src[self.pos..].get(..2) {
+            Some("0x" | "0X") => 16,
+            Some("0o" | "0O") => 8,
+            Some("0b" | "0B") => 2,
+            Some("0d" | "0D") => 10,
+            Some(s) if s.starts_with('0') && s[1..].starts_with(|c: char| c.is_ascii_digit()) => 8,
+            _ => 0,
+        };
+        if radix != 0 && !self.src[self.pos + 1..].starts_with(|c: char| c.is_ascii_digit()) {
+            self.pos += 2;
+        }
+        while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == '_') {
+            self.pos += 1;
+        }
+        let mut float = false;
+        if radix == 0 && self.peek() == Some('.') && self.peek_at(1).is_some_and(|c| c.is_ascii_digit()) {
+            float = true;
+            self.pos += 1;
+            while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == '_') {
+                self.pos += 1;
+            }
+        }
+        let text: String = self.src[start..self.pos].chars().filter(|c| *c != '_').collect();
+        let kind = if float {
+            Kind::Float(text.parse().unwrap_or_default())
+        } else if radix == 0 {
+            Kind::Int(text.parse().unwrap_or_default())
+        } else {
+            // 0x1f, 0o17, 0b11, 0d9, or 017
+            let prefixed = text[1..].starts_with(|c: char| c.is_ascii_alphabetic());
+            let digits = if prefixed { &text[2..] } else { &text[1..] };
+            Kind::Int(i64::from_str_radix(digits, radix).unwrap_or_default())
+        };
+        self.push(kind, start);
+    }
+
+    fn colon(&mut self) {
+        let start = self.pos;
+        match self.peek_at(1) {
+            Some(':') => {
+                self.pos += 2;
+                self.push(Kind::Punct("::"), start);
+            }
+            Some(q @ ('"' | '\'')) => {
+                self.pos += 2;
+                let raw = self.delimited(q, q, q == '"');
+                let symbol = parts_text(&string_parts(raw, q == '"', q));
+                self.push(Kind::Symbol(symbol), start);
+            }
+            Some(c) if is_ident_char(c) && !c.is_ascii_digit() => {
+                self.pos += 1;
+                let name_start = self.pos;
+                while self.peek().is_some_and(is_ident_char) {
+                    self.pos += self.peek().map_or(1, char::len_utf8);
+                }
+                if matches!(self.peek(), Some('?' | '!' | '=')) && !matches!(self.peek_at(1), Some('=' | '>')) {
+                    self.pos += 1;
+                }
+                let symbol = self.src[name_start..self.pos].to_string();
+                self.push(Kind::Symbol(symbol), start);
+            }
+            _ => {
+                self.pos += 1;
+                self.push(Kind::Punct(":"), start);
+            }
+        }
+    }
+
+    /// `%w[...]`, `%i[...]`, `%q(...)`, `%Q(...)`, `%(...)` and `%r{...}`; false if the
+    /// `%` is the operator.
+    fn percent_literal(&mut self) -> bool {
+        let (kind, delimiter) = match (self.peek_at(1), self.peek_at(2)) {
+            (Some(k @ ('w' | 'W' | 'i' | 'I' | 'q' | 'Q' | 'r')), Some(d)) if !d.is_alphanumeric() && !d.is_whitespace() => (k, d),
+            (Some(d @ ('(' | '[' | '{' | '<' | '|' | '!' | '^')), _) => ('Q', d),
+            _ => return false,
+        };
+        if !self.expects_operand(false) {
+            return false;
+        }
+        let start = self.pos;
+        self.pos += if kind == 'Q' && self.peek_at(1) == Some(delimiter) { 2 } else { 3 };
+        let close = match delimiter {
+            '(' => ')',
+            '[' => ']',
+            '{' => '}',
+            '<' => '>',
+            d => d,
+        };
+        let interpolating = matches!(kind, 'W' | 'I' | 'Q' | 'r');
+        let raw = self.delimited(delimiter, close, interpolating);
+        let kind = match kind {
+            'w' | 'W' | 'i' | 'I' => Kind::Words(raw.split_whitespace().map(str::to_string).collect()),
+            'r' => Kind::Regex(raw.to_string()),
+            'q' => Kind::Str(string_parts(raw, false, close)),
+            _ => Kind::Str(string_parts(raw, true, close)),
+        };
+        if matches!(kind, Kind::Regex(_)) {
+            while self.peek().is_some_and(|c| c.is_ascii_lowercase()) {
+                self.pos += 1;
+            }
+        }
+        self.push(kind, start);
+        true
+    }
+
+    /// `<<~EOS`, `<<-EOS` or `<<EOS`; the body is read at the end of the line.
+    fn heredoc(&mut self) -> bool {
+        let rest = &self.src[self.pos..];
+        let Some(after) = rest.strip_prefix("<<") else {
+            return false;
+        };
+        let (squiggly, indented, after) = match after.chars().next() {
+            Some('~') => (true, true, &after[1..]),
+            Some('-') => (false, true, &after[1..]),
+            _ => (false, false, after),
+        };
+        let (id, interpolating, len) = match after.chars().next() {
+            Some(q @ ('\'' | '"')) => {
+                let Some(end) = after[1..].find(q) else {
+                    return false;
+                };
+                (after[1..end + 1].to_string(), q == '"', end + 2)
+            }
+            Some(c) if c.is_ascii_uppercase() || (c == '_') || (indented && c.is_ascii_alphabetic()) => {
+                let end = after.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(after.len());
+                (after[..end].to_string(), true, end)
+            }
+            _ => return false,
+        };
+        if !self.expects_operand(false) {
+            return false;
+        }
+        let start = self.pos;
+        self.pos += rest.len() - after.len() + len;
+        self.heredocs.push(Heredoc { token: self.tokens.len(), id, squiggly, indented, interpolating });
+        self.push(Kind::Str(Vec::new()), start);
+        true
+    }
+
+    fn read_heredocs(&mut self) {
+        for heredoc in std::mem::take(&mut self.heredocs) {
+            let mut lines = Vec::new();
+            while self.pos < self.src.len() {
+                let line_end = self.src[self.pos..].find('\n').map_or(self.src.len(), |i| self.pos + i);
+                let line = self.src[self.pos..line_end].trim_end_matches('\r');
+                self.pos = (line_end + 1).min(self.src.len());
+                let terminator = if heredoc.indented { line.trim_start() } else { line };
+                if terminator == heredoc.id {
+                    self.tokens[heredoc.token].end = line_end;
+                    break;
+                }
+                lines.push(line);
+            }
+            if heredoc.squiggly {
+                let indent = lines.iter()
+                    .filter(|line| !line.trim().is_empty())
+                    .map(|line| line.len() - line.trim_start_matches([' ', '\t']).len())
+                    .min()
+                    .unwrap_or(0);
+                for line in &mut lines {
+                    *line = &line[indent.min(line.len() - line.trim_start_matches([' ', '\t']).len())..];
+                }
+            }
+            let mut body = lines.join("\n");
+            body.push('\n');
+            self.tokens[heredoc.token].kind = Kind::Str(string_parts(&body, heredoc.interpolating, '\0'));
+        }
+    }
+
+    /// The raw text up to the `close` that ends a literal, which is stepped over.
+    /// Nested `open`/`close` pairs, escapes and `#{...}` are skipped.
+    fn delimited(&mut self, open: char, close: char, interpolating: bool) -> &'a str {
+        let start = self.pos;
+        let mut depth = 0;
+        while let Some(c) = self.peek() {
+            if c == '\\' {
+                self.pos += 1 + self.peek_at(1).map_or(0, char::len_utf8);
+                continue;
+            }
+            if interpolating && c == '#' && self.peek_at(1) == Some('{') {
+                self.pos += skip_interpolation(&self.src[self.pos..]);
+                continue;
+            }
+            if c == close && depth == 0 {
+                let raw = &self.src[start..self.pos];
+                self.pos += c.len_utf8();
+                return raw;
+            }
+            if c == close {
+                depth -= 1;
+            } else if c == open {
+                depth += 1;
+            }
+            self.pos += c.len_utf8();
+        }
+        &self.src[start..]
+    }
+}
+
+/// Length of the `#{...}` at the start of `text`, nested braces and strings included.
+fn skip_interpolation(text: &str) -> usize {
+    let mut depth = 0;
+    let mut chars = text.char_indices().skip(1);
+    let mut quote: Option<char> = None;
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (_, '\\') => {
+                chars.next();
+            }
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '{') => depth += 1,
+            (None, '}') => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+/// The parts of a string literal's raw text, escapes resolved. Without interpolation
+/// (single quotes), only `\\` and an escaped `close` are escapes.
+fn string_parts(raw: &str, interpolating: bool, close: char) -> Vec<StrPart> {
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    let mut i = 0;
+    while let Some(c) = raw[i..].chars().next() {
+        if c == '\\' {
+            let next = raw[i + 1..].chars().next();
+            i += 1 + next.map_or(0, char::len_utf8);
+            match (interpolating, next) {
+                (_, None) => text.push('\\'),
+                (false, Some(n)) if n == '\\' || n == close => text.push(n),
+                (false, Some(n)) => {
+                    text.push('\\');
+                    text.push(n);
+                }
+                (true, Some('n')) => text.push('\n'),
+                (true, Some('t')) => text.push('\t'),
+                (true, Some('r')) => text.push('\r'),
+                (true, Some('e')) => text.push('\x1b'),
+                (true, Some('s')) => text.push(' '),
+                (true, Some('0')) => text.push('\0'),
+                (true, Some('\n')) => {}
+                (true, Some(n)) => text.push(n),
+            }
+            continue;
+        }
+        if interpolating && raw[i..].starts_with("#{") {
+            let len = skip_interpolation(&raw[i..]);
+            if !text.is_empty() {
+                parts.push(StrPart::Text(std::mem::take(&mut text)));
+            }
+            let code = raw[i + 2..i + len].strip_suffix('}').unwrap_or(&raw[i + 2..i + len]);
+            parts.push(StrPart::Code(code.trim().to_string()));
+            i += len;
+            continue;
+        }
+        text.push(c);
+        i += c.len_utf8();
+    }
+    if !text.is_empty() || parts.is_empty() {
+        parts.push(StrPart::Text(text));
+    }
+    parts
+}
+
+/// A string's parts as text, with interpolations written back as `#{...}`.
+pub fn parts_text(parts: &[StrPart]) -> String {
+    parts.iter()
+        .map(|part| match part {
+            StrPart::Text(text) => text.clone(),
+            StrPart::Code(code) => format!("#{{{}}}", code),
+        })
+        .collect()
+}
+
+type Parsed<T> = Result<T, ()>;
+
+const ASSIGNMENTS: &[&str] = &["=", "+=", "-=", "*=", "/=", "%=", "**=", "||=", "&&=", "|=", "&=", "<<=", ">>="];
+
+/// Binary operators from the loosest binding to the tightest.
+const BINARY: &[&[&str]] = &[
+    &["..", "..."],
+    &["||"],
+    &["&&"],
+    &["<=>", "==", "===", "!=", "=~", "!~"],
+    &["<", "<=", ">", ">="],
+    &["|", "^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+struct Parser<'t> {
+    tokens: &'t [Token],
+    pos: usize,
+    /// Set while reading a call's arguments without parentheses: a `do` there belongs
+    /// to that call, not to the last argument
+    no_do: bool,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> &Kind {
+        &self.tokens[self.pos].kind
+    }
+
+    fn token(&self) -> &Token {
+        &self.tokens[self.pos]
+    }
+
+    fn advance(&mut self) {
+        if self.pos < self.tokens.len() - 1 {
+            self.pos += 1;
+        }
+    }
+
+    fn is_punct(&self, punct: &str) -> bool {
+        matches!(self.peek(), Kind::Punct(p) if *p == punct)
+    }
+
+    fn is_word(&self, word: &str) -> bool {
+        matches!(self.peek(), Kind::Ident(w) if w == word)
+    }
+
+    fn eat_punct(&mut self, punct: &str) -> bool {
+        let found = self.is_punct(punct);
+        if found {
+            self.advance();
+        }
+        found
+    }
+
+    fn eat_word(&mut self, word: &str) -> bool {
+        let found = self.is_word(word);
+        if found {
+            self.advance();
+        }
+        found
+    }
+
+    fn expect_punct(&mut self, punct: &str) -> Parsed<()> {
+        if self.eat_punct(punct) { Ok(()) } else { Err(()) }
+    }
+
+    fn expect_word(&mut self, word: &str) -> Parsed<()> {
+        if self.eat_word(word) { Ok(()) } else { Err(()) }
+    }
+
+    fn skip_newlines(&mut self) {
+        while *self.peek() == Kind::Newline {
+            self.advance();
+        }
+    }
+
+    fn at_terminator(&self, terminators: &[&str]) -> bool {
+        match self.peek() {
+            Kind::Eof => true,
+            Kind::Ident(word) => terminators.contains(&word.as_str()),
+            Kind::Punct(p) => terminators.contains(p),
+            _ => false,
+        }
+    }
+
+    fn at_statement_end(&self, terminators: &[&str]) -> bool {
+        *self.peek() == Kind::Newline || self.at_terminator(terminators)
+    }
+
+    fn span(&self, from: usize) -> Range<usize> {
+        let tokens = &self.tokens[from..self.pos.max(from + 1)];
+        let end = tokens.iter().filter(|t| t.kind != Kind::Newline).map(|t| t.end).max();
+        self.tokens[from].span.start..end.unwrap_or(self.tokens[from].span.end)
+    }
+
+    /// Statements up to one of `terminators`, which is left for the caller.
+    fn stmts(&mut self, terminators: &[&str]) -> Body {
+        let mut stmts = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.at_terminator(terminators) {
+                break;
+            }
+            let from = self.pos;
+            let saved = self.no_do;
+            self.no_do = false;
+            let node = match self.statement() {
+                Ok(node) if self.at_statement_end(terminators) => node,
+                _ => {
+                    self.pos = from;
+                    self.skip_statement(terminators);
+                    Node::Other
+                }
+            };
+            self.no_do = saved;
+            stmts.push(Stmt { node, span: self.span(from) });
+        }
+        Body { stmts }
+    }
+
+    /// Step over a statement that couldn't be parsed: to the end of its line, or past
+    /// the `end` or bracket matching the one it opens.
+    fn skip_statement(&mut self, terminators: &[&str]) {
+        let from = self.pos;
+        let mut depth = 0usize;
+        loop {
+            let operand_expected = self.pos == from || match &self.tokens[self.pos - 1].kind {
+                Kind::Newline => true,
+                Kind::Punct(p) => !matches!(*p, ")" | "]" | "}"),
+                Kind::Ident(word) => OPERAND_KEYWORDS.contains(&word.as_str()),
+                _ => false,
+            };
+            match self.peek() {
+                Kind::Eof => break,
+                Kind::Newline if depth == 0 => break,
+                _ if depth == 0 && self.pos > from && self.at_terminator(terminators) => break,
+                Kind::Punct("}" | ")" | "]") if depth == 0 && self.pos > from => break,
+                Kind::Ident(word) if word == "end" => depth = depth.saturating_sub(1),
+                Kind::Punct("}" | ")" | "]") => depth = depth.saturating_sub(1),
+                Kind::Punct("{" | "(" | "[") => depth += 1,
+                Kind::Ident(word) if matches!(word.as_str(), "do" | "class" | "module" | "def" | "case" | "begin" | "for") => depth += 1,
+                Kind::Ident(word) if operand_expected && matches!(word.as_str(), "if" | "unless" | "while" | "until") => depth += 1,
+                _ => {}
+            }
+            self.advance();
+        }
+    }
+
+    fn statement(&mut self) -> Parsed<Node> {
+        let from = self.pos;
+        let mut node = self.expression()?;
+        loop {
+            let negated = match self.peek() {
+                Kind::Ident(w) if w == "if" => false,
+                Kind::Ident(w) if w == "unless" => true,
+                Kind::Ident(w) if matches!(w.as_str(), "while" | "until" | "rescue") => {
+                    let keyword = if w == "rescue" { "rescue" } else { "while" };
+                    let span = self.span(from);
+                    self.advance();
+                    self.expression()?;
+                    node = Node::Compound(keyword, vec![Stmt { node, span }]);
+                    continue;
+                }
+                _ => return Ok(node),
+            };
+            let span = self.span(from);
+            self.advance();
+            let cond = self.expression()?;
+            node = Node::If { negated, cond: Box::new(cond), then: vec![Stmt { node, span }], otherwise: Vec::new() };
+        }
+    }
+
+    fn expression(&mut self) -> Parsed<Node> {
+        let mut left = self.not_expression()?;
+        while let Kind::Ident(word) = self.peek() {
+            let op = match word.as_str() {
+                "and" => "&&",
+                "or" => "||",
+                _ => break,
+            };
+            self.advance();
+            self.skip_newlines();
+            let right = self.not_expression()?;
+            left = Node::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn not_expression(&mut self) -> Parsed<Node> {
+        if self.eat_word("not") {
+            return Ok(Node::Unary("!", Box::new(self.not_expression()?)));
+        }
+        self.assignment()
+    }
+
+    fn assignment(&mut self) -> Parsed<Node> {
+        let left = self.ternary()?;
+        let Kind::Punct(op) = *self.peek() else {
+            return Ok(left);
+        };
+        if !ASSIGNMENTS.contains(&op) {
+            return Ok(left);
+        }
+        self.advance();
+        self.skip_newlines();
+        let right = self.not_expression()?;
+        Ok(Node::Assign(op, Box::new(left), Box::new(right)))
+    }
+
+    fn ternary(&mut self) -> Parsed<Node> {
+        let cond = self.binary(0)?;
+        if !self.eat_punct("?") {
+            return Ok(cond);
+        }
+        self.skip_newlines();
+        let then = self.ternary()?;
+        self.skip_newlines();
+        // `a ? b :c` reads `:c` as a symbol
+        let otherwise = match self.peek().clone() {
+            Kind::Symbol(symbol) => {
+                self.advance();
+                Node::Symbol(symbol)
+            }
+            _ => {
+                self.expect_punct(":")?;
+                self.skip_newlines();
+                self.ternary()?
+            }
+        };
+        Ok(Node::Ternary(Box::new(cond), Box::new(then), Box::new(otherwise)))
+    }
+
+    fn binary(&mut self, level: usize) -> Parsed<Node> {
+        if level == BINARY.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        while let Kind::Punct(op) = *self.peek() {
+            if !BINARY[level].contains(&op) {
+                break;
+            }
+            self.advance();
+            self.skip_newlines();
+            let right = self.binary(level + 1)?;
+            left = Node::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Parsed<Node> {
+        match *self.peek() {
+            Kind::Punct(op @ ("!" | "~" | "+")) => {
+                self.advance();
+                Ok(Node::Unary(op, Box::new(self.unary()?)))
+            }
+            Kind::Punct("-") => {
+                self.advance();
+                Ok(match self.unary()? {
+                    Node::Int(i) => Node::Int(-i),
+                    Node::Float(f) => Node::Float(-f),
+                    node => Node::Unary("-", Box::new(node)),
+                })
+            }
+            Kind::Punct("*" | "**" | "&") => {
+                self.advance();
+                Ok(Node::Splat(Box::new(self.unary()?)))
+            }
+            _ => {
+                let base = self.postfix()?;
+                if self.eat_punct("**") {
+                    return Ok(Node::Binary("**", Box::new(base), Box::new(self.unary()?)));
+                }
+                Ok(base)
+            }
+        }
+    }
+
+    fn postfix(&mut self) -> Parsed<Node> {
+        let mut node = self.primary()?;
+        loop {
+            // A call chain continued on the next line: `foo\n  .bar`
+            if *self.peek() == Kind::Newline {
+                let next = self.tokens[self.pos..].iter().find(|t| t.kind != Kind::Newline);
+                if !next.is_some_and(|t| matches!(t.kind, Kind::Punct("." | "&."))) {
+                    break;
+                }
+                self.skip_newlines();
+            }
+            match *self.peek() {
+                Kind::Punct("." | "&.") => {
+                    self.advance();
+                    self.skip_newlines();
+                    let name = match self.peek().clone() {
+                        Kind::Ident(name) | Kind::Const(name) => name,
+                        Kind::Punct("(") => {
+                            node = self.call_rest(Some(node), "call".to_string(), false)?;
+                            continue;
+                        }
+                        _ => return Err(()),
+                    };
+                    self.advance();
+                    node = self.call_rest(Some(node), name, true)?;
+                }
+                Kind::Punct("::") => {
+                    self.advance();
+                    match self.peek().clone() {
+                        Kind::Const(name) if !self.tokens[self.pos + 1].kind.eq(&Kind::Punct("(")) => {
+                            self.advance();
+                            node = match node {
+                                Node::Const(namespace) => Node::Const(format!("{}::{}", namespace, name)),
+                                other => Node::Call(Call { receiver: Some(Box::new(other)), name, args: Vec::new(), block: None }),
+                            };
+                        }
+                        Kind::Ident(name) | Kind::Const(name) => {
+                            self.advance();
+                            node = self.call_rest(Some(node), name, true)?;
+                        }
+                        _ => return Err(()),
+                    }
+                }
+                Kind::Punct("[") if !self.token().spaced => {
+                    self.advance();
+                    let args = self.arguments(Some("]"))?;
+                    node = Node::Index(Box::new(node), args);
+                }
+                _ => break,
+            }
+        }
+        Ok(node)
+    }
+
+    /// Whether the token after a method name starts its first argument, for a call
+    /// without parentheses.
+    fn argument_starts(&self) -> bool {
+        let token = self.token();
+        if !token.spaced {
+            return false;
+        }
+        let tight = || !self.tokens[self.pos + 1].spaced;
+        match &token.kind {
+            Kind::Str(_) | Kind::Words(_) | Kind::Int(_) | Kind::Float(_) | Kind::Symbol(_)
+            | Kind::Label(_) | Kind::Const(_) | Kind::Regex(_) => true,
+            Kind::Ident(word) => !NOT_ARGUMENTS.contains(&word.as_str()),
+            Kind::Punct("[" | "(" | "->") => true,
+            Kind::Punct("*" | "**" | "&" | "-" | "!" | "::" | ":") => tight(),
+            _ => false,
+        }
+    }
+
+    /// The rest of a call to `name`: its arguments and block, if any. A bare name
+    /// without a receiver stays an `Ident`.
+    fn call_rest(&mut self, receiver: Option<Node>, name: String, allow_command: bool) -> Parsed<Node> {
+        let mut args = Vec::new();
+        let mut parenthesized = false;
+        if self.is_punct("(") && !self.token().spaced {
+            self.advance();
+            args = self.arguments(Some(")"))?;
+            parenthesized = true;
+        } else if allow_command && self.argument_starts() {
+            let saved = self.no_do;
+            self.no_do = true;
+            let parsed = self.arguments(None);
+            self.no_do = saved;
+            args = parsed?;
+        }
+        let block = self.block(parenthesized || args.is_empty())?;
+        if receiver.is_none() && args.is_empty() && block.is_none() && !parenthesized {
+            return Ok(Node::Ident(name));
+        }
+        Ok(Node::Call(Call { receiver: receiver.map(Box::new), name, args, block: block.map(Box::new) }))
+    }
+
+    /// A `do ... end` block, or a `{ ... }` one where `braces` allows.
+    fn block(&mut self, braces: bool) -> Parsed<Option<Block>> {
+        let close = if self.is_word("do") && !self.no_do {
+            "end"
+        } else if braces && self.is_punct("{") {
+            "}"
+        } else {
+            return Ok(None);
+        };
+        self.advance();
+        let mut params = Vec::new();
+        if self.eat_punct("|") {
+            loop {
+                match self.peek().clone() {
+                    Kind::Punct("|") => break,
+                    Kind::Eof | Kind::Newline => return Err(()),
+                    Kind::Ident(name) => params.push(name),
+                    _ => {}
+                }
+                self.advance();
+            }
+            self.advance();
+        } else {
+            self.eat_punct("||");
+        }
+        let saved = self.no_do;
+        self.no_do = false;
+        let body = self.body_with_rescue(&[close]);
+        self.no_do = saved;
+        if close == "end" { self.expect_word("end")? } else { self.expect_punct("}")? }
+        Ok(Some(Block { params, body }))
+    }
+
+    /// Statements up to `end` (or `terminators`), with any `rescue`, `else` and
+    /// `ensure` clauses read and dropped.
+    fn body_with_rescue(&mut self, terminators: &[&str]) -> Body {
+        let mut all: Vec<&str> = terminators.to_vec();
+        all.extend(["rescue", "ensure", "else"]);
+        let body = self.stmts(&all);
+        while self.is_word("rescue") || self.is_word("ensure") || self.is_word("else") {
+            while *self.peek() != Kind::Newline && *self.peek() != Kind::Eof {
+                self.advance();
+            }
+            self.stmts(&all);
+        }
+        body
+    }
+
+    /// Comma-separated arguments, up to `close` (which is consumed), or to the end of
+    /// the statement without one. `key: value` and `key => value` pairs are gathered
+    /// into a trailing hash.
+    fn arguments(&mut self, close: Option<&'static str>) -> Parsed<Vec<Node>> {
+        let saved = self.no_do;
+        if close.is_some() {
+            self.no_do = false;
+        }
+        let mut args = Vec::new();
+        let mut pairs = Vec::new();
+        if let Some(close) = close {
+            self.skip_newlines();
+            if self.eat_punct(close) {
+                self.no_do = saved;
+                return Ok(args);
+            }
+        }
+        loop {
+            if let Kind::Label(key) = self.peek().clone() {
+                self.advance();
+                self.skip_newlines();
+                pairs.push((Node::Symbol(key), self.argument()?));
+            } else {
+                let value = self.argument()?;
+                if self.eat_punct("=>") {
+                    self.skip_newlines();
+                    pairs.push((value, self.argument()?));
+                } else {
+                    args.push(value);
+                }
+            }
+            if !self.eat_punct(",") {
+                break;
+            }
+            self.skip_newlines();
+            if close.is_some_and(|close| self.is_punct(close)) {
+                break;
+            }
+        }
+        if let Some(close) = close {
+            self.skip_newlines();
+            self.expect_punct(close)?;
+        }
+        if !pairs.is_empty() {
+            args.push(Node::Hash(pairs));
+        }
+        self.no_do = saved;
+        Ok(args)
+    }
+
+    fn argument(&mut self) -> Parsed<Node> {
+        let node = self.ternary()?;
+        if let Kind::Punct(op) = *self.peek() {
+            if ASSIGNMENTS.contains(&op) {
+                self.advance();
+                self.skip_newlines();
+                return Ok(Node::Assign(op, Box::new(node), Box::new(self.ternary()?)));
+            }
+        }
+        Ok(node)
+    }
+
+    fn primary(&mut self) -> Parsed<Node> {
+        let kind = self.peek().clone();
+        match kind {
+            Kind::Int(i) => {
+                self.advance();
+                Ok(Node::Int(i))
+            }
+            Kind::Float(f) => {
+                self.advance();
+                Ok(Node::Float(f))
+            }
+            Kind::Str(mut parts) => {
+                self.advance();
+                // Adjacent literals join: "a" "b"
+                while let Kind::Str(more) = self.peek() {
+                    parts.extend(more.iter().cloned());
+                    self.advance();
+                }
+                Ok(Node::Str(parts))
+            }
+            Kind::Words(words) => {
+                self.advance();
+                Ok(Node::Array(words.into_iter().map(|w| Node::Str(vec![StrPart::Text(w)])).collect()))
+            }
+            Kind::Symbol(symbol) => {
+                self.advance();
+                Ok(Node::Symbol(symbol))
+            }
+            Kind::Regex(regex) => {
+                self.advance();
+                Ok(Node::Regex(regex))
+            }
+            Kind::Const(name) => {
+                self.advance();
+                if self.is_punct("(") && !self.token().spaced {
+                    return self.call_rest(None, name, false);
+                }
+                Ok(Node::Const(name))
+            }
+            Kind::Punct("::") => {
+                self.advance();
+                match self.peek().clone() {
+                    Kind::Const(name) => {
+                        self.advance();
+                        Ok(Node::Const(name))
+                    }
+                    _ => Err(()),
+                }
+            }
+            Kind::Punct("(") => {
+                self.advance();
+                let saved = self.no_do;
+                self.no_do = false;
+                let body = self.stmts(&[")"]);
+                self.no_do = saved;
+                self.expect_punct(")")?;
+                let mut stmts = body.stmts;
+                Ok(match stmts.len() {
+                    0 => Node::Nil,
+                    1 => stmts.remove(0).node,
+                    _ => Node::Compound("begin", stmts),
+                })
+            }
+            Kind::Punct("[") => {
+                self.advance();
+                Ok(Node::Array(self.arguments(Some("]"))?))
+            }
+            Kind::Punct("{") => self.hash(),
+            Kind::Punct("->") => {
+                self.advance();
+                if self.eat_punct("(") {
+                    let mut depth = 0;
+                    while depth > 0 || !self.is_punct(")") {
+                        match self.peek() {
+                            Kind::Eof => return Err(()),
+                            Kind::Punct("(") => depth += 1,
+                            Kind::Punct(")") => depth -= 1,
+                            _ => {}
+                        }
+                        self.advance();
+                    }
+                    self.advance();
+                } else {
+                    while let Kind::Ident(_) | Kind::Punct(",") = self.peek() {
+                        self.advance();
+                    }
+                }
+                let saved = self.no_do;
+                self.no_do = false;
+                let block = self.block(true)?;
+                self.no_do = saved;
+                block.map(|block| Node::Lambda(Box::new(block))).ok_or(())
+            }
+            Kind::Ident(word) => match word.as_str() {
+                "nil" => {
+                    self.advance();
+                    Ok(Node::Nil)
+                }
+                "true" | "false" => {
+                    self.advance();
+                    Ok(Node::Bool(word == "true"))
+                }
+                "class" | "module" => self.class(),
+                "def" => self.def(),
+                "if" | "unless" => {
+                    self.advance();
+                    let node = self.if_rest(word == "unless")?;
+                    self.expect_word("end")?;
+                    Ok(node)
+                }
+                "while" | "until" | "for" => {
+                    self.advance();
+                    if word == "for" {
+                        while !self.is_word("in") {
+                            if matches!(self.peek(), Kind::Newline | Kind::Eof) {
+                                return Err(());
+                            }
+                            self.advance();
+                        }
+                        self.advance();
+                    }
+                    let saved = self.no_do;
+                    self.no_do = true;
+                    let cond = self.expression();
+                    self.no_do = saved;
+                    cond?;
+                    self.eat_word("do");
+                    let body = self.stmts(&["end"]);
+                    self.expect_word("end")?;
+                    Ok(Node::Compound("while", body.stmts))
+                }
+                "case" => self.case(),
+                "begin" => {
+                    self.advance();
+                    let body = self.body_with_rescue(&["end"]);
+                    self.expect_word("end")?;
+                    Ok(Node::Compound("begin", body.stmts))
+                }
+                _ if NOT_ARGUMENTS.contains(&word.as_str()) => Err(()),
+                _ => {
+                    self.advance();
+                    self.call_rest(None, word, true)
+                }
+            },
+            _ => Err(()),
+        }
+    }
+
+    fn hash(&mut self) -> Parsed<Node> {
+        self.expect_punct("{")?;
+        let saved = self.no_do;
+        self.no_do = false;
+        let mut pairs = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.eat_punct("}") {
+                break;
+            }
+            let key = match self.peek().clone() {
+                Kind::Label(key) => {
+                    self.advance();
+                    Node::Symbol(key)
+                }
+                _ => {
+                    let key = self.ternary()?;
+                    self.skip_newlines();
+                    self.expect_punct("=>")?;
+                    key
+                }
+            };
+            self.skip_newlines();
+            pairs.push((key, self.ternary()?));
+            self.skip_newlines();
+            if !self.eat_punct(",") {
+                self.skip_newlines();
+                self.expect_punct("}")?;
+                break;
+            }
+        }
+        self.no_do = saved;
+        Ok(Node::Hash(pairs))
+    }
+
+    fn class(&mut self) -> Parsed<Node> {
+        self.advance();
+        if self.eat_punct("<<") {
+            self.expression()?;
+            let body = self.stmts(&["end"]);
+            self.expect_word("end")?;
+            return Ok(Node::Compound("class", body.stmts));
+        }
+        // Formula files generated by tools don't always capitalize the class name
+        let mut name = match self.peek().clone() {
+            Kind::Const(name) | Kind::Ident(name) => name,
+            _ => return Err(()),
+        };
+        self.advance();
+        while self.eat_punct("::") {
+            match self.peek().clone() {
+                Kind::Const(part) => name = format!("{}::{}", name, part),
+                _ => return Err(()),
+            }
+            self.advance();
+        }
+        let superclass = if self.eat_punct("<") { Some(Box::new(self.expression()?)) } else { None };
+        let body = self.stmts(&["end"]);
+        self.expect_word("end")?;
+        Ok(Node::Class { name, superclass, body })
+    }
+
+    fn def(&mut self) -> Parsed<Node> {
+        self.advance();
+        if self.is_word("self") && matches!(self.tokens[self.pos + 1].kind, Kind::Punct(".")) {
+            self.advance();
+            self.advance();
+        }
+        let name = match self.peek().clone() {
+            Kind::Ident(name) | Kind::Const(name) => name,
+            Kind::Label(name) => name,
+            Kind::Punct(op) => op.to_string(),
+            _ => return Err(()),
+        };
+        self.advance();
+        if self.is_punct("(") && !self.token().spaced {
+            let mut depth = 0;
+            loop {
+                match self.peek() {
+                    Kind::Eof => return Err(()),
+                    Kind::Punct("(") => depth += 1,
+                    Kind::Punct(")") => {
+                        depth -= 1;
+                        if depth == 0 {
+                            self.advance();
+                            break;
+                        }
+                    }
+                    _ => {}
+                }
+                self.advance();
+            }
+        }
+        // `def name = expression`
+        if self.eat_punct("=") {
+            let from = self.pos;
+            self.skip_newlines();
+            let node = self.statement()?;
+            return Ok(Node::Def { name, body: Body { stmts: vec![Stmt { node, span: self.span(from) }] } });
+        }
+        while !matches!(self.peek(), Kind::Newline | Kind::Eof) {
+            self.advance();
+        }
+        let body = self.body_with_rescue(&["end"]);
+        self.expect_word("end")?;
+        Ok(Node::Def { name, body })
+    }
+
+    /// The rest of an `if`, `unless` or `elsif` after its keyword, up to (not
+    /// including) the shared `end`.
+    fn if_rest(&mut self, negated: bool) -> Parsed<Node> {
+        let saved = self.no_do;
+        self.no_do = true;
+        let cond = self.expression();
+        self.no_do = saved;
+        let cond = cond?;
+        self.eat_word("then");
+        let then = self.stmts(&["elsif", "else", "end"]).stmts;
+        let otherwise = if self.is_word("elsif") {
+            let from = self.pos;
+            self.advance();
+            let node = self.if_rest(false)?;
+            vec![Stmt { node, span: self.span(from) }]
+        } else if self.eat_word("else") {
+            self.stmts(&["end"]).stmts
+        } else {
+            Vec::new()
+        };
+        Ok(Node::If { negated, cond: Box::new(cond), then, otherwise })
+    }
+
+    fn case(&mut self) -> Parsed<Node> {
+        self.advance();
+        if *self.peek() != Kind::Newline {
+            self.expression()?;
+        }
+        self.skip_newlines();
+        let mut stmts = Vec::new();
+        while self.eat_word("when") || self.eat_word("in") {
+            let saved = self.no_do;
+            self.no_do = true;
+            let patterns = self.arguments(None);
+            self.no_do = saved;
+            patterns?;
+            self.eat_word("then");
+            stmts.extend(self.stmts(&["when", "in", "else", "end"]).stmts);
+        }
+        if self.eat_word("else") {
+            stmts.extend(self.stmts(&["end"]).stmts);
+        }
+        self.expect_word("end")?;
+        Ok(Node::Compound("case", stmts))
+    }
+}
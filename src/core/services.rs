@@ -0,0 +1,378 @@
+//! Running formulae's `service do` blocks in the background: as launchd agents on macOS
+//! and systemd user units on Linux, so they start at login and restart as the formula
+//! asks. The service file is written when a service is started and removed when it's
+//! stopped, so a file on disk means a service nitro started.
+
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use super::formula::Formula;
+use super::interpolate::PathContext;
+use super::NitroError;
+
+/// The init system services are registered with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Manager {
+    Launchd,
+    Systemd,
+}
+
+impl Manager {
+    /// launchd on macOS, systemd everywhere else.
+    pub fn detect() -> Self {
+        if cfg!(target_os = "macos") {
+            Manager::Launchd
+        } else {
+            Manager::Systemd
+        }
+    }
+
+    /// Where the current user's service files go: `~/Library/LaunchAgents` or
+    /// `$XDG_CONFIG_HOME/systemd/user`.
+    pub fn service_dir(&self) -> Result<PathBuf> {
+        let dirs = directories::BaseDirs::new()
+            .ok_or_else(|| NitroError::Other("Could not determine the home directory".into()))?;
+        Ok(match self {
+            Manager::Launchd => dirs.home_dir().join("Library/LaunchAgents"),
+            Manager::Systemd => dirs.config_dir().join("systemd/user"),
+        })
+    }
+}
+
+/// Whether a formula's service is running, as `services list` shows it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// Registered and running (or, for a scheduled one, waiting for its next run)
+    Started,
+    /// Registered, but not running
+    Stopped,
+    /// Not registered
+    None,
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Status::Started => "started",
+            Status::Stopped => "stopped",
+            Status::None => "none",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A formula's service with its paths resolved for this prefix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Service {
+    pub formula: String,
+    /// The launchd label and systemd unit name: `nitro.<formula>`
+    pub label: String,
+    /// The command and its arguments
+    pub program: Vec<String>,
+    pub env: BTreeMap<String, String>,
+    pub keep_alive: bool,
+    /// Whether keeping it alive means restarting only after a crash
+    pub keep_alive_crashed: bool,
+    /// Seconds between runs of an interval service
+    pub interval: Option<u32>,
+    /// Schedule of a cron service
+    pub cron: Option<String>,
+    pub working_dir: Option<PathBuf>,
+    pub log_path: Option<PathBuf>,
+    pub error_log_path: Option<PathBuf>,
+}
+
+impl Service {
+    /// `formula`'s service, with `#{...}` placeholders filled in from `paths`, or `None`
+    /// if it has no `service do` block (or one without a command).
+    pub fn for_formula(formula: &Formula, paths: &PathContext, prefix: &Path) -> Option<Self> {
+        let definition = formula.service.as_ref()?;
+        let program: Vec<String> = definition.command.iter().chain(&definition.args)
+            .map(|part| paths.interpolate(part))
+            .collect();
+        if program.is_empty() {
+            return None;
+        }
+
+        // Homebrew's PATH for services: the prefix's commands, then the system's
+        let std_path = format!("{}/bin:{}/sbin:/usr/bin:/bin:/usr/sbin:/sbin", prefix.display(), prefix.display());
+        let env = definition.env.iter()
+            .map(|(key, value)| (key.clone(), paths.interpolate(&value.replace("#{std_service_path_env}", &std_path))))
+            .collect();
+        let run_type = definition.run_type.as_deref().unwrap_or("immediate");
+
+        Some(Self {
+            formula: formula.name.clone(),
+            label: label(&formula.name),
+            program,
+            env,
+            keep_alive: definition.keep_alive,
+            keep_alive_crashed: definition.keep_alive_crashed,
+            interval: definition.interval.filter(|_| run_type == "interval"),
+            cron: definition.cron.clone().filter(|_| run_type == "cron"),
+            working_dir: definition.working_dir.as_deref().map(|dir| paths.interpolate(dir).into()),
+            log_path: definition.log_path.as_deref().map(|path| paths.interpolate(path).into()),
+            error_log_path: definition.error_log_path.as_deref().map(|path| paths.interpolate(path).into()),
+        })
+    }
+
+    /// Whether the service runs on a schedule rather than continuously.
+    pub fn is_scheduled(&self) -> bool {
+        self.interval.is_some() || self.cron.is_some()
+    }
+
+    /// The service files to write for `manager`, as (file name, contents).
+    pub fn files(&self, manager: Manager) -> Vec<(String, String)> {
+        match manager {
+            Manager::Launchd => vec![(format!("{}.plist", self.label), launchd_plist(self))],
+            Manager::Systemd => {
+                let mut files = vec![(format!("{}.service", self.label), systemd_unit(self))];
+                if let Some(timer) = systemd_timer(self) {
+                    files.push((format!("{}.timer", self.label), timer));
+                }
+                files
+            }
+        }
+    }
+
+    /// The unit `systemctl` enables: the timer of a scheduled service.
+    fn systemd_unit_name(&self) -> String {
+        format!("{}.{}", self.label, if self.is_scheduled() { "timer" } else { "service" })
+    }
+}
+
+/// The launchd label and systemd unit name of `formula`'s service.
+pub fn label(formula: &str) -> String {
+    format!("nitro.{}", formula)
+}
+
+/// A launchd agent property list for `service`.
+pub fn launchd_plist(service: &Service) -> String {
+    let mut plist = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n",
+        "<plist version=\"1.0\">\n<dict>\n",
+    ));
+    let string = |key: &str, value: &str| format!("\t<key>{}</key>\n\t<string>{}</string>\n", key, xml_escape(value));
+
+    plist += &string("Label", &service.label);
+    plist += "\t<key>ProgramArguments</key>\n\t<array>\n";
+    for part in &service.program {
+        plist += &format!("\t\t<string>{}</string>\n", xml_escape(part));
+    }
+    plist += "\t</array>\n";
+    if !service.is_scheduled() {
+        plist += "\t<key>RunAtLoad</key>\n\t<true/>\n";
+    }
+    if service.keep_alive_crashed {
+        plist += "\t<key>KeepAlive</key>\n\t<dict>\n\t\t<key>Crashed</key>\n\t\t<true/>\n\t</dict>\n";
+    } else if service.keep_alive {
+        plist += "\t<key>KeepAlive</key>\n\t<true/>\n";
+    }
+    if let Some(interval) = service.interval {
+        plist += &format!("\t<key>StartInterval</key>\n\t<integer>{}</integer>\n", interval);
+    }
+    if let Some(schedule) = service.cron.as_deref().and_then(CronSchedule::parse) {
+        plist += "\t<key>StartCalendarInterval</key>\n\t<dict>\n";
+        for (key, value) in schedule.launchd_keys() {
+            plist += &format!("\t\t<key>{}</key>\n\t\t<integer>{}</integer>\n", key, value);
+        }
+        plist += "\t</dict>\n";
+    }
+    if let Some(dir) = &service.working_dir {
+        plist += &string("WorkingDirectory", &dir.display().to_string());
+    }
+    if let Some(path) = &service.log_path {
+        plist += &string("StandardOutPath", &path.display().to_string());
+    }
+    if let Some(path) = &service.error_log_path {
+        plist += &string("StandardErrorPath", &path.display().to_string());
+    }
+    if !service.env.is_empty() {
+        plist += "\t<key>EnvironmentVariables</key>\n\t<dict>\n";
+        for (key, value) in &service.env {
+            plist += &format!("\t\t<key>{}</key>\n\t\t<string>{}</string>\n", xml_escape(key), xml_escape(value));
+        }
+        plist += "\t</dict>\n";
+    }
+    plist += "</dict>\n</plist>\n";
+    plist
+}
+
+/// A systemd user service unit for `service`. A scheduled service runs once per
+/// activation of its timer (see `systemd_timer`).
+pub fn systemd_unit(service: &Service) -> String {
+    let mut unit = format!("[Unit]\nDescription=nitro service for {}\n\n[Service]\n", service.formula);
+    unit += if service.is_scheduled() { "Type=oneshot\n" } else { "Type=simple\n" };
+    unit += &format!("ExecStart={}\n", service.program.iter().map(|part| systemd_quote(part)).collect::<Vec<_>>().join(" "));
+    if service.keep_alive && !service.is_scheduled() {
+        unit += if service.keep_alive_crashed { "Restart=on-failure\n" } else { "Restart=always\n" };
+    }
+    if let Some(dir) = &service.working_dir {
+        unit += &format!("WorkingDirectory={}\n", dir.display());
+    }
+    if let Some(path) = &service.log_path {
+        unit += &format!("StandardOutput=append:{}\n", path.display());
+    }
+    if let Some(path) = &service.error_log_path {
+        unit += &format!("StandardError=append:{}\n", path.display());
+    }
+    for (key, value) in &service.env {
+        unit += &format!("Environment={}\n", systemd_quote(&format!("{}={}", key, value)));
+    }
+    if !service.is_scheduled() {
+        unit += "\n[Install]\nWantedBy=default.target\n";
+    }
+    unit
+}
+
+/// The timer that runs a scheduled `service`, or `None` for one that runs continuously.
+pub fn systemd_timer(service: &Service) -> Option<String> {
+    let trigger = match (service.interval, service.cron.as_deref().and_then(CronSchedule::parse)) {
+        (Some(interval), _) => format!("OnActiveSec={}\nOnUnitActiveSec={}\n", interval, interval),
+        (None, Some(schedule)) => format!("OnCalendar={}\n", schedule.systemd_calendar()),
+        (None, None) => return None,
+    };
+    Some(format!(
+        "[Unit]\nDescription=Timer for the nitro service for {}\n\n[Timer]\nUnit={}.service\n{}Persistent=true\n\n[Install]\nWantedBy=timers.target\n",
+        service.formula, service.label, trigger
+    ))
+}
+
+/// A five-field crontab schedule in which each field is `*` or a number, which is what
+/// both launchd and systemd calendars can express.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    pub minute: Option<u32>,
+    pub hour: Option<u32>,
+    pub day: Option<u32>,
+    pub month: Option<u32>,
+    pub weekday: Option<u32>,
+}
+
+impl CronSchedule {
+    pub fn parse(spec: &str) -> Option<Self> {
+        let fields: Vec<Option<u32>> = spec.split_whitespace()
+            .map(|field| if field == "*" { Some(None) } else { field.parse().ok().map(Some) })
+            .collect::<Option<_>>()?;
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return None;
+        };
+        Some(Self { minute, hour, day, month, weekday })
+    }
+
+    /// `StartCalendarInterval` keys for the fields that aren't `*`.
+    fn launchd_keys(&self) -> Vec<(&'static str, u32)> {
+        [("Minute", self.minute), ("Hour", self.hour), ("Day", self.day), ("Month", self.month), ("Weekday", self.weekday)]
+            .into_iter()
+            .filter_map(|(key, value)| Some((key, value?)))
+            .collect()
+    }
+
+    /// The schedule as a systemd `OnCalendar` expression: `Mon *-*-* 03:00:00`.
+    fn systemd_calendar(&self) -> String {
+        const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+        let field = |value: Option<u32>| value.map_or("*".to_string(), |v| format!("{:02}", v));
+        let date = format!("*-{}-{} {}:{}:00", field(self.month), field(self.day), field(self.hour), field(self.minute));
+        match self.weekday {
+            Some(day) => format!("{} {}", WEEKDAYS[day as usize % 7], date),
+            None => date,
+        }
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Quote a word for a systemd unit file when it needs it.
+fn systemd_quote(word: &str) -> String {
+    if !word.is_empty() && !word.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\') {
+        return word.to_string();
+    }
+    format!("\"{}\"", word.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Write `service`'s files into `dir` and start it, creating its log and working
+/// directories first.
+pub fn start(manager: Manager, service: &Service, dir: &Path) -> Result<()> {
+    for path in service.log_path.iter().chain(&service.error_log_path) {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+    }
+    if let Some(dir) = &service.working_dir {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::create_dir_all(dir)?;
+    for (name, contents) in service.files(manager) {
+        std::fs::write(dir.join(name), contents)?;
+    }
+
+    match manager {
+        Manager::Launchd => run("launchctl", &["load", "-w", &plist_path(service, dir)]),
+        Manager::Systemd => {
+            run("systemctl", &["--user", "daemon-reload"])?;
+            run("systemctl", &["--user", "enable", "--now", &service.systemd_unit_name()])
+        }
+    }
+}
+
+/// Stop `service` and remove its files from `dir`. One that isn't registered is
+/// already stopped.
+pub fn stop(manager: Manager, service: &Service, dir: &Path) -> Result<()> {
+    let files: Vec<PathBuf> = service.files(manager).into_iter().map(|(name, _)| dir.join(name)).collect();
+    if !files.iter().any(|file| file.exists()) {
+        return Ok(());
+    }
+
+    match manager {
+        Manager::Launchd => run("launchctl", &["unload", "-w", &plist_path(service, dir)])?,
+        Manager::Systemd => run("systemctl", &["--user", "disable", "--now", &service.systemd_unit_name()])?,
+    }
+    for file in files {
+        match std::fs::remove_file(&file) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    if manager == Manager::Systemd {
+        run("systemctl", &["--user", "daemon-reload"])?;
+    }
+    Ok(())
+}
+
+/// Whether `service`, with its files in `dir`, is running.
+pub fn status(manager: Manager, service: &Service, dir: &Path) -> Status {
+    let registered = service.files(manager).iter().any(|(name, _)| dir.join(name).exists());
+    if !registered {
+        return Status::None;
+    }
+    let running = match manager {
+        Manager::Launchd => succeeds("launchctl", &["list", &service.label]),
+        Manager::Systemd => succeeds("systemctl", &["--user", "is-active", "--quiet", &service.systemd_unit_name()]),
+    };
+    if running { Status::Started } else { Status::Stopped }
+}
+
+fn plist_path(service: &Service, dir: &Path) -> String {
+    dir.join(format!("{}.plist", service.label)).display().to_string()
+}
+
+/// Run `program` with `args`, failing with its stderr if it fails.
+fn run(program: &str, args: &[&str]) -> Result<()> {
+    let output = Command::new(program).args(args).output()
+        .map_err(|e| NitroError::Other(format!("Could not run {}: {}", program, e)))?;
+    if !output.status.success() {
+        return Err(NitroError::Other(format!(
+            "`{} {}` failed: {}", program, args.join(" "), String::from_utf8_lossy(&output.stderr).trim()
+        )).into());
+    }
+    Ok(())
+}
+
+fn succeeds(program: &str, args: &[&str]) -> bool {
+    Command::new(program).args(args).output().is_ok_and(|output| output.status.success())
+}
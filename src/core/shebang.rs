@@ -0,0 +1,121 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Directories whose scripts get their shebangs rewritten
+const SCRIPT_DIRS: &[&str] = &["bin", "sbin", "libexec"];
+
+/// Where the interpreters named in a keg's shebangs should come from.
+#[derive(Debug, Clone, Default)]
+pub struct ShebangContext {
+    /// The install prefix, substituted for bottles' `@@HOMEBREW_PREFIX@@` placeholder
+    pub prefix: PathBuf,
+    /// Searched in order for an interpreter by name: the `bin` of each runtime dependency
+    pub search_dirs: Vec<PathBuf>,
+    /// Interpreter name to formula, overriding the search (`python3 = "python@3.12"`)
+    pub interpreters: BTreeMap<String, String>,
+}
+
+impl ShebangContext {
+    /// Search the `bin` directory of each of `dependencies`.
+    pub fn for_dependencies(prefix: &Path, dependencies: &[String], interpreters: BTreeMap<String, String>) -> Self {
+        Self {
+            prefix: prefix.to_path_buf(),
+            search_dirs: dependencies.iter().map(|dep| formula_bin(prefix, dep)).collect(),
+            interpreters,
+        }
+    }
+
+    fn interpreter(&self, name: &str) -> Option<PathBuf> {
+        if let Some(formula) = self.interpreters.get(name) {
+            return Some(formula_bin(&self.prefix, formula).join(name));
+        }
+        self.search_dirs.iter().map(|dir| dir.join(name)).find(|path| path.exists())
+    }
+}
+
+/// A formula's `bin` by way of its `opt/<name>` link, which stays put when the formula
+/// is upgraded, so scripts pointed at it keep working.
+pub fn formula_bin(prefix: &Path, name: &str) -> PathBuf {
+    prefix.join("opt").join(name).join("bin")
+}
+
+/// The rewritten form of a `#!` line, or `None` to leave it alone. `#!/usr/bin/env
+/// python3` and absolute interpreter paths (`#!/usr/local/bin/python3`) point at the
+/// interpreter of a dependency when one provides it; bottle placeholders are filled in.
+pub fn rewrite_shebang(line: &str, context: &ShebangContext) -> Option<String> {
+    let command = line.strip_prefix("#!")?.trim();
+    let mut words = command.split_whitespace();
+    let program = words.next()?;
+
+    let (interpreter, args): (&str, Vec<&str>) = if program.ends_with("/env") {
+        // `env -S python3 -u` splits its argument string; the interpreter follows the flags
+        let rest: Vec<&str> = words.collect();
+        let position = rest.iter().position(|w| !w.starts_with('-'))?;
+        (rest[position], rest[position + 1..].to_vec())
+    } else {
+        (program, words.collect())
+    };
+
+    let name = interpreter.rsplit('/').next()?;
+    let replacement = match context.interpreter(name) {
+        Some(path) => path.display().to_string(),
+        None if interpreter.contains("@@HOMEBREW_PREFIX@@") || interpreter.contains("@@HOMEBREW_CELLAR@@") => {
+            let prefix = context.prefix.display().to_string();
+            interpreter
+                .replace("@@HOMEBREW_CELLAR@@", &format!("{}/Cellar", prefix))
+                .replace("@@HOMEBREW_PREFIX@@", &prefix)
+        }
+        None => return None,
+    };
+
+    let rewritten = std::iter::once(replacement.as_str()).chain(args).collect::<Vec<_>>().join(" ");
+    (rewritten != command).then(|| format!("#!{}", rewritten))
+}
+
+/// Rewrite the shebangs of the scripts in a keg's `bin`, `sbin` and `libexec`.
+/// Returns the number of files changed.
+pub fn rewrite_keg_shebangs(keg: &Path, context: &ShebangContext) -> Result<usize> {
+    let mut rewritten = 0;
+    for dir in SCRIPT_DIRS {
+        for entry in walkdir::WalkDir::new(keg.join(dir)).into_iter().flatten() {
+            // Symlinks point at files rewritten in their own right
+            if entry.file_type().is_file() && rewrite_file(entry.path(), context)? {
+                rewritten += 1;
+            }
+        }
+    }
+    Ok(rewritten)
+}
+
+fn rewrite_file(path: &Path, context: &ShebangContext) -> Result<bool> {
+    use std::io::Read;
+
+    let mut head = [0; 2];
+    let mut file = std::fs::File::open(path)?;
+    if file.read(&mut head)? < 2 || &head != b"#!" {
+        return Ok(false);
+    }
+
+    let content = std::fs::read(path)?;
+    let end = content.iter().position(|&b| b == b'\n').unwrap_or(content.len());
+    let Ok(first_line) = std::str::from_utf8(&content[..end]) else {
+        return Ok(false);
+    };
+    let Some(shebang) = rewrite_shebang(first_line.trim_end_matches('\r'), context) else {
+        return Ok(false);
+    };
+
+    let permissions = std::fs::metadata(path)?.permissions();
+    let mut updated = shebang.into_bytes();
+    updated.extend_from_slice(&content[end..]);
+
+    // Replaced rather than written into: the script may be hard-linked to the unpacked
+    // bottle other kegs are cloned from (and kegs from bottles are read-only anyway)
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let staged = path.with_file_name(format!(".{}.nitro-new", name));
+    std::fs::write(&staged, updated)?;
+    std::fs::set_permissions(&staged, permissions)?;
+    std::fs::rename(&staged, path)?;
+    Ok(true)
+}
@@ -0,0 +1,219 @@
+//! Lines a formula asks users to add to their shell startup file, declared with
+//! `shell_rc "line"` (or `shell_rc "line", shell: :fish`) in the formula. nitro only
+//! adds them on request (`nitro shellrc apply`, or `install.apply_shell_rc`), and puts
+//! each formula's lines in a block fenced with marker comments so it can find and
+//! remove exactly what it wrote.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::core::formula::Formula;
+use crate::core::ruby::{Call, Node};
+use crate::core::NitroError;
+
+/// A `shell_rc` line. Lines without a shell are for bash and zsh; fish's syntax is
+/// different enough that fish lines are always declared separately.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShellRcSnippet {
+    pub line: String,
+    #[serde(default)]
+    pub shell: Option<String>,
+}
+
+/// The line a `shell_rc` stanza declares, with any `#{...}` left in for
+/// `interpolate` to fill in when it's applied.
+pub fn from_call(call: &Call) -> Option<ShellRcSnippet> {
+    let Node::Str(parts) = call.args.first()? else {
+        return None;
+    };
+    Some(ShellRcSnippet {
+        line: crate::core::ruby::parts_text(parts),
+        shell: call.option("shell").and_then(Node::as_str),
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl Shell {
+    /// The shell a name or path (`zsh`, `/bin/bash`) refers to.
+    pub fn parse(shell: &str) -> Option<Self> {
+        match shell.rsplit('/').next().unwrap_or(shell) {
+            "bash" => Some(Self::Bash),
+            "zsh" => Some(Self::Zsh),
+            "fish" => Some(Self::Fish),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Bash => "bash",
+            Self::Zsh => "zsh",
+            Self::Fish => "fish",
+        }
+    }
+
+    /// The startup file interactive shells read, under `home`.
+    pub fn rc_file(&self, home: &Path) -> PathBuf {
+        match self {
+            Self::Bash => home.join(".bashrc"),
+            Self::Zsh => std::env::var_os("ZDOTDIR").map(PathBuf::from).unwrap_or_else(|| home.to_path_buf()).join(".zshrc"),
+            Self::Fish => home.join(".config/fish/config.fish"),
+        }
+    }
+
+    /// The lines `formula` declares for this shell.
+    pub fn lines<'a>(&self, formula: &'a Formula) -> Vec<&'a str> {
+        formula.shell_rc.iter()
+            .filter(|snippet| match &snippet.shell {
+                Some(shell) => shell == self.name(),
+                None => *self != Self::Fish,
+            })
+            .map(|snippet| snippet.line.as_str())
+            .collect()
+    }
+}
+
+fn begin_marker(name: &str) -> String {
+    format!("# >>> nitro: {} >>>", name)
+}
+
+fn end_marker(name: &str) -> String {
+    format!("# <<< nitro: {} <<<", name)
+}
+
+/// `content` with the block for `name` set to `lines`: replaced where it already is,
+/// appended otherwise.
+pub fn with_block(content: &str, name: &str, lines: &[String]) -> String {
+    let block = format!("{}\n{}\n{}\n", begin_marker(name), lines.join("\n"), end_marker(name));
+    let (begin, end) = (begin_marker(name), end_marker(name));
+    let mut out = String::new();
+    let mut replaced = false;
+    let mut inside = false;
+    for line in content.lines() {
+        if line == begin {
+            inside = true;
+        } else if inside {
+            if line == end {
+                inside = false;
+                if !replaced {
+                    out.push_str(&block);
+                    replaced = true;
+                }
+            }
+        } else {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    if !replaced {
+        if !out.is_empty() && !out.ends_with("\n\n") {
+            out.push('\n');
+        }
+        out.push_str(&block);
+    }
+    out
+}
+
+/// `content` without the block for `name`, or `None` if it has none.
+pub fn without_block(content: &str, name: &str) -> Option<String> {
+    let (begin, end) = (begin_marker(name), end_marker(name));
+    let mut out = String::new();
+    let mut found = false;
+    let mut inside = false;
+    for line in content.lines() {
+        if line == begin {
+            inside = true;
+            found = true;
+        } else if inside {
+            inside = line != end;
+        } else {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    // Drop the blank line that separated the block from what came before
+    if found && out.ends_with("\n\n") {
+        out.pop();
+    }
+    found.then_some(out)
+}
+
+/// The formulae with a block in `content`.
+pub fn blocks(content: &str) -> Vec<String> {
+    content.lines()
+        .filter_map(|line| line.strip_prefix("# >>> nitro: ")?.strip_suffix(" >>>"))
+        .map(str::to_string)
+        .collect()
+}
+
+/// One shell's startup file.
+#[derive(Debug, Clone)]
+pub struct RcFile {
+    pub shell: Shell,
+    pub path: PathBuf,
+}
+
+impl RcFile {
+    /// The startup file of `shell` (a name or path), or of `$SHELL`.
+    pub fn detect(shell: Option<&str>) -> Result<Self> {
+        let name = shell.map(str::to_string).or_else(|| std::env::var("SHELL").ok()).unwrap_or_default();
+        let shell = Shell::parse(&name)
+            .ok_or_else(|| NitroError::Other(format!("Unsupported shell '{}'; use --shell bash, zsh or fish", name)))?;
+        let home = directories::BaseDirs::new()
+            .ok_or_else(|| NitroError::Other("Could not determine home directory".into()))?;
+        Ok(Self { shell, path: shell.rc_file(home.home_dir()) })
+    }
+
+    fn read(&self) -> std::io::Result<String> {
+        match std::fs::read_to_string(&self.path) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+            result => result,
+        }
+    }
+
+    /// The formulae with lines in this file.
+    pub fn applied(&self) -> std::io::Result<Vec<String>> {
+        Ok(blocks(&self.read()?))
+    }
+
+    /// Add `formula`'s lines for this shell, with their `#{...}` paths filled in.
+    /// `Ok(false)` when they were already there.
+    pub fn apply(&self, formula: &Formula) -> Result<bool> {
+        let lines = self.shell.lines(formula);
+        if lines.is_empty() {
+            return Err(NitroError::Other(format!(
+                "{} has no shell startup lines for {}", formula.name, self.shell.name()
+            )).into());
+        }
+        let paths = crate::core::interpolate::PathContext::detect(formula)?;
+        let lines: Vec<String> = lines.iter().map(|line| paths.interpolate(line)).collect();
+
+        let content = self.read()?;
+        let updated = with_block(&content, &formula.name, &lines);
+        if updated == content {
+            return Ok(false);
+        }
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        // Written in place rather than renamed over, so a symlinked dotfile stays a symlink
+        std::fs::write(&self.path, updated)?;
+        Ok(true)
+    }
+
+    /// Remove the lines added for `name`. `Ok(false)` when there were none.
+    pub fn remove(&self, name: &str) -> Result<bool> {
+        let Some(updated) = without_block(&self.read()?, name) else {
+            return Ok(false);
+        };
+        std::fs::write(&self.path, updated)?;
+        Ok(true)
+    }
+}
@@ -1,9 +1,13 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
-use tokio::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use crate::core::{NitroError, NitroResult};
+use crate::core::git::{self, GitError};
+use crate::core::policy::Policy;
+use crate::core::{readonly, NitroError, NitroResult};
+use crate::download::github::GitHubClient;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Tap {
@@ -13,6 +17,246 @@ pub struct Tap {
     pub updated_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
+/// Tap defaults, read from the `[taps]` section of the config file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TapsConfig {
+    /// Also add homebrew/cask on first run so casks show up in search
+    pub cask: bool,
+    /// Taps added automatically when missing
+    pub default: Vec<String>,
+    /// Read homebrew/core formulae from the formulae.brew.sh JSON API instead of
+    /// cloning the tap
+    pub api_only: bool,
+    /// Add the default taps automatically; `--no-auto-tap` turns this off for one run
+    pub auto_tap: bool,
+    /// History depth for tap clones; 0 clones the full history
+    pub clone_depth: u32,
+    /// How far each tap is trusted, by tap name or pattern (`"mycorp/*"`). Homebrew's
+    /// own taps are official and anything not listed is untrusted.
+    pub trust: BTreeMap<String, TapTrust>,
+    /// Refuse installs from untrusted taps instead of asking; `--require-trusted` turns
+    /// this on for one run
+    pub require_trusted: bool,
+}
+
+/// How far a tap's formulae are trusted. Installing from an untrusted tap asks first,
+/// showing where the formula downloads from, or is refused with `require_trusted`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TapTrust {
+    Untrusted,
+    /// Reviewed by someone here, e.g. an internal tap
+    Verified,
+    /// Maintained by Homebrew
+    Official,
+}
+
+impl TapTrust {
+    pub fn is_trusted(self) -> bool {
+        self != TapTrust::Untrusted
+    }
+}
+
+impl std::fmt::Display for TapTrust {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            TapTrust::Untrusted => "untrusted",
+            TapTrust::Verified => "verified",
+            TapTrust::Official => "official",
+        })
+    }
+}
+
+impl Default for TapsConfig {
+    fn default() -> Self {
+        Self {
+            cask: false,
+            default: vec!["homebrew/core".to_string()],
+            api_only: false,
+            auto_tap: true,
+            clone_depth: 1,
+            trust: BTreeMap::new(),
+            require_trusted: false,
+        }
+    }
+}
+
+impl TapsConfig {
+    /// The taps to add on startup, after applying `api_only` and `cask`.
+    pub fn taps_to_add(&self) -> Vec<String> {
+        let mut taps: Vec<String> = self.default.iter()
+            .filter(|t| !(self.api_only && t.as_str() == "homebrew/core"))
+            .cloned()
+            .collect();
+        if self.cask && !taps.iter().any(|t| t == "homebrew/cask") {
+            taps.push("homebrew/cask".to_string());
+        }
+        taps
+    }
+
+    /// How far `tap` is trusted: its `trust` entry, the most specific pattern winning,
+    /// else official for `homebrew/*` and untrusted for everything else.
+    pub fn trust(&self, tap: &str) -> TapTrust {
+        if let Some(trust) = self.trust.get(tap) {
+            return *trust;
+        }
+        let matching = self.trust.iter()
+            .filter(|(pattern, _)| crate::core::policy::glob_matches(pattern, tap))
+            .max_by_key(|(pattern, _)| pattern.len());
+        match matching {
+            Some((_, trust)) => *trust,
+            None if tap.to_lowercase().starts_with("homebrew/") => TapTrust::Official,
+            None => TapTrust::Untrusted,
+        }
+    }
+}
+
+/// `owner/repo` of a GitHub clone URL, in HTTPS or SSH form.
+pub fn github_repository(url: &str) -> Option<String> {
+    let path = url.strip_prefix("https://github.com/")
+        .or_else(|| url.strip_prefix("http://github.com/"))
+        .or_else(|| url.strip_prefix("git@github.com:"))
+        .or_else(|| url.strip_prefix("ssh://git@github.com/"))?;
+    let mut parts = path.trim_end_matches('/').trim_end_matches(".git").splitn(3, '/');
+    let (owner, repo) = (parts.next()?, parts.next()?);
+    if owner.is_empty() || repo.is_empty() {
+        return None;
+    }
+    Some(format!("{}/{}", owner, repo))
+}
+
+/// The github.com page for `path` in `repository` (`owner/repo`) at `commit`. With
+/// `github`, the repository is looked up first: a renamed or transferred one gets its
+/// current name, and its default branch stands in for an unknown commit. Without it,
+/// or if the lookup fails, the name is used as given and the commit falls back to
+/// `HEAD`.
+pub async fn github_blob_url(github: Option<&GitHubClient>, repository: &str, commit: Option<String>, path: &Path) -> String {
+    let mut repository = repository.to_string();
+    let mut default_ref = "HEAD".to_string();
+    if let (Some(github), Some((owner, repo))) = (github, repository.split_once('/')) {
+        match github.repository(owner, repo).await {
+            Ok(found) => {
+                repository = found.full_name;
+                default_ref = found.default_branch;
+            }
+            Err(e) => tracing::debug!("Could not look up {} on GitHub: {}", repository, e),
+        }
+    }
+    format!("https://github.com/{}/blob/{}/{}", repository, commit.unwrap_or(default_ref), path.to_string_lossy())
+}
+
+/// A formula held at one exact revision of its file. Name lookups use the pinned copy
+/// until the pin is bumped or removed, whatever the tap updates to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FormulaPin {
+    pub name: String,
+    pub tap: Option<String>,
+    /// SHA-256 of the pinned formula file
+    pub hash: String,
+    /// Tap commit the file was taken from, when known
+    pub tap_commit: Option<String>,
+    pub pinned_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// The tap formulae from the JSON API belong to
+pub const CORE_TAP: &str = "homebrew/core";
+
+/// sled tree of the tap database holding formula pins
+const PINS_TREE: &str = "formula_pins";
+
+/// How many taps `update_all_taps` pulls at once
+const TAP_UPDATE_CONCURRENCY: usize = 4;
+
+/// Outcome of updating one tap.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TapUpdateStatus {
+    Updated { new_formulae: usize },
+    Unchanged,
+    Failed(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct TapUpdate {
+    pub name: String,
+    pub status: TapUpdateStatus,
+}
+
+/// Number of formula files among the paths a pull added.
+/// Formulae live in `Formula/`, `HomebrewFormula/` or the tap root.
+pub fn count_new_formulae<S: AsRef<str>>(added_paths: &[S]) -> usize {
+    added_paths.iter()
+        .map(|path| path.as_ref().trim())
+        .filter(|path| path.ends_with(".rb"))
+        .filter(|path| {
+            path.starts_with("Formula/") || path.starts_with("HomebrewFormula/") || !path.contains('/')
+        })
+        .count()
+}
+
+/// Reset a tap checkout that can't fast-forward (edited files, local commits) to the
+/// upstream commit it last fetched, so the next pull applies cleanly. Local work is saved under `backup` first: uncommitted changes as
+/// `local-changes.patch` plus copies in `files/`, local commits as patches in `commits/`.
+/// Returns whether anything had to be reset.
+pub async fn repair_checkout(checkout: &Path, backup: &Path) -> NitroResult<bool> {
+    let changed = git::changed_paths(checkout).await?;
+    let ahead = git::count_commits(checkout, "@{upstream}..HEAD").await?;
+    if changed.is_empty() && ahead == 0 {
+        return Ok(false);
+    }
+
+    std::fs::create_dir_all(backup)?;
+    if !changed.is_empty() {
+        std::fs::write(backup.join("local-changes.patch"), git::diff_head(checkout).await?)?;
+        for path in &changed {
+            let source = checkout.join(path);
+            if source.is_file() {
+                let dest = backup.join("files").join(path);
+                if let Some(parent) = dest.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::copy(&source, &dest)?;
+            }
+        }
+    }
+    if ahead > 0 {
+        git::format_patch(checkout, "@{upstream}..HEAD", &backup.join("commits")).await?;
+    }
+
+    git::reset_hard(checkout, "@{upstream}").await?;
+    Ok(true)
+}
+
+static AUTO_TAP_DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Skip adding default taps for the rest of this process (`--no-auto-tap`).
+pub fn disable_auto_tap() {
+    AUTO_TAP_DISABLED.store(true, Ordering::SeqCst);
+}
+
+static TRUST_REQUIRED: AtomicBool = AtomicBool::new(false);
+
+/// Refuse installs from untrusted taps for the rest of this process (`--require-trusted`).
+pub fn require_trusted() {
+    TRUST_REQUIRED.store(true, Ordering::SeqCst);
+}
+
+pub fn trust_required() -> bool {
+    TRUST_REQUIRED.load(Ordering::SeqCst)
+}
+
+static UNTRUSTED_ALLOWED: AtomicBool = AtomicBool::new(false);
+
+/// Install from untrusted taps without asking for the rest of this process
+/// (`--allow-untrusted`).
+pub fn allow_untrusted() {
+    UNTRUSTED_ALLOWED.store(true, Ordering::SeqCst);
+}
+
+pub fn untrusted_allowed() -> bool {
+    UNTRUSTED_ALLOWED.load(Ordering::SeqCst)
+}
+
 pub struct TapManager {
     taps_dir: PathBuf,
     db: sled::Db,
@@ -24,16 +268,20 @@ impl TapManager {
             .ok_or_else(|| NitroError::Other("Could not determine config directory".into()))?;
         
         let taps_dir = config_dir.data_dir().join("taps");
-        std::fs::create_dir_all(&taps_dir)?;
-        
         let db_path = config_dir.data_dir().join("taps.db");
-        let db = sled::Config::new()
-            .path(&db_path)
-            .mode(sled::Mode::HighThroughput)
-            .flush_every_ms(Some(1000))
-            .open()?;
 
-        let mut manager = Self { taps_dir, db };
+        let db = if readonly::is_enabled() {
+            readonly::open_snapshot(&db_path)?
+        } else {
+            std::fs::create_dir_all(&taps_dir)?;
+            sled::Config::new()
+                .path(&db_path)
+                .mode(sled::Mode::HighThroughput)
+                .flush_every_ms(Some(1000))
+                .open()?
+        };
+
+        let mut manager = Self::with_db(taps_dir, db);
         
         // Add default Homebrew taps if not present
         manager.ensure_default_taps().await?;
@@ -41,7 +289,15 @@ impl TapManager {
         Ok(manager)
     }
 
+    /// The taps recorded in `db` and checked out under `taps_dir`, rather than this
+    /// machine's. The default taps aren't added.
+    pub fn with_db(taps_dir: PathBuf, db: sled::Db) -> Self {
+        Self { taps_dir, db }
+    }
+
     pub async fn add_tap(&self, name: &str, custom_url: Option<&str>) -> NitroResult<()> {
+        Policy::load()?.check_tap(name)?;
+
         // Check if tap already exists
         if self.db.contains_key(name)? {
             return Err(NitroError::TapError(format!("Tap {} already exists", name)));
@@ -115,59 +371,202 @@ impl TapManager {
         Ok(taps)
     }
 
-    pub async fn update_tap(&self, name: &str) -> NitroResult<()> {
+    /// Pull a tap. With `force`, a checkout with local edits or commits is first reset
+    /// to upstream (see [`repair_checkout`]), keeping the local work under `tap-overrides`.
+    pub async fn update_tap(&self, name: &str, force: bool) -> NitroResult<TapUpdateStatus> {
         let mut tap = self.get_tap(name)?;
         
+        if force {
+            let backup = self.overrides_dir(name).join(chrono::Utc::now().format("%Y%m%d%H%M%S").to_string());
+            if repair_checkout(&tap.path, &backup).await? {
+                println!("Saved local changes to {} under {}", name, backup.display());
+            }
+        }
+
         // Pull latest changes
-        self.pull_tap(&tap.path).await?;
+        let before = git::head(&tap.path).await?;
+        match git::pull_ff_only(&tap.path).await {
+            Err(e @ (GitError::LocalChanges | GitError::NotFastForward)) => {
+                return Err(NitroError::TapError(format!(
+                    "{}: {}; run `nitro tap update {} --force` to reset it to upstream (local changes are saved under tap-overrides)",
+                    name, e, name
+                )));
+            }
+            result => result?,
+        }
+        let after = git::head(&tap.path).await?;
         
         // Update timestamp
         tap.updated_at = Some(chrono::Utc::now());
         self.db.insert(name, serde_json::to_vec(&tap)?)?;
         
-        Ok(())
+        if before == after {
+            return Ok(TapUpdateStatus::Unchanged);
+        }
+        let new_formulae = git::added_paths(&tap.path, &before, &after).await
+            .map(|paths| count_new_formulae(&paths))
+            .unwrap_or(0);
+        Ok(TapUpdateStatus::Updated { new_formulae })
     }
 
-    pub async fn update_all_taps(&self) -> Result<()> {
+    /// Where local changes to a tap are kept when `--force` resets it.
+    fn overrides_dir(&self, name: &str) -> PathBuf {
+        self.taps_dir.with_file_name("tap-overrides").join(name.replace('/', "_"))
+    }
+
+    /// Pull every tap, `TAP_UPDATE_CONCURRENCY` at a time. One tap failing doesn't stop
+    /// the others; its error is reported in the results, which are sorted by tap name.
+    pub async fn update_all_taps(&self, force: bool) -> Result<Vec<TapUpdate>> {
+        use futures::stream::{self, StreamExt};
+
         let taps = self.list_taps().await?;
-        
-        for tap in taps {
-            if let Err(e) = self.update_tap(&tap.name).await {
-                eprintln!("Failed to update tap {}: {}", tap.name, e);
-            }
-        }
-        
-        Ok(())
+        let mut updates: Vec<TapUpdate> = stream::iter(taps)
+            .map(|tap| async move {
+                let status = self.update_tap(&tap.name, force).await
+                    .unwrap_or_else(|e| TapUpdateStatus::Failed(e.to_string()));
+                TapUpdate { name: tap.name, status }
+            })
+            .buffer_unordered(TAP_UPDATE_CONCURRENCY)
+            .collect()
+            .await;
+
+        updates.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(updates)
     }
 
     pub async fn find_formula(&self, name: &str) -> NitroResult<PathBuf> {
         // Search for formula in all taps
         for tap in self.list_taps().await? {
-            // For formulas with @ (like python@3.12), we need to replace @ with at in the filename
-            let file_name = name.replace('@', "at");
-            
-            // Check direct path first (legacy layout)
-            let formula_path = tap.path.join("Formula").join(format!("{}.rb", file_name));
-            if formula_path.exists() {
-                return Ok(formula_path);
+            if let Some(path) = self.formula_in(&tap, name) {
+                return Ok(path);
             }
-            
-            // Check alphabetical subdirectories (modern layout)
-            let formula_dir = tap.path.join("Formula");
-            if formula_dir.exists() {
-                if let Ok(formula_path) = self.find_formula_recursive(&formula_dir, &file_name) {
-                    return Ok(formula_path);
-                }
+        }
+        
+        Err(NitroError::PackageNotFound(name.to_string()))
+    }
+
+    /// Find a formula in one particular tap.
+    pub async fn find_formula_in_tap(&self, tap: &str, name: &str) -> NitroResult<PathBuf> {
+        self.formula_in(&self.get_tap(tap)?, name)
+            .ok_or_else(|| NitroError::PackageNotFound(format!("{}/{}", tap, name)))
+    }
+
+    /// Find a cask in any tap's `Casks/` directory, flat or sharded by first letter.
+    pub async fn find_cask(&self, token: &str) -> NitroResult<PathBuf> {
+        let file_name = format!("{}.rb", token);
+        for tap in self.list_taps().await? {
+            let found = walkdir::WalkDir::new(tap.path.join("Casks")).max_depth(2).into_iter().flatten()
+                .find(|entry| entry.file_type().is_file() && entry.file_name().to_string_lossy() == file_name);
+            if let Some(entry) = found {
+                return Ok(entry.into_path());
             }
-            
-            // Also check HomebrewFormula directory (some taps use this)
-            let alt_path = tap.path.join("HomebrewFormula").join(format!("{}.rb", file_name));
-            if alt_path.exists() {
-                return Ok(alt_path);
+        }
+        Err(NitroError::PackageNotFound(format!("cask {}", token)))
+    }
+
+    fn formula_in(&self, tap: &Tap, name: &str) -> Option<PathBuf> {
+        // For formulas with @ (like python@3.12), we need to replace @ with at in the filename
+        let file_name = name.replace('@', "at");
+        
+        // Check direct path first (legacy layout)
+        let formula_path = tap.path.join("Formula").join(format!("{}.rb", file_name));
+        if formula_path.exists() {
+            return Some(formula_path);
+        }
+        
+        // Check alphabetical subdirectories (modern layout)
+        let formula_dir = tap.path.join("Formula");
+        if formula_dir.exists() {
+            if let Ok(formula_path) = self.find_formula_recursive(&formula_dir, &file_name) {
+                return Some(formula_path);
             }
         }
         
-        Err(NitroError::PackageNotFound(name.to_string()))
+        // Also check HomebrewFormula directory (some taps use this)
+        let alt_path = tap.path.join("HomebrewFormula").join(format!("{}.rb", file_name));
+        if alt_path.exists() {
+            return Some(alt_path);
+        }
+        None
+    }
+
+    /// The commit a tap's checkout is at.
+    pub async fn tap_commit(&self, tap: &str) -> Option<String> {
+        git::head(&self.get_tap(tap).ok()?.path).await.ok()
+    }
+
+    /// Where `formula` can be read on GitHub, at the commit its tap is checked out at
+    /// so it's the file nitro uses. `None` for formulae not read from a tap's file (API
+    /// formulae) and taps not hosted on GitHub. With `github`, the tap's repository is
+    /// looked up through the API (see `github_blob_url`).
+    pub async fn formula_github_url(&self, formula: &crate::core::formula::Formula, github: Option<&GitHubClient>) -> NitroResult<Option<String>> {
+        let (Some(tap), Some(path)) = (&formula.tap, &formula.path) else {
+            return Ok(None);
+        };
+        let tap = self.get_tap(tap)?;
+        let Some(repository) = github_repository(&tap.url) else {
+            return Ok(None);
+        };
+        let relative = path.strip_prefix(&tap.path)
+            .map_err(|_| NitroError::TapError(format!("{} is not in tap {}", path.display(), tap.name)))?;
+        let commit = git::head(&tap.path).await.ok();
+        Ok(Some(github_blob_url(github, &repository, commit, relative).await))
+    }
+
+    /// Contents of a tap's formula file at an earlier commit.
+    pub async fn formula_at_commit(&self, tap: &str, commit: &str, path: &Path) -> NitroResult<String> {
+        let tap = self.get_tap(tap)?;
+        let relative = path.strip_prefix(&tap.path)
+            .map_err(|_| NitroError::TapError(format!("{} is not in tap {}", path.display(), tap.name)))?;
+        Ok(git::show_file(&tap.path, commit, relative).await?)
+    }
+
+    /// Pin `content` as the formula `pin.name` resolves to.
+    pub fn pin_formula(&self, pin: &FormulaPin, content: &str) -> NitroResult<()> {
+        std::fs::create_dir_all(self.pins_dir())?;
+        std::fs::write(self.pinned_file(&pin.name), content)?;
+        self.db.open_tree(PINS_TREE)?.insert(&pin.name, serde_json::to_vec(pin)?)?;
+        Ok(())
+    }
+
+    /// Remove a formula pin. Returns false if the formula wasn't pinned.
+    pub fn unpin_formula(&self, name: &str) -> NitroResult<bool> {
+        let removed = self.db.open_tree(PINS_TREE)?.remove(name)?.is_some();
+        let _ = std::fs::remove_file(self.pinned_file(name));
+        Ok(removed)
+    }
+
+    /// The pin on `name` and the path of its pinned formula file, if pinned.
+    pub fn formula_pin(&self, name: &str) -> NitroResult<Option<(FormulaPin, PathBuf)>> {
+        match self.db.open_tree(PINS_TREE)?.get(name)? {
+            Some(data) => Ok(Some((serde_json::from_slice(&data)?, self.pinned_file(name)))),
+            None => Ok(None),
+        }
+    }
+
+    pub fn list_formula_pins(&self) -> NitroResult<Vec<FormulaPin>> {
+        let mut pins = Vec::new();
+        for entry in self.db.open_tree(PINS_TREE)?.iter() {
+            let (_, value) = entry?;
+            pins.push(serde_json::from_slice(&value)?);
+        }
+        Ok(pins)
+    }
+
+    fn pins_dir(&self) -> PathBuf {
+        self.taps_dir.with_file_name("formula-pins")
+    }
+
+    fn pinned_file(&self, name: &str) -> PathBuf {
+        self.pins_dir().join(format!("{}.rb", name))
+    }
+
+    /// Name of the tap a formula file belongs to.
+    pub async fn tap_for_path(&self, path: &Path) -> Option<String> {
+        self.list_taps().await.ok()?
+            .into_iter()
+            .find(|tap| path.starts_with(&tap.path))
+            .map(|tap| tap.name)
     }
 
     fn find_formula_recursive(&self, dir: &std::path::Path, name: &str) -> NitroResult<PathBuf> {
@@ -195,10 +594,18 @@ impl TapManager {
             eprintln!("Warning: Could not import Homebrew taps: {}", e);
         }
         
-        // Add homebrew/core if not present
-        if !self.db.contains_key("homebrew/core")? {
-            if let Err(e) = self.add_tap("homebrew/core", None).await {
-                eprintln!("Warning: Could not add homebrew/core tap: {}", e);
+        let config = crate::core::config::Config::load().map(|c| c.taps).unwrap_or_default();
+        if !config.auto_tap || AUTO_TAP_DISABLED.load(Ordering::SeqCst) || readonly::is_enabled() {
+            return Ok(());
+        }
+
+        // Taps the policy blocks are left out quietly rather than warned about each run
+        let policy = Policy::load()?;
+        for tap in config.taps_to_add() {
+            if !self.db.contains_key(&tap)? && policy.check_tap(&tap).is_ok() {
+                if let Err(e) = self.add_tap(&tap, None).await {
+                    eprintln!("Warning: Could not add {} tap: {}", tap, e);
+                }
             }
         }
         
@@ -260,7 +667,10 @@ impl TapManager {
                 };
 
                 self.db.insert(&tap_name, serde_json::to_vec(&tap)?)?;
-                println!("Imported existing Homebrew tap: {}", tap_name);
+                // Read-only runs import into a snapshot, so this would repeat every time
+                if !readonly::is_enabled() {
+                    println!("Imported existing Homebrew tap: {}", tap_name);
+                }
             }
         }
 
@@ -268,32 +678,22 @@ impl TapManager {
     }
 
     async fn clone_tap(&self, url: &str, path: &Path) -> Result<()> {
-        let output = Command::new("git")
-            .args(&["clone", "--depth", "1", url, path.to_str().unwrap()])
-            .output()
-            .await?;
-
-        if !output.status.success() {
-            return Err(NitroError::TapError(
-                format!("Failed to clone tap: {}", String::from_utf8_lossy(&output.stderr))
-            ).into());
-        }
-
-        Ok(())
-    }
-
-    async fn pull_tap(&self, path: &Path) -> Result<()> {
-        let output = Command::new("git")
-            .args(&["pull", "--ff-only"])
-            .current_dir(path)
-            .output()
-            .await?;
-
-        if !output.status.success() {
-            return Err(NitroError::TapError(
-                format!("Failed to update tap: {}", String::from_utf8_lossy(&output.stderr))
-            ).into());
-        }
+        use indicatif::{ProgressBar, ProgressStyle};
+
+        let config = crate::core::config::Config::load().map(|c| c.taps).unwrap_or_default();
+        let depth = (config.clone_depth > 0).then_some(config.clone_depth);
+
+        let pb = ProgressBar::new(100);
+        pb.set_style(
+            ProgressStyle::default_bar()
+                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}% {msg}")?
+                .progress_chars("#>-"),
+        );
+        pb.set_message(format!("Cloning {}", url));
+
+        let result = git::clone(url, path, depth, None, |percent| pb.set_position(percent as u64)).await;
+        pb.finish_and_clear();
+        result?;
 
         Ok(())
     }
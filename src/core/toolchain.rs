@@ -0,0 +1,118 @@
+//! The build tools a source build runs (make, cmake, pkg-config, a compiler), so
+//! missing ones can be reported, or installed, before any source is downloaded rather
+//! than failing deep inside `configure`.
+
+use std::path::{Path, PathBuf};
+
+use crate::core::formula::Formula;
+
+/// A program a source build runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuildTool {
+    pub program: &'static str,
+    /// Formula that provides it, if nitro can install it
+    pub formula: Option<&'static str>,
+}
+
+const TOOLS: &[BuildTool] = &[
+    BuildTool { program: "make", formula: Some("make") },
+    BuildTool { program: "cmake", formula: Some("cmake") },
+    BuildTool { program: "ninja", formula: Some("ninja") },
+    BuildTool { program: "meson", formula: Some("meson") },
+    BuildTool { program: "pkg-config", formula: Some("pkgconf") },
+    BuildTool { program: "autoreconf", formula: Some("autoconf") },
+    BuildTool { program: "automake", formula: Some("automake") },
+    BuildTool { program: "libtoolize", formula: Some("libtool") },
+    BuildTool { program: "cargo", formula: Some("rust") },
+    BuildTool { program: "go", formula: Some("go") },
+];
+
+/// Install script helpers that imply a tool without naming it in a `system` call.
+const HELPERS: &[(&str, &str)] = &[
+    ("std_cmake_args", "cmake"),
+    ("std_meson_args", "meson"),
+    ("std_cargo_args", "cargo"),
+    ("std_go_args", "go"),
+];
+
+/// Programs that need a C compiler to be useful.
+const COMPILING: &[&str] = &["make", "cmake", "ninja", "meson", "./configure"];
+
+/// The C compiler. Nitro can't provide `cc`: it comes with the Command Line Tools on
+/// macOS and the system's compiler package elsewhere, and the `gcc` formula only
+/// installs versioned commands such as `gcc-14`.
+pub fn compiler() -> BuildTool {
+    BuildTool { program: "cc", formula: None }
+}
+
+/// The tools building `formula` from source runs: those its install script calls (or
+/// `./configure` and `make` without one), plus a compiler for anything that compiles.
+/// Tools the formula itself provides, or depends on, are left out since the
+/// dependency install takes care of them.
+pub fn required_tools(formula: &Formula) -> Vec<BuildTool> {
+    let mut programs: Vec<&str> = Vec::new();
+    match &formula.install_script {
+        Some(script) => {
+            for line in script.lines().map(str::trim).filter(|l| l.starts_with("system")) {
+                if let Some(program) = line.split('"').nth(1).and_then(|cmd| cmd.split_whitespace().next()) {
+                    programs.push(program);
+                }
+            }
+            for (helper, program) in HELPERS {
+                if script.contains(helper) {
+                    programs.push(program);
+                }
+            }
+        }
+        None => programs.extend(["./configure", "make"]),
+    }
+
+    let declared: Vec<&str> = formula.dependencies.iter()
+        .chain(&formula.build_dependencies)
+        .map(|d| d.name.as_str())
+        .chain([formula.name.as_str()])
+        .collect();
+    let mut tools: Vec<BuildTool> = TOOLS.iter()
+        .filter(|tool| programs.contains(&tool.program))
+        .copied()
+        .collect();
+    if programs.iter().any(|p| COMPILING.contains(p)) {
+        tools.push(compiler());
+    }
+    tools.retain(|tool| !tool.formula.is_some_and(|f| declared.contains(&f)));
+    tools
+}
+
+/// Which of `tools` can't be found on `PATH` or in `extra_dirs`.
+pub fn missing(tools: &[BuildTool], extra_dirs: &[PathBuf]) -> Vec<BuildTool> {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    let dirs: Vec<PathBuf> = std::env::split_paths(&path).chain(extra_dirs.iter().cloned()).collect();
+    tools.iter()
+        .filter(|tool| !dirs.iter().any(|dir| is_executable(&dir.join(tool.program))))
+        .copied()
+        .collect()
+}
+
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata().is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+/// How to get the missing `tools`: the `nitro install` command for those a formula
+/// provides, and for a compiler the Command Line Tools on macOS or the system's
+/// compiler package elsewhere.
+pub fn install_hint(tools: &[BuildTool]) -> String {
+    let formulae: Vec<&str> = tools.iter().filter_map(|t| t.formula).collect();
+    let mut hints = Vec::new();
+    if !formulae.is_empty() {
+        hints.push(format!("run `nitro install {}`", formulae.join(" ")));
+    }
+    if tools.iter().any(|t| t.formula.is_none()) {
+        hints.push(if cfg!(target_os = "macos") {
+            "run `xcode-select --install` for a compiler".to_string()
+        } else {
+            "install your system's compiler package (`build-essential` on Debian and Ubuntu, `gcc` on Fedora) for a compiler".to_string()
+        });
+    }
+    hints.join(" and ")
+}
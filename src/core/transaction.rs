@@ -0,0 +1,235 @@
+//! A journal of what an install changes in the prefix, so a failed or interrupted one
+//! can be undone. Every path created is recorded, and anything in the way is moved
+//! aside into a backup directory (or, for a symlink, its target remembered) rather than
+//! deleted. Rolling back undoes the changes in reverse order; committing forgets them
+//! and drops the backups. A transaction dropped unfinished, as when Ctrl-C's grace
+//! period runs out mid-install, rolls back.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::core::workspace::{self, Workspace};
+
+#[derive(Debug)]
+enum Change {
+    /// A file, symlink or directory that wasn't there before
+    Created(PathBuf),
+    /// Something that was at `path`, now at `backup`
+    MovedAside { path: PathBuf, backup: PathBuf },
+    /// A symlink that was at `path`, pointing at `target`
+    ReplacedLink { path: PathBuf, target: PathBuf },
+    /// The value `key` had in `db`, `None` if it had none
+    DbEntry { db: sled::Db, key: String, previous: Option<sled::IVec> },
+}
+
+/// A point in a transaction to roll back to, from [`Transaction::savepoint`].
+#[derive(Debug, Clone, Copy)]
+pub struct Savepoint(usize);
+
+pub struct Transaction {
+    backups: Option<Workspace>,
+    changes: Mutex<Vec<Change>>,
+    finished: bool,
+}
+
+impl Transaction {
+    /// Start a transaction for installing `name` at `version`, keeping backups in a
+    /// workspace under `root` so they can be renamed back into the prefix.
+    pub fn begin(root: &Path, name: &str, version: &str) -> io::Result<Self> {
+        Ok(Self {
+            backups: Some(Workspace::create(root, &format!("{}-backup", name), version)?),
+            changes: Mutex::new(Vec::new()),
+            finished: false,
+        })
+    }
+
+    fn record(&self, change: Change) {
+        self.changes.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push(change);
+    }
+
+    /// Record that `path` is about to be created; rolling back removes whatever is
+    /// there by then.
+    pub fn created(&self, path: &Path) {
+        self.record(Change::Created(path.to_path_buf()));
+    }
+
+    /// `std::fs::create_dir_all`, recording each directory it creates.
+    pub fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let missing: Vec<&Path> = path.ancestors().take_while(|dir| !dir.exists()).collect();
+        for dir in missing.into_iter().rev() {
+            std::fs::create_dir(dir)?;
+            self.created(dir);
+        }
+        Ok(())
+    }
+
+    /// Clear `path` for something new, keeping what was there for a rollback. Nothing
+    /// there is nothing to do.
+    pub fn move_aside(&self, path: &Path) -> io::Result<()> {
+        let Ok(metadata) = std::fs::symlink_metadata(path) else {
+            return Ok(());
+        };
+        if metadata.file_type().is_symlink() {
+            let target = std::fs::read_link(path)?;
+            std::fs::remove_file(path)?;
+            self.record(Change::ReplacedLink { path: path.to_path_buf(), target });
+            return Ok(());
+        }
+
+        let backups = self.backups.as_ref().expect("transaction is unfinished").path();
+        let backup = backups.join(self.changes.lock().unwrap_or_else(|p| p.into_inner()).len().to_string());
+        move_path(path, &backup)?;
+        self.record(Change::MovedAside { path: path.to_path_buf(), backup });
+        Ok(())
+    }
+
+    /// Symlink `dst` to `src`, moving aside anything already at `dst`. A symlink
+    /// there is swapped for the new one in a single rename, so a command being
+    /// upgraded never goes missing.
+    pub fn symlink(&self, src: &Path, dst: &Path) -> io::Result<()> {
+        let replacing_link = std::fs::symlink_metadata(dst).is_ok_and(|m| m.file_type().is_symlink());
+        if !replacing_link {
+            self.move_aside(dst)?;
+            self.created(dst);
+            return std::os::unix::fs::symlink(src, dst);
+        }
+        let target = std::fs::read_link(dst)?;
+        replace_link(src, dst)?;
+        self.record(Change::ReplacedLink { path: dst.to_path_buf(), target });
+        Ok(())
+    }
+
+    /// Remember `key`'s current value in `db`, to put back on rollback.
+    pub fn record_db_entry(&self, db: &sled::Db, key: &str) -> sled::Result<()> {
+        let previous = db.get(key)?;
+        self.record(Change::DbEntry { db: db.clone(), key: key.to_string(), previous });
+        Ok(())
+    }
+
+    /// The transaction as it stands, to undo what comes after with `rollback_to`.
+    pub fn savepoint(&self) -> Savepoint {
+        Savepoint(self.changes.lock().unwrap_or_else(|p| p.into_inner()).len())
+    }
+
+    /// Undo the changes made since `savepoint`, latest first, and carry on from there.
+    /// Like `rollback`, every change is attempted and the first failure returned.
+    pub fn rollback_to(&self, savepoint: Savepoint) -> io::Result<()> {
+        let later = {
+            let mut changes = self.changes.lock().unwrap_or_else(|p| p.into_inner());
+            let at = savepoint.0.min(changes.len());
+            changes.split_off(at)
+        };
+        let mut first_error = None;
+        for change in later.into_iter().rev() {
+            if let Err(e) = undo(&change) {
+                tracing::warn!("Could not undo {:?}: {}", change, e);
+                first_error.get_or_insert(e);
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+
+    /// Keep every change and drop the backups.
+    pub fn commit(mut self) {
+        self.finished = true;
+        self.changes.get_mut().unwrap_or_else(|p| p.into_inner()).clear();
+    }
+
+    /// Undo every change, latest first. Each is attempted even if an earlier one
+    /// fails; the first failure is returned, and the backups are kept when there is one
+    /// so nothing moved aside is lost.
+    pub fn rollback(mut self) -> io::Result<()> {
+        self.finished = true;
+        self.undo()
+    }
+
+    fn undo(&mut self) -> io::Result<()> {
+        let changes = std::mem::take(self.changes.get_mut().unwrap_or_else(|p| p.into_inner()));
+        let mut first_error = None;
+        for change in changes.into_iter().rev() {
+            if let Err(e) = undo(&change) {
+                tracing::warn!("Could not undo {:?}: {}", change, e);
+                first_error.get_or_insert(e);
+            }
+        }
+        match first_error {
+            Some(e) => {
+                if let Some(backups) = self.backups.take() {
+                    eprintln!("Warning: rollback was incomplete; backups were kept in {}", backups.keep().display());
+                }
+                Err(e)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        if !self.finished {
+            if let Err(e) = self.undo() {
+                eprintln!("Warning: could not roll back an unfinished install: {}", e);
+            }
+        }
+    }
+}
+
+fn undo(change: &Change) -> io::Result<()> {
+    match change {
+        Change::Created(path) => remove_path(path),
+        Change::MovedAside { path, backup } => {
+            remove_path(path)?;
+            move_path(backup, path)
+        }
+        Change::ReplacedLink { path, target } => {
+            if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink()) {
+                return replace_link(target, path);
+            }
+            remove_path(path)?;
+            std::os::unix::fs::symlink(target, path)
+        }
+        Change::DbEntry { db, key, previous } => {
+            match previous {
+                Some(value) => db.insert(key, value.clone())?,
+                None => db.remove(key)?,
+            };
+            Ok(())
+        }
+    }
+}
+
+/// Point the symlink `link` at `target` by renaming a new link over it.
+fn replace_link(target: &Path, link: &Path) -> io::Result<()> {
+    let name = link.file_name().unwrap_or_default().to_string_lossy();
+    let staged = link.with_file_name(format!(".{}.nitro-new", name));
+    let _ = std::fs::remove_file(&staged);
+    std::os::unix::fs::symlink(target, &staged)?;
+    std::fs::rename(&staged, link).inspect_err(|_| {
+        let _ = std::fs::remove_file(&staged);
+    })
+}
+
+/// Remove whatever is at `path`, if anything.
+fn remove_path(path: &Path) -> io::Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => std::fs::remove_dir_all(path),
+        Ok(_) => std::fs::remove_file(path),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Move a file or directory, copying when `to` is on another filesystem.
+fn move_path(from: &Path, to: &Path) -> io::Result<()> {
+    if std::fs::symlink_metadata(from)?.is_dir() {
+        return workspace::move_dir(from, to);
+    }
+    match std::fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            std::fs::copy(from, to)?;
+            std::fs::remove_file(from)
+        }
+        result => result,
+    }
+}
@@ -0,0 +1,325 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fmt;
+
+/// A formula version, compared the way Homebrew compares them: numeric segments as
+/// numbers (`1.10 > 1.9`), trailing zero segments ignored (`1.0 == 1.0.0`), pre-releases
+/// before the release (`1.0rc1 < 1.0`) and patch levels after it (`1.0p1 > 1.0`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Version(String);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Numeric(u64),
+    /// alpha, beta, pre and rc, ranked in that order, with their number
+    PreRelease(u8, u64),
+    Patch(u64),
+    Str(String),
+}
+
+impl Token {
+    /// How mixed token kinds order against each other
+    fn class(&self) -> u8 {
+        match self {
+            Token::Str(_) => 0,
+            Token::PreRelease(..) => 1,
+            Token::Patch(_) => 2,
+            Token::Numeric(_) => 3,
+        }
+    }
+
+    /// This token against a missing one, i.e. `1.0.x` against `1.0`.
+    fn cmp_missing(&self) -> Ordering {
+        match self {
+            Token::Numeric(0) => Ordering::Equal,
+            Token::PreRelease(..) => Ordering::Less,
+            _ => Ordering::Greater,
+        }
+    }
+}
+
+impl Ord for Token {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Token::Numeric(a), Token::Numeric(b)) => a.cmp(b),
+            (Token::PreRelease(ra, a), Token::PreRelease(rb, b)) => ra.cmp(rb).then(a.cmp(b)),
+            (Token::Patch(a), Token::Patch(b)) => a.cmp(b),
+            (Token::Str(a), Token::Str(b)) => a.cmp(b),
+            _ => self.class().cmp(&other.class()),
+        }
+    }
+}
+
+impl PartialOrd for Token {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Version {
+    pub fn new(version: impl Into<String>) -> Self {
+        Self(version.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Versions the parser couldn't determine. They sort before every known version;
+    /// callers deciding whether something is an update should skip them instead.
+    pub fn is_unknown(&self) -> bool {
+        self.0.is_empty() || self.0 == "unknown"
+    }
+
+    /// Work out the version from a download URL, following Homebrew's heuristics:
+    /// the version is looked for at the end of the file name (after dropping archive
+    /// extensions and suffixes like `-src` or `-linux-x86_64`), then in the directory
+    /// names. Returns `None` rather than guessing when nothing looks like a version.
+    pub fn detect_from_url(url: &str) -> Option<Version> {
+        let path = url.split(['?', '#']).next().unwrap_or(url).trim_end_matches('/');
+        let mut segments: Vec<&str> = path.split('/').collect();
+        // SourceForge and friends end in /download
+        if segments.len() > 1 && segments.last().is_some_and(|s| *s == "download" || *s == "get") {
+            segments.pop();
+        }
+        let file_name = *segments.last()?;
+        let stem = strip_archive_extension(file_name);
+
+        for pattern in FILE_PATTERNS {
+            if let Some(cap) = regex::Regex::new(pattern).unwrap().captures(stem) {
+                return Some(Version::new(&cap[1]));
+            }
+        }
+
+        // Versioned directories: /releases/download/v1.2.3/tool.tar.gz, /pub/foo/1.2/foo.tar.gz
+        let directory = regex::Regex::new(r"^[vV]?(\d+(?:\.\d+)+(?:-?(?:alpha|beta|rc|pre)\.?\d*)?)$").unwrap();
+        segments.iter().rev().skip(1)
+            .find_map(|segment| directory.captures(segment))
+            .map(|cap| Version::new(&cap[1]))
+    }
+
+    fn tokens(&self) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        let mut chars = self.0.chars().peekable();
+
+        while let Some(&c) = chars.peek() {
+            if c.is_ascii_digit() {
+                tokens.push(Token::Numeric(take_number(&mut chars)));
+            } else if c.is_ascii_alphabetic() {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek().filter(|c| c.is_ascii_alphabetic()) {
+                    word.push(c.to_ascii_lowercase());
+                    chars.next();
+                }
+                // The number directly after a pre-release or patch word belongs to it
+                let rank = match word.as_str() {
+                    "alpha" | "a" => Some(0),
+                    "beta" | "b" => Some(1),
+                    "pre" => Some(2),
+                    "rc" => Some(3),
+                    "p" | "patch" => None,
+                    _ => {
+                        tokens.push(Token::Str(word));
+                        continue;
+                    }
+                };
+                let number = if chars.peek().is_some_and(|c| c.is_ascii_digit()) { take_number(&mut chars) } else { 0 };
+                tokens.push(match rank {
+                    Some(rank) => Token::PreRelease(rank, number),
+                    None => Token::Patch(number),
+                });
+            } else {
+                // Separators: . - _ + and anything else
+                chars.next();
+            }
+        }
+
+        tokens
+    }
+}
+
+/// Version patterns for a file name without its extension, most specific first. Each
+/// is anchored at the end so numbers inside the name (`bzip2`, `x264`) aren't picked up.
+const FILE_PATTERNS: &[&str] = &[
+    // The whole name is the version: GitHub archives of tags (v1.2.3.tar.gz)
+    r"^[vV]?(\d+(?:\.\d+)+(?:-?(?:alpha|beta|rc|pre|a|b)\.?\d*)?)$",
+    // Binary releases: tool-1.2.3-linux-x86_64, tool_1.2.3_darwin_arm64
+    r"[-_][vV]?(\d+(?:\.\d+)+)[-_.](?:linux|darwin|macos|osx|mac|windows|win64|x86_64|amd64|arm64|aarch64|universal|bin|static)\b",
+    // name-1.2.3, name_1.2.3rc1, name-1.2.3-src, name_1.2.3.orig
+    r"[-_.][vV]?(\d+(?:\.\d+)+(?:-?(?:alpha|beta|rc|pre|a|b|p)\.?\d*)?)(?:[-_.](?:src|source|orig|stable|final|release|full))?$",
+    // Dated snapshots: name-20240102 or name-2024-01-02
+    r"[-_](\d{4}-\d{2}-\d{2}|\d{8})$",
+    // A lone major version: name-5
+    r"[-_][vV]?(\d+)$",
+];
+
+/// `foo-1.2.tar.gz` → `foo-1.2`. Unknown extensions are left alone, as they may be
+/// part of the version.
+fn strip_archive_extension(file_name: &str) -> &str {
+    const EXTENSIONS: &[&str] = &[
+        ".tar.gz", ".tar.bz2", ".tar.xz", ".tar.lz", ".tar.lzma", ".tar.zst", ".tar",
+        ".tgz", ".tbz", ".tbz2", ".txz", ".zip", ".7z", ".gz", ".bz2", ".xz", ".zst",
+        ".gem", ".jar", ".dmg", ".pkg", ".deb", ".rpm", ".crate",
+    ];
+    EXTENSIONS.iter()
+        .find_map(|ext| file_name.strip_suffix(ext))
+        .unwrap_or(file_name)
+}
+
+fn take_number(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> u64 {
+    let mut value: u64 = 0;
+    while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+        value = value.saturating_mul(10).saturating_add(digit as u64);
+        chars.next();
+    }
+    value
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.is_unknown(), other.is_unknown()) {
+            (true, true) => return Ordering::Equal,
+            (true, false) => return Ordering::Less,
+            (false, true) => return Ordering::Greater,
+            (false, false) => {}
+        }
+
+        let (a, b) = (self.tokens(), other.tokens());
+        for i in 0..a.len().max(b.len()) {
+            let ordering = match (a.get(i), b.get(i)) {
+                (Some(x), Some(y)) => x.cmp(y),
+                (Some(x), None) => x.cmp_missing(),
+                (None, Some(y)) => y.cmp_missing().reverse(),
+                (None, None) => Ordering::Equal,
+            };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A version plus formula revision, as found in keg names (`1.2.3_1`). The revision
+/// only matters between equal versions.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PkgVersion {
+    pub version: Version,
+    pub revision: u32,
+}
+
+impl PkgVersion {
+    pub fn new(version: impl Into<String>, revision: u32) -> Self {
+        Self { version: Version::new(version), revision }
+    }
+
+    /// Split a trailing `_N` revision off a keg version.
+    pub fn parse(pkg_version: &str) -> Self {
+        match pkg_version.rsplit_once('_') {
+            Some((version, revision)) if !version.is_empty() && !revision.is_empty() && revision.bytes().all(|b| b.is_ascii_digit()) => {
+                Self::new(version, revision.parse().unwrap_or(0))
+            }
+            _ => Self::new(pkg_version, 0),
+        }
+    }
+}
+
+impl fmt::Display for PkgVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&crate::core::formula::pkg_version(self.version.as_str(), self.revision))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(s: &str) -> Version {
+        Version::new(s)
+    }
+
+    #[test]
+    fn test_numeric_segments() {
+        assert!(v("1.10") > v("1.9"));
+        assert!(v("2.0") > v("1.99.99"));
+        assert!(v("1.2.10") > v("1.2.9"));
+        assert_eq!(v("1.0"), v("1.0.0"));
+        assert!(v("1.0.1") > v("1.0"));
+    }
+
+    #[test]
+    fn test_pre_releases_and_patches() {
+        assert!(v("1.0rc1") < v("1.0"));
+        assert!(v("1.0alpha") < v("1.0beta"));
+        assert!(v("1.0b2") < v("1.0rc1"));
+        assert!(v("1.0rc1") < v("1.0rc2"));
+        assert!(v("1.0-beta.3") < v("1.0"));
+        assert!(v("1.0p1") > v("1.0"));
+        assert!(v("1.0.1") > v("1.0p1"));
+    }
+
+    #[test]
+    fn test_unknown_versions_sort_first() {
+        assert!(v("unknown") < v("0.1"));
+        assert_eq!(v(""), v("unknown"));
+        assert!(v("unknown").is_unknown());
+    }
+
+    #[test]
+    fn test_detect_from_url() {
+        let cases = [
+            ("https://ftp.gnu.org/gnu/wget/wget-1.24.5.tar.gz", "1.24.5"),
+            ("https://github.com/BurntSushi/ripgrep/archive/refs/tags/14.1.0.tar.gz", "14.1.0"),
+            ("https://github.com/junegunn/fzf/archive/refs/tags/v0.54.3.tar.gz", "0.54.3"),
+            ("https://sourceware.org/pub/bzip2/bzip2-1.0.8.tar.gz", "1.0.8"),
+            ("https://www.lua.org/ftp/lua-5.4.6.tar.gz", "5.4.6"),
+            ("https://www.python.org/ftp/python/3.12.4/Python-3.12.4.tar.xz", "3.12.4"),
+            ("https://downloads.sourceforge.net/project/libpng/libpng16/1.6.43/libpng-1.6.43.tar.xz", "1.6.43"),
+            ("https://sourceforge.net/projects/foo/files/foo-2.1.tar.gz/download", "2.1"),
+            ("https://example.com/tool-2.0.1-linux-x86_64.tar.gz", "2.0.1"),
+            ("https://example.com/foo_1.2.3.orig.tar.gz", "1.2.3"),
+            ("https://example.com/foo-1.2.3-src.tar.gz", "1.2.3"),
+            ("https://example.com/foo-1.0rc2.tar.gz", "1.0rc2"),
+            ("https://example.com/foo-1.0-beta.3.tar.gz", "1.0-beta.3"),
+            ("https://example.com/snapshots/x264-snapshot-20191217.tar.bz2", "20191217"),
+            ("https://example.com/tzdata-2024-01-02.tar.gz", "2024-01-02"),
+            ("https://github.com/owner/tool/releases/download/v3.2.1/tool.tar.gz", "3.2.1"),
+            ("https://example.com/foo-7.tar.gz?raw=true", "7"),
+        ];
+        for (url, expected) in cases {
+            assert_eq!(Version::detect_from_url(url).map(|v| v.to_string()).as_deref(), Some(expected), "{}", url);
+        }
+
+        assert!(Version::detect_from_url("https://example.com/curl.tar.gz").is_none());
+    }
+
+    #[test]
+    fn test_pkg_version() {
+        assert_eq!(PkgVersion::parse("1.2.3_1"), PkgVersion::new("1.2.3", 1));
+        assert_eq!(PkgVersion::parse("1.2.3"), PkgVersion::new("1.2.3", 0));
+        assert!(PkgVersion::parse("1.2.3_1") > PkgVersion::parse("1.2.3"));
+        assert!(PkgVersion::parse("1.2.4") > PkgVersion::parse("1.2.3_5"));
+        assert_eq!(PkgVersion::parse("1.2.3_2").to_string(), "1.2.3_2");
+    }
+}
@@ -0,0 +1,124 @@
+//! Scratch directories installs download, extract and build in. They live under the
+//! prefix rather than the system temp directory so the finished keg can be renamed into
+//! the Cellar, and each install gets its own so concurrent installs never collide.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
+
+static KEEP_ALL: AtomicBool = AtomicBool::new(false);
+
+/// Keep every workspace this process creates, successful or not (`--keep-tmp`).
+pub fn keep_all() {
+    KEEP_ALL.store(true, Ordering::SeqCst);
+}
+
+pub fn keeps_all() -> bool {
+    KEEP_ALL.load(Ordering::SeqCst)
+}
+
+/// Where workspaces are created for `prefix`.
+pub fn root(prefix: &Path) -> PathBuf {
+    prefix.join("var/nitro/tmp")
+}
+
+/// How long a failed install's workspace is kept for debugging before `cleanup`
+/// removes it
+pub const KEPT_WORKSPACE_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// The workspaces under `root` not modified for `max_age`, sorted: the kept files of
+/// failed installs nobody came back to. Newer ones may belong to installs still
+/// running, so they're left alone.
+pub fn stale(root: &Path, max_age: Duration) -> Vec<PathBuf> {
+    let now = SystemTime::now();
+    let mut stale: Vec<PathBuf> = std::fs::read_dir(root).into_iter().flatten().flatten()
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+        .filter(|entry| entry.metadata().and_then(|m| m.modified()).is_ok_and(|modified| {
+            now.duration_since(modified).is_ok_and(|age| age >= max_age)
+        }))
+        .map(|entry| entry.path())
+        .collect();
+    stale.sort();
+    stale
+}
+
+/// A directory removed when dropped, unless it has been kept.
+#[derive(Debug)]
+pub struct Workspace {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl Workspace {
+    /// Create a workspace for `name` at `version` under `root`, named `<name>-<version>`
+    /// with a numeric suffix when another install (or a kept failure) already has that.
+    pub fn create(root: &Path, name: &str, version: &str) -> io::Result<Self> {
+        std::fs::create_dir_all(root)?;
+        let base = format!("{}-{}", name, version);
+        for attempt in 0u32.. {
+            let path = match attempt {
+                0 => root.join(&base),
+                n => root.join(format!("{}-{}", base, n)),
+            };
+            // create_dir fails if the directory exists, so two processes never share one
+            match std::fs::create_dir(&path) {
+                Ok(()) => return Ok(Self { path, keep: false }),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        unreachable!("ran out of workspace names")
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Leave the workspace in place, e.g. to debug a failed build, and return its path.
+    pub fn keep(mut self) -> PathBuf {
+        self.keep = true;
+        self.path.clone()
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        if !self.keep {
+            if let Err(e) = std::fs::remove_dir_all(&self.path) {
+                tracing::warn!("Could not remove workspace {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+/// Move the directory `from` to `to`, copying it and removing the original when they're
+/// on different filesystems and a rename isn't possible.
+pub fn move_dir(from: &Path, to: &Path) -> io::Result<()> {
+    match std::fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            copy_dir(from, to).inspect_err(|_| {
+                let _ = std::fs::remove_dir_all(to);
+            })?;
+            std::fs::remove_dir_all(from)
+        }
+        result => result,
+    }
+}
+
+/// Copy the tree at `from` to `to`, keeping symlinks as symlinks and file permissions.
+pub fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    for entry in walkdir::WalkDir::new(from) {
+        let entry = entry?;
+        let dest = to.join(entry.path().strip_prefix(from).unwrap_or(entry.path()));
+        let file_type = entry.file_type();
+        if file_type.is_dir() {
+            std::fs::create_dir_all(&dest)?;
+        } else if file_type.is_symlink() {
+            std::os::unix::fs::symlink(std::fs::read_link(entry.path())?, &dest)?;
+        } else {
+            std::fs::copy(entry.path(), &dest)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,276 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::sync::Mutex;
+use tokio::sync::Notify;
+
+/// Finished jobs kept around for clients to read their outcome.
+const FINISHED_JOBS_KEPT: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobKind {
+    Install,
+    Uninstall,
+    Upgrade,
+    /// Download bottles without installing them
+    Fetch,
+}
+
+impl std::fmt::Display for JobKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            JobKind::Install => "install",
+            JobKind::Uninstall => "uninstall",
+            JobKind::Upgrade => "upgrade",
+            JobKind::Fetch => "fetch",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl JobKind {
+    /// Whether the job changes the prefix or package database. These run one at a
+    /// time; fetches don't and run alongside each other.
+    pub fn is_mutating(self) -> bool {
+        !matches!(self, JobKind::Fetch)
+    }
+}
+
+/// What a client asks the daemon to do.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobRequest {
+    pub kind: JobKind,
+    #[serde(default)]
+    pub packages: Vec<String>,
+    /// Operate on casks rather than formulae
+    #[serde(default)]
+    pub cask: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl std::fmt::Display for JobState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            JobState::Queued => "queued",
+            JobState::Running => "running",
+            JobState::Succeeded => "succeeded",
+            JobState::Failed => "failed",
+            JobState::Cancelled => "cancelled",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl JobState {
+    pub fn is_finished(self) -> bool {
+        matches!(self, JobState::Succeeded | JobState::Failed | JobState::Cancelled)
+    }
+}
+
+/// A progress message, numbered so clients can ask for what they haven't seen.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobEvent {
+    pub seq: u64,
+    pub at: chrono::DateTime<chrono::Utc>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub id: u64,
+    /// The authenticated client that submitted the job; only it may cancel it
+    pub client: String,
+    pub request: JobRequest,
+    pub state: JobState,
+    pub events: Vec<JobEvent>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub finished_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CancelError {
+    NotFound,
+    /// The job belongs to another client
+    NotOwner,
+    AlreadyFinished(JobState),
+}
+
+#[derive(Default)]
+struct Inner {
+    next_id: u64,
+    jobs: BTreeMap<u64, Job>,
+    queue: VecDeque<u64>,
+    /// Running jobs asked to stop at their next safe point
+    cancelled: HashSet<u64>,
+}
+
+/// Jobs submitted by the daemon's clients, in submission order.
+#[derive(Default)]
+pub struct JobQueue {
+    inner: Mutex<Inner>,
+    /// Signalled when a job is queued
+    queued: Notify,
+    /// Signalled when any job gets an event or changes state
+    changed: Notify,
+}
+
+impl JobQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Queue `request` on behalf of `client` and return its job ID.
+    pub fn submit(&self, client: &str, request: JobRequest) -> u64 {
+        let mut inner = self.lock();
+        inner.next_id += 1;
+        let id = inner.next_id;
+        inner.jobs.insert(id, Job {
+            id,
+            client: client.to_string(),
+            request,
+            state: JobState::Queued,
+            events: Vec::new(),
+            created_at: chrono::Utc::now(),
+            finished_at: None,
+        });
+        inner.queue.push_back(id);
+        drop(inner);
+        self.queued.notify_one();
+        id
+    }
+
+    pub fn get(&self, id: u64) -> Option<Job> {
+        self.lock().jobs.get(&id).cloned()
+    }
+
+    /// Jobs submitted by `client`, oldest first.
+    pub fn jobs_for(&self, client: &str) -> Vec<Job> {
+        self.lock().jobs.values().filter(|job| job.client == client).cloned().collect()
+    }
+
+    /// Jobs waiting to run.
+    pub fn depth(&self) -> usize {
+        self.lock().queue.len()
+    }
+
+    /// Jobs running now.
+    pub fn running(&self) -> usize {
+        self.lock().jobs.values().filter(|job| job.state == JobState::Running).count()
+    }
+
+    /// Cancel one of `client`'s jobs. A queued job is dropped at once; a running one
+    /// stops at its next safe point, between packages.
+    pub fn cancel(&self, client: &str, id: u64) -> Result<JobState, CancelError> {
+        let mut inner = self.lock();
+        let job = inner.jobs.get(&id).ok_or(CancelError::NotFound)?;
+        if job.client != client {
+            return Err(CancelError::NotOwner);
+        }
+        let state = match job.state {
+            JobState::Queued => {
+                inner.queue.retain(|queued| *queued != id);
+                JobState::Cancelled
+            }
+            JobState::Running => {
+                inner.cancelled.insert(id);
+                JobState::Running
+            }
+            finished => return Err(CancelError::AlreadyFinished(finished)),
+        };
+        drop(inner);
+
+        if state == JobState::Cancelled {
+            self.finish(id, JobState::Cancelled, "Cancelled before it started");
+        } else {
+            self.push_event(id, "Cancellation requested");
+        }
+        Ok(state)
+    }
+
+    pub fn is_cancelled(&self, id: u64) -> bool {
+        self.lock().cancelled.contains(&id)
+    }
+
+    /// Wait for the next queued job and mark it running.
+    pub async fn next(&self) -> Job {
+        loop {
+            // Registered before checking, so a submit in between isn't missed
+            let queued = self.queued.notified();
+            if let Some(job) = self.start_next(|_| true) {
+                return job;
+            }
+            queued.await;
+        }
+    }
+
+    /// The next queued job, if it satisfies `accept`, marked running.
+    pub fn start_next(&self, accept: impl Fn(&Job) -> bool) -> Option<Job> {
+        let mut inner = self.lock();
+        let id = *inner.queue.front()?;
+        if !accept(&inner.jobs[&id]) {
+            return None;
+        }
+        inner.queue.pop_front();
+        let job = inner.jobs.get_mut(&id)?;
+        job.state = JobState::Running;
+        let job = job.clone();
+        drop(inner);
+        self.changed.notify_waiters();
+        Some(job)
+    }
+
+    pub fn push_event(&self, id: u64, message: impl Into<String>) {
+        let mut inner = self.lock();
+        if let Some(job) = inner.jobs.get_mut(&id) {
+            let seq = job.events.len() as u64 + 1;
+            job.events.push(JobEvent { seq, at: chrono::Utc::now(), message: message.into() });
+        }
+        drop(inner);
+        self.changed.notify_waiters();
+    }
+
+    /// Record a job's outcome, dropping the oldest finished jobs beyond what is kept.
+    pub fn finish(&self, id: u64, state: JobState, message: impl Into<String>) {
+        self.push_event(id, message);
+        let mut inner = self.lock();
+        inner.cancelled.remove(&id);
+        if let Some(job) = inner.jobs.get_mut(&id) {
+            job.state = state;
+            job.finished_at = Some(chrono::Utc::now());
+        }
+
+        let finished: Vec<u64> = inner.jobs.values().filter(|j| j.state.is_finished()).map(|j| j.id).collect();
+        for old in finished.iter().take(finished.len().saturating_sub(FINISHED_JOBS_KEPT)) {
+            inner.jobs.remove(old);
+        }
+        drop(inner);
+        self.changed.notify_waiters();
+    }
+
+    /// Events of job `id` after `seq`, and its state. Waits for something new when
+    /// there's nothing yet and the job is still going.
+    pub async fn events_after(&self, id: u64, seq: u64) -> Option<(Vec<JobEvent>, JobState)> {
+        loop {
+            let changed = self.changed.notified();
+            let job = self.get(id)?;
+            let events: Vec<JobEvent> = job.events.into_iter().filter(|e| e.seq > seq).collect();
+            if !events.is_empty() || job.state.is_finished() {
+                return Some((events, job.state));
+            }
+            changed.await;
+        }
+    }
+}
@@ -0,0 +1,352 @@
+//! Daemon mode: a long-running nitro that clients drive over an authenticated HTTP
+//! API. Jobs from every client go through one queue, so installs never run over
+//! each other, while downloads that touch nothing can run side by side.
+
+pub mod jobs;
+
+use anyhow::Result;
+use http_body_util::{combinators::BoxBody, BodyExt, Full, StreamBody};
+use hyper::body::{Bytes, Frame, Incoming};
+use hyper::{Method, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::core::NitroError;
+use crate::metrics;
+use jobs::{CancelError, Job, JobKind, JobQueue, JobRequest, JobState};
+
+/// Settings for `nitro daemon`, read from the `[daemon]` section of the config file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DaemonConfig {
+    /// Address the API listens on
+    pub listen: String,
+    /// Client name to bearer token. When empty, a token for client `local` is
+    /// generated and written to `daemon.token` in the data directory.
+    pub tokens: BTreeMap<String, String>,
+    /// Fetch jobs run at once
+    pub max_parallel_fetches: usize,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            listen: "127.0.0.1:7878".to_string(),
+            tokens: BTreeMap::new(),
+            max_parallel_fetches: 4,
+        }
+    }
+}
+
+type Body = BoxBody<Bytes, Infallible>;
+
+/// Wait after the first failed accept, doubled for each failure in a row
+const ACCEPT_BACKOFF: std::time::Duration = std::time::Duration::from_millis(10);
+
+/// The longest wait between accepts that keep failing, e.g. while out of file descriptors
+const MAX_ACCEPT_BACKOFF: std::time::Duration = std::time::Duration::from_secs(1);
+
+struct State {
+    queue: JobQueue,
+    tokens: BTreeMap<String, String>,
+}
+
+/// Run the daemon until interrupted: serve the API and work through the job queue.
+pub async fn serve(config: DaemonConfig) -> Result<()> {
+    let tokens = if config.tokens.is_empty() {
+        let (path, token) = local_token()?;
+        println!("No [daemon] tokens configured; client 'local' authenticates with the token in {}", path.display());
+        BTreeMap::from([("local".to_string(), token)])
+    } else {
+        config.tokens.clone()
+    };
+
+    let state = Arc::new(State { queue: JobQueue::new(), tokens });
+    let listener = tokio::net::TcpListener::bind(&config.listen).await
+        .map_err(|e| NitroError::Other(format!("Could not listen on {}: {}", config.listen, e)))?;
+    println!("nitro daemon listening on http://{}", listener.local_addr()?);
+
+    let server_state = state.clone();
+    tokio::spawn(async move {
+        let mut backoff = ACCEPT_BACKOFF;
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    tracing::warn!("daemon could not accept a connection: {}; retrying in {:?}", e, backoff);
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_ACCEPT_BACKOFF);
+                    continue;
+                }
+            };
+            backoff = ACCEPT_BACKOFF;
+            let state = server_state.clone();
+            tokio::spawn(async move {
+                let service = hyper::service::service_fn(move |request| handle(state.clone(), request));
+                let io = hyper_util::rt::TokioIo::new(stream);
+                if let Err(e) = hyper::server::conn::http1::Builder::new().serve_connection(io, service).await {
+                    tracing::debug!("daemon connection error: {}", e);
+                }
+            });
+        }
+    });
+
+    run_worker(&state.queue, config.max_parallel_fetches.max(1)).await
+}
+
+/// The token clients use when none are configured, created on first use and
+/// readable only by the current user.
+fn local_token() -> Result<(PathBuf, String)> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let dirs = directories::ProjectDirs::from("com", "nitro", "nitro")
+        .ok_or_else(|| NitroError::Other("Could not determine data directory".into()))?;
+    let path = dirs.data_dir().join("daemon.token");
+    if let Ok(token) = std::fs::read_to_string(&path) {
+        if !token.trim().is_empty() {
+            return Ok((path, token.trim().to_string()));
+        }
+        // An empty one, left by a crash, is replaced
+        std::fs::remove_file(&path)?;
+    }
+
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes).map_err(|e| NitroError::Other(format!("Could not generate a daemon token: {}", e)))?;
+    let token = hex::encode(bytes);
+    std::fs::create_dir_all(dirs.data_dir())?;
+    // Created private, so there's no moment anyone else can read it
+    let mut file = std::fs::OpenOptions::new().write(true).create_new(true).mode(0o600).open(&path)?;
+    file.write_all(token.as_bytes())?;
+    Ok((path, token))
+}
+
+/// The client a request's `Authorization: Bearer` token belongs to.
+pub fn authenticate(tokens: &BTreeMap<String, String>, authorization: Option<&str>) -> Option<String> {
+    let presented = authorization?.strip_prefix("Bearer ")?.trim();
+    tokens.iter()
+        .find(|(_, token)| constant_time_eq(token.as_bytes(), presented.as_bytes()))
+        .map(|(client, _)| client.clone())
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+async fn handle(state: Arc<State>, request: Request<Incoming>) -> Result<Response<Body>, Infallible> {
+    let path: Vec<&str> = request.uri().path().trim_matches('/').split('/').collect();
+    if request.method() == Method::GET && path == ["health"] {
+        return Ok(json(StatusCode::OK, &serde_json::json!({ "status": "ok" })));
+    }
+    // Scraped by Prometheus, which has no token; nothing in it names a package or client
+    if request.method() == Method::GET && path == ["metrics"] {
+        let body = metrics::global().render(state.queue.depth(), state.queue.running());
+        return Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(hyper::header::CONTENT_TYPE, "text/plain; version=0.0.4")
+            .body(Full::new(Bytes::from(body)).boxed())
+            .unwrap_or_else(|_| error(StatusCode::INTERNAL_SERVER_ERROR, "could not build response")));
+    }
+
+    let authorization = request.headers().get(hyper::header::AUTHORIZATION).and_then(|v| v.to_str().ok());
+    let Some(client) = authenticate(&state.tokens, authorization) else {
+        return Ok(error(StatusCode::UNAUTHORIZED, "missing or invalid bearer token"));
+    };
+
+    let method = request.method().clone();
+    let id = path.get(1).and_then(|id| id.parse::<u64>().ok());
+    let response = match (method, path.as_slice()) {
+        (Method::POST, ["jobs"]) => {
+            let body = match request.into_body().collect().await {
+                Ok(body) => body.to_bytes(),
+                Err(e) => return Ok(error(StatusCode::BAD_REQUEST, &e.to_string())),
+            };
+            match serde_json::from_slice::<JobRequest>(&body) {
+                Ok(job) => {
+                    let id = state.queue.submit(&client, job);
+                    json(StatusCode::ACCEPTED, &serde_json::json!({ "id": id }))
+                }
+                Err(e) => error(StatusCode::BAD_REQUEST, &format!("invalid job: {}", e)),
+            }
+        }
+        (Method::GET, ["jobs"]) => json(StatusCode::OK, &state.queue.jobs_for(&client)),
+        (Method::GET, ["jobs", _]) => match id.and_then(|id| own_job(&state.queue, &client, id)) {
+            Some(job) => json(StatusCode::OK, &job),
+            None => error(StatusCode::NOT_FOUND, "no such job"),
+        },
+        (Method::GET, ["jobs", _, "events"]) => match id.filter(|id| own_job(&state.queue, &client, *id).is_some()) {
+            Some(id) => stream_events(state.clone(), id),
+            None => error(StatusCode::NOT_FOUND, "no such job"),
+        },
+        (Method::DELETE, ["jobs", _]) => match id.map(|id| state.queue.cancel(&client, id)) {
+            Some(Ok(job_state)) => json(StatusCode::OK, &serde_json::json!({ "state": job_state })),
+            Some(Err(CancelError::AlreadyFinished(job_state))) => {
+                error(StatusCode::CONFLICT, &format!("job already {}", job_state))
+            }
+            Some(Err(CancelError::NotOwner)) => error(StatusCode::FORBIDDEN, "job belongs to another client"),
+            Some(Err(CancelError::NotFound)) | None => error(StatusCode::NOT_FOUND, "no such job"),
+        },
+        _ => error(StatusCode::NOT_FOUND, "unknown endpoint"),
+    };
+    Ok(response)
+}
+
+fn own_job(queue: &JobQueue, client: &str, id: u64) -> Option<Job> {
+    queue.get(id).filter(|job| job.client == client)
+}
+
+/// Progress of job `id` as newline-delimited JSON events, ending once the job finishes.
+fn stream_events(state: Arc<State>, id: u64) -> Response<Body> {
+    let events = futures::stream::unfold(Some(0), move |seq| {
+        let state = state.clone();
+        async move {
+            let seq = seq?;
+            let (events, job_state) = state.queue.events_after(id, seq).await?;
+            let mut chunk = String::new();
+            for event in &events {
+                chunk.push_str(&serde_json::to_string(event).unwrap_or_default());
+                chunk.push('\n');
+            }
+            let next = if job_state.is_finished() {
+                chunk.push_str(&serde_json::json!({ "state": job_state }).to_string());
+                chunk.push('\n');
+                None
+            } else {
+                Some(events.last().map(|e| e.seq).unwrap_or(seq))
+            };
+            Some((Ok::<_, Infallible>(Frame::data(Bytes::from(chunk))), next))
+        }
+    });
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(hyper::header::CONTENT_TYPE, "application/x-ndjson")
+        .body(StreamBody::new(events).boxed())
+        .unwrap_or_else(|_| error(StatusCode::INTERNAL_SERVER_ERROR, "could not build response"))
+}
+
+fn json(status: StatusCode, value: &impl Serialize) -> Response<Body> {
+    let body = serde_json::to_vec(value).unwrap_or_default();
+    Response::builder()
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(body)).boxed())
+        .unwrap_or_else(|_| Response::new(Full::new(Bytes::new()).boxed()))
+}
+
+fn error(status: StatusCode, message: &str) -> Response<Body> {
+    json(status, &serde_json::json!({ "error": message }))
+}
+
+/// Run queued jobs until interrupted. Jobs that change the prefix run one at a time,
+/// in order; a fetch at the head of the queue is batched with the fetches behind it.
+async fn run_worker(queue: &JobQueue, max_parallel_fetches: usize) -> Result<()> {
+    use crate::core::package::PackageManager;
+
+    let package_manager = PackageManager::new().await?;
+    loop {
+        let job = tokio::select! {
+            job = queue.next() => job,
+            _ = crate::core::interrupt::wait() => return Ok(()),
+        };
+
+        if job.request.kind.is_mutating() {
+            run_job(queue, &package_manager, &job).await;
+            continue;
+        }
+
+        let mut batch = vec![job];
+        while batch.len() < max_parallel_fetches {
+            match queue.start_next(|next| !next.request.kind.is_mutating()) {
+                Some(job) => batch.push(job),
+                None => break,
+            }
+        }
+        futures::future::join_all(batch.iter().map(|job| run_job(queue, &package_manager, job))).await;
+    }
+}
+
+async fn run_job(queue: &JobQueue, package_manager: &crate::core::package::PackageManager, job: &Job) {
+    use crate::cli::commands::install::InstallArgs;
+    use crate::cli::commands::uninstall::UninstallArgs;
+
+    let request = &job.request;
+    queue.push_event(job.id, format!("Started {} of {}", request.kind, request.packages.join(", ")));
+
+    let mut failed = Vec::new();
+    let packages = match request.kind {
+        // No names means everything outdated
+        JobKind::Upgrade if request.packages.is_empty() => match package_manager.check_updates(&[], None).await {
+            Ok(updates) => updates.into_iter().map(|(name, _, _)| name).collect(),
+            Err(e) => {
+                queue.finish(job.id, JobState::Failed, format!("Could not check for updates: {}", e));
+                return;
+            }
+        },
+        _ => request.packages.clone(),
+    };
+
+    for name in &packages {
+        if queue.is_cancelled(job.id) {
+            queue.finish(job.id, JobState::Cancelled, format!("Cancelled before {}", name));
+            return;
+        }
+        queue.push_event(job.id, format!("Running {} {}", request.kind, name));
+
+        let result = match request.kind {
+            JobKind::Install if request.cask => package_manager.install_cask(name, &InstallArgs {
+                packages: vec![name.clone()],
+                cask: true,
+                ..Default::default()
+            }).await,
+            JobKind::Install => package_manager.install(name, &InstallArgs {
+                packages: vec![name.clone()],
+                ..Default::default()
+            }).await,
+            JobKind::Uninstall => package_manager.uninstall(name, &UninstallArgs {
+                packages: vec![name.clone()],
+                force: false,
+                all_versions: false,
+                cask: request.cask,
+            }).await,
+            JobKind::Upgrade => package_manager.update_packages(std::slice::from_ref(name), None, false, false).await,
+            JobKind::Fetch => fetch(package_manager, name).await,
+        };
+
+        match result {
+            Ok(()) => {
+                if matches!(request.kind, JobKind::Install | JobKind::Upgrade) {
+                    metrics::global().record_install();
+                }
+                queue.push_event(job.id, format!("Finished {}", name));
+            }
+            Err(e) => {
+                metrics::global().record_failure();
+                queue.push_event(job.id, format!("Failed {}: {}", name, e));
+                failed.push(name.clone());
+            }
+        }
+    }
+
+    if failed.is_empty() {
+        queue.finish(job.id, JobState::Succeeded, "Done");
+    } else {
+        queue.finish(job.id, JobState::Failed, format!("Failed: {}", failed.join(", ")));
+    }
+}
+
+/// Download a formula's bottle for this machine into nitro's bottle cache.
+async fn fetch(package_manager: &crate::core::package::PackageManager, name: &str) -> Result<()> {
+    use crate::core::installer::Installer;
+    use crate::download::Downloader;
+
+    let formula = package_manager.formula_manager().get_formula(name).await?;
+    let dir = crate::core::installer::bottle_cache_dir()?;
+    Installer::new(Downloader::shared()?)?
+        .fetch_bottle(&formula, &Installer::platform_tag(), &dir)
+        .await?;
+    Ok(())
+}
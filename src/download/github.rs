@@ -0,0 +1,216 @@
+use anyhow::Result;
+use reqwest::header::{ACCEPT, AUTHORIZATION, RETRY_AFTER};
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::Downloader;
+use crate::core::NitroError;
+
+const DEFAULT_API_URL: &str = "https://api.github.com";
+
+/// Attempts made for a request that keeps getting rate limited
+const MAX_ATTEMPTS: u32 = 4;
+
+/// Longest we're willing to sleep waiting for a rate limit window to reset
+const MAX_WAIT: Duration = Duration::from_secs(60);
+
+/// GitHub access, read from the `[github]` section of the config file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GitHubConfig {
+    /// Personal access token; raises the API limit from 60 to 5000 requests an hour
+    pub token: Option<String>,
+    /// API base URL, for GitHub Enterprise
+    pub api_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Repository {
+    pub full_name: String,
+    pub description: Option<String>,
+    pub default_branch: String,
+    #[serde(default)]
+    pub stargazers_count: u64,
+    #[serde(default)]
+    pub archived: bool,
+    pub pushed_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Release {
+    pub tag_name: String,
+    pub name: Option<String>,
+    pub html_url: String,
+    pub published_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(default)]
+    pub prerelease: bool,
+    #[serde(default)]
+    pub assets: Vec<ReleaseAsset>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReleaseAsset {
+    pub name: String,
+    pub size: u64,
+    pub browser_download_url: String,
+}
+
+/// Rate limit state reported by the last response.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RateLimit {
+    pub remaining: Option<u64>,
+    /// Unix time the current window ends
+    pub reset: Option<i64>,
+}
+
+/// The one place Nitro talks to the GitHub API. Requests carry the configured token,
+/// and the client tracks `X-RateLimit-*` headers so it waits out (or reports) an
+/// exhausted limit instead of hammering the API.
+#[derive(Clone)]
+pub struct GitHubClient {
+    downloader: Downloader,
+    api_url: String,
+    token: Option<String>,
+    rate_limit: Arc<Mutex<RateLimit>>,
+}
+
+impl GitHubClient {
+    pub fn new() -> Result<Self> {
+        let config = crate::core::config::Config::load()?.github;
+        Ok(Self::with_config(Downloader::shared()?, config))
+    }
+
+    /// The token comes from the config file, falling back to the environment variables
+    /// other tools use for the same purpose.
+    pub fn with_config(downloader: Downloader, config: GitHubConfig) -> Self {
+        let token = config.token
+            .or_else(|| std::env::var("NITRO_GITHUB_TOKEN").ok())
+            .or_else(|| std::env::var("HOMEBREW_GITHUB_API_TOKEN").ok())
+            .or_else(|| std::env::var("GITHUB_TOKEN").ok())
+            .filter(|t| !t.is_empty());
+
+        Self {
+            downloader,
+            api_url: config.api_url.unwrap_or_else(|| DEFAULT_API_URL.to_string()).trim_end_matches('/').to_string(),
+            token,
+            rate_limit: Arc::new(Mutex::new(RateLimit::default())),
+        }
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.token.is_some()
+    }
+
+    pub fn rate_limit(&self) -> RateLimit {
+        *self.rate_limit.lock().unwrap()
+    }
+
+    pub async fn repository(&self, owner: &str, repo: &str) -> Result<Repository> {
+        self.get_json(&format!("/repos/{}/{}", owner, repo)).await
+    }
+
+    pub async fn latest_release(&self, owner: &str, repo: &str) -> Result<Release> {
+        self.get_json(&format!("/repos/{}/{}/releases/latest", owner, repo)).await
+    }
+
+    pub async fn releases(&self, owner: &str, repo: &str) -> Result<Vec<Release>> {
+        self.get_json(&format!("/repos/{}/{}/releases", owner, repo)).await
+    }
+
+    /// GET an API path (e.g. `/repos/Homebrew/brew`) and decode the JSON response.
+    pub async fn get_json<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let url = format!("{}{}", self.api_url, path);
+
+        for attempt in 0..MAX_ATTEMPTS {
+            self.wait_for_window().await?;
+
+            let mut request = self.downloader.metadata_request(&url)
+                .header(ACCEPT, "application/vnd.github+json")
+                .header("X-GitHub-Api-Version", "2022-11-28");
+            if let Some(token) = &self.token {
+                request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+            }
+
+            let response = request.send().await?;
+            self.record_rate_limit(response.headers());
+
+            let status = response.status();
+            if status.is_success() {
+                return Ok(response.json().await?);
+            }
+
+            let retry_after = response.headers().get(RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<u64>().ok())
+                .map(Duration::from_secs);
+            let limited = status == StatusCode::TOO_MANY_REQUESTS
+                || (status == StatusCode::FORBIDDEN && (retry_after.is_some() || self.rate_limit().remaining == Some(0)));
+
+            if limited && attempt + 1 < MAX_ATTEMPTS {
+                // Secondary limits send Retry-After; otherwise back off exponentially
+                let delay = retry_after.unwrap_or_else(|| Duration::from_secs(2u64.pow(attempt)));
+                if delay > MAX_WAIT {
+                    return Err(self.limit_error());
+                }
+                tokio::time::sleep(delay).await;
+                continue;
+            }
+            if limited {
+                return Err(self.limit_error());
+            }
+
+            let message = response.json::<serde_json::Value>().await.ok()
+                .and_then(|v| v.get("message").and_then(|m| m.as_str()).map(str::to_string))
+                .unwrap_or_default();
+            return Err(NitroError::DownloadFailed(format!("GitHub API {} for {}: {}", status, path, message)).into());
+        }
+
+        Err(self.limit_error())
+    }
+
+    /// When the last response said the limit is used up, sleep until it resets
+    /// if that's soon, or fail right away if it isn't.
+    async fn wait_for_window(&self) -> Result<()> {
+        let RateLimit { remaining, reset } = self.rate_limit();
+        let (Some(0), Some(reset)) = (remaining, reset) else {
+            return Ok(());
+        };
+
+        let wait = reset - chrono::Utc::now().timestamp();
+        if wait <= 0 {
+            return Ok(());
+        }
+        if wait as u64 > MAX_WAIT.as_secs() {
+            return Err(self.limit_error());
+        }
+        tokio::time::sleep(Duration::from_secs(wait as u64)).await;
+        Ok(())
+    }
+
+    fn record_rate_limit(&self, headers: &reqwest::header::HeaderMap) {
+        let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).and_then(|v| v.parse::<i64>().ok());
+        let mut rate_limit = self.rate_limit.lock().unwrap();
+        if let Some(remaining) = header("x-ratelimit-remaining") {
+            rate_limit.remaining = Some(remaining.max(0) as u64);
+        }
+        if let Some(reset) = header("x-ratelimit-reset") {
+            rate_limit.reset = Some(reset);
+        }
+    }
+
+    fn limit_error(&self) -> anyhow::Error {
+        let reset = self.rate_limit().reset
+            .and_then(|r| chrono::DateTime::from_timestamp(r, 0))
+            .map(|r| format!(" until {}", r.with_timezone(&chrono::Local).format("%H:%M:%S")))
+            .unwrap_or_default();
+        let hint = if self.token.is_none() {
+            ". Set a token with `github.token` in the config or GITHUB_TOKEN for a higher limit"
+        } else {
+            ""
+        };
+        NitroError::DownloadFailed(format!("GitHub API rate limit exceeded{}{}", reset, hint)).into()
+    }
+}
@@ -0,0 +1,120 @@
+use anyhow::Result;
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use super::Downloader;
+use crate::core::NitroError;
+
+/// Validators remembered for a metadata URL so the next fetch can be conditional.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Validators {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    fetched_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FetchedMetadata {
+    /// Local copy of the document
+    pub path: PathBuf,
+    /// True when the server answered 304 (or was unreachable) and the cached copy was reused
+    pub from_cache: bool,
+    /// Why the server couldn't be reached, when the cached copy was reused because of it
+    pub refresh_error: Option<String>,
+}
+
+/// Fetches recurring metadata documents (formula API, manifests) with
+/// `If-None-Match`/`If-Modified-Since`, treating 304 as a cache hit.
+pub struct MetadataFetcher {
+    downloader: Downloader,
+    dir: PathBuf,
+}
+
+impl MetadataFetcher {
+    pub fn new(downloader: Downloader) -> Result<Self> {
+        let config_dir = directories::ProjectDirs::from("com", "nitro", "nitro")
+            .ok_or_else(|| NitroError::Other("Could not determine config directory".into()))?;
+
+        Self::with_dir(downloader, config_dir.cache_dir().join("metadata"))
+    }
+
+    pub fn with_dir(downloader: Downloader, dir: PathBuf) -> Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { downloader, dir })
+    }
+
+    pub async fn fetch(&self, url: &str) -> Result<FetchedMetadata> {
+        let (body_path, meta_path) = self.paths(url);
+        let validators = std::fs::read(&meta_path).ok()
+            .and_then(|data| serde_json::from_slice::<Validators>(&data).ok())
+            .filter(|_| body_path.exists())
+            .unwrap_or_default();
+
+        let mut request = self.downloader.metadata_request(url);
+        if let Some(etag) = &validators.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+
+        let response = match request.send().await {
+            Ok(response) => response,
+            Err(e) if body_path.exists() => {
+                eprintln!("Warning: could not refresh {} ({}), using cached copy", url, e);
+                return Ok(FetchedMetadata { path: body_path, from_cache: true, refresh_error: Some(e.to_string()) });
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        if response.status() == StatusCode::NOT_MODIFIED && body_path.exists() {
+            let validators = Validators {
+                fetched_at: Some(chrono::Utc::now()),
+                ..validators
+            };
+            std::fs::write(&meta_path, serde_json::to_vec_pretty(&validators)?)?;
+            return Ok(FetchedMetadata { path: body_path, from_cache: true, refresh_error: None });
+        }
+
+        if !response.status().is_success() {
+            return Err(NitroError::DownloadFailed(
+                format!("HTTP {}: {}", response.status(), url)
+            ).into());
+        }
+
+        let header = |name| response.headers().get(name)
+            .and_then(|v: &reqwest::header::HeaderValue| v.to_str().ok())
+            .map(str::to_string);
+        let validators = Validators {
+            url: url.to_string(),
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+            fetched_at: Some(chrono::Utc::now()),
+        };
+
+        // Write to a temporary file first so a failed transfer never replaces a good copy
+        let body = response.bytes().await?;
+        let temp_path = body_path.with_extension("partial");
+        std::fs::write(&temp_path, &body)?;
+        std::fs::rename(&temp_path, &body_path)?;
+        std::fs::write(&meta_path, serde_json::to_vec_pretty(&validators)?)?;
+
+        Ok(FetchedMetadata { path: body_path, from_cache: false, refresh_error: None })
+    }
+
+    /// The cached copy of `url`, if it has been fetched before.
+    pub fn cached(&self, url: &str) -> Option<PathBuf> {
+        let (body_path, _) = self.paths(url);
+        body_path.exists().then_some(body_path)
+    }
+
+    fn paths(&self, url: &str) -> (PathBuf, PathBuf) {
+        use sha2::{Digest, Sha256};
+
+        let key = hex::encode(&Sha256::digest(url.as_bytes())[..16]);
+        (self.dir.join(format!("{}.body", key)), self.dir.join(format!("{}.json", key)))
+    }
+}
@@ -2,46 +2,190 @@ use anyhow::Result;
 use futures::StreamExt;
 use indicatif::{ProgressBar, ProgressStyle};
 use reqwest::Client;
+use serde::{Deserialize, Serialize};
 use std::path::Path;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
 
+use crate::core::cancel::CancellationToken;
 use crate::core::NitroError;
 
+pub mod github;
+pub mod metadata;
+pub mod oci;
+pub mod s3;
+pub mod scheme;
+
+use scheme::{FileHandler, SchemeHandler};
+
+/// HTTP client tuning, read from the `[download]` section of the config file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DownloadConfig {
+    /// Time allowed to establish a connection
+    pub connect_timeout_secs: u64,
+    /// Maximum time between two reads of a response body; large downloads are never cut off
+    /// as long as data keeps arriving
+    pub read_timeout_secs: u64,
+    /// Total time allowed for small metadata requests (JSON API, manifests)
+    pub metadata_timeout_secs: u64,
+    /// Idle connections kept open per host for reuse
+    pub pool_max_idle_per_host: usize,
+    /// How long idle pooled connections are kept
+    pub pool_idle_timeout_secs: u64,
+    /// Negotiate HTTP/2 when the server supports it
+    pub http2: bool,
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout_secs: 15,
+            read_timeout_secs: 60,
+            metadata_timeout_secs: 30,
+            pool_max_idle_per_host: 16,
+            pool_idle_timeout_secs: 90,
+            http2: true,
+        }
+    }
+}
+
 pub struct Downloader {
     client: Client,
+    config: DownloadConfig,
+    /// Handlers for non-HTTP URL schemes, consulted by `download_file`
+    handlers: Arc<Vec<Arc<dyn SchemeHandler>>>,
+    /// Stops downloads in progress; by default only Ctrl-C fires it
+    cancel: CancellationToken,
 }
 
 impl Downloader {
     pub fn new() -> Result<Self> {
-        let client = Client::builder()
-            .user_agent("Nitro Package Manager/0.1.0")
-            .timeout(std::time::Duration::from_secs(300))
-            .redirect(reqwest::redirect::Policy::limited(10))
-            .build()?;
+        let config = crate::core::config::Config::load()?.download;
+        Self::with_config(config)
+    }
+
+    pub fn with_config(config: DownloadConfig) -> Result<Self> {
+        let mut builder = Client::builder()
+            .user_agent(concat!("Nitro Package Manager/", env!("CARGO_PKG_VERSION")))
+            .connect_timeout(Duration::from_secs(config.connect_timeout_secs))
+            .read_timeout(Duration::from_secs(config.read_timeout_secs))
+            .pool_max_idle_per_host(config.pool_max_idle_per_host)
+            .pool_idle_timeout(Duration::from_secs(config.pool_idle_timeout_secs))
+            .tcp_keepalive(Duration::from_secs(30))
+            .redirect(reqwest::redirect::Policy::limited(10));
+
+        if !config.http2 {
+            builder = builder.http1_only();
+        }
+
+        let handlers: Vec<Arc<dyn SchemeHandler>> = vec![
+            Arc::new(FileHandler),
+            Arc::new(s3::S3Handler),
+        ];
 
-        Ok(Self { client })
+        Ok(Self {
+            client: builder.build()?,
+            config,
+            handlers: Arc::new(handlers),
+            cancel: CancellationToken::new(),
+        })
+    }
+
+    /// This downloader, sharing its connections, with downloads stopped by `cancel`.
+    pub fn with_cancellation(&self, cancel: CancellationToken) -> Self {
+        Self { cancel, ..self.clone() }
+    }
+
+    /// Register an additional URL scheme handler, replacing any existing handler for the same scheme.
+    pub fn with_scheme_handler(mut self, handler: Arc<dyn SchemeHandler>) -> Self {
+        let mut handlers: Vec<_> = self.handlers.iter()
+            .filter(|h| h.scheme() != handler.scheme())
+            .cloned()
+            .collect();
+        handlers.push(handler);
+        self.handlers = Arc::new(handlers);
+        self
+    }
+
+    fn handler_for(&self, scheme: &str) -> Option<&Arc<dyn SchemeHandler>> {
+        self.handlers.iter().find(|h| h.scheme() == scheme)
+    }
+
+    /// Process-wide downloader. Every caller gets a handle to the same connection pool,
+    /// so a session with many downloads reuses connections.
+    pub fn shared() -> Result<Self> {
+        static SHARED: OnceLock<Downloader> = OnceLock::new();
+
+        if let Some(downloader) = SHARED.get() {
+            return Ok(downloader.clone());
+        }
+        let downloader = Self::new()?;
+        Ok(SHARED.get_or_init(|| downloader).clone())
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// GET request for small metadata documents, bounded by the metadata timeout.
+    pub fn metadata_request(&self, url: &str) -> reqwest::RequestBuilder {
+        self.client
+            .get(url)
+            .timeout(Duration::from_secs(self.config.metadata_timeout_secs))
     }
 
     pub async fn download_file(&self, url: &str, dest: &Path) -> Result<()> {
-        println!("Downloading: {}", url);
-        let response = self.client.get(url).send().await?;
+        match scheme::scheme_of(url).as_deref() {
+            // Registry blobs (bottles on ghcr.io) need a token first
+            Some("http") | Some("https") => match oci::BlobRef::parse(url) {
+                Some(blob) => oci::OciClient::new(self.clone()).download_blob(&blob, dest).await,
+                None => self.download_request(self.client.get(url), url, dest).await,
+            },
+            Some(other) => match self.handler_for(other) {
+                Some(handler) => {
+                    if !crate::ui::is_quiet() {
+                        println!("Downloading: {}", url);
+                    }
+                    handler.fetch(self, url, dest).await
+                }
+                None => Err(NitroError::DownloadFailed(
+                    format!("Unsupported URL scheme '{}': {}", other, url)
+                ).into()),
+            },
+            None => Err(NitroError::DownloadFailed(format!("Invalid URL: {}", url)).into()),
+        }
+    }
+
+    /// Stream a prepared HTTP request into `dest` with progress reporting. Scheme handlers
+    /// that translate to HTTP (S3) use this after adding their own headers.
+    pub(crate) async fn download_request(&self, request: reqwest::RequestBuilder, url: &str, dest: &Path) -> Result<()> {
+        if !crate::ui::is_quiet() {
+            println!("Downloading: {}", url);
+        }
+        let response = request.send().await?;
         
         if !response.status().is_success() {
-            return Err(NitroError::DownloadFailed(
-                format!("HTTP {}: {}", response.status(), url)
-            ).into());
+            let status = response.status();
+            let body = response.bytes().await.unwrap_or_default();
+            return Err(match error_page_message(&body) {
+                Some(message) => NitroError::DownloadFailed(format!("HTTP {}: {} ({})", status, url, message)),
+                None => NitroError::DownloadFailed(format!("HTTP {}: {}", status, url)),
+            }.into());
         }
-        
-        // Check content type - fail if it's HTML (likely an error page)
-        if let Some(content_type) = response.headers().get("content-type") {
-            if let Ok(ct) = content_type.to_str() {
-                if ct.contains("text/html") {
-                    return Err(NitroError::DownloadFailed(
-                        format!("Server returned HTML instead of archive. URL may be incorrect or require authentication: {}", url)
-                    ).into());
-                }
-            }
+
+        // A 200 carrying an HTML or JSON document instead of an archive is an error page
+        // (login walls, expired signed URLs, API errors); surface what the server said
+        let content_type = response.headers().get("content-type")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let expects_json = url.ends_with(".json");
+        if content_type.contains("text/html") || (content_type.contains("application/json") && !expects_json) {
+            let body = response.bytes().await.unwrap_or_default();
+            return Err(Self::error_page(url, &body).into());
         }
 
         let total_size = response.content_length().unwrap_or(0);
@@ -50,7 +194,7 @@ impl Downloader {
             let pb = ProgressBar::new(total_size);
             pb.set_style(
                 ProgressStyle::default_bar()
-                    .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")?
+                    .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})")?
                     .progress_chars("#>-"),
             );
             pb
@@ -58,7 +202,7 @@ impl Downloader {
             let pb = ProgressBar::new_spinner();
             pb.set_style(
                 ProgressStyle::default_spinner()
-                    .template("{spinner:.green} [{elapsed_precise}] {bytes} downloaded")?
+                    .template("{spinner:.green} [{elapsed_precise}] {bytes} downloaded ({bytes_per_sec})")?
             );
             pb
         };
@@ -72,11 +216,33 @@ impl Downloader {
         let mut downloaded: u64 = 0;
         let mut stream = response.bytes_stream();
 
-        while let Some(chunk) = stream.next().await {
+        loop {
+            let chunk = tokio::select! {
+                chunk = stream.next() => chunk,
+                _ = self.cancel.cancelled() => {
+                    // Don't leave a truncated file behind for anything to pick up later
+                    pb.finish_and_clear();
+                    drop(file);
+                    let _ = tokio::fs::remove_file(dest).await;
+                    return Err(self.cancel.error().into());
+                }
+            };
+            let Some(chunk) = chunk else {
+                break;
+            };
             let chunk = chunk?;
+
+            // Servers that mislabel error pages as octet-stream are caught by sniffing the body
+            if downloaded == 0 && !expects_json && looks_like_markup(&chunk) {
+                pb.finish_and_clear();
+                drop(file);
+                let _ = tokio::fs::remove_file(dest).await;
+                return Err(Self::error_page(url, &chunk).into());
+            }
             file.write_all(&chunk).await?;
             
             downloaded += chunk.len() as u64;
+            crate::metrics::global().record_download_bytes(chunk.len() as u64);
             if total_size > 0 {
                 pb.set_position(std::cmp::min(downloaded, total_size));
             } else {
@@ -84,11 +250,44 @@ impl Downloader {
             }
         }
 
+        // tokio writes in the background; make sure the data is on disk before callers read it
+        file.flush().await?;
         pb.finish_with_message("Download complete");
+        crate::metrics::global().record_network_download();
         Ok(())
     }
 
+    /// Download `url`, falling back to each mirror in turn when it fails.
+    /// Interrupts are never retried against a mirror.
+    pub async fn download_with_mirrors(&self, url: &str, mirrors: &[&str], dest: &Path) -> Result<()> {
+        let mut result = self.download_file(url, dest).await;
+
+        for mirror in mirrors {
+            match &result {
+                Ok(()) => break,
+                Err(e) if matches!(e.downcast_ref::<NitroError>(), Some(NitroError::Interrupted)) => break,
+                Err(e) => {
+                    eprintln!("Warning: {} (trying mirror {})", e, mirror);
+                    result = self.download_file(mirror, dest).await;
+                }
+            }
+        }
+
+        result
+    }
+
+    fn error_page(url: &str, body: &[u8]) -> NitroError {
+        let message = error_page_message(body)
+            .unwrap_or_else(|| "URL may be incorrect or require authentication".to_string());
+        NitroError::DownloadFailed(format!("Server returned an error page instead of an archive for {}: {}", url, message))
+    }
+
     pub async fn download_with_resume(&self, url: &str, dest: &Path) -> Result<()> {
+        // Range requests only make sense over HTTP; other schemes fetch in one go
+        if !matches!(scheme::scheme_of(url).as_deref(), Some("http") | Some("https")) {
+            return self.download_file(url, dest).await;
+        }
+
         let mut downloaded = 0;
         
         // Check if file exists and get its size
@@ -120,7 +319,7 @@ impl Downloader {
             content_range
                 .to_str()
                 .ok()
-                .and_then(|s| s.split('/').last())
+                .and_then(|s| s.split('/').next_back())
                 .and_then(|s| s.parse::<u64>().ok())
                 .unwrap_or(0)
         } else {
@@ -130,7 +329,7 @@ impl Downloader {
         let pb = ProgressBar::new(total_size);
         pb.set_style(
             ProgressStyle::default_bar()
-                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")?
+                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})")?
                 .progress_chars("#>-"),
         );
         pb.set_position(downloaded);
@@ -146,15 +345,31 @@ impl Downloader {
         };
 
         let mut stream = response.bytes_stream();
-        while let Some(chunk) = stream.next().await {
+        loop {
+            let chunk = tokio::select! {
+                chunk = stream.next() => chunk,
+                _ = self.cancel.cancelled() => {
+                    // Keep the partial file: this download is resumable
+                    pb.finish_and_clear();
+                    file.flush().await?;
+                    return Err(self.cancel.error().into());
+                }
+            };
+            let Some(chunk) = chunk else {
+                break;
+            };
             let chunk = chunk?;
             file.write_all(&chunk).await?;
             
             downloaded += chunk.len() as u64;
+            crate::metrics::global().record_download_bytes(chunk.len() as u64);
             pb.set_position(downloaded);
         }
 
+        // tokio writes in the background; make sure the data is on disk before callers read it
+        file.flush().await?;
         pb.finish_with_message("Download complete");
+        crate::metrics::global().record_network_download();
         Ok(())
     }
 
@@ -187,6 +402,55 @@ impl Clone for Downloader {
     fn clone(&self) -> Self {
         Self {
             client: self.client.clone(),
+            config: self.config.clone(),
+            handlers: Arc::clone(&self.handlers),
+            cancel: self.cancel.clone(),
         }
     }
-}
\ No newline at end of file
+}
+
+/// The `Content-Length` a response declares. reqwest's own `content_length` is the
+/// body's length, which a HEAD response doesn't have.
+pub fn content_length(response: &reqwest::Response) -> Option<u64> {
+    response.headers().get(reqwest::header::CONTENT_LENGTH)?.to_str().ok()?.parse().ok()
+}
+
+/// True when a response body starts like an HTML or XML document rather than binary data.
+fn looks_like_markup(body: &[u8]) -> bool {
+    let head = String::from_utf8_lossy(&body[..body.len().min(256)]).trim_start().to_ascii_lowercase();
+    head.starts_with("<!doctype html") || head.starts_with("<html") || head.starts_with("<?xml")
+}
+
+/// Best-effort human-readable message from an HTML, XML or JSON error body.
+pub fn error_page_message(body: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(&body[..body.len().min(64 * 1024)]);
+    let trimmed = text.trim();
+
+    if trimmed.starts_with('{') {
+        let value: serde_json::Value = serde_json::from_str(trimmed).ok()?;
+        let message = ["message", "error", "errors", "detail"].iter()
+            .find_map(|key| value.get(key))?;
+        return Some(match message {
+            serde_json::Value::String(s) => s.clone(),
+            // e.g. registry errors: {"errors":[{"code":..,"message":..}]}
+            serde_json::Value::Array(items) => items.iter()
+                .filter_map(|item| item.get("message").and_then(|m| m.as_str()).or(item.as_str()))
+                .collect::<Vec<_>>()
+                .join("; "),
+            other => other.to_string(),
+        }).filter(|m| !m.is_empty());
+    }
+
+    // S3-style XML errors carry <Message>, HTML pages a <title>
+    for tag in ["Message", "title", "h1"] {
+        let re = regex::Regex::new(&format!(r"(?is)<{0}[^>]*>(.*?)</{0}>", tag)).unwrap();
+        if let Some(cap) = re.captures(trimmed) {
+            let message = cap[1].split_whitespace().collect::<Vec<_>>().join(" ");
+            if !message.is_empty() {
+                return Some(message);
+            }
+        }
+    }
+
+    None
+}
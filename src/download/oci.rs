@@ -0,0 +1,191 @@
+//! OCI registry access for bottles. Homebrew publishes bottles to ghcr.io as OCI
+//! images, and even public images need a bearer token: an anonymous one is exchanged
+//! at the registry's `/token` endpoint for each repository, then sent with manifest and
+//! blob requests. Blob requests redirect to a CDN, which the client follows without
+//! the token.
+
+use anyhow::Result;
+use reqwest::header::{ACCEPT, AUTHORIZATION};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+use super::Downloader;
+use crate::core::NitroError;
+
+const INDEX_MEDIA_TYPE: &str = "application/vnd.oci.image.index.v1+json";
+const MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+
+/// Annotation Homebrew puts on each platform's manifest in a bottle index
+const BOTTLE_DIGEST_ANNOTATION: &str = "sh.brew.bottle.digest";
+
+/// A blob URL, `<registry>/v2/<repository>/blobs/<digest>`, as formulae give bottles.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlobRef {
+    /// Scheme and host, e.g. `https://ghcr.io`
+    pub registry: String,
+    /// e.g. `homebrew/core/wget`
+    pub repository: String,
+    /// e.g. `sha256:1f2e...`
+    pub digest: String,
+}
+
+impl BlobRef {
+    pub fn parse(url: &str) -> Option<Self> {
+        let (registry, path) = url.split_once("://").and_then(|(scheme, rest)| {
+            let (host, path) = rest.split_once('/')?;
+            Some((format!("{}://{}", scheme, host), path))
+        })?;
+        let (repository, digest) = path.strip_prefix("v2/")?.rsplit_once("/blobs/")?;
+        if repository.is_empty() || !digest.starts_with("sha256:") {
+            return None;
+        }
+        Some(Self { registry, repository: repository.to_string(), digest: digest.to_string() })
+    }
+
+    /// The registry's name for itself in token requests (`ghcr.io`).
+    pub fn service(&self) -> &str {
+        self.registry.split_once("://").map_or(&self.registry, |(_, host)| host)
+    }
+
+    pub fn url(&self) -> String {
+        format!("{}/v2/{}/blobs/{}", self.registry, self.repository, self.digest)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    #[serde(alias = "access_token")]
+    token: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ImageIndex {
+    #[serde(default)]
+    pub manifests: Vec<IndexEntry>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct IndexEntry {
+    pub digest: String,
+    #[serde(default)]
+    pub annotations: HashMap<String, String>,
+}
+
+impl ImageIndex {
+    /// The bottle digests (bare SHA-256s) the index lists, one per platform.
+    pub fn bottle_digests(&self) -> Vec<&str> {
+        self.manifests.iter()
+            .filter_map(|entry| entry.annotations.get(BOTTLE_DIGEST_ANNOTATION))
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+/// Anonymous tokens by `registry repository`, shared by every client in the process.
+/// They last several minutes, which covers any one install.
+fn tokens() -> &'static Mutex<HashMap<String, String>> {
+    static TOKENS: OnceLock<Mutex<HashMap<String, String>>> = OnceLock::new();
+    TOKENS.get_or_init(Default::default)
+}
+
+#[derive(Clone)]
+pub struct OciClient {
+    downloader: Downloader,
+}
+
+impl OciClient {
+    pub fn new(downloader: Downloader) -> Self {
+        Self { downloader }
+    }
+
+    /// An anonymous pull token for `repository` on `blob`'s registry.
+    pub async fn token(&self, blob: &BlobRef) -> Result<String> {
+        let key = format!("{} {}", blob.registry, blob.repository);
+        if let Some(token) = tokens().lock().unwrap().get(&key) {
+            return Ok(token.clone());
+        }
+
+        let url = format!("{}/token", blob.registry);
+        let scope = format!("repository:{}:pull", blob.repository);
+        let response = self.downloader.metadata_request(&url)
+            .query(&[("scope", scope.as_str()), ("service", blob.service())])
+            .send().await?;
+        if !response.status().is_success() {
+            return Err(NitroError::DownloadFailed(format!(
+                "{} refused a pull token for {} (HTTP {})", blob.service(), blob.repository, response.status()
+            )).into());
+        }
+        let token = response.json::<TokenResponse>().await?.token;
+        tokens().lock().unwrap().insert(key, token.clone());
+        Ok(token)
+    }
+
+    /// The image index tagged `reference` in `blob`'s repository.
+    pub async fn index(&self, blob: &BlobRef, reference: &str) -> Result<ImageIndex> {
+        let url = format!("{}/v2/{}/manifests/{}", blob.registry, blob.repository, reference);
+        let response = self.downloader.metadata_request(&url)
+            .header(AUTHORIZATION, format!("Bearer {}", self.token(blob).await?))
+            .header(ACCEPT, format!("{}, {}", INDEX_MEDIA_TYPE, MANIFEST_MEDIA_TYPE))
+            .send().await?;
+        if !response.status().is_success() {
+            return Err(NitroError::DownloadFailed(format!("HTTP {}: {}", response.status(), url)).into());
+        }
+        Ok(response.json().await?)
+    }
+
+    /// Fail unless the index tagged `tag` lists `blob` as one of its bottles, so a
+    /// formula whose bottles were rebuilt since is reported as such rather than as a
+    /// failed download. An index that can't be fetched proves nothing either way.
+    pub async fn check_published(&self, blob: &BlobRef, tag: &str) -> Result<()> {
+        let index = match self.index(blob, tag).await {
+            Ok(index) => index,
+            Err(e) => {
+                tracing::debug!("Could not fetch the {}:{} manifest: {}", blob.repository, tag, e);
+                return Ok(());
+            }
+        };
+        let digest = blob.digest.trim_start_matches("sha256:");
+        let digests = index.bottle_digests();
+        if !digests.is_empty() && !digests.contains(&digest) {
+            return Err(NitroError::DownloadFailed(format!(
+                "{}:{} has no bottle {}; the formula may be out of date (run `nitro update`)",
+                blob.repository, tag, blob.digest
+            )).into());
+        }
+        Ok(())
+    }
+
+    /// The size of `blob` as the registry reports it to a HEAD request, with the pull
+    /// token a GET would carry.
+    pub async fn blob_size(&self, blob: &BlobRef, timeout: std::time::Duration) -> Result<Option<u64>> {
+        let response = self.downloader.client().head(blob.url())
+            .header(AUTHORIZATION, format!("Bearer {}", self.token(blob).await?))
+            .timeout(timeout)
+            .send().await?;
+        if !response.status().is_success() {
+            return Err(NitroError::DownloadFailed(format!("HTTP {}: {}", response.status(), blob.url())).into());
+        }
+        Ok(super::content_length(&response))
+    }
+
+    /// Stream `blob` to `dest`. A token the registry has since expired is renewed once.
+    pub async fn download_blob(&self, blob: &BlobRef, dest: &Path) -> Result<()> {
+        match self.request_blob(blob, dest).await {
+            Err(e) if e.to_string().contains(&format!("HTTP {}", StatusCode::UNAUTHORIZED)) => {
+                tokens().lock().unwrap().remove(&format!("{} {}", blob.registry, blob.repository));
+                self.request_blob(blob, dest).await
+            }
+            result => result,
+        }
+    }
+
+    async fn request_blob(&self, blob: &BlobRef, dest: &Path) -> Result<()> {
+        let url = blob.url();
+        let request = self.downloader.client().get(&url)
+            .header(AUTHORIZATION, format!("Bearer {}", self.token(blob).await?));
+        self.downloader.download_request(request, &url, dest).await
+    }
+}
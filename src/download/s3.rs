@@ -0,0 +1,230 @@
+use anyhow::Result;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use super::scheme::{FetchFuture, SchemeHandler};
+use super::Downloader;
+use crate::core::NitroError;
+
+/// SHA-256 of an empty body, the payload hash for GET requests
+const EMPTY_PAYLOAD_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AwsCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+impl AwsCredentials {
+    /// Resolve credentials the way the AWS CLI does for the common cases:
+    /// environment variables first, then the shared credentials file.
+    pub fn from_chain() -> Option<Self> {
+        Self::from_env().or_else(Self::from_profile)
+    }
+
+    fn from_env() -> Option<Self> {
+        let access_key_id = std::env::var("AWS_ACCESS_KEY_ID").ok()?;
+        let secret_access_key = std::env::var("AWS_SECRET_ACCESS_KEY").ok()?;
+        Some(Self {
+            access_key_id,
+            secret_access_key,
+            session_token: std::env::var("AWS_SESSION_TOKEN").ok(),
+        })
+    }
+
+    fn from_profile() -> Option<Self> {
+        let path = std::env::var_os("AWS_SHARED_CREDENTIALS_FILE")
+            .map(PathBuf::from)
+            .or_else(|| directories::BaseDirs::new().map(|d| d.home_dir().join(".aws/credentials")))?;
+        let content = std::fs::read_to_string(path).ok()?;
+        let profile = std::env::var("AWS_PROFILE").unwrap_or_else(|_| "default".to_string());
+        let section = parse_ini_section(&content, &profile)?;
+
+        Some(Self {
+            access_key_id: section.get("aws_access_key_id")?.clone(),
+            secret_access_key: section.get("aws_secret_access_key")?.clone(),
+            session_token: section.get("aws_session_token").cloned(),
+        })
+    }
+}
+
+/// Region from the environment or the shared config file, defaulting to us-east-1.
+fn resolve_region() -> String {
+    if let Ok(region) = std::env::var("AWS_REGION").or_else(|_| std::env::var("AWS_DEFAULT_REGION")) {
+        return region;
+    }
+
+    let profile = std::env::var("AWS_PROFILE").unwrap_or_else(|_| "default".to_string());
+    let section_name = if profile == "default" { profile } else { format!("profile {}", profile) };
+    directories::BaseDirs::new()
+        .and_then(|d| std::fs::read_to_string(d.home_dir().join(".aws/config")).ok())
+        .and_then(|content| parse_ini_section(&content, &section_name))
+        .and_then(|section| section.get("region").cloned())
+        .unwrap_or_else(|| "us-east-1".to_string())
+}
+
+fn parse_ini_section(content: &str, name: &str) -> Option<HashMap<String, String>> {
+    let mut in_section = false;
+    let mut values = HashMap::new();
+    let mut found = false;
+
+    for line in content.lines() {
+        let line = line.trim();
+        if line.starts_with('[') && line.ends_with(']') {
+            in_section = line[1..line.len() - 1].trim() == name;
+            found |= in_section;
+        } else if in_section {
+            if let Some((key, value)) = line.split_once('=') {
+                values.insert(key.trim().to_string(), value.trim().to_string());
+            }
+        }
+    }
+
+    found.then_some(values)
+}
+
+/// `s3://bucket/key` URLs, signed with AWS Signature Version 4 when credentials are
+/// available and fetched anonymously otherwise (public buckets).
+pub struct S3Handler;
+
+impl S3Handler {
+    fn split_url(url: &str) -> Result<(String, String)> {
+        let rest = url.strip_prefix("s3://")
+            .ok_or_else(|| NitroError::DownloadFailed(format!("Invalid S3 URL: {}", url)))?;
+        match rest.split_once('/') {
+            Some((bucket, key)) if !bucket.is_empty() && !key.is_empty() => {
+                Ok((bucket.to_string(), key.to_string()))
+            }
+            _ => Err(NitroError::DownloadFailed(format!("S3 URL must be s3://bucket/key: {}", url)).into()),
+        }
+    }
+
+    fn build_request(downloader: &Downloader, url: &str) -> Result<(String, reqwest::RequestBuilder)> {
+        let (bucket, key) = Self::split_url(url)?;
+        let region = resolve_region();
+
+        // Custom endpoints (MinIO, R2, ...) use path-style addressing
+        let (host, path, base) = match std::env::var("AWS_ENDPOINT_URL") {
+            Ok(endpoint) => {
+                let endpoint = endpoint.trim_end_matches('/').to_string();
+                let host = endpoint.split_once("://").map(|(_, h)| h).unwrap_or(&endpoint).to_string();
+                (host, format!("/{}/{}", bucket, uri_encode_path(&key)), endpoint)
+            }
+            Err(_) => {
+                let host = format!("{}.s3.{}.amazonaws.com", bucket, region);
+                let base = format!("https://{}", host);
+                (host, format!("/{}", uri_encode_path(&key)), base)
+            }
+        };
+        let http_url = format!("{}{}", base, path);
+
+        let mut request = downloader.client().get(&http_url);
+        if let Some(credentials) = AwsCredentials::from_chain() {
+            let now = chrono::Utc::now();
+            for (name, value) in sign_get(&credentials, &region, &host, &path, now) {
+                request = request.header(name, value);
+            }
+        }
+
+        Ok((http_url, request))
+    }
+}
+
+impl SchemeHandler for S3Handler {
+    fn scheme(&self) -> &'static str {
+        "s3"
+    }
+
+    fn fetch<'a>(&'a self, downloader: &'a Downloader, url: &'a str, dest: &'a Path) -> FetchFuture<'a> {
+        Box::pin(async move {
+            let (http_url, request) = Self::build_request(downloader, url)?;
+            downloader.download_request(request, &http_url, dest).await
+        })
+    }
+}
+
+/// Percent-encode an object key per SigV4 rules, keeping `/` separators.
+fn uri_encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Derive the SigV4 signing key for a date (YYYYMMDD), region and service.
+pub fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let k_date = hmac_sha256(format!("AWS4{}", secret).as_bytes(), date.as_bytes());
+    let k_region = hmac_sha256(&k_date, region.as_bytes());
+    let k_service = hmac_sha256(&k_region, service.as_bytes());
+    hmac_sha256(&k_service, b"aws4_request")
+}
+
+/// Headers that authenticate an S3 GET of `path` on `host`.
+pub fn sign_get(
+    credentials: &AwsCredentials,
+    region: &str,
+    host: &str,
+    path: &str,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Vec<(String, String)> {
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+
+    let mut headers = vec![
+        ("host".to_string(), host.to_string()),
+        ("x-amz-content-sha256".to_string(), EMPTY_PAYLOAD_SHA256.to_string()),
+        ("x-amz-date".to_string(), amz_date.clone()),
+    ];
+    if let Some(token) = &credentials.session_token {
+        headers.push(("x-amz-security-token".to_string(), token.clone()));
+    }
+    headers.sort();
+
+    let canonical_headers: String = headers.iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+        .collect();
+    let signed_headers = headers.iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>()
+        .join(";");
+
+    let canonical_request = format!(
+        "GET\n{}\n\n{}\n{}\n{}",
+        path, canonical_headers, signed_headers, EMPTY_PAYLOAD_SHA256
+    );
+    let scope = format!("{}/{}/s3/aws4_request", date, region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date, scope, hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    let key = signing_key(&credentials.secret_access_key, &date, region, "s3");
+    let signature = hex::encode(hmac_sha256(&key, string_to_sign.as_bytes()));
+
+    let mut result: Vec<(String, String)> = headers.into_iter()
+        .filter(|(name, _)| name != "host")
+        .collect();
+    result.push((
+        "authorization".to_string(),
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            credentials.access_key_id, scope, signed_headers, signature
+        ),
+    ));
+    result
+}
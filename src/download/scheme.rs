@@ -0,0 +1,67 @@
+use anyhow::Result;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+
+use super::Downloader;
+use crate::core::NitroError;
+
+pub type FetchFuture<'a> = Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+
+/// Fetches URLs of a non-HTTP scheme into a local file.
+pub trait SchemeHandler: Send + Sync {
+    /// URL scheme handled, without the `://`
+    fn scheme(&self) -> &'static str;
+
+    /// Fetch `url` into `dest`. The downloader is passed in so handlers that end up
+    /// issuing HTTP requests reuse its client and progress reporting.
+    fn fetch<'a>(&'a self, downloader: &'a Downloader, url: &'a str, dest: &'a Path) -> FetchFuture<'a>;
+}
+
+/// Scheme portion of a URL, lowercased (`https`, `file`, `s3`, ...).
+pub fn scheme_of(url: &str) -> Option<String> {
+    url.split_once("://").map(|(scheme, _)| scheme.to_ascii_lowercase())
+}
+
+/// `file://` URLs: hardlink the file into place when possible, copy otherwise.
+pub struct FileHandler;
+
+impl FileHandler {
+    fn path_from_url(url: &str) -> Result<PathBuf> {
+        reqwest::Url::parse(url)
+            .ok()
+            .and_then(|u| u.to_file_path().ok())
+            .ok_or_else(|| NitroError::DownloadFailed(format!("Invalid file URL: {}", url)).into())
+    }
+}
+
+impl SchemeHandler for FileHandler {
+    fn scheme(&self) -> &'static str {
+        "file"
+    }
+
+    fn fetch<'a>(&'a self, _downloader: &'a Downloader, url: &'a str, dest: &'a Path) -> FetchFuture<'a> {
+        Box::pin(async move {
+            let source = Self::path_from_url(url)?;
+            if !source.is_file() {
+                return Err(NitroError::DownloadFailed(
+                    format!("File not found: {}", source.display())
+                ).into());
+            }
+
+            if let Some(parent) = dest.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            if dest.exists() {
+                tokio::fs::remove_file(dest).await?;
+            }
+
+            // Hardlinks are free on the same filesystem; fall back to a copy across devices
+            if tokio::fs::hard_link(&source, dest).await.is_err() {
+                tokio::fs::copy(&source, dest).await?;
+            }
+
+            Ok(())
+        })
+    }
+}
@@ -3,4 +3,6 @@ pub mod core;
 pub mod download;
 pub mod cache;
 pub mod search;
-pub mod ui;
\ No newline at end of file
+pub mod ui;
+pub mod daemon;
+pub mod metrics;
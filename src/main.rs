@@ -2,53 +2,83 @@ use anyhow::Result;
 use clap::Parser;
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
-mod cli;
-mod core;
-mod download;
-mod cache;
-mod search;
-mod ui;
-
-use cli::{Cli, Commands};
+use nitro::cli::{self, Cli};
+use nitro::core::analytics::Analytics;
+use nitro::core::interrupt;
+use nitro::ui::i18n;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize tracing
+    // Parse CLI arguments
+    let cli = Cli::parse();
+
+    // Initialize tracing; RUST_LOG takes precedence over -v
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| match cli.verbose {
+        0 => EnvFilter::new("error"),
+        1 => EnvFilter::new("nitro=info"),
+        2 => EnvFilter::new("nitro=debug"),
+        _ => EnvFilter::new("trace"),
+    });
     tracing_subscriber::registry()
         .with(fmt::layer())
-        .with(EnvFilter::from_default_env())
+        .with(filter)
         .init();
 
-    // Parse CLI arguments
-    let cli = Cli::parse();
+    // Run the command, then record usage if analytics are enabled
+    let command_name = cli.command.name();
+    let formulae = cli.command.formulae();
+    let tracked = cli.command.is_tracked();
+    let started = std::time::Instant::now();
 
-    // Handle commands
-    match cli.command {
-        Commands::Install(args) => {
-            cli::commands::install::execute(args).await?;
-        }
-        Commands::Uninstall(args) => {
-            cli::commands::uninstall::execute(args).await?;
-        }
-        Commands::Search(args) => {
-            cli::commands::search::execute(args).await?;
-        }
-        Commands::List(args) => {
-            cli::commands::list::execute(args).await?;
-        }
-        Commands::Update(args) => {
-            cli::commands::update::execute(args).await?;
-        }
-        Commands::Info(args) => {
-            cli::commands::info::execute(args).await?;
-        }
-        Commands::Tap(args) => {
-            cli::commands::tap::execute(args).await?;
+    if cli.no_auto_tap {
+        nitro::core::tap::disable_auto_tap();
+    }
+    if cli.require_trusted {
+        nitro::core::tap::require_trusted();
+    }
+    if cli.allow_untrusted {
+        nitro::core::tap::allow_untrusted();
+    }
+    if cli.deterministic {
+        nitro::core::deterministic::enable();
+    }
+    if cli.quiet {
+        nitro::ui::set_quiet();
+    }
+
+    interrupt::install_handler();
+    let mut command = Box::pin(cli::run(cli.command));
+    let result = tokio::select! {
+        result = &mut command => result,
+        _ = interrupt::wait() => {
+            // Give the command a chance to reach a safe point and roll back its current step
+            match tokio::time::timeout(interrupt::GRACE_PERIOD, &mut command).await {
+                Ok(result) => result,
+                Err(_) => Err(nitro::core::NitroError::Interrupted.into()),
+            }
         }
-        Commands::Homebrew(args) => {
-            cli::commands::homebrew::execute(args).await?;
+    };
+    // Dropping the command releases temp directories and flushes the databases it opened
+    drop(command);
+
+    if tracked {
+        if let Ok(analytics) = Analytics::new() {
+            analytics.record(command_name, &formulae, result.is_ok(), started.elapsed()).await;
         }
     }
 
+    if interrupt::is_interrupted() {
+        eprintln!("{}", i18n::t("error.interrupted"));
+        std::process::exit(130);
+    }
+
+    if let Err(e) = result {
+        let (message, code) = match e.downcast_ref::<nitro::core::NitroError>() {
+            Some(error) => (error.localized(), error.exit_code()),
+            None => (format!("{:#}", e), 1),
+        };
+        eprintln!("{}: {}", i18n::t("error.prefix"), message);
+        std::process::exit(code);
+    }
     Ok(())
-}
\ No newline at end of file
+}
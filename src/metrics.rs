@@ -0,0 +1,117 @@
+//! Process-wide counters of installs, downloads and cache lookups, kept wherever in
+//! nitro they happen. Commands summarize their transfers from them, and the daemon
+//! serves them at `/metrics` in the Prometheus text format.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    installs: AtomicU64,
+    failures: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    download_bytes: AtomicU64,
+    network_downloads: AtomicU64,
+}
+
+/// The counters shared by the whole process.
+pub fn global() -> &'static Metrics {
+    static METRICS: Metrics = Metrics {
+        installs: AtomicU64::new(0),
+        failures: AtomicU64::new(0),
+        cache_hits: AtomicU64::new(0),
+        cache_misses: AtomicU64::new(0),
+        download_bytes: AtomicU64::new(0),
+        network_downloads: AtomicU64::new(0),
+    };
+    &METRICS
+}
+
+/// The download counters at one moment, or how far they moved between two.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Transfers {
+    pub bytes: u64,
+    /// Downloads served from a cache
+    pub cache_hits: u64,
+    /// Downloads fetched from the network
+    pub network: u64,
+}
+
+impl Transfers {
+    /// What was transferred since `earlier`.
+    pub fn since(&self, earlier: &Transfers) -> Transfers {
+        Transfers {
+            bytes: self.bytes.saturating_sub(earlier.bytes),
+            cache_hits: self.cache_hits.saturating_sub(earlier.cache_hits),
+            network: self.network.saturating_sub(earlier.network),
+        }
+    }
+
+    pub fn downloads(&self) -> u64 {
+        self.cache_hits + self.network
+    }
+}
+
+impl Metrics {
+    /// A package installed or upgraded.
+    pub fn record_install(&self) {
+        self.installs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A package a job failed on, whatever the job was.
+    pub fn record_failure(&self) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A download served from the cache (`true`) or fetched from the network.
+    pub fn record_cache_lookup(&self, hit: bool) {
+        let counter = if hit { &self.cache_hits } else { &self.cache_misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_download_bytes(&self, bytes: u64) {
+        self.download_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// A download fetched from the network, cached afterwards or not.
+    pub fn record_network_download(&self) {
+        self.network_downloads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn transfers(&self) -> Transfers {
+        Transfers {
+            bytes: self.download_bytes.load(Ordering::Relaxed),
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            network: self.network_downloads.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Share of cache lookups that were hits; 0 before the first lookup.
+    pub fn cache_hit_ratio(&self) -> f64 {
+        let hits = self.cache_hits.load(Ordering::Relaxed);
+        let total = hits + self.cache_misses.load(Ordering::Relaxed);
+        if total == 0 { 0.0 } else { hits as f64 / total as f64 }
+    }
+
+    /// Every metric, with the job queue's current `queue_depth` and `running` jobs.
+    pub fn render(&self, queue_depth: usize, running: usize) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{} {}", name, value);
+        };
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed).to_string();
+
+        metric("nitro_installs_total", "counter", "Packages installed or upgraded.", load(&self.installs));
+        metric("nitro_failures_total", "counter", "Packages a job failed on.", load(&self.failures));
+        metric("nitro_cache_hits_total", "counter", "Downloads served from the cache.", load(&self.cache_hits));
+        metric("nitro_cache_misses_total", "counter", "Downloads not found in the cache.", load(&self.cache_misses));
+        metric("nitro_cache_hit_ratio", "gauge", "Share of cache lookups that were hits.", self.cache_hit_ratio().to_string());
+        metric("nitro_download_bytes_total", "counter", "Bytes downloaded.", load(&self.download_bytes));
+        metric("nitro_queue_depth", "gauge", "Jobs waiting to run.", queue_depth.to_string());
+        metric("nitro_jobs_running", "gauge", "Jobs running.", running.to_string());
+        out
+    }
+}
@@ -2,11 +2,12 @@ use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use tantivy::collector::TopDocs;
-use tantivy::query::QueryParser;
+use tantivy::query::{BooleanQuery, BoostQuery, Occur, PhraseQuery, Query, QueryParser, QueryParserError, TermQuery};
 use tantivy::schema::*;
 use tantivy::{doc, Index, IndexReader, IndexWriter, ReloadPolicy};
 
 use crate::cli::commands::search::SearchArgs;
+use crate::core::formula::DependencyKind;
 use crate::core::{NitroError, NitroResult};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -17,8 +18,35 @@ pub struct SearchResult {
     pub tap: String,
     pub formula_path: PathBuf,
     pub score: f32,
+    /// True for casks, false for formulae
+    #[serde(default)]
+    pub cask: bool,
 }
 
+/// Graph data stored alongside each indexed formula, so dependency questions can be
+/// answered without parsing .rb files.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IndexedFormula {
+    pub name: String,
+    pub version: String,
+    pub tap: String,
+    /// Direct runtime dependencies
+    pub dependencies: Vec<String>,
+    pub build_dependencies: Vec<String>,
+    /// Platforms with a bottle, as `platform/arch` (e.g. `darwin/aarch64`)
+    pub bottles: Vec<String>,
+}
+
+impl IndexedFormula {
+    pub fn has_bottle_for(&self, platform_tag: &str) -> bool {
+        self.bottles.iter().any(|b| b == platform_tag)
+    }
+}
+
+/// Values of the `kind` field
+const KIND_FORMULA: &str = "formula";
+const KIND_CASK: &str = "cask";
+
 pub struct SearchEngine {
     index: Index,
     reader: IndexReader,
@@ -27,6 +55,11 @@ pub struct SearchEngine {
     version_field: Field,
     tap_field: Field,
     path_field: Field,
+    kind_field: Field,
+    key_field: Field,
+    dependencies_field: Field,
+    build_dependencies_field: Field,
+    bottles_field: Field,
 }
 
 impl SearchEngine {
@@ -34,23 +67,47 @@ impl SearchEngine {
         let config_dir = directories::ProjectDirs::from("com", "nitro", "nitro")
             .ok_or_else(|| NitroError::Other("Could not determine config directory".into()))?;
         
-        let index_dir = config_dir.data_dir().join("search_index");
-        std::fs::create_dir_all(&index_dir)?;
+        Self::with_dir(&config_dir.data_dir().join("search_index")).await
+    }
+
+    pub async fn with_dir(index_dir: &std::path::Path) -> Result<Self> {
+        let read_only = crate::core::readonly::is_enabled();
+        if !read_only {
+            std::fs::create_dir_all(index_dir)?;
+        }
 
         // Create schema
         let mut schema_builder = Schema::builder();
         let name_field = schema_builder.add_text_field("name", TEXT | STORED);
         let description_field = schema_builder.add_text_field("description", TEXT | STORED);
         let version_field = schema_builder.add_text_field("version", STORED);
-        let tap_field = schema_builder.add_text_field("tap", STORED);
-        let path_field = schema_builder.add_text_field("path", STORED);
+        // Tap and kind are untokenized so searches can filter on them exactly
+        let tap_field = schema_builder.add_text_field("tap", STRING | STORED);
+        let path_field = schema_builder.add_text_field("path", STRING | STORED);
+        let kind_field = schema_builder.add_text_field("kind", STRING | STORED);
+        // Untokenized copies for exact lookups and reverse-dependency queries
+        let key_field = schema_builder.add_text_field("key", STRING | STORED);
+        let dependencies_field = schema_builder.add_text_field("dependencies", STRING | STORED);
+        let build_dependencies_field = schema_builder.add_text_field("build_dependencies", STRING | STORED);
+        let bottles_field = schema_builder.add_text_field("bottles", STORED);
         let schema = schema_builder.build();
 
-        // Create or open index
-        let index = if index_dir.join("meta.json").exists() {
-            Index::open_in_dir(&index_dir)?
-        } else {
-            Index::create_in_dir(&index_dir, schema.clone())?
+        // Create or open index; an index written with an older schema is rebuilt from scratch
+        let index = match Index::open_in_dir(index_dir) {
+            Ok(index) if index.schema() == schema => index,
+            // Never rebuild or create the index in read-only mode; search an empty one
+            _ if read_only => {
+                eprintln!("Search index is missing or outdated; run 'nitro update --formulae' to rebuild it");
+                Index::create_in_ram(schema.clone())
+            }
+            Ok(_) => {
+                eprintln!("Search index format changed; run 'nitro update --formulae' to rebuild it");
+                std::fs::remove_dir_all(index_dir)?;
+                std::fs::create_dir_all(index_dir)?;
+                Index::create_in_dir(index_dir, schema.clone())?
+            }
+            Err(_) if !index_dir.join("meta.json").exists() => Index::create_in_dir(index_dir, schema.clone())?,
+            Err(e) => return Err(e.into()),
         };
 
         let reader = index
@@ -66,33 +123,76 @@ impl SearchEngine {
             version_field,
             tap_field,
             path_field,
+            kind_field,
+            key_field,
+            dependencies_field,
+            build_dependencies_field,
+            bottles_field,
         })
     }
 
     pub async fn search(&self, query: &str, args: &SearchArgs) -> NitroResult<Vec<SearchResult>> {
         let searcher = self.reader.searcher();
         
-        let query_parser = if args.fuzzy {
+        // Several plain words ("http server") are matched against descriptions too, since
+        // they rarely all appear in a name
+        let plain_words = is_plain_words(query);
+        let fields = if args.description || args.fuzzy || plain_words {
+            vec![self.name_field, self.description_field]
+        } else {
+            vec![self.name_field]
+        };
+
+        let mut query_parser = QueryParser::for_index(&self.index, fields.clone());
+        if args.fuzzy {
             // For fuzzy search, we'll use a more permissive approach
-            let mut parser = QueryParser::for_index(&self.index, vec![self.name_field, self.description_field]);
-            parser.set_field_fuzzy(self.name_field, true, 1, true);
+            query_parser.set_field_fuzzy(self.name_field, true, 1, true);
             if args.description {
-                parser.set_field_fuzzy(self.description_field, true, 1, true);
+                query_parser.set_field_fuzzy(self.description_field, true, 1, true);
+            }
+        }
+        if !args.any {
+            query_parser.set_conjunction_by_default();
+        }
+
+        // Package names like `c++` or a stray quote aren't valid query syntax; search for
+        // the text literally rather than failing
+        let mut parsed = match query_parser.parse_query(query) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                tracing::info!("Could not parse query ({}); searching for it literally", describe_query_error(query, &e));
+                self.literal_query(query, &fields)?
             }
-            parser
-        } else {
-            let fields = if args.description {
-                vec![self.name_field, self.description_field]
-            } else {
-                vec![self.name_field]
-            };
-            QueryParser::for_index(&self.index, fields)
         };
 
-        let query = query_parser.parse_query(query)
-            .map_err(|e| NitroError::SearchError(format!("Query parse error: {}", e)))?;
+        // Rank results containing the words as a phrase first, names above descriptions
+        if plain_words {
+            parsed = Box::new(BooleanQuery::new(vec![
+                (Occur::Must, parsed),
+                (Occur::Should, Box::new(BoostQuery::new(self.literal_query(query, &[self.name_field])?, 3.0))),
+                (Occur::Should, Box::new(BoostQuery::new(self.literal_query(query, &[self.description_field])?, 2.0))),
+            ]));
+        }
 
-        let top_docs = searcher.search(&query, &TopDocs::with_limit(args.limit))?;
+        // Scope to a tap or kind in the query itself, so the top `limit` hits are all wanted
+        let mut filters: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+        if let Some(tap) = &args.tap {
+            filters.push((Occur::Must, Box::new(TermQuery::new(Term::from_field_text(self.tap_field, tap), IndexRecordOption::Basic))));
+        }
+        let kind = if args.cask {
+            Some(KIND_CASK)
+        } else {
+            args.formula.then_some(KIND_FORMULA)
+        };
+        if let Some(kind) = kind {
+            filters.push((Occur::Must, Box::new(TermQuery::new(Term::from_field_text(self.kind_field, kind), IndexRecordOption::Basic))));
+        }
+        if !filters.is_empty() {
+            filters.insert(0, (Occur::Must, parsed));
+            parsed = Box::new(BooleanQuery::new(filters));
+        }
+
+        let top_docs = searcher.search(&parsed, &TopDocs::with_limit(args.limit))?;
         
         let mut results = Vec::new();
         for (score, doc_address) in top_docs {
@@ -137,6 +237,10 @@ impl SearchEngine {
                 })
                 .unwrap_or_default();
             
+            let cask = retrieved_doc
+                .get_first(self.kind_field)
+                .is_some_and(|v| matches!(v, tantivy::schema::OwnedValue::Str(s) if s == KIND_CASK));
+            
             results.push(SearchResult {
                 name,
                 description,
@@ -144,13 +248,49 @@ impl SearchEngine {
                 tap,
                 formula_path,
                 score,
+                cask,
             });
         }
 
         Ok(results)
     }
 
-    pub async fn index_formula(&self, name: &str, description: Option<&str>, version: &str, tap: &str, path: &PathBuf) -> Result<()> {
+    /// The query text as plain words: a term query for a single word, a phrase query for
+    /// several, on each field. Operators and quotes are dropped by the tokenizer.
+    fn literal_query(&self, text: &str, fields: &[Field]) -> NitroResult<Box<dyn Query>> {
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+        for &field in fields {
+            let mut tokenizer = self.index.tokenizer_for_field(field)?;
+            let mut stream = tokenizer.token_stream(text);
+            let mut terms = Vec::new();
+            while let Some(token) = stream.next() {
+                terms.push(Term::from_field_text(field, &token.text));
+            }
+
+            match terms.len() {
+                0 => {}
+                1 => clauses.push((Occur::Should, Box::new(TermQuery::new(terms.remove(0), IndexRecordOption::WithFreqs)))),
+                _ => clauses.push((Occur::Should, Box::new(PhraseQuery::new(terms)))),
+            }
+        }
+
+        if clauses.is_empty() {
+            return Err(NitroError::SearchError(format!("Nothing searchable in query '{}'", text)));
+        }
+        Ok(Box::new(BooleanQuery::new(clauses)))
+    }
+
+    pub fn writer(&self) -> Result<IndexWriter> {
+        Ok(self.index.writer(50_000_000)?)
+    }
+
+    /// Make documents committed by a writer visible to searches right away.
+    pub fn reload(&self) -> Result<()> {
+        self.reader.reload()?;
+        Ok(())
+    }
+
+    pub async fn index_formula(&self, name: &str, description: Option<&str>, version: &str, tap: &str, path: &std::path::Path) -> Result<()> {
         let mut index_writer: IndexWriter = self.index.writer(50_000_000)?;
         
         let mut doc = doc!();
@@ -161,6 +301,7 @@ impl SearchEngine {
         doc.add_text(self.version_field, version);
         doc.add_text(self.tap_field, tap);
         doc.add_text(self.path_field, path.to_string_lossy());
+        doc.add_text(self.kind_field, KIND_FORMULA);
         
         index_writer.add_document(doc)?;
         index_writer.commit()?;
@@ -170,94 +311,282 @@ impl SearchEngine {
 
     pub async fn rebuild_index(&self) -> Result<()> {
         use crate::core::tap::TapManager;
-        use crate::core::formula::FormulaParser;
         
         // Clear existing index
         let mut index_writer: IndexWriter = self.index.writer(50_000_000)?;
         index_writer.delete_all_documents()?;
         
         let tap_manager = TapManager::new().await?;
-        let formula_parser = FormulaParser::new();
-        
         // Index all formulae from all taps
         for tap in tap_manager.list_taps().await? {
             let formula_dir = tap.path.join("Formula");
-            if !formula_dir.exists() {
-                continue;
+            if formula_dir.exists() {
+                self.index_formulae(&index_writer, &formula_dir, &tap.name)?;
             }
             
-            self.index_formulae_recursive(&mut index_writer, &formula_parser, &formula_dir, &tap.name).await?;
+            let cask_dir = tap.path.join("Casks");
+            if cask_dir.exists() {
+                self.index_casks(&mut index_writer, &cask_dir, &tap.name)?;
+            }
         }
         
         index_writer.commit()?;
         Ok(())
     }
     pub async fn rebuild_index_with_tap_manager(&self, tap_manager: &crate::core::tap::TapManager) -> Result<()> {
-        use crate::core::formula::FormulaParser;
-        
         // Clear existing index
         let mut index_writer: IndexWriter = self.index.writer(50_000_000)?;
         index_writer.delete_all_documents()?;
         
-        let formula_parser = FormulaParser::new();
-        
         // Index all formulae from all taps using the provided tap_manager
         for tap in tap_manager.list_taps().await? {
             let formula_dir = tap.path.join("Formula");
-            if !formula_dir.exists() {
-                continue;
+            if formula_dir.exists() {
+                self.index_formulae(&index_writer, &formula_dir, &tap.name)?;
             }
             
-            self.index_formulae_recursive(&mut index_writer, &formula_parser, &formula_dir, &tap.name).await?;
+            let cask_dir = tap.path.join("Casks");
+            if cask_dir.exists() {
+                self.index_casks(&mut index_writer, &cask_dir, &tap.name)?;
+            }
         }
         
         index_writer.commit()?;
         Ok(())
     }
 
-    fn index_formulae_recursive<'a>(
-        &'a self,
-        index_writer: &'a mut IndexWriter,
-        formula_parser: &'a crate::core::formula::FormulaParser,
-        dir: &'a std::path::Path,
-        tap_name: &'a str
-    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<()>> + Send + 'a>> {
-        Box::pin(async move {
-            let mut count = 0;
-            for entry in std::fs::read_dir(dir)? {
-                let entry = entry?;
-                let path = entry.path();
-                
-                if path.is_dir() {
-                    // Recursively index subdirectories
-                    self.index_formulae_recursive(index_writer, formula_parser, &path, tap_name).await?;
-                } else if path.extension().and_then(|s| s.to_str()) == Some("rb") {
+    /// Index every formula under `dir`. Files are parsed in parallel on the rayon pool
+    /// and the documents funnelled through a channel to the single index writer.
+    /// Returns the number of formulae indexed; files that fail to parse are skipped.
+    pub fn index_formulae(&self, index_writer: &IndexWriter, dir: &std::path::Path, tap_name: &str) -> Result<usize> {
+        use crate::core::formula::FormulaParser;
+        use rayon::prelude::*;
+
+        let paths: Vec<std::path::PathBuf> = walkdir::WalkDir::new(dir)
+            .into_iter()
+            .flatten()
+            .filter(|e| e.file_type().is_file() && e.path().extension().and_then(|s| s.to_str()) == Some("rb"))
+            .map(|e| e.into_path())
+            .collect();
+
+        std::thread::scope(|scope| {
+            let (tx, rx) = std::sync::mpsc::sync_channel::<TantivyDocument>(256);
+
+            scope.spawn(move || {
+                let parser = FormulaParser::new();
+                paths.par_iter().for_each_with(tx, |tx, path| {
+                    let Ok(content) = std::fs::read_to_string(path) else {
+                        return;
+                    };
                     // Skip parsing errors silently to avoid blocking on problematic formulae
-                    if let Ok(formula) = formula_parser.parse_file(&path).await {
-                        let name = path.file_stem()
-                            .and_then(|s| s.to_str())
-                            .unwrap_or(&formula.name);
-                        
-                        let mut doc = doc!();
-                        doc.add_text(self.name_field, name);
-                        if let Some(desc) = &formula.description {
-                            doc.add_text(self.description_field, desc);
-                        }
-                        doc.add_text(self.version_field, &formula.version);
-                        doc.add_text(self.tap_field, tap_name);
-                        doc.add_text(self.path_field, path.to_string_lossy());
-                        
-                        index_writer.add_document(doc)?;
-                        count += 1;
-                        
-                        // Commit every 100 documents to avoid memory issues
-                        if count % 100 == 0 {
-                            index_writer.commit()?;
-                        }
+                    if let Ok(formula) = parser.parse_content(&content) {
+                        // A send error means the writer gave up; nothing left to do
+                        let _ = tx.send(self.formula_document(&formula, tap_name, path));
                     }
-                }
+                });
+            });
+
+            let mut count = 0;
+            for doc in rx {
+                index_writer.add_document(doc)?;
+                count += 1;
             }
-            Ok(())
+            Ok(count)
         })
     }
-}
\ No newline at end of file
+
+    fn formula_document(&self, formula: &crate::core::formula::Formula, tap_name: &str, path: &std::path::Path) -> TantivyDocument {
+        // The file name is the canonical formula name (python@3.12.rb, not PythonAT312)
+        let name = path.file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or(&formula.name);
+        
+        let mut doc = doc!();
+        doc.add_text(self.name_field, name);
+        if let Some(desc) = &formula.description {
+            doc.add_text(self.description_field, desc);
+        }
+        doc.add_text(self.version_field, &formula.version);
+        doc.add_text(self.tap_field, tap_name);
+        doc.add_text(self.path_field, path.to_string_lossy());
+        doc.add_text(self.kind_field, KIND_FORMULA);
+        doc.add_text(self.key_field, name);
+        for dep in &formula.dependencies {
+            match dep.kind() {
+                DependencyKind::Runtime => doc.add_text(self.dependencies_field, &dep.name),
+                DependencyKind::Build => doc.add_text(self.build_dependencies_field, &dep.name),
+                _ => {}
+            }
+        }
+        for bottle in &formula.binary_packages {
+            doc.add_text(self.bottles_field, format!("{}/{}", bottle.platform, bottle.arch));
+        }
+        doc
+    }
+
+    /// The indexed graph data for a formula, by exact name.
+    pub fn lookup(&self, name: &str) -> Result<Option<IndexedFormula>> {
+        let searcher = self.reader.searcher();
+        let query = TermQuery::new(Term::from_field_text(self.key_field, name), IndexRecordOption::Basic);
+        let top_docs = searcher.search(&query, &TopDocs::with_limit(1))?;
+
+        match top_docs.first() {
+            Some((_, address)) => Ok(Some(self.indexed_formula(&searcher.doc(*address)?))),
+            None => Ok(None),
+        }
+    }
+
+    /// Formulae that list `name` as a direct runtime dependency.
+    pub fn dependents(&self, name: &str) -> Result<Vec<IndexedFormula>> {
+        let searcher = self.reader.searcher();
+        let query = TermQuery::new(Term::from_field_text(self.dependencies_field, name), IndexRecordOption::Basic);
+        let limit = (searcher.num_docs() as usize).max(1);
+
+        let mut dependents = Vec::new();
+        for (_, address) in searcher.search(&query, &TopDocs::with_limit(limit))? {
+            dependents.push(self.indexed_formula(&searcher.doc(address)?));
+        }
+        dependents.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(dependents)
+    }
+
+    /// `name` and its transitive runtime dependencies, breadth first. Dependencies
+    /// missing from the index are skipped.
+    pub fn dependency_closure(&self, name: &str) -> Result<Vec<IndexedFormula>> {
+        let mut closure = Vec::new();
+        let mut seen = std::collections::HashSet::new();
+        let mut queue = std::collections::VecDeque::from([name.to_string()]);
+
+        while let Some(next) = queue.pop_front() {
+            if !seen.insert(next.clone()) {
+                continue;
+            }
+            if let Some(formula) = self.lookup(&next)? {
+                queue.extend(formula.dependencies.iter().cloned());
+                closure.push(formula);
+            }
+        }
+        Ok(closure)
+    }
+
+    /// Formulae in the combined runtime closure of `roots` with no bottle for
+    /// `platform_tag`, i.e. those that would have to be built from source. Sorted by name.
+    pub fn missing_bottles(&self, roots: &[String], platform_tag: &str) -> Result<Vec<IndexedFormula>> {
+        let mut missing = std::collections::BTreeMap::new();
+        for root in roots {
+            for formula in self.dependency_closure(root)? {
+                if !formula.has_bottle_for(platform_tag) {
+                    missing.insert(formula.name.clone(), formula);
+                }
+            }
+        }
+        Ok(missing.into_values().collect())
+    }
+
+    fn indexed_formula(&self, doc: &TantivyDocument) -> IndexedFormula {
+        let text = |field: Field| -> Vec<String> {
+            doc.get_all(field)
+                .filter_map(|v| match v {
+                    tantivy::schema::OwnedValue::Str(s) => Some(s.clone()),
+                    _ => None,
+                })
+                .collect()
+        };
+        let first = |field: Field| text(field).into_iter().next().unwrap_or_default();
+
+        IndexedFormula {
+            name: first(self.key_field),
+            version: first(self.version_field),
+            tap: first(self.tap_field),
+            dependencies: text(self.dependencies_field),
+            build_dependencies: text(self.build_dependencies_field),
+            bottles: text(self.bottles_field),
+        }
+    }
+
+    /// Replace the indexed document for a formula file. Not committed.
+    pub fn update_formula(&self, index_writer: &mut IndexWriter, formula: &crate::core::formula::Formula, tap_name: &str, path: &std::path::Path) -> Result<()> {
+        self.remove_path(index_writer, path);
+        index_writer.add_document(self.formula_document(formula, tap_name, path))?;
+        Ok(())
+    }
+
+    /// Drop whatever was indexed from `path`. Not committed.
+    pub fn remove_path(&self, index_writer: &mut IndexWriter, path: &std::path::Path) {
+        index_writer.delete_term(Term::from_field_text(self.path_field, &path.to_string_lossy()));
+    }
+
+    /// Index every cask under a tap's `Casks/` directory (flat or sharded by first letter).
+    pub fn index_casks(&self, index_writer: &mut IndexWriter, dir: &std::path::Path, tap_name: &str) -> Result<()> {
+        use crate::core::cask::CaskParser;
+
+        let parser = CaskParser::new();
+        for entry in walkdir::WalkDir::new(dir).into_iter().flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|s| s.to_str()) != Some("rb") {
+                continue;
+            }
+            // Skip unparseable casks rather than failing the whole rebuild
+            let Ok(cask) = parser.parse_file(path) else {
+                continue;
+            };
+
+            let mut doc = doc!();
+            doc.add_text(self.name_field, &cask.token);
+            // Display names ("Visual Studio Code") are searchable alongside the token
+            for name in &cask.names {
+                doc.add_text(self.name_field, name);
+            }
+            if let Some(desc) = &cask.description {
+                doc.add_text(self.description_field, desc);
+            }
+            doc.add_text(self.version_field, &cask.version);
+            doc.add_text(self.tap_field, tap_name);
+            doc.add_text(self.path_field, path.to_string_lossy());
+            doc.add_text(self.kind_field, KIND_CASK);
+            index_writer.add_document(doc)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether a query is two or more ordinary words with no query syntax in it.
+fn is_plain_words(query: &str) -> bool {
+    const SYNTAX: &[char] = &['"', '(', ')', ':', '+', '^', '~', '*', '[', ']', '{', '}'];
+    let words: Vec<&str> = query.split_whitespace().collect();
+    words.len() > 1
+        && !query.contains(SYNTAX)
+        && !words.iter().any(|w| matches!(*w, "AND" | "OR" | "NOT") || w.starts_with('-'))
+}
+
+/// Point at the part of a query the parser choked on, falling back to tantivy's own
+/// message when the problem isn't one of the usual suspects.
+pub fn describe_query_error(query: &str, error: &QueryParserError) -> String {
+    if query.matches('"').count() % 2 == 1 {
+        let position = query.rfind('"').unwrap_or(0);
+        return format!("unbalanced quote at position {} in '{}'", position + 1, query);
+    }
+
+    let mut depth = 0i32;
+    for (i, c) in query.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        if depth < 0 {
+            return format!("unmatched ')' at position {} in '{}'", i + 1, query);
+        }
+    }
+    if depth > 0 {
+        return format!("unmatched '(' in '{}'", query);
+    }
+
+    // Operators inside a word, e.g. the `++` in `c++`
+    const OPERATORS: &[char] = &['+', '-', '!', '^', '~', '*', '?', ':', '{', '}', '[', ']', '\\', '/'];
+    if let Some(word) = query.split_whitespace().find(|w| w.chars().skip(1).any(|c| OPERATORS.contains(&c))) {
+        return format!("'{}' contains query operators", word);
+    }
+
+    error.to_string()
+}
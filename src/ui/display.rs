@@ -1,58 +1,120 @@
 use crate::core::package::Package;
 use crate::search::SearchResult;
 use crate::core::tap::Tap;
+use crate::ui::i18n::{t, tf};
+
+/// Search results in the order they're shown and numbered: formulae, then casks.
+pub fn search_display_order(results: &[SearchResult]) -> Vec<&SearchResult> {
+    let (casks, formulae): (Vec<_>, Vec<_>) = results.iter().partition(|r| r.cask);
+    formulae.into_iter().chain(casks).collect()
+}
 
 pub fn show_search_results(results: &[SearchResult]) {
-    println!("Found {} package(s):\n", results.len());
-    
-    for result in results {
-        println!("🍺 {} ({})", result.name, result.version);
-        if let Some(description) = &result.description {
-            println!("   {}", description);
+    println!("{}\n", tf("search.found", &[("count", &results.len())]));
+    
+    let ordered = search_display_order(results);
+    let formulae = ordered.iter().filter(|r| !r.cask).count();
+    let sections = [
+        (format!("==> {}", t("search.formulae")), &ordered[..formulae]),
+        (format!("==> {}", t("search.casks")), &ordered[formulae..]),
+    ];
+    let show_headers = sections.iter().all(|(_, items)| !items.is_empty());
+    
+    let mut number = 0;
+    for (header, items) in sections.iter().filter(|(_, items)| !items.is_empty()) {
+        if show_headers {
+            println!("{}\n", header);
         }
-        println!("   From: {}", result.tap);
-        if results.len() > 1 {
-            println!();
+        for result in items.iter() {
+            number += 1;
+            let icon = if result.cask { "🖥 " } else { "🍺" };
+            println!("{:>2}. {} {} ({})", number, icon, result.name, result.version);
+            if let Some(description) = &result.description {
+                println!("    {}", description);
+            }
+            println!("    {}", tf("common.from", &[("source", &result.tap)]));
+            if results.len() > 1 {
+                println!();
+            }
         }
     }
 }
 
+/// The installation of `package`, shown by `info` after the formula itself.
 pub fn show_package_info(package: &Package) {
-    println!("📦 {}", package.name);
-    println!("Version: {}", package.version);
-    
-    if let Some(description) = &package.description {
-        println!("Description: {}", description);
+    // Every field is named, so one added to `Package` has to be shown here or
+    // deliberately left out
+    let Package {
+        name: _,
+        version,
+        description: _,
+        homepage: _,
+        installed: _,
+        installed_version,
+        dependencies: _,
+        install_path,
+        size,
+        tap,
+        version_scheme: _,
+        formula_path,
+        formula_hash: _,
+        tap_commit,
+        checksum_override,
+        installed_on_request,
+        pinned,
+        installed_at,
+        linked,
+    } = package;
+
+    println!("\n{}", t("info.installed_header"));
+    println!("  {}", tf("common.version", &[("version", installed_version.as_ref().unwrap_or(version))]));
+    if let Some(path) = install_path {
+        println!("  {}", tf("info.installed_to", &[("path", &path.display())]));
     }
-    
-    if let Some(homepage) = &package.homepage {
-        println!("Homepage: {}", homepage);
+    if let Some(size) = size {
+        println!("  {}", tf("common.size", &[("size", &format_bytes(*size))]));
     }
-    
-    if !package.dependencies.is_empty() {
-        println!("Dependencies: {}", package.dependencies.join(", "));
+
+    let mut source = match (tap, formula_path) {
+        (Some(tap), Some(path)) => Some(format!("{} ({})", tap, path.display())),
+        (Some(tap), None) => Some(tap.clone()),
+        (None, Some(path)) => Some(path.display().to_string()),
+        (None, None) => None,
+    };
+    if let (Some(source), Some(commit)) = (&mut source, tap_commit) {
+        source.push_str(&format!(" @ {}", &commit[..commit.len().min(7)]));
     }
-    
-    if let Some(path) = &package.install_path {
-        println!("Installed to: {}", path.display());
+    if let Some(source) = source {
+        println!("  {}", tf("common.from", &[("source", &source)]));
     }
-    
-    if package.installed {
-        println!("Status: Installed");
+
+    if let Some(installed_at) = installed_at {
+        println!("  {}", tf("info.installed_on", &[("date", &installed_at.format("%Y-%m-%d %H:%M UTC"))]));
     }
-    
-    if let Some(size) = package.size {
-        println!("Size: {}", format_bytes(size));
+    println!("  {}", if *installed_on_request { t("info.on_request") } else { t("info.as_dependency") });
+    if let Some(linked) = linked {
+        println!("  {}", tf("info.linked", &[("linked", &if *linked { t("common.yes") } else { t("common.no") })]));
+    }
+    if *pinned {
+        println!("  {}", t("info.pinned"));
+    }
+
+    if let Some(checksum) = checksum_override {
+        println!("  {}", tf("info.checksum_override", &[
+            ("accepted", &checksum.accepted),
+            ("expected", &checksum.expected),
+            ("date", &checksum.accepted_at.format("%Y-%m-%d")),
+        ]));
     }
 }
 
 pub fn show_package_list(packages: &[Package]) {
     if packages.is_empty() {
-        println!("No packages installed.");
+        println!("{}", t("list.empty"));
         return;
     }
     
-    println!("Installed packages ({}):\n", packages.len());
+    println!("{}\n", tf("list.header", &[("count", &packages.len())]));
     
     for package in packages {
         println!("🍺 {} ({})", package.name, package.version);
@@ -66,71 +128,345 @@ pub fn show_package_list(packages: &[Package]) {
         }
         
         if let Some(size) = package.size {
-            println!("   Size: {}", format_bytes(size));
+            println!("   {}", tf("common.size", &[("size", &format_bytes(size))]));
         }
         println!();
     }
 }
 
-pub fn show_tap_list(taps: &[Tap]) {
+/// One installed formula as `list --format columns` shows it.
+pub struct ListRow {
+    pub name: String,
+    pub version: String,
+    pub size: Option<u64>,
+    pub tap: Option<String>,
+    pub pinned: bool,
+    /// The newer version available, if the formula is outdated
+    pub latest: Option<String>,
+}
+
+pub fn show_package_table(rows: &[ListRow]) {
+    if rows.is_empty() {
+        println!("{}", t("list.empty"));
+        return;
+    }
+
+    println!("{}\n", tf("list.header", &[("count", &rows.len())]));
+    let width = console::Term::stdout().size_checked().map(|(_, columns)| columns as usize);
+    for line in package_table(rows, width) {
+        println!("{}", line);
+    }
+}
+
+/// `rows` as a header line and one line per formula, in columns padded to line up.
+/// When the table is wider than `width` the tap and then the size column are left
+/// out, and if it still doesn't fit, long names are cut short.
+pub fn package_table(rows: &[ListRow], width: Option<usize>) -> Vec<String> {
+    const GAP: usize = 2;
+    const MIN_NAME: usize = 12;
+
+    // Name, version, size, tap, pinned, outdated; size is aligned right
+    let mut columns: Vec<(String, Vec<String>, bool)> = vec![
+        (t("list.name"), rows.iter().map(|r| r.name.clone()).collect(), false),
+        (t("list.version"), rows.iter().map(|r| r.version.clone()).collect(), false),
+        (t("list.size"), rows.iter().map(|r| r.size.map_or_else(|| "-".to_string(), format_bytes)).collect(), true),
+        (t("list.tap"), rows.iter().map(|r| r.tap.clone().unwrap_or_else(|| "-".to_string())).collect(), false),
+        (t("list.pinned"), rows.iter().map(|r| if r.pinned { t("common.yes") } else { String::new() }).collect(), false),
+        (t("list.outdated"), rows.iter().map(|r| r.latest.as_ref().map(|v| format!("→ {}", v)).unwrap_or_default()).collect(), false),
+    ];
+    let column_width = |(header, cells, _): &(String, Vec<String>, bool)| {
+        cells.iter().chain([header]).map(|cell| cell.chars().count()).max().unwrap_or(0)
+    };
+    let total = |columns: &[(String, Vec<String>, bool)]| {
+        columns.iter().map(column_width).sum::<usize>() + GAP * (columns.len() - 1)
+    };
+
+    let mut name_width = column_width(&columns[0]);
+    if let Some(width) = width {
+        for header in [t("list.tap"), t("list.size")] {
+            if total(&columns) > width {
+                columns.retain(|(h, ..)| *h != header);
+            }
+        }
+        let overflow = total(&columns).saturating_sub(width);
+        name_width = name_width.saturating_sub(overflow).max(MIN_NAME).min(name_width);
+    }
+
+    let widths: Vec<usize> = columns.iter().enumerate()
+        .map(|(i, column)| if i == 0 { name_width } else { column_width(column) })
+        .collect();
+    let line = |cells: Vec<&str>| {
+        let padded: Vec<String> = cells.iter().zip(&widths).zip(&columns)
+            .map(|((cell, &width), (.., right))| {
+                let cell = truncate(cell, width);
+                if *right { format!("{:>width$}", cell) } else { format!("{:<width$}", cell) }
+            })
+            .collect();
+        padded.join(&" ".repeat(GAP)).trim_end().to_string()
+    };
+
+    let mut lines = vec![line(columns.iter().map(|(header, ..)| header.as_str()).collect())];
+    for i in 0..rows.len() {
+        lines.push(line(columns.iter().map(|(_, cells, _)| cells[i].as_str()).collect()));
+    }
+    lines
+}
+
+/// `text` cut to `width` characters, ending in `…` if anything was cut.
+fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let mut cut: String = text.chars().take(width.saturating_sub(1)).collect();
+    cut.push('…');
+    cut
+}
+
+pub fn show_cask_list(casks: &[crate::core::cask_installer::InstalledCask]) {
+    if casks.is_empty() {
+        println!("{}", t("casks.empty"));
+        return;
+    }
+
+    println!("{}\n", tf("casks.header", &[("count", &casks.len())]));
+    for cask in casks {
+        println!("🖥  {} ({})", cask.token, cask.version);
+        println!("   {}", tf("casks.installed_at", &[("date", &cask.installed_at.format("%Y-%m-%d %H:%M"))]));
+    }
+}
+
+/// A cask as its tap describes it, along with what was installed if it is installed.
+pub fn show_cask_info(cask: Option<&crate::core::cask::Cask>, installed: Option<&crate::core::cask_installer::InstalledCask>) {
+    let token = cask.map(|c| c.token.as_str()).or(installed.map(|i| i.token.as_str())).unwrap_or_default();
+    println!("\n🖥  {}", token);
+    if let Some(cask) = cask {
+        if !cask.names.is_empty() {
+            println!("{}", tf("cask.name", &[("names", &cask.names.join(", "))]));
+        }
+        println!("{}", tf("common.version", &[("version", &cask.version)]));
+        if let Some(description) = &cask.description {
+            println!("{}", tf("common.description", &[("description", description)]));
+        }
+        if let Some(homepage) = &cask.homepage {
+            println!("{}", tf("common.homepage", &[("homepage", homepage)]));
+        }
+        if cask.auto_updates {
+            println!("{}", t("cask.auto_updates"));
+        }
+    }
+
+    let Some(installed) = installed else {
+        println!("\n{}", t("cask.not_installed"));
+        return;
+    };
+    println!("\n{}", tf("cask.installed", &[
+        ("version", &installed.version),
+        ("date", &installed.installed_at.format("%Y-%m-%d %H:%M")),
+    ]));
+    if let Some(tap) = &installed.tap {
+        println!("{}", tf("common.from", &[("source", tap)]));
+    }
+    if !installed.artifacts.is_empty() {
+        println!("\n{}", t("cask.artifacts"));
+        for artifact in &installed.artifacts {
+            println!("  • {} ({})", artifact.path(), artifact.stanza());
+        }
+    }
+    for file in &installed.files {
+        println!("  → {}", file.display());
+    }
+    for id in &installed.pkg_ids {
+        println!("  → {}", tf("cask.receipt", &[("id", id)]));
+    }
+}
+
+pub fn show_tap_list(taps: &[Tap], config: &crate::core::tap::TapsConfig) {
     if taps.is_empty() {
-        println!("No taps configured.");
+        println!("{}", t("taps.empty"));
         return;
     }
     
-    println!("Configured taps ({}):\n", taps.len());
+    println!("{}\n", tf("taps.header", &[("count", &taps.len())]));
     
     for tap in taps {
         println!("🔗 {}", tap.name);
-        println!("   URL: {}", tap.url);
+        println!("   {}", tf("taps.url", &[("url", &tap.url)]));
+        println!("   {}", tf("taps.trust", &[("trust", &config.trust(&tap.name))]));
         
         if let Some(updated) = tap.updated_at {
-            println!("   Last updated: {}", updated.format("%Y-%m-%d %H:%M:%S"));
+            println!("   {}", tf("taps.updated_at", &[("date", &updated.format("%Y-%m-%d %H:%M:%S"))]));
         }
         
         // Count formulae in tap (recursively scan subdirectories)
         let formula_dir = tap.path.join("Formula");
         if formula_dir.exists() {
             let count = count_formulae_recursive(&formula_dir);
-            println!("   Formulae: {}", count);
+            println!("   {}", tf("taps.formulae", &[("count", &count)]));
         }
         println!();
     }
 }
 
+pub fn show_tap_update_summary(updates: &[crate::core::tap::TapUpdate]) {
+    use crate::core::tap::TapUpdateStatus;
+
+    if updates.is_empty() {
+        println!("{}", t("taps.empty"));
+        return;
+    }
+
+    println!("\n   {:<32} {:<10} {:>12}", t("tap_update.tap"), t("tap_update.status"), t("tap_update.new_formulae"));
+    let mut failed = 0;
+    for update in updates {
+        match &update.status {
+            TapUpdateStatus::Updated { new_formulae } => {
+                println!("   {:<32} {:<10} {:>12}", update.name, t("tap_update.updated"), new_formulae);
+            }
+            TapUpdateStatus::Unchanged => println!("   {:<32} {:<10} {:>12}", update.name, t("tap_update.unchanged"), "-"),
+            TapUpdateStatus::Failed(_) => {
+                println!("   {:<32} {:<10} {:>12}", update.name, t("tap_update.failed"), "-");
+                failed += 1;
+            }
+        }
+    }
+
+    for update in updates {
+        if let TapUpdateStatus::Failed(error) = &update.status {
+            eprintln!("\n❌ {}: {}", update.name, error.trim());
+        }
+    }
+
+    let new_total: usize = updates.iter()
+        .map(|u| match u.status { TapUpdateStatus::Updated { new_formulae } => new_formulae, _ => 0 })
+        .sum();
+    println!("\n   {}", tf("tap_update.summary", &[("checked", &updates.len()), ("failed", &failed), ("new", &new_total)]));
+}
+
 pub fn show_installation_summary(installed: &[String], failed: &[String]) {
     if !installed.is_empty() {
-        println!("\n✅ Successfully installed:");
+        println!("\n✅ {}", t("install.succeeded"));
         for package in installed {
             println!("   • {}", package);
         }
     }
     
     if !failed.is_empty() {
-        println!("\n❌ Failed to install:");
+        println!("\n❌ {}", t("install.failed"));
         for package in failed {
             println!("   • {}", package);
         }
     }
     
-    println!("\nInstallation complete.");
+    println!("\n{}", t("install.complete"));
+}
+
+/// One line on what a command downloaded: how much, how long it took, how fast that
+/// was, and how many artifacts came from a cache rather than the network.
+pub fn transfer_summary(transfers: &crate::metrics::Transfers, elapsed: std::time::Duration) -> String {
+    let seconds = elapsed.as_secs_f64();
+    let time = if seconds < 60.0 {
+        format!("{:.1}s", seconds)
+    } else {
+        format!("{}m {}s", elapsed.as_secs() / 60, elapsed.as_secs() % 60)
+    };
+    let speed = if seconds > 0.0 { (transfers.bytes as f64 / seconds) as u64 } else { 0 };
+    tf("download.summary", &[
+        ("bytes", &format_bytes(transfers.bytes)),
+        ("time", &time),
+        ("speed", &format_bytes(speed)),
+        ("network", &transfers.network),
+        ("cached", &transfers.cache_hits),
+    ])
 }
 
 pub fn show_uninstall_confirmation(packages: &[String]) -> bool {
     use std::io::{self, Write};
     
-    println!("The following packages will be uninstalled:");
+    println!("{}", t("uninstall.confirm"));
     for package in packages {
         println!("  • {}", package);
     }
     
-    print!("\nProceed? [y/N]: ");
+    print!("\n{} {}: ", t("prompt.proceed"), t("prompt.choices"));
     io::stdout().flush().unwrap();
     
     let mut input = String::new();
     io::stdin().read_line(&mut input).unwrap();
     
-    matches!(input.trim().to_lowercase().as_str(), "y" | "yes")
+    crate::ui::i18n::is_yes(&input)
+}
+
+/// Ask whether to install from a source tarball that doesn't match the formula's checksum.
+pub fn confirm_checksum_override(name: &str, url: &str, expected: &str, actual: &str) -> bool {
+    use std::io::{self, Write};
+
+    println!("\n⚠️  {}", tf("checksum.mismatch", &[("name", &name)]));
+    println!("  {:<11} {}", t("checksum.url"), url);
+    println!("  {:<11} {}", t("checksum.formula"), expected);
+    println!("  {:<11} {}", t("checksum.downloaded"), actual);
+    println!("{}", t("checksum.explanation"));
+
+    print!("\n{} {}: ", t("checksum.confirm"), t("prompt.choices"));
+    io::stdout().flush().unwrap();
+
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).unwrap();
+
+    crate::ui::i18n::is_yes(&input)
+}
+
+/// Ask whether to install `name` from untrusted `tap`, showing the URLs it downloads
+/// and their checksums.
+pub fn confirm_untrusted_tap(name: &str, tap: &str, downloads: &[(String, Option<String>)]) -> bool {
+    use std::io::{self, Write};
+
+    println!("\n⚠️  {}", tf("untrusted.warning", &[("name", &name), ("tap", &tap)]));
+    for (url, sha256) in downloads {
+        println!("  {}", url);
+        println!("    sha256: {}", sha256.clone().unwrap_or_else(|| t("untrusted.no_checksum")));
+    }
+    println!("{}", t("untrusted.hint"));
+
+    print!("\n{} {}: ", t("untrusted.confirm"), t("prompt.choices"));
+    io::stdout().flush().unwrap();
+
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).unwrap();
+
+    crate::ui::i18n::is_yes(&input)
+}
+
+/// Ask which of `providers` to install for `capability`; `None` if the user declines
+/// to pick.
+pub fn choose_provider(capability: &str, providers: &[String]) -> Option<usize> {
+    dialoguer::Select::new()
+        .with_prompt(tf("providers.choose", &[("capability", &capability)]))
+        .items(providers)
+        .default(0)
+        .interact_opt()
+        .ok()
+        .flatten()
+}
+
+/// Ask whether to install the build tools a source build is missing.
+pub fn confirm_build_tools(tools: &[crate::core::toolchain::BuildTool]) -> bool {
+    use std::io::{self, Write};
+
+    println!("{}", t("build_tools.missing"));
+    for tool in tools {
+        match tool.formula {
+            Some(formula) => println!("  • {}", tf("build_tools.from", &[("program", &tool.program), ("formula", &formula)])),
+            None => println!("  • {}", tool.program),
+        }
+    }
+
+    print!("\n{} {}: ", t("build_tools.confirm"), t("prompt.choices"));
+    io::stdout().flush().unwrap();
+
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).unwrap();
+
+    crate::ui::i18n::is_yes(&input)
 }
 
 fn format_bytes(bytes: u64) -> String {
@@ -154,63 +490,180 @@ fn format_bytes(bytes: u64) -> String {
 
 pub fn show_update_summary(updated: &[String], skipped: &[String], failed: &[String]) {
     if !updated.is_empty() {
-        println!("\n📦 Updated packages:");
+        println!("\n📦 {}", t("update.updated"));
         for package in updated {
             println!("   ✓ {}", package);
         }
     }
     
     if !skipped.is_empty() {
-        println!("\n⏭️  Already up to date:");
+        println!("\n⏭️  {}", t("update.up_to_date"));
         for package in skipped {
             println!("   • {}", package);
         }
     }
     
     if !failed.is_empty() {
-        println!("\n❌ Failed to update:");
+        println!("\n❌ {}", t("update.failed"));
         for package in failed {
             println!("   • {}", package);
         }
     }
     
-    println!("\nUpdate complete.");
+    println!("\n{}", t("update.complete"));
 }
 
-pub fn show_formula_info(formula: &crate::core::formula::Formula, _args: &crate::cli::commands::info::InfoArgs) {
+pub fn show_formula_info(
+    formula: &crate::core::formula::Formula,
+    dependencies: &[crate::core::package::DependencyStatus],
+    _args: &crate::cli::commands::info::InfoArgs,
+) {
     println!("\n📦 {}", formula.name);
-    println!("Version: {}", formula.version);
+    if formula.revision > 0 {
+        println!("{}", tf("common.version_revision", &[("version", &formula.version), ("revision", &formula.revision)]));
+    } else {
+        println!("{}", tf("common.version", &[("version", &formula.version)]));
+    }
+    if let Some(head) = &formula.head {
+        println!("{}", tf("info.head", &[("url", &head.url)]));
+    }
     
     if let Some(description) = &formula.description {
-        println!("Description: {}", description);
+        println!("{}", tf("common.description", &[("description", description)]));
     }
     
     if let Some(homepage) = &formula.homepage {
-        println!("Homepage: {}", homepage);
+        println!("{}", tf("common.homepage", &[("homepage", homepage)]));
     }
     
     if let Some(license) = &formula.license {
-        println!("License: {}", license);
+        println!("{}", tf("common.license", &[("license", license)]));
     }
     
-    if !formula.dependencies.is_empty() {
-        println!("\nDependencies:");
+    if !dependencies.is_empty() {
+        println!("\n{}", t("info.dependencies_header"));
+        for dep in dependencies {
+            let installed = match &dep.installed_version {
+                Some(version) => tf("info.dependency_installed", &[("version", version)]),
+                None => t("info.dependency_missing"),
+            };
+            let bottle = match dep.bottle {
+                Some(true) => t("info.bottle"),
+                Some(false) => t("info.source_build"),
+                None => t("info.unknown_formula"),
+            };
+            println!("  • {:<24} {:<9} {:<22} {}", dep.name, dep.kind.to_string(), installed, bottle);
+        }
+    } else if !formula.dependencies.is_empty() {
+        println!("\n{}", t("info.dependencies_header"));
         for dep in &formula.dependencies {
-            let dep_type = if dep.build_only { " (build)" } else { "" };
-            println!("  • {}{}", dep.name, dep_type);
+            println!("  • {} ({})", dep.name, dep.kind());
         }
     }
     
+    if !formula.requirements.is_empty() {
+        let requirements: Vec<String> = formula.requirements.iter().map(|r| r.to_string()).collect();
+        println!("{}", tf("info.requires", &[("requirements", &requirements.join(", "))]));
+    }
+
     if !formula.conflicts.is_empty() {
-        println!("\nConflicts with:");
+        println!("\n{}", t("info.conflicts"));
         for conflict in &formula.conflicts {
             println!("  • {}", conflict);
         }
     }
     
+    // Show real paths for #{opt_prefix} and friends rather than the placeholders
+    let paths = crate::core::interpolate::PathContext::detect(formula).ok();
+    let interpolate = |text: &str| match &paths {
+        Some(paths) => paths.interpolate(text),
+        None => text.to_string(),
+    };
+
+    if let Some(service) = &formula.service {
+        println!("\n{}", t("info.service"));
+        if let Some(command) = &service.command {
+            let command = format!("{} {}", interpolate(command), interpolate(&service.args.join(" ")));
+            println!("  {}", tf("info.service_command", &[("command", &command)]));
+        }
+        if service.keep_alive {
+            println!("  {}", t("info.service_keep_alive"));
+        }
+        if let Some(log_path) = &service.log_path {
+            println!("  {}", tf("info.service_log", &[("path", &interpolate(log_path))]));
+        }
+    }
+    
     if let Some(caveats) = &formula.caveats {
-        println!("\n⚠️  Caveats:");
-        println!("{}", caveats);
+        println!("\n⚠️  {}", t("info.caveats"));
+        println!("{}", interpolate(caveats));
+    }
+}
+
+pub fn show_install_plan(plan: &[crate::core::package::PlanEntry]) {
+    println!("\n📋 {}\n", tf("plan.header", &[("count", &plan.len())]));
+    println!("   {:<24} {:<12} {:<14} {:<8} {:>10}",
+        t("plan.name"), t("plan.version"), t("plan.installed"), t("plan.bottle"), t("plan.download"));
+
+    let mut total = 0;
+    let mut pending = 0;
+    for entry in plan {
+        let installed = entry.installed_version.as_deref().unwrap_or("-");
+        let size = match entry.download_size {
+            Some(size) => format_bytes(size),
+            None => "?".to_string(),
+        };
+        println!("   {:<24} {:<12} {:<14} {:<8} {:>10}",
+            entry.name, entry.version, installed, t(if entry.bottle { "common.yes" } else { "common.no" }), size);
+
+        if entry.installed_version.is_none() {
+            pending += 1;
+            total += entry.download_size.unwrap_or(0);
+        }
+    }
+
+    println!("\n   {}", tf("plan.summary", &[("pending", &pending), ("size", &format_bytes(total))]));
+}
+
+pub fn show_checks(checks: &[crate::core::doctor::Check]) {
+    use crate::core::doctor::CheckStatus;
+
+    for check in checks {
+        let icon = match check.status {
+            CheckStatus::Ok => "✅",
+            CheckStatus::Warning => "⚠️ ",
+            CheckStatus::Error => "❌",
+        };
+        let latency = check.latency_ms.map(|ms| format!(" ({} ms)", ms)).unwrap_or_default();
+        println!("{} {:<28} {}{}", icon, check.name, check.message, latency);
+        if let Some(remediation) = &check.remediation {
+            println!("   {:<28} → {}", "", remediation);
+        }
+    }
+}
+
+pub fn show_usage_stats(stats: &crate::core::analytics::UsageStats, limit: usize) {
+    if stats.commands.is_empty() {
+        println!("{}", t("stats.empty"));
+        println!("{}", t("stats.enable_hint"));
+        return;
+    }
+    
+    println!("📊 {}\n", t("stats.commands"));
+    for command in &stats.commands {
+        let average = command.total_duration_ms / command.runs.max(1);
+        println!("   {:<12} {}", command.command, tf("stats.command_line", &[
+            ("runs", &format!("{:>5}", command.runs)),
+            ("failed", &format!("{:>4}", command.failures)),
+            ("seconds", &format!("{:.1}", average as f64 / 1000.0)),
+        ]));
+    }
+    
+    if !stats.formulae.is_empty() {
+        println!("\n🍺 {}\n", t("stats.formulae"));
+        for (formula, count) in stats.formulae.iter().take(limit) {
+            println!("   {:<24} {}", formula, count);
+        }
     }
 }
 
@@ -218,15 +671,13 @@ fn count_formulae_recursive(dir: &std::path::Path) -> usize {
     let mut count = 0;
     
     if let Ok(entries) = std::fs::read_dir(dir) {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                let path = entry.path();
-                if path.is_dir() {
-                    // Recursively count formulae in subdirectories
-                    count += count_formulae_recursive(&path);
-                } else if path.extension().and_then(|s| s.to_str()) == Some("rb") {
-                    count += 1;
-                }
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                // Recursively count formulae in subdirectories
+                count += count_formulae_recursive(&path);
+            } else if path.extension().and_then(|s| s.to_str()) == Some("rb") {
+                count += 1;
             }
         }
     }
@@ -0,0 +1,260 @@
+//! Message catalog for user-facing text. Every message has a key and an English
+//! template with `{name}` placeholders; a translation is a TOML file of the same keys
+//! (`de.toml`, `pt_BR.toml`) in a locale directory, so a distribution can ship a
+//! translated CLI without patching the source. Keys a translation lacks fall back to
+//! English.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+/// The `[ui]` section of the config file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UiConfig {
+    /// Language to show messages in (`de`, `pt_BR`). Defaults to the `LC_ALL`,
+    /// `LC_MESSAGES` or `LANG` environment variable
+    pub locale: Option<String>,
+}
+
+/// The English messages, which double as the fallback for every locale.
+pub const ENGLISH: &[(&str, &str)] = &[
+    ("search.found", "Found {count} package(s):"),
+    ("search.formulae", "Formulae"),
+    ("search.casks", "Casks"),
+    ("common.from", "From: {source}"),
+    ("common.version", "Version: {version}"),
+    ("common.version_revision", "Version: {version} (revision {revision})"),
+    ("common.description", "Description: {description}"),
+    ("common.homepage", "Homepage: {homepage}"),
+    ("common.license", "License: {license}"),
+    ("common.size", "Size: {size}"),
+    ("common.yes", "yes"),
+    ("common.no", "no"),
+    ("info.dependencies", "Dependencies: {dependencies}"),
+    ("info.installed_to", "Installed to: {path}"),
+    ("info.installed_header", "Installed:"),
+    ("info.installed_on", "Installed on: {date}"),
+    ("info.on_request", "Installed on request"),
+    ("info.as_dependency", "Installed as a dependency"),
+    ("info.linked", "Linked: {linked}"),
+    ("info.pinned", "Pinned: upgrade leaves it at this version"),
+    ("info.checksum_override", "Checksum override: accepted {accepted} in place of {expected} on {date}"),
+    ("info.head", "HEAD: {url}"),
+    ("info.dependencies_header", "Dependencies:"),
+    ("info.dependency_installed", "✓ installed {version}"),
+    ("info.dependency_missing", "✗ not installed"),
+    ("info.bottle", "bottle"),
+    ("info.source_build", "source build"),
+    ("info.unknown_formula", "unknown formula"),
+    ("info.requires", "Requires: {requirements}"),
+    ("info.conflicts", "Conflicts with:"),
+    ("info.service", "Service:"),
+    ("info.service_command", "Command: {command}"),
+    ("info.service_keep_alive", "Keep alive: yes"),
+    ("info.service_log", "Log: {path}"),
+    ("info.caveats", "Caveats:"),
+    ("list.empty", "No packages installed."),
+    ("list.header", "Installed packages ({count}):"),
+    ("list.name", "Name"),
+    ("list.version", "Version"),
+    ("list.size", "Size"),
+    ("list.tap", "Tap"),
+    ("list.pinned", "Pinned"),
+    ("list.outdated", "Outdated"),
+    ("casks.empty", "No casks installed."),
+    ("casks.header", "Installed casks ({count}):"),
+    ("casks.installed_at", "Installed: {date}"),
+    ("cask.name", "Name: {names}"),
+    ("cask.auto_updates", "Auto-updates: yes"),
+    ("cask.not_installed", "Not installed"),
+    ("cask.installed", "Installed: {version} on {date}"),
+    ("cask.artifacts", "Artifacts:"),
+    ("cask.receipt", "receipt {id}"),
+    ("taps.empty", "No taps configured."),
+    ("taps.header", "Configured taps ({count}):"),
+    ("taps.url", "URL: {url}"),
+    ("taps.updated_at", "Last updated: {date}"),
+    ("taps.formulae", "Formulae: {count}"),
+    ("taps.trust", "Trust: {trust}"),
+    ("tap_update.tap", "Tap"),
+    ("tap_update.status", "Status"),
+    ("tap_update.new_formulae", "New formulae"),
+    ("tap_update.updated", "updated"),
+    ("tap_update.unchanged", "unchanged"),
+    ("tap_update.failed", "failed"),
+    ("tap_update.summary", "{checked} tap(s) checked, {failed} failed, {new} new formula(e)"),
+    ("install.succeeded", "Successfully installed:"),
+    ("install.failed", "Failed to install:"),
+    ("install.complete", "Installation complete."),
+    ("uninstall.confirm", "The following packages will be uninstalled:"),
+    ("prompt.proceed", "Proceed?"),
+    ("prompt.choices", "[y/N]"),
+    ("prompt.yes_answers", "y,yes"),
+    ("checksum.mismatch", "The source of {name} doesn't match its formula's checksum:"),
+    ("checksum.url", "URL:"),
+    ("checksum.formula", "Formula:"),
+    ("checksum.downloaded", "Downloaded:"),
+    ("checksum.explanation", "This happens when upstream re-rolls a release, but can also mean the download was tampered with."),
+    ("checksum.confirm", "Install it anyway and record the override?"),
+    ("untrusted.warning", "{name} comes from {tap}, which is not a trusted tap. It will download:"),
+    ("untrusted.no_checksum", "no checksum"),
+    ("untrusted.hint", "Trust the tap with a [taps.trust] entry in the config file to stop being asked."),
+    ("untrusted.confirm", "Install it anyway?"),
+    ("providers.choose", "Nothing providing {capability} is installed; install which?"),
+    ("build_tools.missing", "Building from source needs tools that aren't installed:"),
+    ("build_tools.from", "{program} (from {formula})"),
+    ("build_tools.confirm", "Install them with nitro first?"),
+    ("update.updated", "Updated packages:"),
+    ("update.up_to_date", "Already up to date:"),
+    ("update.failed", "Failed to update:"),
+    ("update.complete", "Update complete."),
+    ("plan.header", "Install plan ({count} formulae):"),
+    ("plan.name", "Name"),
+    ("plan.version", "Version"),
+    ("plan.installed", "Installed"),
+    ("plan.bottle", "Bottle"),
+    ("plan.download", "Download"),
+    ("download.summary", "Downloaded {bytes} in {time} ({speed}/s): {network} from the network, {cached} from the cache"),
+    ("plan.summary", "{pending} to install, {size} to download"),
+    ("stats.empty", "No usage recorded yet."),
+    ("stats.enable_hint", "Enable local statistics with: nitro analytics local"),
+    ("stats.commands", "Command usage:"),
+    ("stats.command_line", "{runs} runs  {failed} failed  avg {seconds}s"),
+    ("stats.formulae", "Most used formulae:"),
+    ("error.prefix", "Error"),
+    ("error.package_not_found", "Package not found: {detail}"),
+    ("error.formula_parse", "Formula parse error: {detail}"),
+    ("error.dependency_resolution", "Dependency resolution failed: {detail}"),
+    ("error.installation_failed", "Installation failed: {detail}"),
+    ("error.download_failed", "Download failed: {detail}"),
+    ("error.cache", "Cache error: {detail}"),
+    ("error.tap", "Tap error: {detail}"),
+    ("error.git", "Git error: {detail}"),
+    ("error.search", "Search error: {detail}"),
+    ("error.insufficient_space", "Insufficient disk space: {detail}"),
+    ("error.checksum_mismatch", "Checksum mismatch: expected {expected}, got {actual}"),
+    ("error.incompatible_bottle", "Incompatible bottle: {detail}"),
+    ("error.blocked_by_policy", "Blocked by policy: {detail}"),
+    ("error.link_conflict", "Link conflict: {detail}"),
+    ("error.untrusted_tap", "Untrusted tap: {detail}"),
+    ("error.interrupted", "Interrupted"),
+    ("error.timed_out", "Timed out: {detail}"),
+    ("error.partial_failure", "{failed} of {total} failed"),
+    ("error.io", "IO error: {detail}"),
+    ("error.http", "HTTP error: {detail}"),
+    ("error.json", "JSON error: {detail}"),
+    ("error.database", "Database error: {detail}"),
+    ("error.tantivy", "Tantivy error: {detail}"),
+    ("error.general", "General error: {detail}"),
+    ("error.other", "Other error: {detail}"),
+];
+
+/// The messages of one locale.
+#[derive(Debug, Clone, Default)]
+pub struct Catalog {
+    messages: HashMap<String, String>,
+}
+
+impl Catalog {
+    /// The catalog for `locale` from the first locale directory that has it: the
+    /// most specific match (`pt_BR.toml`) before the language alone (`pt.toml`).
+    /// English, `C` and `POSIX`, or a locale without a file, give an empty catalog.
+    pub fn load(locale: &str) -> Self {
+        let candidates = locale_candidates(locale);
+        for dir in locale_dirs() {
+            for candidate in &candidates {
+                let path = dir.join(format!("{}.toml", candidate));
+                let Ok(data) = std::fs::read_to_string(&path) else {
+                    continue;
+                };
+                match Self::parse(&data) {
+                    Ok(catalog) => return catalog,
+                    Err(e) => tracing::warn!("Ignoring invalid message catalog {}: {}", path.display(), e),
+                }
+            }
+        }
+        Self::default()
+    }
+
+    /// Parse a catalog file: a table of message keys to templates.
+    pub fn parse(data: &str) -> Result<Self, toml::de::Error> {
+        Ok(Self { messages: toml::from_str(data)? })
+    }
+
+    /// The template for `key`, falling back to English and then to the key itself.
+    pub fn template<'a>(&'a self, key: &'a str) -> &'a str {
+        self.messages.get(key).map(String::as_str)
+            .or_else(|| ENGLISH.iter().find(|(k, _)| *k == key).map(|(_, v)| *v))
+            .unwrap_or(key)
+    }
+
+    /// The message for `key` with its `{name}` placeholders filled from `args`.
+    pub fn message(&self, key: &str, args: &[(&str, &dyn Display)]) -> String {
+        let mut text = self.template(key).to_string();
+        for (name, value) in args {
+            text = text.replace(&format!("{{{}}}", name), &value.to_string());
+        }
+        text
+    }
+}
+
+/// The process's locale: the config file's `ui.locale`, or the usual environment
+/// variables. `NITRO_LOCALE` overrides both.
+pub fn locale() -> String {
+    let configured = || crate::core::config::Config::load().ok().and_then(|c| c.ui.locale);
+    std::env::var("NITRO_LOCALE").ok()
+        .or_else(configured)
+        .or_else(|| ["LC_ALL", "LC_MESSAGES", "LANG"].iter().find_map(|var| std::env::var(var).ok()))
+        .filter(|locale| !locale.is_empty())
+        .unwrap_or_else(|| "en".to_string())
+}
+
+/// `de_DE.UTF-8@euro` → `["de_DE", "de"]`; nothing for English and the C locale.
+pub fn locale_candidates(locale: &str) -> Vec<String> {
+    let base = locale.split(['.', '@']).next().unwrap_or_default().replace('-', "_");
+    let language = base.split('_').next().unwrap_or_default().to_string();
+    if base.is_empty() || matches!(language.as_str(), "en" | "C" | "POSIX") {
+        return Vec::new();
+    }
+    let mut candidates = vec![base.clone()];
+    if language != base {
+        candidates.push(language);
+    }
+    candidates
+}
+
+/// Where translations are looked for: `NITRO_LOCALE_DIR`, then `share/nitro/locales`
+/// next to the executable's prefix, then the system-wide directory.
+fn locale_dirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = std::env::var_os("NITRO_LOCALE_DIR").map(PathBuf::from).into_iter().collect();
+    if let Some(prefix) = std::env::current_exe().ok().and_then(|exe| Some(exe.parent()?.parent()?.to_path_buf())) {
+        dirs.push(prefix.join("share/nitro/locales"));
+    }
+    dirs.push(PathBuf::from("/usr/share/nitro/locales"));
+    dirs
+}
+
+/// The catalog for this process's locale, loaded on first use.
+pub fn catalog() -> &'static Catalog {
+    static CATALOG: OnceLock<Catalog> = OnceLock::new();
+    CATALOG.get_or_init(|| Catalog::load(&locale()))
+}
+
+/// The message for `key` in the user's language.
+pub fn t(key: &str) -> String {
+    catalog().message(key, &[])
+}
+
+/// The message for `key` in the user's language, with placeholders filled in.
+pub fn tf(key: &str, args: &[(&str, &dyn Display)]) -> String {
+    catalog().message(key, args)
+}
+
+/// Whether `answer` to a `[y/N]` prompt means yes. English answers always count.
+pub fn is_yes(answer: &str) -> bool {
+    let answer = answer.trim().to_lowercase();
+    t("prompt.yes_answers").split(',').chain(["y", "yes"]).any(|yes| yes.trim().to_lowercase() == answer)
+}
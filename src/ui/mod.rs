@@ -1,2 +1,28 @@
 pub mod progress;
-pub mod display;
\ No newline at end of file
+pub mod display;
+pub mod i18n;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Print only errors and the data a command was asked for (`--quiet`).
+pub fn set_quiet() {
+    QUIET.store(true, Ordering::SeqCst);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::SeqCst)
+}
+
+/// Open `url` with `$BROWSER`, or the platform's default handler.
+pub fn open_url(url: &str) -> anyhow::Result<()> {
+    let opener = std::env::var("BROWSER").ok().filter(|b| !b.is_empty())
+        .unwrap_or_else(|| if cfg!(target_os = "macos") { "open" } else { "xdg-open" }.to_string());
+    let status = std::process::Command::new(&opener).arg(url).status()
+        .map_err(|e| crate::core::NitroError::Other(format!("Could not run {} to open {}: {}", opener, url, e)))?;
+    if !status.success() {
+        return Err(crate::core::NitroError::Other(format!("{} could not open {}", opener, url)).into());
+    }
+    Ok(())
+}
@@ -1,9 +1,11 @@
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::Mutex;
 
 use crate::core::NitroError;
 
+#[derive(Default, Clone)]
 pub struct ProgressReporter {
     multi: Arc<Mutex<MultiProgress>>,
     bars: Arc<Mutex<std::collections::HashMap<String, ProgressBar>>>,
@@ -93,6 +95,19 @@ impl ProgressReporter {
     }
 }
 
+impl Drop for ProgressReporter {
+    fn drop(&mut self) {
+        // On Ctrl-C the spawned finish tasks may never run; clear bars synchronously
+        if crate::core::interrupt::is_interrupted() {
+            if let Ok(mut bars) = self.bars.try_lock() {
+                for (_, pb) in bars.drain() {
+                    pb.finish_and_clear();
+                }
+            }
+        }
+    }
+}
+
 pub struct DownloadProgress {
     pb: ProgressBar,
 }
@@ -102,7 +117,7 @@ impl DownloadProgress {
         let pb = ProgressBar::new(total_size);
         pb.set_style(
             ProgressStyle::default_bar()
-                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta}) {msg}")
+                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta}) {msg}")
                 .expect("Failed to set progress style")
                 .progress_chars("#>-"),
         );
@@ -149,4 +164,27 @@ impl DependencyProgress {
     pub fn finish(&self) {
         self.pb.finish_with_message("All dependencies resolved");
     }
-}
\ No newline at end of file
+}
+
+/// Times the downloads of one command, to sum them up once it's done.
+pub struct TransferSummary {
+    started: Instant,
+    before: crate::metrics::Transfers,
+}
+
+impl TransferSummary {
+    pub fn start() -> Self {
+        Self {
+            started: Instant::now(),
+            before: crate::metrics::global().transfers(),
+        }
+    }
+
+    /// Print what was downloaded since `start`, if it was more than one artifact.
+    pub fn finish(&self) {
+        let transfers = crate::metrics::global().transfers().since(&self.before);
+        if transfers.downloads() > 1 && !crate::ui::is_quiet() {
+            println!("\n{}", crate::ui::display::transfer_summary(&transfers, self.started.elapsed()));
+        }
+    }
+}
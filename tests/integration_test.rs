@@ -49,12 +49,14 @@ fn test_dependency_resolver() {
                 version: None,
                 build_only: false,
                 optional: false,
+                test_only: false,
             },
             Dependency {
                 name: "dep2".to_string(),
                 version: None,
                 build_only: true,
                 optional: false,
+                test_only: false,
             },
         ],
         build_dependencies: vec![],
@@ -64,6 +66,16 @@ fn test_dependency_resolver() {
         test_script: None,
         caveats: None,
         binary_packages: vec![],
+        service: None,
+        tap: None,
+        revision: 0,
+        version_scheme: 0,
+        head: None,
+        path: None,
+        source_hash: None,
+        resources: vec![],
+        requirements: vec![],
+        shell_rc: vec![],
     };
     
     // This would need FormulaManager to be mockable for full testing
@@ -83,6 +95,7 @@ fn test_search_result_structure() {
         tap: "homebrew/core".to_string(),
         formula_path: PathBuf::from("/path/to/formula.rb"),
         score: 1.0,
+        cask: false,
     };
     
     assert_eq!(result.name, "wget");
@@ -109,4 +122,4359 @@ fn test_error_types() {
     
     let err = NitroError::FormulaParse("invalid syntax".to_string());
     assert_eq!(err.to_string(), "Formula parse error: invalid syntax");
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_config_analytics_roundtrip() {
+    use nitro::core::analytics::AnalyticsMode;
+    use nitro::core::config::Config;
+
+    let config: Config = toml::from_str("[analytics]\nmode = \"local\"\n").unwrap();
+    assert_eq!(config.analytics.mode, AnalyticsMode::Local);
+    assert!(config.analytics.endpoint.is_none());
+
+    let serialized = toml::to_string_pretty(&config).unwrap();
+    let reparsed: Config = toml::from_str(&serialized).unwrap();
+    assert_eq!(reparsed.analytics.mode, AnalyticsMode::Local);
+
+    // Analytics default to off when not configured
+    let empty: Config = toml::from_str("").unwrap();
+    assert_eq!(empty.analytics.mode, AnalyticsMode::Off);
+}
+
+#[test]
+fn test_formula_content_hash() {
+    use nitro::core::formula::content_hash;
+
+    let original = content_hash(b"class Foo < Formula\nend\n");
+    assert_eq!(original, content_hash(b"class Foo < Formula\nend\n"));
+    assert_ne!(original, content_hash(b"class Foo < Formula\n  revision 1\nend\n"));
+    assert_eq!(original.len(), 64);
+}
+
+#[test]
+fn test_memory_cache_evicts_least_recently_used() {
+    use nitro::cache::MemoryCache;
+
+    let cache = MemoryCache::new(2);
+    cache.insert("a", 1);
+    cache.insert("b", 2);
+    assert_eq!(cache.get("a"), Some(1));
+
+    // "b" is now the least recently used entry
+    cache.insert("c", 3);
+    assert_eq!(cache.get("b"), None);
+    assert_eq!(cache.get("a"), Some(1));
+    assert_eq!(cache.get("c"), Some(3));
+    assert_eq!(cache.len(), 2);
+}
+
+#[test]
+fn test_install_state_phases() {
+    use nitro::core::install_state::{InstallPhase, InstallStateStore};
+    use std::path::PathBuf;
+
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let store = InstallStateStore::open(&db).unwrap();
+    let formula = Formula {
+        name: "wget".to_string(),
+        version: "1.24.5".to_string(),
+        ..Default::default()
+    };
+
+    store.begin(&formula, PathBuf::from("/tmp/Cellar/wget/1.24.5"), false).unwrap();
+    store.advance("wget", InstallPhase::Fetched).unwrap();
+    store.advance("wget", InstallPhase::Staged).unwrap();
+
+    let pending = store.pending().unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].phase, InstallPhase::Staged);
+    assert!(pending[0].phase > InstallPhase::Verified);
+
+    store.complete("wget").unwrap();
+    assert!(store.pending().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_metadata_conditional_fetch() {
+    use nitro::download::metadata::MetadataFetcher;
+    use nitro::download::{DownloadConfig, Downloader};
+
+    let mut server = mockito::Server::new_async().await;
+    let url = format!("{}/api/formula.json", server.url());
+
+    let first = server.mock("GET", "/api/formula.json")
+        .with_status(200)
+        .with_header("etag", "\"v1\"")
+        .with_body("[]")
+        .create_async()
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let downloader = Downloader::with_config(DownloadConfig::default()).unwrap();
+    let fetcher = MetadataFetcher::with_dir(downloader, dir.path().to_path_buf()).unwrap();
+
+    let fetched = fetcher.fetch(&url).await.unwrap();
+    assert!(!fetched.from_cache);
+    assert_eq!(std::fs::read_to_string(&fetched.path).unwrap(), "[]");
+    first.assert_async().await;
+
+    let second = server.mock("GET", "/api/formula.json")
+        .match_header("if-none-match", "\"v1\"")
+        .with_status(304)
+        .create_async()
+        .await;
+
+    let fetched = fetcher.fetch(&url).await.unwrap();
+    assert!(fetched.from_cache);
+    assert!(fetched.refresh_error.is_none());
+    assert_eq!(std::fs::read_to_string(&fetched.path).unwrap(), "[]");
+    second.assert_async().await;
+
+    // An unreachable server leaves the cached copy usable, but says it wasn't refreshed
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/api/formula.json", listener.local_addr().unwrap());
+    let serve = std::thread::spawn(move || {
+        use std::io::{Read, Write};
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = [0u8; 1024];
+        let _ = stream.read(&mut request).unwrap();
+        stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\n[]").unwrap();
+    });
+    assert!(fetcher.fetch(&url).await.unwrap().refresh_error.is_none());
+    serve.join().unwrap();
+    let fetched = fetcher.fetch(&url).await.unwrap();
+    assert!(fetched.from_cache);
+    assert!(fetched.refresh_error.is_some());
+    assert_eq!(std::fs::read_to_string(&fetched.path).unwrap(), "[]");
+}
+
+#[tokio::test]
+async fn test_file_url_download() {
+    use nitro::download::{DownloadConfig, Downloader};
+
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source.tar.gz");
+    std::fs::write(&source, b"archive").unwrap();
+    let dest = dir.path().join("out/dest.tar.gz");
+
+    let downloader = Downloader::with_config(DownloadConfig::default()).unwrap();
+    let url = format!("file://{}", source.display());
+    downloader.download_file(&url, &dest).await.unwrap();
+    assert_eq!(std::fs::read(&dest).unwrap(), b"archive");
+
+    assert!(downloader.download_file("ftp://example.com/x.tar.gz", &dest).await.is_err());
+}
+
+#[test]
+fn test_s3_signing_key() {
+    use nitro::download::s3::signing_key;
+
+    // Example from the AWS Signature Version 4 documentation
+    let key = signing_key("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY", "20120215", "us-east-1", "iam");
+    assert_eq!(
+        hex::encode(key),
+        "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+    );
+}
+
+#[tokio::test]
+async fn test_error_page_falls_back_to_mirror() {
+    use nitro::download::{DownloadConfig, Downloader};
+
+    let mut server = mockito::Server::new_async().await;
+    let primary = server.mock("GET", "/pkg-1.0.tar.gz")
+        .with_status(200)
+        .with_header("content-type", "application/octet-stream")
+        .with_body("<!DOCTYPE html><html><head><title>Access Denied</title></head></html>")
+        .expect(2)
+        .create_async()
+        .await;
+    let mirror = server.mock("GET", "/mirror/pkg-1.0.tar.gz")
+        .with_status(200)
+        .with_body("archive")
+        .create_async()
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let dest = dir.path().join("pkg.tar.gz");
+    let downloader = Downloader::with_config(DownloadConfig::default()).unwrap();
+
+    let err = downloader.download_file(&format!("{}/pkg-1.0.tar.gz", server.url()), &dest)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("Access Denied"));
+    assert!(!dest.exists());
+
+    let mirror_url = format!("{}/mirror/pkg-1.0.tar.gz", server.url());
+    downloader.download_with_mirrors(&format!("{}/pkg-1.0.tar.gz", server.url()), &[&mirror_url], &dest)
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(&dest).unwrap(), b"archive");
+    primary.assert_async().await;
+    mirror.assert_async().await;
+}
+
+#[test]
+fn test_disk_space_guard() {
+    use nitro::core::disk::{available_space, ensure_space, SpaceRequirement};
+
+    let dir = tempfile::tempdir().unwrap();
+    assert!(ensure_space(&[SpaceRequirement::new(dir.path(), 0)]).is_ok());
+
+    // Only meaningful where the volume can be inspected
+    if let Some(available) = available_space(dir.path()) {
+        let half = available / 2 + 1;
+        let requirements = [
+            SpaceRequirement::new(dir.path(), half),
+            SpaceRequirement::new(dir.path().join("not/created/yet"), half),
+        ];
+        assert!(ensure_space(&requirements).is_err());
+    }
+}
+
+#[test]
+fn test_formula_service_block() {
+    let ruby_content = r#"
+class Redis < Formula
+  desc "Persistent key-value database"
+  url "https://download.redis.io/releases/redis-7.2.4.tar.gz"
+  sha256 "abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890"
+
+  service do
+    run [opt_bin/"redis-server", etc/"redis.conf"]
+    keep_alive true
+    working_dir var
+    log_path var/"log/redis.log"
+    error_log_path var/"log/redis.log"
+    environment_variables PATH: std_service_path_env, LANG: "C"
+  end
+end
+"#;
+
+    let formula = FormulaParser::new().parse_content(ruby_content).unwrap();
+    let service = formula.service.expect("service block should be parsed");
+
+    assert_eq!(service.command.as_deref(), Some("#{opt_bin}/redis-server"));
+    assert_eq!(service.args, vec!["#{etc}/redis.conf"]);
+    assert!(service.keep_alive);
+    assert_eq!(service.working_dir.as_deref(), Some("#{var}"));
+    assert_eq!(service.log_path.as_deref(), Some("#{var}/log/redis.log"));
+    assert_eq!(service.env.get("LANG").map(String::as_str), Some("C"));
+    assert_eq!(service.env.get("PATH").map(String::as_str), Some("#{std_service_path_env}"));
+}
+
+#[test]
+fn test_brew_translation() {
+    use nitro::cli::commands::brew::translate;
+
+    let args = |s: &str| s.split_whitespace().map(String::from).collect::<Vec<_>>();
+
+    assert_eq!(translate(&args("install -s wget jq")).unwrap(), args("install wget jq --build-from-source"));
+    assert_eq!(translate(&args("rm --force wget")).unwrap(), args("uninstall wget --force"));
+    assert_eq!(translate(&args("info --json=v2 wget")).unwrap(), args("info wget --json"));
+    assert_eq!(translate(&args("tap")).unwrap(), args("tap list"));
+    assert_eq!(translate(&args("tap user/repo")).unwrap(), args("tap add user/repo"));
+    assert_eq!(translate(&args("update")).unwrap(), args("update"));
+    assert_eq!(translate(&args("upgrade")).unwrap(), args("upgrade"));
+    assert_eq!(translate(&args("upgrade -n wget")).unwrap(), args("upgrade wget --dry-run"));
+    assert_eq!(translate(&args("outdated --json=v2 -q")).unwrap(), args("outdated --json --quiet"));
+    assert_eq!(translate(&args("install --keep-tmp wget")).unwrap(), args("install wget --keep-tmp"));
+    assert_eq!(translate(&args("install --interactive wget")).unwrap(), args("debug-build --interactive wget"));
+    assert!(translate(&args("bundle")).is_err());
+}
+
+#[tokio::test]
+async fn test_github_client_retries_rate_limit() {
+    use nitro::download::github::{GitHubClient, GitHubConfig};
+    use nitro::download::{DownloadConfig, Downloader};
+
+    let mut server = mockito::Server::new_async().await;
+    let limited = server.mock("GET", "/repos/Homebrew/brew/releases/latest")
+        .match_header("authorization", "Bearer secret")
+        .with_status(429)
+        .with_header("retry-after", "0")
+        .create_async()
+        .await;
+
+    let downloader = Downloader::with_config(DownloadConfig::default()).unwrap();
+    let client = GitHubClient::with_config(downloader, GitHubConfig {
+        token: Some("secret".to_string()),
+        api_url: Some(server.url()),
+    });
+    assert!(client.is_authenticated());
+
+    let ok = server.mock("GET", "/repos/Homebrew/brew/releases/latest")
+        .match_header("authorization", "Bearer secret")
+        .with_status(200)
+        .with_header("x-ratelimit-remaining", "4999")
+        .with_header("x-ratelimit-reset", "1700000000")
+        .with_body(r#"{"tag_name":"4.3.0","html_url":"https://github.com/Homebrew/brew/releases/tag/4.3.0"}"#)
+        .create_async()
+        .await;
+
+    let release = client.latest_release("Homebrew", "brew").await.unwrap();
+    assert_eq!(release.tag_name, "4.3.0");
+    assert_eq!(client.rate_limit().remaining, Some(4999));
+    limited.assert_async().await;
+    ok.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_github_blob_url_follows_renamed_repository() {
+    use nitro::core::tap::github_blob_url;
+    use nitro::download::github::{GitHubClient, GitHubConfig};
+    use nitro::download::{DownloadConfig, Downloader};
+
+    let mut server = mockito::Server::new_async().await;
+    let renamed = server.mock("GET", "/repos/oldcorp/homebrew-tools")
+        .with_status(200)
+        .with_body(r#"{"full_name":"newcorp/homebrew-tools","description":null,"default_branch":"main"}"#)
+        .expect(2)
+        .create_async()
+        .await;
+    server.mock("GET", "/repos/gone/homebrew-tools").with_status(404).create_async().await;
+
+    let downloader = Downloader::with_config(DownloadConfig::default()).unwrap();
+    let client = GitHubClient::with_config(downloader, GitHubConfig { token: None, api_url: Some(server.url()) });
+    let path = std::path::Path::new("Formula/tool.rb");
+
+    assert_eq!(
+        github_blob_url(Some(&client), "oldcorp/homebrew-tools", Some("abc123".into()), path).await,
+        "https://github.com/newcorp/homebrew-tools/blob/abc123/Formula/tool.rb"
+    );
+    // Without a commit the default branch is linked; a failed lookup keeps the name as given
+    assert_eq!(
+        github_blob_url(Some(&client), "oldcorp/homebrew-tools", None, path).await,
+        "https://github.com/newcorp/homebrew-tools/blob/main/Formula/tool.rb"
+    );
+    assert_eq!(
+        github_blob_url(Some(&client), "gone/homebrew-tools", None, path).await,
+        "https://github.com/gone/homebrew-tools/blob/HEAD/Formula/tool.rb"
+    );
+    assert_eq!(
+        github_blob_url(None, "oldcorp/homebrew-tools", None, path).await,
+        "https://github.com/oldcorp/homebrew-tools/blob/HEAD/Formula/tool.rb"
+    );
+    renamed.assert_async().await;
+}
+
+#[test]
+fn test_dependency_kinds() {
+    use nitro::core::formula::DependencyKind;
+
+    let ruby_content = r#"
+class Foo < Formula
+  url "https://example.com/foo-1.0.tar.gz"
+  sha256 "abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890"
+
+  depends_on "cmake" => :build
+  depends_on "openssl@3"
+  depends_on "bats-core" => :test
+  depends_on "gettext" => :optional
+  depends_on "pkgconf" => [:build, :test]
+end
+"#;
+
+    let formula = FormulaParser::new().parse_content(ruby_content).unwrap();
+    let kinds: Vec<_> = formula.dependencies.iter().map(|d| (d.name.as_str(), d.kind())).collect();
+    assert_eq!(kinds, vec![
+        ("cmake", DependencyKind::Build),
+        ("openssl@3", DependencyKind::Runtime),
+        ("bats-core", DependencyKind::Test),
+        ("gettext", DependencyKind::Optional),
+        ("pkgconf", DependencyKind::Build),
+    ]);
+}
+
+#[tokio::test]
+async fn test_cask_indexing() {
+    use nitro::cli::commands::search::SearchArgs;
+    use nitro::search::SearchEngine;
+
+    let dir = tempfile::tempdir().unwrap();
+    let casks = dir.path().join("Casks/v");
+    std::fs::create_dir_all(&casks).unwrap();
+    std::fs::write(casks.join("visual-studio-code.rb"), r#"
+cask "visual-studio-code" do
+  version "1.89.1"
+  sha256 :no_check
+
+  url "https://update.code.visualstudio.com/#{version}/darwin/stable"
+  name "Microsoft Visual Studio Code"
+  name "VS Code"
+  desc "Open-source code editor"
+  homepage "https://code.visualstudio.com/"
+
+  app "Visual Studio Code.app"
+end
+"#).unwrap();
+
+    let engine = SearchEngine::with_dir(&dir.path().join("index")).await.unwrap();
+    let mut writer = engine.writer().unwrap();
+    engine.index_casks(&mut writer, &dir.path().join("Casks"), "homebrew/cask").unwrap();
+    writer.commit().unwrap();
+    engine.reload().unwrap();
+
+    let args = SearchArgs {
+        query: "code".to_string(),
+        description: true,
+        fuzzy: false,
+        limit: 10,
+        tap: None,
+        cask: true,
+        formula: false,
+        any: false,
+        open: None,
+        info: None,
+        interactive: false,
+    };
+    let results = engine.search("code", &args).await.unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].name, "visual-studio-code");
+    assert_eq!(results[0].version, "1.89.1");
+    assert!(results[0].cask);
+}
+
+#[tokio::test]
+async fn test_search_scoped_to_tap_and_kind() {
+    use nitro::cli::commands::search::SearchArgs;
+    use nitro::search::SearchEngine;
+
+    let dir = tempfile::tempdir().unwrap();
+    let engine = SearchEngine::with_dir(dir.path()).await.unwrap();
+    let mut writer = engine.writer().unwrap();
+    // Far more matches in the other tap than the limit, so only a filter in the query finds ours
+    for i in 0..30 {
+        let formula = Formula { name: format!("widget{}", i), description: Some("widget tool".to_string()), version: "1.0".to_string(), ..Default::default() };
+        engine.update_formula(&mut writer, &formula, "homebrew/core", std::path::Path::new(&format!("/core/widget{}.rb", i))).unwrap();
+    }
+    for i in 0..3 {
+        let formula = Formula { name: format!("mywidget{}", i), description: Some("a small in-house widget for the team".to_string()), version: "1.0".to_string(), ..Default::default() };
+        engine.update_formula(&mut writer, &formula, "me/tools", std::path::Path::new(&format!("/mine/mywidget{}.rb", i))).unwrap();
+    }
+    writer.commit().unwrap();
+    engine.reload().unwrap();
+
+    let mut args = SearchArgs {
+        query: "widget".to_string(),
+        description: true,
+        fuzzy: false,
+        limit: 2,
+        tap: Some("me/tools".to_string()),
+        cask: false,
+        formula: false,
+        any: false,
+        open: None,
+        info: None,
+        interactive: false,
+    };
+    let results = engine.search("widget", &args).await.unwrap();
+    assert_eq!(results.len(), 2);
+    assert!(results.iter().all(|r| r.tap == "me/tools"));
+
+    args.formula = true;
+    assert_eq!(engine.search("widget", &args).await.unwrap().len(), 2);
+    args.formula = false;
+    args.cask = true;
+    assert!(engine.search("widget", &args).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_search_index_update_and_remove() {
+    use nitro::cli::commands::search::SearchArgs;
+    use nitro::search::SearchEngine;
+
+    let dir = tempfile::tempdir().unwrap();
+    let engine = SearchEngine::with_dir(dir.path()).await.unwrap();
+    let path = std::path::Path::new("/taps/me_tools/Formula/frobnicate.rb");
+    let formula = Formula {
+        name: "frobnicate".to_string(),
+        version: "1.0".to_string(),
+        ..Default::default()
+    };
+
+    let args = SearchArgs {
+        query: "frobnicate".to_string(),
+        description: false,
+        fuzzy: false,
+        limit: 10,
+        tap: None,
+        cask: false,
+        formula: false,
+        any: false,
+        open: None,
+        info: None,
+        interactive: false,
+    };
+
+    let mut writer = engine.writer().unwrap();
+    engine.update_formula(&mut writer, &formula, "me/tools", path).unwrap();
+    engine.update_formula(&mut writer, &Formula { version: "1.1".to_string(), ..formula }, "me/tools", path).unwrap();
+    writer.commit().unwrap();
+    engine.reload().unwrap();
+
+    let results = engine.search("frobnicate", &args).await.unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].version, "1.1");
+
+    engine.remove_path(&mut writer, path);
+    writer.commit().unwrap();
+    engine.reload().unwrap();
+    assert!(engine.search("frobnicate", &args).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_search_index_dependency_graph() {
+    use nitro::search::SearchEngine;
+
+    let dir = tempfile::tempdir().unwrap();
+    let engine = SearchEngine::with_dir(dir.path()).await.unwrap();
+    let parser = FormulaParser::new();
+
+    let sources = [
+        ("curl", "class Curl < Formula\n  url \"https://example.com/curl-8.0.tar.gz\"\n  sha256 \"abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890\"\n  depends_on \"openssl@3\"\n  depends_on \"pkgconf\" => :build\nend\n"),
+        ("openssl@3", "class OpensslAT3 < Formula\n  url \"https://example.com/openssl-3.3.0.tar.gz\"\n  sha256 \"abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890\"\n  depends_on \"ca-certificates\"\nend\n"),
+        ("ca-certificates", "class CaCertificates < Formula\n  url \"https://example.com/ca-certificates-2024.pem\"\n  sha256 \"abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890\"\nend\n"),
+    ];
+
+    let mut writer = engine.writer().unwrap();
+    for (name, content) in sources {
+        let formula = parser.parse_content(content).unwrap();
+        let path = std::path::PathBuf::from(format!("/taps/homebrew_core/Formula/{}.rb", name));
+        engine.update_formula(&mut writer, &formula, "homebrew/core", &path).unwrap();
+    }
+    writer.commit().unwrap();
+    engine.reload().unwrap();
+
+    let curl = engine.lookup("curl").unwrap().unwrap();
+    assert_eq!(curl.dependencies, vec!["openssl@3"]);
+    assert_eq!(curl.build_dependencies, vec!["pkgconf"]);
+
+    let dependents: Vec<_> = engine.dependents("openssl@3").unwrap().into_iter().map(|f| f.name).collect();
+    assert_eq!(dependents, vec!["curl"]);
+
+    let closure: Vec<_> = engine.dependency_closure("curl").unwrap().into_iter().map(|f| f.name).collect();
+    assert_eq!(closure, vec!["curl", "openssl@3", "ca-certificates"]);
+}
+
+#[tokio::test]
+async fn test_parallel_formula_indexing() {
+    use nitro::search::SearchEngine;
+
+    let tap = tempfile::tempdir().unwrap();
+    for (dir, name) in [("a", "ack"), ("a", "aria2"), ("w", "wget"), ("w/nested", "wrk")] {
+        let dir = tap.path().join(dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let class = name.replace(|c: char| !c.is_ascii_alphanumeric(), "");
+        std::fs::write(
+            dir.join(format!("{}.rb", name)),
+            format!("class {} < Formula\n  url \"https://example.com/{}-1.0.tar.gz\"\n  sha256 \"abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890\"\nend\n", class, name),
+        ).unwrap();
+    }
+    std::fs::write(tap.path().join("a/README.md"), "not a formula").unwrap();
+
+    let index_dir = tempfile::tempdir().unwrap();
+    let engine = SearchEngine::with_dir(index_dir.path()).await.unwrap();
+    let mut writer = engine.writer().unwrap();
+    let count = engine.index_formulae(&writer, tap.path(), "test/tap").unwrap();
+    writer.commit().unwrap();
+    engine.reload().unwrap();
+
+    assert_eq!(count, 4);
+    for name in ["ack", "aria2", "wget", "wrk"] {
+        assert!(engine.lookup(name).unwrap().is_some(), "{} should be indexed", name);
+    }
+}
+
+#[tokio::test]
+async fn test_unparsable_query_falls_back_to_literal_search() {
+    use nitro::cli::commands::search::SearchArgs;
+    use nitro::search::{describe_query_error, SearchEngine};
+
+    let dir = tempfile::tempdir().unwrap();
+    let engine = SearchEngine::with_dir(dir.path()).await.unwrap();
+    let parser = FormulaParser::new();
+    let formula = parser.parse_content("class Cxx < Formula\n  desc \"Tools for c++ development\"\n  url \"https://example.com/cxx-1.0.tar.gz\"\n  sha256 \"abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890\"\nend\n").unwrap();
+
+    let mut writer = engine.writer().unwrap();
+    engine.update_formula(&mut writer, &formula, "me/tools", std::path::Path::new("/taps/me/Formula/cxx.rb")).unwrap();
+    writer.commit().unwrap();
+    engine.reload().unwrap();
+
+    let args = SearchArgs {
+        query: String::new(),
+        description: true,
+        fuzzy: false,
+        limit: 10,
+        tap: None,
+        cask: false,
+        formula: false,
+        any: false,
+        open: None,
+        info: None,
+        interactive: false,
+    };
+    for query in ["c++ development", "\"for c++", "(development"] {
+        let results = engine.search(query, &args).await.unwrap();
+        assert_eq!(results.len(), 1, "query {:?}", query);
+        assert_eq!(results[0].name, "cxx");
+    }
+
+    let error = tantivy::query::QueryParserError::SyntaxError("x".to_string());
+    assert!(describe_query_error("\"open ssl", &error).contains("unbalanced quote at position 1"));
+    assert!(describe_query_error("ssl)", &error).contains("unmatched ')' at position 4"));
+    assert!(describe_query_error("c++ lib", &error).contains("'c++' contains query operators"));
+}
+
+#[tokio::test]
+async fn test_multi_word_search() {
+    use nitro::cli::commands::search::SearchArgs;
+    use nitro::search::SearchEngine;
+
+    let dir = tempfile::tempdir().unwrap();
+    let engine = SearchEngine::with_dir(dir.path()).await.unwrap();
+    let parser = FormulaParser::new();
+
+    let mut writer = engine.writer().unwrap();
+    for (name, class, desc) in [
+        ("http-server", "HttpServer", "Simple zero-configuration command-line HTTP server"),
+        ("httpie", "Httpie", "User-friendly cURL replacement, an HTTP client"),
+        ("caddy", "Caddy", "Powerful web server with automatic HTTPS, speaks http"),
+    ] {
+        let content = format!("class {} < Formula\n  desc \"{}\"\n  url \"https://example.com/{}-1.0.tar.gz\"\n  sha256 \"abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890\"\nend\n", class, desc, name);
+        let formula = parser.parse_content(&content).unwrap();
+        let path = std::path::PathBuf::from(format!("/taps/homebrew_core/Formula/{}.rb", name));
+        engine.update_formula(&mut writer, &formula, "homebrew/core", &path).unwrap();
+    }
+    writer.commit().unwrap();
+    engine.reload().unwrap();
+
+    let mut args = SearchArgs {
+        query: "http server".to_string(),
+        description: false,
+        fuzzy: false,
+        limit: 10,
+        tap: None,
+        cask: false,
+        formula: false,
+        any: false,
+        open: None,
+        info: None,
+        interactive: false,
+    };
+    let names = |results: Vec<nitro::search::SearchResult>| results.into_iter().map(|r| r.name).collect::<Vec<_>>();
+
+    // All words must match, and the exact phrase ranks first
+    let results = names(engine.search("http server", &args).await.unwrap());
+    assert_eq!(results, vec!["http-server", "caddy"]);
+
+    args.any = true;
+    let results = names(engine.search("http server", &args).await.unwrap());
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0], "http-server");
+}
+
+#[test]
+fn test_installed_package_match_score() {
+    use nitro::core::package::Package;
+
+    let package = Package {
+        name: "ripgrep".to_string(),
+        version: "14.1.0".to_string(),
+        description: Some("Search tool like grep and The Silver Searcher".to_string()),
+        homepage: None,
+        installed: true,
+        installed_version: Some("14.1.0".to_string()),
+        dependencies: vec![],
+        install_path: None,
+        size: None,
+        tap: None,
+        version_scheme: 0,
+        formula_path: None,
+        formula_hash: None,
+        tap_commit: None,
+        checksum_override: None,
+        installed_on_request: true,
+        pinned: false,
+        installed_at: None,
+        linked: None,
+    };
+
+    assert_eq!(package.match_score("grep"), Some(2));
+    assert_eq!(package.match_score("Silver searcher"), Some(2));
+    assert_eq!(package.match_score("rip SEARCH"), Some(3));
+    assert_eq!(package.match_score("grep json"), None);
+}
+
+#[test]
+fn test_default_tap_configuration() {
+    use nitro::core::tap::TapsConfig;
+
+    let config: TapsConfig = toml::from_str("").unwrap();
+    assert_eq!(config.taps_to_add(), vec!["homebrew/core"]);
+    assert!(config.auto_tap);
+
+    let config: TapsConfig = toml::from_str("api_only = true\ncask = true\ndefault = [\"homebrew/core\", \"me/tools\"]").unwrap();
+    assert_eq!(config.taps_to_add(), vec!["me/tools", "homebrew/cask"]);
+}
+
+#[test]
+fn test_formula_from_api_json() {
+    let entry = serde_json::json!({
+        "name": "wget",
+        "desc": "Internet file retriever",
+        "homepage": "https://www.gnu.org/software/wget/",
+        "license": "GPL-3.0-or-later",
+        "versions": { "stable": "1.24.5" },
+        "urls": { "stable": { "url": "https://ftp.gnu.org/gnu/wget/wget-1.24.5.tar.gz", "checksum": "fa2dc35bab5184ecbc46a9ef83def2aaaa3f4c9f3c97d4bd19dcb07d4da637de" } },
+        "dependencies": ["libidn2", "openssl@3"],
+        "build_dependencies": ["pkgconf"],
+        "test_dependencies": [],
+        "bottle": { "stable": { "files": {
+            "arm64_sonoma": { "url": "https://ghcr.io/v2/homebrew/core/wget/blobs/sha256:aaa", "sha256": "aaa" },
+            "x86_64_linux": { "url": "https://ghcr.io/v2/homebrew/core/wget/blobs/sha256:bbb", "sha256": "bbb" },
+            "all": { "url": "https://example.com", "sha256": "ccc" }
+        } } }
+    });
+
+    let formula = nitro::core::formula::Formula::from_api_json(&entry).unwrap();
+    assert_eq!(formula.name, "wget");
+    assert_eq!(formula.version, "1.24.5");
+    assert_eq!(formula.sources[0].sha256, "fa2dc35bab5184ecbc46a9ef83def2aaaa3f4c9f3c97d4bd19dcb07d4da637de");
+    assert_eq!(formula.dependencies.iter().map(|d| d.name.as_str()).collect::<Vec<_>>(), vec!["libidn2", "openssl@3"]);
+    assert!(formula.build_dependencies[0].build_only);
+    assert_eq!(formula.binary_packages.len(), 2);
+    assert_eq!(formula.tap.as_deref(), Some("homebrew/core"));
+}
+
+#[test]
+fn test_read_only_database_snapshot() {
+    use nitro::core::readonly;
+
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("packages.db");
+    {
+        let db = sled::open(&db_path).unwrap();
+        db.insert("wget", "1.24.5").unwrap();
+        db.flush().unwrap();
+    }
+
+    let snapshot = readonly::open_snapshot(&db_path).unwrap();
+    assert_eq!(snapshot.get("wget").unwrap().as_deref(), Some(&b"1.24.5"[..]));
+    snapshot.insert("curl", "8.0").unwrap();
+    drop(snapshot);
+
+    let db = sled::open(&db_path).unwrap();
+    assert!(db.get("curl").unwrap().is_none());
+    drop(db);
+
+    let missing = readonly::open_snapshot(&dir.path().join("missing.db")).unwrap();
+    assert!(missing.is_empty());
+    assert!(!dir.path().join("missing.db").exists());
+}
+
+#[test]
+fn test_link_dir_and_conflicts() {
+    use nitro::core::installer::{link_conflict, resolve_link_dir, InstallConfig};
+
+    let prefix = tempfile::tempdir().unwrap();
+    assert_eq!(resolve_link_dir(prefix.path(), &InstallConfig::default()), prefix.path().join("bin"));
+
+    // A prefix with brew in it gets a separate link directory
+    std::fs::create_dir_all(prefix.path().join("bin")).unwrap();
+    std::fs::write(prefix.path().join("bin/brew"), "").unwrap();
+    assert!(resolve_link_dir(prefix.path(), &InstallConfig::default()).ends_with(".nitro/bin"));
+
+    let configured = InstallConfig { link_dir: Some("/opt/tools/bin".into()), ..Default::default() };
+    assert_eq!(resolve_link_dir(prefix.path(), &configured), std::path::PathBuf::from("/opt/tools/bin"));
+
+    let bin = prefix.path().join("bin");
+    std::os::unix::fs::symlink("../Cellar/wget/1.24.5/bin/wget", bin.join("wget")).unwrap();
+    assert_eq!(link_conflict(&bin.join("wget"), "wget"), None);
+    assert!(link_conflict(&bin.join("wget"), "wget2").unwrap().starts_with("wget "));
+    assert_eq!(link_conflict(&bin.join("brew"), "brew").as_deref(), Some("an existing file"));
+    assert_eq!(link_conflict(&bin.join("curl"), "curl"), None);
+
+    assert_eq!(nitro::cli::commands::shellenv::path_command("/usr/bin/fish", "/x/bin"), "fish_add_path --global --move --path \"/x/bin\";");
+    assert_eq!(nitro::cli::commands::shellenv::path_command("/bin/zsh", "/x/bin"), "export PATH=\"/x/bin:$PATH\";");
+}
+
+#[test]
+fn test_keg_ownership() {
+    use nitro::core::installer::{keg_owner, write_keg_marker, KegOwner};
+
+    let cellar = tempfile::tempdir().unwrap();
+    let keg = cellar.path().join("wget/1.24.5");
+    assert_eq!(keg_owner(&keg), None);
+
+    std::fs::create_dir_all(&keg).unwrap();
+    assert_eq!(keg_owner(&keg), Some(KegOwner::Unknown));
+
+    std::fs::write(keg.join("INSTALL_RECEIPT.json"), "{}").unwrap();
+    assert_eq!(keg_owner(&keg), Some(KegOwner::Homebrew));
+
+    // Bottles ship a brew receipt too; the marker wins
+    write_keg_marker(&keg).unwrap();
+    assert_eq!(keg_owner(&keg), Some(KegOwner::Nitro));
+}
+
+#[test]
+fn test_formula_revision_and_version_scheme() {
+    let parser = FormulaParser::new();
+    let formula = parser.parse_content(r#"class Libfoo < Formula
+  url "https://example.com/libfoo-1.2.3.tar.gz"
+  sha256 "abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890"
+  revision 2
+  version_scheme 1
+
+  resource "extra" do
+    url "https://example.com/extra-0.1.tar.gz"
+  end
+end
+"#).unwrap();
+
+    assert_eq!(formula.version, "1.2.3");
+    assert_eq!(formula.revision, 2);
+    assert_eq!(formula.version_scheme, 1);
+    assert_eq!(formula.pkg_version(), "1.2.3_2");
+
+    let plain = parser.parse_content("class Bar < Formula\n  url \"https://example.com/bar-2.0.tar.gz\"\n  sha256 \"abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890\"\nend\n").unwrap();
+    assert_eq!(plain.revision, 0);
+    assert_eq!(plain.pkg_version(), "2.0");
+}
+
+#[test]
+fn test_formula_specs() {
+    let parser = FormulaParser::new();
+
+    // Resources, patches and head blocks that come before the formula's own url
+    let formula = parser.parse_content(r#"class Tool < Formula
+  desc "A tool"
+  head do
+    url "https://github.com/owner/tool.git", branch: "main"
+    depends_on "autoconf" => :build
+  end
+
+  resource "helper" do
+    url "https://example.com/helper-0.3.tar.gz"
+    sha256 "1111111111111111111111111111111111111111111111111111111111111111"
+  end
+
+  patch do
+    url "https://example.com/fix.patch"
+    sha256 "2222222222222222222222222222222222222222222222222222222222222222"
+  end
+
+  url "https://example.com/tool-2.5.tar.gz"
+  mirror "https://mirror.example.com/tool-2.5.tar.gz"
+  sha256 "3333333333333333333333333333333333333333333333333333333333333333"
+end
+"#).unwrap();
+
+    assert_eq!(formula.sources.len(), 1);
+    assert_eq!(formula.sources[0].url, "https://example.com/tool-2.5.tar.gz");
+    assert_eq!(formula.sources[0].sha256, "3333333333333333333333333333333333333333333333333333333333333333");
+    assert_eq!(formula.sources[0].mirror.as_deref(), Some("https://mirror.example.com/tool-2.5.tar.gz"));
+    assert_eq!(formula.version, "2.5");
+    // The head block's dependencies are only for building HEAD
+    assert!(formula.build_dependencies.is_empty());
+    let head = formula.head.unwrap();
+    assert_eq!(head.url, "https://github.com/owner/tool.git");
+    assert_eq!(head.dependencies.len(), 1);
+    assert_eq!(head.dependencies[0].name, "autoconf");
+    assert!(head.dependencies[0].build_only);
+
+    // An explicit stable block
+    let formula = parser.parse_content(r#"class Other < Formula
+  stable do
+    url "https://example.com/other-1.1.tar.gz"
+    sha256 "4444444444444444444444444444444444444444444444444444444444444444"
+  end
+  head "https://github.com/owner/other.git"
+end
+"#).unwrap();
+    assert_eq!(formula.sources[0].url, "https://example.com/other-1.1.tar.gz");
+    assert_eq!(formula.version, "1.1");
+    assert_eq!(formula.head.unwrap().url, "https://github.com/owner/other.git");
+}
+
+#[test]
+fn test_placeholder_interpolation() {
+    use nitro::core::interpolate::PathContext;
+
+    let formula = Formula {
+        name: "redis".to_string(),
+        version: "7.2.5".to_string(),
+        revision: 1,
+        ..Default::default()
+    };
+    let paths = PathContext::new("/opt/homebrew", &formula);
+
+    assert_eq!(
+        paths.interpolate("Config: #{etc}/redis.conf, data in #{var}/db/redis"),
+        "Config: /opt/homebrew/etc/redis.conf, data in /opt/homebrew/var/db/redis"
+    );
+    assert_eq!(paths.interpolate("#{opt_bin}/redis-server"), "/opt/homebrew/opt/redis/bin/redis-server");
+    assert_eq!(paths.interpolate("--prefix=#{prefix}"), "--prefix=/opt/homebrew/Cellar/redis/7.2.5_1");
+    assert_eq!(paths.interpolate("#{HOMEBREW_PREFIX}/share/#{name}-#{version}"), "/opt/homebrew/share/redis-7.2.5");
+    assert_eq!(paths.interpolate("#{man1}"), "/opt/homebrew/Cellar/redis/7.2.5_1/share/man/man1");
+    assert_eq!(
+        paths.interpolate("-I#{Formula[\"openssl@3\"].opt_include}"),
+        "-I/opt/homebrew/opt/openssl@3/include"
+    );
+    // Unknown expressions are left alone
+    assert_eq!(paths.interpolate("#{ENV[\"HOME\"]}/x"), "#{ENV[\"HOME\"]}/x");
+}
+
+#[tokio::test]
+async fn test_missing_bottles_query() {
+    use nitro::cli::commands::deps::brewfile_formulae;
+    use nitro::core::formula::{platform_tag, BinaryPackage};
+    use nitro::search::SearchEngine;
+
+    let dir = tempfile::tempdir().unwrap();
+    let engine = SearchEngine::with_dir(dir.path()).await.unwrap();
+
+    let bottle = |platform: &str, arch: &str| BinaryPackage {
+        platform: platform.to_string(),
+        arch: arch.to_string(),
+        url: String::new(),
+        sha256: String::new(),
+    };
+    let dep = |name: &str| Dependency {
+        name: name.to_string(),
+        version: None,
+        build_only: false,
+        optional: false,
+        test_only: false,
+    };
+    let formulae = [
+        Formula { name: "wget".into(), version: "1.24".into(), dependencies: vec![dep("openssl@3"), dep("libidn2")], binary_packages: vec![bottle("darwin", "aarch64"), bottle("linux", "x86_64")], ..Default::default() },
+        Formula { name: "openssl@3".into(), version: "3.3.0".into(), binary_packages: vec![bottle("darwin", "aarch64")], ..Default::default() },
+        Formula { name: "libidn2".into(), version: "2.3.7".into(), ..Default::default() },
+        Formula { name: "jq".into(), version: "1.7".into(), binary_packages: vec![bottle("linux", "x86_64")], ..Default::default() },
+    ];
+
+    let mut writer = engine.writer().unwrap();
+    for formula in &formulae {
+        let path = std::path::PathBuf::from(format!("/taps/homebrew_core/Formula/{}.rb", formula.name));
+        engine.update_formula(&mut writer, formula, "homebrew/core", &path).unwrap();
+    }
+    writer.commit().unwrap();
+    engine.reload().unwrap();
+
+    let roots = brewfile_formulae("tap \"homebrew/core\"\nbrew \"wget\"\nbrew 'homebrew/core/jq', restart_service: true\ncask \"firefox\"\n");
+    assert_eq!(roots, vec!["wget", "jq"]);
+
+    let missing = |platform: &str| -> Vec<String> {
+        engine.missing_bottles(&roots, &platform_tag(platform).unwrap()).unwrap().into_iter().map(|f| f.name).collect()
+    };
+    assert_eq!(missing("linux/x86_64"), vec!["libidn2", "openssl@3"]);
+    assert_eq!(missing("arm64_sonoma"), vec!["jq", "libidn2"]);
+    assert_eq!(platform_tag("windows"), None);
+}
+
+#[test]
+fn test_bottle_selection_for_other_platforms() {
+    use nitro::core::formula::{platform_tag, BinaryPackage};
+    use nitro::core::installer::{bottle_filename, bottle_for};
+
+    let bottle = |platform: &str, arch: &str| BinaryPackage {
+        platform: platform.to_string(),
+        arch: arch.to_string(),
+        url: format!("https://ghcr.io/v2/homebrew/core/wget/blobs/{}-{}", platform, arch),
+        sha256: String::new(),
+    };
+    let formula = Formula {
+        name: "wget".to_string(),
+        version: "1.24.5".to_string(),
+        revision: 1,
+        binary_packages: vec![bottle("darwin", "aarch64"), bottle("linux", "x86_64")],
+        ..Default::default()
+    };
+
+    let darwin = platform_tag("arm64_sonoma").unwrap();
+    assert_eq!(bottle_for(&formula, &darwin).unwrap().url, "https://ghcr.io/v2/homebrew/core/wget/blobs/darwin-aarch64");
+    assert_eq!(bottle_for(&formula, "linux/x86_64").unwrap().arch, "x86_64");
+    assert!(bottle_for(&formula, "linux/aarch64").is_none());
+    assert!(bottle_for(&formula, "linux").is_none());
+
+    assert_eq!(bottle_filename(&formula, "linux/x86_64"), "wget--1.24.5_1.linux_x86_64.bottle.tar.gz");
+}
+
+#[test]
+fn test_count_new_formulae() {
+    use nitro::core::tap::count_new_formulae;
+
+    let added = "Formula/a/ack.rb\nFormula/w/wget.rb\nHomebrewFormula/tool.rb\nlegacy.rb\nCasks/f/firefox.rb\ncmd/update.rb\nREADME.md\n";
+    assert_eq!(count_new_formulae(&added.lines().collect::<Vec<_>>()), 4);
+    assert_eq!(count_new_formulae::<&str>(&[]), 0);
+}
+
+#[tokio::test]
+async fn test_git_error_classification() {
+    use nitro::core::git::{self, classify_error, GitError};
+
+    assert_eq!(
+        classify_error("pull", "error: Your local changes to the following files would be overwritten by merge:\n\tFormula/w/wget.rb\nPlease commit your changes or stash them before you merge.\nAborting\n"),
+        GitError::LocalChanges
+    );
+    assert_eq!(
+        classify_error("pull", "hint: Diverging branches can't be fast-forwarded\nfatal: Not possible to fast-forward, aborting.\n"),
+        GitError::NotFastForward
+    );
+    assert!(matches!(
+        classify_error("clone", "Cloning into 'x'...\nfatal: unable to access 'https://github.com/x/y.git/': Could not resolve host: github.com\n"),
+        GitError::Network(_)
+    ));
+    assert_eq!(
+        classify_error("clone", "remote: Repository not found.\nfatal: repository 'https://github.com/x/homebrew-y.git/' not found\n"),
+        GitError::RepositoryNotFound("fatal: repository 'https://github.com/x/homebrew-y.git/' not found".to_string())
+    );
+
+    let missing = tempfile::tempdir().unwrap().path().join("missing");
+    assert_eq!(git::head(&missing).await, Err(GitError::NotARepository(missing.clone())));
+}
+
+#[tokio::test]
+async fn test_repair_tap_checkout() {
+    use nitro::core::git;
+    use nitro::core::tap::repair_checkout;
+
+    let git = |dir: &std::path::Path, args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com", "-c", "init.defaultBranch=main"])
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap();
+        assert!(status.status.success(), "git {:?}: {}", args, String::from_utf8_lossy(&status.stderr));
+    };
+
+    let root = tempfile::tempdir().unwrap();
+    let upstream = root.path().join("upstream");
+    std::fs::create_dir_all(upstream.join("Formula")).unwrap();
+    git(&upstream, &["init", "--quiet"]);
+    std::fs::write(upstream.join("Formula/wget.rb"), "class Wget < Formula\nend\n").unwrap();
+    git(&upstream, &["add", "."]);
+    git(&upstream, &["commit", "--quiet", "-m", "wget"]);
+
+    let checkout = root.path().join("checkout");
+    git(root.path(), &["clone", "--quiet", upstream.to_str().unwrap(), checkout.to_str().unwrap()]);
+    let backup = root.path().join("backup");
+
+    // A clean checkout is left alone
+    assert!(!repair_checkout(&checkout, &backup).await.unwrap());
+
+    // Local edits, a stray file and a local commit, while upstream moves on
+    std::fs::write(checkout.join("Formula/local.rb"), "class Local < Formula\nend\n").unwrap();
+    git(&checkout, &["add", "."]);
+    git(&checkout, &["commit", "--quiet", "-m", "local formula"]);
+    std::fs::write(checkout.join("Formula/wget.rb"), "class Wget < Formula\n  # edited\nend\n").unwrap();
+    std::fs::write(checkout.join("notes.txt"), "mine").unwrap();
+    std::fs::write(upstream.join("Formula/wget.rb"), "class Wget < Formula\n  # upstream\nend\n").unwrap();
+    git(&upstream, &["commit", "--quiet", "-am", "update wget"]);
+    git(&checkout, &["fetch", "--quiet"]);
+    assert!(git::pull_ff_only(&checkout).await.is_err());
+
+    assert!(repair_checkout(&checkout, &backup).await.unwrap());
+    assert!(std::fs::read_to_string(backup.join("local-changes.patch")).unwrap().contains("# edited"));
+    assert_eq!(std::fs::read_to_string(backup.join("files/notes.txt")).unwrap(), "mine");
+    assert_eq!(std::fs::read_dir(backup.join("commits")).unwrap().count(), 1);
+    assert!(!checkout.join("Formula/local.rb").exists());
+    assert!(git::changed_paths(&checkout).await.unwrap().is_empty());
+
+    git::pull_ff_only(&checkout).await.unwrap();
+    assert!(std::fs::read_to_string(checkout.join("Formula/wget.rb")).unwrap().contains("# upstream"));
+
+    // Earlier revisions stay readable, e.g. for pinning what was installed
+    let original = git::show_file(&checkout, "HEAD~1", std::path::Path::new("Formula/wget.rb")).await.unwrap();
+    assert_eq!(original, "class Wget < Formula\nend\n");
+}
+
+#[test]
+fn test_package_records_its_origin() {
+    use nitro::core::package::Package;
+
+    // Records written before the formula path was stored still load
+    let package: Package = serde_json::from_str(r#"{
+        "name": "wget", "version": "1.24.5", "description": null, "homepage": null,
+        "installed": true, "installed_version": "1.24.5", "dependencies": [],
+        "install_path": null, "size": null, "tap": "homebrew/core"
+    }"#).unwrap();
+    assert_eq!(package.tap.as_deref(), Some("homebrew/core"));
+    assert_eq!(package.formula_path, None);
+}
+
+#[tokio::test]
+async fn test_doctor_network_checks() {
+    use nitro::core::doctor::{check_endpoint, proxy_checks, CheckStatus};
+    use nitro::download::{DownloadConfig, Downloader};
+
+    let mut server = mockito::Server::new_async().await;
+    let _mock = server.mock("HEAD", "/v2/").with_status(401).create_async().await;
+    let downloader = Downloader::with_config(DownloadConfig::default()).unwrap();
+
+    // An auth challenge still means the registry is reachable
+    let check = check_endpoint(&downloader, "registry", &format!("{}/v2/", server.url())).await;
+    assert_eq!(check.status, CheckStatus::Ok, "{}", check.message);
+    assert!(check.latency_ms.is_some());
+
+    let check = check_endpoint(&downloader, "closed", "http://127.0.0.1:1/").await;
+    assert_eq!(check.status, CheckStatus::Error);
+    assert!(check.latency_ms.is_none());
+
+    let env = |name: &str| match name {
+        "HTTPS_PROXY" => Some("http://proxy.corp:3128".to_string()),
+        "ALL_PROXY" => Some("proxy.corp:3128".to_string()),
+        _ => None,
+    };
+    let statuses: Vec<_> = proxy_checks(env).into_iter().map(|c| (c.name, c.status)).collect();
+    assert_eq!(statuses, vec![
+        ("proxy HTTPS_PROXY".to_string(), CheckStatus::Ok),
+        ("proxy ALL_PROXY".to_string(), CheckStatus::Error),
+    ]);
+}
+
+#[tokio::test]
+async fn test_doctor_json_report() {
+    use nitro::core::doctor::{check_endpoint, proxy_checks, report_json};
+    use nitro::download::{DownloadConfig, Downloader};
+
+    let downloader = Downloader::with_config(DownloadConfig::default()).unwrap();
+    let env = |name: &str| match name {
+        "HTTPS_PROXY" => Some("http://proxy.corp:3128".to_string()),
+        "ALL_PROXY" => Some("ftp://proxy.corp".to_string()),
+        _ => None,
+    };
+    let mut checks = proxy_checks(env);
+    checks.push(check_endpoint(&downloader, "closed.example", "http://127.0.0.1:1/").await);
+
+    let report = report_json(&checks);
+    assert_eq!(report["summary"], serde_json::json!({ "ok": 1, "warning": 0, "error": 2 }));
+
+    let checks = report["checks"].as_array().unwrap();
+    assert_eq!(checks[0]["id"], "network.proxy.https_proxy");
+    assert_eq!(checks[0]["severity"], "ok");
+    assert!(checks[0]["remediation"].is_null());
+
+    assert_eq!(checks[1]["id"], "network.proxy.all_proxy");
+    assert_eq!(checks[1]["severity"], "error");
+    assert!(checks[1]["remediation"].as_str().unwrap().contains("ALL_PROXY"));
+
+    assert_eq!(checks[2]["id"], "network.endpoint.closed.example");
+    assert_eq!(checks[2]["severity"], "error");
+    assert!(checks[2]["remediation"].is_string());
+    assert!(checks[2]["latency_ms"].is_null());
+}
+
+#[test]
+fn test_shebang_rewriting() {
+    use nitro::core::shebang::{rewrite_keg_shebangs, rewrite_shebang, ShebangContext};
+    use std::os::unix::fs::PermissionsExt;
+
+    let prefix = tempfile::tempdir().unwrap();
+    let python_bin = prefix.path().join("Cellar/python@3.12/3.12.4/bin");
+    std::fs::create_dir_all(&python_bin).unwrap();
+    std::fs::write(python_bin.join("python3"), "").unwrap();
+    std::fs::create_dir_all(prefix.path().join("opt")).unwrap();
+    std::os::unix::fs::symlink(prefix.path().join("Cellar/python@3.12/3.12.4"), prefix.path().join("opt/python@3.12")).unwrap();
+
+    let context = ShebangContext::for_dependencies(prefix.path(), &["python@3.12".to_string()], Default::default());
+    // By way of the opt link, so the scripts survive upgrading python@3.12
+    let python = prefix.path().join("opt/python@3.12/bin/python3").display().to_string();
+
+    assert_eq!(rewrite_shebang("#!/usr/bin/env python3", &context), Some(format!("#!{}", python)));
+    assert_eq!(rewrite_shebang("#!/usr/bin/env -S python3 -u", &context), Some(format!("#!{} -u", python)));
+    assert_eq!(rewrite_shebang("#!/usr/local/bin/python3 -E", &context), Some(format!("#!{} -E", python)));
+    // Interpreters no dependency provides are left alone
+    assert_eq!(rewrite_shebang("#!/bin/sh", &context), None);
+    assert_eq!(rewrite_shebang("#!/usr/bin/env perl", &context), None);
+    assert_eq!(
+        rewrite_shebang("#!@@HOMEBREW_PREFIX@@/opt/ruby/bin/ruby", &context),
+        Some(format!("#!{}/opt/ruby/bin/ruby", prefix.path().display()))
+    );
+
+    // Explicit interpreter mappings win
+    let mapped = ShebangContext {
+        interpreters: [("python3".to_string(), "python@3.13".to_string())].into(),
+        ..context.clone()
+    };
+    assert_eq!(
+        rewrite_shebang("#!/usr/bin/env python3", &mapped),
+        Some(format!("#!{}/opt/python@3.13/bin/python3", prefix.path().display()))
+    );
+
+    let keg = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(keg.path().join("bin")).unwrap();
+    let script = keg.path().join("bin/tool");
+    std::fs::write(&script, "#!/usr/bin/env python3\nprint('hi')\n").unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o555)).unwrap();
+    std::fs::write(keg.path().join("bin/binary"), [0x7f, b'E', b'L', b'F']).unwrap();
+
+    assert_eq!(rewrite_keg_shebangs(keg.path(), &context).unwrap(), 1);
+    assert_eq!(std::fs::read_to_string(&script).unwrap(), format!("#!{}\nprint('hi')\n", python));
+    assert_eq!(std::fs::metadata(&script).unwrap().permissions().mode() & 0o777, 0o555);
+}
+
+#[test]
+fn test_language_formula_resources() {
+    use nitro::core::formula::FormulaParser;
+    use nitro::core::language::{link_entry_points, new_entry_points, write_gem_wrappers, LanguageInstaller};
+    use std::os::unix::fs::PermissionsExt;
+
+    let content = r#"
+class Httpie < Formula
+  include Language::Python::Virtualenv
+
+  desc "User-friendly cURL replacement"
+  url "https://files.pythonhosted.org/packages/httpie-3.2.2.tar.gz"
+  sha256 "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"
+
+  depends_on "python@3.12"
+
+  resource "certifi" do
+    url "https://files.pythonhosted.org/packages/certifi-2024.2.2.tar.gz"
+    sha256 "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb"
+  end
+
+  resource "requests" do
+    url "https://files.pythonhosted.org/packages/requests-2.31.0.tar.gz"
+    sha256 "cccccccccccccccccccccccccccccccccccccccccccccccccccccccccccccccc"
+  end
+
+  def install
+    virtualenv_install_with_resources
+  end
+end
+"#;
+    let formula = FormulaParser::new().parse_content(content).unwrap();
+    assert_eq!(formula.sources[0].url, "https://files.pythonhosted.org/packages/httpie-3.2.2.tar.gz");
+    let names: Vec<&str> = formula.resources.iter().map(|r| r.name.as_str()).collect();
+    assert_eq!(names, ["certifi", "requests"]);
+    assert_eq!(formula.resources[1].sha256, "c".repeat(64));
+
+    let script = formula.install_script.as_deref().unwrap();
+    assert_eq!(LanguageInstaller::detect(script), Some(LanguageInstaller::Python));
+    assert_eq!(LanguageInstaller::detect(r#"system "npm", "install", *std_npm_args"#), Some(LanguageInstaller::Node));
+    assert_eq!(LanguageInstaller::detect(r#"system "./configure", "--prefix=#{prefix}""#), None);
+
+    // Only executables the package itself added to libexec/bin are exposed
+    let keg = tempfile::tempdir().unwrap();
+    let libexec_bin = keg.path().join("libexec/bin");
+    std::fs::create_dir_all(&libexec_bin).unwrap();
+    let executable = |name: &str| {
+        std::fs::write(libexec_bin.join(name), "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(libexec_bin.join(name), std::fs::Permissions::from_mode(0o755)).unwrap();
+    };
+    executable("pip");
+    std::fs::write(libexec_bin.join("activate"), "").unwrap();
+    let before = new_entry_points(&Default::default(), &libexec_bin).into_iter().collect();
+    executable("http");
+    executable("https");
+
+    let entry_points = new_entry_points(&before, &libexec_bin);
+    assert_eq!(entry_points, ["http", "https"]);
+    link_entry_points(keg.path(), &entry_points).unwrap();
+    assert_eq!(std::fs::read_link(keg.path().join("bin/http")).unwrap(), std::path::Path::new("../libexec/bin/http"));
+    assert!(!keg.path().join("bin/pip").exists());
+
+    write_gem_wrappers(keg.path(), &["rubocop".to_string()]).unwrap();
+    let wrapper = std::fs::read_to_string(keg.path().join("bin/rubocop")).unwrap();
+    assert!(wrapper.contains(&format!("GEM_HOME=\"{}\"", keg.path().join("libexec").display())));
+}
+
+#[tokio::test]
+async fn test_font_cask_install_and_uninstall() {
+    use nitro::core::cask::{CaskArtifact, CaskParser};
+    use nitro::core::cask_installer::{self, CaskTargets};
+    use nitro::download::{DownloadConfig, Downloader};
+    use sha2::{Digest, Sha256};
+    use std::io::Write;
+
+    let mut archive = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    for name in ["Fira_Code_v6.2/ttf/FiraCode-Regular.ttf", "Fira_Code_v6.2/ttf/FiraCode-Bold.ttf", "README.txt"] {
+        archive.start_file(name, zip::write::SimpleFileOptions::default()).unwrap();
+        archive.write_all(name.as_bytes()).unwrap();
+    }
+    let body = archive.finish().unwrap().into_inner();
+    let sha256 = hex::encode(Sha256::digest(&body));
+
+    let mut server = mockito::Server::new_async().await;
+    let download = server.mock("GET", "/releases/6.2/Fira_Code_v6.2.zip")
+        .with_status(200)
+        .with_body(&body)
+        .create_async()
+        .await;
+
+    let content = format!(r#"
+cask "font-fira-code" do
+  version "6.2"
+  sha256 "{sha256}"
+
+  url "{}/releases/#{{version}}/Fira_Code_v#{{version}}.zip"
+  name "Fira Code"
+  homepage "https://github.com/tonsky/FiraCode"
+
+  font "Fira_Code_v#{{version}}/ttf/FiraCode-Regular.ttf"
+  font "FiraCode-Bold.ttf"
+end
+"#, server.url());
+    let cask = CaskParser::new().parse_content(&content).unwrap();
+    assert_eq!(cask.url.as_deref(), Some(format!("{}/releases/6.2/Fira_Code_v6.2.zip", server.url()).as_str()));
+    assert_eq!(cask.artifacts, [
+        CaskArtifact::Font("Fira_Code_v6.2/ttf/FiraCode-Regular.ttf".to_string()),
+        CaskArtifact::Font("FiraCode-Bold.ttf".to_string()),
+    ]);
+
+    let fonts = tempfile::tempdir().unwrap();
+    let targets = CaskTargets { font_dir: fonts.path().to_path_buf(), app_dir: fonts.path().join("Applications"), force: false };
+    let downloader = Downloader::with_config(DownloadConfig::default()).unwrap();
+    let installed = cask_installer::install(&cask, &downloader, &targets, None).await.unwrap();
+    let files = installed.files.clone();
+    download.assert_async().await;
+    assert_eq!(files, [fonts.path().join("FiraCode-Regular.ttf"), fonts.path().join("FiraCode-Bold.ttf")]);
+    assert!(!fonts.path().join("README.txt").exists());
+
+    // Reinstalling replaces the previous install's own files without --force
+    std::fs::write(&files[0], "previous").unwrap();
+    let installed = cask_installer::install(&cask, &downloader, &targets, Some(&installed)).await.unwrap();
+    assert_eq!(std::fs::read_to_string(&files[0]).unwrap(), "Fira_Code_v6.2/ttf/FiraCode-Regular.ttf");
+    assert_eq!(std::fs::read_dir(fonts.path()).unwrap().count(), 2);
+
+    // A reinstall that fails leaves the previous install as it was
+    let mut broken = cask.clone();
+    broken.artifacts.push(CaskArtifact::Font("missing.ttf".to_string()));
+    assert!(cask_installer::install(&broken, &downloader, &targets, Some(&installed)).await.is_err());
+    assert!(files.iter().all(|f| f.exists()));
+    assert_eq!(std::fs::read_dir(fonts.path()).unwrap().count(), 2);
+
+    // A font that is already there isn't overwritten, and nothing is left half-installed
+    std::fs::remove_file(&files[0]).unwrap();
+    assert!(cask_installer::install(&cask, &downloader, &targets, None).await.is_err());
+    assert!(!files[0].exists());
+    assert!(files[1].exists());
+
+    cask_installer::uninstall(&installed).unwrap();
+    assert!(files.iter().all(|f| !f.exists()));
+}
+
+#[test]
+fn test_pkg_receipts() {
+    use nitro::core::cask::{CaskArtifact, CaskParser};
+    use nitro::core::pkg::{id_matches, new_ids, receipt_paths, receipt_root};
+    use std::path::{Path, PathBuf};
+
+    let cask = CaskParser::new().parse_content(r#"
+cask "zoom" do
+  version "6.0.2"
+  sha256 :no_check
+
+  url "https://zoom.us/client/#{version}/Zoom.pkg"
+  name "Zoom"
+
+  pkg "Zoom.pkg"
+
+  uninstall pkgutil: ["us.zoom.pkg.videomeeting", "us.zoom.pkg.plugin.*"]
+end
+"#).unwrap();
+    assert_eq!(cask.url.as_deref(), Some("https://zoom.us/client/6.0.2/Zoom.pkg"));
+    assert_eq!(cask.sha256, None);
+    assert_eq!(cask.artifacts, [CaskArtifact::Pkg("Zoom.pkg".to_string())]);
+    assert_eq!(cask.uninstall_pkgutil, ["us.zoom.pkg.videomeeting", "us.zoom.pkg.plugin.*"]);
+
+    assert!(id_matches("us.zoom.pkg.plugin.*", "us.zoom.pkg.plugin.outlook"));
+    assert!(!id_matches("us.zoom.pkg.plugin.*", "us.zoom.pkg.videomeeting"));
+    assert!(!id_matches("us.zoom.pkg", "us.zoom.pkgs"));
+
+    let before = ["com.apple.pkg.Core".to_string()].into();
+    let after = ["com.apple.pkg.Core".to_string(), "us.zoom.pkg.videomeeting".to_string()].into();
+    assert_eq!(new_ids(&before, &after), ["us.zoom.pkg.videomeeting"]);
+
+    let info = "package-id: us.zoom.pkg.videomeeting\nversion: 6.0.2\nvolume: /\nlocation: Applications\ninstall-time: 1700000000\n";
+    let root = receipt_root(info);
+    assert_eq!(root, Path::new("/Applications"));
+    assert_eq!(
+        receipt_paths(&root, "zoom.us.app/Contents/Info.plist\n\nzoom.us.app/Contents/MacOS/zoom.us\n"),
+        [PathBuf::from("/Applications/zoom.us.app/Contents/Info.plist"), PathBuf::from("/Applications/zoom.us.app/Contents/MacOS/zoom.us")]
+    );
+    assert_eq!(receipt_root("package-id: x\nvolume: /\nlocation: \n"), Path::new("/"));
+}
+
+#[test]
+fn test_disk_image_staging() {
+    use nitro::core::cask_installer::stage;
+    use nitro::core::dmg::{copy_app, mount_points};
+    use std::path::PathBuf;
+
+    // APFS images report a container entity with no mount point alongside the volume
+    let plist = r#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0">
+<dict>
+	<key>system-entities</key>
+	<array>
+		<dict>
+			<key>content-hint</key>
+			<string>GUID_partition_scheme</string>
+			<key>dev-entry</key>
+			<string>/dev/disk4</string>
+		</dict>
+		<dict>
+			<key>content-hint</key>
+			<string>41504653-0000-11AA-AA11-00306543ECAC</string>
+			<key>dev-entry</key>
+			<string>/dev/disk5s1</string>
+			<key>mount-point</key>
+			<string>/private/tmp/dmg.Xa81jQ</string>
+		</dict>
+	</array>
+</dict>
+</plist>"#;
+    assert_eq!(mount_points(plist), [PathBuf::from("/private/tmp/dmg.Xa81jQ")]);
+    assert!(mount_points("Software License Agreement\n").is_empty());
+
+    // Apps are found in archives as well as disk images, and copied whole
+    let work = tempfile::tempdir().unwrap();
+    let download = work.path().join("Tool-1.0.tar.gz");
+    let source = work.path().join("src");
+    std::fs::create_dir_all(source.join("Tool/Tool.app/Contents/MacOS")).unwrap();
+    std::fs::write(source.join("Tool/Tool.app/Contents/MacOS/tool"), "binary").unwrap();
+    std::os::unix::fs::symlink("MacOS/tool", source.join("Tool/Tool.app/Contents/current")).unwrap();
+    let status = std::process::Command::new("tar")
+        .args(["czf"]).arg(&download).arg("-C").arg(&source).arg("Tool")
+        .status().unwrap();
+    assert!(status.success());
+
+    let staged = stage(&download, &work.path().join("staged")).unwrap();
+    let app = staged.locate("Tool.app").unwrap();
+    assert_eq!(app, work.path().join("staged/Tool/Tool.app"));
+    assert!(staged.locate("Missing.app").is_err());
+
+    let dest = work.path().join("Applications/Tool.app");
+    std::fs::create_dir_all(dest.parent().unwrap()).unwrap();
+    copy_app(&app, &dest).unwrap();
+    assert_eq!(std::fs::read_to_string(dest.join("Contents/MacOS/tool")).unwrap(), "binary");
+    assert!(std::fs::symlink_metadata(dest.join("Contents/current")).unwrap().file_type().is_symlink());
+}
+
+#[tokio::test]
+async fn test_app_cask_replaces_existing_app() {
+    use nitro::core::cask::{CaskArtifact, CaskParser};
+    use nitro::core::cask_installer::{self, CaskTargets};
+    use nitro::download::{DownloadConfig, Downloader};
+    use sha2::{Digest, Sha256};
+
+    let work = tempfile::tempdir().unwrap();
+    let source = work.path().join("src");
+    std::fs::create_dir_all(source.join("Tool/Tool.app/Contents/MacOS")).unwrap();
+    std::fs::write(source.join("Tool/Tool.app/Contents/MacOS/tool"), "new").unwrap();
+    let download = work.path().join("Tool-2.0.tar.gz");
+    let status = std::process::Command::new("tar")
+        .args(["czf"]).arg(&download).arg("-C").arg(&source).arg("Tool")
+        .status().unwrap();
+    assert!(status.success());
+    let body = std::fs::read(&download).unwrap();
+
+    let mut server = mockito::Server::new_async().await;
+    let _download = server.mock("GET", "/Tool-2.0.tar.gz")
+        .with_status(200)
+        .with_body(&body)
+        .create_async()
+        .await;
+    let cask = CaskParser::new().parse_content(&format!(
+        "cask \"tool\" do\n  version \"2.0\"\n  sha256 \"{}\"\n  url \"{}/Tool-2.0.tar.gz\"\n  app \"Tool.app\"\nend\n",
+        hex::encode(Sha256::digest(&body)), server.url()
+    )).unwrap();
+
+    // An app nitro didn't install is in the way
+    let applications = work.path().join("Applications");
+    let existing = applications.join("Tool.app/Contents/MacOS/tool");
+    std::fs::create_dir_all(existing.parent().unwrap()).unwrap();
+    std::fs::write(&existing, "old").unwrap();
+    let targets = |force| CaskTargets { font_dir: work.path().join("fonts"), app_dir: applications.clone(), force };
+    let downloader = Downloader::with_config(DownloadConfig::default()).unwrap();
+    assert!(cask_installer::install(&cask, &downloader, &targets(false), None).await.is_err());
+    assert_eq!(std::fs::read_to_string(&existing).unwrap(), "old");
+
+    // With --force it's only removed once the whole cask is installed
+    let mut broken = cask.clone();
+    broken.artifacts.push(CaskArtifact::Font("missing.ttf".to_string()));
+    assert!(cask_installer::install(&broken, &downloader, &targets(true), None).await.is_err());
+    assert_eq!(std::fs::read_to_string(&existing).unwrap(), "old");
+    assert_eq!(std::fs::read_dir(&applications).unwrap().count(), 1);
+
+    let installed = cask_installer::install(&cask, &downloader, &targets(true), None).await.unwrap();
+    assert_eq!(installed.files, [applications.join("Tool.app")]);
+    assert_eq!(std::fs::read_to_string(&existing).unwrap(), "new");
+    assert_eq!(std::fs::read_dir(&applications).unwrap().count(), 1);
+}
+
+#[test]
+fn test_greedy_cask_upgrades() {
+    use nitro::core::cask::{CaskParser, Greedy};
+
+    let parse = |version: &str, auto_updates: bool| CaskParser::new().parse_content(&format!(
+        "cask \"app\" do\n  version {}\n  sha256 :no_check\n  url \"https://example.com/app.dmg\"\n{}  app \"App.app\"\nend\n",
+        version,
+        if auto_updates { "  auto_updates true\n" } else { "" },
+    )).unwrap();
+
+    let greedy = Greedy { latest: true, auto_updates: true };
+
+    let versioned = parse("\"2.0\"", false);
+    assert!(!versioned.auto_updates);
+    assert!(versioned.is_outdated("1.0", Greedy::default()));
+    assert!(!versioned.is_outdated("2.0", greedy));
+    assert!(!versioned.is_outdated("2.0.0", Greedy::default()));
+    assert!(!versioned.is_outdated("10.0", Greedy::default()));
+    assert!(parse("\"1.10\"", false).is_outdated("1.9", Greedy::default()));
+
+    // Self-updating apps are only reinstalled when asked to be greedy
+    let self_updating = parse("\"2.0\"", true);
+    assert!(self_updating.auto_updates);
+    assert!(!self_updating.is_outdated("1.0", Greedy::default()));
+    assert!(!self_updating.is_outdated("1.0", Greedy { latest: true, auto_updates: false }));
+    assert!(self_updating.is_outdated("1.0", greedy));
+
+    // `latest` can't be compared, so it would otherwise be reinstalled every time
+    let latest = parse(":latest", false);
+    assert_eq!(latest.version, "latest");
+    assert!(!latest.is_outdated("latest", Greedy::default()));
+    assert!(latest.is_outdated("latest", Greedy { latest: true, auto_updates: false }));
+}
+
+#[test]
+fn test_installed_cask_inventory() {
+    use nitro::core::cask::CaskArtifact;
+    use nitro::core::cask_installer::{CaskStore, InstalledCask};
+
+    let dir = tempfile::tempdir().unwrap();
+    let db = sled::open(dir.path().join("packages.db")).unwrap();
+    let store = CaskStore::open(&db).unwrap();
+
+    let cask = InstalledCask {
+        token: "firefox".to_string(),
+        version: "125.0".to_string(),
+        tap: Some("homebrew/cask".to_string()),
+        artifacts: vec![CaskArtifact::App("Firefox.app".to_string())],
+        files: vec!["/Applications/Firefox.app".into()],
+        pkg_ids: vec![],
+        installed_at: chrono::Utc::now(),
+    };
+    store.insert(&cask).unwrap();
+
+    // Casks don't share the formula namespace of the default tree
+    db.insert("firefox", b"formula".to_vec()).unwrap();
+    let listed = store.list().unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].artifacts, [CaskArtifact::App("Firefox.app".to_string())]);
+    assert_eq!(store.get("firefox").unwrap().unwrap().version, "125.0");
+    assert_eq!(db.get("firefox").unwrap().unwrap(), b"formula".as_ref());
+
+    store.remove("firefox").unwrap();
+    assert!(store.get("firefox").unwrap().is_none());
+    assert!(db.get("firefox").unwrap().is_some());
+}
+
+#[tokio::test]
+async fn test_daemon_job_queue() {
+    use nitro::daemon::authenticate;
+    use nitro::daemon::jobs::{CancelError, JobKind, JobQueue, JobRequest, JobState};
+    use std::collections::BTreeMap;
+
+    let tokens = BTreeMap::from([("alice".to_string(), "a-token".to_string()), ("bob".to_string(), "b-token".to_string())]);
+    assert_eq!(authenticate(&tokens, Some("Bearer b-token")).as_deref(), Some("bob"));
+    assert_eq!(authenticate(&tokens, Some("Bearer a-toke")), None);
+    assert_eq!(authenticate(&tokens, Some("b-token")), None);
+    assert_eq!(authenticate(&tokens, None), None);
+
+    let request = |kind| JobRequest { kind, packages: vec!["wget".to_string()], cask: false };
+    let queue = JobQueue::new();
+    let install = queue.submit("alice", request(JobKind::Install));
+    let fetch = queue.submit("bob", request(JobKind::Fetch));
+    let upgrade = queue.submit("alice", request(JobKind::Upgrade));
+    assert_eq!(queue.depth(), 3);
+
+    // Clients only cancel their own jobs; a queued job is dropped at once
+    assert_eq!(queue.cancel("bob", install), Err(CancelError::NotOwner));
+    assert_eq!(queue.cancel("alice", upgrade), Ok(JobState::Cancelled));
+    assert_eq!(queue.depth(), 2);
+    assert_eq!(queue.cancel("alice", upgrade), Err(CancelError::AlreadyFinished(JobState::Cancelled)));
+
+    // Jobs start in submission order
+    let started = queue.next().await;
+    assert_eq!(started.id, install);
+    assert_eq!(queue.get(install).unwrap().state, JobState::Running);
+    // Only a fetch may join a batch, and the next job is a fetch
+    assert!(queue.start_next(|job| job.request.kind.is_mutating()).is_none());
+    assert_eq!(queue.start_next(|job| !job.request.kind.is_mutating()).unwrap().id, fetch);
+
+    // A running job is flagged and stops at its next safe point
+    assert_eq!(queue.cancel("alice", install), Ok(JobState::Running));
+    assert!(queue.is_cancelled(install));
+
+    queue.push_event(install, "Running install wget");
+    let (events, state) = queue.events_after(install, 0).await.unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(state, JobState::Running);
+
+    let waiter = queue.events_after(install, events.last().unwrap().seq);
+    queue.finish(install, JobState::Cancelled, "Cancelled before wget");
+    let (events, state) = waiter.await.unwrap();
+    assert_eq!(events.last().unwrap().message, "Cancelled before wget");
+    assert_eq!(state, JobState::Cancelled);
+    assert!(!queue.is_cancelled(install));
+
+    assert_eq!(queue.jobs_for("alice").len(), 2);
+    assert_eq!(queue.jobs_for("bob").len(), 1);
+}
+
+#[test]
+fn test_daemon_metrics() {
+    use nitro::metrics::Metrics;
+
+    let metrics = Metrics::default();
+    assert_eq!(metrics.cache_hit_ratio(), 0.0);
+    metrics.record_install();
+    metrics.record_install();
+    metrics.record_failure();
+    metrics.record_cache_lookup(true);
+    metrics.record_cache_lookup(true);
+    metrics.record_cache_lookup(true);
+    metrics.record_cache_lookup(false);
+    metrics.record_download_bytes(1024);
+    metrics.record_download_bytes(512);
+    assert_eq!(metrics.cache_hit_ratio(), 0.75);
+
+    let text = metrics.render(3, 1);
+    let lines: Vec<&str> = text.lines().filter(|line| !line.starts_with('#')).collect();
+    assert!(lines.contains(&"nitro_installs_total 2"));
+    assert!(lines.contains(&"nitro_failures_total 1"));
+    assert!(lines.contains(&"nitro_cache_hits_total 3"));
+    assert!(lines.contains(&"nitro_cache_misses_total 1"));
+    assert!(lines.contains(&"nitro_cache_hit_ratio 0.75"));
+    assert!(lines.contains(&"nitro_download_bytes_total 1536"));
+    assert!(lines.contains(&"nitro_queue_depth 3"));
+    assert!(lines.contains(&"nitro_jobs_running 1"));
+    assert!(text.contains("# TYPE nitro_installs_total counter"));
+    assert!(text.contains("# TYPE nitro_queue_depth gauge"));
+}
+
+#[test]
+fn test_fleet_policy() {
+    use nitro::core::policy::Policy;
+    use nitro::core::NitroError;
+
+    let policy: Policy = toml::from_str(r#"
+        forbidden_licenses = ["AGPL-3.0-only"]
+
+        [formulae]
+        block = ["telnet", "crypto-*"]
+
+        [taps]
+        allow = ["homebrew/*", "mycorp/tools"]
+
+        [required_pins]
+        openssl = "3.1.4"
+    "#).unwrap();
+
+    let formula = |name: &str, version: &str, tap: Option<&str>, license: Option<&str>| Formula {
+        name: name.to_string(),
+        version: version.to_string(),
+        tap: tap.map(str::to_string),
+        license: license.map(str::to_string),
+        ..Default::default()
+    };
+    let blocked = |result: Result<(), NitroError>| match result {
+        Err(NitroError::BlockedByPolicy(message)) => message,
+        other => panic!("expected a policy error, got {:?}", other),
+    };
+
+    assert!(policy.check_formula(&formula("wget", "1.24.5", Some("homebrew/core"), Some("GPL-3.0-or-later"))).is_ok());
+    assert!(blocked(policy.check_formula(&formula("telnet", "1.0", None, None))).contains("telnet is blocked"));
+    assert!(blocked(policy.check_formula(&formula("crypto-miner", "1.0", None, None))).contains("blocked"));
+    assert!(blocked(policy.check_formula(&formula("tool", "1.0", Some("random/tap"), None))).contains("random/tap"));
+    assert!(blocked(policy.check_formula(&formula("db", "2.0", None, Some(r#"any_of: ["MIT", "agpl-3.0-only"]"#))))
+        .contains("AGPL-3.0-only"));
+    assert!(policy.check_formula(&formula("openssl", "3.1.4", None, None)).is_ok());
+    assert!(blocked(policy.check_formula(&formula("openssl", "3.2.0", None, None))).contains("must stay at version 3.1.4"));
+
+    assert!(policy.check_tap("Homebrew/cask").is_ok());
+    assert!(policy.check_tap("mycorp/tools").is_ok());
+    assert!(blocked(policy.check_tap("mycorp/other")).contains("not on the allow list"));
+
+    // No file means no restrictions
+    assert!(Policy::default().check_formula(&formula("telnet", "1.0", Some("random/tap"), Some("AGPL-3.0-only"))).is_ok());
+
+    // A formula with neither tap nor file is from the API, so homebrew/core's
+    let corp_only: Policy = toml::from_str("[taps]\nallow = [\"mycorp/*\"]\n").unwrap();
+    assert!(blocked(corp_only.check_formula(&formula("wget", "1.24.5", None, None))).contains("homebrew/core"));
+    let local = Formula { path: Some("/tmp/wget.rb".into()), ..formula("wget", "1.24.5", None, None) };
+    assert!(corp_only.check_formula(&local).is_ok());
+
+    // NITRO_POLICY only counts where there's no system policy file
+    let dir = tempfile::tempdir().unwrap();
+    let system = dir.path().join("policy.toml");
+    let user = dir.path().join("mine.toml");
+    assert_eq!(Policy::path_from(&system, Some(user.clone())), user);
+    std::fs::write(&system, "").unwrap();
+    assert_eq!(Policy::path_from(&system, Some(user)), system);
+    assert_eq!(Policy::path_from(&system, None), system);
+}
+
+#[test]
+fn test_checksum_override_recorded() {
+    use nitro::core::install_state::{ChecksumOverride, InstallStateStore};
+    use nitro::core::installer::Installer;
+    use nitro::core::package::Package;
+    use nitro::core::NitroError;
+    use std::path::PathBuf;
+
+    let dir = tempfile::tempdir().unwrap();
+    let tarball = dir.path().join("hello-1.0.tar.gz");
+    std::fs::write(&tarball, b"re-rolled").unwrap();
+    let expected = "0".repeat(64);
+
+    // The mismatch carries both hashes so the installer can offer the override
+    let found = match Installer::verify_checksum(&tarball, &expected) {
+        Err(NitroError::ChecksumMismatch { expected: e, actual: a }) => {
+            assert_eq!(e, expected);
+            a
+        }
+        other => panic!("expected a checksum mismatch, got {:?}", other),
+    };
+    assert_eq!(found.len(), 64);
+    assert!(Installer::verify_checksum(&tarball, &found).is_ok());
+
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let store = InstallStateStore::open(&db).unwrap();
+    let formula = Formula { name: "hello".to_string(), version: "1.0".to_string(), ..Default::default() };
+    let checksum_override = ChecksumOverride {
+        url: "https://example.com/hello-1.0.tar.gz".to_string(),
+        expected: expected.clone(),
+        accepted: found,
+        accepted_at: chrono::Utc::now(),
+    };
+    store.begin(&formula, PathBuf::from("/tmp/Cellar/hello/1.0"), true).unwrap();
+    assert_eq!(store.get("hello").unwrap().unwrap().checksum_override, None);
+    store.set_checksum_override("hello", checksum_override.clone()).unwrap();
+    assert_eq!(store.get("hello").unwrap().unwrap().checksum_override, Some(checksum_override));
+
+    // Receipts written before overrides existed still load
+    let old: Package = serde_json::from_str(r#"{"name":"hello","version":"1.0","description":null,"homepage":null,
+        "installed":true,"installed_version":"1.0","dependencies":[],"install_path":null,"size":null}"#).unwrap();
+    assert!(old.checksum_override.is_none());
+}
+
+#[test]
+fn test_source_build_toolchain() {
+    use nitro::core::toolchain::{compiler, install_hint, missing, required_tools, BuildTool};
+    use std::os::unix::fs::PermissionsExt;
+
+    let programs = |formula: &Formula| required_tools(formula).iter().map(|t| t.program).collect::<Vec<_>>();
+
+    // Without an install script the build is ./configure && make
+    let plain = Formula { name: "hello".to_string(), ..Default::default() };
+    assert_eq!(programs(&plain), ["make", "cc"]);
+
+    let cmake = Formula {
+        name: "fmt".to_string(),
+        install_script: Some(r#"system "cmake", "-S", ".", "-B", "build", *std_cmake_args
+system "cmake", "--build", "build"
+system "pkg-config", "--cflags", "zlib""#.to_string()),
+        ..Default::default()
+    };
+    assert_eq!(programs(&cmake), ["cmake", "pkg-config", "cc"]);
+
+    // A declared build dependency brings its own tool
+    let declared = Formula {
+        build_dependencies: vec![Dependency { name: "cmake".to_string(), version: None, build_only: true, optional: false, test_only: false }],
+        ..cmake.clone()
+    };
+    assert_eq!(programs(&declared), ["pkg-config", "cc"]);
+
+    let script_only = Formula {
+        name: "tool".to_string(),
+        install_script: Some(r#"bin.install "tool""#.to_string()),
+        ..Default::default()
+    };
+    assert!(required_tools(&script_only).is_empty());
+
+    let dir = tempfile::tempdir().unwrap();
+    let tool = BuildTool { program: "nitro-test-build-tool", formula: Some("nitro-test") };
+    assert_eq!(missing(&[tool], &[dir.path().to_path_buf()]), [tool]);
+    let program = dir.path().join(tool.program);
+    std::fs::write(&program, "#!/bin/sh\n").unwrap();
+    assert_eq!(missing(&[tool], &[dir.path().to_path_buf()]), [tool]);
+    std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+    assert!(missing(&[tool], &[dir.path().to_path_buf()]).is_empty());
+
+    // No formula provides `cc`, so it's never offered for install
+    assert_eq!(compiler().formula, None);
+    let hint = install_hint(&[tool, compiler()]);
+    assert!(hint.starts_with("run `nitro install nitro-test` and "));
+    assert_eq!(hint.contains("xcode-select"), cfg!(target_os = "macos"));
+    assert_eq!(hint.contains("build-essential"), !cfg!(target_os = "macos"));
+}
+
+#[test]
+fn test_xcode_and_macos_requirements() {
+    use nitro::core::formula::FormulaParser;
+    use nitro::core::requirements::{unmet_for_source_build, Comparator, Host, Requirement};
+    use nitro::core::version::Version;
+
+    let content = r#"
+class Swiftlint < Formula
+  depends_on :xcode => ["12.0", :build]
+  depends_on xcode: :build
+  depends_on macos: :ventura
+  depends_on :macos
+  depends_on maximum_macos: [:sonoma, :build]
+  depends_on macos: ">= :catalina" # old releases lack the SDK
+  depends_on "cmake" => :build
+end
+"#;
+    let requirements = FormulaParser::new().parse_content(content).unwrap().requirements;
+    assert_eq!(requirements, [
+        Requirement::Xcode { version: Some("12.0".to_string()), build_only: true },
+        Requirement::Xcode { version: None, build_only: true },
+        Requirement::Macos { comparator: Comparator::AtLeast, version: Some("13".to_string()), build_only: false },
+        Requirement::Macos { comparator: Comparator::AtLeast, version: None, build_only: false },
+        Requirement::Macos { comparator: Comparator::AtMost, version: Some("14".to_string()), build_only: true },
+        Requirement::Macos { comparator: Comparator::AtLeast, version: Some("10.15".to_string()), build_only: false },
+    ]);
+    assert_eq!(requirements[0].to_string(), "Xcode 12.0 or newer (build)");
+
+    let formula = Formula { name: "swiftlint".to_string(), requirements, ..Default::default() };
+    let host = |macos: &str, xcode: Option<&str>, clt: bool| Host {
+        macos: Some(Version::new(macos)),
+        xcode: xcode.map(Version::new),
+        command_line_tools: clt,
+        arch: None,
+    };
+
+    assert!(unmet_for_source_build(&formula, &host("14.5", Some("15.2"), true)).is_empty());
+
+    let unmet = unmet_for_source_build(&formula, &host("12.7", None, false));
+    assert!(unmet.iter().any(|m| m.contains("requires Xcode 12.0 or newer. Install it from the App Store")));
+    assert!(unmet.iter().any(|m| m.contains("requires macOS 13 (ventura) or newer; this machine runs 12.7")));
+    assert!(unmet.iter().any(|m| m.contains("xcode-select --install")));
+
+    let unmet = unmet_for_source_build(&formula, &host("15.0", Some("11.3"), true));
+    assert_eq!(unmet.len(), 2);
+    assert!(unmet[0].contains("but Xcode 11.3 is installed"));
+    assert!(unmet[1].contains("requires macOS 14 (sonoma) or older"));
+
+    // Off macOS, Xcode and the macOS versions don't apply, but the bare `:macos` does
+    let unmet = unmet_for_source_build(&formula, &Host::default());
+    assert_eq!(unmet, ["swiftlint requires macOS"]);
+    let versioned = Formula {
+        name: "portable".to_string(),
+        requirements: formula.requirements.iter().filter(|r| !matches!(r, Requirement::Macos { version: None, .. })).cloned().collect(),
+        ..Default::default()
+    };
+    assert!(unmet_for_source_build(&versioned, &Host::default()).is_empty());
+    let plain = Formula { name: "hello".to_string(), ..Default::default() };
+    assert!(unmet_for_source_build(&plain, &Host::default()).is_empty());
+
+    let api = serde_json::json!([
+        { "name": "xcode", "version": "14.3", "contexts": ["build"] },
+        { "name": "macos", "version": "13", "contexts": [] },
+        { "name": "linux", "version": null, "contexts": [] }
+    ]);
+    assert_eq!(nitro::core::requirements::from_api_json(&api), [
+        Requirement::Xcode { version: Some("14.3".to_string()), build_only: true },
+        Requirement::Macos { comparator: Comparator::AtLeast, version: Some("13".to_string()), build_only: false },
+    ]);
+}
+
+#[test]
+fn test_linux_bottle_glibc_compatibility() {
+    use nitro::core::glibc::{bottle_incompatibility, bottle_needs, host_incompatibility, needed_versions, newest_symbol_version, BottleNeeds, HostLibraries};
+    use nitro::core::version::Version;
+
+    let data = b"\0GLIBC_2.2.5\0GLIBC_2.34\0GLIBC_PRIVATE\0GLIBC_2.4\0GLIBCXX_3.4.30\0";
+    assert_eq!(newest_symbol_version(data, "GLIBC_"), Some(Version::new("2.34")));
+    assert_eq!(newest_symbol_version(data, "GLIBCXX_"), Some(Version::new("3.4.30")));
+    assert_eq!(newest_symbol_version(b"no symbols", "GLIBC_"), None);
+
+    // A 64-bit ELF file needing GLIBC_2.17 and GLIBC_2.28 from libc and GLIBCXX_3.4.29
+    // from libstdc++, whose string table also names versions it defines itself
+    let strtab = b"\0libc.so.6\0GLIBC_2.17\0GLIBC_2.28\0libstdc++.so.6\0GLIBCXX_3.4.29\0GLIBCXX_3.4.32\0GLIBC_2.99\0";
+    let name = |s: &str| strtab.windows(s.len() + 1).position(|w| w[1..] == *s.as_bytes() && w[0] == 0).unwrap() as u32 + 1;
+    let mut verneed = Vec::new();
+    for (file, versions, next) in [("libc.so.6", &["GLIBC_2.17", "GLIBC_2.28"][..], 16 + 2 * 16), ("libstdc++.so.6", &["GLIBCXX_3.4.29"][..], 0)] {
+        for field in [1u16, versions.len() as u16] {
+            verneed.extend(field.to_le_bytes());
+        }
+        for field in [name(file), 16, next] {
+            verneed.extend(field.to_le_bytes());
+        }
+        for (i, version) in versions.iter().enumerate() {
+            verneed.extend([0u8; 8]);
+            verneed.extend(name(version).to_le_bytes());
+            verneed.extend((if i + 1 < versions.len() { 16u32 } else { 0 }).to_le_bytes());
+        }
+    }
+    let (strtab_at, verneed_at) = (64, 64 + strtab.len());
+    let shoff = verneed_at + verneed.len();
+    let mut elf = b"\x7fELF\x02\x01\x01".to_vec();
+    elf.resize(64, 0);
+    elf[0x28..0x30].copy_from_slice(&(shoff as u64).to_le_bytes());
+    elf[0x3a..0x3c].copy_from_slice(&64u16.to_le_bytes());
+    elf[0x3c..0x3e].copy_from_slice(&3u16.to_le_bytes());
+    elf.extend(strtab);
+    elf.extend(&verneed);
+    let section = |kind: u32, offset: usize, size: usize, link: u32, info: u32| {
+        let mut header = vec![0u8; 64];
+        header[4..8].copy_from_slice(&kind.to_le_bytes());
+        header[24..32].copy_from_slice(&(offset as u64).to_le_bytes());
+        header[32..40].copy_from_slice(&(size as u64).to_le_bytes());
+        header[40..44].copy_from_slice(&link.to_le_bytes());
+        header[44..48].copy_from_slice(&info.to_le_bytes());
+        header
+    };
+    elf.extend(section(0, 0, 0, 0, 0));
+    elf.extend(section(3, strtab_at, strtab.len(), 0, 0));
+    elf.extend(section(0x6fff_fffe, verneed_at, verneed.len(), 1, 2));
+    assert_eq!(needed_versions(&elf), ["GLIBC_2.17", "GLIBC_2.28", "GLIBCXX_3.4.29"]);
+    assert!(needed_versions(b"\x7fELF\x02\x01\x01\0GLIBC_2.17\0").is_empty());
+
+    // Only ELF files count; a script mentioning a symbol version doesn't
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(dir.path().join("tool/1.0/bin")).unwrap();
+    std::fs::write(dir.path().join("tool/1.0/bin/tool"), &elf).unwrap();
+    std::fs::write(dir.path().join("tool/1.0/README"), b"needs GLIBC_2.99").unwrap();
+    let needs = bottle_needs(dir.path());
+    assert_eq!(needs, BottleNeeds { glibc: Some(Version::new("2.28")), glibcxx: Some(Version::new("3.4.29")) });
+
+    let host = |glibc: Option<&str>, glibcxx: Option<&str>| HostLibraries {
+        glibc: glibc.map(Version::new),
+        glibcxx: glibcxx.map(Version::new),
+    };
+    assert!(host_incompatibility(&host(Some("2.39"), None)).is_none());
+    assert!(host_incompatibility(&host(Some("2.31"), None)).unwrap().contains("need glibc 2.35 or newer and this system has 2.31"));
+    assert!(host_incompatibility(&host(None, None)).unwrap().contains("doesn't use glibc"));
+
+    assert!(bottle_incompatibility(&needs, &host(Some("2.35"), Some("3.4.30"))).is_none());
+    assert!(bottle_incompatibility(&needs, &host(Some("2.35"), Some("3.4.28"))).unwrap().contains("GLIBCXX_3.4.29"));
+    assert!(bottle_incompatibility(&needs, &host(Some("2.35"), None)).unwrap().contains("libstdc++"));
+    let newer = BottleNeeds { glibc: Some(Version::new("2.38")), glibcxx: None };
+    assert!(bottle_incompatibility(&newer, &host(Some("2.35"), None)).unwrap().contains("needs glibc 2.38"));
+}
+
+#[test]
+fn test_install_script_env_dsl() {
+    use nitro::core::build_env::BuildEnv;
+    use nitro::core::interpolate::PathContext;
+
+    let formula = Formula {
+        name: "perl-tool".to_string(),
+        version: "1.0".to_string(),
+        ..Default::default()
+    };
+    let paths = PathContext::new("/opt/homebrew", &formula);
+    assert_eq!(paths.evaluate(r#"lib/"pkgconfig""#).as_deref(), Some("/opt/homebrew/Cellar/perl-tool/1.0/lib/pkgconfig"));
+    assert_eq!(paths.evaluate(r##""#{prefix}/share".to_s"##).as_deref(), Some("/opt/homebrew/Cellar/perl-tool/1.0/share"));
+    assert_eq!(paths.evaluate("'#{literal}'").as_deref(), Some("#{literal}"));
+
+    let mut env = BuildEnv::new();
+    let script = [
+        r#"ENV["NITRO_TEST_FOO"] = "bar""#,
+        r#"ENV["NITRO_TEST_GONE"] = nil"#,
+        r#"ENV.append "NITRO_TEST_FOO", "baz""#,
+        r#"ENV.prepend("NITRO_TEST_LIST", "first", ",")"#,
+        r#"ENV.prepend_path "NITRO_TEST_PATH", "/a""#,
+        r#"ENV.prepend_path "NITRO_TEST_PATH", "/b""#,
+        r#"ENV.prepend_path "NITRO_TEST_PATH", "/a""#,
+        r#"ENV.append_path "NITRO_TEST_PATH", lib/"pkgconfig""#,
+        r#"ENV.append_to_cflags "-DNDEBUG""#,
+        "ENV.deparallelize",
+        "ENV.permit_arch_flags",
+    ];
+    for line in script {
+        assert!(env.apply_statement(line, &paths), "{}", line);
+    }
+    assert!(!env.apply_statement(r#"system "make""#, &paths));
+
+    assert_eq!(env.get("NITRO_TEST_FOO").as_deref(), Some("bar baz"));
+    assert_eq!(env.get("NITRO_TEST_GONE"), None);
+    assert_eq!(env.get("NITRO_TEST_LIST").as_deref(), Some("first"));
+    assert_eq!(
+        env.get("NITRO_TEST_PATH").as_deref(),
+        Some("/a:/b:/opt/homebrew/Cellar/perl-tool/1.0/lib/pkgconfig")
+    );
+    assert!(env.get("CFLAGS").unwrap().ends_with("-DNDEBUG"));
+    assert!(env.get("CXXFLAGS").unwrap().ends_with("-DNDEBUG"));
+    assert_eq!(env.get("MAKEFLAGS").as_deref(), Some("-j1"));
+
+    // Applied to the build command, never to nitro's own environment
+    assert!(std::env::var_os("NITRO_TEST_FOO").is_none());
+    let mut command = std::process::Command::new("sh");
+    command.args(["-c", "echo \"$NITRO_TEST_FOO\""]);
+    env.apply_to(&mut command);
+    let output = command.output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "bar baz");
+}
+
+#[test]
+fn test_install_workspaces() {
+    use nitro::core::workspace::{self, Workspace};
+
+    let prefix = tempfile::tempdir().unwrap();
+    let root = workspace::root(prefix.path());
+
+    // Concurrent installs of the same formula each get their own directory
+    let first = Workspace::create(&root, "wget", "1.24.5").unwrap();
+    let second = Workspace::create(&root, "wget", "1.24.5").unwrap();
+    assert_eq!(first.path(), root.join("wget-1.24.5"));
+    assert_eq!(second.path(), root.join("wget-1.24.5-1"));
+
+    // Removed on success, kept on request
+    std::fs::write(first.path().join("build.log"), "ok").unwrap();
+    let first_path = first.path().to_path_buf();
+    drop(first);
+    assert!(!first_path.exists());
+    let kept = second.keep();
+    assert!(kept.is_dir());
+
+    // Kept workspaces are only stale, for cleanup to remove, once they're old enough
+    let week = workspace::KEPT_WORKSPACE_AGE;
+    assert!(workspace::stale(&root, week).is_empty());
+    std::fs::File::open(&kept).unwrap()
+        .set_modified(std::time::SystemTime::now() - week - std::time::Duration::from_secs(60)).unwrap();
+    assert_eq!(workspace::stale(&root, week), [kept]);
+    assert!(workspace::stale(&prefix.path().join("missing"), week).is_empty());
+
+    // Moving a tree, and the copy used when a rename would cross filesystems
+    let staged = root.join("staged");
+    std::fs::create_dir_all(staged.join("bin")).unwrap();
+    std::fs::write(staged.join("bin/tool"), "#!/bin/sh\n").unwrap();
+    std::os::unix::fs::symlink("tool", staged.join("bin/alias")).unwrap();
+    let copy = prefix.path().join("copy");
+    workspace::copy_dir(&staged, &copy).unwrap();
+    assert_eq!(std::fs::read_link(copy.join("bin/alias")).unwrap(), std::path::Path::new("tool"));
+    assert_eq!(std::fs::read_to_string(copy.join("bin/tool")).unwrap(), "#!/bin/sh\n");
+
+    let keg = prefix.path().join("Cellar/tool/1.0");
+    std::fs::create_dir_all(keg.parent().unwrap()).unwrap();
+    workspace::move_dir(&staged, &keg).unwrap();
+    assert!(!staged.exists());
+    assert!(keg.join("bin/tool").exists());
+}
+
+#[test]
+fn test_debug_build_shell() {
+    use nitro::core::debug_build::{enable, keg_populated, shell_at, ShellAt};
+
+    // Only the formula being debugged gets a shell, not its dependencies
+    enable("debugged-formula", ShellAt::Failure);
+    assert_eq!(shell_at("debugged-formula"), Some(ShellAt::Failure));
+    assert_eq!(shell_at("its-dependency"), None);
+
+    let keg = tempfile::tempdir().unwrap();
+    assert!(!keg_populated(keg.path()));
+    assert!(!keg_populated(&keg.path().join("missing")));
+    std::fs::create_dir(keg.path().join("bin")).unwrap();
+    assert!(keg_populated(keg.path()));
+}
+
+#[test]
+fn test_message_catalog() {
+    use nitro::core::NitroError;
+    use nitro::ui::i18n::{locale_candidates, Catalog, ENGLISH};
+
+    let mut keys: Vec<&str> = ENGLISH.iter().map(|(key, _)| *key).collect();
+    keys.sort();
+    keys.dedup();
+    assert_eq!(keys.len(), ENGLISH.len(), "duplicate message keys");
+
+    assert_eq!(locale_candidates("de_DE.UTF-8@euro"), ["de_DE", "de"]);
+    assert_eq!(locale_candidates("pt-BR"), ["pt_BR", "pt"]);
+    assert_eq!(locale_candidates("fr"), ["fr"]);
+    assert!(locale_candidates("en_US.UTF-8").is_empty());
+    assert!(locale_candidates("C.UTF-8").is_empty());
+
+    // Translated keys are used, missing ones fall back to English
+    let german = Catalog::parse(r#"
+"list.header" = "Installierte Pakete ({count}):"
+"list.empty" = "Keine Pakete installiert."
+"#).unwrap();
+    assert_eq!(german.message("list.header", &[("count", &3)]), "Installierte Pakete (3):");
+    assert_eq!(german.message("casks.header", &[("count", &2)]), "Installed casks (2):");
+    assert_eq!(german.message("no.such.key", &[]), "no.such.key");
+
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("de.toml"), r#""list.empty" = "Keine Pakete installiert.""#).unwrap();
+    std::env::set_var("NITRO_LOCALE_DIR", dir.path());
+    assert_eq!(Catalog::load("de_AT.UTF-8").message("list.empty", &[]), "Keine Pakete installiert.");
+    assert_eq!(Catalog::load("fr_FR").message("list.empty", &[]), "No packages installed.");
+
+    // English error messages read exactly as they always have
+    let errors = [
+        NitroError::PackageNotFound("wget".to_string()),
+        NitroError::ChecksumMismatch { expected: "aa".to_string(), actual: "bb".to_string() },
+        NitroError::Interrupted,
+        NitroError::Io(std::io::Error::other("disk on fire")),
+    ];
+    for error in errors {
+        assert_eq!(error.localized(), error.to_string());
+    }
+}
+
+#[test]
+fn test_keg_cache() {
+    use nitro::cache::kegs::KegCache;
+    use std::path::Path;
+
+    let formula = Formula {
+        name: "tool".to_string(),
+        version: "1.0".to_string(),
+        source_hash: Some("abc123".to_string()),
+        ..Default::default()
+    };
+    let prefix = Path::new("/opt/homebrew");
+    let options = ["openssl@3=3.3.1".to_string(), "zlib=1.3".to_string()];
+
+    let key = KegCache::key(&formula, "linux/x86_64 glibc 2.35", prefix, &options);
+    assert!(key.starts_with("tool--1.0--"));
+    // Option order doesn't matter; the definition, platform, prefix and options do
+    let reordered = [options[1].clone(), options[0].clone()];
+    assert_eq!(KegCache::key(&formula, "linux/x86_64 glibc 2.35", prefix, &reordered), key);
+    assert_ne!(KegCache::key(&formula, "linux/aarch64 glibc 2.35", prefix, &options), key);
+    assert_ne!(KegCache::key(&formula, "linux/x86_64 glibc 2.35", Path::new("/usr/local"), &options), key);
+    assert_ne!(KegCache::key(&formula, "linux/x86_64 glibc 2.35", prefix, &options[..1]), key);
+    let changed = Formula { source_hash: Some("def456".to_string()), ..formula.clone() };
+    assert_ne!(KegCache::key(&changed, "linux/x86_64 glibc 2.35", prefix, &options), key);
+
+    let dir = tempfile::tempdir().unwrap();
+    let cache = KegCache::new(dir.path().join("kegs"));
+    assert!(cache.get(&key).is_none());
+
+    let keg = dir.path().join("Cellar/tool/1.0");
+    std::fs::create_dir_all(keg.join("bin")).unwrap();
+    std::fs::write(keg.join("bin/tool"), "#!/bin/sh\necho built\n").unwrap();
+    std::os::unix::fs::symlink("tool", keg.join("bin/tool-alias")).unwrap();
+    let stored = cache.store(&key, &formula, &keg).unwrap();
+    assert_eq!(cache.get(&key), Some(stored));
+
+    let workspace = dir.path().join("workspace");
+    let unpacked = cache.unpack(&key, &formula, &workspace).unwrap();
+    assert_eq!(unpacked, workspace.join("tool/1.0"));
+    assert_eq!(std::fs::read_to_string(unpacked.join("bin/tool")).unwrap(), "#!/bin/sh\necho built\n");
+    assert_eq!(std::fs::read_link(unpacked.join("bin/tool-alias")).unwrap(), Path::new("tool"));
+}
+
+#[tokio::test]
+async fn test_shared_download_cache() {
+    use nitro::cache::shared::SharedCache;
+    use nitro::cache::{CacheManager, DownloadCache};
+    use nitro::download::{DownloadConfig, Downloader};
+    use sha2::{Digest, Sha256};
+
+    let bottle = b"bottle contents".to_vec();
+    let digest = hex::encode(Sha256::digest(&bottle));
+    let other = hex::encode(Sha256::digest(b"something else"));
+
+    let mut server = mockito::Server::new_async().await;
+    let downloader = Downloader::with_config(DownloadConfig::default()).unwrap();
+    let shared = SharedCache::new(&format!("{}/cache/", server.url()), downloader.client().clone(), false);
+    assert_eq!(shared.url(&digest), format!("{}/cache/sha256/{}", server.url(), digest));
+
+    // A copy whose digest doesn't match is a miss, as is a 404; a server error is an error
+    let dir = tempfile::tempdir().unwrap();
+    let dest = dir.path().join("download");
+    let corrupt = server.mock("GET", format!("/cache/sha256/{}", other).as_str())
+        .with_body("tampered").create_async().await;
+    assert!(!shared.get(&other, &dest).await.unwrap());
+    assert!(!dest.exists());
+    corrupt.assert_async().await;
+    let broken = server.mock("GET", "/cache/sha256/broken").with_status(500).create_async().await;
+    assert!(shared.get("broken", &dest).await.is_err());
+    broken.assert_async().await;
+
+    // Miss everywhere: download from the origin, then upload it for the next machine
+    let miss = server.mock("GET", format!("/cache/sha256/{}", digest).as_str())
+        .with_status(404).expect(1).create_async().await;
+    let origin = server.mock("GET", "/origin/bottle.tar.gz")
+        .with_body(&bottle).expect(1).create_async().await;
+    let upload = server.mock("PUT", format!("/cache/sha256/{}", digest).as_str())
+        .match_body(bottle.clone()).with_status(201).expect(1).create_async().await;
+
+    let manager = CacheManager::open(&dir.path().join("local")).unwrap();
+    let cache = DownloadCache::with_manager(manager, downloader.clone()).with_shared(shared);
+    let url = format!("{}/origin/bottle.tar.gz", server.url());
+    cache.fetch_digest(&url, &digest, &dest).await.unwrap();
+    assert_eq!(std::fs::read(&dest).unwrap(), bottle);
+
+    // The second fetch comes from the local cache without touching the network
+    let again = dir.path().join("again");
+    cache.fetch_digest(&url, &digest, &again).await.unwrap();
+    assert_eq!(std::fs::read(&again).unwrap(), bottle);
+    miss.assert_async().await;
+    origin.assert_async().await;
+    upload.assert_async().await;
+    drop(cache);
+
+    // Another machine gets it from the shared cache; read-only clients never upload
+    let hit = server.mock("GET", format!("/cache/sha256/{}", digest).as_str())
+        .with_body(&bottle).expect(1).create_async().await;
+    let no_upload = server.mock("PUT", mockito::Matcher::Any).expect(0).create_async().await;
+    let read_only = SharedCache::new(&format!("{}/cache", server.url()), downloader.client().clone(), true);
+    let manager = CacheManager::open(&dir.path().join("other-machine")).unwrap();
+    let cache = DownloadCache::with_manager(manager, downloader).with_shared(read_only);
+    let fetched = dir.path().join("fetched");
+    cache.fetch_digest(&url, &digest, &fetched).await.unwrap();
+    assert_eq!(std::fs::read(&fetched).unwrap(), bottle);
+    hit.assert_async().await;
+    no_upload.assert_async().await;
+}
+
+#[test]
+fn test_cache_warm_manifest() {
+    use nitro::cli::commands::cache::manifest_formulae;
+    use nitro::core::installer::source_filename;
+
+    // Brewfiles contribute their brew lines only; anything else is a plain list
+    assert_eq!(manifest_formulae("tap \"homebrew/core\"\nbrew \"wget\"\ncask \"firefox\"\nbrew 'homebrew/core/jq'\n"), vec!["wget", "jq"]);
+    assert_eq!(manifest_formulae("# CI tools\nwget\n\n  jq  # for scripts\n"), vec!["wget", "jq"]);
+    assert!(manifest_formulae("cask \"firefox\"\n").is_empty());
+
+    let formula = Formula {
+        name: "wget".to_string(),
+        version: "1.24.5".to_string(),
+        revision: 1,
+        ..Default::default()
+    };
+    assert_eq!(
+        source_filename(&formula, "https://ftp.gnu.org/gnu/wget/wget-1.24.5.tar.gz"),
+        "wget--1.24.5_1--wget-1.24.5.tar.gz"
+    );
+}
+
+#[test]
+fn test_source_date_epoch() {
+    use nitro::core::deterministic::source_date_epoch;
+
+    assert_eq!(source_date_epoch(Some("1700000000")).to_rfc3339(), "2023-11-14T22:13:20+00:00");
+    assert_eq!(source_date_epoch(Some(" 0\n")).timestamp(), 0);
+    // Unset or unparseable falls back to the epoch rather than the current time
+    assert_eq!(source_date_epoch(None).timestamp(), 0);
+    assert_eq!(source_date_epoch(Some("yesterday")).timestamp(), 0);
+}
+
+#[test]
+fn test_shell_rc_blocks() {
+    use nitro::core::formula::FormulaParser;
+    use nitro::core::shellrc::{blocks, with_block, without_block, Shell};
+
+    let snippets = FormulaParser::new().parse_content(r#"
+class Zoxide < Formula
+  shell_rc "eval \"$(zoxide init bash)\"", shell: :bash
+  shell_rc "source #{opt_share}/zoxide/init.sh"
+  shell_rc "zoxide init fish | source", shell: :fish
+end
+"#).unwrap().shell_rc;
+    let formula = Formula { name: "zoxide".to_string(), shell_rc: snippets, ..Default::default() };
+    assert_eq!(Shell::Bash.lines(&formula), vec!["eval \"$(zoxide init bash)\"", "source #{opt_share}/zoxide/init.sh"]);
+    assert_eq!(Shell::Zsh.lines(&formula), vec!["source #{opt_share}/zoxide/init.sh"]);
+    assert_eq!(Shell::Fish.lines(&formula), vec!["zoxide init fish | source"]);
+    assert_eq!(Shell::parse("/usr/bin/zsh"), Some(Shell::Zsh));
+    assert_eq!(Shell::parse("tcsh"), None);
+
+    let rc = "export EDITOR=vi\n";
+    let applied = with_block(rc, "zoxide", &["source /opt/zoxide.sh".to_string()]);
+    assert_eq!(applied, "export EDITOR=vi\n\n# >>> nitro: zoxide >>>\nsource /opt/zoxide.sh\n# <<< nitro: zoxide <<<\n");
+    // Applying again changes nothing; new lines replace the block where it is
+    assert_eq!(with_block(&applied, "zoxide", &["source /opt/zoxide.sh".to_string()]), applied);
+    let edited = format!("{}alias ll='ls -l'\n", applied);
+    assert_eq!(
+        with_block(&edited, "zoxide", &["source /new.sh".to_string()]),
+        "export EDITOR=vi\n\n# >>> nitro: zoxide >>>\nsource /new.sh\n# <<< nitro: zoxide <<<\nalias ll='ls -l'\n"
+    );
+    assert_eq!(blocks(&edited), vec!["zoxide"]);
+
+    assert_eq!(without_block(&applied, "zoxide").as_deref(), Some(rc));
+    assert_eq!(without_block(rc, "zoxide"), None);
+}
+
+#[test]
+fn test_install_requirements() {
+    use nitro::core::formula::FormulaParser;
+    use nitro::core::requirements::{from_api_json, unmet_for_install, Host, Requirement};
+    use nitro::core::version::Version;
+
+    let content = r#"
+class Mactool < Formula
+  depends_on arch: :arm64
+  depends_on macos: :ventura
+  depends_on xcode: ["14.0", :build]
+end
+"#;
+    let parse = |content: &str| FormulaParser::new().parse_content(content).unwrap().requirements;
+    let requirements = parse(content);
+    assert_eq!(requirements[0], Requirement::Arch { arch: "arm64".to_string(), build_only: false });
+    assert_eq!(requirements[0].to_string(), "arm64 architecture");
+    assert_eq!(parse("class Inteltool < Formula\n  depends_on arch: :intel\nend\n"), [Requirement::Arch { arch: "x86_64".to_string(), build_only: false }]);
+    assert_eq!(
+        from_api_json(&serde_json::json!([{ "name": "arch", "version": "x86_64", "contexts": [] }])),
+        [Requirement::Arch { arch: "x86_64".to_string(), build_only: false }]
+    );
+
+    let formula = Formula { name: "mactool".to_string(), requirements, ..Default::default() };
+    let host = |macos: &str, arch: &str| Host {
+        macos: Some(Version::new(macos)),
+        arch: Some(arch.to_string()),
+        ..Default::default()
+    };
+
+    // Build-only requirements (Xcode here) don't stop a bottle install
+    assert!(unmet_for_install(&formula, &host("14.5", "arm64")).is_empty());
+    let unmet = unmet_for_install(&formula, &host("12.7", "x86_64"));
+    assert_eq!(unmet, [
+        "mactool requires an arm64 machine; this one is x86_64",
+        "mactool requires macOS 13 (ventura) or newer; this machine runs 12.7",
+    ]);
+    // An unknown architecture isn't held against the machine
+    assert!(unmet_for_install(&formula, &Host { arch: None, ..host("14.5", "") }).is_empty());
+    // Linux installs it as long as the architecture fits: `macos: :ventura` says which
+    // macOS, not that it needs one
+    let linux = Host { arch: Some("arm64".to_string()), ..Default::default() };
+    assert!(unmet_for_install(&formula, &linux).is_empty());
+}
+
+#[test]
+fn test_language_toolchains() {
+    use nitro::core::build_env::BuildEnv;
+    use nitro::core::language::{python_interpreter, write_python_env_scripts, Runtime, Toolchain};
+    use std::os::unix::fs::PermissionsExt;
+
+    let prefix = tempfile::tempdir().unwrap();
+    let python_keg = prefix.path().join("Cellar/python@3.12/3.12.4");
+    std::fs::create_dir_all(python_keg.join("bin")).unwrap();
+    std::fs::write(python_keg.join("bin/python3"), "").unwrap();
+    std::fs::create_dir_all(prefix.path().join("opt")).unwrap();
+    std::os::unix::fs::symlink(&python_keg, prefix.path().join("opt/python@3.12")).unwrap();
+    let python_bin = prefix.path().join("opt/python@3.12/bin");
+    // An unversioned `python` says which version it is by its lib directory
+    std::fs::create_dir_all(prefix.path().join("opt/python/lib/python3.13")).unwrap();
+    std::fs::create_dir_all(prefix.path().join("opt/python/bin")).unwrap();
+
+    let dep = |name: &str, build_only: bool| Dependency { name: name.to_string(), build_only, ..Default::default() };
+    let formula = Formula {
+        name: "tool".to_string(),
+        version: "1.0".to_string(),
+        dependencies: vec![dep("python@3.12", false), dep("openssl@3", false)],
+        build_dependencies: vec![dep("node", true)],
+        ..Default::default()
+    };
+    let runtime = Toolchain::for_runtime(prefix.path(), &formula);
+    assert_eq!(runtime.len(), 1);
+    assert_eq!((runtime[0].runtime, runtime[0].version.as_deref()), (Runtime::Python, Some("3.12")));
+    let build: Vec<_> = Toolchain::for_build(prefix.path(), &formula).into_iter().map(|t| t.formula).collect();
+    assert_eq!(build, ["python@3.12", "node"]);
+    // Venvs are made with the opt path, so they keep working when python@3.12 is upgraded
+    assert_eq!(python_interpreter(prefix.path(), &formula), python_bin.join("python3"));
+    let unversioned = Toolchain::resolve(prefix.path(), &[dep("python", false)]);
+    assert_eq!(unversioned[0].version.as_deref(), Some("3.13"));
+    // A keg without an opt link isn't named by its version
+    std::fs::create_dir_all(prefix.path().join("Cellar/node/22.1.0/bin")).unwrap();
+    let node = Toolchain::resolve(prefix.path(), &[dep("node", false)]);
+    assert_eq!(node[0].bin, prefix.path().join("opt/node/bin"));
+
+    let keg = prefix.path().join("Cellar/tool/1.0");
+    let mut env = BuildEnv::new();
+    runtime[0].apply_to_build(&mut env, &keg);
+    assert!(env.get("PATH").unwrap().starts_with(&format!("{}:", python_bin.display())));
+    assert_eq!(env.get("PYTHON"), Some(python_bin.join("python3").display().to_string()));
+    let site_packages = keg.join("lib/python3.12/site-packages");
+    assert!(env.get("PYTHONPATH").unwrap().starts_with(&site_packages.display().to_string()));
+
+    // Python scripts get a wrapper setting PYTHONPATH; other scripts and symlinks don't
+    std::fs::create_dir_all(&site_packages).unwrap();
+    std::fs::create_dir_all(keg.join("bin")).unwrap();
+    let script = |name: &str, content: &str| {
+        std::fs::write(keg.join("bin").join(name), content).unwrap();
+        std::fs::set_permissions(keg.join("bin").join(name), std::fs::Permissions::from_mode(0o755)).unwrap();
+    };
+    script("tool", "#!/opt/python@3.12/bin/python3\nimport tool\n");
+    script("helper", "#!/bin/sh\necho hi\n");
+    std::os::unix::fs::symlink("tool", keg.join("bin/tool-alias")).unwrap();
+
+    assert_eq!(write_python_env_scripts(&keg, &runtime[0]).unwrap(), ["tool"]);
+    let wrapper = std::fs::read_to_string(keg.join("bin/tool")).unwrap();
+    assert!(wrapper.contains(&format!("PYTHONPATH=\"{}", site_packages.display())));
+    assert!(wrapper.contains(&keg.join("libexec/bin/tool").display().to_string()));
+    assert_eq!(std::fs::read_to_string(keg.join("libexec/bin/tool")).unwrap(), "#!/opt/python@3.12/bin/python3\nimport tool\n");
+    assert_eq!(std::fs::read_to_string(keg.join("bin/helper")).unwrap(), "#!/bin/sh\necho hi\n");
+}
+
+#[test]
+fn test_update_outdated_upgrade_split() {
+    use clap::Parser;
+    use nitro::cli::commands::outdated::{Outdated, OutdatedPackage};
+    use nitro::cli::{Cli, Commands};
+
+    let parse = |line: &str| Cli::try_parse_from(std::iter::once("nitro").chain(line.split_whitespace())).unwrap();
+
+    let cli = parse("outdated --tap user/repo --json --fetch-only -q");
+    assert!(cli.quiet);
+    let Commands::Outdated(args) = cli.command else { panic!("expected outdated") };
+    assert_eq!(args.tap.as_deref(), Some("user/repo"));
+    assert!(args.json && args.fetch_only);
+
+    let Commands::Upgrade(args) = parse("upgrade wget --greedy --tap user/repo").command else { panic!("expected upgrade") };
+    assert_eq!(args.packages, vec!["wget"]);
+    assert!(args.greedy.greedy().latest && args.greedy.greedy().auto_updates);
+
+    // The old overloaded forms still parse, and are forwarded to upgrade
+    let Commands::Update(args) = parse("update --upgrade --dry-run wget").command else { panic!("expected update") };
+    assert!(args.upgrade && args.dry_run);
+    assert_eq!(args.packages, vec!["wget"]);
+
+    let outdated = Outdated {
+        formulae: vec![OutdatedPackage {
+            name: "wget".into(),
+            installed_version: "1.21".into(),
+            current_version: "1.24.5".into(),
+        }],
+        casks: vec![],
+    };
+    assert!(!outdated.is_empty());
+    assert_eq!(
+        serde_json::to_value(&outdated).unwrap(),
+        serde_json::json!({
+            "formulae": [{ "name": "wget", "installed_version": "1.21", "current_version": "1.24.5" }],
+            "casks": [],
+        })
+    );
+}
+
+#[test]
+fn test_link_conflict_scan() {
+    use nitro::core::installer::{link_conflict, link_conflicts};
+
+    let prefix = tempfile::tempdir().unwrap();
+    let keg = prefix.path().join("Cellar/jq/1.7.1");
+    std::fs::create_dir_all(keg.join("bin")).unwrap();
+    for tool in ["jq", "jq-manual", "jq-stale", "jq-ours"] {
+        std::fs::write(keg.join("bin").join(tool), "").unwrap();
+    }
+    let bin = prefix.path().join("bin");
+    std::fs::create_dir_all(&bin).unwrap();
+    let elsewhere = prefix.path().join("opt/jq-manual");
+    std::fs::create_dir_all(elsewhere.parent().unwrap()).unwrap();
+    std::fs::write(&elsewhere, "").unwrap();
+
+    std::fs::write(bin.join("jq"), "").unwrap();
+    std::os::unix::fs::symlink(&elsewhere, bin.join("jq-manual")).unwrap();
+    std::os::unix::fs::symlink("/nonexistent/jq-stale", bin.join("jq-stale")).unwrap();
+    std::os::unix::fs::symlink("../Cellar/jq/1.7/bin/jq-ours", bin.join("jq-ours")).unwrap();
+
+    // Broken links outside any Cellar are nobody's, so they're replaced without asking
+    assert_eq!(link_conflict(&bin.join("jq-stale"), "jq"), None);
+    assert!(link_conflict(&bin.join("jq-manual"), "jq").unwrap().starts_with("a link to "));
+
+    let conflicts = link_conflicts(&keg, prefix.path(), &bin, "jq").unwrap();
+    let links: Vec<_> = conflicts.iter().map(|c| c.link.file_name().unwrap().to_str().unwrap()).collect();
+    assert_eq!(links, vec!["jq", "jq-manual"]);
+    assert_eq!(conflicts[0].owner, "an existing file");
+}
+
+#[test]
+fn test_prune_broken_links() {
+    use nitro::core::prune::{broken_links, link_dirs, link_owner, prune};
+
+    let prefix = tempfile::tempdir().unwrap();
+    let bin = prefix.path().join("bin");
+    let share = prefix.path().join("share/man/man1");
+    std::fs::create_dir_all(&bin).unwrap();
+    std::fs::create_dir_all(&share).unwrap();
+    std::fs::create_dir_all(prefix.path().join("Cellar/jq/1.7.1/bin")).unwrap();
+    std::fs::write(prefix.path().join("Cellar/jq/1.7.1/bin/jq"), "").unwrap();
+
+    std::os::unix::fs::symlink("../Cellar/jq/1.7.1/bin/jq", bin.join("jq")).unwrap();
+    std::os::unix::fs::symlink("../Cellar/wget/1.24.5/bin/wget", bin.join("wget")).unwrap();
+    std::os::unix::fs::symlink("/nonexistent/tool", bin.join("tool")).unwrap();
+    std::os::unix::fs::symlink("../../../opt/wget/share/man/man1/wget.1", share.join("wget.1")).unwrap();
+
+    assert_eq!(link_owner(std::path::Path::new("/opt/homebrew/opt/openssl@3/lib/libssl.dylib")).as_deref(), Some("openssl@3"));
+    assert_eq!(link_dirs(prefix.path(), &bin).len(), 7);
+    assert_eq!(link_dirs(prefix.path(), std::path::Path::new("/elsewhere/bin")).len(), 1);
+
+    let links = broken_links(&link_dirs(prefix.path(), &bin));
+    let found: Vec<_> = links.iter().map(|l| (l.link.file_name().unwrap().to_str().unwrap(), l.owner.as_deref())).collect();
+    assert_eq!(found, vec![("tool", None), ("wget", Some("wget")), ("wget.1", Some("wget"))]);
+
+    assert_eq!(prune(&links).unwrap().len(), 3);
+    assert!(bin.join("jq").exists());
+    assert!(std::fs::symlink_metadata(bin.join("wget")).is_err());
+    assert!(broken_links(&link_dirs(prefix.path(), &bin)).is_empty());
+}
+
+#[test]
+fn test_search_result_numbering() {
+    use clap::Parser;
+    use nitro::cli::{Cli, Commands};
+    use nitro::search::SearchResult;
+    use nitro::ui::display::search_display_order;
+
+    let result = |name: &str, cask: bool| SearchResult {
+        name: name.into(),
+        description: None,
+        version: "1.0".into(),
+        tap: "homebrew/core".into(),
+        formula_path: format!("/taps/{}.rb", name).into(),
+        score: 1.0,
+        cask,
+    };
+    // Casks are listed (and numbered) after the formulae, whatever their score
+    let results = vec![result("firefox", true), result("fish", false), result("fizz", false)];
+    let names: Vec<_> = search_display_order(&results).iter().map(|r| r.name.as_str()).collect();
+    assert_eq!(names, vec!["fish", "fizz", "firefox"]);
+
+    let cli = Cli::try_parse_from(["nitro", "search", "fi", "--open", "2"]).unwrap();
+    let Commands::Search(args) = &cli.command else { panic!("expected search") };
+    assert_eq!(args.open, Some(2));
+    assert!(cli.command.is_read_only());
+    assert!(!Cli::try_parse_from(["nitro", "search", "fi", "-i"]).unwrap().command.is_read_only());
+    assert!(Cli::try_parse_from(["nitro", "search", "fi", "--open", "1", "--info", "2"]).is_err());
+}
+
+#[tokio::test]
+async fn test_oci_bottle_download() {
+    use nitro::download::oci::{BlobRef, OciClient};
+    use nitro::download::{DownloadConfig, Downloader};
+
+    let digest = "a".repeat(64);
+    assert_eq!(BlobRef::parse("https://example.com/wget-1.0.tar.gz"), None);
+    let parsed = BlobRef::parse(&format!("https://ghcr.io/v2/homebrew/core/python/3.12/blobs/sha256:{}", digest)).unwrap();
+    assert_eq!(parsed.repository, "homebrew/core/python/3.12");
+    assert_eq!(parsed.service(), "ghcr.io");
+
+    let mut server = mockito::Server::new_async().await;
+    let token = server.mock("GET", "/token")
+        .match_query(mockito::Matcher::AllOf(vec![
+            mockito::Matcher::UrlEncoded("scope".into(), "repository:homebrew/core/wget:pull".into()),
+            mockito::Matcher::UrlEncoded("service".into(), server.host_with_port()),
+        ]))
+        .with_body(r#"{"token":"anon"}"#)
+        .expect(1)
+        .create_async()
+        .await;
+    let index = server.mock("GET", "/v2/homebrew/core/wget/manifests/1.24.5")
+        .match_header("authorization", "Bearer anon")
+        .with_header("content-type", "application/vnd.oci.image.index.v1+json")
+        .with_body(format!(r#"{{"manifests":[{{"digest":"sha256:{}","annotations":{{"sh.brew.bottle.digest":"{}"}}}}]}}"#, "b".repeat(64), digest))
+        .create_async()
+        .await;
+    let blob = server.mock("GET", format!("/v2/homebrew/core/wget/blobs/sha256:{}", digest).as_str())
+        .match_header("authorization", "Bearer anon")
+        .with_header("content-type", "application/octet-stream")
+        .with_body("bottle")
+        .create_async()
+        .await;
+
+    let url = format!("{}/v2/homebrew/core/wget/blobs/sha256:{}", server.url(), digest);
+    let blob_ref = BlobRef::parse(&url).unwrap();
+    let downloader = Downloader::with_config(DownloadConfig::default()).unwrap();
+    let client = OciClient::new(downloader.clone());
+    client.check_published(&blob_ref, "1.24.5").await.unwrap();
+
+    // Plain downloads of blob URLs go through the token exchange too
+    let dir = tempfile::tempdir().unwrap();
+    let dest = dir.path().join("wget.bottle.tar.gz");
+    downloader.download_file(&url, &dest).await.unwrap();
+    assert_eq!(std::fs::read_to_string(&dest).unwrap(), "bottle");
+    token.assert_async().await;
+    index.assert_async().await;
+    blob.assert_async().await;
+
+    let rebuilt = BlobRef { digest: format!("sha256:{}", "c".repeat(64)), ..blob_ref };
+    assert!(client.check_published(&rebuilt, "1.24.5").await.unwrap_err().to_string().contains("out of date"));
+}
+
+#[test]
+fn test_formula_changes() {
+    use nitro::core::changes::FormulaChanges;
+    use nitro::core::formula::{Dependency, Formula};
+    use nitro::core::package::Package;
+
+    let dependency = |name: &str, build_only| Dependency { name: name.into(), build_only, ..Default::default() };
+    let installed = Package {
+        name: "wget".into(),
+        version: "1.21".into(),
+        installed_version: Some("1.21_1".into()),
+        description: None,
+        homepage: None,
+        installed: true,
+        dependencies: vec!["openssl@1.1".into(), "libidn2".into()],
+        install_path: None,
+        size: None,
+        tap: None,
+        version_scheme: 0,
+        formula_path: None,
+        formula_hash: None,
+        tap_commit: None,
+        checksum_override: None,
+        installed_on_request: true,
+        pinned: false,
+        installed_at: None,
+        linked: None,
+    };
+    let old = Formula {
+        name: "wget".into(),
+        version: "1.21".into(),
+        license: Some("GPL-3.0-or-later".into()),
+        dependencies: vec![dependency("openssl@1.1", false), dependency("libidn2", false)],
+        ..Default::default()
+    };
+    let new = Formula {
+        version: "1.24.5".into(),
+        license: Some("GPL-3.0-only".into()),
+        caveats: Some("Certificates are read from the system store".into()),
+        dependencies: vec![
+            dependency("openssl@3", false),
+            dependency("libidn2", false),
+            dependency("pkgconf", true),
+        ],
+        ..old.clone()
+    };
+
+    let changes = FormulaChanges::between(&installed, Some(&old), &new);
+    assert_eq!(changes.from_version, "1.21_1");
+    assert_eq!(changes.to_version, "1.24.5");
+    assert_eq!(changes.dependencies_added, vec!["openssl@3"]);
+    assert_eq!(changes.dependencies_removed, vec!["openssl@1.1"]);
+    assert_eq!(changes.license, Some((Some("GPL-3.0-or-later".into()), Some("GPL-3.0-only".into()))));
+    assert!(changes.lines().contains(&"new caveats:".to_string()));
+
+    // Without the installed formula only the receipt is compared
+    let changes = FormulaChanges::between(&installed, None, &new);
+    assert_eq!(changes.dependencies_removed, vec!["openssl@1.1"]);
+    assert_eq!((changes.caveats, changes.license), (None, None));
+}
+
+#[test]
+fn test_audit_log_chain() {
+    use nitro::core::audit::{verify, AuditLog};
+    use nitro::core::formula::Formula;
+    use nitro::core::install_state::{InstallMethod, InstallSource};
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let log = AuditLog::new(temp_dir.path().join("var/log/nitro/audit.jsonl"));
+    assert!(log.entries().unwrap().is_empty());
+
+    let formula = |name: &str| Formula { name: name.into(), version: "1.0".into(), ..Default::default() };
+    let source = InstallSource {
+        method: InstallMethod::Bottle,
+        url: "https://ghcr.io/v2/homebrew/core/wget/blobs/sha256:abc".into(),
+        sha256: Some("abc".into()),
+        fetched_from: "download".into(),
+    };
+    let first = log.record_install(&formula("wget"), Some("deadbeef".into()), Some(source), None).unwrap();
+    let second = log.record_install(&formula("jq"), None, None, Some("f00d".into())).unwrap();
+    assert_eq!((first.seq, second.seq), (1, 2));
+    assert_eq!(second.prev_hash, first.hash);
+
+    let entries = log.entries().unwrap();
+    assert_eq!(entries, vec![first, second]);
+    assert!(verify(&entries).is_ok());
+
+    // Editing an entry, or dropping one, breaks the chain at that line
+    let mut edited = entries.clone();
+    edited[0].formula = "curl".into();
+    assert_eq!(verify(&edited).unwrap_err().line, 1);
+    assert_eq!(verify(&entries[1..]).unwrap_err().line, 1);
+    let mut rehashed = edited.clone();
+    rehashed[0].hash = rehashed[0].compute_hash();
+    assert_eq!(verify(&rehashed).unwrap_err().line, 2);
+}
+
+#[test]
+fn test_audit_log_head() {
+    use nitro::core::audit::{verify, verify_head, AuditLog};
+    use nitro::core::formula::Formula;
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let path = temp_dir.path().join("var/log/nitro/audit.jsonl");
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let formula = |name: &str| Formula { name: name.into(), version: "1.0".into(), ..Default::default() };
+
+    // Appends from separate handles, as the daemon and the CLI would make, still chain
+    let threads: Vec<_> = (0..8).map(|i| {
+        let (path, db) = (path.clone(), db.clone());
+        std::thread::spawn(move || {
+            AuditLog::new(path).with_heads(&db).unwrap().record_install(&formula(&format!("f{}", i)), None, None, None).unwrap()
+        })
+    }).collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    let log = AuditLog::new(&path).with_heads(&db).unwrap();
+    let entries = log.entries().unwrap();
+    assert_eq!(entries.len(), 8);
+    assert!(verify(&entries).is_ok());
+    let head = log.head().unwrap().unwrap();
+    assert_eq!((head.seq, &head.hash), (8, &entries[7].hash));
+    assert!(verify_head(&entries, Some(&head)).is_ok());
+
+    // Cutting off the tail leaves a valid chain, but not the recorded head
+    assert!(verify(&entries[..6]).is_ok());
+    let broken = verify_head(&entries[..6], Some(&head)).unwrap_err();
+    assert_eq!(broken.line, 7);
+
+    // Without a database there's no head to check against
+    assert_eq!(AuditLog::new(&path).head().unwrap(), None);
+}
+
+#[test]
+fn test_transaction_rollback() {
+    use nitro::core::transaction::Transaction;
+
+    let prefix = tempfile::tempdir().unwrap();
+    let root = prefix.path().join("var/nitro/tmp");
+    let keg = prefix.path().join("Cellar/wget/1.24.5");
+    let bin = prefix.path().join("bin");
+    std::fs::create_dir_all(&keg).unwrap();
+    std::fs::write(keg.join("old"), "previous keg").unwrap();
+    std::fs::create_dir_all(&bin).unwrap();
+    std::os::unix::fs::symlink("/elsewhere/wget", bin.join("wget")).unwrap();
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    db.insert("wget", "previous entry").unwrap();
+
+    let stage = |tx: &Transaction| {
+        tx.move_aside(&keg).unwrap();
+        tx.create_dir_all(&keg.join("bin")).unwrap();
+        std::fs::write(keg.join("bin/wget"), "new keg").unwrap();
+        tx.symlink(&keg.join("bin/wget"), &bin.join("wget")).unwrap();
+        tx.create_dir_all(&prefix.path().join("Cellar/libidn2")).unwrap();
+        tx.record_db_entry(&db, "wget").unwrap();
+        db.insert("wget", "new entry").unwrap();
+    };
+
+    // Rolling back puts the old keg, link and entry back, and removes what was created
+    let tx = Transaction::begin(&root, "wget", "1.24.5").unwrap();
+    stage(&tx);
+    tx.rollback().unwrap();
+    assert_eq!(std::fs::read_to_string(keg.join("old")).unwrap(), "previous keg");
+    assert!(!keg.join("bin").exists());
+    assert_eq!(std::fs::read_link(bin.join("wget")).unwrap(), std::path::Path::new("/elsewhere/wget"));
+    assert!(!prefix.path().join("Cellar/libidn2").exists());
+    assert_eq!(db.get("wget").unwrap().unwrap(), "previous entry".as_bytes());
+    assert_eq!(std::fs::read_dir(&root).unwrap().count(), 0);
+
+    // So does dropping one unfinished, as an interrupted install does
+    let tx = Transaction::begin(&root, "wget", "1.24.5").unwrap();
+    stage(&tx);
+    drop(tx);
+    assert!(keg.join("old").exists());
+
+    // Committing keeps everything and drops the backups
+    let tx = Transaction::begin(&root, "wget", "1.24.5").unwrap();
+    stage(&tx);
+    tx.commit();
+    assert!(!keg.join("old").exists());
+    assert_eq!(std::fs::read_link(bin.join("wget")).unwrap(), keg.join("bin/wget"));
+    assert_eq!(db.get("wget").unwrap().unwrap(), "new entry".as_bytes());
+    assert_eq!(std::fs::read_dir(&root).unwrap().count(), 0);
+}
+
+#[test]
+fn test_tap_trust() {
+    use nitro::core::config::Config;
+    use nitro::core::tap::TapTrust;
+
+    let config: Config = toml::from_str(r#"
+        [taps]
+        require_trusted = true
+
+        [taps.trust]
+        "mycorp/*" = "verified"
+        "mycorp/experimental" = "untrusted"
+        "homebrew/cask-versions" = "untrusted"
+    "#).unwrap();
+    let taps = config.taps;
+    assert!(taps.require_trusted);
+    assert_eq!(taps.trust("homebrew/core"), TapTrust::Official);
+    assert_eq!(taps.trust("homebrew/cask-versions"), TapTrust::Untrusted);
+    assert_eq!(taps.trust("mycorp/tools"), TapTrust::Verified);
+    assert_eq!(taps.trust("mycorp/experimental"), TapTrust::Untrusted);
+    assert_eq!(taps.trust("someone/else"), TapTrust::Untrusted);
+    assert!(TapTrust::Verified.is_trusted() && !TapTrust::Untrusted.is_trusted());
+
+    // Nothing is required unless asked for
+    assert!(!Config::default().taps.require_trusted);
+
+    // Accepting untrusted taps unasked is its own flag, and can't be combined with refusing them
+    use clap::Parser;
+    assert!(nitro::cli::Cli::try_parse_from(["nitro", "install", "--allow-untrusted", "tool"]).unwrap().allow_untrusted);
+    assert!(nitro::cli::Cli::try_parse_from(["nitro", "--allow-untrusted", "--require-trusted", "install", "tool"]).is_err());
+}
+
+#[test]
+fn test_transaction_relinks_in_place() {
+    use nitro::core::transaction::Transaction;
+
+    let prefix = tempfile::tempdir().unwrap();
+    let root = prefix.path().join("var/nitro/tmp");
+    let bin = prefix.path().join("bin");
+    let old = prefix.path().join("Cellar/jq/1.6/bin/jq");
+    let new = prefix.path().join("Cellar/jq/1.7.1/bin/jq");
+    std::fs::create_dir_all(&bin).unwrap();
+    std::os::unix::fs::symlink(&old, bin.join("jq")).unwrap();
+
+    // An upgrade swaps the link without removing it first, leaving nothing else behind
+    let tx = Transaction::begin(&root, "jq", "1.7.1").unwrap();
+    tx.symlink(&new, &bin.join("jq")).unwrap();
+    assert_eq!(std::fs::read_link(bin.join("jq")).unwrap(), new);
+    let entries: Vec<_> = std::fs::read_dir(&bin).unwrap().map(|e| e.unwrap().file_name()).collect();
+    assert_eq!(entries, vec![std::ffi::OsString::from("jq")]);
+
+    tx.rollback().unwrap();
+    assert_eq!(std::fs::read_link(bin.join("jq")).unwrap(), old);
+}
+
+#[test]
+fn test_deps_tree_rendering() {
+    use nitro::cli::commands::deps::render_tree;
+
+    let children = |name: &str| -> Vec<String> {
+        let names: &[&str] = match name {
+            "wget" => &["libidn2", "openssl@3"],
+            "libidn2" => &["libunistring", "gettext"],
+            "openssl@3" => &["ca-certificates"],
+            "gettext" => &["libidn2"],
+            _ => &[],
+        };
+        names.iter().map(|n| n.to_string()).collect()
+    };
+    assert_eq!(render_tree("wget", &children), vec![
+        "wget",
+        "├── libidn2",
+        "│   ├── libunistring",
+        "│   └── gettext",
+        "│       └── libidn2 (cycle)",
+        "└── openssl@3",
+        "    └── ca-certificates",
+    ]);
+    assert_eq!(render_tree("zlib", &children), vec!["zlib"]);
+}
+
+#[tokio::test]
+async fn test_formula_source_links() {
+    use nitro::core::formula::Source;
+    use nitro::core::tap::{github_repository, TapManager};
+
+    assert_eq!(github_repository("https://github.com/Homebrew/homebrew-core.git").as_deref(), Some("Homebrew/homebrew-core"));
+    assert_eq!(github_repository("git@github.com:mycorp/homebrew-tools.git").as_deref(), Some("mycorp/homebrew-tools"));
+    assert_eq!(github_repository("https://gitlab.com/mycorp/homebrew-tools.git"), None);
+
+    let source = |url: &str| Source { url: url.into(), ..Default::default() };
+    let mut formula = Formula {
+        name: "jq".into(),
+        homepage: Some("https://jqlang.github.io/jq/".into()),
+        sources: vec![source("https://github.com/jqlang/jq/releases/download/jq-1.7.1/jq-1.7.1.tar.gz")],
+        ..Default::default()
+    };
+    assert_eq!(formula.upstream_repository().as_deref(), Some("https://github.com/jqlang/jq"));
+    formula.sources = vec![source("https://ftp.gnu.org/gnu/wget/wget-1.24.5.tar.gz")];
+    formula.homepage = Some("https://www.gitlab.com/gnuwget/wget2".into());
+    assert_eq!(formula.upstream_repository().as_deref(), Some("https://gitlab.com/gnuwget/wget2"));
+    formula.homepage = Some("https://www.gnu.org/software/wget/".into());
+    assert_eq!(formula.upstream_repository(), None);
+
+    // A formula not read from a tap's file (one from the API) has nothing to link to
+    let dir = tempfile::tempdir().unwrap();
+    let tap_manager = TapManager::with_db(dir.path().join("taps"), sled::Config::new().temporary(true).open().unwrap());
+    assert_eq!(tap_manager.formula_github_url(&formula, None).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn test_cancellation_token() {
+    use nitro::core::cancel::{parse_duration, CancellationToken};
+    use nitro::core::NitroError;
+    use std::time::Duration;
+
+    assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+    assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+    assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(30 * 60)));
+    assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(2 * 60 * 60)));
+    assert!(parse_duration("0").is_err());
+    assert!(parse_duration("2d").is_err());
+    assert!(parse_duration("soon").is_err());
+
+    let token = CancellationToken::new();
+    assert!(token.check().is_ok());
+    token.cancel();
+    assert!(matches!(token.check(), Err(NitroError::Interrupted)));
+
+    let token = CancellationToken::with_timeout("installing wget", Duration::from_millis(50));
+    assert!(token.check().is_ok());
+    let result: Result<(), NitroError> = token.run(std::future::pending()).await;
+    match result {
+        Err(NitroError::TimedOut(detail)) => assert!(detail.starts_with("installing wget took longer than")),
+        other => panic!("expected a timeout, got {:?}", other),
+    }
+    assert!(token.check().is_err());
+
+    // Extraction stops before unpacking anything once the token has fired
+    let dir = tempfile::tempdir().unwrap();
+    let tarball = dir.path().join("source.tar.gz");
+    {
+        let encoder = flate2::write::GzEncoder::new(std::fs::File::create(&tarball).unwrap(), flate2::Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(5);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, "src/hello.txt", &b"hello"[..]).unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+    let extract = nitro::core::installer::Installer::extract_tarball;
+    let out = dir.path().join("out");
+    let cancelled = CancellationToken::new();
+    cancelled.cancel();
+    assert!(extract(&tarball, &out, &cancelled, &|_| {}).is_err());
+    assert!(!out.join("src/hello.txt").exists());
+    assert_eq!(extract(&tarball, &out, &CancellationToken::new(), &|_| {}).unwrap(), 1);
+    assert_eq!(std::fs::read_to_string(out.join("src/hello.txt")).unwrap(), "hello");
+}
+
+#[test]
+fn test_leaves_and_orphans() {
+    use nitro::core::package::{leaves, orphans, Package};
+    use nitro::core::providers::Providers;
+
+    let package = |name: &str, dependencies: &[&str], installed_on_request| Package {
+        name: name.into(),
+        version: "1.0".into(),
+        description: None,
+        homepage: None,
+        installed: true,
+        installed_version: Some("1.0".into()),
+        dependencies: dependencies.iter().map(|d| d.to_string()).collect(),
+        install_path: None,
+        size: None,
+        tap: None,
+        version_scheme: 0,
+        formula_path: None,
+        formula_hash: None,
+        tap_commit: None,
+        checksum_override: None,
+        installed_on_request,
+        pinned: false,
+        installed_at: None,
+        linked: None,
+    };
+    let names = |packages: Vec<&Package>| packages.into_iter().map(|p| p.name.clone()).collect::<Vec<_>>();
+    let providers = Providers::default();
+
+    let mut packages = vec![
+        package("wget", &["libidn2", "openssl@3"], true),
+        package("libidn2", &["libunistring"], false),
+        package("libunistring", &[], false),
+        package("openssl@3", &["ca-certificates"], false),
+        package("ca-certificates", &[], false),
+        package("maven", &["java"], true),
+        package("openjdk@21", &[], false),
+        package("jq", &[], false),
+    ];
+    assert_eq!(names(leaves(&packages, &providers)), vec!["wget", "maven", "jq"]);
+    // jq has no dependents; openjdk@21 provides maven's java
+    assert_eq!(names(orphans(&packages, &providers)), vec!["jq"]);
+
+    // Once wget is gone, its whole dependency chain goes, dependents first
+    packages.remove(0);
+    assert_eq!(names(orphans(&packages, &providers)), vec!["libidn2", "openssl@3", "jq", "libunistring", "ca-certificates"]);
+
+    // Packages recorded before the flag existed count as asked for
+    let old: Package = serde_json::from_str(r#"{"name":"tree","version":"2.1","description":null,"homepage":null,
+        "installed":true,"installed_version":"2.1","dependencies":[],"install_path":null,"size":null}"#).unwrap();
+    assert!(old.installed_on_request);
+}
+
+#[test]
+fn test_transaction_savepoint() {
+    use nitro::core::transaction::Transaction;
+
+    let prefix = tempfile::tempdir().unwrap();
+    let root = prefix.path().join("var/nitro/tmp");
+    let bin = prefix.path().join("bin");
+    let keg = prefix.path().join("Cellar/wget/1.24.5");
+    std::fs::create_dir_all(&bin).unwrap();
+    std::os::unix::fs::symlink("/elsewhere/wget", bin.join("wget")).unwrap();
+
+    let tx = Transaction::begin(&root, "wget", "1.24.5").unwrap();
+    tx.create_dir_all(&prefix.path().join("Cellar/wget")).unwrap();
+    let savepoint = tx.savepoint();
+
+    // A failed bottle pour is undone, and the source build carries on from there
+    tx.create_dir_all(&keg.join("bin")).unwrap();
+    std::fs::write(keg.join("bin/wget"), "bottle").unwrap();
+    tx.symlink(&keg.join("bin/wget"), &bin.join("wget")).unwrap();
+    tx.rollback_to(savepoint).unwrap();
+    assert!(!keg.exists());
+    assert!(prefix.path().join("Cellar/wget").exists());
+    assert_eq!(std::fs::read_link(bin.join("wget")).unwrap(), std::path::Path::new("/elsewhere/wget"));
+
+    tx.create_dir_all(&keg.join("bin")).unwrap();
+    std::fs::write(keg.join("bin/wget"), "built").unwrap();
+    tx.symlink(&keg.join("bin/wget"), &bin.join("wget")).unwrap();
+
+    // Rolling back the whole transaction still undoes everything
+    tx.rollback().unwrap();
+    assert!(!prefix.path().join("Cellar/wget").exists());
+    assert_eq!(std::fs::read_link(bin.join("wget")).unwrap(), std::path::Path::new("/elsewhere/wget"));
+}
+
+#[tokio::test]
+async fn test_cleanup_candidates() {
+    use nitro::cache::CacheManager;
+    use nitro::core::cleanup::old_kegs;
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    let prefix = tempfile::tempdir().unwrap();
+    let cellar = prefix.path().join("Cellar");
+    for keg in ["wget/1.9", "wget/1.10", "wget/1.21", "wget/1.24.5", "jq/1.6", "jq/1.7.1", "tree/2.1", "git/2.44", "git/2.45"] {
+        std::fs::create_dir_all(cellar.join(keg).join("bin")).unwrap();
+        std::fs::write(cellar.join(keg).join("bin/tool"), "12345").unwrap();
+    }
+    // Homebrew's keg in a shared Cellar is left alone
+    std::fs::write(cellar.join("jq/1.6/INSTALL_RECEIPT.json"), "{}").unwrap();
+    std::fs::create_dir_all(prefix.path().join("opt")).unwrap();
+    std::os::unix::fs::symlink(cellar.join("wget/1.24.5"), prefix.path().join("opt/wget")).unwrap();
+
+    // The database says which wget is in use, whatever opt/ (Homebrew's) points at; git's
+    // version in use is unknown, so neither keg goes. Versions sort numerically
+    let installed = HashMap::from([("jq".to_string(), "1.7.1".to_string()), ("wget".to_string(), "1.21".to_string())]);
+    let kegs = old_kegs(&cellar, prefix.path(), &installed);
+    let found: Vec<(&str, &str)> = kegs.iter().map(|k| (k.name.as_str(), k.version.as_str())).collect();
+    assert_eq!(found, vec![("wget", "1.9"), ("wget", "1.10"), ("wget", "1.24.5")]);
+    assert_eq!(kegs[0].size, 5);
+
+    let dir = tempfile::tempdir().unwrap();
+    let cache = CacheManager::open(dir.path()).unwrap();
+    let file = dir.path().join("download");
+    std::fs::write(&file, "bottle").unwrap();
+    cache.put("expired", &file, Some(Duration::from_millis(1))).await.unwrap();
+    cache.put("fresh", &file, None).await.unwrap();
+    let missing = cache.put("missing", &file, None).await.unwrap();
+    std::fs::remove_file(missing).unwrap();
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    let keys = |entries: Vec<nitro::cache::CacheEntry>| {
+        let mut keys: Vec<String> = entries.into_iter().map(|e| e.key).collect();
+        keys.sort();
+        keys
+    };
+    assert_eq!(keys(cache.prune(true).await.unwrap()), vec!["expired", "missing"]);
+    assert_eq!(keys(cache.prune(false).await.unwrap()), vec!["expired", "missing"]);
+    assert!(cache.prune(false).await.unwrap().is_empty());
+    assert!(cache.get("fresh").await.is_some());
+}
+
+#[test]
+fn test_install_dependency_flags() {
+    use clap::Parser;
+    use nitro::cli::{Cli, Commands};
+    use nitro::core::resolver::ResolveOptions;
+
+    let parse = |line: &str| Cli::try_parse_from(std::iter::once("nitro").chain(line.split_whitespace()));
+    let install = |line: &str| match parse(line).unwrap().command {
+        Commands::Install(args) => args,
+        _ => panic!("expected install"),
+    };
+
+    let args = install("install wget --ignore-dependencies");
+    assert!(args.skip_deps && !args.only_deps);
+    let args = install("install wget --only-dependencies --include-optional --include-test");
+    assert!(args.only_deps && !args.skip_deps);
+    assert!(args.include_optional && args.include_test);
+    assert!(parse("install wget --skip-deps --only-deps").is_err());
+    assert!(parse("install wget --ignore-dependencies --include-test").is_err());
+
+    let dependency = |optional, test_only| Dependency { name: "dep".into(), optional, test_only, ..Default::default() };
+    let (runtime, optional, test) = (dependency(false, false), dependency(true, false), dependency(false, true));
+    let default = ResolveOptions::default();
+    assert!(default.wants(&runtime) && !default.wants(&optional) && !default.wants(&test));
+    let all = ResolveOptions { include_optional: true, include_test: true, ..Default::default() };
+    assert!(all.wants(&runtime) && all.wants(&optional) && all.wants(&test));
+    let optional_only = ResolveOptions { include_optional: true, ..Default::default() };
+    assert!(optional_only.wants(&optional) && !optional_only.wants(&test));
+}
+
+#[test]
+fn test_pin_commands() {
+    use clap::Parser;
+    use nitro::cli::{Cli, Commands};
+    use nitro::core::package::Package;
+
+    let cli = Cli::try_parse_from(["nitro", "pin", "wget", "jq"]).unwrap();
+    assert_eq!(cli.command.formulae(), vec!["wget", "jq"]);
+    assert!(matches!(cli.command, Commands::Pin(_)));
+    assert!(matches!(Cli::try_parse_from(["nitro", "unpin", "wget"]).unwrap().command, Commands::Unpin(_)));
+    assert!(Cli::try_parse_from(["nitro", "pin"]).is_err());
+    let Commands::Upgrade(args) = Cli::try_parse_from(["nitro", "upgrade", "--force"]).unwrap().command else { panic!("expected upgrade") };
+    assert!(args.force);
+
+    // Records written before pinning existed aren't pinned, and don't know when they
+    // were installed or whether they were linked
+    let package: Package = serde_json::from_str(r#"{"name":"tree","version":"2.1","description":null,"homepage":null,
+        "installed":true,"installed_version":"2.1","dependencies":[],"install_path":null,"size":null}"#).unwrap();
+    assert!(!package.pinned);
+    assert!(package.installed_at.is_none() && package.linked.is_none());
+}
+
+#[test]
+fn test_checksum_paths_agree() {
+    use nitro::core::checksum::{sha256_buffered, sha256_file, sha256_files, sha256_mapped, MMAP_THRESHOLD};
+
+    let dir = tempfile::tempdir().unwrap();
+    let small = dir.path().join("small");
+    std::fs::write(&small, "hello").unwrap();
+    let large = dir.path().join("large");
+    let content: Vec<u8> = (0..MMAP_THRESHOLD + 12345).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&large, &content).unwrap();
+    let empty = dir.path().join("empty");
+    std::fs::write(&empty, "").unwrap();
+
+    let expected = hex::encode(<sha2::Sha256 as sha2::Digest>::digest(&content));
+    assert_eq!(sha256_file(&large).unwrap(), expected);
+    let file = std::fs::File::open(&large).unwrap();
+    assert_eq!(sha256_mapped(&file).unwrap(), expected);
+    assert_eq!(sha256_buffered(std::fs::File::open(&large).unwrap()).unwrap(), expected);
+    assert_eq!(sha256_file(&small).unwrap(), "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824");
+    assert_eq!(sha256_file(&empty).unwrap(), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+
+    let digests = sha256_files(&[small.clone(), large, dir.path().join("missing")]);
+    assert_eq!(digests[0].as_deref().unwrap(), sha256_file(&small).unwrap());
+    assert_eq!(digests[1].as_deref().unwrap(), expected);
+    assert!(digests[2].is_err());
+
+    assert!(nitro::core::installer::Installer::verify_checksum(&small, &"0".repeat(64)).is_err());
+    nitro::core::installer::Installer::verify_checksum(&small, "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824").unwrap();
+}
+
+#[test]
+fn test_install_manifest() {
+    use nitro::core::manifest::{Manifest, MANIFEST_FILE};
+
+    let dir = tempfile::tempdir().unwrap();
+    let keg = dir.path().join("Cellar/wget/1.24.5");
+    let bin = dir.path().join("bin");
+    std::fs::create_dir_all(keg.join("bin")).unwrap();
+    std::fs::create_dir_all(keg.join("share/man/man1")).unwrap();
+    std::fs::create_dir_all(&bin).unwrap();
+    std::fs::write(keg.join("bin/wget"), "#!/bin/sh\n").unwrap();
+    std::fs::write(keg.join("share/man/man1/wget.1"), "").unwrap();
+    std::os::unix::fs::symlink(keg.join("bin/wget"), bin.join("wget")).unwrap();
+    // A link of someone else's with a similar-looking target stays put
+    std::os::unix::fs::symlink(dir.path().join("elsewhere/Cellar/wget/bin/wget2"), bin.join("wget2")).unwrap();
+
+    let manifest = Manifest::scan("wget", "1.24.5", &keg, vec![bin.join("wget")]).unwrap();
+    assert_eq!(manifest.files, vec![std::path::PathBuf::from("bin/wget"), "share/man/man1/wget.1".into()]);
+    manifest.write().unwrap();
+    assert!(keg.join(MANIFEST_FILE).exists());
+    assert_eq!(Manifest::read(&keg).unwrap(), manifest);
+
+    // Something added to the keg afterwards is left behind, and so is its directory
+    std::fs::write(keg.join("share/notes.txt"), "mine").unwrap();
+    let kept = manifest.remove().unwrap();
+    assert_eq!(kept, vec![keg.join("share/notes.txt")]);
+    assert!(std::fs::symlink_metadata(bin.join("wget")).is_err());
+    assert!(std::fs::symlink_metadata(bin.join("wget2")).is_ok());
+    assert!(!keg.join("bin").exists());
+    assert!(!keg.join("share/man").exists());
+
+    std::fs::remove_file(keg.join("share/notes.txt")).unwrap();
+    assert!(manifest.remove().unwrap().is_empty());
+    assert!(!keg.exists());
+}
+
+#[test]
+fn test_clone_tree() {
+    use nitro::core::cleanup::unused_unpacked_bottles;
+    use nitro::core::clone::{clone_tree, is_shared};
+    use nitro::core::shebang::{rewrite_keg_shebangs, ShebangContext};
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let unpacked = dir.path().join("unpacked");
+    let bottle = unpacked.join("abc123");
+    std::fs::create_dir_all(bottle.join("tool/1.0/bin")).unwrap();
+    std::fs::create_dir_all(bottle.join("tool/1.0/lib")).unwrap();
+    let script = bottle.join("tool/1.0/bin/tool");
+    std::fs::write(&script, "#!@@HOMEBREW_PREFIX@@/opt/ruby/bin/ruby\nputs 1\n").unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o555)).unwrap();
+    std::os::unix::fs::symlink("libtool.1.dylib", bottle.join("tool/1.0/lib/libtool.dylib")).unwrap();
+    std::fs::set_permissions(bottle.join("tool/1.0/lib"), std::fs::Permissions::from_mode(0o555)).unwrap();
+    assert!(!is_shared(&bottle));
+    assert_eq!(unused_unpacked_bottles(&unpacked), vec![bottle.clone()]);
+
+    let clone = dir.path().join("extract");
+    clone_tree(&bottle, &clone).unwrap();
+    let keg = clone.join("tool/1.0");
+    assert_eq!(std::fs::read_link(keg.join("lib/libtool.dylib")).unwrap(), std::path::PathBuf::from("libtool.1.dylib"));
+    assert_eq!(std::fs::metadata(keg.join("lib")).unwrap().permissions().mode() & 0o777, 0o555);
+    assert!(is_shared(&bottle));
+    assert!(unused_unpacked_bottles(&unpacked).is_empty());
+    // Cloning onto something that exists fails and leaves it alone
+    assert!(clone_tree(&bottle, &clone).is_err());
+    assert!(keg.join("bin/tool").exists());
+
+    // Relocating the clone leaves the unpacked bottle as it was
+    let context = ShebangContext::for_dependencies(dir.path(), &[], Default::default());
+    assert_eq!(rewrite_keg_shebangs(&keg, &context).unwrap(), 1);
+    assert!(std::fs::read_to_string(keg.join("bin/tool")).unwrap().starts_with(&format!("#!{}", dir.path().display())));
+    assert!(std::fs::read_to_string(&script).unwrap().starts_with("#!@@HOMEBREW_PREFIX@@"));
+    assert_eq!(std::fs::metadata(keg.join("bin/tool")).unwrap().permissions().mode() & 0o777, 0o555);
+
+    std::fs::set_permissions(keg.join("lib"), std::fs::Permissions::from_mode(0o755)).unwrap();
+    std::fs::remove_dir_all(&clone).unwrap();
+    assert_eq!(unused_unpacked_bottles(&unpacked), vec![bottle.clone()]);
+    std::fs::set_permissions(bottle.join("tool/1.0/lib"), std::fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+#[test]
+fn test_env_doctor_path_checks() {
+    use clap::Parser;
+    use nitro::cli::Cli;
+    use nitro::core::doctor::{linked_commands, path_checks, which, CheckStatus};
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let keg_bin = dir.path().join("Cellar/tool/1.0/bin");
+    let link_dir = dir.path().join("bin");
+    let system = dir.path().join("usr-bin");
+    for d in [&keg_bin, &link_dir, &system] {
+        std::fs::create_dir_all(d).unwrap();
+    }
+    let executable = |path: &std::path::Path| {
+        std::fs::write(path, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    };
+    executable(&keg_bin.join("tool"));
+    executable(&keg_bin.join("tool-config"));
+    executable(&system.join("tool"));
+    // Not executable, so it doesn't shadow anything
+    std::fs::write(system.join("tool-config"), "").unwrap();
+    std::os::unix::fs::symlink(keg_bin.join("tool"), link_dir.join("tool")).unwrap();
+    std::os::unix::fs::symlink("../Cellar/tool/1.0/bin/tool-config", link_dir.join("tool-config")).unwrap();
+    std::os::unix::fs::symlink(system.join("tool"), link_dir.join("elsewhere")).unwrap();
+
+    let commands = linked_commands(&link_dir, &dir.path().join("Cellar"));
+    assert_eq!(commands, vec!["tool".to_string(), "tool-config".to_string()]);
+
+    let path = std::env::join_paths([&system, &link_dir]).unwrap();
+    assert_eq!(which("tool", &path), Some(system.join("tool")));
+    assert_eq!(which("tool-config", &path), Some(link_dir.join("tool-config")));
+    let checks = path_checks(&link_dir, &commands, &path);
+    let summary: Vec<_> = checks.iter().map(|c| (c.id.as_str(), c.status)).collect();
+    assert_eq!(summary, vec![("path.link_dir", CheckStatus::Ok), ("path.shadowed.tool", CheckStatus::Warning)]);
+    assert!(checks[1].message.contains(&system.display().to_string()));
+    assert!(checks[1].remediation.as_deref().unwrap().contains("nitro shellenv"));
+
+    let path = std::env::join_paths([&link_dir, &system]).unwrap();
+    let checks = path_checks(&link_dir, &commands, &path);
+    assert_eq!(checks.iter().map(|c| c.status).collect::<Vec<_>>(), vec![CheckStatus::Ok, CheckStatus::Ok]);
+
+    let checks = path_checks(&link_dir, &commands, &system.clone().into_os_string());
+    assert_eq!(checks.len(), 1);
+    assert_eq!((checks[0].id.as_str(), checks[0].status), ("path.link_dir", CheckStatus::Error));
+
+    assert!(Cli::try_parse_from(["nitro", "env", "doctor", "--json"]).is_ok());
+}
+
+#[test]
+fn test_fetch_args() {
+    use clap::Parser;
+    use nitro::cli::{Cli, Commands};
+
+    let cli = Cli::try_parse_from(["nitro", "fetch", "wget", "--deps", "-s", "-o", "/tmp/seed"]).unwrap();
+    let Commands::Fetch(args) = cli.command else {
+        panic!("expected fetch");
+    };
+    assert_eq!(args.formulae, vec!["wget".to_string()]);
+    assert!(args.deps && args.build_from_source);
+    assert_eq!(args.output_dir, Some(std::path::PathBuf::from("/tmp/seed")));
+
+    let cli = Cli::try_parse_from(["nitro", "fetch", "wget", "--platform", "arm64_sonoma"]).unwrap();
+    let Commands::Fetch(args) = cli.command else {
+        panic!("expected fetch");
+    };
+    assert!(!args.deps && !args.build_from_source);
+    assert!(Cli::try_parse_from(["nitro", "fetch"]).is_err());
+}
+
+#[tokio::test]
+async fn test_fetch_downloads_sources_and_dependencies() {
+    use nitro::cli::commands::fetch::{fetch, FetchArgs};
+    use nitro::core::formula::FormulaManager;
+    use nitro::core::installer::Installer;
+    use nitro::core::tap::TapManager;
+    use nitro::download::{DownloadConfig, Downloader};
+    use sha2::{Digest, Sha256};
+
+    let git = |dir: &std::path::Path, args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com", "-c", "init.defaultBranch=main"])
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap();
+        assert!(status.status.success(), "git {:?}: {}", args, String::from_utf8_lossy(&status.stderr));
+    };
+    let root = tempfile::tempdir().unwrap();
+    let upstream = root.path().join("upstream");
+    std::fs::create_dir_all(upstream.join("Formula")).unwrap();
+    // A formula whose source is a local file; `sha256` stands in for the right checksum
+    let formula = |name: &str, depends_on: Option<&str>, sha256: Option<&str>| {
+        let tarball = root.path().join(format!("{}-1.0.tar.gz", name));
+        std::fs::write(&tarball, name).unwrap();
+        let sha256 = sha256.map(String::from).unwrap_or_else(|| hex::encode(Sha256::digest(name)));
+        let depends_on = depends_on.map(|dep| format!("  depends_on \"{}\"\n", dep)).unwrap_or_default();
+        std::fs::write(
+            upstream.join(format!("Formula/{}.rb", name)),
+            format!("class {}{} < Formula\n  url \"file://{}\"\n  sha256 \"{}\"\n{}end\n", name[..1].to_uppercase(), &name[1..], tarball.display(), sha256, depends_on),
+        ).unwrap();
+    };
+    formula("bar", None, None);
+    formula("foo", Some("bar"), None);
+    formula("baz", None, Some(&"0".repeat(64)));
+    git(&upstream, &["init", "--quiet"]);
+    git(&upstream, &["add", "."]);
+    git(&upstream, &["commit", "--quiet", "-m", "formulae"]);
+
+    let tap_manager = TapManager::with_db(root.path().join("taps"), sled::Config::new().temporary(true).open().unwrap());
+    tap_manager.add_tap("test/tools", Some(upstream.to_str().unwrap())).await.unwrap();
+    std::fs::create_dir_all(root.path().join("cache")).unwrap();
+    let formula_manager = FormulaManager::with_tap_manager(root.path().join("cache"), tap_manager);
+    let installer = Installer::with_prefix(Downloader::with_config(DownloadConfig::default()).unwrap(), &root.path().join("prefix"), &root.path().join("prefix/bin")).unwrap();
+    let out = root.path().join("out");
+    let args = |formulae: &[&str], deps: bool| FetchArgs {
+        formulae: formulae.iter().map(|f| f.to_string()).collect(),
+        deps,
+        build_from_source: false,
+        platforms: vec![],
+        output_dir: Some(out.clone()),
+    };
+    let fetched = || {
+        let mut names: Vec<String> = std::fs::read_dir(&out).unwrap().flatten().map(|e| e.file_name().to_string_lossy().into_owned()).collect();
+        names.sort();
+        names
+    };
+
+    // Without a bottle for this platform, the source is what's fetched
+    fetch(&args(&["foo"], false), &formula_manager, &installer).await.unwrap();
+    assert_eq!(fetched(), ["foo--1.0--foo-1.0.tar.gz"]);
+    assert_eq!(std::fs::read_to_string(out.join("foo--1.0--foo-1.0.tar.gz")).unwrap(), "foo");
+
+    // With --deps, the dependencies' too
+    fetch(&args(&["foo"], true), &formula_manager, &installer).await.unwrap();
+    assert_eq!(fetched(), ["bar--1.0--bar-1.0.tar.gz", "foo--1.0--foo-1.0.tar.gz"]);
+
+    // A download that doesn't match its checksum fails the command and isn't kept
+    let error = fetch(&args(&["baz", "bar"], false), &formula_manager, &installer).await.unwrap_err();
+    assert!(error.to_string().contains("1 download(s) could not be fetched"), "{}", error);
+    assert_eq!(fetched(), ["bar--1.0--bar-1.0.tar.gz", "foo--1.0--foo-1.0.tar.gz"]);
+}
+
+#[test]
+fn test_keep_going_partial_failure() {
+    use clap::Parser;
+    use nitro::cli::{Cli, Commands};
+    use nitro::core::NitroError;
+
+    let cli = Cli::try_parse_from(["nitro", "install", "wget", "jq", "--keep-going"]).unwrap();
+    let Commands::Install(args) = cli.command else {
+        panic!("expected install");
+    };
+    assert!(args.keep_going && !args.force);
+
+    let partial = NitroError::PartialFailure { failed: 1, total: 3 };
+    assert_eq!(partial.exit_code(), 2);
+    assert_eq!(partial.localized(), "1 of 3 failed");
+    assert_eq!(NitroError::PartialFailure { failed: 3, total: 3 }.exit_code(), 1);
+    assert_eq!(NitroError::Other("boom".into()).exit_code(), 1);
+}
+
+#[test]
+fn test_service_files() {
+    use nitro::core::interpolate::PathContext;
+    use nitro::core::services::{label, CronSchedule, Manager, Service};
+
+    let ruby_content = r#"
+class Redis < Formula
+  desc "Persistent key-value database"
+  url "https://download.redis.io/releases/redis-7.2.4.tar.gz"
+  sha256 "abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890"
+
+  service do
+    run [opt_bin/"redis-server", etc/"redis.conf"]
+    keep_alive true
+    working_dir var
+    log_path var/"log/redis.log"
+    error_log_path var/"log/redis.log"
+    environment_variables PATH: std_service_path_env, LANG: "C"
+  end
+end
+"#;
+    let prefix = std::path::Path::new("/opt/nitro");
+    let formula = FormulaParser::new().parse_content(ruby_content).unwrap();
+    let service = Service::for_formula(&formula, &PathContext::new(prefix, &formula), prefix).unwrap();
+    assert_eq!(service.label, label("redis"));
+    assert_eq!(service.program, vec!["/opt/nitro/opt/redis/bin/redis-server", "/opt/nitro/etc/redis.conf"]);
+    assert_eq!(service.env["PATH"], "/opt/nitro/bin:/opt/nitro/sbin:/usr/bin:/bin:/usr/sbin:/sbin");
+    assert!(!service.is_scheduled());
+
+    let files = service.files(Manager::Launchd);
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].0, "nitro.redis.plist");
+    let plist = &files[0].1;
+    assert!(plist.contains("<key>Label</key>\n\t<string>nitro.redis</string>"));
+    assert!(plist.contains("\t\t<string>/opt/nitro/etc/redis.conf</string>\n\t</array>"));
+    assert!(plist.contains("<key>RunAtLoad</key>") && plist.contains("<key>KeepAlive</key>"));
+    assert!(plist.contains("<key>StandardOutPath</key>\n\t<string>/opt/nitro/var/log/redis.log</string>"));
+
+    let files = service.files(Manager::Systemd);
+    assert_eq!(files.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), vec!["nitro.redis.service"]);
+    let unit = &files[0].1;
+    assert!(unit.contains("ExecStart=/opt/nitro/opt/redis/bin/redis-server /opt/nitro/etc/redis.conf\n"));
+    assert!(unit.contains("Restart=always\n") && unit.contains("WantedBy=default.target"));
+    assert!(unit.contains("StandardOutput=append:/opt/nitro/var/log/redis.log\n"));
+    assert!(unit.contains("Environment=LANG=C\n"));
+
+    // Scheduled services get a timer and don't start at login on their own
+    let scheduled = r#"
+class Backup < Formula
+  url "https://example.com/backup-1.0.tar.gz"
+  sha256 "abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890"
+
+  service do
+    run opt_bin/"backup"
+    run_type :cron
+    cron "30 3 * * 1"
+  end
+end
+"#;
+    let formula = FormulaParser::new().parse_content(scheduled).unwrap();
+    let service = Service::for_formula(&formula, &PathContext::new(prefix, &formula), prefix).unwrap();
+    assert_eq!(service.cron.as_deref(), Some("30 3 * * 1"));
+    let files = service.files(Manager::Systemd);
+    assert_eq!(files.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), vec!["nitro.backup.service", "nitro.backup.timer"]);
+    assert!(files[0].1.contains("Type=oneshot") && !files[0].1.contains("[Install]"));
+    assert!(files[1].1.contains("OnCalendar=Mon *-*-* 03:30:00\n"));
+    let plist = &service.files(Manager::Launchd)[0].1;
+    assert!(plist.contains("<key>Hour</key>\n\t\t<integer>3</integer>") && !plist.contains("RunAtLoad"));
+
+    assert_eq!(CronSchedule::parse("*/5 * * * *"), None);
+    assert_eq!(CronSchedule::parse("0 3 * *"), None);
+
+    // The JSON API spells paths with $HOMEBREW_PREFIX
+    let entry = serde_json::json!({
+        "name": "redis",
+        "versions": {"stable": "7.2.4"},
+        "service": {
+            "run": ["$HOMEBREW_PREFIX/opt/redis/bin/redis-server", "$HOMEBREW_PREFIX/etc/redis.conf"],
+            "keep_alive": {"always": true},
+            "working_dir": "$HOMEBREW_PREFIX/var",
+        },
+    });
+    let formula = Formula::from_api_json(&entry).unwrap();
+    let service = Service::for_formula(&formula, &PathContext::new(prefix, &formula), prefix).unwrap();
+    assert_eq!(service.program, vec!["/opt/nitro/opt/redis/bin/redis-server", "/opt/nitro/etc/redis.conf"]);
+    assert!(service.keep_alive);
+    assert_eq!(service.working_dir, Some(std::path::PathBuf::from("/opt/nitro/var")));
+
+    // Kept alive only through crashes: restarted after a failure, not after a clean exit
+    let crashed = r#"
+class Worker < Formula
+  url "https://example.com/worker-1.0.tar.gz"
+  sha256 "abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890"
+
+  service do
+    run opt_bin/"worker"
+    keep_alive crashed: true
+  end
+end
+"#;
+    let formula = FormulaParser::new().parse_content(crashed).unwrap();
+    let service = Service::for_formula(&formula, &PathContext::new(prefix, &formula), prefix).unwrap();
+    assert!(service.keep_alive && service.keep_alive_crashed);
+    let unit = &service.files(Manager::Systemd)[0].1;
+    assert!(unit.contains("Restart=on-failure\n") && !unit.contains("Restart=always"));
+    assert!(service.files(Manager::Launchd)[0].1.contains("<key>KeepAlive</key>\n\t<dict>\n\t\t<key>Crashed</key>\n\t\t<true/>"));
+
+    let entry = serde_json::json!({
+        "name": "worker",
+        "versions": {"stable": "1.0"},
+        "service": {"run": "$HOMEBREW_PREFIX/opt/worker/bin/worker", "keep_alive": {"crashed": true}},
+    });
+    let formula = Formula::from_api_json(&entry).unwrap();
+    let service = Service::for_formula(&formula, &PathContext::new(prefix, &formula), prefix).unwrap();
+    assert!(service.files(Manager::Systemd)[0].1.contains("Restart=on-failure\n"));
+}
+
+#[test]
+fn test_list_columns() {
+    use clap::Parser;
+    use nitro::cli::{Cli, Commands};
+    use nitro::cli::commands::list::{ListFormat, ListSort};
+    use nitro::ui::display::{package_table, ListRow};
+
+    let cli = Cli::try_parse_from(["nitro", "list", "--format", "columns", "--sort", "size"]).unwrap();
+    let Commands::List(args) = cli.command else { panic!("expected list") };
+    assert_eq!((args.format, args.sort), (ListFormat::Columns, Some(ListSort::Size)));
+    let cli = Cli::try_parse_from(["nitro", "list"]).unwrap();
+    let Commands::List(args) = cli.command else { panic!("expected list") };
+    assert_eq!((args.format, args.sort), (ListFormat::Lines, None));
+    assert!(Cli::try_parse_from(["nitro", "list", "--sort", "colour"]).is_err());
+
+    let rows = vec![
+        ListRow { name: "openssl@3".into(), version: "3.3.1".into(), size: Some(2048), tap: Some("homebrew/core".into()), pinned: true, latest: None },
+        ListRow { name: "jq".into(), version: "1.7".into(), size: None, tap: None, pinned: false, latest: Some("1.7.1".into()) },
+    ];
+    let lines = package_table(&rows, None);
+    assert_eq!(lines, vec![
+        "Name       Version    Size  Tap            Pinned  Outdated",
+        "openssl@3  3.3.1    2.0 KB  homebrew/core  yes",
+        "jq         1.7           -  -                      → 1.7.1",
+    ]);
+
+    // Narrower terminals lose the tap, then the size, then the ends of long names
+    let lines = package_table(&rows, Some(50));
+    assert_eq!(lines[0], "Name       Version    Size  Pinned  Outdated");
+    let lines = package_table(&rows, Some(40));
+    assert_eq!(lines[1], "openssl@3  3.3.1    yes");
+    let long = vec![ListRow { name: "a-formula-with-a-very-long-name".into(), ..rows.into_iter().next().unwrap() }];
+    let lines = package_table(&long, Some(40));
+    assert!(lines.iter().all(|line| line.chars().count() <= 40), "{:?}", lines);
+    assert!(lines[1].starts_with("a-formula-wi…  3.3.1"), "{:?}", lines);
+}
+
+#[test]
+fn test_formula_ruby_parser() {
+    let content = r##"# typed: strict
+# frozen_string_literal: true
+
+class Ripgrep < Formula
+  desc "Search tool like grep and The Silver Searcher" # "do ... end" in a comment
+  homepage "https://github.com/BurntSushi/ripgrep"
+  url "https://github.com/BurntSushi/ripgrep.git",
+      tag:      "14.1.0",
+      revision: "e50df40a1923c1e1cd35fb3fe1ad5c0b2b0a3e58"
+  license any_of: ["Unlicense", "MIT"]
+  head "https://github.com/BurntSushi/ripgrep.git", branch: "master"
+
+  livecheck do
+    url :stable
+    regex(/^v?(\d+(?:\.\d+)+)$/i)
+  end
+
+  bottle do
+    rebuild 1
+    sha256 cellar: :any,                 arm64_sonoma: "1111111111111111111111111111111111111111111111111111111111111111"
+    sha256 cellar: :any_skip_relocation, x86_64_linux: "2222222222222222222222222222222222222222222222222222222222222222"
+  end
+
+  depends_on "asciidoctor" => :build
+  depends_on "pkgconf" => :build
+  depends_on "rust" => :build
+  depends_on "pcre2"
+
+  on_macos do
+    depends_on "libiconv"
+  end
+
+  on_linux do
+    depends_on "zlib"
+  end
+
+  conflicts_with "ripgrep-all", because: "both install `rg` binaries"
+
+  def install
+    system "cargo", "install", "--features", "pcre2",
+                    *std_cargo_args # the rest of the line is a comment
+    (buildpath/"rg.sh").write <<~EOS
+      #!/bin/sh
+      exec rg "$@" # not the end
+    EOS
+    generate_completions_from_executable(bin/"rg", "--generate", base_name: "rg")
+  end
+
+  def caveats
+    <<~EOS
+      Installed to #{opt_bin}/rg.
+        Indented lines keep their indentation.
+    EOS
+  end
+
+  test do
+    (testpath/"Hello.txt").write("Hello World!")
+    system bin/"rg", "Hello World!", testpath
+  end
+end
+"##;
+
+    let formula = FormulaParser::new().parse_content(content).unwrap();
+    assert_eq!(formula.name, "ripgrep");
+    assert_eq!(formula.description.as_deref(), Some("Search tool like grep and The Silver Searcher"));
+    assert_eq!(formula.license.as_deref(), Some("Unlicense OR MIT"));
+
+    // The url's options sit on the lines after it
+    let source = &formula.sources[0];
+    assert_eq!(source.url, "https://github.com/BurntSushi/ripgrep.git");
+    assert_eq!(source.tag.as_deref(), Some("14.1.0"));
+    assert_eq!(source.revision.as_deref(), Some("e50df40a1923c1e1cd35fb3fe1ad5c0b2b0a3e58"));
+    assert_eq!(formula.version, "14.1.0");
+    assert_eq!(formula.head.unwrap().url, "https://github.com/BurntSushi/ripgrep.git");
+
+    let bottles: Vec<_> = formula.binary_packages.iter().map(|b| (b.platform.as_str(), b.arch.as_str(), &b.sha256[..1])).collect();
+    assert_eq!(bottles, vec![("darwin", "aarch64", "1"), ("linux", "x86_64", "2")]);
+
+    // Only the platform block for this machine counts
+    let deps: Vec<_> = formula.dependencies.iter().map(|d| d.name.as_str()).collect();
+    let platform_dep = if cfg!(target_os = "macos") { "libiconv" } else { "zlib" };
+    assert_eq!(deps, vec!["asciidoctor", "pkgconf", "rust", "pcre2", platform_dep]);
+    assert_eq!(formula.build_dependencies.len(), 3);
+    assert_eq!(formula.conflicts, vec!["ripgrep-all"]);
+
+    // Calls continued over several lines come back as one, comments dropped
+    let script = formula.install_script.unwrap();
+    let lines: Vec<&str> = script.lines().collect();
+    assert_eq!(lines[0], r#"system "cargo", "install", "--features", "pcre2", *std_cargo_args"#);
+    assert_eq!(lines[1], r#"(buildpath/"rg.sh").write <<~EOS"#);
+    assert_eq!(lines[2], "  #!/bin/sh");
+    assert_eq!(lines.last(), Some(&r#"generate_completions_from_executable(bin/"rg", "--generate", base_name: "rg")"#));
+
+    assert_eq!(formula.caveats.as_deref(), Some("Installed to #{opt_bin}/rg.\n  Indented lines keep their indentation."));
+    assert_eq!(formula.test_script.as_deref(), Some("(testpath/\"Hello.txt\").write(\"Hello World!\")\nsystem bin/\"rg\", \"Hello World!\", testpath"));
+
+    // Interpolated versions in urls, and resources in platform blocks
+    let formula = FormulaParser::new().parse_content(r#"
+module Formulae
+  class Pycli < Formula
+    version "2.4.1"
+    url "https://example.com/releases/v#{version.major_minor}/pycli-#{version}.tar.gz"
+    sha256 "3333333333333333333333333333333333333333333333333333333333333333"
+    revision 1
+
+    resource "certifi" do
+      url "https://example.com/certifi-2024.2.2.tar.gz"
+      sha256 "4444444444444444444444444444444444444444444444444444444444444444"
+    end
+
+    if OS.linux?
+      resource "linux-only" do
+        url "https://example.com/linux-only-1.0.tar.gz"
+        sha256 "5555555555555555555555555555555555555555555555555555555555555555"
+      end
+    end
+
+    def install
+      odie "unsupported" if Hardware::CPU.arm? && !OS.mac?
+      ENV["PYTHONPATH"] = libexec/"lib"
+      virtualenv_install_with_resources
+    end
+  end
+end
+"#).unwrap();
+    assert_eq!(formula.name, "pycli");
+    assert_eq!(formula.sources[0].url, "https://example.com/releases/v2.4/pycli-2.4.1.tar.gz");
+    assert_eq!(formula.revision, 1);
+    let resources: Vec<_> = formula.resources.iter().map(|r| r.name.as_str()).collect();
+    assert_eq!(resources, if cfg!(target_os = "linux") { vec!["certifi", "linux-only"] } else { vec!["certifi"] });
+    assert_eq!(formula.install_script.as_deref(), Some("odie \"unsupported\" if Hardware::CPU.arm? && !OS.mac?\nENV[\"PYTHONPATH\"] = libexec/\"lib\"\nvirtualenv_install_with_resources"));
+
+    assert!(FormulaParser::new().parse_content("class NotAFormula\nend\n").is_err());
+}
+
+#[test]
+fn test_transfer_summary() {
+    use nitro::metrics::{Metrics, Transfers};
+    use nitro::ui::display::transfer_summary;
+    use std::time::Duration;
+
+    let metrics = Metrics::default();
+    metrics.record_network_download();
+    let before = metrics.transfers();
+    metrics.record_cache_lookup(true);
+    metrics.record_network_download();
+    metrics.record_network_download();
+    metrics.record_download_bytes(3 * 1024 * 1024);
+    let transfers = metrics.transfers().since(&before);
+    assert_eq!(transfers, Transfers { bytes: 3 * 1024 * 1024, cache_hits: 1, network: 2 });
+    assert_eq!(transfers.downloads(), 3);
+
+    assert_eq!(
+        transfer_summary(&transfers, Duration::from_millis(1500)),
+        "Downloaded 3.0 MB in 1.5s (2.0 MB/s): 2 from the network, 1 from the cache"
+    );
+    assert_eq!(
+        transfer_summary(&Transfers { bytes: 0, cache_hits: 4, network: 0 }, Duration::from_secs(125)),
+        "Downloaded 0 B in 2m 5s (0 B/s): 0 from the network, 4 from the cache"
+    );
+}
+
+#[tokio::test]
+async fn test_plain_downloads_count_as_network_transfers() {
+    use nitro::download::{DownloadConfig, Downloader};
+
+    let mut server = mockito::Server::new_async().await;
+    let mock = server.mock("GET", "/tool-1.0.tar.gz").with_body("archive").expect(1).create_async().await;
+    let dir = tempfile::tempdir().unwrap();
+
+    // No cache in the way: the download itself is what's counted
+    let before = nitro::metrics::global().transfers();
+    let downloader = Downloader::with_config(DownloadConfig::default()).unwrap();
+    downloader.download_file(&format!("{}/tool-1.0.tar.gz", server.url()), &dir.path().join("tool.tar.gz")).await.unwrap();
+    let transfers = nitro::metrics::global().transfers().since(&before);
+    assert!(transfers.network >= 1 && transfers.bytes >= 7);
+    mock.assert_async().await;
+}
+
+#[test]
+fn test_resource_staging() {
+    use nitro::core::cancel::CancellationToken;
+    use nitro::core::installer::{resource_stage, Installer, ResourceStage};
+
+    let into = |resource: &str, target: &str| Some(ResourceStage::Into { resource: resource.into(), target: target.into() });
+    assert_eq!(resource_stage(r#"resource("six").stage buildpath/"vendor/six""#), into("six", r#"buildpath/"vendor/six""#));
+    assert_eq!(resource_stage(r#"resource("six").stage(libexec/"vendor")"#), into("six", r#"libexec/"vendor""#));
+    assert_eq!(resource_stage(r#"(buildpath/"deps").install resource("six")"#), into("six", r#"(buildpath/"deps")"#));
+    assert_eq!(resource_stage(r#"resource("six").stage"#), into("six", "Pathname.pwd"));
+    assert_eq!(
+        resource_stage(r#"resource("testdata").stage do"#),
+        Some(ResourceStage::Block { resource: "testdata".into(), target: None })
+    );
+    // The resource's declaration isn't staging
+    assert_eq!(resource_stage(r#"resource "testdata" do"#), None);
+    assert_eq!(
+        resource_stage(r#"resource("gen").stage(buildpath/"gen") do |_context|"#),
+        Some(ResourceStage::Block { resource: "gen".into(), target: Some(r#"buildpath/"gen""#.into()) })
+    );
+    assert_eq!(resource_stage(r#"system "make", "install""#), None);
+
+    // An archive with one top-level directory is staged without it, and into a
+    // directory that already has files in it
+    let dir = tempfile::tempdir().unwrap();
+    let tarball = dir.path().join("six-1.16.0.tar.gz");
+    {
+        let encoder = flate2::write::GzEncoder::new(std::fs::File::create(&tarball).unwrap(), flate2::Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        for (path, data) in [("six-1.16.0/six.py", &b"import sys"[..]), ("six-1.16.0/docs/index.rst", b"six")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, data).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+    let target = dir.path().join("build/vendor");
+    std::fs::create_dir_all(target.join("docs")).unwrap();
+    std::fs::write(target.join("docs/README"), "mine").unwrap();
+    Installer::stage_resource(&tarball, &target, &CancellationToken::new()).unwrap();
+    assert_eq!(std::fs::read_to_string(target.join("six.py")).unwrap(), "import sys");
+    assert!(target.join("docs/index.rst").exists() && target.join("docs/README").exists());
+    assert!(!target.join("six-1.16.0").exists());
+
+    // Anything that isn't an archive is copied in whole
+    let wheel = dir.path().join("six-1.16.0-py2.py3-none-any.whl");
+    std::fs::write(&wheel, "PK").unwrap();
+    Installer::stage_resource(&wheel, &target, &CancellationToken::new()).unwrap();
+    assert!(target.join("six-1.16.0-py2.py3-none-any.whl").exists());
+}
+
+#[tokio::test]
+async fn test_bottle_link_conflict() {
+    use nitro::core::cancel::CancellationToken;
+    use nitro::core::formula::{BinaryPackage, Formula};
+    use nitro::core::install_state::InstallStateStore;
+    use nitro::core::installer::{keg_links, unpacked_bottle_dir, Installer};
+    use nitro::core::NitroError;
+    use nitro::download::{DownloadConfig, Downloader};
+    use sha2::{Digest, Sha256};
+
+    let dir = tempfile::tempdir().unwrap();
+    let prefix = dir.path().join("prefix");
+    // Bottles unpack inside the test's directory, not this machine's cache
+    let config = dir.path().join("config.toml");
+    std::fs::write(&config, format!("[install]\nunpacked_bottle_dir = {:?}\n", dir.path().join("unpacked"))).unwrap();
+    std::env::set_var("NITRO_CONFIG", &config);
+    let bottle = dir.path().join("jq--1.7.1.bottle.tar.gz");
+    {
+        let encoder = flate2::write::GzEncoder::new(std::fs::File::create(&bottle).unwrap(), flate2::Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        for path in ["jq/1.7.1/bin/jq", "jq/1.7.1/lib/libjq.1.dylib", "jq/1.7.1/share/man/man1/jq.1"] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(2);
+            header.set_mode(0o755);
+            header.set_cksum();
+            builder.append_data(&mut header, path, &b"jq"[..]).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+    let sha256 = hex::encode(Sha256::digest(std::fs::read(&bottle).unwrap()));
+    let (platform, arch) = Installer::platform_tag().split_once('/').map(|(p, a)| (p.to_string(), a.to_string())).unwrap();
+    let formula = Formula {
+        name: "jq".into(),
+        version: "1.7.1".into(),
+        binary_packages: vec![BinaryPackage { platform, arch, url: format!("file://{}", bottle.display()), sha256: sha256.clone() }],
+        ..Default::default()
+    };
+
+    // A manual page someone put in the prefix by hand is in the way
+    std::fs::create_dir_all(prefix.join("share/man/man1")).unwrap();
+    std::fs::write(prefix.join("share/man/man1/jq.1"), "mine").unwrap();
+
+    let installer = Installer::with_prefix(Downloader::with_config(DownloadConfig::default()).unwrap(), &prefix, &prefix.join("bin")).unwrap();
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let state = InstallStateStore::open(&db).unwrap();
+    state.begin(&formula, installer.get_keg_path(&formula), false).unwrap();
+    let tx = installer.begin(&formula).unwrap();
+    let result = installer.install(&formula, false, &state, &tx, &CancellationToken::new()).await;
+    assert!(unpacked_bottle_dir().unwrap().starts_with(dir.path()));
+
+    // It's reported as a conflict to overwrite, not a failed pour to build instead
+    let Err(NitroError::LinkConflict(detail)) = result else { panic!("expected a link conflict, got {:?}", result) };
+    assert!(detail.contains("share/man/man1/jq.1 (an existing file)"), "{}", detail);
+    assert!(detail.contains("--overwrite"));
+    assert!(!detail.contains("Build files were kept"));
+    assert!(!prefix.join("bin/jq").exists());
+    assert!(!prefix.join("var/nitro/tmp/jq-1.7.1").exists());
+    tx.rollback().unwrap();
+    assert_eq!(std::fs::read_to_string(prefix.join("share/man/man1/jq.1")).unwrap(), "mine");
+
+    // Every file under lib and share is linked, not just the executables
+    let keg = dir.path().join("keg");
+    std::fs::create_dir_all(keg.join("lib/pkgconfig")).unwrap();
+    std::fs::write(keg.join("lib/pkgconfig/jq.pc"), "").unwrap();
+    std::fs::create_dir_all(keg.join("bin")).unwrap();
+    std::fs::write(keg.join("bin/jq"), "").unwrap();
+    let links: Vec<_> = keg_links(&keg, &prefix, &prefix.join("bin")).unwrap().into_iter().map(|(_, link)| link).collect();
+    assert_eq!(links, vec![prefix.join("bin/jq"), prefix.join("lib/pkgconfig/jq.pc")]);
+    // Linking outside the prefix leaves its lib and share to whoever owns it
+    let links = keg_links(&keg, &prefix, &dir.path().join("elsewhere/bin")).unwrap();
+    assert_eq!(links.len(), 1);
+    std::env::remove_var("NITRO_CONFIG");
+}
+
+#[test]
+fn test_unlink_keg_removes_recorded_links() {
+    use nitro::core::installer::Installer;
+    use nitro::core::manifest::Manifest;
+    use nitro::download::{DownloadConfig, Downloader};
+
+    let dir = tempfile::tempdir().unwrap();
+    let prefix = dir.path().join("prefix");
+    let installer = Installer::with_prefix(Downloader::with_config(DownloadConfig::default()).unwrap(), &prefix, &prefix.join("bin")).unwrap();
+    let keg = prefix.join("Cellar/tool/1.0");
+    std::fs::create_dir_all(keg.join("bin")).unwrap();
+    std::fs::create_dir_all(keg.join("share/doc")).unwrap();
+    std::fs::create_dir_all(prefix.join("share/doc")).unwrap();
+    std::fs::write(keg.join("bin/tool"), "").unwrap();
+    std::fs::write(keg.join("share/doc/README"), "").unwrap();
+    std::os::unix::fs::symlink(keg.join("bin/tool"), prefix.join("bin/tool")).unwrap();
+    std::os::unix::fs::symlink(keg.join("share/doc/README"), prefix.join("share/doc/README")).unwrap();
+    // Made by hand, and not what linking the keg makes
+    std::os::unix::fs::symlink(keg.join("bin/tool"), prefix.join("share/doc/tool-link")).unwrap();
+
+    // Without a manifest, the links linking it would make
+    let removed = installer.unlink_keg(&keg).unwrap();
+    assert_eq!(removed, [prefix.join("bin/tool"), prefix.join("share/doc/README")]);
+    assert!(std::fs::symlink_metadata(prefix.join("share/doc/tool-link")).is_ok());
+
+    // With one, what it recorded; a link since pointed elsewhere stays
+    std::os::unix::fs::symlink(keg.join("bin/tool"), prefix.join("bin/tool")).unwrap();
+    std::os::unix::fs::symlink("/elsewhere/README", prefix.join("share/doc/README")).unwrap();
+    Manifest::scan("tool", "1.0", &keg, vec![prefix.join("bin/tool"), prefix.join("share/doc/README")]).unwrap().write().unwrap();
+    assert_eq!(installer.unlink_keg(&keg).unwrap(), [prefix.join("bin/tool")]);
+    assert!(std::fs::symlink_metadata(prefix.join("share/doc/README")).is_ok());
+}
+
+#[tokio::test]
+async fn test_failed_pour_retried_from_source_drops_its_workspace() {
+    use nitro::core::cancel::CancellationToken;
+    use nitro::core::formula::{BinaryPackage, Formula};
+    use nitro::core::install_state::InstallStateStore;
+    use nitro::core::installer::Installer;
+    use nitro::download::{DownloadConfig, Downloader};
+
+    let dir = tempfile::tempdir().unwrap();
+    let prefix = dir.path().join("prefix");
+    let bottle = dir.path().join("tree--2.1.bottle.tar.gz");
+    std::fs::write(&bottle, "not the bottle the formula describes").unwrap();
+    let (platform, arch) = Installer::platform_tag().split_once('/').map(|(p, a)| (p.to_string(), a.to_string())).unwrap();
+    let formula = Formula {
+        name: "tree".into(),
+        version: "2.1".into(),
+        binary_packages: vec![BinaryPackage { platform, arch, url: format!("file://{}", bottle.display()), sha256: "0".repeat(64) }],
+        ..Default::default()
+    };
+
+    let installer = Installer::with_prefix(Downloader::with_config(DownloadConfig::default()).unwrap(), &prefix, &prefix.join("bin")).unwrap();
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let state = InstallStateStore::open(&db).unwrap();
+    let tx = installer.begin(&formula).unwrap();
+    let error = installer.install(&formula, false, &state, &tx, &CancellationToken::new()).await.unwrap_err();
+    tx.rollback().unwrap();
+
+    // The source build it fell back to failed on its own; the pour's files are gone
+    // rather than pointed at
+    assert!(error.to_string().contains("No source URL"), "{}", error);
+    assert!(!error.to_string().contains("Build files were kept"));
+    let leftovers: Vec<_> = std::fs::read_dir(nitro::core::workspace::root(&prefix)).unwrap()
+        .flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("tree-"))
+        .collect();
+    assert!(leftovers.is_empty(), "{:?}", leftovers);
+}
+
+#[tokio::test]
+async fn test_fetch_bottle_from_registry() {
+    use nitro::core::formula::{BinaryPackage, Formula};
+    use nitro::core::installer::{bottle_filename, Installer};
+    use nitro::download::{DownloadConfig, Downloader};
+    use sha2::{Digest, Sha256};
+
+    let body = b"a registry bottle";
+    let digest = hex::encode(Sha256::digest(body));
+    let mut server = mockito::Server::new_async().await;
+    let token = server.mock("GET", "/token")
+        .match_query(mockito::Matcher::UrlEncoded("scope".into(), "repository:homebrew/core/fetched:pull".into()))
+        .with_body(r#"{"token":"fetch-token"}"#)
+        .expect(1)
+        .create_async()
+        .await;
+    let path = format!("/v2/homebrew/core/fetched/blobs/sha256:{}", digest);
+    let anonymous = server.mock("GET", path.as_str())
+        .match_header("authorization", mockito::Matcher::Missing)
+        .with_status(401)
+        .expect(0)
+        .create_async()
+        .await;
+    let blob = server.mock("GET", path.as_str())
+        .match_header("authorization", "Bearer fetch-token")
+        .with_header("content-type", "application/octet-stream")
+        .with_body(body)
+        .create_async()
+        .await;
+
+    let formula = Formula {
+        name: "fetched".into(),
+        version: "1.0".into(),
+        binary_packages: vec![BinaryPackage {
+            platform: "linux".into(),
+            arch: "x86_64".into(),
+            url: format!("{}{}", server.url(), path),
+            sha256: digest,
+        }],
+        ..Default::default()
+    };
+    let dir = tempfile::tempdir().unwrap();
+    let installer = Installer::with_prefix(Downloader::with_config(DownloadConfig::default()).unwrap(), &dir.path().join("prefix"), &dir.path().join("prefix/bin")).unwrap();
+
+    // `nitro fetch` and `cache warm` exchange a token just like installs do
+    let fetched = installer.fetch_bottle(&formula, "linux/x86_64", &dir.path().join("bottles")).await.unwrap();
+    assert_eq!(fetched, dir.path().join("bottles").join(bottle_filename(&formula, "linux/x86_64")));
+    assert_eq!(std::fs::read(&fetched).unwrap(), body);
+    token.assert_async().await;
+    anonymous.assert_async().await;
+    blob.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_bottle_download_size_from_registry() {
+    use nitro::core::formula::{BinaryPackage, Formula};
+    use nitro::core::installer::Installer;
+    use nitro::download::{DownloadConfig, Downloader};
+
+    let digest = "ab".repeat(32);
+    let mut server = mockito::Server::new_async().await;
+    server.mock("GET", "/token")
+        .match_query(mockito::Matcher::UrlEncoded("scope".into(), "repository:homebrew/core/sized:pull".into()))
+        .with_body(r#"{"token":"size-token"}"#)
+        .create_async()
+        .await;
+    let path = format!("/v2/homebrew/core/sized/blobs/sha256:{}", digest);
+    server.mock("HEAD", path.as_str())
+        .match_header("authorization", mockito::Matcher::Missing)
+        .with_status(401)
+        .create_async()
+        .await;
+    let head = server.mock("HEAD", path.as_str())
+        .match_header("authorization", "Bearer size-token")
+        .with_header("content-length", "4096")
+        .create_async()
+        .await;
+
+    let platform = Installer::platform_tag();
+    let (platform, arch) = platform.split_once('/').unwrap();
+    let formula = Formula {
+        name: "sized".into(),
+        version: "1.0".into(),
+        binary_packages: vec![BinaryPackage {
+            platform: platform.into(),
+            arch: arch.into(),
+            url: format!("{}{}", server.url(), path),
+            sha256: digest.clone(),
+        }],
+        ..Default::default()
+    };
+    let dir = tempfile::tempdir().unwrap();
+    let installer = Installer::with_prefix(Downloader::with_config(DownloadConfig::default()).unwrap(), &dir.path().join("prefix"), &dir.path().join("prefix/bin")).unwrap();
+
+    assert_eq!(installer.download_size(&formula, false).await, Some(4096));
+    head.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_uninstall_leaves_files_the_manifest_does_not_list() {
+    use nitro::core::installer::Installer;
+    use nitro::core::manifest::Manifest;
+    use nitro::core::package::Package;
+    use nitro::download::{DownloadConfig, Downloader};
+
+    let dir = tempfile::tempdir().unwrap();
+    let prefix = dir.path().join("prefix");
+    let installer = Installer::with_prefix(Downloader::with_config(DownloadConfig::default()).unwrap(), &prefix, &prefix.join("bin")).unwrap();
+    let keg = prefix.join("Cellar/tool/1.0");
+    std::fs::create_dir_all(keg.join("bin")).unwrap();
+    std::fs::write(keg.join("bin/tool"), "#!/bin/sh\n").unwrap();
+    std::os::unix::fs::symlink(keg.join("bin/tool"), prefix.join("bin/tool")).unwrap();
+    let manifest = Manifest::scan("tool", "1.0", &keg, vec![prefix.join("bin/tool")]).unwrap();
+    manifest.write().unwrap();
+    // Added after the install, e.g. by the user's own plugin
+    std::fs::write(keg.join("bin/plugin"), "mine").unwrap();
+    // Linked later, for a dependent's shebangs
+    std::fs::create_dir_all(prefix.join("opt")).unwrap();
+    std::os::unix::fs::symlink(&keg, prefix.join("opt/tool")).unwrap();
+
+    let package = Package {
+        name: "tool".to_string(),
+        version: "1.0".to_string(),
+        description: None,
+        homepage: None,
+        installed: true,
+        installed_version: Some("1.0".to_string()),
+        dependencies: vec![],
+        install_path: Some(prefix.join("Cellar/tool")),
+        size: None,
+        tap: None,
+        version_scheme: 0,
+        formula_path: None,
+        formula_hash: None,
+        tap_commit: None,
+        checksum_override: None,
+        installed_on_request: true,
+        pinned: false,
+        installed_at: None,
+        linked: None,
+    };
+    installer.uninstall(&package, Some(&manifest)).await.unwrap();
+
+    assert!(!keg.join("bin/tool").exists());
+    assert!(std::fs::symlink_metadata(prefix.join("bin/tool")).is_err());
+    assert!(std::fs::symlink_metadata(prefix.join("opt/tool")).is_err());
+    assert_eq!(std::fs::read_to_string(keg.join("bin/plugin")).unwrap(), "mine");
+}
+
+#[tokio::test]
+async fn test_link_creates_opt_link() {
+    use nitro::core::installer::Installer;
+    use nitro::core::transaction::Transaction;
+    use nitro::download::{DownloadConfig, Downloader};
+
+    let dir = tempfile::tempdir().unwrap();
+    let prefix = dir.path().join("prefix");
+    let installer = Installer::with_prefix(Downloader::with_config(DownloadConfig::default()).unwrap(), &prefix, &prefix.join("bin")).unwrap();
+    let formula = FormulaParser::new().parse_content(r#"
+class Redis < Formula
+  url "https://download.redis.io/releases/redis-7.2.4.tar.gz"
+  sha256 "abcdef1234567890abcdef1234567890abcdef1234567890abcdef1234567890"
+end
+"#).unwrap();
+    let keg = installer.get_keg_path(&formula);
+    std::fs::create_dir_all(keg.join("bin")).unwrap();
+    std::fs::write(keg.join("bin/redis-server"), "#!/bin/sh\n").unwrap();
+
+    let tx = Transaction::begin(dir.path(), "redis", "7.2.4").unwrap();
+    installer.link(&formula, &tx).await.unwrap();
+    tx.commit();
+    // What services and rewritten shebangs point at
+    assert_eq!(std::fs::read_link(prefix.join("opt/redis")).unwrap(), keg);
+    assert!(prefix.join("opt/redis/bin/redis-server").exists());
+
+    // A brew keg's opt link is brew's to manage
+    let brew_keg = prefix.join("Cellar/redis/7.0.0");
+    std::fs::create_dir_all(&brew_keg).unwrap();
+    std::fs::write(brew_keg.join("INSTALL_RECEIPT.json"), "{}").unwrap();
+    std::fs::remove_file(prefix.join("opt/redis")).unwrap();
+    std::os::unix::fs::symlink("../Cellar/redis/7.0.0", prefix.join("opt/redis")).unwrap();
+    let tx = Transaction::begin(dir.path(), "redis", "7.2.4").unwrap();
+    installer.link(&formula, &tx).await.unwrap();
+    tx.commit();
+    assert_eq!(std::fs::read_link(prefix.join("opt/redis")).unwrap(), std::path::Path::new("../Cellar/redis/7.0.0"));
+}
+
+#[test]
+fn test_extract_read_only_directories() {
+    use nitro::core::cancel::CancellationToken;
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let tarball = dir.path().join("bottle.tar.gz");
+    {
+        let encoder = flate2::write::GzEncoder::new(std::fs::File::create(&tarball).unwrap(), flate2::Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        // A read-only directory listed before what's in it, as bottles have them
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_size(0);
+        header.set_mode(0o555);
+        header.set_cksum();
+        builder.append_data(&mut header, "tool/1.0/share/", std::io::empty()).unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_mode(0o444);
+        header.set_cksum();
+        builder.append_data(&mut header, "tool/1.0/share/data", &b"data"[..]).unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+
+    let out = dir.path().join("out");
+    let reports = std::cell::RefCell::new(Vec::new());
+    let count = nitro::core::installer::Installer::extract_tarball(&tarball, &out, &CancellationToken::new(), &|e| reports.borrow_mut().push(*e)).unwrap();
+    assert_eq!(count, 2);
+    // Progress only goes forward, and ends with the whole archive read
+    let reports = reports.into_inner();
+    let size = std::fs::metadata(&tarball).unwrap().len();
+    assert!(reports.windows(2).all(|w| w[0].read <= w[1].read && w[0].entries <= w[1].entries));
+    let last = reports.last().unwrap();
+    assert_eq!((last.entries, last.read, last.total, last.percent()), (2, size, size, 100));
+    let share = out.join("tool/1.0/share");
+    assert_eq!(std::fs::read_to_string(share.join("data")).unwrap(), "data");
+    assert_eq!(std::fs::metadata(&share).unwrap().permissions().mode() & 0o777, 0o555);
+    std::fs::set_permissions(&share, std::fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+#[test]
+fn test_cancelled_command_kills_its_process_group() {
+    use nitro::core::cancel::CancellationToken;
+    use nitro::core::NitroError;
+    use std::time::Duration;
+
+    let dir = tempfile::tempdir().unwrap();
+    let pidfile = dir.path().join("pid");
+    let token = CancellationToken::with_timeout("building", Duration::from_millis(500));
+    let mut command = std::process::Command::new("sh");
+    command.arg("-c").arg(format!("sleep 30 & echo $! > {}; wait", pidfile.display()));
+    assert!(matches!(token.run_command(&mut command), Err(NitroError::TimedOut(_))));
+
+    // The shell's own child went with it
+    let pid = std::fs::read_to_string(&pidfile).unwrap().trim().to_string();
+    std::thread::sleep(Duration::from_millis(100));
+    let state = std::fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap_or_default();
+    assert!(state.is_empty() || state.split(' ').nth(2) == Some("Z"), "sleep {} still running: {}", pid, state);
+}
+
+#[tokio::test]
+async fn test_tap_formula_pins() {
+    use nitro::download::{DownloadConfig, Downloader};
+    use nitro::core::formula::{content_hash, FormulaManager};
+    use nitro::core::git;
+    use nitro::core::installer::Installer;
+    use nitro::core::package::{Package, PackageManager};
+    use nitro::core::tap::TapManager;
+
+    let git = |dir: &std::path::Path, args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com", "-c", "init.defaultBranch=main"])
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap();
+        assert!(status.status.success(), "git {:?}: {}", args, String::from_utf8_lossy(&status.stderr));
+    };
+    let formula = |version: &str| format!(
+        "class Foo < Formula\n  url \"https://example.com/foo-{}.tar.gz\"\n  sha256 \"{}\"\nend\n",
+        version, "0".repeat(64)
+    );
+
+    let root = tempfile::tempdir().unwrap();
+    let upstream = root.path().join("upstream");
+    std::fs::create_dir_all(upstream.join("Formula")).unwrap();
+    git(&upstream, &["init", "--quiet"]);
+    std::fs::write(upstream.join("Formula/foo.rb"), formula("1.0")).unwrap();
+    git(&upstream, &["add", "."]);
+    git(&upstream, &["commit", "--quiet", "-m", "foo 1.0"]);
+
+    let tap_manager = TapManager::with_db(root.path().join("taps"), sled::Config::new().temporary(true).open().unwrap());
+    tap_manager.add_tap("test/tools", Some(upstream.to_str().unwrap())).await.unwrap();
+    let installed_from = root.path().join("taps/test_tools/Formula/foo.rb");
+    let installed_commit = git::head(&root.path().join("taps/test_tools")).await.unwrap();
+
+    let prefix = root.path().join("prefix");
+    let installer = Installer::with_prefix(Downloader::with_config(DownloadConfig::default()).unwrap(), &prefix, &prefix.join("bin")).unwrap();
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let package_manager = PackageManager::with_db(
+        db.clone(),
+        FormulaManager::with_tap_manager(root.path().join("cache"), tap_manager),
+        installer,
+    ).unwrap();
+
+    // foo 1.0 is installed from the tap, which then moves on to 2.0
+    let package = Package {
+        name: "foo".to_string(),
+        version: "1.0".to_string(),
+        description: None,
+        homepage: None,
+        installed: true,
+        installed_version: Some("1.0".to_string()),
+        dependencies: vec![],
+        install_path: None,
+        size: None,
+        tap: Some("test/tools".to_string()),
+        version_scheme: 0,
+        formula_path: Some(installed_from.clone()),
+        formula_hash: Some(content_hash(formula("1.0").as_bytes())),
+        tap_commit: Some(installed_commit.clone()),
+        checksum_override: None,
+        installed_on_request: true,
+        pinned: false,
+        installed_at: None,
+        linked: None,
+    };
+    db.insert("foo", serde_json::to_vec(&package).unwrap()).unwrap();
+    std::fs::write(upstream.join("Formula/foo.rb"), formula("2.0")).unwrap();
+    git(&upstream, &["commit", "--quiet", "-am", "foo 2.0"]);
+    let tap_manager = package_manager.formula_manager().tap_manager();
+    tap_manager.update_tap("test/tools", false).await.unwrap();
+    assert_eq!(package_manager.installed_formula(&package).await.unwrap().version, "2.0");
+
+    // Pinning takes the file foo was installed from out of the tap's history
+    let pin = package_manager.pin_formula("foo", false).await.unwrap();
+    assert_eq!(pin.hash, content_hash(formula("1.0").as_bytes()));
+    assert_eq!(pin.tap.as_deref(), Some("test/tools"));
+    assert_eq!(pin.tap_commit.as_deref(), Some(installed_commit.as_str()));
+    assert_eq!(tap_manager.list_formula_pins().unwrap().len(), 1);
+
+    // Reinstalling and upgrading use the pinned file, not the tap's
+    let pinned = package_manager.formula_manager().pinned_formula("foo").await.unwrap().unwrap();
+    assert_eq!(pinned.version, "1.0");
+    assert_eq!(pinned.tap.as_deref(), Some("test/tools"));
+    assert_eq!(package_manager.installed_formula(&package).await.unwrap().version, "1.0");
+    assert!(package_manager.check_updates(&[], None).await.unwrap().is_empty());
+
+    // A pinned file edited by hand is refused rather than used
+    let (_, pinned_path) = tap_manager.formula_pin("foo").unwrap().unwrap();
+    std::fs::write(&pinned_path, formula("1.1")).unwrap();
+    assert!(package_manager.installed_formula(&package).await.is_err());
+
+    // Bumping re-pins to the tap's current file
+    let pin = package_manager.pin_formula("foo", true).await.unwrap();
+    assert_eq!(pin.hash, content_hash(formula("2.0").as_bytes()));
+    assert_eq!(pin.tap_commit, git::head(&root.path().join("taps/test_tools")).await.ok());
+    assert_eq!(package_manager.installed_formula(&package).await.unwrap().version, "2.0");
+
+    // Unpinning goes back to the tap, and says whether there was a pin
+    assert!(tap_manager.unpin_formula("foo").unwrap());
+    assert!(!pinned_path.exists());
+    assert!(package_manager.formula_manager().pinned_formula("foo").await.unwrap().is_none());
+    assert!(tap_manager.list_formula_pins().unwrap().is_empty());
+    assert!(!tap_manager.unpin_formula("foo").unwrap());
+}
+
+#[tokio::test]
+async fn test_upgrade_replaces_old_keg_links() {
+    use nitro::cli::commands::install::InstallArgs;
+    use nitro::core::formula::FormulaManager;
+    use nitro::core::installer::Installer;
+    use nitro::core::package::PackageManager;
+    use nitro::core::tap::TapManager;
+    use nitro::download::{DownloadConfig, Downloader};
+    use sha2::{Digest, Sha256};
+
+    let git = |dir: &std::path::Path, args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com", "-c", "init.defaultBranch=main"])
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap();
+        assert!(status.status.success(), "git {:?}: {}", args, String::from_utf8_lossy(&status.stderr));
+    };
+    let root = tempfile::tempdir().unwrap();
+    // A source tarball whose build copies `files` into the keg
+    let formula = |version: &str, files: &[&str]| {
+        let tarball = root.path().join(format!("foo-{}.tar.gz", version));
+        let encoder = flate2::write::GzEncoder::new(std::fs::File::create(&tarball).unwrap(), flate2::Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        for file in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(version.len() as u64);
+            header.set_mode(0o755);
+            header.set_cksum();
+            builder.append_data(&mut header, format!("foo-{}/keg/{}", version, file), version.as_bytes()).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+        format!(
+            "class Foo < Formula\n  url \"file://{}\"\n  sha256 \"{}\"\n\n  def install\n    system \"cp -R keg/. #{{prefix}}\"\n  end\nend\n",
+            tarball.display(),
+            hex::encode(Sha256::digest(std::fs::read(&tarball).unwrap()))
+        )
+    };
+
+    let upstream = root.path().join("upstream");
+    std::fs::create_dir_all(upstream.join("Formula")).unwrap();
+    git(&upstream, &["init", "--quiet"]);
+    std::fs::write(upstream.join("Formula/foo.rb"), formula("1.0", &["bin/foo", "share/foo/old.txt"])).unwrap();
+    git(&upstream, &["add", "."]);
+    git(&upstream, &["commit", "--quiet", "-m", "foo 1.0"]);
+
+    let tap_manager = TapManager::with_db(root.path().join("taps"), sled::Config::new().temporary(true).open().unwrap());
+    tap_manager.add_tap("test/tools", Some(upstream.to_str().unwrap())).await.unwrap();
+    std::fs::create_dir_all(root.path().join("cache")).unwrap();
+    let prefix = root.path().join("prefix");
+    let installer = Installer::with_prefix(Downloader::with_config(DownloadConfig::default()).unwrap(), &prefix, &prefix.join("bin")).unwrap();
+    let package_manager = PackageManager::with_db(
+        sled::Config::new().temporary(true).open().unwrap(),
+        FormulaManager::with_tap_manager(root.path().join("cache"), tap_manager),
+        installer,
+    ).unwrap();
+    // As with --allow-untrusted: there's no terminal to confirm the tap on
+    nitro::core::tap::allow_untrusted();
+    package_manager.install("foo", &InstallArgs { packages: vec!["foo".into()], ..Default::default() }).await.unwrap();
+    let cellar = prefix.join("Cellar/foo");
+    assert_eq!(std::fs::read_link(prefix.join("bin/foo")).unwrap(), cellar.join("1.0/bin/foo"));
+    assert!(std::fs::symlink_metadata(prefix.join("share/foo/old.txt")).is_ok());
+    assert!(prefix.join("var/log/nitro/audit.jsonl").exists());
+
+    let release = |version: &str, files: &[&str]| {
+        std::fs::write(upstream.join("Formula/foo.rb"), formula(version, files)).unwrap();
+        git(&upstream, &["commit", "--quiet", "-am", &format!("foo {}", version)]);
+    };
+    let tap_manager = package_manager.formula_manager().tap_manager();
+
+    // 2.0 goes into a keg of its own; the links 1.0 had that 2.0 doesn't are removed
+    release("2.0", &["bin/foo"]);
+    tap_manager.update_tap("test/tools", false).await.unwrap();
+    package_manager.update_packages(&[], None, false, false).await.unwrap();
+    assert_eq!(std::fs::read_link(prefix.join("bin/foo")).unwrap(), cellar.join("2.0/bin/foo"));
+    assert!(std::fs::symlink_metadata(prefix.join("share/foo/old.txt")).is_err());
+    assert!(cellar.join("1.0").exists());
+    assert_eq!(package_manager.installed_package("foo").unwrap().unwrap().installed_version.as_deref(), Some("2.0"));
+    assert!(package_manager.check_updates(&[], None).await.unwrap().is_empty());
+
+    // With cleanup, the keg it replaced goes too
+    release("3.0", &["bin/foo"]);
+    tap_manager.update_tap("test/tools", false).await.unwrap();
+    package_manager.update_packages(&[], None, true, false).await.unwrap();
+    assert_eq!(std::fs::read_link(prefix.join("bin/foo")).unwrap(), cellar.join("3.0/bin/foo"));
+    assert!(!cellar.join("2.0").exists());
+    assert!(cellar.join("1.0").exists());
+}